@@ -11,18 +11,31 @@
 //!     components::aes_virtual_component_static!(nrf52840::aes::AesECB<'static>),
 //! );
 //!
+//! let chacha = components::chacha20_poly1305::ChaCha20Poly1305SoftwareComponent::new()
+//!     .finalize(components::chacha20_poly1305_software_component_static!());
+//!
 //! let aes = components::aes::AesDriverComponent::new(
 //!     board_kernel,
 //!     capsules_extra::symmetric_encryption::aes::DRIVER_NUM,
 //!     aes_driver_device,
+//!     chacha,
 //! )
 //! .finalize(components::aes_driver_component_static!(
 //!     capsules_core::virtualizers::virtual_aes_ccm::VirtualAES128CCM<
 //!         'static,
 //!         nrf52840::aes::AesECB<'static>,
-//!     >
+//!     >,
+//!     capsules_extra::symmetric_encryption::chacha20_poly1305::ChaCha20Poly1305Software<'static>,
 //! ));
 //! ```
+//!
+//! On chips without an AES peripheral, a software implementation can be used
+//! instead:
+//!
+//! ```rust
+//! let aes = components::aes::AesSoftwareComponent::new()
+//!     .finalize(components::aes_software_component_static!());
+//! ```
 
 use core::mem::MaybeUninit;
 use kernel::capabilities;
@@ -30,7 +43,7 @@ use kernel::component::Component;
 use kernel::create_capability;
 use kernel::hil;
 use kernel::hil::symmetric_encryption::{
-    AES128Ctr, AES128, AES128CBC, AES128CCM, AES128ECB, AES128GCM,
+    AES128Ctr, ChaCha20Poly1305, AES128, AES128CBC, AES128CCM, AES128ECB, AES128GCM,
 };
 
 const CRYPT_SIZE: usize = 7 * hil::symmetric_encryption::AES128_BLOCK_SIZE;
@@ -50,14 +63,17 @@ macro_rules! aes_virtual_component_static {
 
 #[macro_export]
 macro_rules! aes_driver_component_static {
-    ($A:ty $(,)?) => {{
+    ($A:ty, $C:ty $(,)?) => {{
         const CRYPT_SIZE: usize = 7 * kernel::hil::symmetric_encryption::AES128_BLOCK_SIZE;
         let aes_src_buffer = kernel::static_buf!([u8; 16]);
         let aes_dst_buffer = kernel::static_buf!([u8; CRYPT_SIZE]);
-        let aes_driver =
-            kernel::static_buf!(capsules_extra::symmetric_encryption::aes::AesDriver<'static, $A>);
+        let aes_key_buffer =
+            kernel::static_buf!([u8; kernel::hil::symmetric_encryption::AES_MAX_KEY_SIZE]);
+        let aes_driver = kernel::static_buf!(
+            capsules_extra::symmetric_encryption::aes::AesDriver<'static, $A, $C>
+        );
 
-        (aes_driver, aes_src_buffer, aes_dst_buffer)
+        (aes_driver, aes_src_buffer, aes_dst_buffer, aes_key_buffer)
     };};
 }
 
@@ -99,24 +115,32 @@ impl<A: 'static + AES128<'static> + AES128Ctr + AES128CBC + AES128ECB> Component
     }
 }
 
-pub struct AesDriverComponent<A: AES128<'static> + AES128CCM<'static> + 'static> {
+pub struct AesDriverComponent<
+    A: AES128<'static> + AES128CCM<'static> + 'static,
+    C: ChaCha20Poly1305<'static> + 'static,
+> {
     board_kernel: &'static kernel::Kernel,
     driver_num: usize,
     aes: &'static A,
+    chacha: &'static C,
 }
 
-impl<A: AES128<'static> + AES128Ctr + AES128CBC + AES128ECB + AES128CCM<'static>>
-    AesDriverComponent<A>
+impl<
+        A: AES128<'static> + AES128Ctr + AES128CBC + AES128ECB + AES128CCM<'static>,
+        C: ChaCha20Poly1305<'static>,
+    > AesDriverComponent<A, C>
 {
     pub fn new(
         board_kernel: &'static kernel::Kernel,
         driver_num: usize,
         aes: &'static A,
-    ) -> AesDriverComponent<A> {
+        chacha: &'static C,
+    ) -> AesDriverComponent<A, C> {
         AesDriverComponent {
             board_kernel,
             driver_num,
             aes,
+            chacha,
         }
     }
 }
@@ -128,33 +152,77 @@ impl<
             + AES128ECB
             + AES128CCM<'static>
             + AES128GCM<'static>,
-    > Component for AesDriverComponent<A>
+        C: ChaCha20Poly1305<'static>,
+    > Component for AesDriverComponent<A, C>
 {
     type StaticInput = (
-        &'static mut MaybeUninit<capsules_extra::symmetric_encryption::aes::AesDriver<'static, A>>,
+        &'static mut MaybeUninit<
+            capsules_extra::symmetric_encryption::aes::AesDriver<'static, A, C>,
+        >,
         &'static mut MaybeUninit<[u8; 16]>,
         &'static mut MaybeUninit<[u8; CRYPT_SIZE]>,
+        &'static mut MaybeUninit<[u8; hil::symmetric_encryption::AES_MAX_KEY_SIZE]>,
     );
-    type Output = &'static capsules_extra::symmetric_encryption::aes::AesDriver<'static, A>;
+    type Output = &'static capsules_extra::symmetric_encryption::aes::AesDriver<'static, A, C>;
 
     fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
         let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
         let aes_src_buf = static_buffer.1.write([0; 16]);
         let aes_dst_buf = static_buffer.2.write([0; CRYPT_SIZE]);
+        let aes_key_buf = static_buffer
+            .3
+            .write([0; hil::symmetric_encryption::AES_MAX_KEY_SIZE]);
 
         let aes_driver =
             static_buffer
                 .0
                 .write(capsules_extra::symmetric_encryption::aes::AesDriver::new(
                     self.aes,
+                    self.chacha,
                     aes_src_buf,
                     aes_dst_buf,
+                    aes_key_buf,
                     self.board_kernel.create_grant(self.driver_num, &grant_cap),
                 ));
 
         hil::symmetric_encryption::AES128CCM::set_client(self.aes, aes_driver);
         hil::symmetric_encryption::AES128::set_client(self.aes, aes_driver);
+        hil::symmetric_encryption::ChaCha20Poly1305::set_client(self.chacha, aes_driver);
 
         aes_driver
     }
 }
+
+#[macro_export]
+macro_rules! aes_software_component_static {
+    ($(,)?) => {{
+        kernel::static_buf!(
+            capsules_extra::symmetric_encryption::aes_software::Aes128Software<'static>
+        )
+    };};
+}
+
+pub struct AesSoftwareComponent {}
+
+impl AesSoftwareComponent {
+    pub fn new() -> AesSoftwareComponent {
+        AesSoftwareComponent {}
+    }
+}
+
+impl Component for AesSoftwareComponent {
+    type StaticInput = &'static mut MaybeUninit<
+        capsules_extra::symmetric_encryption::aes_software::Aes128Software<'static>,
+    >;
+    type Output =
+        &'static capsules_extra::symmetric_encryption::aes_software::Aes128Software<'static>;
+
+    fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let aes =
+            s.write(capsules_extra::symmetric_encryption::aes_software::Aes128Software::new());
+
+        kernel::deferred_call::DeferredCallClient::register(aes);
+
+        aes
+    }
+}
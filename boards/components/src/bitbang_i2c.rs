@@ -0,0 +1,90 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Component for an I2C controller bit-banged over GPIO pins.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let i2c = components::bitbang_i2c::BitbangI2CComponent::new(
+//!     mux_alarm,
+//!     &nrf52840_peripherals.gpio_port[SCL_PIN],
+//!     &nrf52840_peripherals.gpio_port[SDA_PIN],
+//!     100_000,
+//! )
+//! .finalize(components::bitbang_i2c_component_static!(
+//!     nrf52840::rtc::Rtc<'static>
+//! ));
+//! ```
+
+use capsules_core::virtualizers::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use capsules_extra::bitbang_i2c::BitbangI2C;
+use core::mem::MaybeUninit;
+use kernel::component::Component;
+use kernel::hil::gpio::Pin;
+use kernel::hil::i2c::I2CMaster;
+use kernel::hil::time::Alarm;
+
+#[macro_export]
+macro_rules! bitbang_i2c_component_static {
+    ($A:ty $(,)?) => {{
+        let alarm = kernel::static_buf!(
+            capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>
+        );
+        let i2c = kernel::static_buf!(
+            capsules_extra::bitbang_i2c::BitbangI2C<
+                'static,
+                capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>,
+            >
+        );
+
+        (alarm, i2c)
+    };};
+}
+
+pub type BitbangI2CComponentType<A> = BitbangI2C<'static, VirtualMuxAlarm<'static, A>>;
+
+pub struct BitbangI2CComponent<A: 'static + Alarm<'static>> {
+    mux_alarm: &'static MuxAlarm<'static, A>,
+    scl: &'static dyn Pin,
+    sda: &'static dyn Pin,
+    speed: u32,
+}
+
+impl<A: 'static + Alarm<'static>> BitbangI2CComponent<A> {
+    /// Create the component, clocking the bus at up to `speed` Hz.
+    pub fn new(
+        mux_alarm: &'static MuxAlarm<'static, A>,
+        scl: &'static dyn Pin,
+        sda: &'static dyn Pin,
+        speed: u32,
+    ) -> BitbangI2CComponent<A> {
+        BitbangI2CComponent {
+            mux_alarm,
+            scl,
+            sda,
+            speed,
+        }
+    }
+}
+
+impl<A: 'static + Alarm<'static>> Component for BitbangI2CComponent<A> {
+    type StaticInput = (
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+        &'static mut MaybeUninit<BitbangI2C<'static, VirtualMuxAlarm<'static, A>>>,
+    );
+    type Output = &'static BitbangI2C<'static, VirtualMuxAlarm<'static, A>>;
+
+    fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let alarm = s.0.write(VirtualMuxAlarm::new(self.mux_alarm));
+        alarm.setup();
+
+        let i2c =
+            s.1.write(BitbangI2C::new(alarm, self.scl, self.sda, self.speed));
+        alarm.set_alarm_client(i2c);
+        i2c.enable();
+
+        i2c
+    }
+}
@@ -0,0 +1,76 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Component for a 1-Wire bus controller bit-banged over a GPIO pin.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let onewire = components::bitbang_onewire::BitbangOneWireComponent::new(
+//!     mux_alarm,
+//!     &earlgrey::gpio::PORT[ONEWIRE_PIN],
+//! )
+//! .finalize(components::bitbang_onewire_component_static!(
+//!     earlgrey::timer::RvTimer<'static>
+//! ));
+//! ```
+
+use capsules_core::virtualizers::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use capsules_extra::bitbang_onewire::BitbangOneWire;
+use core::mem::MaybeUninit;
+use kernel::component::Component;
+use kernel::hil::gpio::Pin;
+use kernel::hil::time::Alarm;
+
+#[macro_export]
+macro_rules! bitbang_onewire_component_static {
+    ($A:ty $(,)?) => {{
+        let alarm = kernel::static_buf!(
+            capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>
+        );
+        let onewire = kernel::static_buf!(
+            capsules_extra::bitbang_onewire::BitbangOneWire<
+                'static,
+                capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>,
+            >
+        );
+
+        (alarm, onewire)
+    };};
+}
+
+pub type BitbangOneWireComponentType<A> = BitbangOneWire<'static, VirtualMuxAlarm<'static, A>>;
+
+pub struct BitbangOneWireComponent<A: 'static + Alarm<'static>> {
+    mux_alarm: &'static MuxAlarm<'static, A>,
+    pin: &'static dyn Pin,
+}
+
+impl<A: 'static + Alarm<'static>> BitbangOneWireComponent<A> {
+    pub fn new(
+        mux_alarm: &'static MuxAlarm<'static, A>,
+        pin: &'static dyn Pin,
+    ) -> BitbangOneWireComponent<A> {
+        BitbangOneWireComponent { mux_alarm, pin }
+    }
+}
+
+impl<A: 'static + Alarm<'static>> Component for BitbangOneWireComponent<A> {
+    type StaticInput = (
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+        &'static mut MaybeUninit<BitbangOneWire<'static, VirtualMuxAlarm<'static, A>>>,
+    );
+    type Output = &'static BitbangOneWire<'static, VirtualMuxAlarm<'static, A>>;
+
+    fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let alarm = s.0.write(VirtualMuxAlarm::new(self.mux_alarm));
+        alarm.setup();
+
+        let onewire = s.1.write(BitbangOneWire::new(alarm, self.pin));
+        alarm.set_alarm_client(onewire);
+        onewire.init();
+
+        onewire
+    }
+}
@@ -0,0 +1,89 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Component for an SPI controller bit-banged over GPIO pins.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let spi = components::bitbang_spi::BitbangSpiComponent::new(
+//!     mux_alarm,
+//!     &nrf52840_peripherals.gpio_port[SCK_PIN],
+//!     &nrf52840_peripherals.gpio_port[MOSI_PIN],
+//!     &nrf52840_peripherals.gpio_port[MISO_PIN],
+//! )
+//! .finalize(components::bitbang_spi_component_static!(
+//!     nrf52840::rtc::Rtc<'static>
+//! ));
+//! ```
+
+use capsules_core::virtualizers::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use capsules_extra::bitbang_spi::BitbangSpi;
+use core::mem::MaybeUninit;
+use kernel::component::Component;
+use kernel::hil::gpio::Pin;
+use kernel::hil::spi::SpiMaster;
+use kernel::hil::time::Alarm;
+
+#[macro_export]
+macro_rules! bitbang_spi_component_static {
+    ($A:ty $(,)?) => {{
+        let alarm = kernel::static_buf!(
+            capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>
+        );
+        let spi = kernel::static_buf!(
+            capsules_extra::bitbang_spi::BitbangSpi<
+                'static,
+                capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>,
+            >
+        );
+
+        (alarm, spi)
+    };};
+}
+
+pub type BitbangSpiComponentType<A> = BitbangSpi<'static, VirtualMuxAlarm<'static, A>>;
+
+pub struct BitbangSpiComponent<A: 'static + Alarm<'static>> {
+    mux_alarm: &'static MuxAlarm<'static, A>,
+    sck: &'static dyn Pin,
+    mosi: &'static dyn Pin,
+    miso: &'static dyn Pin,
+}
+
+impl<A: 'static + Alarm<'static>> BitbangSpiComponent<A> {
+    pub fn new(
+        mux_alarm: &'static MuxAlarm<'static, A>,
+        sck: &'static dyn Pin,
+        mosi: &'static dyn Pin,
+        miso: &'static dyn Pin,
+    ) -> BitbangSpiComponent<A> {
+        BitbangSpiComponent {
+            mux_alarm,
+            sck,
+            mosi,
+            miso,
+        }
+    }
+}
+
+impl<A: 'static + Alarm<'static>> Component for BitbangSpiComponent<A> {
+    type StaticInput = (
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+        &'static mut MaybeUninit<BitbangSpi<'static, VirtualMuxAlarm<'static, A>>>,
+    );
+    type Output = &'static BitbangSpi<'static, VirtualMuxAlarm<'static, A>>;
+
+    fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let alarm = s.0.write(VirtualMuxAlarm::new(self.mux_alarm));
+        alarm.setup();
+
+        let spi =
+            s.1.write(BitbangSpi::new(alarm, self.sck, self.mosi, self.miso));
+        alarm.set_alarm_client(spi);
+        let _ = spi.init();
+
+        spi
+    }
+}
@@ -0,0 +1,48 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Component for the software ChaCha20-Poly1305 implementation.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let chacha = components::chacha20_poly1305::ChaCha20Poly1305SoftwareComponent::new()
+//!     .finalize(components::chacha20_poly1305_software_component_static!());
+//! ```
+
+use capsules_extra::symmetric_encryption::chacha20_poly1305::ChaCha20Poly1305Software;
+use core::mem::MaybeUninit;
+use kernel::component::Component;
+
+#[macro_export]
+macro_rules! chacha20_poly1305_software_component_static {
+    ($(,)?) => {{
+        kernel::static_buf!(
+            capsules_extra::symmetric_encryption::chacha20_poly1305::ChaCha20Poly1305Software<
+                'static,
+            >
+        )
+    };};
+}
+
+pub struct ChaCha20Poly1305SoftwareComponent {}
+
+impl ChaCha20Poly1305SoftwareComponent {
+    pub fn new() -> ChaCha20Poly1305SoftwareComponent {
+        ChaCha20Poly1305SoftwareComponent {}
+    }
+}
+
+impl Component for ChaCha20Poly1305SoftwareComponent {
+    type StaticInput = &'static mut MaybeUninit<ChaCha20Poly1305Software<'static>>;
+    type Output = &'static ChaCha20Poly1305Software<'static>;
+
+    fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let chacha = s.write(ChaCha20Poly1305Software::new());
+
+        kernel::deferred_call::DeferredCallClient::register(chacha);
+
+        chacha
+    }
+}
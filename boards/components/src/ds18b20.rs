@@ -0,0 +1,80 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Component for DS18B20 temperature probes on a 1-Wire bus.
+//!
+//! The driver must be the only client of the bus.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let ds18b20 = components::ds18b20::Ds18b20Component::new(mux_alarm, onewire)
+//!     .finalize(components::ds18b20_component_static!(
+//!         earlgrey::timer::RvTimer<'static>,
+//!         4
+//!     ));
+//! ```
+
+use capsules_core::virtualizers::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use capsules_extra::ds18b20::Ds18b20;
+use core::mem::MaybeUninit;
+use kernel::component::Component;
+use kernel::hil::onewire::OneWire;
+use kernel::hil::time::Alarm;
+
+#[macro_export]
+macro_rules! ds18b20_component_static {
+    ($A:ty, $probes:literal $(,)?) => {{
+        let alarm = kernel::static_buf!(
+            capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>
+        );
+        let roms = kernel::static_buf!([u64; $probes]);
+        let ds18b20 = kernel::static_buf!(
+            capsules_extra::ds18b20::Ds18b20<
+                'static,
+                capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>,
+            >
+        );
+
+        (alarm, roms, ds18b20)
+    };};
+}
+
+pub type Ds18b20ComponentType<A> = Ds18b20<'static, VirtualMuxAlarm<'static, A>>;
+
+pub struct Ds18b20Component<A: 'static + Alarm<'static>, const PROBES: usize> {
+    mux_alarm: &'static MuxAlarm<'static, A>,
+    onewire: &'static dyn OneWire<'static>,
+}
+
+impl<A: 'static + Alarm<'static>, const PROBES: usize> Ds18b20Component<A, PROBES> {
+    /// Create the component, keeping the ROM codes of up to `PROBES` probes.
+    pub fn new(
+        mux_alarm: &'static MuxAlarm<'static, A>,
+        onewire: &'static dyn OneWire<'static>,
+    ) -> Ds18b20Component<A, PROBES> {
+        Ds18b20Component { mux_alarm, onewire }
+    }
+}
+
+impl<A: 'static + Alarm<'static>, const PROBES: usize> Component for Ds18b20Component<A, PROBES> {
+    type StaticInput = (
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+        &'static mut MaybeUninit<[u64; PROBES]>,
+        &'static mut MaybeUninit<Ds18b20<'static, VirtualMuxAlarm<'static, A>>>,
+    );
+    type Output = &'static Ds18b20<'static, VirtualMuxAlarm<'static, A>>;
+
+    fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let alarm = s.0.write(VirtualMuxAlarm::new(self.mux_alarm));
+        alarm.setup();
+
+        let roms = s.1.write([0; PROBES]);
+        let ds18b20 = s.2.write(Ds18b20::new(self.onewire, alarm, roms));
+        alarm.set_alarm_client(ds18b20);
+        self.onewire.set_client(ds18b20);
+
+        ds18b20
+    }
+}
@@ -0,0 +1,164 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Components for ECDH key agreement.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let x25519 = components::ecdh::X25519SoftwareComponent::new()
+//!     .finalize(components::x25519_software_component_static!());
+//!
+//! let ecdh = components::ecdh::EcdhDriverComponent::new(
+//!     board_kernel,
+//!     capsules_extra::ecdh::DRIVER_NUM,
+//!     x25519,
+//!     &peripherals.trng,
+//! )
+//! .finalize(components::ecdh_driver_component_static!(
+//!     capsules_extra::public_key_crypto::x25519::X25519Software<'static>,
+//!     32,
+//!     32
+//! ));
+//! ```
+
+use capsules_extra::ecdh::EcdhDriver;
+use capsules_extra::public_key_crypto::p256::P256EcdhSoftware;
+use capsules_extra::public_key_crypto::x25519::X25519Software;
+use core::mem::MaybeUninit;
+use kernel::capabilities;
+use kernel::component::Component;
+use kernel::create_capability;
+use kernel::hil::entropy::Entropy32;
+use kernel::hil::public_key_crypto::ecdh::Ecdh;
+
+#[macro_export]
+macro_rules! x25519_software_component_static {
+    ($(,)?) => {{
+        kernel::static_buf!(capsules_extra::public_key_crypto::x25519::X25519Software<'static>)
+    };};
+}
+
+#[macro_export]
+macro_rules! p256_ecdh_software_component_static {
+    ($(,)?) => {{
+        kernel::static_buf!(capsules_extra::public_key_crypto::p256::P256EcdhSoftware<'static>)
+    };};
+}
+
+#[macro_export]
+macro_rules! ecdh_driver_component_static {
+    ($E:ty, $PKL:expr, $SSL:expr $(,)?) => {{
+        let driver = kernel::static_buf!(capsules_extra::ecdh::EcdhDriver<'static, $E, $PKL, $SSL>);
+        let public_key = kernel::static_buf!([u8; $PKL]);
+        let peer_public_key = kernel::static_buf!([u8; $PKL]);
+        let shared_secret = kernel::static_buf!([u8; $SSL]);
+
+        (driver, public_key, peer_public_key, shared_secret)
+    };};
+}
+
+pub struct X25519SoftwareComponent {}
+
+impl X25519SoftwareComponent {
+    pub fn new() -> X25519SoftwareComponent {
+        X25519SoftwareComponent {}
+    }
+}
+
+impl Component for X25519SoftwareComponent {
+    type StaticInput = &'static mut MaybeUninit<X25519Software<'static>>;
+    type Output = &'static X25519Software<'static>;
+
+    fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let x25519 = s.write(X25519Software::new());
+
+        kernel::deferred_call::DeferredCallClient::register(x25519);
+
+        x25519
+    }
+}
+
+pub struct P256EcdhSoftwareComponent {}
+
+impl P256EcdhSoftwareComponent {
+    pub fn new() -> P256EcdhSoftwareComponent {
+        P256EcdhSoftwareComponent {}
+    }
+}
+
+impl Component for P256EcdhSoftwareComponent {
+    type StaticInput = &'static mut MaybeUninit<P256EcdhSoftware<'static>>;
+    type Output = &'static P256EcdhSoftware<'static>;
+
+    fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let p256 = s.write(P256EcdhSoftware::new());
+
+        kernel::deferred_call::DeferredCallClient::register(p256);
+
+        p256
+    }
+}
+
+pub struct EcdhDriverComponent<
+    E: 'static + Ecdh<'static, PKL, SSL>,
+    const PKL: usize,
+    const SSL: usize,
+> {
+    board_kernel: &'static kernel::Kernel,
+    driver_num: usize,
+    ecdh: &'static E,
+    trng: &'static dyn Entropy32<'static>,
+}
+
+impl<E: 'static + Ecdh<'static, PKL, SSL>, const PKL: usize, const SSL: usize>
+    EcdhDriverComponent<E, PKL, SSL>
+{
+    pub fn new(
+        board_kernel: &'static kernel::Kernel,
+        driver_num: usize,
+        ecdh: &'static E,
+        trng: &'static dyn Entropy32<'static>,
+    ) -> EcdhDriverComponent<E, PKL, SSL> {
+        EcdhDriverComponent {
+            board_kernel,
+            driver_num,
+            ecdh,
+            trng,
+        }
+    }
+}
+
+impl<E: 'static + Ecdh<'static, PKL, SSL>, const PKL: usize, const SSL: usize> Component
+    for EcdhDriverComponent<E, PKL, SSL>
+{
+    type StaticInput = (
+        &'static mut MaybeUninit<EcdhDriver<'static, E, PKL, SSL>>,
+        &'static mut MaybeUninit<[u8; PKL]>,
+        &'static mut MaybeUninit<[u8; PKL]>,
+        &'static mut MaybeUninit<[u8; SSL]>,
+    );
+    type Output = &'static EcdhDriver<'static, E, PKL, SSL>;
+
+    fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+
+        let public_key = s.1.write([0; PKL]);
+        let peer_public_key = s.2.write([0; PKL]);
+        let shared_secret = s.3.write([0; SSL]);
+
+        let ecdh = s.0.write(EcdhDriver::new(
+            self.ecdh,
+            self.trng,
+            public_key,
+            peer_public_key,
+            shared_secret,
+            self.board_kernel.create_grant(self.driver_num, &grant_cap),
+        ));
+
+        self.ecdh.set_client(ecdh);
+
+        ecdh
+    }
+}
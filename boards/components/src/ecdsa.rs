@@ -0,0 +1,61 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Component for software ECDSA signing.
+//!
+//! The signer gets its own virtual RNG device from `mux_rng` for the
+//! per-signature secret.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let ecdsa = components::ecdsa::P256EcdsaSoftwareComponent::new(mux_rng)
+//!     .finalize(components::p256_ecdsa_software_component_static!());
+//! ```
+
+use capsules_core::virtualizers::virtual_rng::{MuxRngMaster, VirtualRngMasterDevice};
+use capsules_extra::public_key_crypto::ecdsa_software::P256EcdsaSoftware;
+use core::mem::MaybeUninit;
+use kernel::component::Component;
+use kernel::hil::rng::Rng;
+
+#[macro_export]
+macro_rules! p256_ecdsa_software_component_static {
+    ($(,)?) => {{
+        let rng = kernel::static_buf!(
+            capsules_core::virtualizers::virtual_rng::VirtualRngMasterDevice<'static>
+        );
+        let ecdsa = kernel::static_buf!(
+            capsules_extra::public_key_crypto::ecdsa_software::P256EcdsaSoftware<'static>
+        );
+
+        (rng, ecdsa)
+    };};
+}
+
+pub struct P256EcdsaSoftwareComponent {
+    mux_rng: &'static MuxRngMaster<'static>,
+}
+
+impl P256EcdsaSoftwareComponent {
+    pub fn new(mux_rng: &'static MuxRngMaster<'static>) -> P256EcdsaSoftwareComponent {
+        P256EcdsaSoftwareComponent { mux_rng }
+    }
+}
+
+impl Component for P256EcdsaSoftwareComponent {
+    type StaticInput = (
+        &'static mut MaybeUninit<VirtualRngMasterDevice<'static>>,
+        &'static mut MaybeUninit<P256EcdsaSoftware<'static>>,
+    );
+    type Output = &'static P256EcdsaSoftware<'static>;
+
+    fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let rng = s.0.write(VirtualRngMasterDevice::new(self.mux_rng));
+        let ecdsa = s.1.write(P256EcdsaSoftware::new(rng));
+        rng.set_client(ecdsa);
+
+        ecdsa
+    }
+}
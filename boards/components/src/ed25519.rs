@@ -0,0 +1,73 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Component for software Ed25519 signature verification.
+//!
+//! The verifier becomes the client of the SHA-512 engine, so the engine can't
+//! be shared with other users.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let sha512 = components::sha::ShaSoftware512Component::new()
+//!     .finalize(components::sha_software_512_component_static!(64));
+//! let ed25519 = components::ed25519::Ed25519SoftwareComponent::new(sha512)
+//!     .finalize(components::ed25519_software_component_static!(
+//!         capsules_extra::sha512::Sha512Software<'static>
+//!     ));
+//! ```
+
+use capsules_extra::public_key_crypto::ed25519::{Ed25519Software, DATA_LEN};
+use core::mem::MaybeUninit;
+use kernel::component::Component;
+use kernel::hil::digest;
+
+#[macro_export]
+macro_rules! ed25519_software_component_static {
+    ($S:ty $(,)?) => {{
+        let ed25519 = kernel::static_buf!(
+            capsules_extra::public_key_crypto::ed25519::Ed25519Software<'static, $S>
+        );
+        let data = kernel::static_buf!([u8; capsules_extra::public_key_crypto::ed25519::DATA_LEN]);
+        let challenge = kernel::static_buf!([u8; 64]);
+
+        (ed25519, data, challenge)
+    };};
+}
+
+pub struct Ed25519SoftwareComponent<
+    S: digest::Sha512 + digest::DigestDataHash<'static, 64> + digest::Digest<'static, 64> + 'static,
+> {
+    sha: &'static S,
+}
+
+impl<S: digest::Sha512 + digest::DigestDataHash<'static, 64> + digest::Digest<'static, 64>>
+    Ed25519SoftwareComponent<S>
+{
+    pub fn new(sha: &'static S) -> Self {
+        Ed25519SoftwareComponent { sha }
+    }
+}
+
+impl<S: digest::Sha512 + digest::DigestDataHash<'static, 64> + digest::Digest<'static, 64>>
+    Component for Ed25519SoftwareComponent<S>
+{
+    type StaticInput = (
+        &'static mut MaybeUninit<Ed25519Software<'static, S>>,
+        &'static mut MaybeUninit<[u8; DATA_LEN]>,
+        &'static mut MaybeUninit<[u8; 64]>,
+    );
+    type Output = &'static Ed25519Software<'static, S>;
+
+    fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let data = s.1.write([0; DATA_LEN]);
+        let challenge = s.2.write([0; 64]);
+
+        let ed25519 = s.0.write(Ed25519Software::new(self.sha, data, challenge));
+
+        digest::Digest::set_client(self.sha, ed25519);
+
+        ed25519
+    }
+}
@@ -0,0 +1,336 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Components for a kernel FIDO2 authenticator.
+//!
+//! `AuthenticatorComponent` creates the CTAP2 authenticator. It must be the
+//! only client of the SHA-256, HMAC, signing, ECDH and AES engines passed to
+//! it. `CtapHidTransportComponent` connects it to a CTAP HID device.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let authenticator = components::fido::AuthenticatorComponent::new(
+//!     mux_alarm,
+//!     sha,
+//!     hmac,
+//!     ecdsa,
+//!     ecdh,
+//!     aes,
+//!     mux_rng,
+//!     virtual_kv,
+//!     StoragePermissions::new_kernel(&storage_cap),
+//!     AAGUID,
+//! )
+//! .finalize(components::authenticator_component_static!(
+//!     earlgrey::timer::RvTimer,
+//!     Sha256Type,
+//!     HmacType,
+//!     capsules_extra::public_key_crypto::ecdsa_software::P256EcdsaSoftware<'static>,
+//!     capsules_extra::public_key_crypto::p256::P256EcdhSoftware<'static>,
+//!     earlgrey::aes::Aes<'static>,
+//! ));
+//! let _ = authenticator.start();
+//!
+//! let (ctap_hid, _) = components::ctap::CtapComponent::new(...)
+//!     .finalize(components::ctap_component_static!(lowrisc::usbdev::Usb));
+//! let ctap_transport = components::fido::CtapHidTransportComponent::new(
+//!     ctap_hid,
+//!     mux_alarm,
+//!     authenticator,
+//! )
+//! .finalize(components::ctap_hid_transport_component_static!(
+//!     capsules_extra::usb::ctap::CtapHid<'static, lowrisc::usbdev::Usb>,
+//!     earlgrey::timer::RvTimer,
+//! ));
+//! ctap_hid.set_client(ctap_transport);
+//! let _ = ctap_transport.start();
+//! ```
+
+use capsules_core::rng::RandomToEntropy32;
+use capsules_core::virtualizers::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use capsules_core::virtualizers::virtual_rng::{MuxRngMaster, VirtualRngMasterDevice};
+use capsules_extra::fido::authenticator::{Authenticator, DATA_LEN, KV_KEY_LEN, KV_VALUE_LEN};
+use capsules_extra::fido::ctaphid::{CtapHidTransport, PACKET_LEN};
+use capsules_extra::fido::{CtapCommand, MAX_MESSAGE_LEN};
+use core::mem::MaybeUninit;
+use kernel::component::Component;
+use kernel::hil::digest;
+use kernel::hil::kv::KVPermissions;
+use kernel::hil::public_key_crypto::ecdh::Ecdh;
+use kernel::hil::public_key_crypto::keys::SetPrivateKey;
+use kernel::hil::public_key_crypto::signature::SignatureSign;
+use kernel::hil::rng::Rng;
+use kernel::hil::symmetric_encryption::{AES128, AES128CBC};
+use kernel::hil::time::Alarm;
+use kernel::hil::usb_hid::UsbHid;
+use kernel::storage_permissions::StoragePermissions;
+
+#[macro_export]
+macro_rules! authenticator_component_static {
+    ($A:ty, $D:ty, $H:ty, $S:ty, $E:ty, $C:ty $(,)?) => {{
+        let alarm = kernel::static_buf!(
+            capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>
+        );
+        let rng = kernel::static_buf!(
+            capsules_core::virtualizers::virtual_rng::VirtualRngMasterDevice<'static>
+        );
+        let entropy_rng = kernel::static_buf!(
+            capsules_core::virtualizers::virtual_rng::VirtualRngMasterDevice<'static>
+        );
+        let entropy = kernel::static_buf!(
+            capsules_core::rng::RandomToEntropy32<
+                'static,
+                capsules_core::virtualizers::virtual_rng::VirtualRngMasterDevice<'static>,
+            >
+        );
+        let authenticator = kernel::static_buf!(
+            capsules_extra::fido::authenticator::Authenticator<
+                'static,
+                capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>,
+                $D,
+                $H,
+                $S,
+                $E,
+                $C,
+            >
+        );
+        let data = kernel::static_buf!([u8; capsules_extra::fido::authenticator::DATA_LEN]);
+        let digest = kernel::static_buf!([u8; 32]);
+        let signature = kernel::static_buf!([u8; 64]);
+        let ecdh_public = kernel::static_buf!([u8; 64]);
+        let ecdh_secret = kernel::static_buf!([u8; 32]);
+        let kv_key = kernel::static_buf!([u8; capsules_extra::fido::authenticator::KV_KEY_LEN]);
+        let kv_value = kernel::static_buf!([u8; capsules_extra::fido::authenticator::KV_VALUE_LEN]);
+
+        (
+            alarm,
+            rng,
+            entropy_rng,
+            entropy,
+            authenticator,
+            data,
+            digest,
+            signature,
+            ecdh_public,
+            ecdh_secret,
+            kv_key,
+            kv_value,
+        )
+    };};
+}
+
+#[macro_export]
+macro_rules! ctap_hid_transport_component_static {
+    ($U:ty, $A:ty $(,)?) => {{
+        let alarm = kernel::static_buf!(
+            capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>
+        );
+        let transport = kernel::static_buf!(
+            capsules_extra::fido::ctaphid::CtapHidTransport<
+                'static,
+                $U,
+                capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>,
+            >
+        );
+        let request = kernel::static_buf!([u8; capsules_extra::fido::MAX_MESSAGE_LEN]);
+        let response = kernel::static_buf!([u8; capsules_extra::fido::MAX_MESSAGE_LEN]);
+        let send_packet = kernel::static_buf!([u8; capsules_extra::fido::ctaphid::PACKET_LEN]);
+        let recv_packet = kernel::static_buf!([u8; capsules_extra::fido::ctaphid::PACKET_LEN]);
+
+        (
+            alarm,
+            transport,
+            request,
+            response,
+            send_packet,
+            recv_packet,
+        )
+    };};
+}
+
+pub type AuthenticatorComponentType<A, D, H, S, E, C> =
+    Authenticator<'static, VirtualMuxAlarm<'static, A>, D, H, S, E, C>;
+
+pub struct AuthenticatorComponent<
+    A: 'static + Alarm<'static>,
+    D: 'static + digest::Digest<'static, 32> + digest::Sha256,
+    H: 'static + digest::Digest<'static, 32> + digest::HmacSha256,
+    S: 'static + SignatureSign<'static, 32, 64> + SetPrivateKey<32, 64>,
+    E: 'static + Ecdh<'static, 64, 32>,
+    C: 'static + AES128<'static> + AES128CBC,
+> {
+    mux_alarm: &'static MuxAlarm<'static, A>,
+    sha: &'static D,
+    hmac: &'static H,
+    signer: &'static S,
+    ecdh: &'static E,
+    aes: &'static C,
+    mux_rng: &'static MuxRngMaster<'static>,
+    kv: &'static dyn KVPermissions<'static>,
+    storage_permissions: StoragePermissions,
+    aaguid: [u8; 16],
+}
+
+impl<
+        A: 'static + Alarm<'static>,
+        D: 'static + digest::Digest<'static, 32> + digest::Sha256,
+        H: 'static + digest::Digest<'static, 32> + digest::HmacSha256,
+        S: 'static + SignatureSign<'static, 32, 64> + SetPrivateKey<32, 64>,
+        E: 'static + Ecdh<'static, 64, 32>,
+        C: 'static + AES128<'static> + AES128CBC,
+    > AuthenticatorComponent<A, D, H, S, E, C>
+{
+    pub fn new(
+        mux_alarm: &'static MuxAlarm<'static, A>,
+        sha: &'static D,
+        hmac: &'static H,
+        signer: &'static S,
+        ecdh: &'static E,
+        aes: &'static C,
+        mux_rng: &'static MuxRngMaster<'static>,
+        kv: &'static dyn KVPermissions<'static>,
+        storage_permissions: StoragePermissions,
+        aaguid: [u8; 16],
+    ) -> Self {
+        Self {
+            mux_alarm,
+            sha,
+            hmac,
+            signer,
+            ecdh,
+            aes,
+            mux_rng,
+            kv,
+            storage_permissions,
+            aaguid,
+        }
+    }
+}
+
+impl<
+        A: 'static + Alarm<'static>,
+        D: 'static + digest::Digest<'static, 32> + digest::Sha256,
+        H: 'static + digest::Digest<'static, 32> + digest::HmacSha256,
+        S: 'static + SignatureSign<'static, 32, 64> + SetPrivateKey<32, 64>,
+        E: 'static + Ecdh<'static, 64, 32>,
+        C: 'static + AES128<'static> + AES128CBC,
+    > Component for AuthenticatorComponent<A, D, H, S, E, C>
+{
+    type StaticInput = (
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+        &'static mut MaybeUninit<VirtualRngMasterDevice<'static>>,
+        &'static mut MaybeUninit<VirtualRngMasterDevice<'static>>,
+        &'static mut MaybeUninit<RandomToEntropy32<'static, VirtualRngMasterDevice<'static>>>,
+        &'static mut MaybeUninit<AuthenticatorComponentType<A, D, H, S, E, C>>,
+        &'static mut MaybeUninit<[u8; DATA_LEN]>,
+        &'static mut MaybeUninit<[u8; 32]>,
+        &'static mut MaybeUninit<[u8; 64]>,
+        &'static mut MaybeUninit<[u8; 64]>,
+        &'static mut MaybeUninit<[u8; 32]>,
+        &'static mut MaybeUninit<[u8; KV_KEY_LEN]>,
+        &'static mut MaybeUninit<[u8; KV_VALUE_LEN]>,
+    );
+    type Output = &'static AuthenticatorComponentType<A, D, H, S, E, C>;
+
+    fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let alarm = s.0.write(VirtualMuxAlarm::new(self.mux_alarm));
+        alarm.setup();
+        let rng = s.1.write(VirtualRngMasterDevice::new(self.mux_rng));
+        let entropy_rng = s.2.write(VirtualRngMasterDevice::new(self.mux_rng));
+        let entropy = s.3.write(RandomToEntropy32::new(entropy_rng));
+
+        let authenticator = s.4.write(Authenticator::new(
+            alarm,
+            self.sha,
+            self.hmac,
+            self.signer,
+            self.ecdh,
+            entropy,
+            self.aes,
+            rng,
+            self.kv,
+            self.storage_permissions,
+            self.aaguid,
+            s.5.write([0; DATA_LEN]),
+            s.6.write([0; 32]),
+            s.7.write([0; 64]),
+            s.8.write([0; 64]),
+            s.9.write([0; 32]),
+            s.10.write([0; KV_KEY_LEN]),
+            s.11.write([0; KV_VALUE_LEN]),
+        ));
+
+        alarm.set_alarm_client(authenticator);
+        rng.set_client(authenticator);
+        entropy_rng.set_client(entropy);
+        digest::Digest::set_client(self.sha, authenticator);
+        digest::Digest::set_client(self.hmac, authenticator);
+        self.signer.set_sign_client(authenticator);
+        self.ecdh.set_client(authenticator);
+        self.aes.set_client(authenticator);
+        self.kv.set_client(authenticator);
+        kernel::deferred_call::DeferredCallClient::register(authenticator);
+
+        authenticator
+    }
+}
+
+pub struct CtapHidTransportComponent<
+    U: 'static + UsbHid<'static, [u8; PACKET_LEN]>,
+    A: 'static + Alarm<'static>,
+> {
+    hid: &'static U,
+    mux_alarm: &'static MuxAlarm<'static, A>,
+    authenticator: &'static dyn CtapCommand<'static>,
+}
+
+impl<U: 'static + UsbHid<'static, [u8; PACKET_LEN]>, A: 'static + Alarm<'static>>
+    CtapHidTransportComponent<U, A>
+{
+    pub fn new(
+        hid: &'static U,
+        mux_alarm: &'static MuxAlarm<'static, A>,
+        authenticator: &'static dyn CtapCommand<'static>,
+    ) -> Self {
+        Self {
+            hid,
+            mux_alarm,
+            authenticator,
+        }
+    }
+}
+
+impl<U: 'static + UsbHid<'static, [u8; PACKET_LEN]>, A: 'static + Alarm<'static>> Component
+    for CtapHidTransportComponent<U, A>
+{
+    type StaticInput = (
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+        &'static mut MaybeUninit<CtapHidTransport<'static, U, VirtualMuxAlarm<'static, A>>>,
+        &'static mut MaybeUninit<[u8; MAX_MESSAGE_LEN]>,
+        &'static mut MaybeUninit<[u8; MAX_MESSAGE_LEN]>,
+        &'static mut MaybeUninit<[u8; PACKET_LEN]>,
+        &'static mut MaybeUninit<[u8; PACKET_LEN]>,
+    );
+    type Output = &'static CtapHidTransport<'static, U, VirtualMuxAlarm<'static, A>>;
+
+    fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let alarm = s.0.write(VirtualMuxAlarm::new(self.mux_alarm));
+        alarm.setup();
+
+        let transport = s.1.write(CtapHidTransport::new(
+            self.hid,
+            alarm,
+            self.authenticator,
+            s.2.write([0; MAX_MESSAGE_LEN]),
+            s.3.write([0; MAX_MESSAGE_LEN]),
+            s.4.write([0; PACKET_LEN]),
+            s.5.write([0; PACKET_LEN]),
+        ));
+        alarm.set_alarm_client(transport);
+        self.authenticator.set_client(transport);
+
+        transport
+    }
+}
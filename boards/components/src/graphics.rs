@@ -0,0 +1,169 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Components for drawing on a pixel screen.
+//!
+//! `GraphicsComponent` sets up the drawing layer on a screen. Either
+//! `GraphicsDriverComponent`, which gives processes drawing commands, or
+//! `ScreenTextComponent`, a text screen in a bitmap font, can then use it.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let graphics = components::graphics::GraphicsComponent::new(tft)
+//!     .finalize(components::graphics_component_static!(1024));
+//!
+//! let graphics_driver = components::graphics::GraphicsDriverComponent::new(
+//!     board_kernel,
+//!     capsules_extra::graphics_driver::DRIVER_NUM,
+//!     graphics,
+//! )
+//! .finalize(components::graphics_driver_component_static!(1024));
+//! ```
+
+use capsules_extra::graphics::{Graphics, TextStyle};
+use capsules_extra::graphics_driver::GraphicsDriver;
+use capsules_extra::screen_text::ScreenText;
+use core::mem::MaybeUninit;
+use kernel::capabilities;
+use kernel::component::Component;
+use kernel::create_capability;
+use kernel::deferred_call::DeferredCallClient;
+use kernel::hil::screen::Screen;
+
+#[macro_export]
+macro_rules! graphics_component_static {
+    ($s:literal $(,)?) => {{
+        let buffer = kernel::static_buf!([u8; $s]);
+        let graphics = kernel::static_buf!(capsules_extra::graphics::Graphics<'static>);
+
+        (buffer, graphics)
+    };};
+}
+
+pub struct GraphicsComponent<const BUF_LEN: usize> {
+    screen: &'static dyn Screen<'static>,
+}
+
+impl<const BUF_LEN: usize> GraphicsComponent<BUF_LEN> {
+    pub fn new(screen: &'static dyn Screen<'static>) -> GraphicsComponent<BUF_LEN> {
+        GraphicsComponent { screen }
+    }
+}
+
+impl<const BUF_LEN: usize> Component for GraphicsComponent<BUF_LEN> {
+    type StaticInput = (
+        &'static mut MaybeUninit<[u8; BUF_LEN]>,
+        &'static mut MaybeUninit<Graphics<'static>>,
+    );
+    type Output = &'static Graphics<'static>;
+
+    fn finalize(self, static_input: Self::StaticInput) -> Self::Output {
+        let buffer = static_input.0.write([0; BUF_LEN]);
+        let graphics = static_input.1.write(Graphics::new(self.screen, buffer));
+        self.screen.set_client(graphics);
+
+        graphics
+    }
+}
+
+#[macro_export]
+macro_rules! graphics_driver_component_static {
+    ($s:literal $(,)?) => {{
+        let buffer = kernel::static_buf!([u8; $s]);
+        let driver = kernel::static_buf!(capsules_extra::graphics_driver::GraphicsDriver<'static>);
+
+        (buffer, driver)
+    };};
+}
+
+pub struct GraphicsDriverComponent<const BUF_LEN: usize> {
+    board_kernel: &'static kernel::Kernel,
+    driver_num: usize,
+    graphics: &'static Graphics<'static>,
+}
+
+impl<const BUF_LEN: usize> GraphicsDriverComponent<BUF_LEN> {
+    pub fn new(
+        board_kernel: &'static kernel::Kernel,
+        driver_num: usize,
+        graphics: &'static Graphics<'static>,
+    ) -> GraphicsDriverComponent<BUF_LEN> {
+        GraphicsDriverComponent {
+            board_kernel,
+            driver_num,
+            graphics,
+        }
+    }
+}
+
+impl<const BUF_LEN: usize> Component for GraphicsDriverComponent<BUF_LEN> {
+    type StaticInput = (
+        &'static mut MaybeUninit<[u8; BUF_LEN]>,
+        &'static mut MaybeUninit<GraphicsDriver<'static>>,
+    );
+    type Output = &'static GraphicsDriver<'static>;
+
+    fn finalize(self, static_input: Self::StaticInput) -> Self::Output {
+        let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+
+        let buffer = static_input.0.write([0; BUF_LEN]);
+        let driver = static_input.1.write(GraphicsDriver::new(
+            self.graphics,
+            buffer,
+            self.board_kernel.create_grant(self.driver_num, &grant_cap),
+        ));
+        self.graphics.set_client(driver);
+
+        driver
+    }
+}
+
+#[macro_export]
+macro_rules! screen_text_component_static {
+    ($s:literal $(,)?) => {{
+        let text = kernel::static_buf!([u8; $s]);
+        let screen_text = kernel::static_buf!(capsules_extra::screen_text::ScreenText<'static>);
+
+        (text, screen_text)
+    };};
+}
+
+pub struct ScreenTextComponent<const TEXT_LEN: usize> {
+    graphics: &'static Graphics<'static>,
+    style: TextStyle,
+}
+
+impl<const TEXT_LEN: usize> ScreenTextComponent<TEXT_LEN> {
+    /// Create the component. The text screen has as many rows and columns of
+    /// characters in `style` as fit on the screen and in the `TEXT_LEN`
+    /// character buffer.
+    pub fn new(
+        graphics: &'static Graphics<'static>,
+        style: TextStyle,
+    ) -> ScreenTextComponent<TEXT_LEN> {
+        ScreenTextComponent { graphics, style }
+    }
+}
+
+impl<const TEXT_LEN: usize> Component for ScreenTextComponent<TEXT_LEN> {
+    type StaticInput = (
+        &'static mut MaybeUninit<[u8; TEXT_LEN]>,
+        &'static mut MaybeUninit<ScreenText<'static>>,
+    );
+    type Output = &'static ScreenText<'static>;
+
+    fn finalize(self, static_input: Self::StaticInput) -> Self::Output {
+        let text = static_input.0.write([0; TEXT_LEN]);
+        let screen_text = static_input
+            .1
+            .write(ScreenText::new(self.graphics, self.style, text));
+        self.graphics.set_client(screen_text);
+        self.graphics.set_pixel_source(screen_text);
+        screen_text.register();
+
+        screen_text
+    }
+}
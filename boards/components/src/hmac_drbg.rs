@@ -0,0 +1,137 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Component for a userspace random number generator using `HmacDrbg`.
+//!
+//! This provides one Component, HmacDrbgRngComponent, which seeds an
+//! HMAC-DRBG from an entropy source and provides its output to userspace with
+//! the RNG syscall interface. The DRBG is shared through a `MuxRngMaster`,
+//! which is also returned so that kernel capsules can use it.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let (rng, mux_rng) = components::hmac_drbg::HmacDrbgRngComponent::new(
+//!     board_kernel,
+//!     capsules_core::rng::DRIVER_NUM,
+//!     &peripherals.trng,
+//!     hmac,
+//!     capsules_extra::hmac_drbg::DEFAULT_RESEED_INTERVAL,
+//! )
+//! .finalize(components::hmac_drbg_rng_component_static!(
+//!     nrf52840::trng::Trng<'static>,
+//!     capsules_extra::hmac_sha256::HmacSha256Software<
+//!         'static,
+//!         capsules_extra::sha256::Sha256Software<'static>,
+//!     >,
+//! ));
+//! ```
+
+use capsules_core::rng::RngDriver;
+use capsules_core::virtualizers::virtual_rng::{MuxRngMaster, VirtualRngMasterDevice};
+use capsules_extra::hmac_drbg::{HmacDrbg, DATA_LEN};
+use core::mem::MaybeUninit;
+use kernel::capabilities;
+use kernel::component::Component;
+use kernel::create_capability;
+use kernel::hil::digest;
+use kernel::hil::entropy::Entropy32;
+use kernel::hil::rng::Rng;
+
+#[macro_export]
+macro_rules! hmac_drbg_rng_component_static {
+    ($E: ty, $H: ty $(,)?) => {{
+        let drbg = kernel::static_buf!(capsules_extra::hmac_drbg::HmacDrbg<'static, $E, $H>);
+        let mux =
+            kernel::static_buf!(capsules_core::virtualizers::virtual_rng::MuxRngMaster<'static>);
+        let device = kernel::static_buf!(
+            capsules_core::virtualizers::virtual_rng::VirtualRngMasterDevice<'static>
+        );
+        let rng = kernel::static_buf!(
+            capsules_core::rng::RngDriver<
+                'static,
+                capsules_core::virtualizers::virtual_rng::VirtualRngMasterDevice<'static>,
+            >
+        );
+        let data = kernel::static_buf!([u8; capsules_extra::hmac_drbg::DATA_LEN]);
+        let digest = kernel::static_buf!([u8; 32]);
+
+        (drbg, mux, device, rng, data, digest)
+    };};
+}
+
+pub type HmacDrbgRngComponentType = RngDriver<'static, VirtualRngMasterDevice<'static>>;
+
+pub struct HmacDrbgRngComponent<
+    E: Entropy32<'static> + 'static,
+    H: digest::Digest<'static, 32> + digest::HmacSha256 + 'static,
+> {
+    board_kernel: &'static kernel::Kernel,
+    driver_num: usize,
+    trng: &'static E,
+    hmac: &'static H,
+    reseed_interval: u32,
+}
+
+impl<E: Entropy32<'static>, H: digest::Digest<'static, 32> + digest::HmacSha256>
+    HmacDrbgRngComponent<E, H>
+{
+    pub fn new(
+        board_kernel: &'static kernel::Kernel,
+        driver_num: usize,
+        trng: &'static E,
+        hmac: &'static H,
+        reseed_interval: u32,
+    ) -> Self {
+        Self {
+            board_kernel,
+            driver_num,
+            trng,
+            hmac,
+            reseed_interval,
+        }
+    }
+}
+
+impl<E: Entropy32<'static>, H: digest::Digest<'static, 32> + digest::HmacSha256> Component
+    for HmacDrbgRngComponent<E, H>
+{
+    type StaticInput = (
+        &'static mut MaybeUninit<HmacDrbg<'static, E, H>>,
+        &'static mut MaybeUninit<MuxRngMaster<'static>>,
+        &'static mut MaybeUninit<VirtualRngMasterDevice<'static>>,
+        &'static mut MaybeUninit<HmacDrbgRngComponentType>,
+        &'static mut MaybeUninit<[u8; DATA_LEN]>,
+        &'static mut MaybeUninit<[u8; 32]>,
+    );
+    type Output = (
+        &'static HmacDrbgRngComponentType,
+        &'static MuxRngMaster<'static>,
+    );
+
+    fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+
+        let data = static_buffer.4.write([0; DATA_LEN]);
+        let digest = static_buffer.5.write([0; 32]);
+
+        let drbg = static_buffer.0.write(HmacDrbg::new(
+            self.trng,
+            self.hmac,
+            self.reseed_interval,
+            data,
+            digest,
+        ));
+        let mux = static_buffer.1.write(MuxRngMaster::new(drbg));
+        let device = static_buffer.2.write(VirtualRngMasterDevice::new(mux));
+        let rng = static_buffer.3.write(RngDriver::new(
+            device,
+            self.board_kernel.create_grant(self.driver_num, &grant_cap),
+        ));
+        digest::Digest::set_client(self.hmac, drbg);
+        device.set_client(rng);
+
+        (rng, mux)
+    }
+}
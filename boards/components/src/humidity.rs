@@ -12,6 +12,7 @@
 //! ```
 
 use capsules_extra::humidity::HumiditySensor;
+use capsules_extra::virtual_humidity::{MuxHumidity, VirtualHumidity};
 use core::mem::MaybeUninit;
 use kernel::capabilities;
 use kernel::component::Component;
@@ -63,3 +64,64 @@ impl<T: 'static + hil::sensors::HumidityDriver<'static>> Component for HumidityC
         humidity
     }
 }
+
+#[macro_export]
+macro_rules! humidity_mux_component_static {
+    ($H:ty $(,)?) => {{
+        kernel::static_buf!(capsules_extra::virtual_humidity::MuxHumidity<'static, $H>)
+    };};
+}
+
+pub type HumidityMuxComponentType<H> = capsules_extra::virtual_humidity::MuxHumidity<'static, H>;
+
+pub struct HumidityMuxComponent<H: 'static + hil::sensors::HumidityDriver<'static>> {
+    sensor: &'static H,
+}
+
+impl<H: 'static + hil::sensors::HumidityDriver<'static>> HumidityMuxComponent<H> {
+    pub fn new(sensor: &'static H) -> HumidityMuxComponent<H> {
+        HumidityMuxComponent { sensor }
+    }
+}
+
+impl<H: 'static + hil::sensors::HumidityDriver<'static>> Component for HumidityMuxComponent<H> {
+    type StaticInput = &'static mut MaybeUninit<MuxHumidity<'static, H>>;
+    type Output = &'static MuxHumidity<'static, H>;
+
+    fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let mux = static_buffer.write(MuxHumidity::new(self.sensor));
+        hil::sensors::HumidityDriver::set_client(self.sensor, mux);
+        mux
+    }
+}
+
+#[macro_export]
+macro_rules! virtual_humidity_component_static {
+    ($H:ty $(,)?) => {{
+        kernel::static_buf!(capsules_extra::virtual_humidity::VirtualHumidity<'static, $H>)
+    };};
+}
+
+pub type VirtualHumidityComponentType<H> =
+    capsules_extra::virtual_humidity::VirtualHumidity<'static, H>;
+
+pub struct VirtualHumidityComponent<H: 'static + hil::sensors::HumidityDriver<'static>> {
+    mux: &'static MuxHumidity<'static, H>,
+}
+
+impl<H: 'static + hil::sensors::HumidityDriver<'static>> VirtualHumidityComponent<H> {
+    pub fn new(mux: &'static MuxHumidity<'static, H>) -> VirtualHumidityComponent<H> {
+        VirtualHumidityComponent { mux }
+    }
+}
+
+impl<H: 'static + hil::sensors::HumidityDriver<'static>> Component for VirtualHumidityComponent<H> {
+    type StaticInput = &'static mut MaybeUninit<VirtualHumidity<'static, H>>;
+    type Output = &'static VirtualHumidity<'static, H>;
+
+    fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let user = static_buffer.write(VirtualHumidity::new(self.mux));
+        user.setup();
+        user
+    }
+}
@@ -0,0 +1,109 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Component for the ISO-TP syscall interface over CAN.
+//!
+//! The capsule must be the only client of the CAN controller, whose bitrate
+//! must be set before processes open sessions.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let isotp = components::isotp::IsoTpComponent::new(
+//!     board_kernel,
+//!     capsules_extra::isotp::DRIVER_NUM,
+//!     &peripherals.can1,
+//!     mux_alarm,
+//! )
+//! .finalize(components::isotp_component_static!(
+//!     stm32f429zi::can::Can<'static>,
+//!     stm32f429zi::tim2::Tim2<'static>
+//! ));
+//! ```
+
+use capsules_core::virtualizers::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use capsules_extra::isotp::IsoTp;
+use core::mem::MaybeUninit;
+use kernel::component::Component;
+use kernel::hil::can;
+use kernel::hil::time::Alarm;
+use kernel::{capabilities, create_capability};
+
+#[macro_export]
+macro_rules! isotp_component_static {
+    ($C:ty, $A:ty $(,)?) => {{
+        use kernel::hil::can;
+        use kernel::static_buf;
+
+        let alarm =
+            static_buf!(capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>);
+        let tx_buffer = static_buf!([u8; can::STANDARD_CAN_PACKET_SIZE]);
+        let rx_buffer = static_buf!([u8; can::STANDARD_CAN_PACKET_SIZE]);
+        let isotp = static_buf!(
+            capsules_extra::isotp::IsoTp<
+                'static,
+                $C,
+                capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>,
+            >
+        );
+
+        (alarm, tx_buffer, rx_buffer, isotp)
+    };};
+}
+
+pub type IsoTpComponentType<C, A> = IsoTp<'static, C, VirtualMuxAlarm<'static, A>>;
+
+pub struct IsoTpComponent<C: 'static + can::Can, A: 'static + Alarm<'static>> {
+    board_kernel: &'static kernel::Kernel,
+    driver_num: usize,
+    can: &'static C,
+    mux_alarm: &'static MuxAlarm<'static, A>,
+}
+
+impl<C: 'static + can::Can, A: 'static + Alarm<'static>> IsoTpComponent<C, A> {
+    pub fn new(
+        board_kernel: &'static kernel::Kernel,
+        driver_num: usize,
+        can: &'static C,
+        mux_alarm: &'static MuxAlarm<'static, A>,
+    ) -> IsoTpComponent<C, A> {
+        IsoTpComponent {
+            board_kernel,
+            driver_num,
+            can,
+            mux_alarm,
+        }
+    }
+}
+
+impl<C: 'static + can::Can, A: 'static + Alarm<'static>> Component for IsoTpComponent<C, A> {
+    type StaticInput = (
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+        &'static mut MaybeUninit<[u8; can::STANDARD_CAN_PACKET_SIZE]>,
+        &'static mut MaybeUninit<[u8; can::STANDARD_CAN_PACKET_SIZE]>,
+        &'static mut MaybeUninit<IsoTp<'static, C, VirtualMuxAlarm<'static, A>>>,
+    );
+    type Output = &'static IsoTp<'static, C, VirtualMuxAlarm<'static, A>>;
+
+    fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+
+        let alarm = s.0.write(VirtualMuxAlarm::new(self.mux_alarm));
+        alarm.setup();
+
+        let isotp = s.3.write(IsoTp::new(
+            self.can,
+            alarm,
+            self.board_kernel.create_grant(self.driver_num, &grant_cap),
+            s.1.write([0; can::STANDARD_CAN_PACKET_SIZE]),
+            s.2.write([0; can::STANDARD_CAN_PACKET_SIZE]),
+        ));
+        alarm.set_alarm_client(isotp);
+        can::Controller::set_client(self.can, Some(isotp));
+        can::Transmit::set_client(self.can, Some(isotp));
+        can::Receive::set_client(self.can, Some(isotp));
+
+        isotp
+    }
+}
@@ -0,0 +1,151 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Components for key derivation with HKDF and PBKDF2.
+//!
+//! `$BUF` is the length of the buffers the inputs are copied into, and `$OUT`
+//! is the longest key the driver can derive.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let kdf = components::kdf::KdfHmacSha256Component::new(hmac)
+//!     .finalize(components::kdf_hmac_sha256_component_static!(
+//!         capsules_extra::hmac_sha256::HmacSha256Software<
+//!             'static,
+//!             capsules_extra::sha256::Sha256Software<'static>,
+//!         >,
+//!         256,
+//!     ));
+//!
+//! let kdf_driver = components::kdf::KdfDriverComponent::new(
+//!     board_kernel,
+//!     capsules_extra::kdf::DRIVER_NUM,
+//!     kdf,
+//! )
+//! .finalize(components::kdf_driver_component_static!(
+//!     components::kdf::KdfHmacSha256ComponentType<
+//!         capsules_extra::hmac_sha256::HmacSha256Software<
+//!             'static,
+//!             capsules_extra::sha256::Sha256Software<'static>,
+//!         >,
+//!     >,
+//!     256,
+//!     64,
+//! ));
+//! kdf_driver.set_keystore(keystore);
+//! ```
+
+use capsules_extra::kdf::KdfDriver;
+use capsules_extra::kdf_hmac_sha256::KdfHmacSha256;
+use core::mem::MaybeUninit;
+use kernel::capabilities;
+use kernel::component::Component;
+use kernel::create_capability;
+use kernel::hil::digest;
+use kernel::hil::kdf::Kdf;
+
+#[macro_export]
+macro_rules! kdf_hmac_sha256_component_static {
+    ($H:ty, $BUF:expr $(,)?) => {{
+        let kdf = kernel::static_buf!(capsules_extra::kdf_hmac_sha256::KdfHmacSha256<'static, $H>);
+        let buffer = kernel::static_buf!([u8; $BUF]);
+        let digest = kernel::static_buf!([u8; 32]);
+
+        (kdf, buffer, digest)
+    };};
+}
+
+#[macro_export]
+macro_rules! kdf_driver_component_static {
+    ($K:ty, $BUF:expr, $OUT:expr $(,)?) => {{
+        let driver = kernel::static_buf!(capsules_extra::kdf::KdfDriver<'static, $K>);
+        let input = kernel::static_buf!([u8; $BUF]);
+        let output = kernel::static_buf!([u8; $OUT]);
+
+        (driver, input, output)
+    };};
+}
+
+pub type KdfHmacSha256ComponentType<H> = KdfHmacSha256<'static, H>;
+
+pub struct KdfHmacSha256Component<
+    H: digest::Digest<'static, 32> + digest::HmacSha256 + 'static,
+    const BUF: usize,
+> {
+    hmac: &'static H,
+}
+
+impl<H: digest::Digest<'static, 32> + digest::HmacSha256, const BUF: usize>
+    KdfHmacSha256Component<H, BUF>
+{
+    pub fn new(hmac: &'static H) -> Self {
+        Self { hmac }
+    }
+}
+
+impl<H: digest::Digest<'static, 32> + digest::HmacSha256, const BUF: usize> Component
+    for KdfHmacSha256Component<H, BUF>
+{
+    type StaticInput = (
+        &'static mut MaybeUninit<KdfHmacSha256<'static, H>>,
+        &'static mut MaybeUninit<[u8; BUF]>,
+        &'static mut MaybeUninit<[u8; 32]>,
+    );
+    type Output = &'static KdfHmacSha256<'static, H>;
+
+    fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let buffer = s.1.write([0; BUF]);
+        let digest = s.2.write([0; 32]);
+
+        let kdf = s.0.write(KdfHmacSha256::new(self.hmac, buffer, digest));
+        digest::Digest::set_client(self.hmac, kdf);
+
+        kdf
+    }
+}
+
+pub struct KdfDriverComponent<K: Kdf<'static> + 'static, const BUF: usize, const OUT: usize> {
+    board_kernel: &'static kernel::Kernel,
+    driver_num: usize,
+    kdf: &'static K,
+}
+
+impl<K: Kdf<'static>, const BUF: usize, const OUT: usize> KdfDriverComponent<K, BUF, OUT> {
+    pub fn new(board_kernel: &'static kernel::Kernel, driver_num: usize, kdf: &'static K) -> Self {
+        Self {
+            board_kernel,
+            driver_num,
+            kdf,
+        }
+    }
+}
+
+impl<K: Kdf<'static>, const BUF: usize, const OUT: usize> Component
+    for KdfDriverComponent<K, BUF, OUT>
+{
+    type StaticInput = (
+        &'static mut MaybeUninit<KdfDriver<'static, K>>,
+        &'static mut MaybeUninit<[u8; BUF]>,
+        &'static mut MaybeUninit<[u8; OUT]>,
+    );
+    type Output = &'static KdfDriver<'static, K>;
+
+    fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+
+        let input = s.1.write([0; BUF]);
+        let output = s.2.write([0; OUT]);
+
+        let driver = s.0.write(KdfDriver::new(
+            self.kdf,
+            input,
+            output,
+            self.board_kernel.create_grant(self.driver_num, &grant_cap),
+        ));
+        self.kdf.set_client(driver);
+
+        driver
+    }
+}
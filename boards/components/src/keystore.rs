@@ -0,0 +1,93 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Component for the kernel keystore.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! let keystore = components::keystore::KeyStoreComponent::new(
+//!     board_kernel,
+//!     capsules_extra::keystore::DRIVER_NUM,
+//!     &KEY_PERMISSIONS,
+//!     4,
+//! )
+//! .finalize(components::keystore_component_static!(8));
+//! ```
+
+use capsules_extra::keystore::{KernelKeyStore, KeyPermission, KeySlot, KV_KEY_LEN, KV_VALUE_LEN};
+use core::mem::MaybeUninit;
+use kernel::capabilities;
+use kernel::component::Component;
+use kernel::create_capability;
+use kernel::hil::keystore::DERIVED_KEY_LEN;
+
+#[macro_export]
+macro_rules! keystore_component_static {
+    ($SLOTS:expr $(,)?) => {{
+        let slots = kernel::static_buf!([capsules_extra::keystore::KeySlot; $SLOTS]);
+        let kv_key = kernel::static_buf!([u8; capsules_extra::keystore::KV_KEY_LEN]);
+        let kv_value = kernel::static_buf!([u8; capsules_extra::keystore::KV_VALUE_LEN]);
+        let derived_key = kernel::static_buf!([u8; kernel::hil::keystore::DERIVED_KEY_LEN]);
+        let keystore = kernel::static_buf!(capsules_extra::keystore::KernelKeyStore<'static>);
+
+        (slots, kv_key, kv_value, derived_key, keystore)
+    };};
+}
+
+pub struct KeyStoreComponent<const SLOTS: usize> {
+    board_kernel: &'static kernel::Kernel,
+    driver_num: usize,
+    permissions: &'static [KeyPermission],
+    max_app_slots: usize,
+}
+
+impl<const SLOTS: usize> KeyStoreComponent<SLOTS> {
+    pub fn new(
+        board_kernel: &'static kernel::Kernel,
+        driver_num: usize,
+        permissions: &'static [KeyPermission],
+        max_app_slots: usize,
+    ) -> Self {
+        Self {
+            board_kernel,
+            driver_num,
+            permissions,
+            max_app_slots,
+        }
+    }
+}
+
+impl<const SLOTS: usize> Component for KeyStoreComponent<SLOTS> {
+    type StaticInput = (
+        &'static mut MaybeUninit<[KeySlot; SLOTS]>,
+        &'static mut MaybeUninit<[u8; KV_KEY_LEN]>,
+        &'static mut MaybeUninit<[u8; KV_VALUE_LEN]>,
+        &'static mut MaybeUninit<[u8; DERIVED_KEY_LEN]>,
+        &'static mut MaybeUninit<KernelKeyStore<'static>>,
+    );
+    type Output = &'static KernelKeyStore<'static>;
+
+    fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+
+        let slots = static_buffer
+            .0
+            .write(core::array::from_fn(|_| KeySlot::new()));
+        let kv_key = static_buffer.1.write([0; KV_KEY_LEN]);
+        let kv_value = static_buffer.2.write([0; KV_VALUE_LEN]);
+        let derived_key = static_buffer.3.write([0; DERIVED_KEY_LEN]);
+
+        static_buffer.4.write(KernelKeyStore::new(
+            slots,
+            self.permissions,
+            self.max_app_slots,
+            kv_key,
+            kv_value,
+            derived_key,
+            self.board_kernel.create_grant(self.driver_num, &grant_cap),
+        ))
+    }
+}
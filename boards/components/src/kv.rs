@@ -5,7 +5,8 @@
 //! Components for KV stack capsules.
 
 use capsules_extra::kv_driver::KVStoreDriver;
-use capsules_extra::kv_store_permissions::KVStorePermissions;
+use capsules_extra::kv_store_encryption::KVStoreEncryption;
+use capsules_extra::kv_store_permissions::{KVStorePermissions, UsageEntry};
 use capsules_extra::tickv::{KVSystem, KeyType};
 use capsules_extra::tickv_kv_store::TicKVKVStore;
 use capsules_extra::virtual_kv::{MuxKVPermissions, VirtualKVPermissions};
@@ -156,11 +157,17 @@ impl<V: hil::kv::KVPermissions<'static> + 'static> Component for VirtualKVPermis
 macro_rules! kv_store_permissions_component_static {
     ($V:ty $(,)?) => {{
         let buffer = kernel::static_buf!([u8; capsules_extra::kv_store_permissions::HEADER_LENGTH]);
+        let scan_key =
+            kernel::static_buf!([u8; capsules_extra::kv_store_permissions::SCAN_KEY_LENGTH]);
+        let usage = kernel::static_buf!(
+            [Option<capsules_extra::kv_store_permissions::UsageEntry>;
+                capsules_extra::kv_store_permissions::USAGE_TABLE_LENGTH]
+        );
         let kv_store = kernel::static_buf!(
             capsules_extra::kv_store_permissions::KVStorePermissions<'static, $V>
         );
 
-        (kv_store, buffer)
+        (kv_store, buffer, scan_key, usage)
     };};
 }
 
@@ -181,6 +188,10 @@ impl<V: hil::kv::KV<'static> + 'static> Component for KVStorePermissionsComponen
     type StaticInput = (
         &'static mut MaybeUninit<KVStorePermissions<'static, V>>,
         &'static mut MaybeUninit<[u8; capsules_extra::kv_store_permissions::HEADER_LENGTH]>,
+        &'static mut MaybeUninit<[u8; capsules_extra::kv_store_permissions::SCAN_KEY_LENGTH]>,
+        &'static mut MaybeUninit<
+            [Option<UsageEntry>; capsules_extra::kv_store_permissions::USAGE_TABLE_LENGTH],
+        >,
     );
     type Output = &'static KVStorePermissions<'static, V>;
 
@@ -188,10 +199,16 @@ impl<V: hil::kv::KV<'static> + 'static> Component for KVStorePermissionsComponen
         let buffer = static_buffer
             .1
             .write([0; capsules_extra::kv_store_permissions::HEADER_LENGTH]);
+        let scan_key = static_buffer
+            .2
+            .write([0; capsules_extra::kv_store_permissions::SCAN_KEY_LENGTH]);
+        let usage = static_buffer
+            .3
+            .write([None; capsules_extra::kv_store_permissions::USAGE_TABLE_LENGTH]);
 
         let kv_store_permissions = static_buffer
             .0
-            .write(KVStorePermissions::new(self.kv, buffer));
+            .write(KVStorePermissions::new(self.kv, buffer, scan_key, usage));
 
         self.kv.set_client(kv_store_permissions);
 
@@ -248,3 +265,92 @@ impl<K: 'static + KVSystem<'static, K = T>, T: 'static + KeyType + Default> Comp
         kv_store
     }
 }
+
+/////////////////////
+// KV Store Encryption
+/////////////////////
+
+#[macro_export]
+macro_rules! kv_store_encryption_component_static {
+    ($V:ty, $A:ty, $R:ty, $BUF_LEN:expr $(,)?) => {{
+        let buffer = kernel::static_buf!([u8; $BUF_LEN]);
+        let kv_store = kernel::static_buf!(
+            capsules_extra::kv_store_encryption::KVStoreEncryption<'static, $V, $A, $R>
+        );
+
+        (kv_store, buffer)
+    };};
+}
+
+pub type KVStoreEncryptionComponentType<V, A, R> =
+    capsules_extra::kv_store_encryption::KVStoreEncryption<'static, V, A, R>;
+
+pub struct KVStoreEncryptionComponent<
+    V: hil::kv::KV<'static> + 'static,
+    A: hil::symmetric_encryption::AES128CCM<'static> + 'static,
+    R: hil::rng::Rng<'static> + 'static,
+    const BUF_LEN: usize,
+> {
+    kv: &'static V,
+    aes: &'static A,
+    rng: &'static R,
+    device_key: [u8; hil::symmetric_encryption::AES128_KEY_SIZE],
+    ccm_buffer_length: usize,
+}
+
+impl<
+        V: hil::kv::KV<'static> + 'static,
+        A: hil::symmetric_encryption::AES128CCM<'static> + 'static,
+        R: hil::rng::Rng<'static> + 'static,
+        const BUF_LEN: usize,
+    > KVStoreEncryptionComponent<V, A, R, BUF_LEN>
+{
+    pub fn new(
+        kv: &'static V,
+        aes: &'static A,
+        rng: &'static R,
+        device_key: [u8; hil::symmetric_encryption::AES128_KEY_SIZE],
+        ccm_buffer_length: usize,
+    ) -> Self {
+        Self {
+            kv,
+            aes,
+            rng,
+            device_key,
+            ccm_buffer_length,
+        }
+    }
+}
+
+impl<
+        V: hil::kv::KV<'static> + 'static,
+        A: hil::symmetric_encryption::AES128CCM<'static> + 'static,
+        R: hil::rng::Rng<'static> + 'static,
+        const BUF_LEN: usize,
+    > Component for KVStoreEncryptionComponent<V, A, R, BUF_LEN>
+{
+    type StaticInput = (
+        &'static mut MaybeUninit<KVStoreEncryption<'static, V, A, R>>,
+        &'static mut MaybeUninit<[u8; BUF_LEN]>,
+    );
+    type Output = &'static KVStoreEncryption<'static, V, A, R>;
+
+    fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let buffer = static_buffer.1.write([0; BUF_LEN]);
+
+        let kv_store_encryption = static_buffer.0.write(KVStoreEncryption::new(
+            self.kv,
+            self.aes,
+            self.rng,
+            self.device_key,
+            buffer,
+            self.ccm_buffer_length,
+        ));
+
+        self.kv.set_client(kv_store_encryption);
+        hil::symmetric_encryption::AES128CCM::set_client(self.aes, kv_store_encryption);
+        self.rng.set_client(kv_store_encryption);
+
+        kv_store_encryption
+    }
+}
@@ -0,0 +1,85 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Component for the syscall interface to an addressable LED strip.
+//!
+//! The frame buffers take a byte per colour channel of each LED, so 3 bytes
+//! per LED for RGB strips and 4 for RGBW ones.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let led_strip = components::led_strip::LedStripComponent::new(
+//!     board_kernel,
+//!     capsules_extra::led_strip::DRIVER_NUM,
+//!     ws2812,
+//! )
+//! .finalize(components::led_strip_component_static!(
+//!     components::ws2812::Ws2812ComponentType<rp2040::spi::Spi<'static>>,
+//!     180
+//! ));
+//! ```
+
+use capsules_extra::led_strip::LedStripDriver;
+use core::mem::MaybeUninit;
+use kernel::capabilities;
+use kernel::component::Component;
+use kernel::create_capability;
+use kernel::hil::led_strip::LedStrip;
+
+#[macro_export]
+macro_rules! led_strip_component_static {
+    ($L:ty, $len:expr $(,)?) => {{
+        let frame = kernel::static_buf!([u8; $len]);
+        let output = kernel::static_buf!([u8; $len]);
+        let driver = kernel::static_buf!(capsules_extra::led_strip::LedStripDriver<'static, $L>);
+
+        (frame, output, driver)
+    };};
+}
+
+pub struct LedStripComponent<L: 'static + LedStrip<'static>, const BUF_LEN: usize> {
+    board_kernel: &'static kernel::Kernel,
+    driver_num: usize,
+    strip: &'static L,
+}
+
+impl<L: 'static + LedStrip<'static>, const BUF_LEN: usize> LedStripComponent<L, BUF_LEN> {
+    pub fn new(
+        board_kernel: &'static kernel::Kernel,
+        driver_num: usize,
+        strip: &'static L,
+    ) -> LedStripComponent<L, BUF_LEN> {
+        LedStripComponent {
+            board_kernel,
+            driver_num,
+            strip,
+        }
+    }
+}
+
+impl<L: 'static + LedStrip<'static>, const BUF_LEN: usize> Component
+    for LedStripComponent<L, BUF_LEN>
+{
+    type StaticInput = (
+        &'static mut MaybeUninit<[u8; BUF_LEN]>,
+        &'static mut MaybeUninit<[u8; BUF_LEN]>,
+        &'static mut MaybeUninit<LedStripDriver<'static, L>>,
+    );
+    type Output = &'static LedStripDriver<'static, L>;
+
+    fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+
+        let driver = s.2.write(LedStripDriver::new(
+            self.strip,
+            self.board_kernel.create_grant(self.driver_num, &grant_cap),
+            s.0.write([0; BUF_LEN]),
+            s.1.write([0; BUF_LEN]),
+        ));
+        self.strip.set_client(driver);
+
+        driver
+    }
+}
@@ -14,6 +14,9 @@ pub mod apds9960;
 pub mod app_flash_driver;
 pub mod appid;
 pub mod atecc508a;
+pub mod bitbang_i2c;
+pub mod bitbang_onewire;
+pub mod bitbang_spi;
 pub mod ble;
 pub mod bme280;
 pub mod bmm150;
@@ -23,6 +26,7 @@ pub mod button;
 pub mod can;
 pub mod ccs811;
 pub mod cdc;
+pub mod chacha20_poly1305;
 pub mod console;
 pub mod crc;
 pub mod ctap;
@@ -30,25 +34,36 @@ pub mod dac;
 pub mod date_time;
 pub mod debug_queue;
 pub mod debug_writer;
+pub mod ds18b20;
+pub mod ecdh;
+pub mod ecdsa;
+pub mod ed25519;
 pub mod eui64;
+pub mod fido;
 pub mod flash;
 pub mod fm25cl;
 pub mod ft6x06;
 pub mod fxos8700;
 pub mod gpio;
+pub mod graphics;
 pub mod hd44780;
 pub mod hmac;
+pub mod hmac_drbg;
 pub mod hs3003;
 pub mod hts221;
 pub mod humidity;
 pub mod i2c;
 pub mod ieee802154;
 pub mod isl29035;
+pub mod isotp;
+pub mod kdf;
 pub mod keyboard_hid;
+pub mod keystore;
 pub mod kv;
 pub mod l3gd20;
 pub mod led;
 pub mod led_matrix;
+pub mod led_strip;
 pub mod lldb;
 pub mod loader;
 pub mod lpm013m126;
@@ -59,10 +74,12 @@ pub mod lsm303dlhc;
 pub mod lsm6dsox;
 pub mod ltc294x;
 pub mod mlx90614;
+pub mod modbus;
 pub mod mx25r6435f;
 pub mod ninedof;
 pub mod nonvolatile_storage;
 pub mod nrf51822;
+pub mod orientation;
 pub mod panic_button;
 pub mod pressure;
 pub mod process_console;
@@ -74,12 +91,16 @@ pub mod rng;
 pub mod sched;
 pub mod screen;
 pub mod segger_rtt;
+pub mod sensor_hub;
 pub mod sh1106;
 pub mod sha;
 pub mod sht3x;
 pub mod sht4x;
 pub mod si7021;
+pub mod signature_verify;
 pub mod siphash;
+pub mod sntp;
+pub mod software_date_time;
 pub mod sound_pressure;
 pub mod spi;
 pub mod ssd1306;
@@ -96,3 +117,4 @@ pub mod touch;
 pub mod udp_driver;
 pub mod udp_mux;
 pub mod usb;
+pub mod ws2812;
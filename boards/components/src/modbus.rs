@@ -0,0 +1,127 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Component for the Modbus RTU syscall interface over a UART.
+//!
+//! The capsule must be the only client of the UART, which the board
+//! configures at the baud rate given, usually with 8 data bits, even parity
+//! and one stop bit. The driver enable pin of an RS-485 transceiver is
+//! optional.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let modbus = components::modbus::ModbusComponent::new(
+//!     board_kernel,
+//!     capsules_extra::modbus::DRIVER_NUM,
+//!     &peripherals.usart2,
+//!     mux_alarm,
+//!     Some(&peripherals.gpio_ports.pins[0][1].as_ref().unwrap()),
+//!     19200,
+//! )
+//! .finalize(components::modbus_component_static!(
+//!     stm32f429zi::usart::Usart<'static>,
+//!     stm32f429zi::tim2::Tim2<'static>
+//! ));
+//! ```
+
+use capsules_core::virtualizers::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use capsules_extra::modbus::{Modbus, MAX_FRAME_LEN};
+use core::mem::MaybeUninit;
+use kernel::component::Component;
+use kernel::hil::gpio;
+use kernel::hil::time::Alarm;
+use kernel::hil::uart;
+use kernel::{capabilities, create_capability};
+
+#[macro_export]
+macro_rules! modbus_component_static {
+    ($U:ty, $A:ty $(,)?) => {{
+        use capsules_extra::modbus::MAX_FRAME_LEN;
+        use kernel::static_buf;
+
+        let alarm =
+            static_buf!(capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>);
+        let tx_buffer = static_buf!([u8; MAX_FRAME_LEN]);
+        let rx_buffer = static_buf!([u8; MAX_FRAME_LEN]);
+        let rx_byte = static_buf!([u8; 1]);
+        let modbus = static_buf!(
+            capsules_extra::modbus::Modbus<
+                'static,
+                $U,
+                capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>,
+            >
+        );
+
+        (alarm, tx_buffer, rx_buffer, rx_byte, modbus)
+    };};
+}
+
+pub type ModbusComponentType<U, A> = Modbus<'static, U, VirtualMuxAlarm<'static, A>>;
+
+pub struct ModbusComponent<U: 'static + uart::UartData<'static>, A: 'static + Alarm<'static>> {
+    board_kernel: &'static kernel::Kernel,
+    driver_num: usize,
+    uart: &'static U,
+    mux_alarm: &'static MuxAlarm<'static, A>,
+    driver_enable: Option<&'static dyn gpio::Pin>,
+    baud_rate: u32,
+}
+
+impl<U: 'static + uart::UartData<'static>, A: 'static + Alarm<'static>> ModbusComponent<U, A> {
+    pub fn new(
+        board_kernel: &'static kernel::Kernel,
+        driver_num: usize,
+        uart: &'static U,
+        mux_alarm: &'static MuxAlarm<'static, A>,
+        driver_enable: Option<&'static dyn gpio::Pin>,
+        baud_rate: u32,
+    ) -> ModbusComponent<U, A> {
+        ModbusComponent {
+            board_kernel,
+            driver_num,
+            uart,
+            mux_alarm,
+            driver_enable,
+            baud_rate,
+        }
+    }
+}
+
+impl<U: 'static + uart::UartData<'static>, A: 'static + Alarm<'static>> Component
+    for ModbusComponent<U, A>
+{
+    type StaticInput = (
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+        &'static mut MaybeUninit<[u8; MAX_FRAME_LEN]>,
+        &'static mut MaybeUninit<[u8; MAX_FRAME_LEN]>,
+        &'static mut MaybeUninit<[u8; 1]>,
+        &'static mut MaybeUninit<Modbus<'static, U, VirtualMuxAlarm<'static, A>>>,
+    );
+    type Output = &'static Modbus<'static, U, VirtualMuxAlarm<'static, A>>;
+
+    fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+
+        let alarm = s.0.write(VirtualMuxAlarm::new(self.mux_alarm));
+        alarm.setup();
+
+        let modbus = s.4.write(Modbus::new(
+            self.uart,
+            alarm,
+            self.driver_enable,
+            self.board_kernel.create_grant(self.driver_num, &grant_cap),
+            self.baud_rate,
+            s.1.write([0; MAX_FRAME_LEN]),
+            s.2.write([0; MAX_FRAME_LEN]),
+            s.3.write([0; 1]),
+        ));
+        alarm.set_alarm_client(modbus);
+        uart::Transmit::set_transmit_client(self.uart, modbus);
+        uart::Receive::set_receive_client(self.uart, modbus);
+        modbus.start();
+
+        modbus
+    }
+}
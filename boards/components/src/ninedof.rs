@@ -13,6 +13,7 @@
 //! ```
 
 use capsules_extra::ninedof::NineDof;
+use capsules_extra::virtual_ninedof::{MuxNineDof, VirtualNineDof};
 use core::mem::MaybeUninit;
 use kernel::capabilities;
 use kernel::component::Component;
@@ -73,3 +74,64 @@ impl Component for NineDofComponent {
         ninedof
     }
 }
+
+#[macro_export]
+macro_rules! ninedof_mux_component_static {
+    ($N:ty $(,)?) => {{
+        kernel::static_buf!(capsules_extra::virtual_ninedof::MuxNineDof<'static, $N>)
+    };};
+}
+
+pub type NineDofMuxComponentType<N> = capsules_extra::virtual_ninedof::MuxNineDof<'static, N>;
+
+pub struct NineDofMuxComponent<N: 'static + kernel::hil::sensors::NineDof<'static>> {
+    sensor: &'static N,
+}
+
+impl<N: 'static + kernel::hil::sensors::NineDof<'static>> NineDofMuxComponent<N> {
+    pub fn new(sensor: &'static N) -> NineDofMuxComponent<N> {
+        NineDofMuxComponent { sensor }
+    }
+}
+
+impl<N: 'static + kernel::hil::sensors::NineDof<'static>> Component for NineDofMuxComponent<N> {
+    type StaticInput = &'static mut MaybeUninit<MuxNineDof<'static, N>>;
+    type Output = &'static MuxNineDof<'static, N>;
+
+    fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let mux = static_buffer.write(MuxNineDof::new(self.sensor));
+        kernel::hil::sensors::NineDof::set_client(self.sensor, mux);
+        mux
+    }
+}
+
+#[macro_export]
+macro_rules! virtual_ninedof_component_static {
+    ($N:ty $(,)?) => {{
+        kernel::static_buf!(capsules_extra::virtual_ninedof::VirtualNineDof<'static, $N>)
+    };};
+}
+
+pub type VirtualNineDofComponentType<N> =
+    capsules_extra::virtual_ninedof::VirtualNineDof<'static, N>;
+
+pub struct VirtualNineDofComponent<N: 'static + kernel::hil::sensors::NineDof<'static>> {
+    mux: &'static MuxNineDof<'static, N>,
+}
+
+impl<N: 'static + kernel::hil::sensors::NineDof<'static>> VirtualNineDofComponent<N> {
+    pub fn new(mux: &'static MuxNineDof<'static, N>) -> VirtualNineDofComponent<N> {
+        VirtualNineDofComponent { mux }
+    }
+}
+
+impl<N: 'static + kernel::hil::sensors::NineDof<'static>> Component for VirtualNineDofComponent<N> {
+    type StaticInput = &'static mut MaybeUninit<VirtualNineDof<'static, N>>;
+    type Output = &'static VirtualNineDof<'static, N>;
+
+    fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let user = static_buffer.write(VirtualNineDof::new(self.mux));
+        user.setup();
+        user
+    }
+}
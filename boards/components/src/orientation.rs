@@ -0,0 +1,139 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Component for the orientation filter.
+//!
+//! The filter must be the only client of the 9DOF sensor passed to it, which
+//! can be a `VirtualNineDof` to share the sensor with the ninedof driver.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let orientation = components::orientation::OrientationComponent::new(
+//!     board_kernel,
+//!     capsules_extra::orientation::DRIVER_NUM,
+//!     mux_alarm,
+//!     ninedof_user,
+//!     virtual_kv,
+//!     StoragePermissions::new_kernel(&storage_cap),
+//!     capsules_extra::orientation::MADGWICK,
+//!     20,
+//!     1000,
+//! )
+//! .finalize(components::orientation_component_static!(
+//!     nrf52840::rtc::Rtc<'static>
+//! ));
+//! ```
+
+use capsules_core::virtualizers::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use capsules_extra::orientation::{Filter, Orientation, KV_KEY_LEN, KV_VALUE_LEN};
+use core::mem::MaybeUninit;
+use kernel::capabilities;
+use kernel::component::Component;
+use kernel::create_capability;
+use kernel::hil::kv::KVPermissions;
+use kernel::hil::sensors::NineDof;
+use kernel::hil::time::Alarm;
+use kernel::storage_permissions::StoragePermissions;
+
+#[macro_export]
+macro_rules! orientation_component_static {
+    ($A:ty $(,)?) => {{
+        let alarm = kernel::static_buf!(
+            capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>
+        );
+        let orientation = kernel::static_buf!(
+            capsules_extra::orientation::Orientation<
+                'static,
+                capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>,
+            >
+        );
+        let kv_key = kernel::static_buf!([u8; capsules_extra::orientation::KV_KEY_LEN]);
+        let kv_value = kernel::static_buf!([u8; capsules_extra::orientation::KV_VALUE_LEN]);
+
+        (alarm, orientation, kv_key, kv_value)
+    };};
+}
+
+pub type OrientationComponentType<A> = Orientation<'static, VirtualMuxAlarm<'static, A>>;
+
+pub struct OrientationComponent<A: 'static + Alarm<'static>> {
+    board_kernel: &'static kernel::Kernel,
+    driver_num: usize,
+    mux_alarm: &'static MuxAlarm<'static, A>,
+    ninedof: &'static dyn NineDof<'static>,
+    kv: &'static dyn KVPermissions<'static>,
+    storage_permissions: StoragePermissions,
+    filter: Filter,
+    period_ms: u32,
+    gyro_scale: u32,
+}
+
+impl<A: 'static + Alarm<'static>> OrientationComponent<A> {
+    /// Create the component. The sensor is sampled every `period_ms`, and its
+    /// gyroscope reports `gyro_scale` units per degree per second.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        board_kernel: &'static kernel::Kernel,
+        driver_num: usize,
+        mux_alarm: &'static MuxAlarm<'static, A>,
+        ninedof: &'static dyn NineDof<'static>,
+        kv: &'static dyn KVPermissions<'static>,
+        storage_permissions: StoragePermissions,
+        filter: Filter,
+        period_ms: u32,
+        gyro_scale: u32,
+    ) -> OrientationComponent<A> {
+        OrientationComponent {
+            board_kernel,
+            driver_num,
+            mux_alarm,
+            ninedof,
+            kv,
+            storage_permissions,
+            filter,
+            period_ms,
+            gyro_scale,
+        }
+    }
+}
+
+impl<A: 'static + Alarm<'static>> Component for OrientationComponent<A> {
+    type StaticInput = (
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+        &'static mut MaybeUninit<Orientation<'static, VirtualMuxAlarm<'static, A>>>,
+        &'static mut MaybeUninit<[u8; KV_KEY_LEN]>,
+        &'static mut MaybeUninit<[u8; KV_VALUE_LEN]>,
+    );
+    type Output = &'static Orientation<'static, VirtualMuxAlarm<'static, A>>;
+
+    fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+
+        let alarm = s.0.write(VirtualMuxAlarm::new(self.mux_alarm));
+        alarm.setup();
+
+        let kv_key = s.2.write([0; KV_KEY_LEN]);
+        let kv_value = s.3.write([0; KV_VALUE_LEN]);
+
+        let orientation = s.1.write(Orientation::new(
+            alarm,
+            self.ninedof,
+            self.kv,
+            self.storage_permissions,
+            self.filter,
+            self.period_ms,
+            self.gyro_scale,
+            kv_key,
+            kv_value,
+            self.board_kernel.create_grant(self.driver_num, &grant_cap),
+        ));
+
+        alarm.set_alarm_client(orientation);
+        self.ninedof.set_client(orientation);
+        self.kv.set_client(orientation);
+
+        orientation
+    }
+}
@@ -12,6 +12,7 @@
 //! ```
 
 use capsules_extra::pressure::PressureSensor;
+use capsules_extra::virtual_pressure::{MuxPressure, VirtualPressure};
 use core::mem::MaybeUninit;
 use kernel::capabilities;
 use kernel::component::Component;
@@ -62,3 +63,64 @@ impl<T: 'static + hil::sensors::PressureDriver<'static>> Component for PressureC
         pressure
     }
 }
+
+#[macro_export]
+macro_rules! pressure_mux_component_static {
+    ($P:ty $(,)?) => {{
+        kernel::static_buf!(capsules_extra::virtual_pressure::MuxPressure<'static, $P>)
+    };};
+}
+
+pub type PressureMuxComponentType<P> = capsules_extra::virtual_pressure::MuxPressure<'static, P>;
+
+pub struct PressureMuxComponent<P: 'static + hil::sensors::PressureDriver<'static>> {
+    sensor: &'static P,
+}
+
+impl<P: 'static + hil::sensors::PressureDriver<'static>> PressureMuxComponent<P> {
+    pub fn new(sensor: &'static P) -> PressureMuxComponent<P> {
+        PressureMuxComponent { sensor }
+    }
+}
+
+impl<P: 'static + hil::sensors::PressureDriver<'static>> Component for PressureMuxComponent<P> {
+    type StaticInput = &'static mut MaybeUninit<MuxPressure<'static, P>>;
+    type Output = &'static MuxPressure<'static, P>;
+
+    fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let mux = static_buffer.write(MuxPressure::new(self.sensor));
+        hil::sensors::PressureDriver::set_client(self.sensor, mux);
+        mux
+    }
+}
+
+#[macro_export]
+macro_rules! virtual_pressure_component_static {
+    ($P:ty $(,)?) => {{
+        kernel::static_buf!(capsules_extra::virtual_pressure::VirtualPressure<'static, $P>)
+    };};
+}
+
+pub type VirtualPressureComponentType<P> =
+    capsules_extra::virtual_pressure::VirtualPressure<'static, P>;
+
+pub struct VirtualPressureComponent<P: 'static + hil::sensors::PressureDriver<'static>> {
+    mux: &'static MuxPressure<'static, P>,
+}
+
+impl<P: 'static + hil::sensors::PressureDriver<'static>> VirtualPressureComponent<P> {
+    pub fn new(mux: &'static MuxPressure<'static, P>) -> VirtualPressureComponent<P> {
+        VirtualPressureComponent { mux }
+    }
+}
+
+impl<P: 'static + hil::sensors::PressureDriver<'static>> Component for VirtualPressureComponent<P> {
+    type StaticInput = &'static mut MaybeUninit<VirtualPressure<'static, P>>;
+    type Output = &'static VirtualPressure<'static, P>;
+
+    fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let user = static_buffer.write(VirtualPressure::new(self.mux));
+        user.setup();
+        user
+    }
+}
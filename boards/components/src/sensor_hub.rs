@@ -0,0 +1,128 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Component for the batched sensor sampling driver.
+//!
+//! The hub becomes the client of every sensor it is given, so sensors that
+//! only support one client can't also be used by other drivers.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let sensor_hub = components::sensor_hub::SensorHubComponent::new(
+//!     board_kernel,
+//!     capsules_extra::sensor_hub::DRIVER_NUM,
+//!     mux_alarm,
+//!     Some(temperature),
+//!     Some(humidity),
+//!     None,
+//!     Some(ninedof),
+//! )
+//! .finalize(components::sensor_hub_component_static!(
+//!     nrf52840::rtc::Rtc<'static>,
+//!     16
+//! ));
+//! ```
+
+use capsules_core::virtualizers::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use capsules_extra::sensor_hub::SensorHub;
+use core::mem::MaybeUninit;
+use kernel::capabilities;
+use kernel::component::Component;
+use kernel::create_capability;
+use kernel::hil::sensors;
+use kernel::hil::time::Alarm;
+
+#[macro_export]
+macro_rules! sensor_hub_component_static {
+    ($A:ty, $N:expr $(,)?) => {{
+        let alarm = kernel::static_buf!(
+            capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>
+        );
+        let hub = kernel::static_buf!(
+            capsules_extra::sensor_hub::SensorHub<
+                'static,
+                capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>,
+                $N,
+            >
+        );
+
+        (alarm, hub)
+    };};
+}
+
+pub type SensorHubComponentType<A, const N: usize> =
+    SensorHub<'static, VirtualMuxAlarm<'static, A>, N>;
+
+pub struct SensorHubComponent<A: 'static + Alarm<'static>, const N: usize> {
+    board_kernel: &'static kernel::Kernel,
+    driver_num: usize,
+    mux_alarm: &'static MuxAlarm<'static, A>,
+    temperature: Option<&'static dyn sensors::TemperatureDriver<'static>>,
+    humidity: Option<&'static dyn sensors::HumidityDriver<'static>>,
+    pressure: Option<&'static dyn sensors::PressureDriver<'static>>,
+    ninedof: Option<&'static dyn sensors::NineDof<'static>>,
+}
+
+impl<A: 'static + Alarm<'static>, const N: usize> SensorHubComponent<A, N> {
+    pub fn new(
+        board_kernel: &'static kernel::Kernel,
+        driver_num: usize,
+        mux_alarm: &'static MuxAlarm<'static, A>,
+        temperature: Option<&'static dyn sensors::TemperatureDriver<'static>>,
+        humidity: Option<&'static dyn sensors::HumidityDriver<'static>>,
+        pressure: Option<&'static dyn sensors::PressureDriver<'static>>,
+        ninedof: Option<&'static dyn sensors::NineDof<'static>>,
+    ) -> SensorHubComponent<A, N> {
+        SensorHubComponent {
+            board_kernel,
+            driver_num,
+            mux_alarm,
+            temperature,
+            humidity,
+            pressure,
+            ninedof,
+        }
+    }
+}
+
+impl<A: 'static + Alarm<'static>, const N: usize> Component for SensorHubComponent<A, N> {
+    type StaticInput = (
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+        &'static mut MaybeUninit<SensorHub<'static, VirtualMuxAlarm<'static, A>, N>>,
+    );
+    type Output = &'static SensorHub<'static, VirtualMuxAlarm<'static, A>, N>;
+
+    fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+
+        let alarm = s.0.write(VirtualMuxAlarm::new(self.mux_alarm));
+        alarm.setup();
+
+        let hub = s.1.write(SensorHub::new(
+            alarm,
+            self.temperature,
+            self.humidity,
+            self.pressure,
+            self.ninedof,
+            self.board_kernel.create_grant(self.driver_num, &grant_cap),
+        ));
+
+        alarm.set_alarm_client(hub);
+        if let Some(temperature) = self.temperature {
+            temperature.set_client(hub);
+        }
+        if let Some(humidity) = self.humidity {
+            humidity.set_client(hub);
+        }
+        if let Some(pressure) = self.pressure {
+            pressure.set_client(hub);
+        }
+        if let Some(ninedof) = self.ninedof {
+            ninedof.set_client(hub);
+        }
+
+        hub
+    }
+}
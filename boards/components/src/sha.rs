@@ -127,3 +127,33 @@ impl Component for ShaSoftware256Component {
         sha_256_sw
     }
 }
+
+#[macro_export]
+macro_rules! sha_software_512_component_static {
+    ($L:expr $(,)?) => {{
+        kernel::static_buf!(capsules_extra::sha512::Sha512Software<'static, $L>)
+    };};
+}
+
+/// Software SHA-512, or SHA-384 if `L` is 48.
+pub struct ShaSoftware512Component<const L: usize> {}
+
+impl<const L: usize> ShaSoftware512Component<L> {
+    pub fn new() -> ShaSoftware512Component<L> {
+        ShaSoftware512Component {}
+    }
+}
+
+impl<const L: usize> Component for ShaSoftware512Component<L> {
+    type StaticInput = &'static mut MaybeUninit<capsules_extra::sha512::Sha512Software<'static, L>>;
+
+    type Output = &'static capsules_extra::sha512::Sha512Software<'static, L>;
+
+    fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let sha_512_sw = s.write(capsules_extra::sha512::Sha512Software::new());
+
+        kernel::deferred_call::DeferredCallClient::register(sha_512_sw);
+
+        sha_512_sw
+    }
+}
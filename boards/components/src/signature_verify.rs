@@ -0,0 +1,109 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Component for the userspace signature verification driver.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let signature_verify = components::signature_verify::SignatureVerifyDriverComponent::new(
+//!     board_kernel,
+//!     capsules_extra::signature_verify::DRIVER_NUM,
+//!     ed25519,
+//! )
+//! .finalize(components::signature_verify_driver_component_static!(
+//!     capsules_extra::public_key_crypto::ed25519::Ed25519Software<
+//!         'static,
+//!         capsules_extra::sha512::Sha512Software<'static>,
+//!     >,
+//!     64,
+//!     64,
+//!     32
+//! ));
+//! ```
+
+use capsules_extra::signature_verify::SignatureVerifyDriver;
+use core::mem::MaybeUninit;
+use kernel::capabilities;
+use kernel::component::Component;
+use kernel::create_capability;
+use kernel::hil::public_key_crypto::keys::SetPublicKey;
+use kernel::hil::public_key_crypto::signature::SignatureVerify;
+
+#[macro_export]
+macro_rules! signature_verify_driver_component_static {
+    ($V:ty, $HL:expr, $SL:expr, $PKL:expr $(,)?) => {{
+        let driver = kernel::static_buf!(
+            capsules_extra::signature_verify::SignatureVerifyDriver<'static, $V, $HL, $SL, $PKL>
+        );
+        let hash = kernel::static_buf!([u8; $HL]);
+        let signature = kernel::static_buf!([u8; $SL]);
+
+        (driver, hash, signature)
+    };};
+}
+
+pub struct SignatureVerifyDriverComponent<
+    V: 'static + SignatureVerify<'static, HL, SL> + SetPublicKey<PKL>,
+    const HL: usize,
+    const SL: usize,
+    const PKL: usize,
+> {
+    board_kernel: &'static kernel::Kernel,
+    driver_num: usize,
+    verifier: &'static V,
+}
+
+impl<
+        V: 'static + SignatureVerify<'static, HL, SL> + SetPublicKey<PKL>,
+        const HL: usize,
+        const SL: usize,
+        const PKL: usize,
+    > SignatureVerifyDriverComponent<V, HL, SL, PKL>
+{
+    pub fn new(
+        board_kernel: &'static kernel::Kernel,
+        driver_num: usize,
+        verifier: &'static V,
+    ) -> SignatureVerifyDriverComponent<V, HL, SL, PKL> {
+        SignatureVerifyDriverComponent {
+            board_kernel,
+            driver_num,
+            verifier,
+        }
+    }
+}
+
+impl<
+        V: 'static + SignatureVerify<'static, HL, SL> + SetPublicKey<PKL>,
+        const HL: usize,
+        const SL: usize,
+        const PKL: usize,
+    > Component for SignatureVerifyDriverComponent<V, HL, SL, PKL>
+{
+    type StaticInput = (
+        &'static mut MaybeUninit<SignatureVerifyDriver<'static, V, HL, SL, PKL>>,
+        &'static mut MaybeUninit<[u8; HL]>,
+        &'static mut MaybeUninit<[u8; SL]>,
+    );
+    type Output = &'static SignatureVerifyDriver<'static, V, HL, SL, PKL>;
+
+    fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+
+        let hash = s.1.write([0; HL]);
+        let signature = s.2.write([0; SL]);
+
+        let driver = s.0.write(SignatureVerifyDriver::new(
+            self.verifier,
+            hash,
+            signature,
+            self.board_kernel.create_grant(self.driver_num, &grant_cap),
+        ));
+
+        self.verifier.set_verify_client(driver);
+
+        driver
+    }
+}
@@ -0,0 +1,198 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Component for the SNTP client.
+//!
+//! Binds the client to a local UDP port, from which it queries the NTP
+//! server given every `poll_interval_s` seconds to keep a software clock in
+//! time. The first query is sent when the component is finalized.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let sntp = components::sntp::SntpComponent::new(
+//!     board_kernel,
+//!     capsules_extra::net::sntp::DRIVER_NUM,
+//!     udp_send_mux,
+//!     udp_recv_mux,
+//!     udp_port_table,
+//!     mux_alarm,
+//!     clock,
+//!     SERVER_ADDR,
+//!     SNTP_LOCAL_PORT,
+//!     1024,
+//! )
+//! .finalize(components::sntp_component_static!(nrf52840::rtc::Rtc));
+//! ```
+
+use capsules_core::virtualizers::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use capsules_extra::net::ipv6::ip_utils::IPAddr;
+use capsules_extra::net::ipv6::ipv6_send::IP6SendStruct;
+use capsules_extra::net::network_capabilities::{
+    AddrRange, NetworkCapability, PortRange, UdpVisibilityCapability,
+};
+use capsules_extra::net::sntp::{SntpClient, NTP_PORT, PACKET_LEN};
+use capsules_extra::net::udp::udp_port_table::UdpPortManager;
+use capsules_extra::net::udp::udp_recv::{MuxUdpReceiver, UDPReceiver};
+use capsules_extra::net::udp::udp_send::{MuxUdpSender, UDPSendStruct, UDPSender};
+use capsules_extra::software_date_time::SoftwareDateTime;
+use core::mem::MaybeUninit;
+use kernel::capabilities::NetworkCapabilityCreationCapability;
+use kernel::component::Component;
+use kernel::hil::time::Alarm;
+use kernel::utilities::leasable_buffer::SubSliceMut;
+use kernel::{capabilities, create_capability};
+
+#[macro_export]
+macro_rules! sntp_component_static {
+    ($A:ty $(,)?) => {{
+        use capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm;
+        use kernel::static_buf;
+
+        let udp_send = static_buf!(
+            capsules_extra::net::udp::udp_send::UDPSendStruct<
+                'static,
+                capsules_extra::net::ipv6::ipv6_send::IP6SendStruct<
+                    'static,
+                    VirtualMuxAlarm<'static, $A>,
+                >,
+            >
+        );
+        let udp_vis_cap =
+            static_buf!(capsules_extra::net::network_capabilities::UdpVisibilityCapability);
+        let net_cap = static_buf!(capsules_extra::net::network_capabilities::NetworkCapability);
+        let udp_recv = static_buf!(capsules_extra::net::udp::udp_recv::UDPReceiver<'static>);
+        let alarm = static_buf!(VirtualMuxAlarm<'static, $A>);
+        let buffer = static_buf!([u8; capsules_extra::net::sntp::PACKET_LEN]);
+        let sntp = static_buf!(
+            capsules_extra::net::sntp::SntpClient<'static, VirtualMuxAlarm<'static, $A>>
+        );
+
+        (
+            udp_send,
+            udp_vis_cap,
+            net_cap,
+            udp_recv,
+            alarm,
+            buffer,
+            sntp,
+        )
+    };};
+}
+
+pub type SntpComponentType<A> = SntpClient<'static, VirtualMuxAlarm<'static, A>>;
+
+pub struct SntpComponent<A: Alarm<'static> + 'static> {
+    board_kernel: &'static kernel::Kernel,
+    driver_num: usize,
+    udp_send_mux:
+        &'static MuxUdpSender<'static, IP6SendStruct<'static, VirtualMuxAlarm<'static, A>>>,
+    udp_recv_mux: &'static MuxUdpReceiver<'static>,
+    port_table: &'static UdpPortManager,
+    mux_alarm: &'static MuxAlarm<'static, A>,
+    clock: &'static SoftwareDateTime<'static, VirtualMuxAlarm<'static, A>>,
+    server: IPAddr,
+    local_port: u16,
+    poll_interval_s: u32,
+}
+
+impl<A: Alarm<'static> + 'static> SntpComponent<A> {
+    pub fn new(
+        board_kernel: &'static kernel::Kernel,
+        driver_num: usize,
+        udp_send_mux: &'static MuxUdpSender<
+            'static,
+            IP6SendStruct<'static, VirtualMuxAlarm<'static, A>>,
+        >,
+        udp_recv_mux: &'static MuxUdpReceiver<'static>,
+        port_table: &'static UdpPortManager,
+        mux_alarm: &'static MuxAlarm<'static, A>,
+        clock: &'static SoftwareDateTime<'static, VirtualMuxAlarm<'static, A>>,
+        server: IPAddr,
+        local_port: u16,
+        poll_interval_s: u32,
+    ) -> Self {
+        Self {
+            board_kernel,
+            driver_num,
+            udp_send_mux,
+            udp_recv_mux,
+            port_table,
+            mux_alarm,
+            clock,
+            server,
+            local_port,
+            poll_interval_s,
+        }
+    }
+}
+
+impl<A: Alarm<'static> + 'static> Component for SntpComponent<A> {
+    type StaticInput = (
+        &'static mut MaybeUninit<
+            UDPSendStruct<'static, IP6SendStruct<'static, VirtualMuxAlarm<'static, A>>>,
+        >,
+        &'static mut MaybeUninit<UdpVisibilityCapability>,
+        &'static mut MaybeUninit<NetworkCapability>,
+        &'static mut MaybeUninit<UDPReceiver<'static>>,
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+        &'static mut MaybeUninit<[u8; PACKET_LEN]>,
+        &'static mut MaybeUninit<SntpClient<'static, VirtualMuxAlarm<'static, A>>>,
+    );
+    type Output = &'static SntpClient<'static, VirtualMuxAlarm<'static, A>>;
+
+    fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+        let create_cap = create_capability!(NetworkCapabilityCreationCapability);
+
+        let udp_vis = s.1.write(UdpVisibilityCapability::new(&create_cap));
+        let udp_send = s.0.write(UDPSendStruct::new(self.udp_send_mux, udp_vis));
+        let net_cap = s.2.write(NetworkCapability::new(
+            AddrRange::Any,
+            PortRange::Port(self.local_port),
+            PortRange::Port(NTP_PORT),
+            &create_cap,
+        ));
+
+        let alarm = s.4.write(VirtualMuxAlarm::new(self.mux_alarm));
+        alarm.setup();
+
+        let sntp = s.6.write(SntpClient::new(
+            udp_send,
+            alarm,
+            self.clock,
+            self.board_kernel.create_grant(self.driver_num, &grant_cap),
+            net_cap,
+            self.server,
+            self.poll_interval_s,
+            SubSliceMut::new(s.5.write([0; PACKET_LEN])),
+        ));
+        alarm.set_alarm_client(sntp);
+        udp_send.set_client(sntp);
+
+        let udp_recv = s.3.write(UDPReceiver::new());
+        udp_recv.set_client(sntp);
+
+        // The client cannot work without a socket, so running out of them is
+        // a board configuration error.
+        self.port_table
+            .create_socket()
+            .map(|socket| {
+                self.port_table
+                    .bind(socket, self.local_port, net_cap)
+                    .map_or_else(
+                        |_| (),
+                        |(tx_bind, rx_bind)| {
+                            udp_recv.set_binding(rx_bind);
+                            udp_send.set_binding(tx_bind);
+                        },
+                    )
+            })
+            .unwrap();
+        self.udp_recv_mux.add_client(udp_recv);
+
+        sntp.start();
+        sntp
+    }
+}
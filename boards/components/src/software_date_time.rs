@@ -0,0 +1,73 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Component for a date and time clock kept in software on an alarm.
+//!
+//! The clock starts at the Unix epoch; it is set through the `DateTime`
+//! interface or kept in time by a time source such as the SNTP client.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let clock = components::software_date_time::SoftwareDateTimeComponent::new(mux_alarm)
+//!     .finalize(components::software_date_time_component_static!(
+//!         nrf52840::rtc::Rtc<'static>
+//!     ));
+//! ```
+
+use capsules_core::virtualizers::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use capsules_extra::software_date_time::SoftwareDateTime;
+use core::mem::MaybeUninit;
+use kernel::component::Component;
+use kernel::deferred_call::DeferredCallClient;
+use kernel::hil::time::Alarm;
+
+#[macro_export]
+macro_rules! software_date_time_component_static {
+    ($A:ty $(,)?) => {{
+        let alarm = kernel::static_buf!(
+            capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>
+        );
+        let clock = kernel::static_buf!(
+            capsules_extra::software_date_time::SoftwareDateTime<
+                'static,
+                capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>,
+            >
+        );
+
+        (alarm, clock)
+    };};
+}
+
+pub type SoftwareDateTimeComponentType<A> = SoftwareDateTime<'static, VirtualMuxAlarm<'static, A>>;
+
+pub struct SoftwareDateTimeComponent<A: 'static + Alarm<'static>> {
+    mux_alarm: &'static MuxAlarm<'static, A>,
+}
+
+impl<A: 'static + Alarm<'static>> SoftwareDateTimeComponent<A> {
+    pub fn new(mux_alarm: &'static MuxAlarm<'static, A>) -> SoftwareDateTimeComponent<A> {
+        SoftwareDateTimeComponent { mux_alarm }
+    }
+}
+
+impl<A: 'static + Alarm<'static>> Component for SoftwareDateTimeComponent<A> {
+    type StaticInput = (
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+        &'static mut MaybeUninit<SoftwareDateTime<'static, VirtualMuxAlarm<'static, A>>>,
+    );
+    type Output = &'static SoftwareDateTime<'static, VirtualMuxAlarm<'static, A>>;
+
+    fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let alarm = s.0.write(VirtualMuxAlarm::new(self.mux_alarm));
+        alarm.setup();
+
+        let clock = s.1.write(SoftwareDateTime::new(alarm));
+        alarm.set_alarm_client(clock);
+        clock.register();
+        clock.init();
+
+        clock
+    }
+}
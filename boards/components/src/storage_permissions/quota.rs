@@ -0,0 +1,85 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Component for limiting how much storage applications can use, on top of
+//! another storage permissions policy.
+//!
+//! ```rust
+//! let storage_permissions_policy =
+//!     components::storage_permissions::quota::StoragePermissionsQuotaComponent::new(
+//!         individual_storage_permissions_policy,
+//!         kernel::storage_permissions::StorageQuota {
+//!             bytes: 2048,
+//!             objects: 16,
+//!         },
+//!         &[],
+//!     )
+//!     .finalize(components::storage_permissions_quota_component_static!(
+//!         nrf52840dk_lib::Chip,
+//!         components::storage_permissions::individual::StoragePermissionsIndividualComponentType<
+//!             nrf52840dk_lib::Chip,
+//!         >,
+//!     ));
+//! ```
+
+use capsules_system::storage_permissions::quota::QuotaStoragePermissions;
+use core::mem::MaybeUninit;
+use kernel::component::Component;
+use kernel::platform::chip::Chip;
+use kernel::process::ProcessStandardStoragePermissionsPolicy;
+use kernel::storage_permissions::StorageQuota;
+
+#[macro_export]
+macro_rules! storage_permissions_quota_component_static {
+    ($C:ty, $P:ty $(,)?) => {{
+        kernel::static_buf!(
+            capsules_system::storage_permissions::quota::QuotaStoragePermissions<'static, $C, $P>
+        )
+    };};
+}
+
+pub type StoragePermissionsQuotaComponentType<C, P> =
+    capsules_system::storage_permissions::quota::QuotaStoragePermissions<'static, C, P>;
+
+pub struct StoragePermissionsQuotaComponent<
+    C: Chip,
+    P: ProcessStandardStoragePermissionsPolicy<C> + 'static,
+> {
+    policy: &'static P,
+    default_quota: StorageQuota,
+    quotas: &'static [(u32, StorageQuota)],
+    _chip: core::marker::PhantomData<C>,
+}
+
+impl<C: Chip, P: ProcessStandardStoragePermissionsPolicy<C>>
+    StoragePermissionsQuotaComponent<C, P>
+{
+    pub fn new(
+        policy: &'static P,
+        default_quota: StorageQuota,
+        quotas: &'static [(u32, StorageQuota)],
+    ) -> Self {
+        Self {
+            policy,
+            default_quota,
+            quotas,
+            _chip: core::marker::PhantomData,
+        }
+    }
+}
+
+impl<C: Chip + 'static, P: ProcessStandardStoragePermissionsPolicy<C>> Component
+    for StoragePermissionsQuotaComponent<C, P>
+{
+    type StaticInput = &'static mut MaybeUninit<QuotaStoragePermissions<'static, C, P>>;
+    type Output = &'static QuotaStoragePermissions<'static, C, P>;
+
+    fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        s.write(QuotaStoragePermissions::new(
+            self.policy,
+            self.default_quota,
+            self.quotas,
+        ))
+    }
+}
@@ -12,6 +12,7 @@
 //! ```
 
 use capsules_extra::temperature::TemperatureSensor;
+use capsules_extra::virtual_temperature::{MuxTemperature, VirtualTemperature};
 use core::mem::MaybeUninit;
 use kernel::capabilities;
 use kernel::component::Component;
@@ -63,3 +64,69 @@ impl<T: 'static + hil::sensors::TemperatureDriver<'static>> Component for Temper
         temp
     }
 }
+
+#[macro_export]
+macro_rules! temperature_mux_component_static {
+    ($T:ty $(,)?) => {{
+        kernel::static_buf!(capsules_extra::virtual_temperature::MuxTemperature<'static, $T>)
+    };};
+}
+
+pub type TemperatureMuxComponentType<T> =
+    capsules_extra::virtual_temperature::MuxTemperature<'static, T>;
+
+pub struct TemperatureMuxComponent<T: 'static + hil::sensors::TemperatureDriver<'static>> {
+    sensor: &'static T,
+}
+
+impl<T: 'static + hil::sensors::TemperatureDriver<'static>> TemperatureMuxComponent<T> {
+    pub fn new(sensor: &'static T) -> TemperatureMuxComponent<T> {
+        TemperatureMuxComponent { sensor }
+    }
+}
+
+impl<T: 'static + hil::sensors::TemperatureDriver<'static>> Component
+    for TemperatureMuxComponent<T>
+{
+    type StaticInput = &'static mut MaybeUninit<MuxTemperature<'static, T>>;
+    type Output = &'static MuxTemperature<'static, T>;
+
+    fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let mux = static_buffer.write(MuxTemperature::new(self.sensor));
+        hil::sensors::TemperatureDriver::set_client(self.sensor, mux);
+        mux
+    }
+}
+
+#[macro_export]
+macro_rules! virtual_temperature_component_static {
+    ($T:ty $(,)?) => {{
+        kernel::static_buf!(capsules_extra::virtual_temperature::VirtualTemperature<'static, $T>)
+    };};
+}
+
+pub type VirtualTemperatureComponentType<T> =
+    capsules_extra::virtual_temperature::VirtualTemperature<'static, T>;
+
+pub struct VirtualTemperatureComponent<T: 'static + hil::sensors::TemperatureDriver<'static>> {
+    mux: &'static MuxTemperature<'static, T>,
+}
+
+impl<T: 'static + hil::sensors::TemperatureDriver<'static>> VirtualTemperatureComponent<T> {
+    pub fn new(mux: &'static MuxTemperature<'static, T>) -> VirtualTemperatureComponent<T> {
+        VirtualTemperatureComponent { mux }
+    }
+}
+
+impl<T: 'static + hil::sensors::TemperatureDriver<'static>> Component
+    for VirtualTemperatureComponent<T>
+{
+    type StaticInput = &'static mut MaybeUninit<VirtualTemperature<'static, T>>;
+    type Output = &'static VirtualTemperature<'static, T>;
+
+    fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let user = static_buffer.write(VirtualTemperature::new(self.mux));
+        user.setup();
+        user
+    }
+}
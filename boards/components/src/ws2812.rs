@@ -0,0 +1,95 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Component for WS2812 and SK6812 LED strips on an SPI bus.
+//!
+//! The strip is driven from the data out pin of the bus. The chip select
+//! given is toggled around frames but isn't connected to the strip.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let ws2812 = components::ws2812::Ws2812Component::new(
+//!     mux_spi,
+//!     &peripherals.pins.get_pin(RPGpio::GPIO17),
+//!     PixelOrder::Grb,
+//! )
+//! .finalize(components::ws2812_component_static!(
+//!     rp2040::spi::Spi,
+//!     60,
+//!     PixelOrder::Grb
+//! ));
+//! ```
+
+use capsules_core::virtualizers::virtual_spi::{MuxSpiMaster, VirtualSpiMasterDevice};
+use capsules_extra::ws2812::{PixelOrder, Ws2812};
+use core::mem::MaybeUninit;
+use kernel::component::Component;
+use kernel::hil::spi;
+use kernel::hil::spi::SpiMasterDevice;
+
+#[macro_export]
+macro_rules! ws2812_component_static {
+    ($S:ty, $pixels:expr, $order:expr $(,)?) => {{
+        let spi = kernel::static_buf!(
+            capsules_core::virtualizers::virtual_spi::VirtualSpiMasterDevice<'static, $S>
+        );
+        let buffer = kernel::static_buf!([u8; capsules_extra::ws2812::buffer_len($pixels, $order)]);
+        let ws2812 = kernel::static_buf!(
+            capsules_extra::ws2812::Ws2812<
+                'static,
+                capsules_core::virtualizers::virtual_spi::VirtualSpiMasterDevice<'static, $S>,
+            >
+        );
+
+        (spi, buffer, ws2812)
+    };};
+}
+
+pub type Ws2812ComponentType<S> = Ws2812<'static, VirtualSpiMasterDevice<'static, S>>;
+
+pub struct Ws2812Component<S: 'static + spi::SpiMaster<'static>, const BUF_LEN: usize> {
+    spi_mux: &'static MuxSpiMaster<'static, S>,
+    chip_select: S::ChipSelect,
+    order: PixelOrder,
+}
+
+impl<S: 'static + spi::SpiMaster<'static>, const BUF_LEN: usize> Ws2812Component<S, BUF_LEN> {
+    pub fn new(
+        spi_mux: &'static MuxSpiMaster<'static, S>,
+        chip_select: S::ChipSelect,
+        order: PixelOrder,
+    ) -> Ws2812Component<S, BUF_LEN> {
+        Ws2812Component {
+            spi_mux,
+            chip_select,
+            order,
+        }
+    }
+}
+
+impl<S: 'static + spi::SpiMaster<'static>, const BUF_LEN: usize> Component
+    for Ws2812Component<S, BUF_LEN>
+{
+    type StaticInput = (
+        &'static mut MaybeUninit<VirtualSpiMasterDevice<'static, S>>,
+        &'static mut MaybeUninit<[u8; BUF_LEN]>,
+        &'static mut MaybeUninit<Ws2812<'static, VirtualSpiMasterDevice<'static, S>>>,
+    );
+    type Output = &'static Ws2812<'static, VirtualSpiMasterDevice<'static, S>>;
+
+    fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let spi_device =
+            s.0.write(VirtualSpiMasterDevice::new(self.spi_mux, self.chip_select));
+        spi_device.setup();
+
+        let buffer = s.1.write([0; BUF_LEN]);
+        let ws2812 = s.2.write(Ws2812::new(spi_device, self.order, buffer));
+        spi_device.set_client(ws2812);
+        // Frames fail with OFF if the bus can't run at a rate the LEDs take.
+        let _ = ws2812.init();
+
+        ws2812
+    }
+}
@@ -27,11 +27,9 @@ use earlgrey::pinmux_config::EarlGreyPinmuxConfig;
 use kernel::capabilities;
 use kernel::component::Component;
 use kernel::hil;
-use kernel::hil::entropy::Entropy32;
 use kernel::hil::hasher::Hasher;
 use kernel::hil::i2c::I2CMaster;
 use kernel::hil::led::LedHigh;
-use kernel::hil::rng::Rng;
 use kernel::hil::symmetric_encryption::AES128;
 use kernel::platform::scheduler_timer::VirtualSchedulerTimer;
 use kernel::platform::{KernelResources, SyscallDriverLookup, TbfHeaderFilterDefaultAllow};
@@ -198,16 +196,24 @@ struct EarlGrey {
             lowrisc::spi_host::SpiHost<'static>,
         >,
     >,
-    rng: &'static capsules_core::rng::RngDriver<
-        'static,
-        capsules_core::rng::Entropy32ToRandom<'static, lowrisc::csrng::CsRng<'static>>,
-    >,
+    rng: &'static components::hmac_drbg::HmacDrbgRngComponentType,
     aes: &'static capsules_extra::symmetric_encryption::aes::AesDriver<
         'static,
         aes_gcm::Aes128Gcm<
             'static,
             virtual_aes_ccm::VirtualAES128CCM<'static, earlgrey::aes::Aes<'static>>,
         >,
+        capsules_extra::symmetric_encryption::chacha20_poly1305::ChaCha20Poly1305Software<'static>,
+    >,
+    keystore: &'static capsules_extra::keystore::KernelKeyStore<'static>,
+    kdf: &'static capsules_extra::kdf::KdfDriver<
+        'static,
+        components::kdf::KdfHmacSha256ComponentType<
+            capsules_extra::hmac_sha256::HmacSha256Software<
+                'static,
+                capsules_extra::sha256::Sha256Software<'static>,
+            >,
+        >,
     >,
     kv_driver: &'static capsules_extra::kv_driver::KVStoreDriver<
         'static,
@@ -256,6 +262,8 @@ impl SyscallDriverLookup for EarlGrey {
             capsules_core::spi_controller::DRIVER_NUM => f(Some(self.spi_controller)),
             capsules_core::rng::DRIVER_NUM => f(Some(self.rng)),
             capsules_extra::symmetric_encryption::aes::DRIVER_NUM => f(Some(self.aes)),
+            capsules_extra::keystore::DRIVER_NUM => f(Some(self.keystore)),
+            capsules_extra::kdf::DRIVER_NUM => f(Some(self.kdf)),
             capsules_extra::kv_driver::DRIVER_NUM => f(Some(self.kv_driver)),
             _ => f(None),
         }
@@ -765,24 +773,28 @@ unsafe fn setup() -> (
         debug!("Unable to find otbn-rsa, disabling RSA support");
     }
 
-    // Convert hardware RNG to the Random interface.
-    let entropy_to_random = static_init!(
-        capsules_core::rng::Entropy32ToRandom<'static, lowrisc::csrng::CsRng<'static>>,
-        capsules_core::rng::Entropy32ToRandom::new(&peripherals.rng)
+    // Seed a DRBG from the hardware RNG, and provide its output to userspace.
+    let drbg_sha256 = components::sha::ShaSoftware256Component::new()
+        .finalize(components::sha_software_256_component_static!());
+    let drbg_hmac = components::hmac::HmacSha256SoftwareComponent::new(drbg_sha256).finalize(
+        components::hmac_sha256_software_component_static!(
+            capsules_extra::sha256::Sha256Software<'static>
+        ),
     );
-    peripherals.rng.set_client(entropy_to_random);
-    // Setup RNG for userspace
-    let rng = static_init!(
-        capsules_core::rng::RngDriver<
+    let (rng, mux_rng) = components::hmac_drbg::HmacDrbgRngComponent::new(
+        board_kernel,
+        capsules_core::rng::DRIVER_NUM,
+        &peripherals.rng,
+        drbg_hmac,
+        capsules_extra::hmac_drbg::DEFAULT_RESEED_INTERVAL,
+    )
+    .finalize(components::hmac_drbg_rng_component_static!(
+        lowrisc::csrng::CsRng<'static>,
+        capsules_extra::hmac_sha256::HmacSha256Software<
             'static,
-            capsules_core::rng::Entropy32ToRandom<'static, lowrisc::csrng::CsRng<'static>>,
+            capsules_extra::sha256::Sha256Software<'static>,
         >,
-        capsules_core::rng::RngDriver::new(
-            entropy_to_random,
-            board_kernel.create_grant(capsules_core::rng::DRIVER_NUM, &memory_allocation_cap)
-        )
-    );
-    entropy_to_random.set_client(rng);
+    ));
 
     const CRYPT_SIZE: usize = 7 * AES128_BLOCK_SIZE;
 
@@ -807,20 +819,105 @@ unsafe fn setup() -> (
     );
     ccm_client.set_client(gcm_client);
 
+    let chacha = components::chacha20_poly1305::ChaCha20Poly1305SoftwareComponent::new()
+        .finalize(components::chacha20_poly1305_software_component_static!());
+
     let aes = components::aes::AesDriverComponent::new(
         board_kernel,
         capsules_extra::symmetric_encryption::aes::DRIVER_NUM,
         gcm_client,
+        chacha,
     )
     .finalize(components::aes_driver_component_static!(
         aes_gcm::Aes128Gcm<
             'static,
             virtual_aes_ccm::VirtualAES128CCM<'static, earlgrey::aes::Aes<'static>>,
         >,
+        capsules_extra::symmetric_encryption::chacha20_poly1305::ChaCha20Poly1305Software<'static>,
     ));
 
     AES = Some(gcm_client);
 
+    // Keys held by the kernel, which apps can use with the AES and HMAC
+    // drivers by handle.
+    let virtual_kv_keystore = components::kv::VirtualKVPermissionsComponent::new(mux_kv).finalize(
+        components::virtual_kv_permissions_component_static!(
+            capsules_extra::kv_store_permissions::KVStorePermissions<
+                capsules_extra::tickv_kv_store::TicKVKVStore<
+                    capsules_extra::tickv::TicKVSystem<
+                        capsules_core::virtualizers::virtual_flash::FlashUser<
+                            lowrisc::flash_ctrl::FlashCtrl,
+                        >,
+                        capsules_extra::sip_hash::SipHasher24<'static>,
+                        2048,
+                    >,
+                    capsules_extra::tickv::TicKVKeyType,
+                >,
+            >
+        ),
+    );
+
+    let keystore = components::keystore::KeyStoreComponent::new(
+        board_kernel,
+        capsules_extra::keystore::DRIVER_NUM,
+        &[],
+        4,
+    )
+    .finalize(components::keystore_component_static!(8));
+    let keystore_chacha = components::chacha20_poly1305::ChaCha20Poly1305SoftwareComponent::new()
+        .finalize(components::chacha20_poly1305_software_component_static!());
+    let keystore_rng = static_init!(
+        capsules_core::virtualizers::virtual_rng::VirtualRngMasterDevice<'static>,
+        capsules_core::virtualizers::virtual_rng::VirtualRngMasterDevice::new(mux_rng)
+    );
+    keystore.set_key_derivation(&peripherals.keymgr);
+    keystore.set_kv_store(
+        virtual_kv_keystore,
+        kernel::storage_permissions::StoragePermissions::new_kernel(&create_capability!(
+            capabilities::KerneluserStorageCapability
+        )),
+        keystore_chacha,
+        keystore_rng,
+    );
+    let _ = keystore.restore();
+
+    aes.set_keystore(keystore);
+    hmac.set_keystore(keystore);
+
+    // Derive keys with HKDF and PBKDF2, either for apps or into the keystore.
+    let kdf_sha256 = components::sha::ShaSoftware256Component::new()
+        .finalize(components::sha_software_256_component_static!());
+    let kdf_hmac = components::hmac::HmacSha256SoftwareComponent::new(kdf_sha256).finalize(
+        components::hmac_sha256_software_component_static!(
+            capsules_extra::sha256::Sha256Software<'static>
+        ),
+    );
+    let kdf_hmac_sha256 = components::kdf::KdfHmacSha256Component::new(kdf_hmac).finalize(
+        components::kdf_hmac_sha256_component_static!(
+            capsules_extra::hmac_sha256::HmacSha256Software<
+                'static,
+                capsules_extra::sha256::Sha256Software<'static>,
+            >,
+            256,
+        ),
+    );
+    let kdf = components::kdf::KdfDriverComponent::new(
+        board_kernel,
+        capsules_extra::kdf::DRIVER_NUM,
+        kdf_hmac_sha256,
+    )
+    .finalize(components::kdf_driver_component_static!(
+        components::kdf::KdfHmacSha256ComponentType<
+            capsules_extra::hmac_sha256::HmacSha256Software<
+                'static,
+                capsules_extra::sha256::Sha256Software<'static>,
+            >,
+        >,
+        256,
+        64,
+    ));
+    kdf.set_keystore(keystore);
+
     #[cfg(test)]
     {
         use capsules_extra::sha256::Sha256Software;
@@ -852,6 +949,8 @@ unsafe fn setup() -> (
             spi_controller,
             rng,
             aes,
+            keystore,
+            kdf,
             kv_driver,
             syscall_filter,
             scheduler,
@@ -17,7 +17,8 @@ use ghash::GHash;
 use ghash::Key;
 use kernel::hil::symmetric_encryption;
 use kernel::hil::symmetric_encryption::{
-    AES128Ctr, AES128, AES128CBC, AES128CCM, AES128ECB, AES128_BLOCK_SIZE, AES128_KEY_SIZE,
+    aes_key_size_valid, AES128Ctr, AES128, AES128CBC, AES128CCM, AES128ECB, AES128_BLOCK_SIZE,
+    AES128_KEY_SIZE, AES_MAX_KEY_SIZE,
 };
 use kernel::utilities::cells::{OptionalCell, TakeCell};
 use kernel::ErrorCode;
@@ -46,7 +47,8 @@ pub struct Aes128Gcm<'a, A: AES128<'a> + AES128Ctr + AES128CBC + AES128ECB + AES
     buf: TakeCell<'static, [u8]>,
 
     pos: Cell<(usize, usize, usize)>,
-    key: Cell<[u8; AES128_KEY_SIZE]>,
+    key: Cell<[u8; AES_MAX_KEY_SIZE]>,
+    key_len: Cell<usize>,
     iv: Cell<[u8; AES128_KEY_SIZE]>,
 }
 
@@ -69,6 +71,7 @@ impl<'a, A: AES128<'a> + AES128Ctr + AES128CBC + AES128ECB + AES128CCM<'a>> Aes1
             buf: TakeCell::empty(),
             pos: Cell::new((0, 0, 0)),
             key: Cell::new(Default::default()),
+            key_len: Cell::new(AES128_KEY_SIZE),
             iv: Cell::new(Default::default()),
         }
     }
@@ -76,7 +79,7 @@ impl<'a, A: AES128<'a> + AES128Ctr + AES128CBC + AES128ECB + AES128CCM<'a>> Aes1
     fn start_ctr_encrypt(&self) -> Result<(), ErrorCode> {
         self.aes.set_mode_aes128ctr(self.encrypting.get())?;
 
-        let res = AES128::set_key(self.aes, &self.key.get());
+        let res = AES128::set_key(self.aes, &self.key.get()[..self.key_len.get()]);
         if res != Ok(()) {
             return res;
         }
@@ -120,7 +123,7 @@ impl<'a, A: AES128<'a> + AES128Ctr + AES128CBC + AES128ECB + AES128CCM<'a>> Aes1
         self.encrypting.set(encrypting);
 
         self.aes.set_mode_aes128ctr(self.encrypting.get()).unwrap();
-        AES128::set_key(self.aes, &self.key.get()).unwrap();
+        AES128::set_key(self.aes, &self.key.get()[..self.key_len.get()]).unwrap();
         self.aes.set_iv(&[0; AES128_BLOCK_SIZE]).unwrap();
 
         self.aes.start_message();
@@ -163,12 +166,13 @@ impl<'a, A: AES128<'a> + AES128Ctr + AES128CBC + AES128ECB + AES128CCM<'a>>
     }
 
     fn set_key(&self, key: &[u8]) -> Result<(), ErrorCode> {
-        if key.len() < AES128_KEY_SIZE {
+        if !aes_key_size_valid(key.len()) {
             Err(ErrorCode::INVAL)
         } else {
-            let mut new_key = [0u8; AES128_KEY_SIZE];
-            new_key.copy_from_slice(key);
+            let mut new_key = [0u8; AES_MAX_KEY_SIZE];
+            new_key[..key.len()].copy_from_slice(key);
             self.key.set(new_key);
+            self.key_len.set(key.len());
             Ok(())
         }
     }
@@ -34,6 +34,8 @@ pub enum NUM {
     UsbUser               = 0x20005,
     I2cMasterSlave        = 0x20006,
     Can                   = 0x20007,
+    IsoTp                 = 0x20008,
+    Modbus                = 0x20009,
 
     // Radio
     BleAdvertising        = 0x30000,
@@ -43,6 +45,7 @@ pub enum NUM {
     LoRaPhyGPIO           = 0x30004,
     Thread                = 0x30005,
     Eui64                 = 0x30006,
+    Sntp                  = 0x30007,
 
     // Cryptography
     Rng                   = 0x40001,
@@ -51,6 +54,10 @@ pub enum NUM {
     CtapHid               = 0x40004,
     Sha                   = 0x40005,
     Aes                   = 0x40006,
+    Ecdh                  = 0x40007,
+    Keystore              = 0x40008,
+    Kdf                   = 0x40009,
+    SignatureVerify       = 0x4000A,
 
     // Storage
     AppFlash              = 0x50000,
@@ -67,6 +74,8 @@ pub enum NUM {
     SoundPressure         = 0x60006,
     AirQuality            = 0x60007,
     Pressure              = 0x60008,
+    SensorHub             = 0x60009,
+    Orientation           = 0x6000A,
 
     // Sensor ICs
     Tsl2561               = 0x70000,
@@ -91,7 +100,9 @@ pub enum NUM {
     TextScreen            = 0x90003,
     SevenSegment          = 0x90004,
     KeyboardHid           = 0x90005,
+    Graphics              = 0x90006,
     DateTime              = 0x90007,
     CycleCount            = 0x90008,
+    LedStrip              = 0x90009,
 }
 }
@@ -278,6 +278,59 @@ impl Iterator for Entropy32ToRandomIter<'_> {
     }
 }
 
+/// Use a random number generator where an `Entropy32` source is expected.
+///
+/// This is meant for sharing a (virtualized) random number generator with a
+/// capsule that asks for entropy, for example ECDH key generation. The output
+/// is only as good as the random number generator.
+///
+/// The client of `rng` must be set to this adapter when the board is set up.
+/// Capsules may set their entropy client before every request, so doing it in
+/// `set_client()` would register a virtual random number generator more than
+/// once.
+pub struct RandomToEntropy32<'a, R: Rng<'a>> {
+    rng: &'a R,
+    client: OptionalCell<&'a dyn entropy::Client32>,
+}
+
+impl<'a, R: Rng<'a>> RandomToEntropy32<'a, R> {
+    pub fn new(rng: &'a R) -> Self {
+        Self {
+            rng,
+            client: OptionalCell::empty(),
+        }
+    }
+}
+
+impl<'a, R: Rng<'a>> Entropy32<'a> for RandomToEntropy32<'a, R> {
+    fn get(&self) -> Result<(), ErrorCode> {
+        self.rng.get()
+    }
+
+    fn cancel(&self) -> Result<(), ErrorCode> {
+        self.rng.cancel()
+    }
+
+    fn set_client(&'a self, client: &'a dyn entropy::Client32) {
+        self.client.set(client);
+    }
+}
+
+impl<'a, R: Rng<'a>> rng::Client for RandomToEntropy32<'a, R> {
+    fn randomness_available(
+        &self,
+        randomness: &mut dyn Iterator<Item = u32>,
+        error: Result<(), ErrorCode>,
+    ) -> Continue {
+        self.client.map_or(Continue::Done, |client| {
+            match client.entropy_available(randomness, error) {
+                entropy::Continue::More => Continue::More,
+                entropy::Continue::Done => Continue::Done,
+            }
+        })
+    }
+}
+
 pub struct Entropy8To32<'a, E: Entropy8<'a>> {
     egen: &'a E,
     client: OptionalCell<&'a dyn entropy::Client32>,
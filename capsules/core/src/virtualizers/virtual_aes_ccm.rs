@@ -87,7 +87,8 @@ use kernel::debug;
 use kernel::deferred_call::{DeferredCall, DeferredCallClient};
 use kernel::hil::symmetric_encryption;
 use kernel::hil::symmetric_encryption::{
-    AES128Ctr, AES128, AES128CBC, AES128ECB, AES128_BLOCK_SIZE, AES128_KEY_SIZE, CCM_NONCE_LENGTH,
+    aes_key_size_valid, AES128Ctr, AES128, AES128CBC, AES128ECB, AES128_BLOCK_SIZE,
+    AES128_KEY_SIZE, AES_MAX_KEY_SIZE, CCM_NONCE_LENGTH,
 };
 use kernel::utilities::cells::{OptionalCell, TakeCell};
 use kernel::ErrorCode;
@@ -247,7 +248,8 @@ pub struct VirtualAES128CCM<'a, A: AES128<'a> + AES128Ctr + AES128CBC + AES128EC
 
     buf: TakeCell<'static, [u8]>,
     pos: Cell<(usize, usize, usize, usize)>,
-    key: Cell<[u8; AES128_KEY_SIZE]>,
+    key: Cell<[u8; AES_MAX_KEY_SIZE]>,
+    key_len: Cell<usize>,
     nonce: Cell<[u8; CCM_NONCE_LENGTH]>,
     saved_tag: Cell<[u8; AES128_BLOCK_SIZE]>,
     queued_up: OptionalCell<CryptFunctionParameters>,
@@ -272,6 +274,7 @@ impl<'a, A: AES128<'a> + AES128Ctr + AES128CBC + AES128ECB> VirtualAES128CCM<'a,
             buf: TakeCell::empty(),
             pos: Cell::new((0, 0, 0, 0)),
             key: Cell::new(Default::default()),
+            key_len: Cell::new(AES128_KEY_SIZE),
             nonce: Cell::new(Default::default()),
             saved_tag: Cell::new(Default::default()),
             queued_up: OptionalCell::empty(),
@@ -411,7 +414,7 @@ impl<'a, A: AES128<'a> + AES128Ctr + AES128CBC + AES128ECB> VirtualAES128CCM<'a,
         if res != Ok(()) {
             return res;
         }
-        let res = self.aes.set_key(&self.key.get());
+        let res = self.aes.set_key(&self.key.get()[..self.key_len.get()]);
         if res != Ok(()) {
             return res;
         }
@@ -459,7 +462,7 @@ impl<'a, A: AES128<'a> + AES128Ctr + AES128CBC + AES128ECB> VirtualAES128CCM<'a,
 
         self.aes.set_mode_aes128ctr(self.encrypting.get())?;
 
-        let res = self.aes.set_key(&self.key.get());
+        let res = self.aes.set_key(&self.key.get()[..self.key_len.get()]);
         if res != Ok(()) {
             return res;
         }
@@ -667,12 +670,13 @@ impl<'a, A: AES128<'a> + AES128Ctr + AES128CBC + AES128ECB> symmetric_encryption
     }
 
     fn set_key(&self, key: &[u8]) -> Result<(), ErrorCode> {
-        if key.len() < AES128_KEY_SIZE {
+        if !aes_key_size_valid(key.len()) {
             Err(ErrorCode::INVAL)
         } else {
-            let mut new_key = [0u8; AES128_KEY_SIZE];
-            new_key.copy_from_slice(key);
+            let mut new_key = [0u8; AES_MAX_KEY_SIZE];
+            new_key[..key.len()].copy_from_slice(key);
             self.key.set(new_key);
+            self.key_len.set(key.len());
             Ok(())
         }
     }
@@ -0,0 +1,116 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! An alarm for unit tests of capsules that use the time HIL.
+//!
+//! Time in `FakeAlarm` passes when `fire()` moves it to the alarm and calls
+//! the client, and by a set number of ticks at each call of `now()`, for
+//! capsules that poll the counter.
+
+use core::cell::Cell;
+use core::marker::PhantomData;
+
+use kernel::hil::time::{Alarm, AlarmClient, Frequency, Ticks, Ticks32, Time};
+use kernel::utilities::cells::OptionalCell;
+use kernel::ErrorCode;
+
+pub(crate) struct FakeAlarm<F: Frequency> {
+    now: Cell<u32>,
+    reference: Cell<u32>,
+    dt: Cell<u32>,
+    armed: Cell<bool>,
+    tick: Cell<u32>,
+    client: OptionalCell<&'static dyn AlarmClient>,
+    frequency: PhantomData<F>,
+}
+
+impl<F: Frequency> FakeAlarm<F> {
+    pub(crate) fn new() -> Self {
+        Self {
+            now: Cell::new(0),
+            reference: Cell::new(0),
+            dt: Cell::new(0),
+            armed: Cell::new(false),
+            tick: Cell::new(0),
+            client: OptionalCell::empty(),
+            frequency: PhantomData,
+        }
+    }
+
+    /// Make time pass by `ticks` at each call of `now()`.
+    pub(crate) fn set_tick(&self, ticks: u32) {
+        self.tick.set(ticks);
+    }
+
+    /// The current time, without letting any pass.
+    pub(crate) fn ticks(&self) -> u32 {
+        self.now.get()
+    }
+
+    /// Move time to the alarm, if it is in the future, and call the client.
+    /// Returns `false` if the alarm wasn't armed.
+    pub(crate) fn fire(&self) -> bool {
+        if !self.armed.get() {
+            return false;
+        }
+        let reference = Ticks32::from(self.reference.get());
+        let expiration = reference.wrapping_add(Ticks32::from(self.dt.get()));
+        if Ticks32::from(self.now.get()).within_range(reference, expiration) {
+            self.now.set(expiration.into_u32());
+        }
+        self.armed.set(false);
+        self.client.map(|client| client.alarm());
+        true
+    }
+
+    /// Fire the alarm until it isn't armed, and return how many times it
+    /// fired.
+    pub(crate) fn fire_all(&self) -> usize {
+        let mut count = 0;
+        while self.fire() {
+            count += 1;
+        }
+        count
+    }
+}
+
+impl<F: Frequency> Time for FakeAlarm<F> {
+    type Ticks = Ticks32;
+    type Frequency = F;
+
+    fn now(&self) -> Ticks32 {
+        let now = self.now.get();
+        self.now.set(now.wrapping_add(self.tick.get()));
+        now.into()
+    }
+}
+
+impl<F: Frequency> Alarm<'static> for FakeAlarm<F> {
+    fn set_alarm_client(&self, client: &'static dyn AlarmClient) {
+        self.client.set(client);
+    }
+
+    fn set_alarm(&self, reference: Ticks32, dt: Ticks32) {
+        self.reference.set(reference.into_u32());
+        self.dt.set(dt.into_u32());
+        self.armed.set(true);
+    }
+
+    fn get_alarm(&self) -> Ticks32 {
+        self.reference.get().wrapping_add(self.dt.get()).into()
+    }
+
+    fn disarm(&self) -> Result<(), ErrorCode> {
+        self.armed.set(false);
+        Ok(())
+    }
+
+    fn is_armed(&self) -> bool {
+        self.armed.get()
+    }
+
+    fn minimum_dt(&self) -> Ticks32 {
+        1u32.into()
+    }
+}
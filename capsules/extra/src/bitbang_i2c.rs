@@ -0,0 +1,668 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! I2C controller bit-banged over two GPIO pins.
+//!
+//! Implements `hil::i2c::I2CMaster` for boards whose I2C peripherals are all
+//! in use or not routed to the right pins. Each half period of the clock is
+//! one alarm, so the bus runs at the set speed at most, and slower if the
+//! alarm is too coarse; a 32 kHz alarm gives at most 16 kHz.
+//!
+//! The lines are driven as open drain: a pin is made an output and cleared to
+//! pull its line low, and made an input with a pull-up to release it. The
+//! pins should also have external pull-ups, as the internal ones are usually
+//! too weak for the bus capacitance. A device may stretch the clock by
+//! holding SCL low, for up to `STRETCH_TIMEOUT_MS` before the transfer fails
+//! with `Busy`. A controller that reads a line low while releasing it has
+//! lost arbitration, and stops with `ArbitrationLost`.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! let i2c = components::bitbang_i2c::BitbangI2CComponent::new(
+//!     mux_alarm,
+//!     &nrf52840_peripherals.gpio_port[SCL_PIN],
+//!     &nrf52840_peripherals.gpio_port[SDA_PIN],
+//!     100_000,
+//! )
+//! .finalize(components::bitbang_i2c_component_static!(
+//!     nrf52840::rtc::Rtc<'static>
+//! ));
+//! let mux_i2c = components::i2c::I2CMuxComponent::new(i2c, None)
+//!     .finalize(components::i2c_mux_component_static!(
+//!         components::bitbang_i2c::BitbangI2CComponentType<
+//!             nrf52840::rtc::Rtc<'static>,
+//!         >
+//!     ));
+//! ```
+
+use core::cell::Cell;
+
+use kernel::hil::gpio::{FloatingState, Pin};
+use kernel::hil::i2c::{Error, I2CHwMasterClient, I2CMaster};
+use kernel::hil::time::{Alarm, AlarmClient, ConvertTicks, Frequency, Ticks};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+
+/// How long a device may hold the clock low.
+pub const STRETCH_TIMEOUT_MS: u32 = 25;
+
+/// The part of the transfer being clocked.
+#[derive(Clone, Copy, PartialEq)]
+enum Stage {
+    AddressWrite,
+    Write,
+    AddressRead,
+    Read,
+}
+
+/// What follows the next falling edge of the clock.
+#[derive(Clone, Copy, PartialEq)]
+enum Action {
+    /// A data or acknowledge bit.
+    Bit,
+    /// A repeated start condition.
+    Restart,
+    /// A stop condition.
+    Stop,
+}
+
+/// What the next alarm does.
+#[derive(Clone, Copy, PartialEq)]
+enum Phase {
+    Idle,
+    /// Pull SCL low and set up SDA for the action.
+    Low,
+    /// Release SCL, and wait while a device holds it low.
+    High,
+    /// Read SDA, then go on as for `Low`.
+    Sample,
+    /// Pull SDA low with SCL high for a repeated start.
+    Restart,
+    /// Release SDA with SCL high to end the stop condition.
+    StopEnd,
+}
+
+pub struct BitbangI2C<'a, A: Alarm<'a>> {
+    alarm: &'a A,
+    scl: &'a dyn Pin,
+    sda: &'a dyn Pin,
+    client: OptionalCell<&'a dyn I2CHwMasterClient>,
+    speed: Cell<u32>,
+    buffer: TakeCell<'static, [u8]>,
+    addr: Cell<u8>,
+    write_len: Cell<usize>,
+    read_len: Cell<usize>,
+    phase: Cell<Phase>,
+    action: Cell<Action>,
+    stage: Cell<Stage>,
+    /// The byte of the stage being clocked.
+    index: Cell<usize>,
+    /// The bit of the byte being clocked, 8 for the acknowledge bit.
+    bit: Cell<u8>,
+    /// The byte being read.
+    byte: Cell<u8>,
+    /// When the device started stretching the clock, if it is.
+    stretch: OptionalCell<A::Ticks>,
+    status: Cell<Result<(), Error>>,
+}
+
+impl<'a, A: Alarm<'a>> BitbangI2C<'a, A> {
+    /// Create a controller clocking the bus at up to `speed` Hz.
+    pub fn new(alarm: &'a A, scl: &'a dyn Pin, sda: &'a dyn Pin, speed: u32) -> Self {
+        BitbangI2C {
+            alarm,
+            scl,
+            sda,
+            client: OptionalCell::empty(),
+            speed: Cell::new(speed),
+            buffer: TakeCell::empty(),
+            addr: Cell::new(0),
+            write_len: Cell::new(0),
+            read_len: Cell::new(0),
+            phase: Cell::new(Phase::Idle),
+            action: Cell::new(Action::Bit),
+            stage: Cell::new(Stage::AddressWrite),
+            index: Cell::new(0),
+            bit: Cell::new(0),
+            byte: Cell::new(0),
+            stretch: OptionalCell::empty(),
+            status: Cell::new(Ok(())),
+        }
+    }
+
+    /// Set the clock speed in Hz for the following transfers.
+    pub fn set_speed(&self, speed: u32) {
+        self.speed.set(speed);
+    }
+
+    pub fn get_speed(&self) -> u32 {
+        self.speed.get()
+    }
+
+    fn release(pin: &dyn Pin) {
+        pin.make_input();
+    }
+
+    fn pull_low(pin: &dyn Pin) {
+        pin.clear();
+        pin.make_output();
+    }
+
+    fn schedule(&self, phase: Phase) {
+        self.phase.set(phase);
+        let half_period = A::Frequency::frequency() / (2 * self.speed.get().max(1));
+        self.alarm
+            .set_alarm(self.alarm.now(), A::Ticks::from(half_period.max(1)));
+    }
+
+    fn start(
+        &self,
+        addr: u8,
+        buffer: &'static mut [u8],
+        write_len: usize,
+        read_len: usize,
+    ) -> Result<(), (Error, &'static mut [u8])> {
+        if self.phase.get() != Phase::Idle {
+            return Err((Error::Busy, buffer));
+        }
+        if write_len > buffer.len() || read_len > buffer.len() {
+            return Err((Error::NotSupported, buffer));
+        }
+        // A line held low means another controller or a stuck device.
+        if !self.scl.read() || !self.sda.read() {
+            return Err((Error::Busy, buffer));
+        }
+
+        self.buffer.replace(buffer);
+        self.addr.set(addr);
+        self.write_len.set(write_len);
+        self.read_len.set(read_len);
+        self.stage.set(if write_len == 0 && read_len > 0 {
+            Stage::AddressRead
+        } else {
+            Stage::AddressWrite
+        });
+        self.index.set(0);
+        self.bit.set(0);
+        self.action.set(Action::Bit);
+        self.status.set(Ok(()));
+
+        // Start condition: SDA falls while SCL is high.
+        Self::pull_low(self.sda);
+        self.schedule(Phase::Low);
+        Ok(())
+    }
+
+    /// The byte sent in the current stage.
+    fn out_byte(&self) -> u8 {
+        match self.stage.get() {
+            Stage::AddressWrite => self.addr.get() << 1,
+            Stage::AddressRead => self.addr.get() << 1 | 1,
+            Stage::Write => self.buffer.map_or(0, |buffer| buffer[self.index.get()]),
+            Stage::Read => 0xff,
+        }
+    }
+
+    fn clock_low(&self) {
+        Self::pull_low(self.scl);
+        match self.action.get() {
+            Action::Bit => {
+                let bit = self.bit.get();
+                let high = if self.stage.get() == Stage::Read {
+                    // Acknowledge all but the last byte read.
+                    bit < 8 || self.index.get() + 1 == self.read_len.get()
+                } else {
+                    bit == 8 || self.out_byte() & (0x80 >> bit) != 0
+                };
+                if high {
+                    Self::release(self.sda);
+                } else {
+                    Self::pull_low(self.sda);
+                }
+            }
+            Action::Restart => Self::release(self.sda),
+            Action::Stop => Self::pull_low(self.sda),
+        }
+        self.schedule(Phase::High);
+    }
+
+    fn clock_high(&self) {
+        Self::release(self.scl);
+        if !self.scl.read() {
+            let now = self.alarm.now();
+            match self.stretch.get() {
+                None => self.stretch.set(now),
+                Some(start) => {
+                    let timeout = self.alarm.ticks_from_ms(STRETCH_TIMEOUT_MS);
+                    if now.wrapping_sub(start).into_u32() > timeout.into_u32() {
+                        self.stretch.clear();
+                        Self::release(self.sda);
+                        self.finish(Err(Error::Busy));
+                        return;
+                    }
+                }
+            }
+            self.schedule(Phase::High);
+            return;
+        }
+        self.stretch.clear();
+        self.schedule(match self.action.get() {
+            Action::Bit => Phase::Sample,
+            Action::Restart => Phase::Restart,
+            Action::Stop => Phase::StopEnd,
+        });
+    }
+
+    fn sample(&self) {
+        let sda = self.sda.read();
+        let bit = self.bit.get();
+        if bit < 8 {
+            if self.stage.get() == Stage::Read {
+                self.byte.set(self.byte.get() << 1 | sda as u8);
+            } else if !sda && self.out_byte() & (0x80 >> bit) != 0 {
+                // Another controller pulled SDA low; leave the bus to it.
+                Self::release(self.sda);
+                self.finish(Err(Error::ArbitrationLost));
+                return;
+            }
+            self.bit.set(bit + 1);
+        } else {
+            self.bit.set(0);
+            self.byte_done(!sda);
+        }
+        self.clock_low();
+    }
+
+    /// Move on after the acknowledge bit of a byte.
+    fn byte_done(&self, ack: bool) {
+        let index = self.index.get() + 1;
+        let after_write = || {
+            if self.read_len.get() > 0 {
+                Action::Restart
+            } else {
+                Action::Stop
+            }
+        };
+        let action = match self.stage.get() {
+            Stage::AddressWrite | Stage::AddressRead if !ack => {
+                self.status.set(Err(Error::AddressNak));
+                Action::Stop
+            }
+            Stage::Write if !ack => {
+                self.status.set(Err(Error::DataNak));
+                Action::Stop
+            }
+            Stage::AddressWrite if self.write_len.get() > 0 => {
+                self.stage.set(Stage::Write);
+                self.index.set(0);
+                Action::Bit
+            }
+            Stage::AddressWrite => after_write(),
+            Stage::Write if index < self.write_len.get() => {
+                self.index.set(index);
+                Action::Bit
+            }
+            Stage::Write => after_write(),
+            Stage::AddressRead => {
+                self.stage.set(Stage::Read);
+                self.index.set(0);
+                Action::Bit
+            }
+            Stage::Read => {
+                self.buffer
+                    .map(|buffer| buffer[index - 1] = self.byte.get());
+                if index < self.read_len.get() {
+                    self.index.set(index);
+                    Action::Bit
+                } else {
+                    Action::Stop
+                }
+            }
+        };
+        self.action.set(action);
+    }
+
+    fn finish(&self, status: Result<(), Error>) {
+        self.phase.set(Phase::Idle);
+        self.buffer.take().map(|buffer| {
+            self.client
+                .map(move |client| client.command_complete(buffer, status))
+        });
+    }
+}
+
+impl<'a, A: Alarm<'a>> AlarmClient for BitbangI2C<'a, A> {
+    fn alarm(&self) {
+        match self.phase.get() {
+            Phase::Idle => {}
+            Phase::Low => self.clock_low(),
+            Phase::High => self.clock_high(),
+            Phase::Sample => self.sample(),
+            Phase::Restart => {
+                Self::pull_low(self.sda);
+                self.stage.set(Stage::AddressRead);
+                self.action.set(Action::Bit);
+                self.schedule(Phase::Low);
+            }
+            Phase::StopEnd => {
+                Self::release(self.sda);
+                self.finish(self.status.get());
+            }
+        }
+    }
+}
+
+impl<'a, A: Alarm<'a>> I2CMaster<'a> for BitbangI2C<'a, A> {
+    fn set_master_client(&self, master_client: &'a dyn I2CHwMasterClient) {
+        self.client.set(master_client);
+    }
+
+    fn enable(&self) {
+        for pin in [self.scl, self.sda] {
+            pin.set_floating_state(FloatingState::PullUp);
+            Self::release(pin);
+        }
+    }
+
+    fn disable(&self) {
+        Self::release(self.scl);
+        Self::release(self.sda);
+    }
+
+    fn write_read(
+        &self,
+        addr: u8,
+        data: &'static mut [u8],
+        write_len: usize,
+        read_len: usize,
+    ) -> Result<(), (Error, &'static mut [u8])> {
+        self.start(addr, data, write_len, read_len)
+    }
+
+    fn write(
+        &self,
+        addr: u8,
+        data: &'static mut [u8],
+        len: usize,
+    ) -> Result<(), (Error, &'static mut [u8])> {
+        self.start(addr, data, len, 0)
+    }
+
+    fn read(
+        &self,
+        addr: u8,
+        buffer: &'static mut [u8],
+        len: usize,
+    ) -> Result<(), (Error, &'static mut [u8])> {
+        self.start(addr, buffer, 0, len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::alarm_fake::FakeAlarm;
+    use crate::gpio_fake::{FakePin, PinListener};
+    use kernel::hil::time::{Freq1MHz, Time};
+    use kernel::utilities::cells::MapCell;
+    use std::boxed::Box;
+    use std::cell::RefCell;
+    use std::vec;
+    use std::vec::Vec;
+
+    #[derive(Clone, Copy, PartialEq, Debug)]
+    enum Condition {
+        Start,
+        Stop,
+    }
+
+    #[derive(Clone, Copy, PartialEq)]
+    enum State {
+        Address,
+        Write,
+        Read,
+        Ignore,
+    }
+
+    /// A device on the bus, which stores the bytes written to it and sends
+    /// `data` when read.
+    struct FakeDevice {
+        scl: &'static FakePin,
+        sda: &'static FakePin,
+        address: u8,
+        levels: Cell<(bool, bool)>,
+        state: Cell<State>,
+        /// The bit clocked next, 8 for the acknowledge bit.
+        bit: Cell<usize>,
+        byte: Cell<u8>,
+        conditions: RefCell<Vec<Condition>>,
+        written: RefCell<Vec<u8>>,
+        data: RefCell<Vec<u8>>,
+    }
+
+    impl FakeDevice {
+        fn rising(&self, sda: bool) {
+            let bit = self.bit.get();
+            match self.state.get() {
+                State::Address | State::Write if bit < 8 => {
+                    self.byte.set(self.byte.get() << 1 | sda as u8);
+                }
+                // The controller doesn't acknowledge the last byte it reads.
+                State::Read if bit == 8 && sda => self.state.set(State::Ignore),
+                _ => {}
+            }
+            self.bit.set((bit + 1) % 9);
+        }
+
+        fn falling(&self) {
+            let bit = self.bit.get();
+            match self.state.get() {
+                State::Address if bit == 8 => {
+                    let byte = self.byte.get();
+                    if byte >> 1 == self.address {
+                        self.sda.hold_low(true);
+                        self.state.set(if byte & 1 != 0 {
+                            State::Read
+                        } else {
+                            State::Write
+                        });
+                    } else {
+                        self.state.set(State::Ignore);
+                    }
+                }
+                State::Write if bit == 8 => {
+                    self.written.borrow_mut().push(self.byte.get());
+                    self.sda.hold_low(true);
+                }
+                State::Read if bit == 0 => {
+                    let mut data = self.data.borrow_mut();
+                    let byte = if data.is_empty() {
+                        0xff
+                    } else {
+                        data.remove(0)
+                    };
+                    self.byte.set(byte);
+                    self.sda.hold_low(byte & 0x80 == 0);
+                }
+                State::Read if bit < 8 => self.sda.hold_low(self.byte.get() & (0x80 >> bit) == 0),
+                // Release SDA for the acknowledge bit of the controller, or
+                // after acknowledging a byte written.
+                State::Read | State::Write => self.sda.hold_low(false),
+                _ => {}
+            }
+        }
+    }
+
+    impl PinListener for FakeDevice {
+        fn changed(&self) {
+            let (scl, sda) = (self.scl.level(), self.sda.level());
+            let (last_scl, last_sda) = self.levels.get();
+            if scl && last_scl && sda != last_sda {
+                if sda {
+                    self.conditions.borrow_mut().push(Condition::Stop);
+                    self.state.set(State::Ignore);
+                } else {
+                    self.conditions.borrow_mut().push(Condition::Start);
+                    self.state.set(State::Address);
+                    self.bit.set(0);
+                    self.byte.set(0);
+                }
+            } else if scl && !last_scl {
+                self.rising(sda);
+            } else if !scl && last_scl {
+                self.falling();
+            }
+            self.levels.set((self.scl.level(), self.sda.level()));
+        }
+    }
+
+    struct FakeClient {
+        result: MapCell<(&'static mut [u8], Result<(), Error>)>,
+    }
+
+    impl I2CHwMasterClient for FakeClient {
+        fn command_complete(&self, buffer: &'static mut [u8], status: Result<(), Error>) {
+            self.result.replace((buffer, status));
+        }
+    }
+
+    struct Bus {
+        i2c: &'static BitbangI2C<'static, FakeAlarm<Freq1MHz>>,
+        alarm: &'static FakeAlarm<Freq1MHz>,
+        device: &'static FakeDevice,
+        client: &'static FakeClient,
+    }
+
+    fn setup() -> Bus {
+        let alarm = Box::leak(Box::new(FakeAlarm::new()));
+        let scl = Box::leak(Box::new(FakePin::new()));
+        let sda = Box::leak(Box::new(FakePin::new()));
+        let i2c = Box::leak(Box::new(BitbangI2C::new(alarm, scl, sda, 100_000)));
+        let device = Box::leak(Box::new(FakeDevice {
+            scl,
+            sda,
+            address: 0x50,
+            levels: Cell::new((true, true)),
+            state: Cell::new(State::Ignore),
+            bit: Cell::new(0),
+            byte: Cell::new(0),
+            conditions: RefCell::new(Vec::new()),
+            written: RefCell::new(Vec::new()),
+            data: RefCell::new(Vec::new()),
+        }));
+        let client = Box::leak(Box::new(FakeClient {
+            result: MapCell::empty(),
+        }));
+        alarm.set_alarm_client(i2c);
+        scl.set_listener(device);
+        sda.set_listener(device);
+        i2c.set_master_client(client);
+        i2c.enable();
+        Bus {
+            i2c,
+            alarm,
+            device,
+            client,
+        }
+    }
+
+    /// Clock the transfer to the end, and return the buffer and result.
+    fn finish(bus: &Bus) -> (&'static mut [u8], Result<(), Error>) {
+        bus.alarm.fire_all();
+        bus.client.result.take().unwrap()
+    }
+
+    #[test]
+    fn write_then_read() {
+        let bus = setup();
+        *bus.device.data.borrow_mut() = vec![0xab, 0xcd];
+        let buffer = Box::leak(Box::new([0x12, 0x34, 0, 0]));
+        assert!(bus.i2c.write_read(0x50, buffer, 2, 2).is_ok());
+        let (buffer, result) = finish(&bus);
+        assert_eq!(result, Ok(()));
+        assert_eq!(buffer, &[0xab, 0xcd, 0, 0]);
+        assert_eq!(*bus.device.written.borrow(), [0x12, 0x34]);
+        assert_eq!(
+            *bus.device.conditions.borrow(),
+            [Condition::Start, Condition::Start, Condition::Stop]
+        );
+        // The bus is released when the transfer ends.
+        assert!(bus.device.scl.level() && bus.device.sda.level());
+    }
+
+    #[test]
+    fn read_only() {
+        let bus = setup();
+        *bus.device.data.borrow_mut() = vec![0x81, 0x7e, 0x00];
+        let buffer = Box::leak(Box::new([0; 3]));
+        assert!(bus.i2c.read(0x50, buffer, 3).is_ok());
+        let (buffer, result) = finish(&bus);
+        assert_eq!(result, Ok(()));
+        assert_eq!(buffer, &[0x81, 0x7e, 0x00]);
+        assert!(bus.device.written.borrow().is_empty());
+        assert_eq!(
+            *bus.device.conditions.borrow(),
+            [Condition::Start, Condition::Stop]
+        );
+    }
+
+    #[test]
+    fn address_nak() {
+        let bus = setup();
+        let buffer = Box::leak(Box::new([0x12]));
+        assert!(bus.i2c.write(0x51, buffer, 1).is_ok());
+        assert_eq!(finish(&bus).1, Err(Error::AddressNak));
+        assert!(bus.device.written.borrow().is_empty());
+        assert_eq!(
+            *bus.device.conditions.borrow(),
+            [Condition::Start, Condition::Stop]
+        );
+    }
+
+    #[test]
+    fn arbitration_lost() {
+        let bus = setup();
+        let buffer = Box::leak(Box::new([0x12]));
+        assert!(bus.i2c.write(0x50, buffer, 1).is_ok());
+        // Another controller sends a 0 where this one sends the first 1 of
+        // the address.
+        bus.device.sda.hold_low(true);
+        assert_eq!(finish(&bus).1, Err(Error::ArbitrationLost));
+        bus.device.sda.hold_low(false);
+        assert!(bus.device.scl.level());
+    }
+
+    #[test]
+    fn clock_stretching_times_out() {
+        let bus = setup();
+        let buffer = Box::leak(Box::new([0x12]));
+        assert!(bus.i2c.write(0x50, buffer, 1).is_ok());
+        bus.device.scl.hold_low(true);
+        let start = bus.alarm.now();
+        assert_eq!(finish(&bus).1, Err(Error::Busy));
+        let waited = bus.alarm.now().wrapping_sub(start).into_u32();
+        assert!(waited > STRETCH_TIMEOUT_MS * 1000);
+        assert!(waited < (STRETCH_TIMEOUT_MS + 1) * 1000);
+    }
+
+    #[test]
+    fn busy_and_invalid_length() {
+        let bus = setup();
+        let buffer = Box::leak(Box::new([0; 2]));
+        let (error, buffer) = bus.i2c.write(0x50, buffer, 3).unwrap_err();
+        assert_eq!(error, Error::NotSupported);
+        assert!(bus.i2c.write(0x50, buffer, 2).is_ok());
+        let other = Box::leak(Box::new([0; 2]));
+        let (error, other) = bus.i2c.read(0x50, other, 1).unwrap_err();
+        assert_eq!(error, Error::Busy);
+        assert_eq!(finish(&bus).1, Ok(()));
+
+        // A line held low when the transfer starts.
+        bus.device.sda.hold_low(true);
+        assert_eq!(bus.i2c.read(0x50, other, 1).unwrap_err().0, Error::Busy);
+    }
+}
@@ -0,0 +1,644 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! 1-Wire bus controller bit-banged over a GPIO pin.
+//!
+//! Implements `hil::onewire::OneWire` at standard speed. The line is driven
+//! as open drain: the pin is made an output and cleared to pull the line low,
+//! and made an input to release it, so the bus needs a pull-up resistor,
+//! usually 4.7 kOhm.
+//!
+//! Each bit is one time slot of about 70 us. The part of a slot in which the
+//! line is low and sampled is timed by polling the alarm counter, and the
+//! alarm waits out the rest of the slot and the reset pulse. The alarm should
+//! count at 1 MHz or faster to time the slots; interrupts taking more than a
+//! few microseconds while the line is low can corrupt a bit, which devices
+//! catch with the CRCs of their data.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! let onewire = components::bitbang_onewire::BitbangOneWireComponent::new(
+//!     mux_alarm,
+//!     &earlgrey::gpio::PORT[ONEWIRE_PIN],
+//! )
+//! .finalize(components::bitbang_onewire_component_static!(
+//!     earlgrey::timer::RvTimer<'static>
+//! ));
+//! ```
+
+use core::cell::Cell;
+
+use kernel::hil::gpio::{FloatingState, Pin};
+use kernel::hil::onewire::{self, OneWire, OneWireClient};
+use kernel::hil::time::{Alarm, AlarmClient, ConvertTicks, Ticks};
+use kernel::utilities::cells::OptionalCell;
+use kernel::ErrorCode;
+
+/// The bit slots of a search: the command, then for each of the 64 bits of
+/// the ROM code the bit, its complement and the direction chosen.
+const SEARCH_SLOTS: u8 = 8 + 64 * 3;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Operation {
+    Idle,
+    Reset,
+    Write,
+    Read,
+    Search,
+}
+
+/// What the next alarm does.
+#[derive(Clone, Copy, PartialEq)]
+enum Step {
+    /// End the reset pulse and listen for presence pulses.
+    ResetRelease,
+    /// The reset is over.
+    ResetEnd,
+    /// Run the next bit slot, or complete the operation after the last one.
+    Slot,
+}
+
+pub struct BitbangOneWire<'a, A: Alarm<'a>> {
+    alarm: &'a A,
+    pin: &'a dyn Pin,
+    client: OptionalCell<&'a dyn OneWireClient>,
+    operation: Cell<Operation>,
+    step: Cell<Step>,
+    presence: Cell<bool>,
+    /// The bits written or read.
+    value: Cell<u8>,
+    /// The next slot of the operation, and the number of slots.
+    slot: Cell<u8>,
+    slots: Cell<u8>,
+    /// The ROM code of the search in progress or of the last device found.
+    rom: Cell<u64>,
+    /// The bit read in the first slot of a search bit.
+    id_bit: Cell<bool>,
+    /// The last bit, from 1, where the search took the 0 branch of a
+    /// discrepancy, in the last search step and in the one in progress.
+    last_discrepancy: Cell<u8>,
+    last_zero: Cell<u8>,
+    last_device: Cell<bool>,
+    search_failed: Cell<bool>,
+}
+
+impl<'a, A: Alarm<'a>> BitbangOneWire<'a, A> {
+    pub fn new(alarm: &'a A, pin: &'a dyn Pin) -> Self {
+        BitbangOneWire {
+            alarm,
+            pin,
+            client: OptionalCell::empty(),
+            operation: Cell::new(Operation::Idle),
+            step: Cell::new(Step::Slot),
+            presence: Cell::new(false),
+            value: Cell::new(0),
+            slot: Cell::new(0),
+            slots: Cell::new(0),
+            rom: Cell::new(0),
+            id_bit: Cell::new(false),
+            last_discrepancy: Cell::new(0),
+            last_zero: Cell::new(0),
+            last_device: Cell::new(false),
+            search_failed: Cell::new(false),
+        }
+    }
+
+    /// Release the line.
+    pub fn init(&self) {
+        self.pin.set_floating_state(FloatingState::PullNone);
+        self.pin.make_input();
+    }
+
+    fn pull_low(&self) {
+        self.pin.clear();
+        self.pin.make_output();
+    }
+
+    fn release(&self) {
+        self.pin.make_input();
+    }
+
+    fn wait_us(&self, us: u32) {
+        let start = self.alarm.now();
+        let ticks = self.alarm.ticks_from_us(us).into_u32();
+        while self.alarm.now().wrapping_sub(start).into_u32() < ticks {}
+    }
+
+    fn schedule(&self, step: Step, us: u32) {
+        self.step.set(step);
+        let ticks = self.alarm.ticks_from_us(us);
+        let ticks = if ticks.into_u32() == 0 {
+            A::Ticks::from(1)
+        } else {
+            ticks
+        };
+        self.alarm.set_alarm(self.alarm.now(), ticks);
+    }
+
+    /// Write a bit, returning how long the rest of the slot lasts in us.
+    fn write_slot(&self, bit: bool) -> u32 {
+        self.pull_low();
+        if bit {
+            self.wait_us(6);
+            self.release();
+            64
+        } else {
+            self.wait_us(60);
+            self.release();
+            10
+        }
+    }
+
+    /// Read a bit, also returning how long the rest of the slot lasts in us.
+    fn read_slot(&self) -> (bool, u32) {
+        self.pull_low();
+        self.wait_us(6);
+        self.release();
+        self.wait_us(9);
+        (self.pin.read(), 55)
+    }
+
+    fn start(&self, operation: Operation, value: u8, slots: u8) -> Result<(), ErrorCode> {
+        if self.operation.get() != Operation::Idle {
+            return Err(ErrorCode::BUSY);
+        }
+        self.operation.set(operation);
+        self.value.set(value);
+        self.slot.set(0);
+        self.slots.set(slots);
+        self.schedule(Step::Slot, 1);
+        Ok(())
+    }
+
+    fn start_reset(&self, operation: Operation) -> Result<(), ErrorCode> {
+        if self.operation.get() != Operation::Idle {
+            return Err(ErrorCode::BUSY);
+        }
+        self.operation.set(operation);
+        self.pull_low();
+        self.schedule(Step::ResetRelease, 480);
+        Ok(())
+    }
+
+    fn search_slot(&self, slot: u8) -> u32 {
+        if slot < 8 {
+            return self.write_slot(onewire::SEARCH_ROM & (1 << slot) != 0);
+        }
+        let bit = (slot - 8) / 3;
+        let mask = 1u64 << bit;
+        match (slot - 8) % 3 {
+            0 => {
+                let (id_bit, rest) = self.read_slot();
+                self.id_bit.set(id_bit);
+                rest
+            }
+            1 => {
+                let (complement, rest) = self.read_slot();
+                let id_bit = self.id_bit.get();
+                let direction = if id_bit != complement {
+                    id_bit
+                } else if id_bit {
+                    // No device answered.
+                    self.search_failed.set(true);
+                    self.slot.set(SEARCH_SLOTS);
+                    return rest;
+                } else {
+                    // Devices differ at this bit: take the branch the last
+                    // step took before its last discrepancy, then the 1
+                    // branch at it, and the 0 branch after it.
+                    let discrepancy = self.last_discrepancy.get();
+                    let direction = if bit + 1 < discrepancy {
+                        self.rom.get() & mask != 0
+                    } else {
+                        bit + 1 == discrepancy
+                    };
+                    if !direction {
+                        self.last_zero.set(bit + 1);
+                    }
+                    direction
+                };
+                let rom = self.rom.get() & !mask;
+                self.rom.set(if direction { rom | mask } else { rom });
+                rest
+            }
+            _ => self.write_slot(self.rom.get() & mask != 0),
+        }
+    }
+
+    fn search_result(&self) -> Result<Option<u64>, ErrorCode> {
+        if self.slots.get() == 0 {
+            return Ok(None);
+        }
+        let rom = self.rom.get();
+        if self.search_failed.get() || onewire::crc8(&rom.to_le_bytes()) != 0 {
+            self.last_discrepancy.set(0);
+            self.last_device.set(false);
+            return Err(ErrorCode::FAIL);
+        }
+        self.last_discrepancy.set(self.last_zero.get());
+        self.last_device.set(self.last_zero.get() == 0);
+        Ok(Some(rom))
+    }
+
+    fn complete(&self) {
+        let operation = self.operation.get();
+        self.operation.set(Operation::Idle);
+        self.client.map(|client| match operation {
+            Operation::Idle => {}
+            Operation::Reset => client.reset_done(self.presence.get()),
+            Operation::Write => client.write_done(),
+            Operation::Read => client.read_done(self.value.get()),
+            Operation::Search => client.search_done(self.search_result()),
+        });
+    }
+}
+
+impl<'a, A: Alarm<'a>> AlarmClient for BitbangOneWire<'a, A> {
+    fn alarm(&self) {
+        match self.step.get() {
+            Step::ResetRelease => {
+                self.release();
+                self.wait_us(70);
+                self.presence.set(!self.pin.read());
+                self.schedule(Step::ResetEnd, 410);
+            }
+            Step::ResetEnd => {
+                if self.operation.get() != Operation::Search {
+                    self.complete();
+                } else if !self.presence.get() {
+                    self.operation.set(Operation::Idle);
+                    self.last_discrepancy.set(0);
+                    self.last_device.set(false);
+                    self.client.map(|client| client.search_done(Ok(None)));
+                } else {
+                    self.slot.set(0);
+                    self.slots.set(SEARCH_SLOTS);
+                    self.last_zero.set(0);
+                    self.search_failed.set(false);
+                    self.schedule(Step::Slot, 1);
+                }
+            }
+            Step::Slot => {
+                let slot = self.slot.get();
+                if slot >= self.slots.get() {
+                    self.complete();
+                    return;
+                }
+                self.slot.set(slot + 1);
+                let rest = match self.operation.get() {
+                    Operation::Write => self.write_slot(self.value.get() & (1 << slot) != 0),
+                    Operation::Read => {
+                        let (bit, rest) = self.read_slot();
+                        self.value.set(self.value.get() | (bit as u8) << slot);
+                        rest
+                    }
+                    Operation::Search => self.search_slot(slot),
+                    Operation::Idle | Operation::Reset => 0,
+                };
+                self.schedule(Step::Slot, rest);
+            }
+        }
+    }
+}
+
+impl<'a, A: Alarm<'a>> OneWire<'a> for BitbangOneWire<'a, A> {
+    fn set_client(&self, client: &'a dyn OneWireClient) {
+        self.client.set(client);
+    }
+
+    fn reset(&self) -> Result<(), ErrorCode> {
+        self.start_reset(Operation::Reset)
+    }
+
+    fn write_bit(&self, bit: bool) -> Result<(), ErrorCode> {
+        self.start(Operation::Write, bit as u8, 1)
+    }
+
+    fn read_bit(&self) -> Result<(), ErrorCode> {
+        self.start(Operation::Read, 0, 1)
+    }
+
+    fn write_byte(&self, byte: u8) -> Result<(), ErrorCode> {
+        self.start(Operation::Write, byte, 8)
+    }
+
+    fn read_byte(&self) -> Result<(), ErrorCode> {
+        self.start(Operation::Read, 0, 8)
+    }
+
+    fn search_rom(&self, first: bool) -> Result<(), ErrorCode> {
+        if self.operation.get() != Operation::Idle {
+            return Err(ErrorCode::BUSY);
+        }
+        if first {
+            self.last_discrepancy.set(0);
+            self.last_device.set(false);
+        }
+        if self.last_device.get() {
+            // All devices were found: complete without a bus transaction,
+            // and start over at the next step.
+            self.last_discrepancy.set(0);
+            self.last_device.set(false);
+            return self.start(Operation::Search, 0, 0);
+        }
+        self.start_reset(Operation::Search)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::alarm_fake::FakeAlarm;
+    use crate::gpio_fake::{FakePin, PinListener};
+    use kernel::hil::time::Freq1MHz;
+    use std::boxed::Box;
+    use std::cell::RefCell;
+    use std::vec;
+    use std::vec::Vec;
+
+    #[derive(Clone, Copy, PartialEq)]
+    enum State {
+        Command,
+        Search,
+        Data,
+        Inactive,
+    }
+
+    /// A device with ROM code `rom`. It takes part in searches, and answers
+    /// other commands by sending `data`.
+    struct FakeDevice {
+        rom: u64,
+        holding: Cell<bool>,
+        state: Cell<State>,
+        /// The number of slots of the state done.
+        slots: Cell<usize>,
+        command: Cell<u8>,
+        data: Vec<u8>,
+    }
+
+    impl FakeDevice {
+        fn new(rom: u64, data: Vec<u8>) -> Self {
+            FakeDevice {
+                rom,
+                holding: Cell::new(false),
+                state: Cell::new(State::Inactive),
+                slots: Cell::new(0),
+                command: Cell::new(0),
+                data,
+            }
+        }
+
+        fn rom_bit(&self, bit: usize) -> bool {
+            self.rom & (1 << bit) != 0
+        }
+
+        /// The controller pulled the line low: send a 0 by holding it.
+        fn slot_start(&self) {
+            let slots = self.slots.get();
+            let zero = match self.state.get() {
+                State::Search => match slots % 3 {
+                    0 => !self.rom_bit(slots / 3),
+                    1 => self.rom_bit(slots / 3),
+                    _ => false,
+                },
+                State::Data => {
+                    let byte = self.data.get(slots / 8).copied().unwrap_or(0xff);
+                    byte & (1 << (slots % 8)) == 0
+                }
+                State::Command | State::Inactive => false,
+            };
+            self.holding.set(zero);
+        }
+
+        /// The controller released the line after `us` microseconds.
+        fn slot_end(&self, us: u32) {
+            if us >= 480 {
+                self.state.set(State::Command);
+                self.slots.set(0);
+                self.command.set(0);
+                // Presence pulse.
+                self.holding.set(true);
+                return;
+            }
+            let slots = self.slots.get();
+            let bit = us < 15;
+            match self.state.get() {
+                State::Command => {
+                    self.command.set(self.command.get() | (bit as u8) << slots);
+                    if slots == 7 {
+                        self.state
+                            .set(if self.command.get() == onewire::SEARCH_ROM {
+                                State::Search
+                            } else {
+                                State::Data
+                            });
+                        self.slots.set(0);
+                        return;
+                    }
+                }
+                // Devices drop out of the search where the controller takes
+                // the other branch, and after the last bit.
+                State::Search if slots % 3 == 2 => {
+                    if bit != self.rom_bit(slots / 3) || slots / 3 == 63 {
+                        self.state.set(State::Inactive);
+                    }
+                }
+                _ => {}
+            }
+            self.slots.set(slots + 1);
+        }
+    }
+
+    struct FakeBus {
+        pin: &'static FakePin,
+        alarm: &'static FakeAlarm<Freq1MHz>,
+        devices: Vec<FakeDevice>,
+        /// When the controller started pulling the line low, while it does.
+        low_since: Cell<Option<u32>>,
+    }
+
+    impl PinListener for FakeBus {
+        fn changed(&self) {
+            let now = self.alarm.ticks();
+            match (self.pin.driven_low(), self.low_since.get()) {
+                (true, None) => {
+                    self.low_since.set(Some(now));
+                    self.devices.iter().for_each(FakeDevice::slot_start);
+                }
+                (false, Some(since)) => {
+                    self.low_since.set(None);
+                    for device in &self.devices {
+                        device.slot_end(now.wrapping_sub(since));
+                    }
+                }
+                _ => {}
+            }
+            self.pin
+                .hold_low(self.devices.iter().any(|device| device.holding.get()));
+        }
+    }
+
+    #[derive(PartialEq, Debug)]
+    enum Event {
+        Reset(bool),
+        Write,
+        Read(u8),
+        Search(Result<Option<u64>, ErrorCode>),
+    }
+
+    struct FakeClient {
+        events: RefCell<Vec<Event>>,
+    }
+
+    impl OneWireClient for FakeClient {
+        fn reset_done(&self, presence: bool) {
+            self.events.borrow_mut().push(Event::Reset(presence));
+        }
+
+        fn write_done(&self) {
+            self.events.borrow_mut().push(Event::Write);
+        }
+
+        fn read_done(&self, value: u8) {
+            self.events.borrow_mut().push(Event::Read(value));
+        }
+
+        fn search_done(&self, rom: Result<Option<u64>, ErrorCode>) {
+            self.events.borrow_mut().push(Event::Search(rom));
+        }
+    }
+
+    struct Setup {
+        onewire: &'static BitbangOneWire<'static, FakeAlarm<Freq1MHz>>,
+        alarm: &'static FakeAlarm<Freq1MHz>,
+        bus: &'static FakeBus,
+        client: &'static FakeClient,
+    }
+
+    impl Setup {
+        /// Run an operation to its callback, and return the event.
+        fn run(&self, operation: Result<(), ErrorCode>) -> Event {
+            assert_eq!(operation, Ok(()));
+            self.alarm.fire_all();
+            let mut events = self.client.events.borrow_mut();
+            assert_eq!(events.len(), 1);
+            events.pop().unwrap()
+        }
+    }
+
+    fn setup(devices: Vec<FakeDevice>) -> Setup {
+        let alarm = Box::leak(Box::new(FakeAlarm::new()));
+        // Each poll of the counter takes a microsecond.
+        alarm.set_tick(1);
+        let pin = Box::leak(Box::new(FakePin::new()));
+        let onewire = Box::leak(Box::new(BitbangOneWire::new(alarm, pin)));
+        let bus = Box::leak(Box::new(FakeBus {
+            pin,
+            alarm,
+            devices,
+            low_since: Cell::new(None),
+        }));
+        let client = Box::leak(Box::new(FakeClient {
+            events: RefCell::new(Vec::new()),
+        }));
+        alarm.set_alarm_client(onewire);
+        pin.set_listener(bus);
+        onewire.set_client(client);
+        onewire.init();
+        Setup {
+            onewire,
+            alarm,
+            bus,
+            client,
+        }
+    }
+
+    /// A ROM code with family code 0x28, `serial` and its CRC.
+    fn rom(serial: u64) -> u64 {
+        let code = 0x28 | serial << 8;
+        code | (onewire::crc8(&code.to_le_bytes()[..7]) as u64) << 56
+    }
+
+    #[test]
+    fn reset_presence() {
+        let setup = setup(Vec::new());
+        assert_eq!(setup.run(setup.onewire.reset()), Event::Reset(false));
+
+        let setup = self::setup(vec![FakeDevice::new(rom(1), Vec::new())]);
+        assert_eq!(setup.run(setup.onewire.reset()), Event::Reset(true));
+    }
+
+    #[test]
+    fn write_and_read() {
+        let setup = setup(vec![FakeDevice::new(rom(1), vec![0x50, 0x05, 0x81])]);
+        let onewire = setup.onewire;
+        assert_eq!(setup.run(onewire.reset()), Event::Reset(true));
+        assert_eq!(setup.run(onewire.write_byte(0xbe)), Event::Write);
+        assert_eq!(setup.bus.devices[0].command.get(), 0xbe);
+        assert_eq!(setup.run(onewire.read_byte()), Event::Read(0x50));
+        assert_eq!(setup.run(onewire.read_byte()), Event::Read(0x05));
+        assert_eq!(setup.run(onewire.read_bit()), Event::Read(1));
+        assert_eq!(setup.run(onewire.read_bit()), Event::Read(0));
+
+        assert_eq!(onewire.write_bit(true), Ok(()));
+        assert_eq!(onewire.read_byte(), Err(ErrorCode::BUSY));
+        assert_eq!(onewire.search_rom(true), Err(ErrorCode::BUSY));
+    }
+
+    #[test]
+    fn search() {
+        let devices = [1, 2, 3].map(|serial| FakeDevice::new(rom(serial), Vec::new()));
+        let setup = setup(devices.into());
+        let onewire = setup.onewire;
+        // The search takes the 0 branch first where the ROM codes differ,
+        // from the least significant bit.
+        assert_eq!(
+            setup.run(onewire.search_rom(true)),
+            Event::Search(Ok(Some(rom(2))))
+        );
+        assert_eq!(
+            setup.run(onewire.search_rom(false)),
+            Event::Search(Ok(Some(rom(1))))
+        );
+        assert_eq!(
+            setup.run(onewire.search_rom(false)),
+            Event::Search(Ok(Some(rom(3))))
+        );
+        assert_eq!(
+            setup.run(onewire.search_rom(false)),
+            Event::Search(Ok(None))
+        );
+        // The next step starts over.
+        assert_eq!(
+            setup.run(onewire.search_rom(false)),
+            Event::Search(Ok(Some(rom(2))))
+        );
+        assert_eq!(
+            setup.run(onewire.search_rom(true)),
+            Event::Search(Ok(Some(rom(2))))
+        );
+    }
+
+    #[test]
+    fn search_without_devices() {
+        let setup = setup(Vec::new());
+        assert_eq!(
+            setup.run(setup.onewire.search_rom(true)),
+            Event::Search(Ok(None))
+        );
+    }
+
+    #[test]
+    fn search_bad_crc() {
+        let setup = setup(vec![FakeDevice::new(rom(1) ^ 1 << 60, Vec::new())]);
+        assert_eq!(
+            setup.run(setup.onewire.search_rom(true)),
+            Event::Search(Err(ErrorCode::FAIL))
+        );
+    }
+}
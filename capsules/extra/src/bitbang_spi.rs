@@ -0,0 +1,575 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! SPI controller bit-banged over GPIO pins.
+//!
+//! Implements `hil::spi::SpiMaster` with a clock, a data out and a data in
+//! pin, and a GPIO pin as chip select. Bytes are sent most significant bit
+//! first, in any of the four clock modes.
+//!
+//! Each half period of the clock is one alarm, so rates are rounded down to
+//! a whole number of alarm ticks. For rates above half the alarm frequency,
+//! each alarm instead clocks a whole byte as fast as the pins can be toggled,
+//! and `set_rate` returns the rate asked for; the devices on the bus must
+//! then accept the speed of the pins.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! let spi = components::bitbang_spi::BitbangSpiComponent::new(
+//!     mux_alarm,
+//!     &nrf52840_peripherals.gpio_port[SCK_PIN],
+//!     &nrf52840_peripherals.gpio_port[MOSI_PIN],
+//!     &nrf52840_peripherals.gpio_port[MISO_PIN],
+//! )
+//! .finalize(components::bitbang_spi_component_static!(
+//!     nrf52840::rtc::Rtc<'static>
+//! ));
+//! let mux_spi = components::spi::SpiMuxComponent::new(spi).finalize(
+//!     components::spi_mux_component_static!(
+//!         components::bitbang_spi::BitbangSpiComponentType<
+//!             nrf52840::rtc::Rtc<'static>,
+//!         >
+//!     ),
+//! );
+//! ```
+
+use core::cell::Cell;
+use core::cmp;
+
+use kernel::hil::gpio::Pin;
+use kernel::hil::spi::{ClockPhase, ClockPolarity, SpiMaster, SpiMasterClient};
+use kernel::hil::time::{Alarm, AlarmClient, Frequency};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::ErrorCode;
+
+pub struct BitbangSpi<'a, A: Alarm<'a>> {
+    alarm: &'a A,
+    sck: &'a dyn Pin,
+    mosi: &'a dyn Pin,
+    miso: &'a dyn Pin,
+    chip_select: OptionalCell<&'a dyn Pin>,
+    client: OptionalCell<&'a dyn SpiMasterClient>,
+    rate: Cell<u32>,
+    polarity: Cell<ClockPolarity>,
+    phase: Cell<ClockPhase>,
+    hold_low: Cell<bool>,
+    busy: Cell<bool>,
+    write_buffer: TakeCell<'static, [u8]>,
+    read_buffer: TakeCell<'static, [u8]>,
+    len: Cell<usize>,
+    /// The number of clock edges of the transfer done.
+    edge: Cell<usize>,
+    /// The byte being read.
+    byte: Cell<u8>,
+}
+
+impl<'a, A: Alarm<'a>> BitbangSpi<'a, A> {
+    pub fn new(alarm: &'a A, sck: &'a dyn Pin, mosi: &'a dyn Pin, miso: &'a dyn Pin) -> Self {
+        BitbangSpi {
+            alarm,
+            sck,
+            mosi,
+            miso,
+            chip_select: OptionalCell::empty(),
+            client: OptionalCell::empty(),
+            rate: Cell::new(A::Frequency::frequency() / 2),
+            polarity: Cell::new(ClockPolarity::IdleLow),
+            phase: Cell::new(ClockPhase::SampleLeading),
+            hold_low: Cell::new(false),
+            busy: Cell::new(false),
+            write_buffer: TakeCell::empty(),
+            read_buffer: TakeCell::empty(),
+            len: Cell::new(0),
+            edge: Cell::new(0),
+            byte: Cell::new(0),
+        }
+    }
+
+    /// The alarm ticks in half a clock period, or 0 to clock bytes as fast as
+    /// possible.
+    fn half_period(&self) -> u32 {
+        A::Frequency::frequency() / (2 * self.rate.get())
+    }
+
+    fn set_sck(&self, active: bool) {
+        if active == (self.polarity.get() == ClockPolarity::IdleLow) {
+            self.sck.set();
+        } else {
+            self.sck.clear();
+        }
+    }
+
+    fn set_mosi(&self, bit: bool) {
+        if bit {
+            self.mosi.set();
+        } else {
+            self.mosi.clear();
+        }
+    }
+
+    /// Set up the first bit, for devices sampling on the leading edge.
+    fn begin(&self, out: bool) {
+        if self.phase.get() == ClockPhase::SampleLeading {
+            self.set_mosi(out);
+        }
+    }
+
+    /// Clock edge `edge` of a transfer of `bits` bits, where `out` gives the
+    /// bits sent. Returns the bit read, if the edge samples one.
+    fn clock_edge(&self, edge: usize, bits: usize, out: impl Fn(usize) -> bool) -> Option<bool> {
+        let bit = edge / 2;
+        let sample_leading = self.phase.get() == ClockPhase::SampleLeading;
+        if edge % 2 == 0 {
+            self.set_sck(true);
+            if sample_leading {
+                return Some(self.miso.read());
+            }
+            self.set_mosi(out(bit));
+            None
+        } else {
+            self.set_sck(false);
+            if sample_leading {
+                if bit + 1 < bits {
+                    self.set_mosi(out(bit + 1));
+                }
+                return None;
+            }
+            Some(self.miso.read())
+        }
+    }
+
+    fn transfer_byte(&self, val: u8) -> Result<u8, ErrorCode> {
+        if self.busy.get() {
+            return Err(ErrorCode::BUSY);
+        }
+        let out = |bit: usize| val & (0x80 >> bit) != 0;
+        self.begin(out(0));
+        let mut byte = 0;
+        for edge in 0..16 {
+            if let Some(bit) = self.clock_edge(edge, 8, out) {
+                byte = byte << 1 | bit as u8;
+            }
+        }
+        Ok(byte)
+    }
+
+    /// Clock the next edge of the transfer, storing each byte read.
+    fn step(&self) {
+        let edge = self.edge.get();
+        let bits = self.len.get() * 8;
+        let sampled = self.write_buffer.map_or(None, |write| {
+            self.clock_edge(edge, bits, |bit| write[bit / 8] & (0x80 >> (bit % 8)) != 0)
+        });
+        if let Some(bit) = sampled {
+            let byte = self.byte.get() << 1 | bit as u8;
+            self.byte.set(byte);
+            let bit = edge / 2;
+            if bit % 8 == 7 {
+                self.read_buffer.map(|read| read[bit / 8] = byte);
+            }
+        }
+        self.edge.set(edge + 1);
+    }
+
+    fn schedule(&self) {
+        let ticks = cmp::max(self.half_period(), 1);
+        self.alarm
+            .set_alarm(self.alarm.now(), A::Ticks::from(ticks));
+    }
+}
+
+impl<'a, A: Alarm<'a>> AlarmClient for BitbangSpi<'a, A> {
+    fn alarm(&self) {
+        if !self.busy.get() {
+            return;
+        }
+        let edges = self.len.get() * 16;
+        if self.half_period() == 0 {
+            // A whole byte at a time.
+            self.step();
+            while self.edge.get() % 16 != 0 {
+                self.step();
+            }
+        } else {
+            self.step();
+        }
+
+        if self.edge.get() < edges {
+            self.schedule();
+            return;
+        }
+        if !self.hold_low.get() {
+            self.chip_select.map(|cs| cs.set());
+        }
+        self.busy.set(false);
+        if let Some(write) = self.write_buffer.take() {
+            let read = self.read_buffer.take();
+            let len = self.len.get();
+            self.client
+                .map(move |client| client.read_write_done(write, read, len, Ok(())));
+        }
+    }
+}
+
+impl<'a, A: Alarm<'a>> SpiMaster<'a> for BitbangSpi<'a, A> {
+    type ChipSelect = &'a dyn Pin;
+
+    fn init(&self) -> Result<(), ErrorCode> {
+        self.sck.make_output();
+        self.set_sck(false);
+        self.mosi.make_output();
+        self.mosi.clear();
+        self.miso.make_input();
+        Ok(())
+    }
+
+    fn set_client(&self, client: &'a dyn SpiMasterClient) {
+        self.client.set(client);
+    }
+
+    fn is_busy(&self) -> bool {
+        self.busy.get()
+    }
+
+    fn read_write_bytes(
+        &self,
+        write_buffer: &'static mut [u8],
+        read_buffer: Option<&'static mut [u8]>,
+        len: usize,
+    ) -> Result<(), (ErrorCode, &'static mut [u8], Option<&'static mut [u8]>)> {
+        if self.busy.get() {
+            return Err((ErrorCode::BUSY, write_buffer, read_buffer));
+        }
+        let len = read_buffer
+            .as_ref()
+            .map_or(write_buffer.len(), |read| {
+                cmp::min(read.len(), write_buffer.len())
+            })
+            .min(len);
+        if len == 0 {
+            return Err((ErrorCode::INVAL, write_buffer, read_buffer));
+        }
+        let Some(cs) = self.chip_select.get() else {
+            return Err((ErrorCode::NODEVICE, write_buffer, read_buffer));
+        };
+
+        cs.clear();
+        self.begin(write_buffer[0] & 0x80 != 0);
+        self.write_buffer.replace(write_buffer);
+        if let Some(read) = read_buffer {
+            self.read_buffer.replace(read);
+        }
+        self.len.set(len);
+        self.edge.set(0);
+        self.busy.set(true);
+        self.schedule();
+        Ok(())
+    }
+
+    fn write_byte(&self, val: u8) -> Result<(), ErrorCode> {
+        self.transfer_byte(val).map(|_| ())
+    }
+
+    fn read_byte(&self) -> Result<u8, ErrorCode> {
+        self.transfer_byte(0)
+    }
+
+    fn read_write_byte(&self, val: u8) -> Result<u8, ErrorCode> {
+        self.transfer_byte(val)
+    }
+
+    fn specify_chip_select(&self, cs: Self::ChipSelect) -> Result<(), ErrorCode> {
+        if self.busy.get() {
+            return Err(ErrorCode::BUSY);
+        }
+        cs.make_output();
+        cs.set();
+        self.chip_select.set(cs);
+        Ok(())
+    }
+
+    fn set_rate(&self, rate: u32) -> Result<u32, ErrorCode> {
+        if rate == 0 {
+            return Err(ErrorCode::INVAL);
+        }
+        if self.busy.get() {
+            return Err(ErrorCode::BUSY);
+        }
+        self.rate.set(rate);
+        Ok(self.get_rate())
+    }
+
+    fn get_rate(&self) -> u32 {
+        match self.half_period() {
+            0 => self.rate.get(),
+            ticks => A::Frequency::frequency() / (2 * ticks),
+        }
+    }
+
+    fn set_polarity(&self, polarity: ClockPolarity) -> Result<(), ErrorCode> {
+        if self.busy.get() {
+            return Err(ErrorCode::BUSY);
+        }
+        self.polarity.set(polarity);
+        self.set_sck(false);
+        Ok(())
+    }
+
+    fn get_polarity(&self) -> ClockPolarity {
+        self.polarity.get()
+    }
+
+    fn set_phase(&self, phase: ClockPhase) -> Result<(), ErrorCode> {
+        if self.busy.get() {
+            return Err(ErrorCode::BUSY);
+        }
+        self.phase.set(phase);
+        Ok(())
+    }
+
+    fn get_phase(&self) -> ClockPhase {
+        self.phase.get()
+    }
+
+    fn hold_low(&self) {
+        self.hold_low.set(true);
+    }
+
+    fn release_low(&self) {
+        self.hold_low.set(false);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::alarm_fake::FakeAlarm;
+    use crate::gpio_fake::{FakePin, PinListener};
+    use kernel::hil::gpio::Output;
+    use kernel::hil::time::Freq1MHz;
+    use kernel::utilities::cells::MapCell;
+    use std::boxed::Box;
+    use std::cell::RefCell;
+    use std::vec;
+    use std::vec::Vec;
+
+    /// A device that stores the bytes it is sent and replies with `data`,
+    /// in the mode set by `polarity` and `phase`.
+    struct FakeDevice {
+        sck: &'static FakePin,
+        mosi: &'static FakePin,
+        miso: &'static FakePin,
+        cs: &'static FakePin,
+        polarity: Cell<ClockPolarity>,
+        phase: Cell<ClockPhase>,
+        selected: Cell<bool>,
+        sck_level: Cell<bool>,
+        /// The number of bits received.
+        bit: Cell<usize>,
+        byte: Cell<u8>,
+        received: RefCell<Vec<u8>>,
+        data: RefCell<Vec<u8>>,
+    }
+
+    impl FakeDevice {
+        fn drive(&self) {
+            let bit = self.bit.get();
+            let byte = self.data.borrow().get(bit / 8).copied().unwrap_or(0xff);
+            self.miso.hold_low(byte & (0x80 >> (bit % 8)) == 0);
+        }
+
+        fn sample(&self) {
+            let byte = self.byte.get() << 1 | self.mosi.level() as u8;
+            self.byte.set(byte);
+            self.bit.set(self.bit.get() + 1);
+            if self.bit.get() % 8 == 0 {
+                self.received.borrow_mut().push(byte);
+            }
+        }
+    }
+
+    impl PinListener for FakeDevice {
+        fn changed(&self) {
+            let sck = self.sck.level();
+            let last_sck = self.sck_level.replace(sck);
+            let selected = !self.cs.level();
+            if !selected {
+                self.selected.set(false);
+                self.miso.hold_low(false);
+                return;
+            }
+            let sample_leading = self.phase.get() == ClockPhase::SampleLeading;
+            if !self.selected.replace(true) {
+                self.bit.set(0);
+                if sample_leading {
+                    self.drive();
+                }
+            } else if sck != last_sck {
+                let leading = sck == (self.polarity.get() == ClockPolarity::IdleLow);
+                if leading == sample_leading {
+                    self.sample();
+                } else {
+                    self.drive();
+                }
+            }
+        }
+    }
+
+    struct FakeClient {
+        result: MapCell<(&'static mut [u8], Option<&'static mut [u8]>, usize)>,
+    }
+
+    impl SpiMasterClient for FakeClient {
+        fn read_write_done(
+            &self,
+            write_buffer: &'static mut [u8],
+            read_buffer: Option<&'static mut [u8]>,
+            len: usize,
+            status: Result<(), ErrorCode>,
+        ) {
+            assert_eq!(status, Ok(()));
+            self.result.replace((write_buffer, read_buffer, len));
+        }
+    }
+
+    struct Bus {
+        spi: &'static BitbangSpi<'static, FakeAlarm<Freq1MHz>>,
+        alarm: &'static FakeAlarm<Freq1MHz>,
+        device: &'static FakeDevice,
+        client: &'static FakeClient,
+    }
+
+    fn setup() -> Bus {
+        let alarm = Box::leak(Box::new(FakeAlarm::new()));
+        let [sck, mosi, miso, cs] = [(); 4].map(|()| &*Box::leak(Box::new(FakePin::new())));
+        let spi = Box::leak(Box::new(BitbangSpi::new(alarm, sck, mosi, miso)));
+        let device = Box::leak(Box::new(FakeDevice {
+            sck,
+            mosi,
+            miso,
+            cs,
+            polarity: Cell::new(ClockPolarity::IdleLow),
+            phase: Cell::new(ClockPhase::SampleLeading),
+            selected: Cell::new(false),
+            sck_level: Cell::new(false),
+            bit: Cell::new(0),
+            byte: Cell::new(0),
+            received: RefCell::new(Vec::new()),
+            data: RefCell::new(Vec::new()),
+        }));
+        let client = Box::leak(Box::new(FakeClient {
+            result: MapCell::empty(),
+        }));
+        alarm.set_alarm_client(spi);
+        sck.set_listener(device);
+        cs.set_listener(device);
+        spi.set_client(client);
+        assert_eq!(spi.init(), Ok(()));
+        assert_eq!(spi.specify_chip_select(cs), Ok(()));
+        Bus {
+            spi,
+            alarm,
+            device,
+            client,
+        }
+    }
+
+    #[test]
+    fn transfer_in_each_mode() {
+        for polarity in [ClockPolarity::IdleLow, ClockPolarity::IdleHigh] {
+            for phase in [ClockPhase::SampleLeading, ClockPhase::SampleTrailing] {
+                let bus = setup();
+                assert_eq!(bus.spi.set_polarity(polarity), Ok(()));
+                assert_eq!(bus.spi.set_phase(phase), Ok(()));
+                bus.device.polarity.set(polarity);
+                bus.device.phase.set(phase);
+                *bus.device.data.borrow_mut() = vec![0x5a, 0xc3, 0x01];
+
+                let write = Box::leak(Box::new([0xa5, 0x3c, 0x80]));
+                let read = Box::leak(Box::new([0; 3]));
+                assert!(bus.spi.read_write_bytes(write, Some(read), 3).is_ok());
+                assert!(bus.spi.is_busy());
+                // One alarm for each half period of the clock.
+                assert_eq!(bus.alarm.fire_all(), 48);
+                let (_write, read, len) = bus.client.result.take().unwrap();
+                assert_eq!(len, 3);
+                assert_eq!(read.unwrap(), &[0x5a, 0xc3, 0x01]);
+                assert_eq!(*bus.device.received.borrow(), [0xa5, 0x3c, 0x80]);
+                // The chip select is released, and the clock left idle.
+                assert!(bus.device.cs.level());
+                assert_eq!(bus.device.sck.level(), polarity == ClockPolarity::IdleHigh);
+                assert!(!bus.spi.is_busy());
+            }
+        }
+    }
+
+    #[test]
+    fn byte_at_a_time() {
+        let bus = setup();
+        // At the frequency of the alarm a byte is clocked on each alarm.
+        assert_eq!(bus.spi.set_rate(1_000_000), Ok(1_000_000));
+        *bus.device.data.borrow_mut() = vec![0x12, 0x34];
+        let write = Box::leak(Box::new([0xfe, 0xdc]));
+        assert!(bus.spi.read_write_bytes(write, None, 2).is_ok());
+        assert_eq!(bus.alarm.fire_all(), 2);
+        let (write, read, len) = bus.client.result.take().unwrap();
+        assert_eq!((write[0], read.is_none(), len), (0xfe, true, 2));
+        assert_eq!(*bus.device.received.borrow(), [0xfe, 0xdc]);
+    }
+
+    #[test]
+    fn single_bytes() {
+        let bus = setup();
+        *bus.device.data.borrow_mut() = vec![0x96, 0x0f];
+        bus.device.cs.clear();
+        assert_eq!(bus.spi.read_write_byte(0x42), Ok(0x96));
+        assert_eq!(bus.spi.read_byte(), Ok(0x0f));
+        assert_eq!(bus.spi.write_byte(0x24), Ok(()));
+        assert_eq!(*bus.device.received.borrow(), [0x42, 0x00, 0x24]);
+    }
+
+    #[test]
+    fn hold_chip_select_low() {
+        let bus = setup();
+        bus.spi.hold_low();
+        let write = Box::leak(Box::new([0x01]));
+        assert!(bus.spi.read_write_bytes(write, None, 1).is_ok());
+        bus.alarm.fire_all();
+        assert!(!bus.device.cs.level());
+        bus.spi.release_low();
+        let (write, _, _) = bus.client.result.take().unwrap();
+        assert!(bus.spi.read_write_bytes(write, None, 1).is_ok());
+        bus.alarm.fire_all();
+        assert!(bus.device.cs.level());
+    }
+
+    #[test]
+    fn errors() {
+        let bus = setup();
+        assert_eq!(bus.spi.set_rate(0), Err(ErrorCode::INVAL));
+        // The rate is rounded to a whole number of ticks per half period.
+        assert_eq!(bus.spi.set_rate(300_000), Ok(500_000));
+
+        let write = Box::leak(Box::new([0x01, 0x02]));
+        let (error, write, _) = bus.spi.read_write_bytes(write, None, 0).unwrap_err();
+        assert_eq!(error, ErrorCode::INVAL);
+        assert!(bus.spi.read_write_bytes(write, None, 2).is_ok());
+        assert_eq!(bus.spi.read_byte(), Err(ErrorCode::BUSY));
+        assert_eq!(bus.spi.set_rate(1000), Err(ErrorCode::BUSY));
+        let other = Box::leak(Box::new([0x03]));
+        let (error, other, _) = bus.spi.read_write_bytes(other, None, 1).unwrap_err();
+        assert_eq!(error, ErrorCode::BUSY);
+        bus.alarm.fire_all();
+
+        let alarm = Box::leak(Box::new(FakeAlarm::<Freq1MHz>::new()));
+        let pin = Box::leak(Box::new(FakePin::new()));
+        let spi = BitbangSpi::new(alarm, pin, pin, pin);
+        let (error, _, _) = spi.read_write_bytes(other, None, 1).unwrap_err();
+        assert_eq!(error, ErrorCode::NODEVICE);
+    }
+}
@@ -0,0 +1,543 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Driver for DS18B20 1-Wire temperature probes.
+//!
+//! Implements `hil::sensors::TemperatureDriver` for any number of probes on
+//! one 1-Wire bus. The first reading searches the bus for probes, keeping the
+//! ROM codes of as many as fit in the buffer given, and later readings
+//! measure the selected probe, the first one by default. `rescan` makes the
+//! next reading search the bus again, after probes were added or removed.
+//!
+//! Probes must be externally powered, not parasite powered, and are read at
+//! their default 12-bit resolution, which takes 750 ms per reading.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! let ds18b20 = components::ds18b20::Ds18b20Component::new(mux_alarm, onewire)
+//!     .finalize(components::ds18b20_component_static!(
+//!         earlgrey::timer::RvTimer<'static>,
+//!         4
+//!     ));
+//! ds18b20.select_probe(1);
+//! let temperature = components::temperature::TemperatureComponent::new(
+//!     board_kernel,
+//!     capsules_extra::temperature::DRIVER_NUM,
+//!     ds18b20,
+//! )
+//! .finalize(components::temperature_component_static!(
+//!     components::ds18b20::Ds18b20ComponentType<earlgrey::timer::RvTimer<'static>>
+//! ));
+//! ```
+
+use core::cell::Cell;
+
+use kernel::hil::onewire::{self, OneWire, OneWireClient};
+use kernel::hil::sensors::{TemperatureClient, TemperatureDriver};
+use kernel::hil::time::{Alarm, AlarmClient, ConvertTicks};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::ErrorCode;
+
+/// The family code in the ROM codes of DS18B20s.
+pub const FAMILY_CODE: u8 = 0x28;
+
+const CONVERT_T: u8 = 0x44;
+const READ_SCRATCHPAD: u8 = 0xBE;
+
+/// How long a conversion at 12-bit resolution takes.
+const CONVERSION_MS: u32 = 750;
+
+/// A ROM command selecting a probe, then a function command.
+const COMMAND_LEN: usize = 10;
+const SCRATCHPAD_LEN: usize = 9;
+
+/// The temperature in a scratchpad in hundredths of a degree, or `FAIL` if
+/// its CRC is wrong.
+fn temperature(scratchpad: &[u8; SCRATCHPAD_LEN]) -> Result<i32, ErrorCode> {
+    if onewire::crc8(scratchpad) != 0 {
+        return Err(ErrorCode::FAIL);
+    }
+    // The temperature is in sixteenths of a degree.
+    let raw = i16::from_le_bytes([scratchpad[0], scratchpad[1]]) as i32;
+    Ok(raw * 100 / 16)
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum State {
+    Idle,
+    Search,
+    ConvertReset,
+    ConvertCommand,
+    Converting,
+    ReadReset,
+    ReadCommand,
+    ReadScratchpad,
+}
+
+pub struct Ds18b20<'a, A: Alarm<'a>> {
+    onewire: &'a dyn OneWire<'a>,
+    alarm: &'a A,
+    client: OptionalCell<&'a dyn TemperatureClient>,
+    /// The ROM codes of the probes found.
+    roms: TakeCell<'static, [u64]>,
+    probes: Cell<usize>,
+    searched: Cell<bool>,
+    selected: Cell<usize>,
+    state: Cell<State>,
+    command: Cell<[u8; COMMAND_LEN]>,
+    scratchpad: Cell<[u8; SCRATCHPAD_LEN]>,
+    /// The next byte of the command or scratchpad.
+    index: Cell<usize>,
+}
+
+impl<'a, A: Alarm<'a>> Ds18b20<'a, A> {
+    /// Create the driver, keeping the ROM codes of up to `roms.len()` probes.
+    pub fn new(onewire: &'a dyn OneWire<'a>, alarm: &'a A, roms: &'static mut [u64]) -> Self {
+        Ds18b20 {
+            onewire,
+            alarm,
+            client: OptionalCell::empty(),
+            roms: TakeCell::new(roms),
+            probes: Cell::new(0),
+            searched: Cell::new(false),
+            selected: Cell::new(0),
+            state: Cell::new(State::Idle),
+            command: Cell::new([0; COMMAND_LEN]),
+            scratchpad: Cell::new([0; SCRATCHPAD_LEN]),
+            index: Cell::new(0),
+        }
+    }
+
+    /// The number of probes found by the last search.
+    pub fn probes(&self) -> usize {
+        self.probes.get()
+    }
+
+    /// The ROM code of probe `index`, once the bus was searched.
+    pub fn rom(&self, index: usize) -> Option<u64> {
+        if index < self.probes.get() {
+            self.roms.map_or(None, |roms| Some(roms[index]))
+        } else {
+            None
+        }
+    }
+
+    /// Make the following readings measure probe `index`, in the order the
+    /// search found them. Readings fail with `NODEVICE` if there are not
+    /// that many probes.
+    pub fn select_probe(&self, index: usize) {
+        self.selected.set(index);
+    }
+
+    /// Search the bus for probes again at the next reading.
+    pub fn rescan(&self) {
+        self.searched.set(false);
+    }
+
+    fn check(&self, r: Result<(), ErrorCode>) {
+        if let Err(e) = r {
+            self.finish(Err(e));
+        }
+    }
+
+    fn finish(&self, value: Result<i32, ErrorCode>) {
+        self.state.set(State::Idle);
+        self.client.map(|client| client.callback(value));
+    }
+
+    fn convert(&self) {
+        if self.selected.get() >= self.probes.get() {
+            self.finish(Err(ErrorCode::NODEVICE));
+            return;
+        }
+        self.state.set(State::ConvertReset);
+        self.check(self.onewire.reset());
+    }
+
+    /// Write a function command to the selected probe.
+    fn send_command(&self, function: u8) {
+        let rom = self.rom(self.selected.get()).unwrap_or(0);
+        let mut command = [0; COMMAND_LEN];
+        command[0] = onewire::MATCH_ROM;
+        command[1..9].copy_from_slice(&rom.to_le_bytes());
+        command[9] = function;
+        self.command.set(command);
+        self.index.set(0);
+        self.check(self.onewire.write_byte(command[0]));
+    }
+}
+
+impl<'a, A: Alarm<'a>> TemperatureDriver<'a> for Ds18b20<'a, A> {
+    fn set_client(&self, client: &'a dyn TemperatureClient) {
+        self.client.set(client);
+    }
+
+    fn read_temperature(&self) -> Result<(), ErrorCode> {
+        if self.state.get() != State::Idle {
+            return Err(ErrorCode::BUSY);
+        }
+        if self.searched.get() {
+            if self.selected.get() >= self.probes.get() {
+                return Err(ErrorCode::NODEVICE);
+            }
+            self.state.set(State::ConvertReset);
+            self.onewire
+                .reset()
+                .inspect_err(|_| self.state.set(State::Idle))
+        } else {
+            self.probes.set(0);
+            self.state.set(State::Search);
+            self.onewire
+                .search_rom(true)
+                .inspect_err(|_| self.state.set(State::Idle))
+        }
+    }
+}
+
+impl<'a, A: Alarm<'a>> OneWireClient for Ds18b20<'a, A> {
+    fn reset_done(&self, presence: bool) {
+        if !presence {
+            self.finish(Err(ErrorCode::NODEVICE));
+            return;
+        }
+        match self.state.get() {
+            State::ConvertReset => {
+                self.state.set(State::ConvertCommand);
+                self.send_command(CONVERT_T);
+            }
+            State::ReadReset => {
+                self.state.set(State::ReadCommand);
+                self.send_command(READ_SCRATCHPAD);
+            }
+            _ => {}
+        }
+    }
+
+    fn write_done(&self) {
+        let index = self.index.get() + 1;
+        self.index.set(index);
+        if index < COMMAND_LEN {
+            self.check(self.onewire.write_byte(self.command.get()[index]));
+            return;
+        }
+        match self.state.get() {
+            State::ConvertCommand => {
+                self.state.set(State::Converting);
+                self.alarm
+                    .set_alarm(self.alarm.now(), self.alarm.ticks_from_ms(CONVERSION_MS));
+            }
+            State::ReadCommand => {
+                self.state.set(State::ReadScratchpad);
+                self.index.set(0);
+                self.check(self.onewire.read_byte());
+            }
+            _ => {}
+        }
+    }
+
+    fn read_done(&self, value: u8) {
+        let mut scratchpad = self.scratchpad.get();
+        let index = self.index.get();
+        scratchpad[index] = value;
+        self.scratchpad.set(scratchpad);
+        self.index.set(index + 1);
+        if index + 1 < SCRATCHPAD_LEN {
+            self.check(self.onewire.read_byte());
+        } else {
+            self.finish(temperature(&scratchpad));
+        }
+    }
+
+    fn search_done(&self, rom: Result<Option<u64>, ErrorCode>) {
+        match rom {
+            Ok(Some(rom)) => {
+                if rom as u8 == FAMILY_CODE {
+                    let probes = self.probes.get();
+                    self.roms.map(|roms| {
+                        if let Some(slot) = roms.get_mut(probes) {
+                            *slot = rom;
+                            self.probes.set(probes + 1);
+                        }
+                    });
+                }
+                self.check(self.onewire.search_rom(false));
+            }
+            Ok(None) => {
+                self.searched.set(true);
+                self.convert();
+            }
+            Err(e) => self.finish(Err(e)),
+        }
+    }
+}
+
+impl<'a, A: Alarm<'a>> AlarmClient for Ds18b20<'a, A> {
+    fn alarm(&self) {
+        if self.state.get() == State::Converting {
+            self.state.set(State::ReadReset);
+            self.check(self.onewire.reset());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::alarm_fake::FakeAlarm;
+    use kernel::hil::time::Freq1KHz;
+    use std::boxed::Box;
+    use std::cell::RefCell;
+    use std::vec;
+    use std::vec::Vec;
+
+    /// A scratchpad holding the raw temperature `raw`, with its CRC.
+    fn scratchpad(raw: i16) -> [u8; SCRATCHPAD_LEN] {
+        let [lsb, msb] = raw.to_le_bytes();
+        let mut scratchpad = [lsb, msb, 0x4B, 0x46, 0x7F, 0xFF, 0x0C, 0x10, 0];
+        scratchpad[8] = onewire::crc8(&scratchpad[..8]);
+        scratchpad
+    }
+
+    #[test]
+    fn temperature_conversion() {
+        // The table of temperatures and readings of the DS18B20 datasheet.
+        for (raw, expected) in [
+            (0x07D0, 12500),
+            (0x0550, 8500),
+            (0x0191, 2506),
+            (0x00A2, 1012),
+            (0x0008, 50),
+            (0x0000, 0),
+            (0xFFF8u16 as i16, -50),
+            (0xFF5Eu16 as i16, -1012),
+            (0xFE6Fu16 as i16, -2506),
+            (0xFC90u16 as i16, -5500),
+        ] {
+            assert_eq!(temperature(&scratchpad(raw)), Ok(expected));
+        }
+        // The scratchpad at power up, with its CRC.
+        let power_up = [0x50, 0x05, 0x4B, 0x46, 0x7F, 0xFF, 0x0C, 0x10, 0x1C];
+        assert_eq!(temperature(&power_up), Ok(8500));
+    }
+
+    #[test]
+    fn bad_crc() {
+        let mut corrupted = scratchpad(0x0191);
+        corrupted[0] ^= 0x01;
+        assert_eq!(temperature(&corrupted), Err(ErrorCode::FAIL));
+    }
+
+    #[derive(Clone, Copy, PartialEq, Debug)]
+    enum Op {
+        Reset,
+        Write(u8),
+        Read,
+        Search(bool),
+    }
+
+    /// A bus that finds `roms` and returns `reads` to reads, and issues the
+    /// callback of an operation when `complete()` is called.
+    struct FakeOneWire {
+        client: OptionalCell<&'static dyn OneWireClient>,
+        ops: RefCell<Vec<Op>>,
+        pending: Cell<Option<Op>>,
+        presence: Cell<bool>,
+        roms: RefCell<Vec<u64>>,
+        found: Cell<usize>,
+        reads: RefCell<Vec<u8>>,
+    }
+
+    impl FakeOneWire {
+        fn start(&self, op: Op) -> Result<(), ErrorCode> {
+            if self.pending.get().is_some() {
+                return Err(ErrorCode::BUSY);
+            }
+            self.ops.borrow_mut().push(op);
+            self.pending.set(Some(op));
+            Ok(())
+        }
+
+        fn complete_all(&self) {
+            while let Some(op) = self.pending.take() {
+                self.client.map(|client| match op {
+                    Op::Reset => client.reset_done(self.presence.get()),
+                    Op::Write(_) => client.write_done(),
+                    Op::Read => client.read_done(self.reads.borrow_mut().remove(0)),
+                    Op::Search(first) => {
+                        let found = if first { 0 } else { self.found.get() };
+                        let rom = self.roms.borrow().get(found).copied();
+                        self.found.set(found + 1);
+                        client.search_done(Ok(rom));
+                    }
+                });
+            }
+        }
+    }
+
+    impl OneWire<'static> for FakeOneWire {
+        fn set_client(&self, client: &'static dyn OneWireClient) {
+            self.client.set(client);
+        }
+
+        fn reset(&self) -> Result<(), ErrorCode> {
+            self.start(Op::Reset)
+        }
+
+        fn write_bit(&self, _bit: bool) -> Result<(), ErrorCode> {
+            Err(ErrorCode::NOSUPPORT)
+        }
+
+        fn read_bit(&self) -> Result<(), ErrorCode> {
+            Err(ErrorCode::NOSUPPORT)
+        }
+
+        fn write_byte(&self, byte: u8) -> Result<(), ErrorCode> {
+            self.start(Op::Write(byte))
+        }
+
+        fn read_byte(&self) -> Result<(), ErrorCode> {
+            self.start(Op::Read)
+        }
+
+        fn search_rom(&self, first: bool) -> Result<(), ErrorCode> {
+            self.start(Op::Search(first))
+        }
+    }
+
+    struct FakeClient {
+        value: Cell<Option<Result<i32, ErrorCode>>>,
+    }
+
+    impl TemperatureClient for FakeClient {
+        fn callback(&self, value: Result<i32, ErrorCode>) {
+            self.value.set(Some(value));
+        }
+    }
+
+    struct Setup {
+        ds18b20: &'static Ds18b20<'static, FakeAlarm<Freq1KHz>>,
+        onewire: &'static FakeOneWire,
+        alarm: &'static FakeAlarm<Freq1KHz>,
+        client: &'static FakeClient,
+    }
+
+    fn setup(roms: Vec<u64>) -> Setup {
+        let onewire = Box::leak(Box::new(FakeOneWire {
+            client: OptionalCell::empty(),
+            ops: RefCell::new(Vec::new()),
+            pending: Cell::new(None),
+            presence: Cell::new(true),
+            roms: RefCell::new(roms),
+            found: Cell::new(0),
+            reads: RefCell::new(Vec::new()),
+        }));
+        let alarm = Box::leak(Box::new(FakeAlarm::new()));
+        let ds18b20 = Box::leak(Box::new(Ds18b20::new(
+            onewire,
+            alarm,
+            Box::leak(Box::new([0; 2])),
+        )));
+        let client = Box::leak(Box::new(FakeClient {
+            value: Cell::new(None),
+        }));
+        onewire.set_client(ds18b20);
+        alarm.set_alarm_client(ds18b20);
+        ds18b20.set_client(client);
+        Setup {
+            ds18b20,
+            onewire,
+            alarm,
+            client,
+        }
+    }
+
+    /// The operations selecting the probe with ROM code `rom` and sending it
+    /// `function`.
+    fn command(rom: u64, function: u8) -> Vec<Op> {
+        let mut ops = vec![Op::Reset, Op::Write(onewire::MATCH_ROM)];
+        ops.extend(rom.to_le_bytes().map(Op::Write));
+        ops.push(Op::Write(function));
+        ops
+    }
+
+    impl Setup {
+        /// Run a reading that finds `scratchpad`, and return the operations
+        /// on the bus.
+        fn read(&self, scratchpad: [u8; SCRATCHPAD_LEN]) -> Vec<Op> {
+            *self.onewire.reads.borrow_mut() = scratchpad.into();
+            assert_eq!(self.ds18b20.read_temperature(), Ok(()));
+            assert_eq!(self.ds18b20.read_temperature(), Err(ErrorCode::BUSY));
+            self.onewire.complete_all();
+            // The conversion takes 750 ms.
+            let start = self.alarm.ticks();
+            assert!(self.alarm.fire());
+            assert_eq!(self.alarm.ticks() - start, CONVERSION_MS);
+            self.onewire.complete_all();
+            self.onewire.ops.take()
+        }
+    }
+
+    // ROM codes with their CRCs.
+    const PROBE_1: u64 = 0xD300_0000_0001_0028;
+    const PROBE_2: u64 = 0x9D00_0000_0002_0028;
+    const OTHER: u64 = 0xB500_0000_0003_0010;
+
+    #[test]
+    fn search_and_read() {
+        let setup = setup(vec![PROBE_1, OTHER, PROBE_2]);
+        let ops = setup.read(scratchpad(0x0191));
+        assert_eq!(setup.client.value.take(), Some(Ok(2506)));
+        // Devices of other families are skipped.
+        assert_eq!(setup.ds18b20.probes(), 2);
+        assert_eq!(setup.ds18b20.rom(0), Some(PROBE_1));
+        assert_eq!(setup.ds18b20.rom(1), Some(PROBE_2));
+        assert_eq!(setup.ds18b20.rom(2), None);
+
+        // A search step for each device, and one finding no more.
+        let mut expected = vec![Op::Search(true)];
+        expected.extend([Op::Search(false); 3]);
+        expected.extend(command(PROBE_1, CONVERT_T));
+        expected.extend(command(PROBE_1, READ_SCRATCHPAD));
+        expected.extend([Op::Read; SCRATCHPAD_LEN]);
+        assert_eq!(ops, expected);
+
+        // Later readings don't search again.
+        setup.ds18b20.select_probe(1);
+        let ops = setup.read(scratchpad(0xFC90u16 as i16));
+        assert_eq!(setup.client.value.take(), Some(Ok(-5500)));
+        assert_eq!(ops[..11], command(PROBE_2, CONVERT_T));
+
+        setup.ds18b20.select_probe(2);
+        assert_eq!(setup.ds18b20.read_temperature(), Err(ErrorCode::NODEVICE));
+    }
+
+    #[test]
+    fn read_errors() {
+        let setup = setup(vec![PROBE_1]);
+        let mut corrupted = scratchpad(0x0550);
+        corrupted[1] ^= 0x80;
+        setup.read(corrupted);
+        assert_eq!(setup.client.value.take(), Some(Err(ErrorCode::FAIL)));
+
+        // The probe stops answering.
+        setup.onewire.presence.set(false);
+        assert_eq!(setup.ds18b20.read_temperature(), Ok(()));
+        setup.onewire.complete_all();
+        assert_eq!(setup.client.value.take(), Some(Err(ErrorCode::NODEVICE)));
+        assert!(!setup.alarm.is_armed());
+    }
+
+    #[test]
+    fn no_probes() {
+        let setup = setup(vec![OTHER]);
+        assert_eq!(setup.ds18b20.read_temperature(), Ok(()));
+        setup.onewire.complete_all();
+        assert_eq!(setup.client.value.take(), Some(Err(ErrorCode::NODEVICE)));
+        assert_eq!(setup.ds18b20.probes(), 0);
+    }
+}
@@ -0,0 +1,327 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Elliptic-curve Diffie-Hellman key agreement for userspace.
+//!
+//! An app generates a key pair and receives the public key, which it sends to
+//! its peer. It then passes the peer's public key to the driver and receives
+//! the shared secret. The private key never leaves the kernel.
+//!
+//! The underlying `Ecdh` implementation holds a single key pair, which belongs
+//! to the app that generated it until that app releases it or exits. Other
+//! apps can't generate a key pair in the meantime.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! let x25519 = components::ecdh::X25519SoftwareComponent::new()
+//!     .finalize(components::x25519_software_component_static!());
+//!
+//! let ecdh = components::ecdh::EcdhDriverComponent::new(
+//!     board_kernel,
+//!     capsules_extra::ecdh::DRIVER_NUM,
+//!     x25519,
+//!     &peripherals.trng,
+//! )
+//! .finalize(components::ecdh_driver_component_static!(
+//!     capsules_extra::public_key_crypto::x25519::X25519Software<'static>,
+//!     32,
+//!     32
+//! ));
+//! ```
+
+use capsules_core::driver;
+/// Syscall driver number.
+pub const DRIVER_NUM: usize = driver::NUM::Ecdh as usize;
+
+use kernel::errorcode::into_statuscode;
+use kernel::grant::{AllowRoCount, AllowRwCount, Grant, UpcallCount};
+use kernel::hil::entropy::Entropy32;
+use kernel::hil::public_key_crypto::ecdh::{Ecdh, EcdhClient};
+use kernel::processbuffer::{ReadableProcessBuffer, WriteableProcessBuffer};
+use kernel::syscall::{CommandReturn, SyscallDriver};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::{ErrorCode, ProcessId};
+
+/// Ids for read-only allow buffers
+mod ro_allow {
+    pub const PEER_PUBLIC_KEY: usize = 0;
+    /// The number of allow buffers the kernel stores for this grant
+    pub const COUNT: u8 = 1;
+}
+
+/// Ids for read-write allow buffers
+mod rw_allow {
+    pub const PUBLIC_KEY: usize = 0;
+    pub const SHARED_SECRET: usize = 1;
+    /// The number of allow buffers the kernel stores for this grant
+    pub const COUNT: u8 = 2;
+}
+
+mod upcall {
+    pub const GENERATION_DONE: usize = 0;
+    pub const SHARED_SECRET_DONE: usize = 1;
+    /// The number of upcalls the kernel stores for this grant
+    pub const COUNT: u8 = 2;
+}
+
+#[derive(Default)]
+pub struct App {}
+
+pub struct EcdhDriver<'a, E: Ecdh<'a, PKL, SSL>, const PKL: usize, const SSL: usize> {
+    ecdh: &'a E,
+    trng: &'a dyn Entropy32<'a>,
+
+    apps: Grant<
+        App,
+        UpcallCount<{ upcall::COUNT }>,
+        AllowRoCount<{ ro_allow::COUNT }>,
+        AllowRwCount<{ rw_allow::COUNT }>,
+    >,
+    /// The app the key pair belongs to.
+    owner: OptionalCell<ProcessId>,
+    /// The app with an operation in progress.
+    processid: OptionalCell<ProcessId>,
+
+    public_key: TakeCell<'static, [u8; PKL]>,
+    peer_public_key: TakeCell<'static, [u8; PKL]>,
+    shared_secret: TakeCell<'static, [u8; SSL]>,
+}
+
+impl<'a, E: Ecdh<'a, PKL, SSL>, const PKL: usize, const SSL: usize> EcdhDriver<'a, E, PKL, SSL> {
+    pub fn new(
+        ecdh: &'a E,
+        trng: &'a dyn Entropy32<'a>,
+        public_key: &'static mut [u8; PKL],
+        peer_public_key: &'static mut [u8; PKL],
+        shared_secret: &'static mut [u8; SSL],
+        grant: Grant<
+            App,
+            UpcallCount<{ upcall::COUNT }>,
+            AllowRoCount<{ ro_allow::COUNT }>,
+            AllowRwCount<{ rw_allow::COUNT }>,
+        >,
+    ) -> Self {
+        EcdhDriver {
+            ecdh,
+            trng,
+            apps: grant,
+            owner: OptionalCell::empty(),
+            processid: OptionalCell::empty(),
+            public_key: TakeCell::new(public_key),
+            peer_public_key: TakeCell::new(peer_public_key),
+            shared_secret: TakeCell::new(shared_secret),
+        }
+    }
+
+    /// Check if the key pair belongs to an app, other than `processid`, that
+    /// still exists.
+    fn owned_by_other(&self, processid: ProcessId) -> bool {
+        self.owner.map_or(false, |owner| {
+            owner != processid && self.apps.enter(owner, |_, _| ()).is_ok()
+        })
+    }
+
+    fn generate(&self, processid: ProcessId) -> Result<(), ErrorCode> {
+        if self.processid.is_some() || self.owned_by_other(processid) {
+            return Err(ErrorCode::BUSY);
+        }
+
+        let public_key = self.public_key.take().ok_or(ErrorCode::BUSY)?;
+
+        // Any previous key pair is replaced, even if generation fails.
+        self.owner.clear();
+
+        match self.ecdh.generate(self.trng, public_key) {
+            Ok(()) => {
+                self.processid.set(processid);
+                Ok(())
+            }
+            Err((e, public_key)) => {
+                self.public_key.replace(public_key);
+                Err(e)
+            }
+        }
+    }
+
+    fn compute_shared_secret(&self, processid: ProcessId) -> Result<(), ErrorCode> {
+        if self.processid.is_some() {
+            return Err(ErrorCode::BUSY);
+        }
+        if self.owner.get() != Some(processid) {
+            return Err(ErrorCode::RESERVE);
+        }
+
+        let peer_public_key = self.peer_public_key.take().ok_or(ErrorCode::BUSY)?;
+
+        let copied = self
+            .apps
+            .enter(processid, |_, kernel_data| {
+                kernel_data
+                    .get_readonly_processbuffer(ro_allow::PEER_PUBLIC_KEY)
+                    .and_then(|key| {
+                        key.enter(|key| {
+                            if key.len() != PKL {
+                                return Err(ErrorCode::SIZE);
+                            }
+                            key.copy_to_slice(peer_public_key);
+                            Ok(())
+                        })
+                    })
+                    .unwrap_or(Err(ErrorCode::RESERVE))
+            })
+            .unwrap_or_else(|err| Err(err.into()));
+
+        if let Err(e) = copied {
+            self.peer_public_key.replace(peer_public_key);
+            return Err(e);
+        }
+
+        let shared_secret = match self.shared_secret.take() {
+            Some(shared_secret) => shared_secret,
+            None => {
+                self.peer_public_key.replace(peer_public_key);
+                return Err(ErrorCode::BUSY);
+            }
+        };
+
+        match self.ecdh.shared_secret(peer_public_key, shared_secret) {
+            Ok(()) => {
+                self.processid.set(processid);
+                Ok(())
+            }
+            Err((e, peer_public_key, shared_secret)) => {
+                self.peer_public_key.replace(peer_public_key);
+                self.shared_secret.replace(shared_secret);
+                Err(e)
+            }
+        }
+    }
+}
+
+impl<'a, E: Ecdh<'a, PKL, SSL>, const PKL: usize, const SSL: usize> EcdhClient<PKL, SSL>
+    for EcdhDriver<'a, E, PKL, SSL>
+{
+    fn generation_done(&self, result: Result<(), ErrorCode>, public_key: &'static mut [u8; PKL]) {
+        if let Some(processid) = self.processid.take() {
+            let _ = self.apps.enter(processid, |_, kernel_data| {
+                let result = result.and_then(|()| {
+                    kernel_data
+                        .get_readwrite_processbuffer(rw_allow::PUBLIC_KEY)
+                        .and_then(|dest| {
+                            dest.mut_enter(|dest| {
+                                if dest.len() < PKL {
+                                    return Err(ErrorCode::SIZE);
+                                }
+                                dest[..PKL].copy_from_slice(public_key);
+                                Ok(())
+                            })
+                        })
+                        .unwrap_or(Err(ErrorCode::RESERVE))
+                });
+
+                if result.is_ok() {
+                    self.owner.set(processid);
+                }
+
+                kernel_data
+                    .schedule_upcall(upcall::GENERATION_DONE, (into_statuscode(result), PKL, 0))
+                    .ok();
+            });
+        }
+
+        self.public_key.replace(public_key);
+    }
+
+    fn shared_secret_done(
+        &self,
+        result: Result<(), ErrorCode>,
+        peer_public_key: &'static mut [u8; PKL],
+        shared_secret: &'static mut [u8; SSL],
+    ) {
+        if let Some(processid) = self.processid.take() {
+            let _ = self.apps.enter(processid, |_, kernel_data| {
+                let result = result.and_then(|()| {
+                    kernel_data
+                        .get_readwrite_processbuffer(rw_allow::SHARED_SECRET)
+                        .and_then(|dest| {
+                            dest.mut_enter(|dest| {
+                                if dest.len() < SSL {
+                                    return Err(ErrorCode::SIZE);
+                                }
+                                dest[..SSL].copy_from_slice(shared_secret);
+                                Ok(())
+                            })
+                        })
+                        .unwrap_or(Err(ErrorCode::RESERVE))
+                });
+
+                kernel_data
+                    .schedule_upcall(
+                        upcall::SHARED_SECRET_DONE,
+                        (into_statuscode(result), SSL, 0),
+                    )
+                    .ok();
+            });
+        }
+
+        // Don't leave the secret lying around in the kernel.
+        shared_secret.fill(0);
+        self.peer_public_key.replace(peer_public_key);
+        self.shared_secret.replace(shared_secret);
+    }
+}
+
+impl<'a, E: Ecdh<'a, PKL, SSL>, const PKL: usize, const SSL: usize> SyscallDriver
+    for EcdhDriver<'a, E, PKL, SSL>
+{
+    /// Control the ECDH driver.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver existence check.
+    /// - `1`: Generate a new key pair. The public key is written to the
+    ///        `PUBLIC_KEY` read-write allow buffer.
+    /// - `2`: Compute the shared secret with the public key in the
+    ///        `PEER_PUBLIC_KEY` read-only allow buffer. The shared secret is
+    ///        written to the `SHARED_SECRET` read-write allow buffer.
+    /// - `3`: Release the key pair, erasing the private key.
+    /// - `4`: Return the length of a public key and of the shared secret.
+    fn command(
+        &self,
+        command_num: usize,
+        _data1: usize,
+        _data2: usize,
+        processid: ProcessId,
+    ) -> CommandReturn {
+        match command_num {
+            0 => CommandReturn::success(),
+
+            1 => self.generate(processid).into(),
+
+            2 => self.compute_shared_secret(processid).into(),
+
+            3 => {
+                if self.processid.is_some() {
+                    CommandReturn::failure(ErrorCode::BUSY)
+                } else if self.owner.get() == Some(processid) {
+                    self.ecdh.clear_key();
+                    self.owner.clear();
+                    CommandReturn::success()
+                } else {
+                    CommandReturn::failure(ErrorCode::RESERVE)
+                }
+            }
+
+            4 => CommandReturn::success_u32_u32(PKL as u32, SSL as u32),
+
+            _ => CommandReturn::failure(ErrorCode::NOSUPPORT),
+        }
+    }
+
+    fn allocate_grant(&self, processid: ProcessId) -> Result<(), kernel::process::Error> {
+        self.apps.enter(processid, |_, _| {})
+    }
+}
@@ -0,0 +1,2566 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! A CTAP2.0 authenticator.
+//!
+//! Implements these commands:
+//!
+//! - `authenticatorMakeCredential`, with ES256 (P-256) credentials and
+//!   "packed" self attestation.
+//! - `authenticatorGetAssertion` and `authenticatorGetNextAssertion`, for
+//!   credentials from the allow list or for discoverable (resident)
+//!   credentials of the relying party.
+//! - `authenticatorGetInfo`.
+//! - `authenticatorClientPIN` with PIN protocol one.
+//! - `authenticatorReset`.
+//!
+//! Each credential is kept in the KV store under `fido-c` followed by its 16
+//! byte random credential ID. The stored object holds the credential's
+//! private key, so the KV store should be encrypted (see
+//! `kv_store_encryption`) on boards where the flash can be read out. The PIN
+//! hash and retry counter are kept under `fido-pin`.
+//!
+//! User presence is confirmed with a button, see
+//! `set_user_presence_button()`. Commands that need user presence wait up to
+//! 30 seconds for the button to be pressed. Without a button every request
+//! is treated as confirmed, which is only suitable for testing.
+//!
+//! The authenticator has no built-in user verification, so it doesn't
+//! support the `uv` option. Resetting doesn't check how long ago the
+//! authenticator was powered up.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! let authenticator = components::fido::AuthenticatorComponent::new(
+//!     mux_alarm,
+//!     sha,
+//!     hmac,
+//!     ecdsa,
+//!     ecdh,
+//!     aes,
+//!     mux_rng,
+//!     virtual_kv,
+//!     StoragePermissions::new_kernel(&storage_cap),
+//!     AAGUID,
+//! )
+//! .finalize(components::authenticator_component_static!(...));
+//! authenticator.set_user_presence_button(
+//!     button_pin,
+//!     kernel::hil::gpio::ActivationMode::ActiveLow,
+//!     kernel::hil::gpio::FloatingState::PullUp,
+//! );
+//! authenticator.start();
+//! ```
+
+use core::cell::Cell;
+
+use kernel::deferred_call::{DeferredCall, DeferredCallClient};
+use kernel::hil::digest;
+use kernel::hil::entropy::Entropy32;
+use kernel::hil::gpio;
+use kernel::hil::kv;
+use kernel::hil::public_key_crypto::ecdh::{Ecdh, EcdhClient};
+use kernel::hil::public_key_crypto::keys::SetPrivateKey;
+use kernel::hil::public_key_crypto::signature::{ClientSign, SignatureSign};
+use kernel::hil::rng::{self, Rng};
+use kernel::hil::symmetric_encryption::{self, AES128, AES128CBC};
+use kernel::hil::time::{Alarm, AlarmClient, ConvertTicks};
+use kernel::storage_permissions::StoragePermissions;
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::utilities::leasable_buffer::{SubSlice, SubSliceMut};
+use kernel::ErrorCode;
+
+use super::cbor::{self, Reader, Writer};
+use super::{CtapCommand, CtapCommandClient, MAX_MESSAGE_LEN};
+use crate::kv_store_permissions::HEADER_LENGTH;
+
+/// The length of the credential IDs this authenticator creates.
+pub const CREDENTIAL_ID_LEN: usize = 16;
+/// The longest user handle a credential can store.
+pub const MAX_USER_ID_LEN: usize = 64;
+
+/// The length of the scratch buffer for data that is hashed, MACed or
+/// encrypted.
+pub const DATA_LEN: usize = 256;
+/// The length of the buffer for KV keys. Keys from other users of the KV
+/// store that are longer are skipped while searching for credentials.
+pub const KV_KEY_LEN: usize = 64;
+
+const RECORD_VERSION: u8 = 1;
+/// Version, flags, RP ID hash, private key, signature counter and user
+/// handle length.
+const RECORD_HEADER_LEN: usize = 1 + 1 + 32 + 32 + 4 + 1;
+/// The longest credential record.
+pub const RECORD_LEN: usize = RECORD_HEADER_LEN + MAX_USER_ID_LEN;
+/// The length of the buffer for KV values, including the KV header.
+pub const KV_VALUE_LEN: usize = HEADER_LENGTH + RECORD_LEN;
+
+const KV_PREFIX: &[u8] = b"fido-";
+const CREDENTIAL_PREFIX: &[u8] = b"fido-c";
+const PIN_STATE_KEY: &[u8] = b"fido-pin";
+const PIN_STATE_LEN: usize = 3 + 16;
+
+/// The number of discoverable credentials returned for one relying party.
+const MAX_DISCOVERED: usize = 4;
+/// The number of wrong PINs before the PIN is blocked.
+const MAX_PIN_RETRIES: u8 = 8;
+/// The number of consecutive wrong PINs before PIN entry is blocked until
+/// the authenticator is power cycled.
+const MAX_PIN_MISMATCHES: u8 = 3;
+const MIN_PIN_LEN: usize = 4;
+const PIN_TOKEN_LEN: usize = 32;
+const USER_PRESENCE_TIMEOUT_MS: u32 = 30_000;
+
+/// ES256, ECDSA with P-256 and SHA-256.
+const COSE_ALG_ES256: i64 = -7;
+/// ECDH-ES with HKDF-256, used for the PIN protocol key agreement key.
+const COSE_ALG_ECDH_ES_HKDF_256: i64 = -25;
+
+mod command {
+    pub const MAKE_CREDENTIAL: u8 = 0x01;
+    pub const GET_ASSERTION: u8 = 0x02;
+    pub const GET_INFO: u8 = 0x04;
+    pub const CLIENT_PIN: u8 = 0x06;
+    pub const RESET: u8 = 0x07;
+    pub const GET_NEXT_ASSERTION: u8 = 0x08;
+}
+
+mod client_pin {
+    pub const GET_RETRIES: u64 = 0x01;
+    pub const GET_KEY_AGREEMENT: u64 = 0x02;
+    pub const SET_PIN: u64 = 0x03;
+    pub const CHANGE_PIN: u64 = 0x04;
+    pub const GET_PIN_TOKEN: u64 = 0x05;
+}
+
+mod status {
+    pub const OK: u8 = 0x00;
+    pub const INVALID_COMMAND: u8 = 0x01;
+    pub const INVALID_PARAMETER: u8 = 0x02;
+    pub const INVALID_LENGTH: u8 = 0x03;
+    pub const CBOR_UNEXPECTED_TYPE: u8 = 0x11;
+    pub const INVALID_CBOR: u8 = 0x12;
+    pub const MISSING_PARAMETER: u8 = 0x14;
+    pub const CREDENTIAL_EXCLUDED: u8 = 0x19;
+    pub const UNSUPPORTED_ALGORITHM: u8 = 0x26;
+    pub const KEY_STORE_FULL: u8 = 0x28;
+    pub const UNSUPPORTED_OPTION: u8 = 0x2b;
+    pub const INVALID_OPTION: u8 = 0x2c;
+    pub const KEEPALIVE_CANCEL: u8 = 0x2d;
+    pub const NO_CREDENTIALS: u8 = 0x2e;
+    pub const USER_ACTION_TIMEOUT: u8 = 0x2f;
+    pub const NOT_ALLOWED: u8 = 0x30;
+    pub const PIN_INVALID: u8 = 0x31;
+    pub const PIN_BLOCKED: u8 = 0x32;
+    pub const PIN_AUTH_INVALID: u8 = 0x33;
+    pub const PIN_AUTH_BLOCKED: u8 = 0x34;
+    pub const PIN_NOT_SET: u8 = 0x35;
+    pub const PIN_REQUIRED: u8 = 0x36;
+    pub const PIN_POLICY_VIOLATION: u8 = 0x37;
+    pub const OTHER: u8 = 0x7f;
+}
+
+/// Authenticator data flags.
+mod flags {
+    pub const USER_PRESENT: u8 = 0x01;
+    pub const USER_VERIFIED: u8 = 0x04;
+    pub const ATTESTED_CREDENTIAL_DATA: u8 = 0x40;
+}
+
+impl From<cbor::Error> for u8 {
+    fn from(error: cbor::Error) -> u8 {
+        match error {
+            cbor::Error::UnexpectedType => status::CBOR_UNEXPECTED_TYPE,
+            cbor::Error::Invalid | cbor::Error::TooDeep => status::INVALID_CBOR,
+        }
+    }
+}
+
+/// What to do once the user confirmed their presence.
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum AfterPresence {
+    Finish(u8),
+    CreateCredential,
+    Assert,
+    Reset,
+}
+
+/// The asynchronous operation the authenticator is waiting for.
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Step {
+    Idle,
+    /// Waiting for the response to be passed to the client.
+    Responding,
+
+    InitPinToken,
+    InitKeyAgreement,
+    InitPinState,
+
+    RpIdHashed,
+    PinAuthChecked,
+    WaitForUser(AfterPresence),
+    AuthDataHashed,
+    Signed,
+
+    ExcludeChecked,
+    CredentialRandom,
+    CredentialStored,
+
+    AllowChecked,
+    Discovered,
+    AssertionLoaded,
+    CounterStored,
+
+    SharedPoint,
+    SharedSecretHashed,
+    PinMacChecked,
+    PinRetriesStored,
+    PinHashDecrypted,
+    KeyAgreementRegenerated(u8),
+    NewPinDecrypted,
+    NewPinHashed,
+    PinStateStored,
+    PinTokenEncrypted,
+
+    ResetScanned,
+    ResetDeleted,
+    ResetPinToken,
+}
+
+/// A credential, as kept in the KV store.
+#[derive(Clone, Copy)]
+struct Credential {
+    id: [u8; CREDENTIAL_ID_LEN],
+    discoverable: bool,
+    rp_id_hash: [u8; 32],
+    private_key: [u8; 32],
+    sign_count: u32,
+    user_id: [u8; MAX_USER_ID_LEN],
+    user_id_len: usize,
+}
+
+impl Credential {
+    const EMPTY: Credential = Credential {
+        id: [0; CREDENTIAL_ID_LEN],
+        discoverable: false,
+        rp_id_hash: [0; 32],
+        private_key: [0; 32],
+        sign_count: 0,
+        user_id: [0; MAX_USER_ID_LEN],
+        user_id_len: 0,
+    };
+
+    fn encode(&self, record: &mut [u8]) -> usize {
+        record[0] = RECORD_VERSION;
+        record[1] = self.discoverable as u8;
+        record[2..34].copy_from_slice(&self.rp_id_hash);
+        record[34..66].copy_from_slice(&self.private_key);
+        record[66..70].copy_from_slice(&self.sign_count.to_be_bytes());
+        record[70] = self.user_id_len as u8;
+        record[71..71 + self.user_id_len].copy_from_slice(&self.user_id[..self.user_id_len]);
+        RECORD_HEADER_LEN + self.user_id_len
+    }
+
+    fn decode(id: &[u8], record: &[u8]) -> Option<Credential> {
+        if record.len() < RECORD_HEADER_LEN || record[0] != RECORD_VERSION {
+            return None;
+        }
+        let user_id_len = record[70] as usize;
+        if user_id_len > MAX_USER_ID_LEN || record.len() < RECORD_HEADER_LEN + user_id_len {
+            return None;
+        }
+
+        let mut credential = Credential::EMPTY;
+        credential.id.copy_from_slice(id.get(..CREDENTIAL_ID_LEN)?);
+        credential.discoverable = record[1] & 1 == 1;
+        credential.rp_id_hash.copy_from_slice(&record[2..34]);
+        credential.private_key.copy_from_slice(&record[34..66]);
+        credential.sign_count =
+            u32::from_be_bytes([record[66], record[67], record[68], record[69]]);
+        credential.user_id[..user_id_len].copy_from_slice(&record[71..71 + user_id_len]);
+        credential.user_id_len = user_id_len;
+        Some(credential)
+    }
+}
+
+/// The `pinAuth` parameter of a request.
+#[derive(Clone, Copy, PartialEq)]
+enum PinAuth {
+    Absent,
+    /// An empty `pinAuth`, which platforms send to check whether a PIN is
+    /// set.
+    Empty,
+    Present([u8; 16]),
+}
+
+/// A range of the request buffer.
+type Range = (usize, usize);
+
+/// Compare without returning early, so the time taken doesn't depend on
+/// where `a` and `b` differ.
+fn equal(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Write the COSE encoding of a P-256 public key.
+fn write_cose_key(w: &mut Writer, alg: i64, public_key: &[u8; 64]) {
+    w.map(5);
+    // kty: EC2
+    w.unsigned(1);
+    w.unsigned(2);
+    w.unsigned(3);
+    w.integer(alg);
+    // crv: P-256
+    w.integer(-1);
+    w.unsigned(1);
+    w.integer(-2);
+    w.bytes(&public_key[..32]);
+    w.integer(-3);
+    w.bytes(&public_key[32..]);
+}
+
+/// Encode the `r | s` signature as a DER `ECDSA-Sig-Value`, returning the
+/// length.
+fn der_signature(signature: &[u8; 64], out: &mut [u8; 72]) -> usize {
+    let mut len = 2;
+    for half in signature.chunks(32) {
+        let start = half.iter().position(|b| *b != 0).unwrap_or(31);
+        let value = &half[start..];
+        let pad = (value[0] & 0x80 != 0) as usize;
+        out[len] = 0x02;
+        out[len + 1] = (value.len() + pad) as u8;
+        out[len + 2] = 0;
+        out[len + 2 + pad..len + 2 + pad + value.len()].copy_from_slice(value);
+        len += 2 + pad + value.len();
+    }
+    out[0] = 0x30;
+    out[1] = (len - 2) as u8;
+    len
+}
+
+/// The parameters of `authenticatorMakeCredential` or
+/// `authenticatorGetAssertion`.
+struct Parameters {
+    client_data_hash: [u8; 32],
+    rp_id: Range,
+    /// The position and length of the exclude or allow list.
+    list: Option<(usize, usize)>,
+    pin_auth: PinAuth,
+    /// Whether the platform asked for user presence. Only used by
+    /// `authenticatorGetAssertion`.
+    user_presence: bool,
+    /// The credential to create. Only used by `authenticatorMakeCredential`.
+    credential: Credential,
+}
+
+// Parsing requests.
+
+/// Read a map with text keys, calling `entry` with each key.
+fn read_text_map<'b, F: FnMut(&str, &mut Reader<'b>) -> Result<(), u8>>(
+    r: &mut Reader<'b>,
+    mut entry: F,
+) -> Result<(), u8> {
+    for _ in 0..r.map()? {
+        let key = r.text()?;
+        entry(key, r)?;
+    }
+    Ok(())
+}
+
+/// Read the range of a text or byte string in the request.
+fn read_range(r: &mut Reader, text: bool) -> Result<Range, u8> {
+    let len = if text {
+        r.text()?.len()
+    } else {
+        r.bytes()?.len()
+    };
+    Ok((r.position() - len, len))
+}
+
+/// Read the start of an exclude or allow list and check its entries.
+fn read_credential_list(r: &mut Reader) -> Result<(usize, usize), u8> {
+    let len = r.array()?;
+    let position = r.position();
+    for _ in 0..len {
+        read_credential_descriptor(r)?;
+    }
+    Ok((position, len))
+}
+
+/// Read a `PublicKeyCredentialDescriptor` and return its ID.
+fn read_credential_descriptor<'b>(r: &mut Reader<'b>) -> Result<&'b [u8], u8> {
+    let mut id = None;
+    for _ in 0..r.map()? {
+        match r.text()? {
+            "id" => id = Some(r.bytes()?),
+            "type" => {
+                r.text()?;
+            }
+            _ => r.skip()?,
+        }
+    }
+    id.ok_or(status::MISSING_PARAMETER)
+}
+
+fn read_pin_auth(r: &mut Reader) -> Result<PinAuth, u8> {
+    let pin_auth = r.bytes()?;
+    match pin_auth.len() {
+        0 => Ok(PinAuth::Empty),
+        16 => {
+            let mut auth = [0; 16];
+            auth.copy_from_slice(pin_auth);
+            Ok(PinAuth::Present(auth))
+        }
+        _ => Err(status::PIN_AUTH_INVALID),
+    }
+}
+
+fn read_client_data_hash(r: &mut Reader) -> Result<[u8; 32], u8> {
+    let hash = r.bytes()?;
+    if hash.len() != 32 {
+        return Err(status::INVALID_LENGTH);
+    }
+    let mut client_data_hash = [0; 32];
+    client_data_hash.copy_from_slice(hash);
+    Ok(client_data_hash)
+}
+
+fn parse_make_credential(request: &[u8]) -> Result<Parameters, u8> {
+    let mut r = Reader::new(request, 1);
+    let mut credential = Credential::EMPTY;
+    let (mut client_data_hash, mut rp_id, mut list, mut pin_auth) =
+        (None, None, None, PinAuth::Absent);
+    let (mut has_user, mut has_params) = (false, false);
+    let mut supported = false;
+    let mut pin_protocol = None;
+
+    for _ in 0..r.map()? {
+        match r.unsigned()? {
+            1 => client_data_hash = Some(read_client_data_hash(&mut r)?),
+            2 => read_text_map(&mut r, |key, r| {
+                match key {
+                    "id" => rp_id = Some(read_range(r, true)?),
+                    _ => r.skip()?,
+                }
+                Ok(())
+            })?,
+            3 => read_text_map(&mut r, |key, r| {
+                match key {
+                    "id" => {
+                        let id = r.bytes()?;
+                        if id.len() > MAX_USER_ID_LEN {
+                            return Err(status::INVALID_LENGTH);
+                        }
+                        credential.user_id[..id.len()].copy_from_slice(id);
+                        credential.user_id_len = id.len();
+                        has_user = true;
+                    }
+                    _ => r.skip()?,
+                }
+                Ok(())
+            })?,
+            4 => {
+                for _ in 0..r.array()? {
+                    let (mut alg, mut public_key) = (None, false);
+                    read_text_map(&mut r, |key, r| {
+                        match key {
+                            "alg" => alg = Some(r.integer()?),
+                            "type" => public_key = r.text()? == "public-key",
+                            _ => r.skip()?,
+                        }
+                        Ok(())
+                    })?;
+                    supported |= public_key && alg == Some(COSE_ALG_ES256);
+                }
+                has_params = true;
+            }
+            5 => list = Some(read_credential_list(&mut r)?),
+            7 => read_text_map(&mut r, |key, r| {
+                let value = r.bool()?;
+                match key {
+                    "rk" => credential.discoverable = value,
+                    "uv" if value => return Err(status::UNSUPPORTED_OPTION),
+                    "up" if !value => return Err(status::INVALID_OPTION),
+                    _ => {}
+                }
+                Ok(())
+            })?,
+            8 => pin_auth = read_pin_auth(&mut r)?,
+            9 => pin_protocol = Some(r.unsigned()?),
+            _ => r.skip()?,
+        }
+    }
+
+    let (Some(client_data_hash), Some(rp_id), true, true) =
+        (client_data_hash, rp_id, has_user, has_params)
+    else {
+        return Err(status::MISSING_PARAMETER);
+    };
+    if !supported {
+        return Err(status::UNSUPPORTED_ALGORITHM);
+    }
+    if matches!(pin_auth, PinAuth::Present(_)) && pin_protocol != Some(1) {
+        return Err(status::PIN_AUTH_INVALID);
+    }
+    Ok(Parameters {
+        client_data_hash,
+        rp_id,
+        list,
+        pin_auth,
+        user_presence: true,
+        credential,
+    })
+}
+
+fn parse_get_assertion(request: &[u8]) -> Result<Parameters, u8> {
+    let mut r = Reader::new(request, 1);
+    let (mut client_data_hash, mut rp_id, mut list, mut pin_auth) =
+        (None, None, None, PinAuth::Absent);
+    let mut pin_protocol = None;
+    let mut user_presence = true;
+
+    for _ in 0..r.map()? {
+        match r.unsigned()? {
+            1 => rp_id = Some(read_range(&mut r, true)?),
+            2 => client_data_hash = Some(read_client_data_hash(&mut r)?),
+            3 => list = Some(read_credential_list(&mut r)?),
+            5 => read_text_map(&mut r, |key, r| {
+                let value = r.bool()?;
+                match key {
+                    "up" => user_presence = value,
+                    "uv" if value => return Err(status::UNSUPPORTED_OPTION),
+                    "rk" => return Err(status::INVALID_OPTION),
+                    _ => {}
+                }
+                Ok(())
+            })?,
+            6 => pin_auth = read_pin_auth(&mut r)?,
+            7 => pin_protocol = Some(r.unsigned()?),
+            _ => r.skip()?,
+        }
+    }
+
+    let (Some(client_data_hash), Some(rp_id)) = (client_data_hash, rp_id) else {
+        return Err(status::MISSING_PARAMETER);
+    };
+    if matches!(pin_auth, PinAuth::Present(_)) && pin_protocol != Some(1) {
+        return Err(status::PIN_AUTH_INVALID);
+    }
+    Ok(Parameters {
+        client_data_hash,
+        rp_id,
+        list,
+        pin_auth,
+        user_presence,
+        credential: Credential::EMPTY,
+    })
+}
+
+pub struct Authenticator<
+    'a,
+    A: Alarm<'a>,
+    D: digest::Digest<'a, 32> + digest::Sha256,
+    H: digest::Digest<'a, 32> + digest::HmacSha256,
+    S: SignatureSign<'a, 32, 64> + SetPrivateKey<32, 64>,
+    E: Ecdh<'a, 64, 32>,
+    C: AES128<'a> + AES128CBC,
+> {
+    alarm: &'a A,
+    sha: &'a D,
+    hmac: &'a H,
+    signer: &'a S,
+    ecdh: &'a E,
+    /// Randomness for the ECDH key generation.
+    entropy: &'a dyn Entropy32<'a>,
+    aes: &'a C,
+    rng: &'a dyn Rng<'a>,
+    kv: &'a dyn kv::KVPermissions<'a>,
+    storage_permissions: StoragePermissions,
+    aaguid: [u8; 16],
+    user_presence: OptionalCell<(&'a dyn gpio::InterruptPin<'a>, gpio::ActivationMode)>,
+
+    client: OptionalCell<&'a dyn CtapCommandClient>,
+    deferred_call: DeferredCall,
+    ready: Cell<bool>,
+    step: Cell<Step>,
+    /// Whether the engine currently hashing is the HMAC.
+    using_hmac: Cell<bool>,
+
+    request: TakeCell<'static, [u8]>,
+    request_len: Cell<usize>,
+    response: TakeCell<'static, [u8]>,
+    response_len: Cell<usize>,
+
+    // The parameters of the current command.
+    command: Cell<u8>,
+    client_data_hash: Cell<[u8; 32]>,
+    rp_id: Cell<Range>,
+    rp_id_hash: Cell<[u8; 32]>,
+    /// The position and remaining length of the exclude or allow list.
+    list: Cell<Option<(usize, usize)>>,
+    pin_auth: Cell<PinAuth>,
+    user_present: Cell<bool>,
+    user_verified: Cell<bool>,
+    sub_command: Cell<u64>,
+    new_pin_enc: Cell<Range>,
+    pin_hash_enc: Cell<Range>,
+    auth_data_len: Cell<usize>,
+
+    credential: Cell<Credential>,
+    /// Credentials found by `authenticatorGetAssertion`.
+    credentials: Cell<[[u8; CREDENTIAL_ID_LEN]; MAX_DISCOVERED]>,
+    credential_count: Cell<usize>,
+    next_credential: Cell<usize>,
+
+    // The PIN protocol state.
+    pin_hash: Cell<Option<[u8; 16]>>,
+    pin_retries: Cell<u8>,
+    pin_mismatches: Cell<u8>,
+    pin_token: Cell<[u8; PIN_TOKEN_LEN]>,
+    key_agreement: Cell<[u8; 64]>,
+    shared_secret: Cell<[u8; 32]>,
+
+    random: Cell<[u8; 48]>,
+    random_len: Cell<usize>,
+    random_wanted: Cell<usize>,
+    record: Cell<[u8; RECORD_LEN]>,
+    record_len: Cell<usize>,
+
+    data: TakeCell<'static, [u8]>,
+    digest: TakeCell<'static, [u8; 32]>,
+    signature: TakeCell<'static, [u8; 64]>,
+    ecdh_public: TakeCell<'static, [u8; 64]>,
+    ecdh_secret: TakeCell<'static, [u8; 32]>,
+    kv_key: TakeCell<'static, [u8]>,
+    kv_value: TakeCell<'static, [u8]>,
+}
+
+impl<
+        'a,
+        A: Alarm<'a>,
+        D: digest::Digest<'a, 32> + digest::Sha256,
+        H: digest::Digest<'a, 32> + digest::HmacSha256,
+        S: SignatureSign<'a, 32, 64> + SetPrivateKey<32, 64>,
+        E: Ecdh<'a, 64, 32>,
+        C: AES128<'a> + AES128CBC,
+    > Authenticator<'a, A, D, H, S, E, C>
+{
+    pub fn new(
+        alarm: &'a A,
+        sha: &'a D,
+        hmac: &'a H,
+        signer: &'a S,
+        ecdh: &'a E,
+        entropy: &'a dyn Entropy32<'a>,
+        aes: &'a C,
+        rng: &'a dyn Rng<'a>,
+        kv: &'a dyn kv::KVPermissions<'a>,
+        storage_permissions: StoragePermissions,
+        aaguid: [u8; 16],
+        data: &'static mut [u8],
+        digest: &'static mut [u8; 32],
+        signature: &'static mut [u8; 64],
+        ecdh_public: &'static mut [u8; 64],
+        ecdh_secret: &'static mut [u8; 32],
+        kv_key: &'static mut [u8],
+        kv_value: &'static mut [u8],
+    ) -> Self {
+        Authenticator {
+            alarm,
+            sha,
+            hmac,
+            signer,
+            ecdh,
+            entropy,
+            aes,
+            rng,
+            kv,
+            storage_permissions,
+            aaguid,
+            user_presence: OptionalCell::empty(),
+            client: OptionalCell::empty(),
+            deferred_call: DeferredCall::new(),
+            ready: Cell::new(false),
+            step: Cell::new(Step::Idle),
+            using_hmac: Cell::new(false),
+            request: TakeCell::empty(),
+            request_len: Cell::new(0),
+            response: TakeCell::empty(),
+            response_len: Cell::new(0),
+            command: Cell::new(0),
+            client_data_hash: Cell::new([0; 32]),
+            rp_id: Cell::new((0, 0)),
+            rp_id_hash: Cell::new([0; 32]),
+            list: Cell::new(None),
+            pin_auth: Cell::new(PinAuth::Absent),
+            user_present: Cell::new(false),
+            user_verified: Cell::new(false),
+            sub_command: Cell::new(0),
+            new_pin_enc: Cell::new((0, 0)),
+            pin_hash_enc: Cell::new((0, 0)),
+            auth_data_len: Cell::new(0),
+            credential: Cell::new(Credential::EMPTY),
+            credentials: Cell::new([[0; CREDENTIAL_ID_LEN]; MAX_DISCOVERED]),
+            credential_count: Cell::new(0),
+            next_credential: Cell::new(0),
+            pin_hash: Cell::new(None),
+            pin_retries: Cell::new(MAX_PIN_RETRIES),
+            pin_mismatches: Cell::new(0),
+            pin_token: Cell::new([0; PIN_TOKEN_LEN]),
+            key_agreement: Cell::new([0; 64]),
+            shared_secret: Cell::new([0; 32]),
+            random: Cell::new([0; 48]),
+            random_len: Cell::new(0),
+            random_wanted: Cell::new(0),
+            record: Cell::new([0; RECORD_LEN]),
+            record_len: Cell::new(0),
+            data: TakeCell::new(data),
+            digest: TakeCell::new(digest),
+            signature: TakeCell::new(signature),
+            ecdh_public: TakeCell::new(ecdh_public),
+            ecdh_secret: TakeCell::new(ecdh_secret),
+            kv_key: TakeCell::new(kv_key),
+            kv_value: TakeCell::new(kv_value),
+        }
+    }
+
+    /// Use `pin` as the button the user presses to confirm their presence.
+    pub fn set_user_presence_button(
+        &'a self,
+        pin: &'a dyn gpio::InterruptPin<'a>,
+        mode: gpio::ActivationMode,
+        floating_state: gpio::FloatingState,
+    ) {
+        pin.make_input();
+        pin.set_floating_state(floating_state);
+        pin.set_client(self);
+        self.user_presence.set((pin, mode));
+    }
+
+    /// Create the PIN token and the PIN protocol key agreement key, and load
+    /// the PIN state. Commands are rejected until this is done.
+    pub fn start(&self) -> Result<(), ErrorCode> {
+        if self.ready.get() || self.step.get() != Step::Idle {
+            return Err(ErrorCode::ALREADY);
+        }
+        self.aes.enable();
+        self.start_random(PIN_TOKEN_LEN, Step::InitPinToken)
+    }
+
+    // Completing commands.
+
+    /// Finish the command with an error, or with a response that only has
+    /// the status.
+    fn finish(&self, status: u8) {
+        self.response.map(|response| response[0] = status);
+        self.complete(1);
+    }
+
+    /// Finish the command with `status::OK` and the CBOR written by `build`.
+    fn respond<F: FnOnce(&mut Writer)>(&self, build: F) {
+        let len = self.write_response(build);
+        self.respond_with(len);
+    }
+
+    /// Write `status::OK` and the CBOR written by `build` to the response,
+    /// returning the length or `None` if it didn't fit.
+    fn write_response<F: FnOnce(&mut Writer)>(&self, build: F) -> Option<usize> {
+        self.response.map_or(None, |response| {
+            response[0] = status::OK;
+            let mut writer = Writer::new(&mut response[1..]);
+            build(&mut writer);
+            writer.finish().map(|len| len + 1)
+        })
+    }
+
+    fn respond_with(&self, len: Option<usize>) {
+        match len {
+            Some(len) => self.complete(len),
+            None => self.finish(status::OTHER),
+        }
+    }
+
+    fn complete(&self, len: usize) {
+        // Don't keep secrets from this command around.
+        self.signer.clear_private_key();
+        self.credential.set(Credential::EMPTY);
+        self.shared_secret.set([0; 32]);
+        self.random.set([0; 48]);
+        self.data.map(|data| data.fill(0));
+
+        self.response_len.set(len);
+        self.step.set(Step::Responding);
+        self.deferred_call.set();
+    }
+
+    // Starting asynchronous operations. On error these finish the command.
+
+    fn start_random(&self, len: usize, step: Step) -> Result<(), ErrorCode> {
+        self.random_len.set(0);
+        self.random_wanted.set(len);
+        self.step.set(step);
+        self.rng.get().inspect_err(|_| {
+            self.step.set(Step::Idle);
+        })
+    }
+
+    /// Hash `data[..len]` with SHA-256, or with HMAC-SHA-256 if `key` is
+    /// given.
+    fn start_hash(&self, key: Option<&[u8]>, len: usize, step: Step) {
+        let data = match self.data.take() {
+            Some(data) => data,
+            None => return self.finish(status::OTHER),
+        };
+        let mut data = SubSliceMut::new(data);
+        data.slice(..len);
+
+        self.step.set(step);
+        self.using_hmac.set(key.is_some());
+        let mode = match key {
+            Some(key) => {
+                self.hmac.clear_data();
+                self.hmac.set_mode_hmacsha256(key)
+            }
+            None => {
+                self.sha.clear_data();
+                self.sha.set_mode_sha256()
+            }
+        };
+        let result = match (mode, key) {
+            (Err(e), _) => Err((e, data)),
+            (Ok(()), Some(_)) => self.hmac.add_mut_data(data),
+            (Ok(()), None) => self.sha.add_mut_data(data),
+        };
+        if let Err((_, data)) = result {
+            self.data.replace(data.take());
+            self.finish(status::OTHER);
+        }
+    }
+
+    /// Encrypt or decrypt `data[..len]` in place with AES-256-CBC, a zero IV
+    /// and the shared secret, as PIN protocol one does.
+    fn start_crypt(&self, encrypting: bool, len: usize, step: Step) {
+        let data = match self.data.take() {
+            Some(data) => data,
+            None => return self.finish(status::OTHER),
+        };
+
+        let setup = self
+            .aes
+            .set_key(&self.shared_secret.get())
+            .and_then(|()| self.aes.set_iv(&[0; 16]))
+            .and_then(|()| self.aes.set_mode_aes128cbc(encrypting));
+        if setup.is_err() {
+            self.data.replace(data);
+            return self.finish(status::OTHER);
+        }
+        self.aes.start_message();
+
+        self.step.set(step);
+        if let Some((_, _, data)) = self.aes.crypt(None, data, 0, len) {
+            self.data.replace(data);
+            self.finish(status::OTHER);
+        }
+    }
+
+    fn kv_key_for(&self, id: Option<&[u8; CREDENTIAL_ID_LEN]>) -> Option<SubSliceMut<'static, u8>> {
+        let key = self.kv_key.take()?;
+        let len = match id {
+            Some(id) => {
+                key[..CREDENTIAL_PREFIX.len()].copy_from_slice(CREDENTIAL_PREFIX);
+                key[CREDENTIAL_PREFIX.len()..CREDENTIAL_PREFIX.len() + CREDENTIAL_ID_LEN]
+                    .copy_from_slice(id);
+                CREDENTIAL_PREFIX.len() + CREDENTIAL_ID_LEN
+            }
+            None => {
+                key[..PIN_STATE_KEY.len()].copy_from_slice(PIN_STATE_KEY);
+                PIN_STATE_KEY.len()
+            }
+        };
+        let mut key = SubSliceMut::new(key);
+        key.slice(..len);
+        Some(key)
+    }
+
+    /// Read the credential `id`, or the PIN state if `id` is `None`.
+    fn start_kv_get(&self, id: Option<&[u8; CREDENTIAL_ID_LEN]>, step: Step) {
+        let (key, value) = match (self.kv_key_for(id), self.kv_value.take()) {
+            (Some(key), Some(value)) => (key, value),
+            (key, value) => {
+                key.map(|key| self.kv_key.replace(key.take()));
+                value.map(|value| self.kv_value.replace(value));
+                return self.finish(status::OTHER);
+            }
+        };
+
+        self.step.set(step);
+        if let Err((key, value, _)) =
+            self.kv
+                .get(key, SubSliceMut::new(value), self.storage_permissions)
+        {
+            self.kv_key.replace(key.take());
+            self.kv_value.replace(value.take());
+            self.finish(status::OTHER);
+        }
+    }
+
+    /// Store the current credential, or the PIN state if `credential` is
+    /// false.
+    fn start_kv_set(&self, credential: bool, step: Step) {
+        let id = self.credential.get().id;
+        let (key, value) = match (
+            self.kv_key_for(if credential { Some(&id) } else { None }),
+            self.kv_value.take(),
+        ) {
+            (Some(key), Some(value)) => (key, value),
+            (key, value) => {
+                key.map(|key| self.kv_key.replace(key.take()));
+                value.map(|value| self.kv_value.replace(value));
+                return self.finish(status::OTHER);
+            }
+        };
+
+        let header_size = self.kv.header_size();
+        let record = &mut value[header_size..];
+        let len = if credential {
+            self.credential.get().encode(record)
+        } else {
+            record[0] = RECORD_VERSION;
+            record[1] = self.pin_retries.get();
+            record[2] = self.pin_hash.get().is_some() as u8;
+            record[3..PIN_STATE_LEN].copy_from_slice(&self.pin_hash.get().unwrap_or([0; 16]));
+            PIN_STATE_LEN
+        };
+        let mut value = SubSliceMut::new(value);
+        value.slice(..header_size + len);
+
+        self.step.set(step);
+        if let Err((key, value, _)) = self.kv.set(key, value, self.storage_permissions) {
+            self.kv_key.replace(key.take());
+            let value = value.take();
+            value.fill(0);
+            self.kv_value.replace(value);
+            self.finish(status::OTHER);
+        }
+    }
+
+    fn start_next_key(&self, position: usize, step: Step) {
+        let (key, value) = match (self.kv_key.take(), self.kv_value.take()) {
+            (Some(key), Some(value)) => (key, value),
+            (key, value) => {
+                key.map(|key| self.kv_key.replace(key));
+                value.map(|value| self.kv_value.replace(value));
+                return self.finish(status::OTHER);
+            }
+        };
+
+        self.step.set(step);
+        if let Err((key, value, _)) = self.kv.next_key(
+            position,
+            SubSliceMut::new(key),
+            SubSliceMut::new(value),
+            self.storage_permissions,
+        ) {
+            self.kv_key.replace(key.take());
+            self.kv_value.replace(value.take());
+            self.finish(status::OTHER);
+        }
+    }
+
+    fn start_key_agreement(&self, step: Step) -> Result<(), ErrorCode> {
+        let public_key = self.ecdh_public.take().ok_or(ErrorCode::BUSY)?;
+        self.step.set(step);
+        self.ecdh
+            .generate(self.entropy, public_key)
+            .map_err(|(e, public_key)| {
+                self.ecdh_public.replace(public_key);
+                e
+            })
+    }
+
+    fn wait_for_user(&self, after: AfterPresence) {
+        self.user_present.set(true);
+        match self.user_presence.get() {
+            Some((pin, _)) => {
+                self.step.set(Step::WaitForUser(after));
+                pin.enable_interrupts(gpio::InterruptEdge::EitherEdge);
+                self.alarm.set_alarm(
+                    self.alarm.now(),
+                    self.alarm.ticks_from_ms(USER_PRESENCE_TIMEOUT_MS),
+                );
+            }
+            None => self.user_confirmed(after),
+        }
+    }
+
+    fn stop_waiting(&self) {
+        self.user_presence.map(|(pin, _)| pin.disable_interrupts());
+        let _ = self.alarm.disarm();
+    }
+
+    fn user_confirmed(&self, after: AfterPresence) {
+        match after {
+            AfterPresence::Finish(status) => self.finish(status),
+            AfterPresence::CreateCredential => {
+                if self
+                    .start_random(CREDENTIAL_ID_LEN + 32, Step::CredentialRandom)
+                    .is_err()
+                {
+                    self.finish(status::OTHER);
+                }
+            }
+            AfterPresence::Assert => self.assert(0),
+            AfterPresence::Reset => self.start_next_key(0, Step::ResetScanned),
+        }
+    }
+
+    // Parsing requests.
+
+    fn set_parameters(&self, parameters: &Parameters) {
+        self.client_data_hash.set(parameters.client_data_hash);
+        self.rp_id.set(parameters.rp_id);
+        self.list.set(parameters.list);
+        self.pin_auth.set(parameters.pin_auth);
+    }
+
+    fn parse_client_pin(&self, request: &[u8]) -> Result<(), u8> {
+        let mut r = Reader::new(request, 1);
+        let (mut pin_protocol, mut sub_command) = (None, None);
+        let mut has_key_agreement = false;
+
+        for _ in 0..r.map()? {
+            match r.unsigned()? {
+                1 => pin_protocol = Some(r.unsigned()?),
+                2 => sub_command = Some(r.unsigned()?),
+                3 => {
+                    let mut public_key = [0; 64];
+                    for _ in 0..r.map()? {
+                        match r.integer()? {
+                            -2 => {
+                                let x = r.bytes()?;
+                                if x.len() != 32 {
+                                    return Err(status::INVALID_PARAMETER);
+                                }
+                                public_key[..32].copy_from_slice(x);
+                            }
+                            -3 => {
+                                let y = r.bytes()?;
+                                if y.len() != 32 {
+                                    return Err(status::INVALID_PARAMETER);
+                                }
+                                public_key[32..].copy_from_slice(y);
+                            }
+                            _ => r.skip()?,
+                        }
+                    }
+                    self.ecdh_public.map(|peer| *peer = public_key);
+                    has_key_agreement = true;
+                }
+                4 => self.pin_auth.set(read_pin_auth(&mut r)?),
+                5 => self.new_pin_enc.set(read_range(&mut r, false)?),
+                6 => self.pin_hash_enc.set(read_range(&mut r, false)?),
+                _ => r.skip()?,
+            }
+        }
+
+        match pin_protocol {
+            None => return Err(status::MISSING_PARAMETER),
+            Some(1) => {}
+            Some(_) => return Err(status::INVALID_PARAMETER),
+        }
+        let sub_command = sub_command.ok_or(status::MISSING_PARAMETER)?;
+        self.sub_command.set(sub_command);
+
+        let has_pin_auth = matches!(self.pin_auth.get(), PinAuth::Present(_));
+        let has_new_pin = self.new_pin_enc.get().1 != 0;
+        let has_pin_hash = self.pin_hash_enc.get().1 != 0;
+        let complete = match sub_command {
+            client_pin::GET_RETRIES | client_pin::GET_KEY_AGREEMENT => true,
+            client_pin::SET_PIN => has_key_agreement && has_pin_auth && has_new_pin,
+            client_pin::CHANGE_PIN => {
+                has_key_agreement && has_pin_auth && has_new_pin && has_pin_hash
+            }
+            client_pin::GET_PIN_TOKEN => has_key_agreement && has_pin_hash,
+            _ => return Err(status::INVALID_PARAMETER),
+        };
+        if !complete {
+            return Err(status::MISSING_PARAMETER);
+        }
+        if has_new_pin && self.new_pin_enc.get().1 != 64 {
+            return Err(status::PIN_POLICY_VIOLATION);
+        }
+        if has_pin_hash && self.pin_hash_enc.get().1 != 16 {
+            return Err(status::INVALID_PARAMETER);
+        }
+        Ok(())
+    }
+
+    // Running commands.
+
+    fn run_command(&self) -> Result<(), u8> {
+        let len = self.request_len.get();
+        let command = self.request.map_or(0, |request| request[0]);
+        self.command.set(command);
+
+        if command != command::GET_NEXT_ASSERTION {
+            // Any other command ends a sequence of assertions.
+            self.credential_count.set(0);
+            self.next_credential.set(0);
+            self.list.set(None);
+            self.pin_auth.set(PinAuth::Absent);
+            self.user_verified.set(false);
+            self.user_present.set(false);
+            self.new_pin_enc.set((0, 0));
+            self.pin_hash_enc.set((0, 0));
+        }
+
+        let parsed = self
+            .request
+            .map_or(Err(status::OTHER), |request| match command {
+                command::MAKE_CREDENTIAL => {
+                    parse_make_credential(&request[..len]).map(|parameters| {
+                        self.set_parameters(&parameters);
+                        self.credential.set(parameters.credential);
+                    })
+                }
+                command::GET_ASSERTION => parse_get_assertion(&request[..len]).map(|parameters| {
+                    self.set_parameters(&parameters);
+                    self.user_present.set(parameters.user_presence);
+                }),
+                command::CLIENT_PIN => self.parse_client_pin(&request[..len]),
+                command::GET_INFO | command::RESET | command::GET_NEXT_ASSERTION => Ok(()),
+                _ => Err(status::INVALID_COMMAND),
+            });
+        parsed?;
+
+        match command {
+            command::MAKE_CREDENTIAL | command::GET_ASSERTION => {
+                let rp_id = self.rp_id.get();
+                if rp_id.1 > DATA_LEN {
+                    return Err(status::INVALID_LENGTH);
+                }
+                let len = self.copy_to_data(&[rp_id]);
+                self.start_hash(None, len, Step::RpIdHashed);
+            }
+            command::GET_NEXT_ASSERTION => {
+                let next = self.next_credential.get();
+                if next == 0 || next >= self.credential_count.get() {
+                    return Err(status::NOT_ALLOWED);
+                }
+                self.assert(next);
+            }
+            command::GET_INFO => self.respond_get_info(),
+            command::CLIENT_PIN => self.run_client_pin()?,
+            command::RESET => self.wait_for_user(AfterPresence::Reset),
+            _ => return Err(status::INVALID_COMMAND),
+        }
+        Ok(())
+    }
+
+    fn respond_get_info(&self) {
+        let pin_set = self.pin_hash.get().is_some();
+        self.respond(|w| {
+            w.map(5);
+            w.unsigned(1);
+            w.array(1);
+            w.text("FIDO_2_0");
+            w.unsigned(3);
+            w.bytes(&self.aaguid);
+            w.unsigned(4);
+            w.map(4);
+            w.text("rk");
+            w.bool(true);
+            w.text("up");
+            w.bool(true);
+            w.text("plat");
+            w.bool(false);
+            w.text("clientPin");
+            w.bool(pin_set);
+            w.unsigned(5);
+            w.unsigned(MAX_MESSAGE_LEN as u64);
+            w.unsigned(6);
+            w.array(1);
+            w.unsigned(1);
+        });
+    }
+
+    /// Check the `pinAuth` of a makeCredential or getAssertion request.
+    fn check_pin_auth(&self) {
+        let pin_set = self.pin_hash.get().is_some();
+        match self.pin_auth.get() {
+            PinAuth::Absent => {
+                if pin_set && self.command.get() == command::MAKE_CREDENTIAL {
+                    self.finish(status::PIN_REQUIRED);
+                } else {
+                    self.pin_auth_checked();
+                }
+            }
+            PinAuth::Empty => self.wait_for_user(AfterPresence::Finish(if pin_set {
+                status::PIN_INVALID
+            } else {
+                status::PIN_NOT_SET
+            })),
+            PinAuth::Present(_) => {
+                if !pin_set {
+                    return self.finish(status::PIN_NOT_SET);
+                }
+                self.data
+                    .map(|data| data[..32].copy_from_slice(&self.client_data_hash.get()));
+                self.start_hash(Some(&self.pin_token.get()), 32, Step::PinAuthChecked);
+            }
+        }
+    }
+
+    fn pin_auth_checked(&self) {
+        if self.command.get() == command::MAKE_CREDENTIAL {
+            self.check_next_excluded();
+        } else if self.list.get().is_some() {
+            self.check_next_allowed();
+        } else {
+            self.start_next_key(0, Step::Discovered);
+        }
+    }
+
+    /// Take the next credential ID of the exclude or allow list.
+    fn next_listed(&self) -> Option<[u8; CREDENTIAL_ID_LEN]> {
+        loop {
+            let (position, remaining) = self.list.get()?;
+            if remaining == 0 {
+                return None;
+            }
+
+            let len = self.request_len.get();
+            let id = self.request.map_or(None, |request| {
+                let mut r = Reader::new(&request[..len], position);
+                let id = read_credential_descriptor(&mut r).ok()?;
+                self.list.set(Some((r.position(), remaining - 1)));
+                let mut listed = [0; CREDENTIAL_ID_LEN];
+                if id.len() == CREDENTIAL_ID_LEN {
+                    listed.copy_from_slice(id);
+                    Some(Some(listed))
+                } else {
+                    // Not created by this authenticator.
+                    Some(None)
+                }
+            })?;
+            if id.is_some() {
+                return id;
+            }
+        }
+    }
+
+    fn check_next_excluded(&self) {
+        match self.next_listed() {
+            Some(id) => self.start_kv_get(Some(&id), Step::ExcludeChecked),
+            None => self.wait_for_user(AfterPresence::CreateCredential),
+        }
+    }
+
+    fn check_next_allowed(&self) {
+        match self.next_listed() {
+            Some(id) => {
+                self.credentials.set([id; MAX_DISCOVERED]);
+                self.start_kv_get(Some(&id), Step::AllowChecked)
+            }
+            None => self.finish(status::NO_CREDENTIALS),
+        }
+    }
+
+    /// The credential last read from the KV store, if it belongs to the
+    /// relying party of the request.
+    fn loaded_credential(&self, id: &[u8; CREDENTIAL_ID_LEN]) -> Option<Credential> {
+        let record = self.record.get();
+        Credential::decode(id, &record[..self.record_len.get()])
+            .filter(|credential| equal(&credential.rp_id_hash, &self.rp_id_hash.get()))
+    }
+
+    fn credentials_found(&self) {
+        if self.credential_count.get() == 0 {
+            self.finish(status::NO_CREDENTIALS);
+        } else if self.user_present.get() {
+            self.wait_for_user(AfterPresence::Assert);
+        } else {
+            self.assert(0);
+        }
+    }
+
+    fn assert(&self, index: usize) {
+        self.next_credential.set(index + 1);
+        let id = self.credentials.get()[index];
+        self.start_kv_get(Some(&id), Step::AssertionLoaded);
+    }
+
+    /// Write the authenticator data followed by the client data hash into
+    /// `data`, and hash them.
+    fn sign_auth_data(&self, attested: bool) {
+        let credential = self.credential.get();
+        let mut public_key = [0; 64];
+        if self
+            .signer
+            .set_private_key(&credential.private_key, &mut public_key)
+            .is_err()
+        {
+            return self.finish(status::OTHER);
+        }
+
+        let mut flags = 0;
+        if self.user_present.get() {
+            flags |= flags::USER_PRESENT;
+        }
+        if self.user_verified.get() {
+            flags |= flags::USER_VERIFIED;
+        }
+        if attested {
+            flags |= flags::ATTESTED_CREDENTIAL_DATA;
+        }
+
+        let len = self.data.map_or(None, |data| {
+            let mut w = Writer::new(data);
+            w.raw(&self.rp_id_hash.get());
+            w.raw(&[flags]);
+            w.raw(&credential.sign_count.to_be_bytes());
+            if attested {
+                w.raw(&self.aaguid);
+                w.raw(&(CREDENTIAL_ID_LEN as u16).to_be_bytes());
+                w.raw(&credential.id);
+                write_cose_key(&mut w, COSE_ALG_ES256, &public_key);
+            }
+            w.raw(&self.client_data_hash.get());
+            w.finish()
+        });
+        match len {
+            Some(len) => {
+                self.auth_data_len.set(len - 32);
+                self.start_hash(None, len, Step::AuthDataHashed);
+            }
+            None => self.finish(status::OTHER),
+        }
+    }
+
+    fn respond_signed(&self) {
+        let mut der = [0; 72];
+        let der_len = match self
+            .signature
+            .map(|signature| der_signature(signature, &mut der))
+        {
+            Some(len) => len,
+            None => return self.finish(status::OTHER),
+        };
+        let credential = self.credential.get();
+        let auth_data_len = self.auth_data_len.get();
+        let first = self.next_credential.get() == 1;
+        let count = self.credential_count.get();
+
+        let len = self.data.map_or(None, |data| {
+            let auth_data = &data[..auth_data_len];
+            let signature = &der[..der_len];
+            if self.command.get() == command::MAKE_CREDENTIAL {
+                self.write_response(|w| {
+                    w.map(3);
+                    w.unsigned(1);
+                    w.text("packed");
+                    w.unsigned(2);
+                    w.bytes(auth_data);
+                    w.unsigned(3);
+                    w.map(2);
+                    w.text("alg");
+                    w.integer(COSE_ALG_ES256);
+                    w.text("sig");
+                    w.bytes(signature);
+                })
+            } else {
+                let user = credential.discoverable && credential.user_id_len > 0;
+                let with_count = first && count > 1;
+                self.write_response(|w| {
+                    w.map(3 + user as usize + with_count as usize);
+                    w.unsigned(1);
+                    w.map(2);
+                    w.text("id");
+                    w.bytes(&credential.id);
+                    w.text("type");
+                    w.text("public-key");
+                    w.unsigned(2);
+                    w.bytes(auth_data);
+                    w.unsigned(3);
+                    w.bytes(signature);
+                    if user {
+                        w.unsigned(4);
+                        w.map(1);
+                        w.text("id");
+                        w.bytes(&credential.user_id[..credential.user_id_len]);
+                    }
+                    if with_count {
+                        w.unsigned(5);
+                        w.unsigned(count as u64);
+                    }
+                })
+            }
+        });
+        self.respond_with(len);
+    }
+
+    fn run_client_pin(&self) -> Result<(), u8> {
+        let pin_set = self.pin_hash.get().is_some();
+        let sub_command = self.sub_command.get();
+
+        match sub_command {
+            client_pin::GET_RETRIES => {
+                let retries = self.pin_retries.get();
+                self.respond(|w| {
+                    w.map(1);
+                    w.unsigned(3);
+                    w.unsigned(retries as u64);
+                });
+                return Ok(());
+            }
+            client_pin::GET_KEY_AGREEMENT => {
+                let key_agreement = self.key_agreement.get();
+                self.respond(|w| {
+                    w.map(1);
+                    w.unsigned(1);
+                    write_cose_key(w, COSE_ALG_ECDH_ES_HKDF_256, &key_agreement);
+                });
+                return Ok(());
+            }
+            client_pin::SET_PIN => {
+                if pin_set {
+                    return Err(status::NOT_ALLOWED);
+                }
+            }
+            _ => {
+                if !pin_set {
+                    return Err(status::PIN_NOT_SET);
+                }
+                if self.pin_retries.get() == 0 {
+                    return Err(status::PIN_BLOCKED);
+                }
+                if self.pin_mismatches.get() >= MAX_PIN_MISMATCHES {
+                    return Err(status::PIN_AUTH_BLOCKED);
+                }
+            }
+        }
+
+        let (peer, secret) = match (self.ecdh_public.take(), self.ecdh_secret.take()) {
+            (Some(peer), Some(secret)) => (peer, secret),
+            (peer, secret) => {
+                peer.map(|peer| self.ecdh_public.replace(peer));
+                secret.map(|secret| self.ecdh_secret.replace(secret));
+                return Err(status::OTHER);
+            }
+        };
+        self.step.set(Step::SharedPoint);
+        self.ecdh
+            .shared_secret(peer, secret)
+            .map_err(|(_, peer, secret)| {
+                self.ecdh_public.replace(peer);
+                self.ecdh_secret.replace(secret);
+                status::OTHER
+            })
+    }
+
+    /// Copy ranges of the request into `data`, returning the total length.
+    fn copy_to_data(&self, ranges: &[Range]) -> usize {
+        self.data.map_or(0, |data| {
+            self.request.map_or(0, |request| {
+                let mut len = 0;
+                for (start, range_len) in ranges {
+                    data[len..len + range_len].copy_from_slice(&request[*start..start + range_len]);
+                    len += range_len;
+                }
+                len
+            })
+        })
+    }
+
+    fn check_pin_hash(&self) {
+        let ok = self.data.map_or(false, |data| {
+            self.pin_hash
+                .get()
+                .map_or(false, |pin_hash| equal(&data[..16], &pin_hash))
+        });
+
+        if !ok {
+            self.pin_mismatches.set(self.pin_mismatches.get() + 1);
+            let status = if self.pin_retries.get() == 0 {
+                status::PIN_BLOCKED
+            } else if self.pin_mismatches.get() >= MAX_PIN_MISMATCHES {
+                status::PIN_AUTH_BLOCKED
+            } else {
+                status::PIN_INVALID
+            };
+            // The platform has to get a new key agreement key before
+            // trying again.
+            if self
+                .start_key_agreement(Step::KeyAgreementRegenerated(status))
+                .is_err()
+            {
+                self.finish(status);
+            }
+            return;
+        }
+
+        self.pin_mismatches.set(0);
+        self.pin_retries.set(MAX_PIN_RETRIES);
+        if self.sub_command.get() == client_pin::CHANGE_PIN {
+            self.decrypt_new_pin();
+        } else {
+            self.start_kv_set(false, Step::PinStateStored);
+        }
+    }
+
+    fn decrypt_new_pin(&self) {
+        let len = self.copy_to_data(&[self.new_pin_enc.get()]);
+        self.start_crypt(false, len, Step::NewPinDecrypted);
+    }
+
+    /// Continue the command after an asynchronous operation finished.
+    fn advance(&self, result: Result<(), ErrorCode>) {
+        let step = self.step.get();
+        match step {
+            Step::InitPinToken | Step::InitKeyAgreement | Step::InitPinState => {
+                return self.advance_init(step, result)
+            }
+            Step::AssertionLoaded | Step::ExcludeChecked | Step::AllowChecked => {}
+            Step::SharedPoint if result.is_err() => return self.finish(status::INVALID_PARAMETER),
+            Step::CredentialStored if result == Err(ErrorCode::NOMEM) => {
+                return self.finish(status::KEY_STORE_FULL)
+            }
+            Step::KeyAgreementRegenerated(status) => return self.finish(status),
+            _ if result.is_err() => return self.finish(status::OTHER),
+            _ => {}
+        }
+
+        let digest = self.digest.map_or([0; 32], |digest| *digest);
+        match step {
+            Step::RpIdHashed => {
+                self.rp_id_hash.set(digest);
+                self.check_pin_auth();
+            }
+
+            Step::PinAuthChecked => match self.pin_auth.get() {
+                PinAuth::Present(pin_auth) if equal(&digest[..16], &pin_auth) => {
+                    self.user_verified.set(true);
+                    self.pin_auth_checked();
+                }
+                _ => self.finish(status::PIN_AUTH_INVALID),
+            },
+
+            Step::ExcludeChecked => {
+                if result.is_ok() && self.loaded_credential(&[0; CREDENTIAL_ID_LEN]).is_some() {
+                    self.wait_for_user(AfterPresence::Finish(status::CREDENTIAL_EXCLUDED));
+                } else if result.is_ok() || result == Err(ErrorCode::NOSUPPORT) {
+                    self.check_next_excluded();
+                } else {
+                    self.finish(status::OTHER);
+                }
+            }
+
+            Step::CredentialRandom => {
+                let random = self.random.get();
+                let mut credential = self.credential.get();
+                credential.id.copy_from_slice(&random[..CREDENTIAL_ID_LEN]);
+                credential
+                    .private_key
+                    .copy_from_slice(&random[CREDENTIAL_ID_LEN..]);
+                credential.rp_id_hash = self.rp_id_hash.get();
+                credential.sign_count = 0;
+
+                let mut public_key = [0; 64];
+                match self
+                    .signer
+                    .set_private_key(&credential.private_key, &mut public_key)
+                {
+                    Ok(()) => {
+                        self.credential.set(credential);
+                        self.start_kv_set(true, Step::CredentialStored);
+                    }
+                    // Not a valid P-256 private key, try new random bytes.
+                    Err(ErrorCode::INVAL) => {
+                        if self
+                            .start_random(CREDENTIAL_ID_LEN + 32, Step::CredentialRandom)
+                            .is_err()
+                        {
+                            self.finish(status::OTHER);
+                        }
+                    }
+                    Err(_) => self.finish(status::OTHER),
+                }
+            }
+
+            Step::CredentialStored => self.sign_auth_data(true),
+
+            Step::AuthDataHashed => {
+                let (hash, signature) = match (self.digest.take(), self.signature.take()) {
+                    (Some(hash), Some(signature)) => (hash, signature),
+                    (hash, signature) => {
+                        hash.map(|hash| self.digest.replace(hash));
+                        signature.map(|signature| self.signature.replace(signature));
+                        return self.finish(status::OTHER);
+                    }
+                };
+                self.step.set(Step::Signed);
+                if let Err((_, hash, signature)) = self.signer.sign(hash, signature) {
+                    self.digest.replace(hash);
+                    self.signature.replace(signature);
+                    self.finish(status::OTHER);
+                }
+            }
+
+            Step::Signed => self.respond_signed(),
+
+            Step::AllowChecked => {
+                let id = self.credentials.get()[0];
+                if result.is_ok() && self.loaded_credential(&id).is_some() {
+                    self.credential_count.set(1);
+                    self.credentials_found();
+                } else if result.is_ok() || result == Err(ErrorCode::NOSUPPORT) {
+                    self.check_next_allowed();
+                } else {
+                    self.finish(status::OTHER);
+                }
+            }
+
+            Step::AssertionLoaded => {
+                let id = self.credentials.get()[self.next_credential.get() - 1];
+                match self.loaded_credential(&id) {
+                    Some(mut credential) if result.is_ok() => {
+                        credential.sign_count = credential.sign_count.wrapping_add(1);
+                        self.credential.set(credential);
+                        self.start_kv_set(true, Step::CounterStored);
+                    }
+                    _ => self.finish(status::OTHER),
+                }
+            }
+
+            Step::CounterStored => self.sign_auth_data(false),
+
+            Step::SharedPoint => {
+                let point = self.ecdh_secret.map_or([0; 32], |secret| *secret);
+                self.data.map(|data| data[..32].copy_from_slice(&point));
+                self.ecdh_secret.map(|secret| secret.fill(0));
+                self.start_hash(None, 32, Step::SharedSecretHashed);
+            }
+
+            Step::SharedSecretHashed => {
+                self.shared_secret.set(digest);
+                match self.sub_command.get() {
+                    client_pin::SET_PIN => {
+                        let len = self.copy_to_data(&[self.new_pin_enc.get()]);
+                        self.start_hash(Some(&digest), len, Step::PinMacChecked);
+                    }
+                    client_pin::CHANGE_PIN => {
+                        let len =
+                            self.copy_to_data(&[self.new_pin_enc.get(), self.pin_hash_enc.get()]);
+                        self.start_hash(Some(&digest), len, Step::PinMacChecked);
+                    }
+                    _ => {
+                        self.pin_retries.set(self.pin_retries.get() - 1);
+                        self.start_kv_set(false, Step::PinRetriesStored);
+                    }
+                }
+            }
+
+            Step::PinMacChecked => {
+                let ok = match self.pin_auth.get() {
+                    PinAuth::Present(pin_auth) => equal(&digest[..16], &pin_auth),
+                    _ => false,
+                };
+                if !ok {
+                    self.finish(status::PIN_AUTH_INVALID);
+                } else if self.sub_command.get() == client_pin::SET_PIN {
+                    self.decrypt_new_pin();
+                } else {
+                    self.pin_retries.set(self.pin_retries.get() - 1);
+                    self.start_kv_set(false, Step::PinRetriesStored);
+                }
+            }
+
+            Step::PinRetriesStored => {
+                let len = self.copy_to_data(&[self.pin_hash_enc.get()]);
+                self.start_crypt(false, len, Step::PinHashDecrypted);
+            }
+
+            Step::PinHashDecrypted => self.check_pin_hash(),
+
+            Step::NewPinDecrypted => {
+                let pin_len = self.data.map_or(0, |data| {
+                    data[..64].iter().position(|b| *b == 0).unwrap_or(64)
+                });
+                if !(MIN_PIN_LEN..64).contains(&pin_len) {
+                    return self.finish(status::PIN_POLICY_VIOLATION);
+                }
+                self.start_hash(None, pin_len, Step::NewPinHashed);
+            }
+
+            Step::NewPinHashed => {
+                let mut pin_hash = [0; 16];
+                pin_hash.copy_from_slice(&digest[..16]);
+                self.pin_hash.set(Some(pin_hash));
+                self.pin_retries.set(MAX_PIN_RETRIES);
+                self.pin_mismatches.set(0);
+                self.start_kv_set(false, Step::PinStateStored);
+            }
+
+            Step::PinStateStored => {
+                if self.sub_command.get() == client_pin::GET_PIN_TOKEN {
+                    self.data
+                        .map(|data| data[..PIN_TOKEN_LEN].copy_from_slice(&self.pin_token.get()));
+                    self.start_crypt(true, PIN_TOKEN_LEN, Step::PinTokenEncrypted);
+                } else {
+                    self.finish(status::OK);
+                }
+            }
+
+            Step::PinTokenEncrypted => {
+                let mut token = [0; PIN_TOKEN_LEN];
+                self.data
+                    .map(|data| token.copy_from_slice(&data[..PIN_TOKEN_LEN]));
+                self.respond(|w| {
+                    w.map(1);
+                    w.unsigned(2);
+                    w.bytes(&token);
+                });
+            }
+
+            Step::ResetDeleted => self.start_next_key(0, Step::ResetScanned),
+
+            Step::ResetPinToken => {
+                let mut token = [0; PIN_TOKEN_LEN];
+                token.copy_from_slice(&self.random.get()[..PIN_TOKEN_LEN]);
+                self.pin_token.set(token);
+                self.finish(status::OK);
+            }
+
+            _ => {}
+        }
+    }
+
+    fn advance_init(&self, step: Step, result: Result<(), ErrorCode>) {
+        match (step, result) {
+            (Step::InitPinToken, Ok(())) => {
+                let mut token = [0; PIN_TOKEN_LEN];
+                token.copy_from_slice(&self.random.get()[..PIN_TOKEN_LEN]);
+                self.pin_token.set(token);
+                self.random.set([0; 48]);
+                if self.start_key_agreement(Step::InitKeyAgreement).is_err() {
+                    self.step.set(Step::Idle);
+                }
+            }
+            (Step::InitKeyAgreement, Ok(())) => {
+                let (key, value) = match (self.kv_key_for(None), self.kv_value.take()) {
+                    (Some(key), Some(value)) => (key, value),
+                    (key, value) => {
+                        key.map(|key| self.kv_key.replace(key.take()));
+                        value.map(|value| self.kv_value.replace(value));
+                        return self.step.set(Step::Idle);
+                    }
+                };
+                self.step.set(Step::InitPinState);
+                if let Err((key, value, _)) =
+                    self.kv
+                        .get(key, SubSliceMut::new(value), self.storage_permissions)
+                {
+                    self.kv_key.replace(key.take());
+                    self.kv_value.replace(value.take());
+                    self.step.set(Step::Idle);
+                }
+            }
+            (Step::InitPinState, Ok(())) => {
+                let record = self.record.get();
+                if self.record_len.get() >= PIN_STATE_LEN && record[0] == RECORD_VERSION {
+                    self.pin_retries.set(record[1]);
+                    if record[2] == 1 {
+                        let mut pin_hash = [0; 16];
+                        pin_hash.copy_from_slice(&record[3..PIN_STATE_LEN]);
+                        self.pin_hash.set(Some(pin_hash));
+                    }
+                }
+                self.step.set(Step::Idle);
+                self.ready.set(true);
+            }
+            // No PIN has been set yet.
+            (Step::InitPinState, Err(ErrorCode::NOSUPPORT)) => {
+                self.step.set(Step::Idle);
+                self.ready.set(true);
+            }
+            // Stay unusable if the state couldn't be read, rather than
+            // allowing a new PIN to be set.
+            _ => self.step.set(Step::Idle),
+        }
+    }
+
+    /// Handle a key found while searching for discoverable credentials or
+    /// deleting all credentials.
+    fn key_found(
+        &self,
+        step: Step,
+        position: usize,
+        mut key: SubSliceMut<'static, u8>,
+        value: &[u8],
+    ) {
+        let is_credential = key.len() == CREDENTIAL_PREFIX.len() + CREDENTIAL_ID_LEN
+            && key.as_slice().starts_with(CREDENTIAL_PREFIX);
+
+        if step == Step::ResetScanned {
+            if key.as_slice().starts_with(KV_PREFIX) {
+                self.step.set(Step::ResetDeleted);
+                if let Err((key, _)) = self.kv.delete(key, self.storage_permissions) {
+                    self.kv_key.replace(key.take());
+                    self.finish(status::OTHER);
+                }
+            } else {
+                self.kv_key.replace(key.take());
+                self.start_next_key(position, step);
+            }
+            return;
+        }
+
+        if is_credential {
+            let id = &key.as_slice()[CREDENTIAL_PREFIX.len()..];
+            let found = Credential::decode(id, value).filter(|credential| {
+                credential.discoverable && equal(&credential.rp_id_hash, &self.rp_id_hash.get())
+            });
+            if let Some(credential) = found {
+                let count = self.credential_count.get();
+                let mut credentials = self.credentials.get();
+                credentials[count] = credential.id;
+                self.credentials.set(credentials);
+                self.credential_count.set(count + 1);
+            }
+        }
+        self.kv_key.replace(key.take());
+
+        if self.credential_count.get() == MAX_DISCOVERED {
+            self.credentials_found();
+        } else {
+            self.start_next_key(position, step);
+        }
+    }
+}
+
+impl<
+        'a,
+        A: Alarm<'a>,
+        D: digest::Digest<'a, 32> + digest::Sha256,
+        H: digest::Digest<'a, 32> + digest::HmacSha256,
+        S: SignatureSign<'a, 32, 64> + SetPrivateKey<32, 64>,
+        E: Ecdh<'a, 64, 32>,
+        C: AES128<'a> + AES128CBC,
+    > CtapCommand<'a> for Authenticator<'a, A, D, H, S, E, C>
+{
+    fn set_client(&self, client: &'a dyn CtapCommandClient) {
+        self.client.set(client);
+    }
+
+    fn process(
+        &self,
+        request: &'static mut [u8],
+        len: usize,
+        response: &'static mut [u8],
+    ) -> Result<(), (ErrorCode, &'static mut [u8], &'static mut [u8])> {
+        if !self.ready.get() || self.step.get() != Step::Idle {
+            return Err((ErrorCode::BUSY, request, response));
+        }
+        if len == 0 || len > request.len() || response.len() < 2 {
+            return Err((ErrorCode::SIZE, request, response));
+        }
+
+        self.request.replace(request);
+        self.request_len.set(len);
+        self.response.replace(response);
+        if let Err(status) = self.run_command() {
+            self.finish(status);
+        }
+        Ok(())
+    }
+
+    fn cancel(&self) {
+        if let Step::WaitForUser(_) = self.step.get() {
+            self.stop_waiting();
+            self.finish(status::KEEPALIVE_CANCEL);
+        }
+    }
+
+    fn waiting_for_user(&self) -> bool {
+        matches!(self.step.get(), Step::WaitForUser(_))
+    }
+}
+
+impl<
+        'a,
+        A: Alarm<'a>,
+        D: digest::Digest<'a, 32> + digest::Sha256,
+        H: digest::Digest<'a, 32> + digest::HmacSha256,
+        S: SignatureSign<'a, 32, 64> + SetPrivateKey<32, 64>,
+        E: Ecdh<'a, 64, 32>,
+        C: AES128<'a> + AES128CBC,
+    > DeferredCallClient for Authenticator<'a, A, D, H, S, E, C>
+{
+    fn register(&'static self) {
+        self.deferred_call.register(self);
+    }
+
+    fn handle_deferred_call(&self) {
+        if self.step.get() != Step::Responding {
+            return;
+        }
+        self.step.set(Step::Idle);
+        if let (Some(request), Some(response)) = (self.request.take(), self.response.take()) {
+            let len = self.response_len.get();
+            self.client
+                .map(move |client| client.response_ready(request, response, len));
+        }
+    }
+}
+
+impl<
+        'a,
+        A: Alarm<'a>,
+        D: digest::Digest<'a, 32> + digest::Sha256,
+        H: digest::Digest<'a, 32> + digest::HmacSha256,
+        S: SignatureSign<'a, 32, 64> + SetPrivateKey<32, 64>,
+        E: Ecdh<'a, 64, 32>,
+        C: AES128<'a> + AES128CBC,
+    > digest::ClientData<32> for Authenticator<'a, A, D, H, S, E, C>
+{
+    fn add_data_done(&self, _result: Result<(), ErrorCode>, _data: SubSlice<'static, u8>) {}
+
+    fn add_mut_data_done(&self, result: Result<(), ErrorCode>, data: SubSliceMut<'static, u8>) {
+        self.data.replace(data.take());
+        if result.is_err() {
+            return self.advance(result);
+        }
+
+        let digest = match self.digest.take() {
+            Some(digest) => digest,
+            None => return self.advance(Err(ErrorCode::FAIL)),
+        };
+        let run = if self.using_hmac.get() {
+            self.hmac.run(digest)
+        } else {
+            self.sha.run(digest)
+        };
+        if let Err((e, digest)) = run {
+            self.digest.replace(digest);
+            self.advance(Err(e));
+        }
+    }
+}
+
+impl<
+        'a,
+        A: Alarm<'a>,
+        D: digest::Digest<'a, 32> + digest::Sha256,
+        H: digest::Digest<'a, 32> + digest::HmacSha256,
+        S: SignatureSign<'a, 32, 64> + SetPrivateKey<32, 64>,
+        E: Ecdh<'a, 64, 32>,
+        C: AES128<'a> + AES128CBC,
+    > digest::ClientHash<32> for Authenticator<'a, A, D, H, S, E, C>
+{
+    fn hash_done(&self, result: Result<(), ErrorCode>, digest: &'static mut [u8; 32]) {
+        self.digest.replace(digest);
+        self.advance(result);
+    }
+}
+
+impl<
+        'a,
+        A: Alarm<'a>,
+        D: digest::Digest<'a, 32> + digest::Sha256,
+        H: digest::Digest<'a, 32> + digest::HmacSha256,
+        S: SignatureSign<'a, 32, 64> + SetPrivateKey<32, 64>,
+        E: Ecdh<'a, 64, 32>,
+        C: AES128<'a> + AES128CBC,
+    > digest::ClientVerify<32> for Authenticator<'a, A, D, H, S, E, C>
+{
+    fn verification_done(&self, _result: Result<bool, ErrorCode>, compare: &'static mut [u8; 32]) {
+        self.digest.replace(compare);
+    }
+}
+
+impl<
+        'a,
+        A: Alarm<'a>,
+        D: digest::Digest<'a, 32> + digest::Sha256,
+        H: digest::Digest<'a, 32> + digest::HmacSha256,
+        S: SignatureSign<'a, 32, 64> + SetPrivateKey<32, 64>,
+        E: Ecdh<'a, 64, 32>,
+        C: AES128<'a> + AES128CBC,
+    > ClientSign<32, 64> for Authenticator<'a, A, D, H, S, E, C>
+{
+    fn signing_done(
+        &self,
+        result: Result<(), ErrorCode>,
+        hash: &'static mut [u8; 32],
+        signature: &'static mut [u8; 64],
+    ) {
+        self.digest.replace(hash);
+        self.signature.replace(signature);
+        self.advance(result);
+    }
+}
+
+impl<
+        'a,
+        A: Alarm<'a>,
+        D: digest::Digest<'a, 32> + digest::Sha256,
+        H: digest::Digest<'a, 32> + digest::HmacSha256,
+        S: SignatureSign<'a, 32, 64> + SetPrivateKey<32, 64>,
+        E: Ecdh<'a, 64, 32>,
+        C: AES128<'a> + AES128CBC,
+    > EcdhClient<64, 32> for Authenticator<'a, A, D, H, S, E, C>
+{
+    fn generation_done(&self, result: Result<(), ErrorCode>, public_key: &'static mut [u8; 64]) {
+        if result.is_ok() {
+            self.key_agreement.set(*public_key);
+        }
+        self.ecdh_public.replace(public_key);
+        self.advance(result);
+    }
+
+    fn shared_secret_done(
+        &self,
+        result: Result<(), ErrorCode>,
+        peer_public_key: &'static mut [u8; 64],
+        shared_secret: &'static mut [u8; 32],
+    ) {
+        self.ecdh_public.replace(peer_public_key);
+        self.ecdh_secret.replace(shared_secret);
+        self.advance(result);
+    }
+}
+
+impl<
+        'a,
+        A: Alarm<'a>,
+        D: digest::Digest<'a, 32> + digest::Sha256,
+        H: digest::Digest<'a, 32> + digest::HmacSha256,
+        S: SignatureSign<'a, 32, 64> + SetPrivateKey<32, 64>,
+        E: Ecdh<'a, 64, 32>,
+        C: AES128<'a> + AES128CBC,
+    > symmetric_encryption::Client<'a> for Authenticator<'a, A, D, H, S, E, C>
+{
+    fn crypt_done(&'a self, _source: Option<&'static mut [u8]>, dest: &'static mut [u8]) {
+        self.data.replace(dest);
+        self.advance(Ok(()));
+    }
+}
+
+impl<
+        'a,
+        A: Alarm<'a>,
+        D: digest::Digest<'a, 32> + digest::Sha256,
+        H: digest::Digest<'a, 32> + digest::HmacSha256,
+        S: SignatureSign<'a, 32, 64> + SetPrivateKey<32, 64>,
+        E: Ecdh<'a, 64, 32>,
+        C: AES128<'a> + AES128CBC,
+    > rng::Client for Authenticator<'a, A, D, H, S, E, C>
+{
+    fn randomness_available(
+        &self,
+        randomness: &mut dyn Iterator<Item = u32>,
+        error: Result<(), ErrorCode>,
+    ) -> rng::Continue {
+        if error.is_err() {
+            self.advance(error);
+            return rng::Continue::Done;
+        }
+
+        let mut random = self.random.get();
+        let mut len = self.random_len.get();
+        while len < self.random_wanted.get() {
+            match randomness.next() {
+                Some(word) => {
+                    random[len..len + 4].copy_from_slice(&word.to_le_bytes());
+                    len += 4;
+                }
+                None => break,
+            }
+        }
+        self.random.set(random);
+        self.random_len.set(len);
+
+        if len < self.random_wanted.get() {
+            return rng::Continue::More;
+        }
+        self.advance(Ok(()));
+        rng::Continue::Done
+    }
+}
+
+impl<
+        'a,
+        A: Alarm<'a>,
+        D: digest::Digest<'a, 32> + digest::Sha256,
+        H: digest::Digest<'a, 32> + digest::HmacSha256,
+        S: SignatureSign<'a, 32, 64> + SetPrivateKey<32, 64>,
+        E: Ecdh<'a, 64, 32>,
+        C: AES128<'a> + AES128CBC,
+    > kv::KVClient for Authenticator<'a, A, D, H, S, E, C>
+{
+    fn get_complete(
+        &self,
+        result: Result<(), ErrorCode>,
+        key: SubSliceMut<'static, u8>,
+        mut value: SubSliceMut<'static, u8>,
+    ) {
+        self.kv_key.replace(key.take());
+
+        let mut record = [0; RECORD_LEN];
+        let len = if result.is_ok() {
+            let stored = value.as_slice();
+            let len = stored.len().min(RECORD_LEN);
+            record[..len].copy_from_slice(&stored[..len]);
+            len
+        } else {
+            0
+        };
+        self.record.set(record);
+        self.record_len.set(len);
+
+        let value = value.take();
+        value.fill(0);
+        self.kv_value.replace(value);
+        self.advance(result);
+    }
+
+    fn set_complete(
+        &self,
+        result: Result<(), ErrorCode>,
+        key: SubSliceMut<'static, u8>,
+        value: SubSliceMut<'static, u8>,
+    ) {
+        self.kv_key.replace(key.take());
+        let value = value.take();
+        value.fill(0);
+        self.kv_value.replace(value);
+        self.advance(result);
+    }
+
+    fn add_complete(
+        &self,
+        result: Result<(), ErrorCode>,
+        key: SubSliceMut<'static, u8>,
+        value: SubSliceMut<'static, u8>,
+    ) {
+        self.set_complete(result, key, value);
+    }
+
+    fn update_complete(
+        &self,
+        result: Result<(), ErrorCode>,
+        key: SubSliceMut<'static, u8>,
+        value: SubSliceMut<'static, u8>,
+    ) {
+        self.set_complete(result, key, value);
+    }
+
+    fn delete_complete(&self, result: Result<(), ErrorCode>, key: SubSliceMut<'static, u8>) {
+        self.kv_key.replace(key.take());
+        self.advance(result);
+    }
+
+    fn next_key_complete(
+        &self,
+        result: Result<(), ErrorCode>,
+        position: usize,
+        key: SubSliceMut<'static, u8>,
+        mut value: SubSliceMut<'static, u8>,
+    ) {
+        let step = self.step.get();
+        let mut record = [0; RECORD_LEN];
+        let len = value.len().min(RECORD_LEN);
+        record[..len].copy_from_slice(&value.as_slice()[..len]);
+        let value = value.take();
+        value.fill(0);
+        self.kv_value.replace(value);
+
+        match result {
+            // Skip objects that don't fit in the buffers, they aren't ours.
+            Ok(()) | Err(ErrorCode::SIZE) => self.key_found(step, position, key, &record[..len]),
+            // The end of the store.
+            Err(ErrorCode::NOSUPPORT) => {
+                self.kv_key.replace(key.take());
+                if step == Step::ResetScanned {
+                    self.pin_hash.set(None);
+                    self.pin_retries.set(MAX_PIN_RETRIES);
+                    self.pin_mismatches.set(0);
+                    if self
+                        .start_random(PIN_TOKEN_LEN, Step::ResetPinToken)
+                        .is_err()
+                    {
+                        self.finish(status::OTHER);
+                    }
+                } else {
+                    self.credentials_found();
+                }
+            }
+            Err(_) => {
+                self.kv_key.replace(key.take());
+                self.finish(status::OTHER);
+            }
+        }
+    }
+}
+
+impl<
+        'a,
+        A: Alarm<'a>,
+        D: digest::Digest<'a, 32> + digest::Sha256,
+        H: digest::Digest<'a, 32> + digest::HmacSha256,
+        S: SignatureSign<'a, 32, 64> + SetPrivateKey<32, 64>,
+        E: Ecdh<'a, 64, 32>,
+        C: AES128<'a> + AES128CBC,
+    > gpio::Client for Authenticator<'a, A, D, H, S, E, C>
+{
+    fn fired(&self) {
+        if let Step::WaitForUser(after) = self.step.get() {
+            let pressed = self.user_presence.map_or(false, |(pin, mode)| {
+                pin.read_activation(mode) == gpio::ActivationState::Active
+            });
+            if pressed {
+                self.stop_waiting();
+                self.user_confirmed(after);
+            }
+        }
+    }
+}
+
+impl<
+        'a,
+        A: Alarm<'a>,
+        D: digest::Digest<'a, 32> + digest::Sha256,
+        H: digest::Digest<'a, 32> + digest::HmacSha256,
+        S: SignatureSign<'a, 32, 64> + SetPrivateKey<32, 64>,
+        E: Ecdh<'a, 64, 32>,
+        C: AES128<'a> + AES128CBC,
+    > AlarmClient for Authenticator<'a, A, D, H, S, E, C>
+{
+    fn alarm(&self) {
+        if let Step::WaitForUser(_) = self.step.get() {
+            self.stop_waiting();
+            self.finish(status::USER_ACTION_TIMEOUT);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use std::vec::Vec;
+
+    const HASH: [u8; 32] = [0x42; 32];
+
+    /// Encode a request for `command`, with the parameters written by `f`.
+    fn encode<F: FnOnce(&mut Writer)>(command: u8, f: F) -> Vec<u8> {
+        let mut buf = [0; 512];
+        let mut w = Writer::new(&mut buf);
+        w.raw(&[command]);
+        f(&mut w);
+        let len = w.finish().unwrap();
+        buf[..len].to_vec()
+    }
+
+    fn make_credential_params(w: &mut Writer, user_id: &[u8], alg: i64) {
+        w.unsigned(1);
+        w.bytes(&HASH);
+        w.unsigned(2);
+        w.map(1);
+        w.text("id");
+        w.text("example.com");
+        w.unsigned(3);
+        w.map(1);
+        w.text("id");
+        w.bytes(user_id);
+        w.unsigned(4);
+        w.array(1);
+        w.map(2);
+        w.text("alg");
+        w.integer(alg);
+        w.text("type");
+        w.text("public-key");
+    }
+
+    /// A makeCredential request with the required parameters and `options`.
+    fn make_credential(options: &[(&str, bool)]) -> Vec<u8> {
+        encode(command::MAKE_CREDENTIAL, |w| {
+            w.map(if options.is_empty() { 4 } else { 5 });
+            make_credential_params(w, b"user", COSE_ALG_ES256);
+            if !options.is_empty() {
+                w.unsigned(7);
+                w.map(options.len());
+                for (key, value) in options {
+                    w.text(key);
+                    w.bool(*value);
+                }
+            }
+        })
+    }
+
+    /// A getAssertion request with the required parameters and `options`.
+    fn get_assertion(options: &[(&str, bool)]) -> Vec<u8> {
+        encode(command::GET_ASSERTION, |w| {
+            w.map(if options.is_empty() { 2 } else { 3 });
+            w.unsigned(1);
+            w.text("example.com");
+            w.unsigned(2);
+            w.bytes(&HASH);
+            if !options.is_empty() {
+                w.unsigned(5);
+                w.map(options.len());
+                for (key, value) in options {
+                    w.text(key);
+                    w.bool(*value);
+                }
+            }
+        })
+    }
+
+    fn error<T>(result: Result<T, u8>) -> u8 {
+        result.err().expect("request should be rejected")
+    }
+
+    #[test]
+    fn make_credential_valid() {
+        let request = make_credential(&[("rk", true)]);
+        let parameters = parse_make_credential(&request).unwrap();
+        assert_eq!(parameters.client_data_hash, HASH);
+        let (start, len) = parameters.rp_id;
+        assert_eq!(&request[start..start + len], b"example.com");
+        assert!(parameters.list.is_none());
+        assert!(parameters.pin_auth == PinAuth::Absent);
+        assert!(parameters.credential.discoverable);
+        assert_eq!(
+            &parameters.credential.user_id[..parameters.credential.user_id_len],
+            b"user"
+        );
+    }
+
+    #[test]
+    fn make_credential_missing_parameter() {
+        // Each request leaves out one of the required parameters.
+        for missing in 1..=4 {
+            let request = encode(command::MAKE_CREDENTIAL, |w| {
+                let mut buf = [0; 256];
+                let mut params = Writer::new(&mut buf);
+                make_credential_params(&mut params, b"user", COSE_ALG_ES256);
+                let len = params.finish().unwrap();
+                // Re-encode the parameters, skipping the missing one.
+                let mut r = Reader::new(&buf[..len], 0);
+                w.map(3);
+                for key in 1..=4 {
+                    assert_eq!(r.unsigned().unwrap(), key);
+                    let start = r.position();
+                    r.skip().unwrap();
+                    if key != missing {
+                        w.unsigned(key);
+                        w.raw(&buf[start..r.position()]);
+                    }
+                }
+            });
+            assert_eq!(
+                error(parse_make_credential(&request)),
+                status::MISSING_PARAMETER
+            );
+        }
+    }
+
+    #[test]
+    fn make_credential_unsupported_algorithm() {
+        // RS256.
+        let request = encode(command::MAKE_CREDENTIAL, |w| {
+            w.map(4);
+            make_credential_params(w, b"user", -257);
+        });
+        assert_eq!(
+            error(parse_make_credential(&request)),
+            status::UNSUPPORTED_ALGORITHM
+        );
+    }
+
+    #[test]
+    fn make_credential_options() {
+        let request = make_credential(&[("uv", true)]);
+        assert_eq!(
+            error(parse_make_credential(&request)),
+            status::UNSUPPORTED_OPTION
+        );
+        let request = make_credential(&[("up", false)]);
+        assert_eq!(
+            error(parse_make_credential(&request)),
+            status::INVALID_OPTION
+        );
+        let request = make_credential(&[("up", true), ("uv", false)]);
+        assert!(parse_make_credential(&request).is_ok());
+    }
+
+    #[test]
+    fn make_credential_user_id_too_long() {
+        let request = encode(command::MAKE_CREDENTIAL, |w| {
+            w.map(4);
+            make_credential_params(w, &[0; MAX_USER_ID_LEN + 1], COSE_ALG_ES256);
+        });
+        assert_eq!(
+            error(parse_make_credential(&request)),
+            status::INVALID_LENGTH
+        );
+    }
+
+    #[test]
+    fn make_credential_pin_auth() {
+        let pin_auth = |auth: &[u8], protocol: Option<u64>| {
+            encode(command::MAKE_CREDENTIAL, |w| {
+                w.map(if protocol.is_some() { 6 } else { 5 });
+                make_credential_params(w, b"user", COSE_ALG_ES256);
+                w.unsigned(8);
+                w.bytes(auth);
+                if let Some(protocol) = protocol {
+                    w.unsigned(9);
+                    w.unsigned(protocol);
+                }
+            })
+        };
+
+        let parameters = parse_make_credential(&pin_auth(&[1; 16], Some(1))).unwrap();
+        assert!(parameters.pin_auth == PinAuth::Present([1; 16]));
+        let parameters = parse_make_credential(&pin_auth(&[], None)).unwrap();
+        assert!(parameters.pin_auth == PinAuth::Empty);
+
+        for request in [
+            pin_auth(&[1; 16], None),
+            pin_auth(&[1; 16], Some(2)),
+            pin_auth(&[1; 15], Some(1)),
+        ] {
+            assert_eq!(
+                error(parse_make_credential(&request)),
+                status::PIN_AUTH_INVALID
+            );
+        }
+    }
+
+    #[test]
+    fn get_assertion_valid() {
+        let parameters = parse_get_assertion(&get_assertion(&[])).unwrap();
+        assert_eq!(parameters.client_data_hash, HASH);
+        assert!(parameters.user_presence);
+        let parameters = parse_get_assertion(&get_assertion(&[("up", false)])).unwrap();
+        assert!(!parameters.user_presence);
+    }
+
+    #[test]
+    fn get_assertion_missing_parameter() {
+        let request = encode(command::GET_ASSERTION, |w| {
+            w.map(1);
+            w.unsigned(2);
+            w.bytes(&HASH);
+        });
+        assert_eq!(
+            error(parse_get_assertion(&request)),
+            status::MISSING_PARAMETER
+        );
+        let request = encode(command::GET_ASSERTION, |w| {
+            w.map(1);
+            w.unsigned(1);
+            w.text("example.com");
+        });
+        assert_eq!(
+            error(parse_get_assertion(&request)),
+            status::MISSING_PARAMETER
+        );
+    }
+
+    #[test]
+    fn get_assertion_options() {
+        assert_eq!(
+            error(parse_get_assertion(&get_assertion(&[("uv", true)]))),
+            status::UNSUPPORTED_OPTION
+        );
+        assert_eq!(
+            error(parse_get_assertion(&get_assertion(&[("rk", false)]))),
+            status::INVALID_OPTION
+        );
+    }
+
+    #[test]
+    fn get_assertion_client_data_hash_length() {
+        let request = encode(command::GET_ASSERTION, |w| {
+            w.map(2);
+            w.unsigned(1);
+            w.text("example.com");
+            w.unsigned(2);
+            w.bytes(&HASH[..31]);
+        });
+        assert_eq!(error(parse_get_assertion(&request)), status::INVALID_LENGTH);
+    }
+
+    #[test]
+    fn get_assertion_allow_list() {
+        let allow_list = |id: bool| {
+            encode(command::GET_ASSERTION, |w| {
+                w.map(3);
+                w.unsigned(1);
+                w.text("example.com");
+                w.unsigned(2);
+                w.bytes(&HASH);
+                w.unsigned(3);
+                w.array(2);
+                for _ in 0..2 {
+                    w.map(if id { 2 } else { 1 });
+                    if id {
+                        w.text("id");
+                        w.bytes(&[7; CREDENTIAL_ID_LEN]);
+                    }
+                    w.text("type");
+                    w.text("public-key");
+                }
+            })
+        };
+
+        let request = allow_list(true);
+        let (position, len) = parse_get_assertion(&request).unwrap().list.unwrap();
+        assert_eq!(len, 2);
+        let mut r = Reader::new(&request, position);
+        assert_eq!(
+            read_credential_descriptor(&mut r).unwrap(),
+            &[7; CREDENTIAL_ID_LEN]
+        );
+
+        assert_eq!(
+            error(parse_get_assertion(&allow_list(false))),
+            status::MISSING_PARAMETER
+        );
+    }
+
+    #[test]
+    fn invalid_cbor() {
+        // Truncated.
+        let request = make_credential(&[]);
+        assert_eq!(
+            error(parse_make_credential(&request[..request.len() - 1])),
+            status::INVALID_CBOR
+        );
+        // Not a map.
+        let request = encode(command::GET_ASSERTION, |w| w.array(0));
+        assert_eq!(
+            error(parse_get_assertion(&request)),
+            status::CBOR_UNEXPECTED_TYPE
+        );
+        // The relying party ID is bytes instead of text.
+        let request = encode(command::GET_ASSERTION, |w| {
+            w.map(2);
+            w.unsigned(1);
+            w.bytes(b"example.com");
+            w.unsigned(2);
+            w.bytes(&HASH);
+        });
+        assert_eq!(
+            error(parse_get_assertion(&request)),
+            status::CBOR_UNEXPECTED_TYPE
+        );
+    }
+}
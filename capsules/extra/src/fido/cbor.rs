@@ -0,0 +1,409 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Minimal CBOR encoding and decoding for CTAP2 messages.
+//!
+//! This only covers what CTAP2 uses: integers, byte and text strings, arrays,
+//! maps and booleans, all with definite lengths. Nothing is allocated, the
+//! `Reader` returns slices of the message and the `Writer` writes straight
+//! into the output buffer.
+
+/// A CBOR decoding error.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Error {
+    /// The message is not valid CBOR, or uses a feature CTAP2 doesn't allow
+    /// such as indefinite lengths.
+    Invalid,
+    /// The value has a different type than expected.
+    UnexpectedType,
+    /// Values are nested deeper than `MAX_DEPTH`.
+    TooDeep,
+}
+
+/// The deepest nesting of arrays and maps that `Reader::skip()` accepts.
+pub const MAX_DEPTH: usize = 4;
+
+const UNSIGNED: u8 = 0;
+const NEGATIVE: u8 = 1;
+const BYTES: u8 = 2;
+const TEXT: u8 = 3;
+const ARRAY: u8 = 4;
+const MAP: u8 = 5;
+const TAG: u8 = 6;
+const SIMPLE: u8 = 7;
+
+const FALSE: u64 = 20;
+const TRUE: u64 = 21;
+
+pub struct Reader<'b> {
+    buf: &'b [u8],
+    pos: usize,
+}
+
+impl<'b> Reader<'b> {
+    /// Decode `buf` starting at `pos`.
+    pub fn new(buf: &'b [u8], pos: usize) -> Self {
+        Reader { buf, pos }
+    }
+
+    /// The offset of the next value in the buffer.
+    pub fn position(&self) -> usize {
+        self.pos
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pos >= self.buf.len()
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'b [u8], Error> {
+        let end = self.pos.checked_add(len).ok_or(Error::Invalid)?;
+        let bytes = self.buf.get(self.pos..end).ok_or(Error::Invalid)?;
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    /// The major type of the next value.
+    pub fn peek_type(&self) -> Option<u8> {
+        self.buf.get(self.pos).map(|initial| initial >> 5)
+    }
+
+    /// Read the major type and argument of the next value.
+    fn header(&mut self) -> Result<(u8, u64), Error> {
+        let initial = self.take(1)?[0];
+        let argument = match initial & 0x1f {
+            short @ 0..=23 => short as u64,
+            24 => self.take(1)?[0] as u64,
+            25 => {
+                let b = self.take(2)?;
+                u16::from_be_bytes([b[0], b[1]]) as u64
+            }
+            26 => {
+                let b = self.take(4)?;
+                u32::from_be_bytes([b[0], b[1], b[2], b[3]]) as u64
+            }
+            27 => {
+                let mut b = [0; 8];
+                b.copy_from_slice(self.take(8)?);
+                u64::from_be_bytes(b)
+            }
+            _ => return Err(Error::Invalid),
+        };
+        Ok((initial >> 5, argument))
+    }
+
+    fn expect(&mut self, major: u8) -> Result<u64, Error> {
+        if self.peek_type() != Some(major) {
+            return match self.peek_type() {
+                Some(_) => Err(Error::UnexpectedType),
+                None => Err(Error::Invalid),
+            };
+        }
+        self.header().map(|(_, argument)| argument)
+    }
+
+    pub fn unsigned(&mut self) -> Result<u64, Error> {
+        self.expect(UNSIGNED)
+    }
+
+    /// Read an unsigned or negative integer.
+    pub fn integer(&mut self) -> Result<i64, Error> {
+        match self.peek_type() {
+            Some(UNSIGNED) => {
+                let value = self.unsigned()?;
+                i64::try_from(value).map_err(|_| Error::Invalid)
+            }
+            Some(NEGATIVE) => {
+                let value = self.expect(NEGATIVE)?;
+                let value = i64::try_from(value).map_err(|_| Error::Invalid)?;
+                Ok(-1 - value)
+            }
+            Some(_) => Err(Error::UnexpectedType),
+            None => Err(Error::Invalid),
+        }
+    }
+
+    pub fn bytes(&mut self) -> Result<&'b [u8], Error> {
+        let len = self.expect(BYTES)?;
+        self.take(usize::try_from(len).map_err(|_| Error::Invalid)?)
+    }
+
+    pub fn text(&mut self) -> Result<&'b str, Error> {
+        let len = self.expect(TEXT)?;
+        let text = self.take(usize::try_from(len).map_err(|_| Error::Invalid)?)?;
+        core::str::from_utf8(text).map_err(|_| Error::Invalid)
+    }
+
+    /// Read the header of an array and return the number of items.
+    pub fn array(&mut self) -> Result<usize, Error> {
+        let len = self.expect(ARRAY)?;
+        usize::try_from(len).map_err(|_| Error::Invalid)
+    }
+
+    /// Read the header of a map and return the number of entries.
+    pub fn map(&mut self) -> Result<usize, Error> {
+        let len = self.expect(MAP)?;
+        usize::try_from(len).map_err(|_| Error::Invalid)
+    }
+
+    pub fn bool(&mut self) -> Result<bool, Error> {
+        match self.expect(SIMPLE)? {
+            FALSE => Ok(false),
+            TRUE => Ok(true),
+            _ => Err(Error::UnexpectedType),
+        }
+    }
+
+    /// Skip the next value, including everything nested in it.
+    pub fn skip(&mut self) -> Result<(), Error> {
+        self.skip_nested(0)
+    }
+
+    fn skip_nested(&mut self, depth: usize) -> Result<(), Error> {
+        if depth > MAX_DEPTH {
+            return Err(Error::TooDeep);
+        }
+
+        let (major, argument) = self.header()?;
+        let len = usize::try_from(argument).map_err(|_| Error::Invalid)?;
+        match major {
+            UNSIGNED | NEGATIVE | SIMPLE => Ok(()),
+            BYTES | TEXT => self.take(len).map(|_| ()),
+            ARRAY => (0..len).try_for_each(|_| self.skip_nested(depth + 1)),
+            MAP => (0..len).try_for_each(|_| {
+                self.skip_nested(depth + 1)?;
+                self.skip_nested(depth + 1)
+            }),
+            TAG => self.skip_nested(depth + 1),
+            _ => Err(Error::Invalid),
+        }
+    }
+}
+
+/// Encode CBOR into a buffer.
+///
+/// Writes that don't fit are dropped and remembered, so callers only need to
+/// check the result of `finish()`.
+pub struct Writer<'b> {
+    buf: &'b mut [u8],
+    pos: usize,
+    overflow: bool,
+}
+
+impl<'b> Writer<'b> {
+    pub fn new(buf: &'b mut [u8]) -> Self {
+        Writer {
+            buf,
+            pos: 0,
+            overflow: false,
+        }
+    }
+
+    /// Return the number of bytes written, or `None` if they didn't all fit.
+    pub fn finish(self) -> Option<usize> {
+        if self.overflow {
+            None
+        } else {
+            Some(self.pos)
+        }
+    }
+
+    /// Copy already encoded bytes.
+    pub fn raw(&mut self, bytes: &[u8]) {
+        match self.buf.get_mut(self.pos..self.pos + bytes.len()) {
+            Some(dest) => {
+                dest.copy_from_slice(bytes);
+                self.pos += bytes.len();
+            }
+            None => self.overflow = true,
+        }
+    }
+
+    fn header(&mut self, major: u8, argument: u64) {
+        let major = major << 5;
+        match argument {
+            0..=23 => self.raw(&[major | argument as u8]),
+            24..=0xff => self.raw(&[major | 24, argument as u8]),
+            0x100..=0xffff => {
+                self.raw(&[major | 25]);
+                self.raw(&(argument as u16).to_be_bytes());
+            }
+            0x1_0000..=0xffff_ffff => {
+                self.raw(&[major | 26]);
+                self.raw(&(argument as u32).to_be_bytes());
+            }
+            _ => {
+                self.raw(&[major | 27]);
+                self.raw(&argument.to_be_bytes());
+            }
+        }
+    }
+
+    pub fn unsigned(&mut self, value: u64) {
+        self.header(UNSIGNED, value);
+    }
+
+    pub fn integer(&mut self, value: i64) {
+        if value < 0 {
+            self.header(NEGATIVE, (-1 - value) as u64);
+        } else {
+            self.header(UNSIGNED, value as u64);
+        }
+    }
+
+    pub fn bytes(&mut self, bytes: &[u8]) {
+        self.header(BYTES, bytes.len() as u64);
+        self.raw(bytes);
+    }
+
+    pub fn text(&mut self, text: &str) {
+        self.header(TEXT, text.len() as u64);
+        self.raw(text.as_bytes());
+    }
+
+    /// Start an array of `len` items.
+    pub fn array(&mut self, len: usize) {
+        self.header(ARRAY, len as u64);
+    }
+
+    /// Start a map of `len` entries.
+    pub fn map(&mut self, len: usize) {
+        self.header(MAP, len as u64);
+    }
+
+    pub fn bool(&mut self, value: bool) {
+        self.header(SIMPLE, if value { TRUE } else { FALSE });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let mut buf = [0; 64];
+        let mut writer = Writer::new(&mut buf);
+        writer.map(2);
+        writer.unsigned(1);
+        writer.array(3);
+        writer.integer(-1);
+        writer.integer(-500);
+        writer.unsigned(0x1_0000_0000);
+        writer.text("rp");
+        writer.bytes(&[1, 2, 3]);
+        writer.bool(true);
+        let len = writer.finish().unwrap();
+
+        let mut reader = Reader::new(&buf[..len], 0);
+        assert_eq!(reader.map(), Ok(2));
+        assert_eq!(reader.unsigned(), Ok(1));
+        assert_eq!(reader.array(), Ok(3));
+        assert_eq!(reader.integer(), Ok(-1));
+        assert_eq!(reader.integer(), Ok(-500));
+        assert_eq!(reader.integer(), Ok(0x1_0000_0000));
+        assert_eq!(reader.text(), Ok("rp"));
+        assert_eq!(reader.bytes(), Ok(&[1, 2, 3][..]));
+        assert_eq!(reader.bool(), Ok(true));
+        assert!(reader.is_empty());
+    }
+
+    #[test]
+    fn encoding() {
+        // RFC 8949 appendix A.
+        let mut buf = [0; 16];
+        let mut writer = Writer::new(&mut buf);
+        writer.unsigned(1000);
+        writer.integer(-100);
+        writer.unsigned(1_000_000);
+        assert_eq!(writer.finish(), Some(10));
+        assert_eq!(
+            buf[..10],
+            [0x19, 0x03, 0xe8, 0x38, 0x63, 0x1a, 0x00, 0x0f, 0x42, 0x40]
+        );
+    }
+
+    #[test]
+    fn writer_overflow() {
+        let mut buf = [0; 4];
+        let mut writer = Writer::new(&mut buf);
+        writer.bytes(&[0; 4]);
+        assert_eq!(writer.finish(), None);
+    }
+
+    #[test]
+    fn truncated() {
+        // An integer missing its last argument byte.
+        assert_eq!(
+            Reader::new(&[0x19, 0x03], 0).unsigned(),
+            Err(Error::Invalid)
+        );
+        // A byte string shorter than its length.
+        assert_eq!(Reader::new(&[0x43, 1, 2], 0).bytes(), Err(Error::Invalid));
+        // An array missing an item.
+        assert_eq!(Reader::new(&[0x82, 0x01], 0).skip(), Err(Error::Invalid));
+        // Nothing at all.
+        assert_eq!(Reader::new(&[], 0).unsigned(), Err(Error::Invalid));
+        assert_eq!(Reader::new(&[], 0).skip(), Err(Error::Invalid));
+    }
+
+    #[test]
+    fn too_large() {
+        // Unsigned and negative integers that don't fit in an `i64`.
+        let mut reader = Reader::new(&[0x1b, 0x80, 0, 0, 0, 0, 0, 0, 0], 0);
+        assert_eq!(reader.integer(), Err(Error::Invalid));
+        let mut reader = Reader::new(&[0x3b, 0x80, 0, 0, 0, 0, 0, 0, 0], 0);
+        assert_eq!(reader.integer(), Err(Error::Invalid));
+        // The largest values that do fit.
+        let mut reader = Reader::new(&[0x1b, 0x7f, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff], 0);
+        assert_eq!(reader.integer(), Ok(i64::MAX));
+        let mut reader = Reader::new(&[0x3b, 0x7f, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff], 0);
+        assert_eq!(reader.integer(), Ok(i64::MIN));
+        // A byte string length past the end of the address space.
+        let mut reader = Reader::new(&[0x5b, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff], 0);
+        assert_eq!(reader.bytes(), Err(Error::Invalid));
+    }
+
+    #[test]
+    fn unsupported() {
+        // Indefinite length byte string.
+        assert_eq!(
+            Reader::new(&[0x5f, 0x41, 0, 0xff], 0).bytes(),
+            Err(Error::Invalid)
+        );
+        // Reserved additional information.
+        assert_eq!(Reader::new(&[0x1c], 0).unsigned(), Err(Error::Invalid));
+        // Invalid UTF-8.
+        assert_eq!(Reader::new(&[0x61, 0xff], 0).text(), Err(Error::Invalid));
+        // Simple values other than booleans.
+        assert_eq!(Reader::new(&[0xf6], 0).bool(), Err(Error::UnexpectedType));
+    }
+
+    #[test]
+    fn unexpected_type() {
+        let mut reader = Reader::new(&[0x41, 0x00], 0);
+        assert_eq!(reader.text(), Err(Error::UnexpectedType));
+        assert_eq!(reader.integer(), Err(Error::UnexpectedType));
+        // The value is left to be read as the right type.
+        assert_eq!(reader.bytes(), Ok(&[0][..]));
+    }
+
+    #[test]
+    fn nesting() {
+        // `MAX_DEPTH + 1` levels of arrays can be skipped.
+        let mut nested = [0x81; MAX_DEPTH + 2];
+        nested[MAX_DEPTH] = 0x80;
+        let mut reader = Reader::new(&nested[..MAX_DEPTH + 1], 0);
+        assert_eq!(reader.skip(), Ok(()));
+        assert!(reader.is_empty());
+
+        // One more is too deep.
+        nested[MAX_DEPTH] = 0x81;
+        nested[MAX_DEPTH + 1] = 0x80;
+        assert_eq!(Reader::new(&nested, 0).skip(), Err(Error::TooDeep));
+
+        // Maps and tags count too.
+        let map = [0xa1, 0x01, 0xa1, 0x01, 0xa1, 0x01, 0xc1, 0xa1, 0x01, 0xa0];
+        assert_eq!(Reader::new(&map, 0).skip(), Err(Error::TooDeep));
+    }
+}
@@ -0,0 +1,915 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! The CTAPHID transport from the CTAP2 specification.
+//!
+//! CTAPHID splits messages into 64 byte HID reports. The first report of a
+//! message (the initialization packet) carries the channel, the command and
+//! the message length, the following reports (continuation packets) carry
+//! the channel and a sequence number.
+//!
+//! `CtapHidTransport` handles `CTAPHID_INIT`, `CTAPHID_PING` and
+//! `CTAPHID_CANCEL` itself and passes `CTAPHID_CBOR` messages to a
+//! `CtapCommand`. While a command runs, keepalive messages are sent every
+//! 100 ms so the host knows whether the authenticator is waiting for the
+//! user. Only one transaction runs at a time, other channels get
+//! `ERR_CHANNEL_BUSY` until it is done. The U2F `CTAPHID_MSG` command is not
+//! supported.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! let ctap_transport = components::fido::CtapHidTransportComponent::new(
+//!     ctap_hid,
+//!     mux_alarm,
+//!     authenticator,
+//! )
+//! .finalize(components::ctap_hid_transport_component_static!(
+//!     capsules_extra::usb::ctap::CtapHid<'static, UsbController>,
+//!     Alarm,
+//! ));
+//! ctap_hid.set_client(ctap_transport);
+//! ctap_transport.start();
+//! ```
+
+use core::cell::Cell;
+
+use kernel::hil::time::{Alarm, AlarmClient, ConvertTicks};
+use kernel::hil::usb_hid::{self, UsbHid};
+use kernel::utilities::cells::TakeCell;
+use kernel::ErrorCode;
+
+use super::{CtapCommand, CtapCommandClient};
+
+/// The length of a HID report.
+pub const PACKET_LEN: usize = 64;
+
+const INIT_DATA_LEN: usize = PACKET_LEN - 7;
+const CONT_DATA_LEN: usize = PACKET_LEN - 5;
+
+const BROADCAST_CHANNEL: u32 = 0xffff_ffff;
+
+/// How often keepalive messages are sent while a command runs.
+const KEEPALIVE_INTERVAL_MS: u32 = 100;
+/// How long to wait for the next continuation packet of a message.
+const RECEIVE_TIMEOUT_MS: u32 = 500;
+
+mod command {
+    pub const PING: u8 = 0x01;
+    pub const INIT: u8 = 0x06;
+    pub const CBOR: u8 = 0x10;
+    pub const CANCEL: u8 = 0x11;
+    pub const KEEPALIVE: u8 = 0x3b;
+    pub const ERROR: u8 = 0x3f;
+}
+
+mod error {
+    pub const INVALID_CMD: u8 = 0x01;
+    pub const INVALID_LEN: u8 = 0x03;
+    pub const INVALID_SEQ: u8 = 0x04;
+    pub const MSG_TIMEOUT: u8 = 0x05;
+    pub const CHANNEL_BUSY: u8 = 0x06;
+    pub const INVALID_CHANNEL: u8 = 0x0b;
+    pub const OTHER: u8 = 0x7f;
+}
+
+mod keepalive {
+    pub const PROCESSING: u8 = 1;
+    pub const UP_NEEDED: u8 = 2;
+}
+
+/// The CTAPHID protocol version.
+const PROTOCOL_VERSION: u8 = 2;
+/// `CAPABILITY_CBOR | CAPABILITY_NMSG`.
+const CAPABILITIES: u8 = 0x04 | 0x08;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum State {
+    Idle,
+    /// Receiving the continuation packets of a message.
+    Receiving,
+    /// The authenticator is running a command.
+    Processing,
+    /// Sending the response from the `response` buffer.
+    Sending,
+}
+
+pub struct CtapHidTransport<'a, U: UsbHid<'a, [u8; PACKET_LEN]>, A: Alarm<'a>> {
+    hid: &'a U,
+    alarm: &'a A,
+    authenticator: &'a dyn CtapCommand<'a>,
+
+    state: Cell<State>,
+    /// The channel of the transaction in progress.
+    channel: Cell<u32>,
+    command: Cell<u8>,
+    message_len: Cell<usize>,
+    /// How much of the message has been received or sent.
+    offset: Cell<usize>,
+    seq: Cell<u8>,
+    /// Drop the response of the running command, because its channel was
+    /// reinitialized.
+    discard: Cell<bool>,
+    next_channel: Cell<u32>,
+
+    request: TakeCell<'static, [u8]>,
+    response: TakeCell<'static, [u8]>,
+    send_packet: TakeCell<'static, [u8; PACKET_LEN]>,
+    recv_packet: TakeCell<'static, [u8; PACKET_LEN]>,
+    /// A single packet message, such as an error, waiting to be sent.
+    pending: Cell<Option<[u8; PACKET_LEN]>>,
+}
+
+impl<'a, U: UsbHid<'a, [u8; PACKET_LEN]>, A: Alarm<'a>> CtapHidTransport<'a, U, A> {
+    pub fn new(
+        hid: &'a U,
+        alarm: &'a A,
+        authenticator: &'a dyn CtapCommand<'a>,
+        request: &'static mut [u8],
+        response: &'static mut [u8],
+        send_packet: &'static mut [u8; PACKET_LEN],
+        recv_packet: &'static mut [u8; PACKET_LEN],
+    ) -> Self {
+        CtapHidTransport {
+            hid,
+            alarm,
+            authenticator,
+            state: Cell::new(State::Idle),
+            channel: Cell::new(0),
+            command: Cell::new(0),
+            message_len: Cell::new(0),
+            offset: Cell::new(0),
+            seq: Cell::new(0),
+            discard: Cell::new(false),
+            next_channel: Cell::new(1),
+            request: TakeCell::new(request),
+            response: TakeCell::new(response),
+            send_packet: TakeCell::new(send_packet),
+            recv_packet: TakeCell::new(recv_packet),
+            pending: Cell::new(None),
+        }
+    }
+
+    /// Start receiving packets from the host.
+    pub fn start(&self) -> Result<(), ErrorCode> {
+        let packet = self.recv_packet.take().ok_or(ErrorCode::ALREADY)?;
+        self.hid.receive_buffer(packet).map_err(|(e, packet)| {
+            self.recv_packet.replace(packet);
+            e
+        })
+    }
+
+    fn allocate_channel(&self) -> u32 {
+        let channel = self.next_channel.get();
+        let next = match channel.wrapping_add(1) {
+            0 | BROADCAST_CHANNEL => 1,
+            next => next,
+        };
+        self.next_channel.set(next);
+        channel
+    }
+
+    /// Queue a message that fits in one packet.
+    fn send_single(&self, channel: u32, command: u8, data: &[u8]) {
+        let mut packet = [0; PACKET_LEN];
+        packet[0..4].copy_from_slice(&channel.to_be_bytes());
+        packet[4] = command | 0x80;
+        packet[5..7].copy_from_slice(&(data.len() as u16).to_be_bytes());
+        packet[7..7 + data.len()].copy_from_slice(data);
+
+        // If another message is already waiting the packet is dropped, the
+        // host will retry.
+        if self.pending.get().is_none() {
+            self.pending.set(Some(packet));
+        }
+        self.send_next();
+    }
+
+    fn send_error(&self, channel: u32, code: u8) {
+        self.send_single(channel, command::ERROR, &[code]);
+    }
+
+    /// Send the next packet, if the send buffer is free.
+    fn send_next(&self) {
+        let packet = match self.send_packet.take() {
+            Some(packet) => packet,
+            None => return,
+        };
+
+        if let Some(pending) = self.pending.take() {
+            *packet = pending;
+        } else if self.state.get() == State::Sending {
+            self.fill_response_packet(packet);
+        } else {
+            self.send_packet.replace(packet);
+            return;
+        }
+
+        if let Err((_, packet)) = self.hid.send_buffer(packet) {
+            self.send_packet.replace(packet);
+        }
+    }
+
+    /// Fill `packet` with the next part of the response.
+    fn fill_response_packet(&self, packet: &mut [u8; PACKET_LEN]) {
+        let len = self.message_len.get();
+        let offset = self.offset.get();
+
+        packet.fill(0);
+        packet[0..4].copy_from_slice(&self.channel.get().to_be_bytes());
+        let data = if offset == 0 {
+            packet[4] = self.command.get() | 0x80;
+            packet[5..7].copy_from_slice(&(len as u16).to_be_bytes());
+            &mut packet[7..]
+        } else {
+            packet[4] = self.seq.get();
+            self.seq.set(self.seq.get() + 1);
+            &mut packet[5..]
+        };
+
+        let count = data.len().min(len - offset);
+        self.response.map(|response| {
+            data[..count].copy_from_slice(&response[offset..offset + count]);
+        });
+        self.offset.set(offset + count);
+
+        if offset + count >= len {
+            self.state.set(State::Idle);
+        }
+    }
+
+    fn start_sending(&self, command: u8, len: usize) {
+        self.command.set(command);
+        self.message_len.set(len);
+        self.offset.set(0);
+        self.seq.set(0);
+        self.state.set(State::Sending);
+        self.send_next();
+    }
+
+    fn handle_packet(&self, packet: &[u8; PACKET_LEN]) {
+        let channel = u32::from_be_bytes([packet[0], packet[1], packet[2], packet[3]]);
+
+        if packet[4] & 0x80 == 0 {
+            self.handle_continuation(channel, packet[4], &packet[5..]);
+            return;
+        }
+
+        let command = packet[4] & 0x7f;
+        let len = u16::from_be_bytes([packet[5], packet[6]]) as usize;
+        let data = &packet[7..];
+
+        if channel == 0 || (channel == BROADCAST_CHANNEL && command != command::INIT) {
+            self.send_error(channel, error::INVALID_CHANNEL);
+            return;
+        }
+
+        match command {
+            command::INIT => self.handle_init(channel, len, data),
+
+            command::CANCEL => {
+                if self.state.get() == State::Processing && channel == self.channel.get() {
+                    self.authenticator.cancel();
+                }
+            }
+
+            _ => {
+                let state = self.state.get();
+                if state == State::Receiving && channel == self.channel.get() {
+                    self.state.set(State::Idle);
+                    let _ = self.alarm.disarm();
+                    self.send_error(channel, error::INVALID_SEQ);
+                    return;
+                }
+                if state != State::Idle {
+                    self.send_error(channel, error::CHANNEL_BUSY);
+                    return;
+                }
+
+                let capacity = self.request.map_or(0, |request| request.len());
+                if len > capacity {
+                    self.send_error(channel, error::INVALID_LEN);
+                    return;
+                }
+
+                let count = len.min(INIT_DATA_LEN);
+                self.request.map(|request| {
+                    request[..count].copy_from_slice(&data[..count]);
+                });
+                self.channel.set(channel);
+                self.command.set(command);
+                self.message_len.set(len);
+                self.offset.set(count);
+                self.seq.set(0);
+
+                if count == len {
+                    self.dispatch();
+                } else {
+                    self.state.set(State::Receiving);
+                    self.alarm.set_alarm(
+                        self.alarm.now(),
+                        self.alarm.ticks_from_ms(RECEIVE_TIMEOUT_MS),
+                    );
+                }
+            }
+        }
+    }
+
+    fn handle_init(&self, channel: u32, len: usize, data: &[u8]) {
+        if len != 8 {
+            self.send_error(channel, error::INVALID_LEN);
+            return;
+        }
+
+        // Reinitializing a channel abandons its transaction.
+        if self.state.get() != State::Idle && channel == self.channel.get() {
+            match self.state.get() {
+                State::Processing => {
+                    self.discard.set(true);
+                    self.authenticator.cancel();
+                }
+                _ => {
+                    self.state.set(State::Idle);
+                    let _ = self.alarm.disarm();
+                }
+            }
+        }
+
+        let new_channel = if channel == BROADCAST_CHANNEL {
+            self.allocate_channel()
+        } else {
+            channel
+        };
+
+        let mut reply = [0; 17];
+        reply[..8].copy_from_slice(&data[..8]);
+        reply[8..12].copy_from_slice(&new_channel.to_be_bytes());
+        reply[12] = PROTOCOL_VERSION;
+        // Device version 1.0.0.
+        reply[13] = 1;
+        reply[16] = CAPABILITIES;
+        self.send_single(channel, command::INIT, &reply);
+    }
+
+    fn handle_continuation(&self, channel: u32, seq: u8, data: &[u8]) {
+        if self.state.get() != State::Receiving || channel != self.channel.get() {
+            return;
+        }
+
+        if seq != self.seq.get() {
+            self.state.set(State::Idle);
+            let _ = self.alarm.disarm();
+            self.send_error(channel, error::INVALID_SEQ);
+            return;
+        }
+
+        let offset = self.offset.get();
+        let count = (self.message_len.get() - offset).min(CONT_DATA_LEN);
+        self.request.map(|request| {
+            request[offset..offset + count].copy_from_slice(&data[..count]);
+        });
+        self.offset.set(offset + count);
+        self.seq.set(seq.wrapping_add(1));
+
+        if self.offset.get() == self.message_len.get() {
+            let _ = self.alarm.disarm();
+            self.dispatch();
+        } else {
+            self.alarm.set_alarm(
+                self.alarm.now(),
+                self.alarm.ticks_from_ms(RECEIVE_TIMEOUT_MS),
+            );
+        }
+    }
+
+    /// Handle the message in `request`.
+    fn dispatch(&self) {
+        let channel = self.channel.get();
+        let len = self.message_len.get();
+
+        match self.command.get() {
+            command::PING => {
+                self.request.map(|request| {
+                    self.response.map(|response| {
+                        response[..len].copy_from_slice(&request[..len]);
+                    });
+                });
+                self.start_sending(command::PING, len);
+            }
+
+            command::CBOR => {
+                if len == 0 {
+                    self.state.set(State::Idle);
+                    self.send_error(channel, error::INVALID_LEN);
+                    return;
+                }
+
+                let (request, response) = match (self.request.take(), self.response.take()) {
+                    (Some(request), Some(response)) => (request, response),
+                    (request, response) => {
+                        request.map(|request| self.request.replace(request));
+                        response.map(|response| self.response.replace(response));
+                        self.state.set(State::Idle);
+                        self.send_error(channel, error::OTHER);
+                        return;
+                    }
+                };
+
+                self.state.set(State::Processing);
+                self.discard.set(false);
+                match self.authenticator.process(request, len, response) {
+                    Ok(()) => {
+                        self.alarm.set_alarm(
+                            self.alarm.now(),
+                            self.alarm.ticks_from_ms(KEEPALIVE_INTERVAL_MS),
+                        );
+                    }
+                    Err((e, request, response)) => {
+                        self.request.replace(request);
+                        self.response.replace(response);
+                        self.state.set(State::Idle);
+                        let code = match e {
+                            ErrorCode::BUSY => error::CHANNEL_BUSY,
+                            _ => error::OTHER,
+                        };
+                        self.send_error(channel, code);
+                    }
+                }
+            }
+
+            _ => {
+                self.state.set(State::Idle);
+                self.send_error(channel, error::INVALID_CMD);
+            }
+        }
+    }
+}
+
+impl<'a, U: UsbHid<'a, [u8; PACKET_LEN]>, A: Alarm<'a>> usb_hid::Client<'a, [u8; PACKET_LEN]>
+    for CtapHidTransport<'a, U, A>
+{
+    fn packet_received(
+        &'a self,
+        result: Result<(), ErrorCode>,
+        buffer: &'static mut [u8; PACKET_LEN],
+        _endpoint: usize,
+    ) {
+        let packet = *buffer;
+        self.recv_packet.replace(buffer);
+
+        if result.is_ok() {
+            self.handle_packet(&packet);
+        }
+
+        // Ask for the next packet.
+        if let Some(buffer) = self.recv_packet.take() {
+            if let Err((_, buffer)) = self.hid.receive_buffer(buffer) {
+                self.recv_packet.replace(buffer);
+            }
+        }
+    }
+
+    fn packet_transmitted(
+        &'a self,
+        _result: Result<(), ErrorCode>,
+        buffer: &'static mut [u8; PACKET_LEN],
+        _endpoint: usize,
+    ) {
+        self.send_packet.replace(buffer);
+        self.send_next();
+    }
+
+    fn can_receive(&'a self) -> bool {
+        true
+    }
+}
+
+impl<'a, U: UsbHid<'a, [u8; PACKET_LEN]>, A: Alarm<'a>> CtapCommandClient
+    for CtapHidTransport<'a, U, A>
+{
+    fn response_ready(&self, request: &'static mut [u8], response: &'static mut [u8], len: usize) {
+        self.request.replace(request);
+        self.response.replace(response);
+        if self.state.get() != State::Processing {
+            return;
+        }
+        let _ = self.alarm.disarm();
+
+        if self.discard.get() {
+            self.state.set(State::Idle);
+            return;
+        }
+
+        self.start_sending(command::CBOR, len);
+    }
+}
+
+impl<'a, U: UsbHid<'a, [u8; PACKET_LEN]>, A: Alarm<'a>> AlarmClient for CtapHidTransport<'a, U, A> {
+    fn alarm(&self) {
+        match self.state.get() {
+            State::Receiving => {
+                self.state.set(State::Idle);
+                self.send_error(self.channel.get(), error::MSG_TIMEOUT);
+            }
+            State::Processing => {
+                let status = if self.authenticator.waiting_for_user() {
+                    keepalive::UP_NEEDED
+                } else {
+                    keepalive::PROCESSING
+                };
+                self.send_single(self.channel.get(), command::KEEPALIVE, &[status]);
+                self.alarm.set_alarm(
+                    self.alarm.now(),
+                    self.alarm.ticks_from_ms(KEEPALIVE_INTERVAL_MS),
+                );
+            }
+            State::Idle | State::Sending => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use kernel::hil::time::{Freq1KHz, Ticks32, Time};
+    use kernel::utilities::cells::{MapCell, OptionalCell};
+    use std::boxed::Box;
+    use std::vec::Vec;
+
+    /// A HID device that keeps the packets sent to the host.
+    struct FakeHid {
+        client: OptionalCell<&'static dyn usb_hid::Client<'static, [u8; PACKET_LEN]>>,
+        recv: TakeCell<'static, [u8; PACKET_LEN]>,
+        send: TakeCell<'static, [u8; PACKET_LEN]>,
+        sent: MapCell<Vec<[u8; PACKET_LEN]>>,
+    }
+
+    impl FakeHid {
+        /// Deliver a packet from the host.
+        fn receive(&self, packet: [u8; PACKET_LEN]) {
+            let buffer = self.recv.take().unwrap();
+            *buffer = packet;
+            self.client
+                .map(move |client| client.packet_received(Ok(()), buffer, 0));
+        }
+
+        /// Finish sending packets, and return all the packets sent.
+        fn sent(&self) -> Vec<[u8; PACKET_LEN]> {
+            while let Some(buffer) = self.send.take() {
+                self.client
+                    .map(move |client| client.packet_transmitted(Ok(()), buffer, 0));
+            }
+            self.sent.take().map_or(Vec::new(), |sent| {
+                self.sent.replace(Vec::new());
+                sent
+            })
+        }
+    }
+
+    impl UsbHid<'static, [u8; PACKET_LEN]> for FakeHid {
+        fn send_buffer(
+            &'static self,
+            send: &'static mut [u8; PACKET_LEN],
+        ) -> Result<usize, (ErrorCode, &'static mut [u8; PACKET_LEN])> {
+            self.sent.map(|sent| sent.push(*send));
+            self.send.replace(send);
+            Ok(PACKET_LEN)
+        }
+
+        fn send_cancel(&'static self) -> Result<&'static mut [u8; PACKET_LEN], ErrorCode> {
+            Err(ErrorCode::FAIL)
+        }
+
+        fn receive_buffer(
+            &'static self,
+            recv: &'static mut [u8; PACKET_LEN],
+        ) -> Result<(), (ErrorCode, &'static mut [u8; PACKET_LEN])> {
+            self.recv.replace(recv);
+            Ok(())
+        }
+
+        fn receive_cancel(&'static self) -> Result<&'static mut [u8; PACKET_LEN], ErrorCode> {
+            self.recv.take().ok_or(ErrorCode::FAIL)
+        }
+    }
+
+    struct FakeAlarm {
+        armed: Cell<bool>,
+    }
+
+    impl Time for FakeAlarm {
+        type Ticks = Ticks32;
+        type Frequency = Freq1KHz;
+
+        fn now(&self) -> Ticks32 {
+            0u32.into()
+        }
+    }
+
+    impl Alarm<'static> for FakeAlarm {
+        fn set_alarm_client(&self, _client: &'static dyn AlarmClient) {}
+
+        fn set_alarm(&self, _reference: Ticks32, _dt: Ticks32) {
+            self.armed.set(true);
+        }
+
+        fn get_alarm(&self) -> Ticks32 {
+            0u32.into()
+        }
+
+        fn disarm(&self) -> Result<(), ErrorCode> {
+            self.armed.set(false);
+            Ok(())
+        }
+
+        fn is_armed(&self) -> bool {
+            self.armed.get()
+        }
+
+        fn minimum_dt(&self) -> Ticks32 {
+            0u32.into()
+        }
+    }
+
+    /// An authenticator that keeps the command it was given until
+    /// `respond()` is called.
+    struct FakeAuthenticator {
+        client: OptionalCell<&'static dyn CtapCommandClient>,
+        buffers: MapCell<(&'static mut [u8], &'static mut [u8])>,
+        request: MapCell<Vec<u8>>,
+        waiting: Cell<bool>,
+        cancelled: Cell<bool>,
+    }
+
+    impl FakeAuthenticator {
+        fn respond(&self, data: &[u8]) {
+            let (request, response) = self.buffers.take().unwrap();
+            response[..data.len()].copy_from_slice(data);
+            self.client
+                .map(move |client| client.response_ready(request, response, data.len()));
+        }
+    }
+
+    impl<'a> CtapCommand<'a> for FakeAuthenticator {
+        fn set_client(&self, _client: &'a dyn CtapCommandClient) {}
+
+        fn process(
+            &self,
+            request: &'static mut [u8],
+            len: usize,
+            response: &'static mut [u8],
+        ) -> Result<(), (ErrorCode, &'static mut [u8], &'static mut [u8])> {
+            self.request.replace(request[..len].to_vec());
+            self.buffers.replace((request, response));
+            Ok(())
+        }
+
+        fn cancel(&self) {
+            self.cancelled.set(true);
+        }
+
+        fn waiting_for_user(&self) -> bool {
+            self.waiting.get()
+        }
+    }
+
+    type TestTransport = CtapHidTransport<'static, FakeHid, FakeAlarm>;
+
+    fn setup() -> (
+        &'static TestTransport,
+        &'static FakeHid,
+        &'static FakeAlarm,
+        &'static FakeAuthenticator,
+    ) {
+        let hid = Box::leak(Box::new(FakeHid {
+            client: OptionalCell::empty(),
+            recv: TakeCell::empty(),
+            send: TakeCell::empty(),
+            sent: MapCell::new(Vec::new()),
+        }));
+        let alarm = Box::leak(Box::new(FakeAlarm {
+            armed: Cell::new(false),
+        }));
+        let authenticator = Box::leak(Box::new(FakeAuthenticator {
+            client: OptionalCell::empty(),
+            buffers: MapCell::empty(),
+            request: MapCell::new(Vec::new()),
+            waiting: Cell::new(false),
+            cancelled: Cell::new(false),
+        }));
+        let transport = Box::leak(Box::new(CtapHidTransport::new(
+            &*hid,
+            &*alarm,
+            &*authenticator,
+            Box::leak(Box::new([0; 256])),
+            Box::leak(Box::new([0; 256])),
+            Box::leak(Box::new([0; PACKET_LEN])),
+            Box::leak(Box::new([0; PACKET_LEN])),
+        )));
+        hid.client.set(transport);
+        authenticator.client.set(transport);
+        assert_eq!(transport.start(), Ok(()));
+        (transport, hid, alarm, authenticator)
+    }
+
+    fn init_packet(channel: u32, command: u8, len: usize, data: &[u8]) -> [u8; PACKET_LEN] {
+        let mut packet = [0; PACKET_LEN];
+        packet[0..4].copy_from_slice(&channel.to_be_bytes());
+        packet[4] = command | 0x80;
+        packet[5..7].copy_from_slice(&(len as u16).to_be_bytes());
+        packet[7..7 + data.len()].copy_from_slice(data);
+        packet
+    }
+
+    fn cont_packet(channel: u32, seq: u8, data: &[u8]) -> [u8; PACKET_LEN] {
+        let mut packet = [0; PACKET_LEN];
+        packet[0..4].copy_from_slice(&channel.to_be_bytes());
+        packet[4] = seq;
+        packet[5..5 + data.len()].copy_from_slice(data);
+        packet
+    }
+
+    /// Check `packets` is a single error message.
+    fn assert_error(packets: &[[u8; PACKET_LEN]], channel: u32, code: u8) {
+        assert_eq!(packets.len(), 1);
+        assert_eq!(
+            packets[0][..8],
+            init_packet(channel, command::ERROR, 1, &[code])[..8]
+        );
+    }
+
+    #[test]
+    fn init() {
+        let (_, hid, _, _) = setup();
+        let nonce = [1, 2, 3, 4, 5, 6, 7, 8];
+        hid.receive(init_packet(BROADCAST_CHANNEL, command::INIT, 8, &nonce));
+
+        let sent = hid.sent();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(
+            sent[0][..7],
+            init_packet(BROADCAST_CHANNEL, command::INIT, 17, &[])[..7]
+        );
+        assert_eq!(sent[0][7..15], nonce);
+        // The first channel allocated.
+        assert_eq!(sent[0][15..19], [0, 0, 0, 1]);
+        assert_eq!(sent[0][19], PROTOCOL_VERSION);
+        assert_eq!(sent[0][23], CAPABILITIES);
+
+        // The next allocation gets a new channel.
+        hid.receive(init_packet(BROADCAST_CHANNEL, command::INIT, 8, &nonce));
+        assert_eq!(hid.sent()[0][15..19], [0, 0, 0, 2]);
+
+        // INIT always has an 8 byte nonce.
+        hid.receive(init_packet(BROADCAST_CHANNEL, command::INIT, 4, &nonce));
+        assert_error(&hid.sent(), BROADCAST_CHANNEL, error::INVALID_LEN);
+    }
+
+    #[test]
+    fn invalid_channel() {
+        let (_, hid, _, _) = setup();
+        hid.receive(init_packet(0, command::PING, 1, &[0]));
+        assert_error(&hid.sent(), 0, error::INVALID_CHANNEL);
+        hid.receive(init_packet(BROADCAST_CHANNEL, command::PING, 1, &[0]));
+        assert_error(&hid.sent(), BROADCAST_CHANNEL, error::INVALID_CHANNEL);
+    }
+
+    #[test]
+    fn ping_over_several_packets() {
+        let (_, hid, alarm, _) = setup();
+        let message: Vec<u8> = (0..150).map(|i| i as u8).collect();
+        let (first, rest) = message.split_at(INIT_DATA_LEN);
+        let (second, third) = rest.split_at(CONT_DATA_LEN);
+
+        hid.receive(init_packet(7, command::PING, message.len(), first));
+        // Waiting for the rest of the message.
+        assert!(alarm.is_armed());
+        hid.receive(cont_packet(7, 0, second));
+        hid.receive(cont_packet(7, 1, third));
+        assert!(!alarm.is_armed());
+
+        // The message is echoed back in the same packets.
+        let sent = hid.sent();
+        assert_eq!(sent.len(), 3);
+        assert_eq!(sent[0], init_packet(7, command::PING, message.len(), first));
+        assert_eq!(sent[1], cont_packet(7, 0, second));
+        assert_eq!(sent[2], cont_packet(7, 1, third));
+    }
+
+    #[test]
+    fn wrong_sequence_number() {
+        let (transport, hid, alarm, _) = setup();
+        hid.receive(init_packet(7, command::PING, 100, &[0; INIT_DATA_LEN]));
+        hid.receive(cont_packet(7, 1, &[0; CONT_DATA_LEN]));
+        assert_error(&hid.sent(), 7, error::INVALID_SEQ);
+        assert_eq!(transport.state.get(), State::Idle);
+        assert!(!alarm.is_armed());
+
+        // Continuation packets without a message are ignored.
+        hid.receive(cont_packet(7, 0, &[0; CONT_DATA_LEN]));
+        assert!(hid.sent().is_empty());
+
+        // A new message on the channel before the last one was complete.
+        hid.receive(init_packet(7, command::PING, 100, &[0; INIT_DATA_LEN]));
+        hid.receive(init_packet(7, command::PING, 1, &[0]));
+        assert_error(&hid.sent(), 7, error::INVALID_SEQ);
+    }
+
+    #[test]
+    fn message_timeout() {
+        let (transport, hid, alarm, _) = setup();
+        hid.receive(init_packet(7, command::PING, 100, &[0; INIT_DATA_LEN]));
+        assert!(alarm.is_armed());
+        transport.alarm();
+        assert_error(&hid.sent(), 7, error::MSG_TIMEOUT);
+        assert_eq!(transport.state.get(), State::Idle);
+    }
+
+    #[test]
+    fn message_too_long() {
+        let (_, hid, _, _) = setup();
+        hid.receive(init_packet(7, command::PING, 257, &[0; INIT_DATA_LEN]));
+        assert_error(&hid.sent(), 7, error::INVALID_LEN);
+    }
+
+    #[test]
+    fn channel_busy() {
+        let (_, hid, _, authenticator) = setup();
+        hid.receive(init_packet(7, command::PING, 100, &[0; INIT_DATA_LEN]));
+        hid.receive(init_packet(8, command::PING, 1, &[0]));
+        assert_error(&hid.sent(), 8, error::CHANNEL_BUSY);
+
+        // The first message still completes.
+        hid.receive(cont_packet(7, 0, &[0; CONT_DATA_LEN]));
+        assert_eq!(hid.sent().len(), 2);
+
+        // Other channels are also busy while a command runs.
+        hid.receive(init_packet(7, command::CBOR, 1, &[0x04]));
+        hid.receive(init_packet(8, command::CBOR, 1, &[0x04]));
+        assert_error(&hid.sent(), 8, error::CHANNEL_BUSY);
+        authenticator.respond(&[0x00]);
+        assert_eq!(hid.sent().len(), 1);
+    }
+
+    #[test]
+    fn cbor_command() {
+        let (transport, hid, alarm, authenticator) = setup();
+        hid.receive(init_packet(7, command::CBOR, 3, &[0x04, 0xa0, 0xf5]));
+        assert_eq!(
+            authenticator.request.take(),
+            Some(std::vec![0x04, 0xa0, 0xf5])
+        );
+
+        // Keepalives are sent while the command runs.
+        assert!(alarm.is_armed());
+        transport.alarm();
+        authenticator.waiting.set(true);
+        transport.alarm();
+        let sent = hid.sent();
+        assert_eq!(sent.len(), 2);
+        assert_eq!(
+            sent[0][..8],
+            init_packet(7, command::KEEPALIVE, 1, &[keepalive::PROCESSING])[..8]
+        );
+        assert_eq!(
+            sent[1][..8],
+            init_packet(7, command::KEEPALIVE, 1, &[keepalive::UP_NEEDED])[..8]
+        );
+
+        // A cancel from the host is passed on.
+        hid.receive(init_packet(7, command::CANCEL, 0, &[]));
+        assert!(authenticator.cancelled.get());
+
+        authenticator.respond(&[0x00, 0xa1, 0x01, 0x02]);
+        assert!(!alarm.is_armed());
+        assert_eq!(
+            hid.sent(),
+            [init_packet(7, command::CBOR, 4, &[0x00, 0xa1, 0x01, 0x02])]
+        );
+    }
+
+    #[test]
+    fn reinit_discards_response() {
+        let (_, hid, _, authenticator) = setup();
+        hid.receive(init_packet(7, command::CBOR, 1, &[0x04]));
+        hid.receive(init_packet(7, command::INIT, 8, &[0; 8]));
+        assert!(authenticator.cancelled.get());
+        assert_eq!(hid.sent().len(), 1);
+
+        authenticator.respond(&[0x2d]);
+        assert!(hid.sent().is_empty());
+    }
+
+    #[test]
+    fn unknown_command() {
+        let (_, hid, _, _) = setup();
+        hid.receive(init_packet(7, 0x03, 1, &[0]));
+        assert_error(&hid.sent(), 7, error::INVALID_CMD);
+        hid.receive(init_packet(7, command::CBOR, 0, &[]));
+        assert_error(&hid.sent(), 7, error::INVALID_LEN);
+    }
+}
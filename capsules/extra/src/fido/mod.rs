@@ -0,0 +1,57 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! FIDO2 security key support.
+//!
+//! The kernel implements both layers of CTAP2:
+//!
+//! - `ctaphid::CtapHidTransport` reassembles CTAPHID messages from the 64
+//!   byte reports of a USB HID device (such as `usb::ctap::CtapHid`), handles
+//!   the CTAPHID commands itself and passes CBOR commands on.
+//! - `authenticator::Authenticator` runs the CTAP2 commands, with the
+//!   credentials kept in a KV store.
+//!
+//! The two are connected through the `CtapCommand` trait, so another
+//! transport (such as NFC or BLE) can use the same authenticator.
+
+pub mod authenticator;
+pub mod cbor;
+pub mod ctaphid;
+
+use kernel::ErrorCode;
+
+/// The longest CTAP2 message the transport and authenticator handle.
+pub const MAX_MESSAGE_LEN: usize = 1024;
+
+/// Runs CTAP2 commands received by a transport.
+pub trait CtapCommand<'a> {
+    fn set_client(&self, client: &'a dyn CtapCommandClient);
+
+    /// Run the command in `request[..len]`, which starts with the CTAP2
+    /// command byte followed by the CBOR parameters.
+    ///
+    /// The response, the status byte followed by any CBOR response data, is
+    /// written to `response` and passed to `response_ready()`. Returns `BUSY`
+    /// if a command is already running.
+    fn process(
+        &self,
+        request: &'static mut [u8],
+        len: usize,
+        response: &'static mut [u8],
+    ) -> Result<(), (ErrorCode, &'static mut [u8], &'static mut [u8])>;
+
+    /// Cancel the running command if it is waiting for the user. The command
+    /// then finishes with `CTAP2_ERR_KEEPALIVE_CANCEL`.
+    fn cancel(&self);
+
+    /// Whether the running command is waiting for the user to confirm their
+    /// presence.
+    fn waiting_for_user(&self) -> bool;
+}
+
+pub trait CtapCommandClient {
+    /// Called when a command is done, with the response in
+    /// `response[..len]`.
+    fn response_ready(&self, request: &'static mut [u8], response: &'static mut [u8], len: usize);
+}
@@ -0,0 +1,129 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! A GPIO pin for unit tests of capsules that use the GPIO HIL.
+//!
+//! `FakePin` reads its own output while it is an output, and high, as if
+//! pulled up, while it is an input. A device simulated by a test can hold
+//! the line low with `hold_low()`, and is told when the capsule changes the
+//! pin through `PinListener`.
+
+use core::cell::Cell;
+
+use kernel::hil::gpio::{Configuration, Configure, FloatingState, Input, Output};
+use kernel::utilities::cells::OptionalCell;
+
+pub(crate) trait PinListener {
+    /// The capsule changed the level or configuration of the pin.
+    fn changed(&self);
+}
+
+pub(crate) struct FakePin {
+    output: Cell<bool>,
+    value: Cell<bool>,
+    held_low: Cell<bool>,
+    floating: Cell<FloatingState>,
+    listener: OptionalCell<&'static dyn PinListener>,
+}
+
+impl FakePin {
+    pub(crate) fn new() -> Self {
+        Self {
+            output: Cell::new(false),
+            value: Cell::new(false),
+            held_low: Cell::new(false),
+            floating: Cell::new(FloatingState::PullNone),
+            listener: OptionalCell::empty(),
+        }
+    }
+
+    pub(crate) fn set_listener(&self, listener: &'static dyn PinListener) {
+        self.listener.set(listener);
+    }
+
+    /// The level of the line.
+    pub(crate) fn level(&self) -> bool {
+        (!self.output.get() || self.value.get()) && !self.held_low.get()
+    }
+
+    /// Whether the capsule pulls the line low.
+    pub(crate) fn driven_low(&self) -> bool {
+        self.output.get() && !self.value.get()
+    }
+
+    /// Hold the line low, or release it, as a device would.
+    pub(crate) fn hold_low(&self, low: bool) {
+        self.held_low.set(low);
+    }
+
+    fn changed(&self) {
+        self.listener.map(|listener| listener.changed());
+    }
+}
+
+impl Configure for FakePin {
+    fn configuration(&self) -> Configuration {
+        if self.output.get() {
+            Configuration::Output
+        } else {
+            Configuration::Input
+        }
+    }
+
+    fn make_output(&self) -> Configuration {
+        self.output.set(true);
+        self.changed();
+        Configuration::Output
+    }
+
+    fn disable_output(&self) -> Configuration {
+        self.make_input()
+    }
+
+    fn make_input(&self) -> Configuration {
+        self.output.set(false);
+        self.changed();
+        Configuration::Input
+    }
+
+    fn disable_input(&self) -> Configuration {
+        self.configuration()
+    }
+
+    fn deactivate_to_low_power(&self) {
+        self.make_input();
+    }
+
+    fn set_floating_state(&self, state: FloatingState) {
+        self.floating.set(state);
+    }
+
+    fn floating_state(&self) -> FloatingState {
+        self.floating.get()
+    }
+}
+
+impl Input for FakePin {
+    fn read(&self) -> bool {
+        self.level()
+    }
+}
+
+impl Output for FakePin {
+    fn set(&self) {
+        self.value.set(true);
+        self.changed();
+    }
+
+    fn clear(&self) {
+        self.value.set(false);
+        self.changed();
+    }
+
+    fn toggle(&self) -> bool {
+        self.value.set(!self.value.get());
+        self.changed();
+        self.value.get()
+    }
+}
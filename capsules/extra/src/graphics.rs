@@ -0,0 +1,754 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Drawing primitives over a pixel screen.
+//!
+//! `hil::screen::Screen` only accepts raw pixel data written to a frame.
+//! `Graphics` draws filled rectangles, rectangle outlines, lines, text in a
+//! bitmap font and bitmaps on any such screen. It keeps no frame buffer:
+//! pixels are generated in the screen's pixel format as they are written out
+//! through a small staging buffer.
+//!
+//! Colours are given as `0xAARRGGBB` and converted to the pixel format of the
+//! screen. Only `ARGB_8888` screens keep the alpha channel, there is no
+//! blending as the screen can't be read back.
+//!
+//! Monochrome screens take eight pixels per byte, so on them the frame of
+//! each primitive is widened to whole bytes and the pixels added on either
+//! side are painted with the background colour.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! let graphics = components::graphics::GraphicsComponent::new(tft)
+//!     .finalize(components::graphics_component_static!(1024));
+//! ```
+
+use core::cell::Cell;
+use core::cmp;
+
+use kernel::hil::screen::{Screen, ScreenClient, ScreenPixelFormat};
+use kernel::utilities::cells::{MapCell, OptionalCell, TakeCell};
+use kernel::utilities::leasable_buffer::SubSliceMut;
+use kernel::ErrorCode;
+
+/// The pixel format with the given number in the screen system call API.
+pub fn pixel_format_from(pixel_format: usize) -> Option<ScreenPixelFormat> {
+    match pixel_format {
+        0 => Some(ScreenPixelFormat::Mono),
+        1 => Some(ScreenPixelFormat::RGB_233),
+        2 => Some(ScreenPixelFormat::RGB_565),
+        3 => Some(ScreenPixelFormat::RGB_888),
+        4 => Some(ScreenPixelFormat::ARGB_8888),
+        _ => None,
+    }
+}
+
+/// Encode a `0xAARRGGBB` colour as a pixel in `format`.
+///
+/// Monochrome pixels are light if the luminance of the colour is at least
+/// half of the maximum.
+pub fn encode(format: ScreenPixelFormat, color: u32) -> u32 {
+    let r = (color >> 16) & 0xFF;
+    let g = (color >> 8) & 0xFF;
+    let b = color & 0xFF;
+    match format {
+        ScreenPixelFormat::Mono => ((r * 77 + g * 150 + b * 29) >> 15) & 1,
+        ScreenPixelFormat::RGB_233 => (r >> 6) << 6 | (g >> 5) << 3 | b >> 5,
+        ScreenPixelFormat::RGB_565 => (r >> 3) << 11 | (g >> 2) << 5 | b >> 3,
+        ScreenPixelFormat::RGB_888 => color & 0xFF_FFFF,
+        ScreenPixelFormat::ARGB_8888 => color,
+    }
+}
+
+/// Decode a pixel in `format` into an opaque `0xAARRGGBB` colour, or the
+/// colour itself for `ARGB_8888`.
+pub fn decode(format: ScreenPixelFormat, pixel: u32) -> u32 {
+    // Widen a channel to 8 bits by repeating its bits.
+    let widen = |value: u32, bits: u32| {
+        let mut channel = 0;
+        let mut shift = 8;
+        while shift > 0 {
+            channel |= if shift >= bits {
+                value << (shift - bits)
+            } else {
+                value >> (bits - shift)
+            };
+            shift = shift.saturating_sub(bits);
+        }
+        channel & 0xFF
+    };
+    let rgb = |r: u32, g: u32, b: u32| 0xFF00_0000 | r << 16 | g << 8 | b;
+    match format {
+        ScreenPixelFormat::Mono => {
+            if pixel & 1 != 0 {
+                0xFFFF_FFFF
+            } else {
+                0xFF00_0000
+            }
+        }
+        ScreenPixelFormat::RGB_233 => rgb(
+            widen((pixel >> 6) & 0x3, 2),
+            widen((pixel >> 3) & 0x7, 3),
+            widen(pixel & 0x7, 3),
+        ),
+        ScreenPixelFormat::RGB_565 => rgb(
+            widen((pixel >> 11) & 0x1F, 5),
+            widen((pixel >> 5) & 0x3F, 6),
+            widen(pixel & 0x1F, 5),
+        ),
+        ScreenPixelFormat::RGB_888 => 0xFF00_0000 | pixel,
+        ScreenPixelFormat::ARGB_8888 => pixel,
+    }
+}
+
+/// Convert a pixel from one format to another.
+pub fn convert(from: ScreenPixelFormat, to: ScreenPixelFormat, pixel: u32) -> u32 {
+    encode(to, decode(from, pixel))
+}
+
+/// Read pixel `index` of `data`.
+///
+/// Monochrome pixels are packed eight per byte with the first pixel in the
+/// most significant bit, larger pixels are stored most significant byte
+/// first.
+pub fn read_pixel(format: ScreenPixelFormat, data: &[u8], index: usize) -> u32 {
+    match format.get_bits_per_pixel() {
+        1 => (data[index / 8] as u32 >> (7 - index % 8)) & 1,
+        bits => {
+            let bytes = bits / 8;
+            data[index * bytes..][..bytes]
+                .iter()
+                .fold(0, |pixel, byte| pixel << 8 | *byte as u32)
+        }
+    }
+}
+
+/// Write pixel `index` of `data`, packed as by `read_pixel`.
+pub fn write_pixel(format: ScreenPixelFormat, data: &mut [u8], index: usize, pixel: u32) {
+    match format.get_bits_per_pixel() {
+        1 => {
+            let mask = 0x80 >> (index % 8);
+            if pixel & 1 != 0 {
+                data[index / 8] |= mask;
+            } else {
+                data[index / 8] &= !mask;
+            }
+        }
+        bits => {
+            let bytes = bits / 8;
+            for (i, byte) in data[index * bytes..][..bytes].iter_mut().enumerate() {
+                *byte = (pixel >> (8 * (bytes - 1 - i))) as u8;
+            }
+        }
+    }
+}
+
+/// The number of bytes in a row of a bitmap `width` pixels wide. Rows start
+/// on a byte boundary.
+pub fn row_bytes(format: ScreenPixelFormat, width: usize) -> usize {
+    (width * format.get_bits_per_pixel()).div_ceil(8)
+}
+
+/// A bitmap font with glyphs of the same width.
+///
+/// Each glyph is `width` bytes, one per column from left to right, with the
+/// top row in the least significant bit.
+pub struct Font {
+    pub width: usize,
+    pub height: usize,
+    /// The character of the first glyph.
+    pub first: u8,
+    pub glyphs: &'static [u8],
+}
+
+impl Font {
+    /// The size of a character cell at `scale`. Cells are one column wider
+    /// than the glyphs, so characters don't touch.
+    pub fn cell_size(&self, scale: usize) -> (usize, usize) {
+        ((self.width + 1) * scale, self.height * scale)
+    }
+
+    /// Whether pixel (`x`, `y`) of the glyph of `c` is set. Characters
+    /// without a glyph are blank.
+    pub fn pixel(&self, c: u8, x: usize, y: usize) -> bool {
+        if x >= self.width || y >= self.height {
+            return false;
+        }
+        let index = c.wrapping_sub(self.first) as usize;
+        self.glyphs
+            .get(index * self.width + x)
+            .is_some_and(|column| column & (1 << y) != 0)
+    }
+}
+
+/// A 5x8 pixel font of the printable ASCII characters, with descenders in
+/// the bottom row.
+pub const FONT_5X8: Font = Font {
+    width: 5,
+    height: 8,
+    first: b' ',
+    glyphs: &FONT_5X8_GLYPHS,
+};
+
+#[rustfmt::skip]
+const FONT_5X8_GLYPHS: [u8; 95 * 5] = [
+    0x00, 0x00, 0x00, 0x00, 0x00, // ' '
+    0x00, 0x00, 0x5F, 0x00, 0x00, // '!'
+    0x00, 0x07, 0x00, 0x07, 0x00, // '"'
+    0x14, 0x7F, 0x14, 0x7F, 0x14, // '#'
+    0x24, 0x2A, 0x7F, 0x2A, 0x12, // '$'
+    0x23, 0x13, 0x08, 0x64, 0x62, // '%'
+    0x36, 0x49, 0x55, 0x22, 0x50, // '&'
+    0x00, 0x00, 0x07, 0x00, 0x00, // '''
+    0x00, 0x1C, 0x22, 0x41, 0x00, // '('
+    0x00, 0x41, 0x22, 0x1C, 0x00, // ')'
+    0x2A, 0x1C, 0x7F, 0x1C, 0x2A, // '*'
+    0x08, 0x08, 0x3E, 0x08, 0x08, // '+'
+    0x00, 0x80, 0x70, 0x30, 0x00, // ','
+    0x08, 0x08, 0x08, 0x08, 0x08, // '-'
+    0x00, 0x00, 0x60, 0x60, 0x00, // '.'
+    0x20, 0x10, 0x08, 0x04, 0x02, // '/'
+    0x3E, 0x51, 0x49, 0x45, 0x3E, // '0'
+    0x00, 0x42, 0x7F, 0x40, 0x00, // '1'
+    0x42, 0x61, 0x51, 0x49, 0x46, // '2'
+    0x21, 0x41, 0x45, 0x4B, 0x31, // '3'
+    0x18, 0x14, 0x12, 0x7F, 0x10, // '4'
+    0x27, 0x45, 0x45, 0x45, 0x39, // '5'
+    0x3C, 0x4A, 0x49, 0x49, 0x30, // '6'
+    0x01, 0x71, 0x09, 0x05, 0x03, // '7'
+    0x36, 0x49, 0x49, 0x49, 0x36, // '8'
+    0x06, 0x49, 0x49, 0x29, 0x1E, // '9'
+    0x00, 0x36, 0x36, 0x00, 0x00, // ':'
+    0x00, 0x56, 0x36, 0x00, 0x00, // ';'
+    0x08, 0x14, 0x22, 0x41, 0x00, // '<'
+    0x14, 0x14, 0x14, 0x14, 0x14, // '='
+    0x00, 0x41, 0x22, 0x14, 0x08, // '>'
+    0x02, 0x01, 0x51, 0x09, 0x06, // '?'
+    0x3E, 0x41, 0x5D, 0x59, 0x4E, // '@'
+    0x7C, 0x12, 0x11, 0x12, 0x7C, // 'A'
+    0x7F, 0x49, 0x49, 0x49, 0x36, // 'B'
+    0x3E, 0x41, 0x41, 0x41, 0x22, // 'C'
+    0x7F, 0x41, 0x41, 0x22, 0x1C, // 'D'
+    0x7F, 0x49, 0x49, 0x49, 0x41, // 'E'
+    0x7F, 0x09, 0x09, 0x09, 0x01, // 'F'
+    0x3E, 0x41, 0x49, 0x49, 0x7A, // 'G'
+    0x7F, 0x08, 0x08, 0x08, 0x7F, // 'H'
+    0x00, 0x41, 0x7F, 0x41, 0x00, // 'I'
+    0x20, 0x40, 0x41, 0x3F, 0x01, // 'J'
+    0x7F, 0x08, 0x14, 0x22, 0x41, // 'K'
+    0x7F, 0x40, 0x40, 0x40, 0x40, // 'L'
+    0x7F, 0x02, 0x0C, 0x02, 0x7F, // 'M'
+    0x7F, 0x04, 0x08, 0x10, 0x7F, // 'N'
+    0x3E, 0x41, 0x41, 0x41, 0x3E, // 'O'
+    0x7F, 0x09, 0x09, 0x09, 0x06, // 'P'
+    0x3E, 0x41, 0x51, 0x21, 0x5E, // 'Q'
+    0x7F, 0x09, 0x19, 0x29, 0x46, // 'R'
+    0x46, 0x49, 0x49, 0x49, 0x31, // 'S'
+    0x01, 0x01, 0x7F, 0x01, 0x01, // 'T'
+    0x3F, 0x40, 0x40, 0x40, 0x3F, // 'U'
+    0x1F, 0x20, 0x40, 0x20, 0x1F, // 'V'
+    0x3F, 0x40, 0x38, 0x40, 0x3F, // 'W'
+    0x63, 0x14, 0x08, 0x14, 0x63, // 'X'
+    0x07, 0x08, 0x70, 0x08, 0x07, // 'Y'
+    0x61, 0x51, 0x49, 0x45, 0x43, // 'Z'
+    0x00, 0x7F, 0x41, 0x41, 0x00, // '['
+    0x02, 0x04, 0x08, 0x10, 0x20, // '\'
+    0x00, 0x41, 0x41, 0x7F, 0x00, // ']'
+    0x04, 0x02, 0x01, 0x02, 0x04, // '^'
+    0x40, 0x40, 0x40, 0x40, 0x40, // '_'
+    0x00, 0x01, 0x02, 0x04, 0x00, // '`'
+    0x20, 0x54, 0x54, 0x54, 0x78, // 'a'
+    0x7F, 0x48, 0x44, 0x44, 0x38, // 'b'
+    0x38, 0x44, 0x44, 0x44, 0x20, // 'c'
+    0x38, 0x44, 0x44, 0x48, 0x7F, // 'd'
+    0x38, 0x54, 0x54, 0x54, 0x18, // 'e'
+    0x08, 0x7E, 0x09, 0x01, 0x02, // 'f'
+    0x18, 0xA4, 0xA4, 0xA4, 0x7C, // 'g'
+    0x7F, 0x08, 0x04, 0x04, 0x78, // 'h'
+    0x00, 0x44, 0x7D, 0x40, 0x00, // 'i'
+    0x40, 0x80, 0x84, 0x7D, 0x00, // 'j'
+    0x7F, 0x10, 0x28, 0x44, 0x00, // 'k'
+    0x00, 0x41, 0x7F, 0x40, 0x00, // 'l'
+    0x7C, 0x04, 0x18, 0x04, 0x78, // 'm'
+    0x7C, 0x08, 0x04, 0x04, 0x78, // 'n'
+    0x38, 0x44, 0x44, 0x44, 0x38, // 'o'
+    0xFC, 0x24, 0x24, 0x24, 0x18, // 'p'
+    0x18, 0x24, 0x24, 0x24, 0xFC, // 'q'
+    0x7C, 0x08, 0x04, 0x04, 0x08, // 'r'
+    0x48, 0x54, 0x54, 0x54, 0x20, // 's'
+    0x04, 0x3F, 0x44, 0x40, 0x20, // 't'
+    0x3C, 0x40, 0x40, 0x20, 0x7C, // 'u'
+    0x1C, 0x20, 0x40, 0x20, 0x1C, // 'v'
+    0x3C, 0x40, 0x30, 0x40, 0x3C, // 'w'
+    0x44, 0x28, 0x10, 0x28, 0x44, // 'x'
+    0x1C, 0xA0, 0xA0, 0xA0, 0x7C, // 'y'
+    0x44, 0x64, 0x54, 0x4C, 0x44, // 'z'
+    0x00, 0x08, 0x36, 0x41, 0x00, // '{'
+    0x00, 0x00, 0x7F, 0x00, 0x00, // '|'
+    0x00, 0x41, 0x36, 0x08, 0x00, // '}'
+    0x08, 0x04, 0x08, 0x10, 0x08, // '~'
+];
+
+/// How text is drawn.
+#[derive(Clone, Copy)]
+pub struct TextStyle {
+    pub font: &'static Font,
+    /// How many screen pixels wide and high each pixel of the font is.
+    pub scale: usize,
+    pub foreground: u32,
+    pub background: u32,
+}
+
+/// A rectangle of pixels.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Rect {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Rect {
+    pub const fn new(x: usize, y: usize, width: usize, height: usize) -> Rect {
+        Rect {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    fn contains(&self, x: usize, y: usize) -> bool {
+        x >= self.x && x < self.x + self.width && y >= self.y && y < self.y + self.height
+    }
+}
+
+/// Pixels drawn by `Graphics::draw`.
+pub trait PixelSource {
+    /// The colour of pixel (`x`, `y`) of the rectangle being drawn, as
+    /// `0xAARRGGBB`.
+    fn pixel(&self, x: usize, y: usize) -> u32;
+}
+
+pub trait GraphicsClient {
+    /// A rectangle, line or `draw` finished.
+    fn command_complete(&self, r: Result<(), ErrorCode>);
+
+    /// Text or a bitmap finished, and its buffer is returned.
+    fn write_complete(&self, buffer: SubSliceMut<'static, u8>, r: Result<(), ErrorCode>);
+}
+
+/// Steps along a line with Bresenham's algorithm, in runs of pixels in the
+/// same row or column, which are each written as one frame.
+#[derive(Clone, Copy)]
+struct Line {
+    x: isize,
+    y: isize,
+    x1: isize,
+    y1: isize,
+    dx: isize,
+    dy: isize,
+    sx: isize,
+    sy: isize,
+    err: isize,
+    done: bool,
+}
+
+impl Line {
+    fn new(x0: usize, y0: usize, x1: usize, y1: usize) -> Line {
+        let (x0, y0, x1, y1) = (x0 as isize, y0 as isize, x1 as isize, y1 as isize);
+        let dx = (x1 - x0).abs();
+        let dy = -(y1 - y0).abs();
+        Line {
+            x: x0,
+            y: y0,
+            x1,
+            y1,
+            dx,
+            dy,
+            sx: if x0 < x1 { 1 } else { -1 },
+            sy: if y0 < y1 { 1 } else { -1 },
+            err: dx + dy,
+            done: false,
+        }
+    }
+
+    fn step(&mut self) -> Option<(isize, isize)> {
+        if self.done {
+            return None;
+        }
+        let point = (self.x, self.y);
+        if self.x == self.x1 && self.y == self.y1 {
+            self.done = true;
+        } else {
+            let e2 = 2 * self.err;
+            if e2 >= self.dy {
+                self.err += self.dy;
+                self.x += self.sx;
+            }
+            if e2 <= self.dx {
+                self.err += self.dx;
+                self.y += self.sy;
+            }
+        }
+        Some(point)
+    }
+
+    fn next_run(&mut self) -> Option<Rect> {
+        let (x, y) = self.step()?;
+        let (mut last_x, mut last_y) = (x, y);
+        loop {
+            // Extend the run along its row or column while the line does.
+            let mut next = *self;
+            match next.step() {
+                Some((nx, ny)) if ny == y && last_y == y && (nx - last_x).abs() == 1 => {
+                    last_x = nx;
+                }
+                Some((nx, ny)) if nx == x && last_x == x && (ny - last_y).abs() == 1 => {
+                    last_y = ny;
+                }
+                _ => break,
+            }
+            *self = next;
+        }
+        Some(Rect::new(
+            cmp::min(x, last_x) as usize,
+            cmp::min(y, last_y) as usize,
+            (x - last_x).unsigned_abs() + 1,
+            (y - last_y).unsigned_abs() + 1,
+        ))
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Shape {
+    Fill(u32),
+    Outline(u32),
+    Line(Line, u32),
+    Text(TextStyle),
+    Bitmap(ScreenPixelFormat),
+    Source,
+}
+
+pub struct Graphics<'a> {
+    screen: &'a dyn Screen<'a>,
+    client: OptionalCell<&'a dyn GraphicsClient>,
+    source: OptionalCell<&'a dyn PixelSource>,
+    /// The staging buffer pixels are written out through.
+    buffer: TakeCell<'static, [u8]>,
+    background: Cell<u32>,
+    /// The shape being drawn.
+    shape: OptionalCell<Shape>,
+    /// The rectangle the shape is drawn in.
+    area: Cell<Rect>,
+    /// The number of parts of the shape drawn, for rectangle outlines.
+    parts: Cell<usize>,
+    /// The part of the shape being written.
+    part: Cell<Rect>,
+    /// The frame the part is written to.
+    frame: Cell<Rect>,
+    /// The number of pixels of the frame written.
+    position: Cell<usize>,
+    /// The text or bitmap being drawn.
+    data: MapCell<SubSliceMut<'static, u8>>,
+}
+
+impl<'a> Graphics<'a> {
+    pub fn new(screen: &'a dyn Screen<'a>, buffer: &'static mut [u8]) -> Graphics<'a> {
+        Graphics {
+            screen,
+            client: OptionalCell::empty(),
+            source: OptionalCell::empty(),
+            buffer: TakeCell::new(buffer),
+            background: Cell::new(0xFF00_0000),
+            shape: OptionalCell::empty(),
+            area: Cell::new(Rect::new(0, 0, 0, 0)),
+            parts: Cell::new(0),
+            part: Cell::new(Rect::new(0, 0, 0, 0)),
+            frame: Cell::new(Rect::new(0, 0, 0, 0)),
+            position: Cell::new(0),
+            data: MapCell::empty(),
+        }
+    }
+
+    pub fn set_client(&self, client: &'a dyn GraphicsClient) {
+        self.client.set(client);
+    }
+
+    pub fn get_resolution(&self) -> (usize, usize) {
+        self.screen.get_resolution()
+    }
+
+    pub fn get_pixel_format(&self) -> ScreenPixelFormat {
+        self.screen.get_pixel_format()
+    }
+
+    /// Set the colour pixels added around the frames on monochrome screens
+    /// are painted with.
+    pub fn set_background(&self, color: u32) {
+        self.background.set(color);
+    }
+
+    /// Fill `rect` with `color`.
+    pub fn fill_rect(&self, rect: Rect, color: u32) -> Result<(), ErrorCode> {
+        self.start(Shape::Fill(color), rect)
+    }
+
+    /// Draw the one pixel wide outline of `rect` in `color`.
+    pub fn draw_rect(&self, rect: Rect, color: u32) -> Result<(), ErrorCode> {
+        self.start(Shape::Outline(color), rect)
+    }
+
+    /// Draw a line from (`x0`, `y0`) to (`x1`, `y1`), both included, in
+    /// `color`.
+    pub fn draw_line(
+        &self,
+        x0: usize,
+        y0: usize,
+        x1: usize,
+        y1: usize,
+        color: u32,
+    ) -> Result<(), ErrorCode> {
+        let area = Rect::new(
+            cmp::min(x0, x1),
+            cmp::min(y0, y1),
+            x0.abs_diff(x1) + 1,
+            y0.abs_diff(y1) + 1,
+        );
+        self.start(Shape::Line(Line::new(x0, y0, x1, y1), color), area)
+    }
+
+    /// Draw the characters of `text` on one line, with the top left corner
+    /// of the first at (`x`, `y`).
+    pub fn draw_text(
+        &self,
+        x: usize,
+        y: usize,
+        text: SubSliceMut<'static, u8>,
+        style: TextStyle,
+    ) -> Result<(), (ErrorCode, SubSliceMut<'static, u8>)> {
+        let (width, height) = style.font.cell_size(style.scale);
+        let area = Rect::new(x, y, width * text.len(), height);
+        self.start_with_data(Shape::Text(style), area, text)
+    }
+
+    /// Draw `bitmap`, `rect.width` by `rect.height` pixels in `format`, in
+    /// `rect`. Each row starts on a byte boundary.
+    pub fn draw_bitmap(
+        &self,
+        rect: Rect,
+        format: ScreenPixelFormat,
+        bitmap: SubSliceMut<'static, u8>,
+    ) -> Result<(), (ErrorCode, SubSliceMut<'static, u8>)> {
+        if bitmap.len() < row_bytes(format, rect.width) * rect.height {
+            return Err((ErrorCode::SIZE, bitmap));
+        }
+        self.start_with_data(Shape::Bitmap(format), rect, bitmap)
+    }
+
+    /// Set where `draw` gets pixels from.
+    pub fn set_pixel_source(&self, source: &'a dyn PixelSource) {
+        self.source.set(source);
+    }
+
+    /// Draw `rect` with the pixels of the pixel source.
+    pub fn draw(&self, rect: Rect) -> Result<(), ErrorCode> {
+        self.start(Shape::Source, rect)
+    }
+
+    fn start_with_data(
+        &self,
+        shape: Shape,
+        area: Rect,
+        data: SubSliceMut<'static, u8>,
+    ) -> Result<(), (ErrorCode, SubSliceMut<'static, u8>)> {
+        if self.shape.is_some() {
+            return Err((ErrorCode::BUSY, data));
+        }
+        self.data.replace(data);
+        self.start(shape, area)
+            .map_err(|e| (e, self.data.take().unwrap()))
+    }
+
+    fn start(&self, shape: Shape, area: Rect) -> Result<(), ErrorCode> {
+        if self.shape.is_some() {
+            return Err(ErrorCode::BUSY);
+        }
+        let (width, height) = self.screen.get_resolution();
+        if area.width == 0
+            || area.height == 0
+            || area.x + area.width > width
+            || area.y + area.height > height
+        {
+            return Err(ErrorCode::INVAL);
+        }
+
+        self.shape.set(shape);
+        self.area.set(area);
+        self.parts.set(0);
+        self.next_part()
+            .ok_or(ErrorCode::INVAL)
+            .and_then(|part| self.write_frame(part))
+            .inspect_err(|_| self.shape.clear())
+    }
+
+    /// The next part of the shape to write, if any.
+    fn next_part(&self) -> Option<Rect> {
+        let area = self.area.get();
+        let parts = self.parts.get();
+        self.parts.set(parts + 1);
+        match self.shape.get()? {
+            Shape::Outline(_) => {
+                // Top, bottom, left and right, skipping sides that overlap.
+                let (right, bottom) = (area.x + area.width - 1, area.y + area.height - 1);
+                let sides = [
+                    Some(Rect::new(area.x, area.y, area.width, 1)),
+                    (area.height > 1).then(|| Rect::new(area.x, bottom, area.width, 1)),
+                    (area.height > 2).then(|| Rect::new(area.x, area.y + 1, 1, area.height - 2)),
+                    (area.height > 2 && area.width > 1)
+                        .then(|| Rect::new(right, area.y + 1, 1, area.height - 2)),
+                ];
+                let side = sides.iter().skip(parts).position(Option::is_some)?;
+                self.parts.set(parts + side + 1);
+                sides[parts + side]
+            }
+            Shape::Line(mut line, color) => {
+                let run = line.next_run();
+                self.shape.set(Shape::Line(line, color));
+                run
+            }
+            _ => (parts == 0).then_some(area),
+        }
+    }
+
+    /// Set the frame to write `part` to.
+    fn write_frame(&self, part: Rect) -> Result<(), ErrorCode> {
+        let mut frame = part;
+        if self.screen.get_pixel_format() == ScreenPixelFormat::Mono {
+            let (width, _) = self.screen.get_resolution();
+            let end = cmp::min((part.x + part.width).next_multiple_of(8), width);
+            frame.x = part.x & !7;
+            frame.width = end - frame.x;
+        }
+        self.part.set(part);
+        self.frame.set(frame);
+        self.position.set(0);
+        self.screen
+            .set_write_frame(frame.x, frame.y, frame.width, frame.height)
+    }
+
+    /// The colour of the pixel at (`x`, `y`) on the screen.
+    fn pixel(&self, shape: Shape, x: usize, y: usize) -> u32 {
+        if !self.part.get().contains(x, y) {
+            return self.background.get();
+        }
+        let area = self.area.get();
+        let (x, y) = (x - area.x, y - area.y);
+        match shape {
+            Shape::Fill(color) | Shape::Outline(color) | Shape::Line(_, color) => color,
+            Shape::Text(style) => {
+                let (width, _) = style.font.cell_size(style.scale);
+                let c = self.data.map_or(0, |text| text[x / width]);
+                if style
+                    .font
+                    .pixel(c, x % width / style.scale, y / style.scale)
+                {
+                    style.foreground
+                } else {
+                    style.background
+                }
+            }
+            Shape::Bitmap(format) => self.data.map_or(0, |bitmap| {
+                let row = &bitmap[y * row_bytes(format, area.width)..];
+                decode(format, read_pixel(format, row, x))
+            }),
+            Shape::Source => self
+                .source
+                .map_or(self.background.get(), |source| source.pixel(x, y)),
+        }
+    }
+
+    /// Fill `buffer` with the next pixels of the frame, returning the number
+    /// of bytes used.
+    fn fill_buffer(&self, buffer: &mut [u8]) -> usize {
+        let Some(shape) = self.shape.get() else {
+            return 0;
+        };
+        let format = self.screen.get_pixel_format();
+        let bits = format.get_bits_per_pixel();
+        let frame = self.frame.get();
+        let position = self.position.get();
+        let count = cmp::min(
+            buffer.len() * 8 / bits,
+            frame.width * frame.height - position,
+        );
+        for i in 0..count {
+            let x = frame.x + (position + i) % frame.width;
+            let y = frame.y + (position + i) / frame.width;
+            write_pixel(format, buffer, i, encode(format, self.pixel(shape, x, y)));
+        }
+        self.position.set(position + count);
+        (count * bits).div_ceil(8)
+    }
+
+    /// Write the next pixels of the frame from `buffer`.
+    fn write_pixels(&self, buffer: &'static mut [u8], continue_write: bool) {
+        let len = self.fill_buffer(buffer);
+        let mut data = SubSliceMut::new(buffer);
+        data.slice(..len);
+        if let Err(e) = self.screen.write(data, continue_write) {
+            self.finish(Err(e));
+        }
+    }
+
+    fn finish(&self, r: Result<(), ErrorCode>) {
+        self.shape.clear();
+        match self.data.take() {
+            Some(data) => self.client.map(|client| client.write_complete(data, r)),
+            None => self.client.map(|client| client.command_complete(r)),
+        };
+    }
+}
+
+impl<'a> ScreenClient for Graphics<'a> {
+    fn command_complete(&self, r: Result<(), ErrorCode>) {
+        if self.shape.is_none() {
+            return;
+        }
+        match r {
+            Ok(()) => match self.buffer.take() {
+                Some(buffer) => self.write_pixels(buffer, false),
+                None => self.finish(Err(ErrorCode::NOMEM)),
+            },
+            Err(e) => self.finish(Err(e)),
+        }
+    }
+
+    fn write_complete(&self, data: SubSliceMut<'static, u8>, r: Result<(), ErrorCode>) {
+        let buffer = data.take();
+        let frame = self.frame.get();
+        if r.is_ok() && self.position.get() < frame.width * frame.height {
+            self.write_pixels(buffer, true);
+            return;
+        }
+
+        self.buffer.replace(buffer);
+        if r.is_err() {
+            self.finish(r);
+            return;
+        }
+        match self.next_part() {
+            Some(part) => {
+                if let Err(e) = self.write_frame(part) {
+                    self.finish(Err(e));
+                }
+            }
+            None => self.finish(Ok(())),
+        }
+    }
+
+    fn screen_is_ready(&self) {}
+}
@@ -0,0 +1,413 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Provides userspace with drawing primitives on a screen.
+//!
+//! Lets processes fill and outline rectangles, draw lines, text in the
+//! built-in 5x8 font and bitmaps in any pixel format through `Graphics`,
+//! without a graphics library of their own. Each process can have one
+//! drawing command pending; commands from different processes are run in
+//! turn.
+//!
+//! Bitmaps are copied from the process a few rows at a time, as many as fit
+//! in the driver's buffer.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! let graphics_driver = components::graphics::GraphicsDriverComponent::new(
+//!     board_kernel,
+//!     capsules_extra::graphics_driver::DRIVER_NUM,
+//!     graphics,
+//! )
+//! .finalize(components::graphics_driver_component_static!(1024));
+//! ```
+
+use core::cell::Cell;
+use core::cmp;
+
+use kernel::grant::{AllowRoCount, AllowRwCount, Grant, UpcallCount};
+use kernel::hil::screen::ScreenPixelFormat;
+use kernel::processbuffer::ReadableProcessBuffer;
+use kernel::syscall::{CommandReturn, SyscallDriver};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::utilities::leasable_buffer::SubSliceMut;
+use kernel::{ErrorCode, ProcessId};
+
+use crate::graphics::{self, Graphics, GraphicsClient, Rect, TextStyle, FONT_5X8};
+
+/// Syscall driver number.
+use capsules_core::driver;
+pub const DRIVER_NUM: usize = driver::NUM::Graphics as usize;
+
+/// Ids for read-only allow buffers
+mod ro_allow {
+    /// Text or bitmap to draw.
+    pub const DATA: usize = 0;
+    /// The number of allow buffers the kernel stores for this grant
+    pub const COUNT: u8 = 1;
+}
+
+mod upcall {
+    /// A drawing command finished.
+    pub const DONE: usize = 0;
+    /// The number of upcalls the kernel stores for this grant
+    pub const COUNT: u8 = 1;
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Command {
+    FillRect(Rect),
+    DrawRect(Rect),
+    DrawLine {
+        x0: usize,
+        y0: usize,
+        x1: usize,
+        y1: usize,
+    },
+    DrawText {
+        x: usize,
+        y: usize,
+        len: usize,
+    },
+    DrawBitmap(Rect),
+}
+
+pub struct App {
+    command: Option<Command>,
+    foreground: u32,
+    background: u32,
+    scale: usize,
+    /// The format of bitmaps, the screen's if not set.
+    format: Option<ScreenPixelFormat>,
+    /// The number of rows of the bitmap drawn.
+    bitmap_rows: usize,
+}
+
+impl Default for App {
+    fn default() -> App {
+        App {
+            command: None,
+            foreground: 0xFFFF_FFFF,
+            background: 0xFF00_0000,
+            scale: 1,
+            format: None,
+            bitmap_rows: 0,
+        }
+    }
+}
+
+/// Unpack a rectangle from `x << 16 | y` and `width << 16 | height`.
+fn rect_from(data1: usize, data2: usize) -> Rect {
+    Rect::new(
+        (data1 >> 16) & 0xFFFF,
+        data1 & 0xFFFF,
+        (data2 >> 16) & 0xFFFF,
+        data2 & 0xFFFF,
+    )
+}
+
+pub struct GraphicsDriver<'a> {
+    graphics: &'a Graphics<'a>,
+    apps: Grant<
+        App,
+        UpcallCount<{ upcall::COUNT }>,
+        AllowRoCount<{ ro_allow::COUNT }>,
+        AllowRwCount<0>,
+    >,
+    current_process: OptionalCell<ProcessId>,
+    /// Holds the text or bitmap rows being drawn.
+    buffer: TakeCell<'static, [u8]>,
+    /// The number of bitmap rows being drawn.
+    bitmap_rows: Cell<usize>,
+}
+
+impl<'a> GraphicsDriver<'a> {
+    pub fn new(
+        graphics: &'a Graphics<'a>,
+        buffer: &'static mut [u8],
+        grant: Grant<
+            App,
+            UpcallCount<{ upcall::COUNT }>,
+            AllowRoCount<{ ro_allow::COUNT }>,
+            AllowRwCount<0>,
+        >,
+    ) -> GraphicsDriver<'a> {
+        GraphicsDriver {
+            graphics,
+            apps: grant,
+            current_process: OptionalCell::empty(),
+            buffer: TakeCell::new(buffer),
+            bitmap_rows: Cell::new(0),
+        }
+    }
+
+    /// Change the drawing settings of a process, unless it has a command
+    /// pending.
+    fn configure(&self, processid: ProcessId, f: impl FnOnce(&mut App)) -> CommandReturn {
+        self.apps
+            .enter(processid, |app, _| {
+                if app.command.is_some() {
+                    Err(ErrorCode::BUSY)
+                } else {
+                    f(app);
+                    Ok(())
+                }
+            })
+            .unwrap_or_else(|err| Err(err.into()))
+            .into()
+    }
+
+    fn enqueue_command(&self, processid: ProcessId, command: Command) -> CommandReturn {
+        let res = self
+            .apps
+            .enter(processid, |app, _| {
+                if app.command.is_some() {
+                    Err(ErrorCode::BUSY)
+                } else {
+                    app.command = Some(command);
+                    app.bitmap_rows = 0;
+                    Ok(())
+                }
+            })
+            .unwrap_or_else(|err| Err(err.into()));
+
+        if res.is_ok() && self.current_process.is_none() {
+            self.current_process.set(processid);
+            if let Err(e) = self.run_command(processid) {
+                self.current_process.clear();
+                let _ = self.apps.enter(processid, |app, _| app.command = None);
+                return CommandReturn::failure(e);
+            }
+        }
+        res.into()
+    }
+
+    /// Start, or continue drawing the bitmap of, the pending command of
+    /// `processid`.
+    fn run_command(&self, processid: ProcessId) -> Result<(), ErrorCode> {
+        self.apps
+            .enter(processid, |app, kernel_data| {
+                let command = app.command.ok_or(ErrorCode::FAIL)?;
+                self.graphics.set_background(app.background);
+                match command {
+                    Command::FillRect(rect) => self.graphics.fill_rect(rect, app.foreground),
+                    Command::DrawRect(rect) => self.graphics.draw_rect(rect, app.foreground),
+                    Command::DrawLine { x0, y0, x1, y1 } => {
+                        self.graphics.draw_line(x0, y0, x1, y1, app.foreground)
+                    }
+                    Command::DrawText { x, y, len } => {
+                        let buffer = self.buffer.take().ok_or(ErrorCode::NOMEM)?;
+                        let len = kernel_data
+                            .get_readonly_processbuffer(ro_allow::DATA)
+                            .and_then(|data| {
+                                data.enter(|data| {
+                                    let len = cmp::min(len, cmp::min(data.len(), buffer.len()));
+                                    data[..len].copy_to_slice(&mut buffer[..len]);
+                                    len
+                                })
+                            })
+                            .unwrap_or(0);
+                        let mut text = SubSliceMut::new(buffer);
+                        text.slice(..len);
+                        let style = TextStyle {
+                            font: &FONT_5X8,
+                            scale: app.scale,
+                            foreground: app.foreground,
+                            background: app.background,
+                        };
+                        self.graphics
+                            .draw_text(x, y, text, style)
+                            .map_err(|(e, text)| {
+                                self.buffer.replace(text.take());
+                                e
+                            })
+                    }
+                    Command::DrawBitmap(rect) => {
+                        if rect.width == 0 || rect.height == 0 {
+                            return Err(ErrorCode::INVAL);
+                        }
+                        let format = app.format.unwrap_or(self.graphics.get_pixel_format());
+                        let row_len = graphics::row_bytes(format, rect.width);
+                        let buffer = self.buffer.take().ok_or(ErrorCode::NOMEM)?;
+                        let rows = cmp::min(buffer.len() / row_len, rect.height - app.bitmap_rows);
+                        let start = app.bitmap_rows * row_len;
+                        let copied = kernel_data
+                            .get_readonly_processbuffer(ro_allow::DATA)
+                            .and_then(|data| {
+                                data.enter(|data| {
+                                    data.get(start..start + rows * row_len)
+                                        .map(|chunk| {
+                                            chunk.copy_to_slice(&mut buffer[..chunk.len()])
+                                        })
+                                        .is_some()
+                                })
+                            })
+                            .unwrap_or(false);
+                        if rows == 0 || !copied {
+                            self.buffer.replace(buffer);
+                            return Err(ErrorCode::SIZE);
+                        }
+
+                        self.bitmap_rows.set(rows);
+                        let mut bitmap = SubSliceMut::new(buffer);
+                        bitmap.slice(..rows * row_len);
+                        self.graphics
+                            .draw_bitmap(
+                                Rect::new(rect.x, rect.y + app.bitmap_rows, rect.width, rows),
+                                format,
+                                bitmap,
+                            )
+                            .map_err(|(e, bitmap)| {
+                                self.buffer.replace(bitmap.take());
+                                e
+                            })
+                    }
+                }
+            })
+            .unwrap_or_else(|err| Err(err.into()))
+    }
+
+    /// Notify `processid` that its command finished.
+    fn command_done(&self, processid: ProcessId, r: Result<(), ErrorCode>) {
+        let _ = self.apps.enter(processid, |app, kernel_data| {
+            app.command = None;
+            kernel_data
+                .schedule_upcall(upcall::DONE, (kernel::errorcode::into_statuscode(r), 0, 0))
+                .ok();
+        });
+    }
+
+    /// Finish the current command and start the next pending one.
+    fn run_next_command(&self, r: Result<(), ErrorCode>) {
+        if let Some(processid) = self.current_process.take() {
+            self.command_done(processid, r);
+        }
+
+        for cntr in self.apps.iter() {
+            let processid = cntr.processid();
+            if cntr.enter(|app, _| app.command.is_some()) {
+                self.current_process.set(processid);
+                match self.run_command(processid) {
+                    Ok(()) => return,
+                    Err(e) => {
+                        self.current_process.clear();
+                        self.command_done(processid, Err(e));
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl<'a> GraphicsClient for GraphicsDriver<'a> {
+    fn command_complete(&self, r: Result<(), ErrorCode>) {
+        self.run_next_command(r);
+    }
+
+    fn write_complete(&self, buffer: SubSliceMut<'static, u8>, r: Result<(), ErrorCode>) {
+        self.buffer.replace(buffer.take());
+
+        // Draw the next rows of a bitmap.
+        let more_rows = r.is_ok()
+            && self.current_process.map_or(false, |processid| {
+                self.apps
+                    .enter(processid, |app, _| {
+                        app.bitmap_rows += self.bitmap_rows.get();
+                        matches!(app.command, Some(Command::DrawBitmap(rect)) if app.bitmap_rows < rect.height)
+                    })
+                    .unwrap_or(false)
+            });
+        if more_rows {
+            let result = self
+                .current_process
+                .map_or(Err(ErrorCode::FAIL), |processid| {
+                    self.run_command(processid)
+                });
+            if let Err(e) = result {
+                self.run_next_command(Err(e));
+            }
+        } else {
+            self.run_next_command(r);
+        }
+    }
+}
+
+impl<'a> SyscallDriver for GraphicsDriver<'a> {
+    /// Drawing commands.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver existence check.
+    /// - `1`: Get the resolution of the screen, as width and height.
+    /// - `2`: Get the pixel format of the screen.
+    /// - `3`: Set the foreground colour to `data1`, as `0xAARRGGBB`.
+    /// - `4`: Set the background colour to `data1`.
+    /// - `5`: Set the text scale to `data1`.
+    /// - `6`: Set the pixel format of bitmaps to `data1`, numbered as by the
+    ///   screen driver.
+    /// - `10`: Fill the rectangle at `data1 = x << 16 | y` of size
+    ///   `data2 = width << 16 | height` with the foreground colour.
+    /// - `11`: Outline the rectangle given as for `10`.
+    /// - `12`: Draw a line from `data1 = x0 << 16 | y0` to
+    ///   `data2 = x1 << 16 | y1`.
+    /// - `13`: Draw the first `data2` characters of the read-only allow
+    ///   buffer at `data1 = x << 16 | y`.
+    /// - `14`: Draw the bitmap in the read-only allow buffer in the
+    ///   rectangle given as for `10`.
+    fn command(
+        &self,
+        command_num: usize,
+        data1: usize,
+        data2: usize,
+        processid: ProcessId,
+    ) -> CommandReturn {
+        match command_num {
+            0 => CommandReturn::success(),
+            1 => {
+                let (width, height) = self.graphics.get_resolution();
+                CommandReturn::success_u32_u32(width as u32, height as u32)
+            }
+            2 => CommandReturn::success_u32(self.graphics.get_pixel_format() as u32),
+            3 => self.configure(processid, |app| app.foreground = data1 as u32),
+            4 => self.configure(processid, |app| app.background = data1 as u32),
+            5 => {
+                if data1 == 0 {
+                    return CommandReturn::failure(ErrorCode::INVAL);
+                }
+                self.configure(processid, |app| app.scale = data1)
+            }
+            6 => match graphics::pixel_format_from(data1) {
+                Some(format) => self.configure(processid, |app| app.format = Some(format)),
+                None => CommandReturn::failure(ErrorCode::INVAL),
+            },
+            10 => self.enqueue_command(processid, Command::FillRect(rect_from(data1, data2))),
+            11 => self.enqueue_command(processid, Command::DrawRect(rect_from(data1, data2))),
+            12 => self.enqueue_command(
+                processid,
+                Command::DrawLine {
+                    x0: (data1 >> 16) & 0xFFFF,
+                    y0: data1 & 0xFFFF,
+                    x1: (data2 >> 16) & 0xFFFF,
+                    y1: data2 & 0xFFFF,
+                },
+            ),
+            13 => self.enqueue_command(
+                processid,
+                Command::DrawText {
+                    x: (data1 >> 16) & 0xFFFF,
+                    y: data1 & 0xFFFF,
+                    len: data2,
+                },
+            ),
+            14 => self.enqueue_command(processid, Command::DrawBitmap(rect_from(data1, data2))),
+            _ => CommandReturn::failure(ErrorCode::NOSUPPORT),
+        }
+    }
+
+    fn allocate_grant(&self, processid: ProcessId) -> Result<(), kernel::process::Error> {
+        self.apps.enter(processid, |_, _| {})
+    }
+}
@@ -52,6 +52,7 @@ use core::cell::Cell;
 
 use kernel::grant::{AllowRoCount, AllowRwCount, Grant, UpcallCount};
 use kernel::hil::digest;
+use kernel::hil::keystore::{KeyHandle, KeyStore, KeyUsage};
 use kernel::processbuffer::{ReadableProcessBuffer, WriteableProcessBuffer};
 use kernel::syscall::{CommandReturn, SyscallDriver};
 use kernel::utilities::cells::{OptionalCell, TakeCell};
@@ -86,6 +87,7 @@ pub struct HmacDriver<'a, H: digest::Digest<'a, L>, const L: usize> {
     data_buffer: TakeCell<'static, [u8]>,
     data_copied: Cell<usize>,
     dest_buffer: TakeCell<'static, [u8; L]>,
+    keystore: OptionalCell<&'a dyn KeyStore>,
 }
 
 impl<
@@ -113,6 +115,20 @@ impl<
             data_buffer: TakeCell::new(data_buffer),
             data_copied: Cell::new(0),
             dest_buffer: TakeCell::new(dest_buffer),
+            keystore: OptionalCell::empty(),
+        }
+    }
+
+    /// Use `keystore` for apps that pass a key handle instead of a key.
+    pub fn set_keystore(&self, keystore: &'a dyn KeyStore) {
+        self.keystore.set(keystore);
+    }
+
+    fn set_key(&self, op: &ShaOperation, key: &[u8]) -> Result<(), ErrorCode> {
+        match op {
+            ShaOperation::Sha256 => self.hmac.set_mode_hmacsha256(key),
+            ShaOperation::Sha384 => self.hmac.set_mode_hmacsha384(key),
+            ShaOperation::Sha512 => self.hmac.set_mode_hmacsha512(key),
         }
     }
 
@@ -120,33 +136,29 @@ impl<
         self.processid.map_or(Err(ErrorCode::RESERVE), |processid| {
             self.apps
                 .enter(processid, |app, kernel_data| {
-                    kernel_data
-                        .get_readonly_processbuffer(ro_allow::KEY)
-                        .and_then(|key| {
-                            key.enter(|k| {
-                                if let Some(op) = &app.sha_operation {
+                    let op = app.sha_operation.as_ref().ok_or(ErrorCode::INVAL)?;
+                    match (app.key_handle, self.keystore.get()) {
+                        (Some(handle), Some(keystore)) => keystore.with_key(
+                            handle,
+                            processid.short_app_id(),
+                            KeyUsage::Hmac,
+                            &mut |key| self.set_key(op, key),
+                        )?,
+                        (Some(_), None) => return Err(ErrorCode::NOSUPPORT),
+                        (None, _) => kernel_data
+                            .get_readonly_processbuffer(ro_allow::KEY)
+                            .and_then(|key| {
+                                key.enter(|k| {
                                     let mut tmp_key_buffer: [u8; TMP_KEY_BUFFER_SIZE] =
                                         [0; TMP_KEY_BUFFER_SIZE];
                                     let key_len = core::cmp::min(k.len(), TMP_KEY_BUFFER_SIZE);
                                     k[..key_len].copy_to_slice(&mut tmp_key_buffer[..key_len]);
 
-                                    match op {
-                                        ShaOperation::Sha256 => self
-                                            .hmac
-                                            .set_mode_hmacsha256(&tmp_key_buffer[..key_len]),
-                                        ShaOperation::Sha384 => self
-                                            .hmac
-                                            .set_mode_hmacsha384(&tmp_key_buffer[..key_len]),
-                                        ShaOperation::Sha512 => self
-                                            .hmac
-                                            .set_mode_hmacsha512(&tmp_key_buffer[..key_len]),
-                                    }
-                                } else {
-                                    Err(ErrorCode::INVAL)
-                                }
+                                    self.set_key(op, &tmp_key_buffer[..key_len])
+                                })
                             })
-                        })
-                        .unwrap_or(Err(ErrorCode::RESERVE))?;
+                            .unwrap_or(Err(ErrorCode::RESERVE))?,
+                    }
 
                     kernel_data
                         .get_readonly_processbuffer(ro_allow::DATA)
@@ -518,6 +530,10 @@ impl<
     /// - `1`: run
     /// - `2`: update
     /// - `3`: finish
+    /// - `4`: verify
+    /// - `5`: verify_finish
+    /// - `6`: set_key_handle, use the keystore key with handle `data1`
+    /// - `7`: clear_key_handle, use the key buffer again
     fn command(
         &self,
         command_num: usize,
@@ -739,6 +755,25 @@ impl<
                         }
                     }
 
+                    // set_key_handle
+                    // Use the keystore key with handle `data1` instead of the
+                    // key buffer
+                    6 => {
+                        if self.keystore.is_some() {
+                            app.key_handle = Some(KeyHandle::new(data1 as u32));
+                            CommandReturn::success()
+                        } else {
+                            CommandReturn::failure(ErrorCode::NOSUPPORT)
+                        }
+                    }
+
+                    // clear_key_handle
+                    // Use the key buffer again
+                    7 => {
+                        app.key_handle = None;
+                        CommandReturn::success()
+                    }
+
                     // default
                     _ => CommandReturn::failure(ErrorCode::NOSUPPORT),
                 }
@@ -763,4 +798,6 @@ pub struct App {
     pending_run_app: Option<ProcessId>,
     sha_operation: Option<ShaOperation>,
     op: Cell<Option<UserSpaceOp>>,
+    /// Use this keystore key instead of the key buffer.
+    key_handle: Option<KeyHandle>,
 }
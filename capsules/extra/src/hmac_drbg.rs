@@ -0,0 +1,764 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! HMAC-DRBG random number generator, as specified in NIST SP 800-90A.
+//!
+//! `HmacDrbg` sits between an entropy source and users of the `Rng` HIL.
+//! Instead of passing the output of the entropy source straight through, it
+//! uses the entropy to seed a deterministic random bit generator built on
+//! HMAC-SHA256, and returns the output of the generator.
+//!
+//! Every sample from the entropy source goes through the continuous health
+//! tests from NIST SP 800-90B, the repetition count test and the adaptive
+//! proportion test. If a test fails the seed is discarded, the generator is
+//! uninstantiated and the client receives a `FAIL` error.
+//!
+//! The generator is seeded the first time randomness is requested, and is
+//! reseeded from the entropy source after `reseed_interval` requests.
+//! Each request produces `OUTPUT_LEN` bytes, and no additional input or
+//! personalization string is used.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! let sha256 = components::sha::ShaSoftware256Component::new()
+//!     .finalize(components::sha_software_256_component_static!());
+//! let hmac = components::hmac::HmacSha256SoftwareComponent::new(sha256).finalize(
+//!     components::hmac_sha256_software_component_static!(Sha256Software<'static>),
+//! );
+//! let rng = components::hmac_drbg::HmacDrbgRngComponent::new(
+//!     board_kernel,
+//!     capsules_core::rng::DRIVER_NUM,
+//!     &peripherals.trng,
+//!     hmac,
+//!     capsules_extra::hmac_drbg::DEFAULT_RESEED_INTERVAL,
+//! )
+//! .finalize(components::hmac_drbg_rng_component_static!(
+//!     nrf52840::trng::Trng<'static>,
+//!     HmacSha256Software<'static, Sha256Software<'static>>,
+//! ));
+//! ```
+
+use core::cell::Cell;
+
+use kernel::hil::digest;
+use kernel::hil::entropy::{self, Entropy32};
+use kernel::hil::rng::{self, Rng};
+use kernel::utilities::cells::{MapCell, OptionalCell, TakeCell};
+use kernel::utilities::leasable_buffer::{SubSlice, SubSliceMut};
+use kernel::ErrorCode;
+
+/// Length of the HMAC-SHA256 output, and so of `K` and `V`.
+const DIGEST_LEN: usize = 32;
+
+/// Bytes of entropy used to seed the generator. This is 256 bits of entropy
+/// input and a 128 bit nonce, both taken from the entropy source.
+pub const SEED_LEN: usize = 48;
+
+/// Bytes produced by each request to the generator. This is the 1024 bit
+/// output length used by the NIST CAVP test vectors.
+pub const OUTPUT_LEN: usize = 128;
+
+/// Length of the buffer used to pass data to the HMAC, which has to hold
+/// `V || 0x01 || seed`.
+pub const DATA_LEN: usize = DIGEST_LEN + 1 + SEED_LEN;
+
+/// Number of requests between reseeds, if the board has no reason to pick
+/// something else.
+pub const DEFAULT_RESEED_INTERVAL: u32 = 1024;
+
+// The health test cutoffs assume the entropy source provides at least 8 bits
+// of min-entropy in each 32 bit sample, and give a false positive rate of
+// 2^-20 (NIST SP 800-90B, sections 4.4.1 and 4.4.2).
+
+/// A sample repeated this many times in a row fails the repetition count test.
+const RCT_CUTOFF: u32 = 4;
+/// Number of samples in an adaptive proportion test window.
+const APT_WINDOW: u32 = 512;
+/// The first sample of a window appearing this many times in the window fails
+/// the adaptive proportion test.
+const APT_CUTOFF: u32 = 13;
+
+/// The continuous health tests run on each sample from the entropy source.
+struct HealthTests {
+    /// The last sample and how many times in a row it has been seen.
+    rct_sample: Cell<Option<u32>>,
+    rct_count: Cell<u32>,
+    /// The first sample of the current window and how many times it has been
+    /// seen.
+    apt_sample: Cell<Option<u32>>,
+    apt_count: Cell<u32>,
+    /// Number of samples seen in the current window.
+    apt_position: Cell<u32>,
+}
+
+impl HealthTests {
+    const fn new() -> Self {
+        HealthTests {
+            rct_sample: Cell::new(None),
+            rct_count: Cell::new(0),
+            apt_sample: Cell::new(None),
+            apt_count: Cell::new(0),
+            apt_position: Cell::new(0),
+        }
+    }
+
+    fn reset(&self) {
+        self.rct_sample.set(None);
+        self.rct_count.set(0);
+        self.apt_sample.set(None);
+        self.apt_count.set(0);
+        self.apt_position.set(0);
+    }
+
+    /// Run the tests on a new sample. Returns `false` if a test failed.
+    fn check(&self, sample: u32) -> bool {
+        if self.rct_sample.get() == Some(sample) {
+            self.rct_count.set(self.rct_count.get() + 1);
+        } else {
+            self.rct_sample.set(Some(sample));
+            self.rct_count.set(1);
+        }
+
+        match self.apt_sample.get() {
+            Some(first) => {
+                if first == sample {
+                    self.apt_count.set(self.apt_count.get() + 1);
+                }
+                self.apt_position.set(self.apt_position.get() + 1);
+            }
+            None => {
+                self.apt_sample.set(Some(sample));
+                self.apt_count.set(1);
+                self.apt_position.set(1);
+            }
+        }
+        let apt_count = self.apt_count.get();
+        if self.apt_position.get() == APT_WINDOW {
+            self.apt_sample.set(None);
+        }
+
+        self.rct_count.get() < RCT_CUTOFF && apt_count < APT_CUTOFF
+    }
+}
+
+/// One HMAC computation of the generator.
+#[derive(Clone, Copy, PartialEq)]
+enum Step {
+    /// `K = HMAC(K, V || n || seed)`, where `n` is the round of the update.
+    UpdateKey(u8),
+    /// `V = HMAC(K, V)`, in the given round of the update.
+    UpdateValue(u8),
+    /// `V = HMAC(K, V)`, producing the given block of output.
+    Generate(usize),
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum State {
+    Idle,
+    /// Collecting a seed from the entropy source.
+    Seeding,
+    /// Waiting for the HMAC.
+    Hmac(Step),
+}
+
+pub struct HmacDrbg<'a, E: Entropy32<'a>, H: digest::Digest<'a, DIGEST_LEN> + digest::HmacSha256> {
+    entropy: &'a E,
+    hmac: &'a H,
+    client: OptionalCell<&'a dyn rng::Client>,
+
+    state: Cell<State>,
+    /// Whether the client is waiting for randomness.
+    requested: Cell<bool>,
+    health: HealthTests,
+
+    /// The working state of the generator.
+    key: Cell<[u8; DIGEST_LEN]>,
+    value: Cell<[u8; DIGEST_LEN]>,
+    seeded: Cell<bool>,
+    reseed_counter: Cell<u32>,
+    reseed_interval: u32,
+
+    /// Entropy collected for the next seed. This is only non-empty while
+    /// seeding.
+    seed: Cell<[u8; SEED_LEN]>,
+    seed_len: Cell<usize>,
+
+    output: MapCell<[u8; OUTPUT_LEN]>,
+    data: TakeCell<'static, [u8]>,
+    digest: TakeCell<'static, [u8; DIGEST_LEN]>,
+}
+
+impl<'a, E: Entropy32<'a>, H: digest::Digest<'a, DIGEST_LEN> + digest::HmacSha256>
+    HmacDrbg<'a, E, H>
+{
+    pub fn new(
+        entropy: &'a E,
+        hmac: &'a H,
+        reseed_interval: u32,
+        data: &'static mut [u8; DATA_LEN],
+        digest: &'static mut [u8; DIGEST_LEN],
+    ) -> Self {
+        HmacDrbg {
+            entropy,
+            hmac,
+            client: OptionalCell::empty(),
+            state: Cell::new(State::Idle),
+            requested: Cell::new(false),
+            health: HealthTests::new(),
+            key: Cell::new([0; DIGEST_LEN]),
+            value: Cell::new([0; DIGEST_LEN]),
+            seeded: Cell::new(false),
+            reseed_counter: Cell::new(0),
+            reseed_interval,
+            seed: Cell::new([0; SEED_LEN]),
+            seed_len: Cell::new(0),
+            output: MapCell::new([0; OUTPUT_LEN]),
+            data: TakeCell::new(data),
+            digest: TakeCell::new(digest),
+        }
+    }
+
+    /// Produce the next output, seeding the generator first if needed.
+    fn start_request(&self) -> Result<(), ErrorCode> {
+        if !self.seeded.get() || self.reseed_counter.get() > self.reseed_interval {
+            self.seed_len.set(0);
+            self.state.set(State::Seeding);
+            self.entropy.get().inspect_err(|_| {
+                self.state.set(State::Idle);
+            })
+        } else {
+            self.start_step(Step::Generate(0))
+        }
+    }
+
+    /// Start an HMAC keyed with `K` over `V`, followed by the round and the
+    /// seed for the first step of an update.
+    fn start_step(&self, step: Step) -> Result<(), ErrorCode> {
+        self.hmac.clear_data();
+        self.hmac.set_mode_hmacsha256(&self.key.get())?;
+
+        let data = self.data.take().ok_or(ErrorCode::BUSY)?;
+        data[..DIGEST_LEN].copy_from_slice(&self.value.get());
+        let mut len = DIGEST_LEN;
+        if let Step::UpdateKey(round) = step {
+            let seed_len = self.seed_len.get();
+            data[len] = round;
+            data[len + 1..len + 1 + seed_len].copy_from_slice(&self.seed.get()[..seed_len]);
+            len += 1 + seed_len;
+        }
+
+        let mut data = SubSliceMut::new(data);
+        data.slice(0..len);
+        self.state.set(State::Hmac(step));
+        self.hmac.add_mut_data(data).map_err(|(e, data)| {
+            self.data.replace(data.take());
+            e
+        })
+    }
+
+    /// Handle the result of an HMAC, and start the next one.
+    fn step_done(&self, step: Step, digest: &[u8; DIGEST_LEN]) -> Result<(), ErrorCode> {
+        match step {
+            Step::UpdateKey(round) => {
+                self.key.set(*digest);
+                self.start_step(Step::UpdateValue(round))
+            }
+            Step::UpdateValue(round) => {
+                self.value.set(*digest);
+                if round == 0 && self.seed_len.get() > 0 {
+                    self.start_step(Step::UpdateKey(1))
+                } else {
+                    self.update_done();
+                    Ok(())
+                }
+            }
+            Step::Generate(block) => {
+                self.value.set(*digest);
+                self.output.map(|output| {
+                    output[block * DIGEST_LEN..(block + 1) * DIGEST_LEN].copy_from_slice(digest);
+                });
+                if (block + 1) * DIGEST_LEN < OUTPUT_LEN {
+                    self.start_step(Step::Generate(block + 1))
+                } else {
+                    // Update the state after generating, so the output can't
+                    // be recovered from it.
+                    self.start_step(Step::UpdateKey(0))
+                }
+            }
+        }
+    }
+
+    fn update_done(&self) {
+        self.state.set(State::Idle);
+
+        if self.seed_len.get() > 0 {
+            // This was an instantiate or reseed.
+            self.seed.set([0; SEED_LEN]);
+            self.seed_len.set(0);
+            self.seeded.set(true);
+            self.reseed_counter.set(1);
+
+            if self.requested.get() {
+                if let Err(e) = self.start_request() {
+                    self.fail(e);
+                }
+            }
+        } else {
+            // This was the end of a generate.
+            self.reseed_counter.set(self.reseed_counter.get() + 1);
+            self.deliver();
+        }
+    }
+
+    /// Pass the output to the client, if it still wants it.
+    fn deliver(&self) {
+        let more = self.output.map_or(false, |output| {
+            let mut more = false;
+            if self.requested.take() {
+                let mut words = output
+                    .chunks(4)
+                    .map(|word| u32::from_le_bytes([word[0], word[1], word[2], word[3]]));
+                self.client.map(|client| {
+                    more = client.randomness_available(&mut words, Ok(())) == rng::Continue::More;
+                });
+            }
+            // Each output is only used once.
+            output.fill(0);
+            more
+        });
+
+        if more {
+            self.requested.set(true);
+            if let Err(e) = self.start_request() {
+                self.fail(e);
+            }
+        }
+    }
+
+    /// Uninstantiate the generator and report `error` to the client. If the
+    /// client still wants randomness the generator is seeded again.
+    fn fail(&self, error: ErrorCode) {
+        self.state.set(State::Idle);
+        self.seeded.set(false);
+        self.key.set([0; DIGEST_LEN]);
+        self.value.set([0; DIGEST_LEN]);
+        self.seed.set([0; SEED_LEN]);
+        self.seed_len.set(0);
+        self.health.reset();
+
+        if self.requested.take() {
+            let more = self.client.map_or(false, |client| {
+                client.randomness_available(&mut core::iter::empty(), Err(error))
+                    == rng::Continue::More
+            });
+            if more {
+                self.requested.set(true);
+                if let Err(e) = self.start_request() {
+                    self.requested.set(false);
+                    self.client.map(|client| {
+                        client.randomness_available(&mut core::iter::empty(), Err(e))
+                    });
+                }
+            }
+        }
+    }
+}
+
+impl<'a, E: Entropy32<'a>, H: digest::Digest<'a, DIGEST_LEN> + digest::HmacSha256> Rng<'a>
+    for HmacDrbg<'a, E, H>
+{
+    fn get(&self) -> Result<(), ErrorCode> {
+        self.requested.set(true);
+        if self.state.get() == State::Idle {
+            if let Err(e) = self.start_request() {
+                self.requested.set(false);
+                return Err(if e == ErrorCode::OFF {
+                    ErrorCode::OFF
+                } else {
+                    ErrorCode::FAIL
+                });
+            }
+        }
+        Ok(())
+    }
+
+    fn cancel(&self) -> Result<(), ErrorCode> {
+        // Any operation in progress runs to completion, but the output isn't
+        // passed to the client.
+        self.requested.set(false);
+        Ok(())
+    }
+
+    fn set_client(&'a self, client: &'a dyn rng::Client) {
+        self.entropy.set_client(self);
+        self.client.set(client);
+    }
+}
+
+impl<'a, E: Entropy32<'a>, H: digest::Digest<'a, DIGEST_LEN> + digest::HmacSha256> entropy::Client32
+    for HmacDrbg<'a, E, H>
+{
+    fn entropy_available(
+        &self,
+        entropy: &mut dyn Iterator<Item = u32>,
+        error: Result<(), ErrorCode>,
+    ) -> entropy::Continue {
+        if self.state.get() != State::Seeding {
+            return entropy::Continue::Done;
+        }
+        if let Err(e) = error {
+            self.fail(e);
+            return entropy::Continue::Done;
+        }
+
+        let mut seed = self.seed.get();
+        let mut seed_len = self.seed_len.get();
+        while seed_len < SEED_LEN {
+            let Some(sample) = entropy.next() else {
+                break;
+            };
+            if !self.health.check(sample) {
+                seed.fill(0);
+                self.fail(ErrorCode::FAIL);
+                return entropy::Continue::Done;
+            }
+            seed[seed_len..seed_len + 4].copy_from_slice(&sample.to_le_bytes());
+            seed_len += 4;
+        }
+        self.seed.set(seed);
+        self.seed_len.set(seed_len);
+
+        if seed_len < SEED_LEN {
+            return entropy::Continue::More;
+        }
+
+        if !self.seeded.get() {
+            // HMAC_DRBG_Instantiate_algorithm starts from fixed values.
+            self.key.set([0x00; DIGEST_LEN]);
+            self.value.set([0x01; DIGEST_LEN]);
+        }
+        if let Err(e) = self.start_step(Step::UpdateKey(0)) {
+            self.fail(e);
+        }
+        entropy::Continue::Done
+    }
+}
+
+impl<'a, E: Entropy32<'a>, H: digest::Digest<'a, DIGEST_LEN> + digest::HmacSha256>
+    digest::ClientData<DIGEST_LEN> for HmacDrbg<'a, E, H>
+{
+    fn add_data_done(&self, _result: Result<(), ErrorCode>, _data: SubSlice<'static, u8>) {}
+
+    fn add_mut_data_done(&self, result: Result<(), ErrorCode>, data: SubSliceMut<'static, u8>) {
+        let data = data.take();
+        // The data may include the seed.
+        data.fill(0);
+        self.data.replace(data);
+
+        let result = result.and_then(|()| {
+            let digest = self.digest.take().ok_or(ErrorCode::FAIL)?;
+            self.hmac.run(digest).map_err(|(e, digest)| {
+                self.digest.replace(digest);
+                e
+            })
+        });
+        if let Err(e) = result {
+            self.fail(e);
+        }
+    }
+}
+
+impl<'a, E: Entropy32<'a>, H: digest::Digest<'a, DIGEST_LEN> + digest::HmacSha256>
+    digest::ClientHash<DIGEST_LEN> for HmacDrbg<'a, E, H>
+{
+    fn hash_done(&self, result: Result<(), ErrorCode>, digest: &'static mut [u8; DIGEST_LEN]) {
+        let output = *digest;
+        digest.fill(0);
+        self.digest.replace(digest);
+
+        let result = result.and_then(|()| match self.state.get() {
+            State::Hmac(step) => self.step_done(step, &output),
+            _ => Err(ErrorCode::FAIL),
+        });
+
+        if let Err(e) = result {
+            self.fail(e);
+        }
+    }
+}
+
+impl<'a, E: Entropy32<'a>, H: digest::Digest<'a, DIGEST_LEN> + digest::HmacSha256>
+    digest::ClientVerify<DIGEST_LEN> for HmacDrbg<'a, E, H>
+{
+    fn verification_done(
+        &self,
+        _result: Result<bool, ErrorCode>,
+        _compare: &'static mut [u8; DIGEST_LEN],
+    ) {
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::hmac_sha256_fake::FakeHmacSha256;
+    use std::boxed::Box;
+    use std::vec::Vec;
+
+    /// An entropy source that hands out the samples passed to `supply()`.
+    struct FakeEntropy {
+        client: OptionalCell<&'static dyn entropy::Client32>,
+        /// Number of calls to `get()`.
+        requests: Cell<usize>,
+    }
+
+    impl FakeEntropy {
+        fn supply(&self, samples: &[u32]) -> entropy::Continue {
+            self.client.map_or(entropy::Continue::Done, |client| {
+                client.entropy_available(&mut samples.iter().copied(), Ok(()))
+            })
+        }
+    }
+
+    impl Entropy32<'static> for FakeEntropy {
+        fn get(&self) -> Result<(), ErrorCode> {
+            self.requests.set(self.requests.get() + 1);
+            Ok(())
+        }
+
+        fn cancel(&self) -> Result<(), ErrorCode> {
+            Ok(())
+        }
+
+        fn set_client(&'static self, client: &'static dyn entropy::Client32) {
+            self.client.set(client);
+        }
+    }
+
+    /// An RNG client that keeps the outputs and errors it receives.
+    struct TestClient {
+        outputs: MapCell<Vec<Vec<u8>>>,
+        errors: MapCell<Vec<ErrorCode>>,
+    }
+
+    impl rng::Client for TestClient {
+        fn randomness_available(
+            &self,
+            randomness: &mut dyn Iterator<Item = u32>,
+            error: Result<(), ErrorCode>,
+        ) -> rng::Continue {
+            match error {
+                Ok(()) => self.outputs.map(|outputs| {
+                    outputs.push(randomness.flat_map(|word| word.to_le_bytes()).collect())
+                }),
+                Err(e) => self.errors.map(|errors| errors.push(e)),
+            };
+            rng::Continue::Done
+        }
+    }
+
+    type TestDrbg = HmacDrbg<'static, FakeEntropy, FakeHmacSha256>;
+
+    fn setup(
+        reseed_interval: u32,
+    ) -> (
+        &'static TestDrbg,
+        &'static FakeEntropy,
+        &'static FakeHmacSha256,
+        &'static TestClient,
+    ) {
+        let entropy = Box::leak(Box::new(FakeEntropy {
+            client: OptionalCell::empty(),
+            requests: Cell::new(0),
+        }));
+        let hmac = Box::leak(Box::new(FakeHmacSha256::new()));
+        let drbg = Box::leak(Box::new(HmacDrbg::new(
+            &*entropy,
+            &*hmac,
+            reseed_interval,
+            Box::leak(Box::new([0; DATA_LEN])),
+            Box::leak(Box::new([0; DIGEST_LEN])),
+        )));
+        let client = Box::leak(Box::new(TestClient {
+            outputs: MapCell::new(Vec::new()),
+            errors: MapCell::new(Vec::new()),
+        }));
+        digest::Digest::set_client(hmac, drbg);
+        drbg.set_client(client);
+        (drbg, entropy, hmac, client)
+    }
+
+    /// Distinct samples, so the seed passes the health tests.
+    fn seed_samples(start: u32) -> Vec<u32> {
+        (start..start + (SEED_LEN / 4) as u32).collect()
+    }
+
+    #[test]
+    fn cavp_no_reseed() {
+        // NIST CAVP HMAC_DRBG.rsp, [SHA-256], no prediction resistance, no
+        // personalization string or additional input, COUNT = 0.
+        let entropy_input = [
+            0xca, 0x85, 0x19, 0x11, 0x34, 0x93, 0x84, 0xbf, 0xfe, 0x89, 0xde, 0x1c, 0xbd, 0xc4,
+            0x6e, 0x68, 0x31, 0xe4, 0x4d, 0x34, 0xa4, 0xfb, 0x93, 0x5e, 0xe2, 0x85, 0xdd, 0x14,
+            0xb7, 0x1a, 0x74, 0x88,
+        ];
+        let nonce = [
+            0x65, 0x9b, 0xa9, 0x6c, 0x60, 0x1d, 0xc6, 0x9f, 0xc9, 0x02, 0x94, 0x08, 0x05, 0xec,
+            0x0c, 0xa8,
+        ];
+        let returned_bits = [
+            0xe5, 0x28, 0xe9, 0xab, 0xf2, 0xde, 0xce, 0x54, 0xd4, 0x7c, 0x7e, 0x75, 0xe5, 0xfe,
+            0x30, 0x21, 0x49, 0xf8, 0x17, 0xea, 0x9f, 0xb4, 0xbe, 0xe6, 0xf4, 0x19, 0x96, 0x97,
+            0xd0, 0x4d, 0x5b, 0x89, 0xd5, 0x4f, 0xbb, 0x97, 0x8a, 0x15, 0xb5, 0xc4, 0x43, 0xc9,
+            0xec, 0x21, 0x03, 0x6d, 0x24, 0x60, 0xb6, 0xf7, 0x3e, 0xba, 0xd0, 0xdc, 0x2a, 0xba,
+            0x6e, 0x62, 0x4a, 0xbf, 0x07, 0x74, 0x5b, 0xc1, 0x07, 0x69, 0x4b, 0xb7, 0x54, 0x7b,
+            0xb0, 0x99, 0x5f, 0x70, 0xde, 0x25, 0xd6, 0xb2, 0x9e, 0x2d, 0x30, 0x11, 0xbb, 0x19,
+            0xd2, 0x76, 0x76, 0xc0, 0x71, 0x62, 0xc8, 0xb5, 0xcc, 0xde, 0x06, 0x68, 0x96, 0x1d,
+            0xf8, 0x68, 0x03, 0x48, 0x2c, 0xb3, 0x7e, 0xd6, 0xd5, 0xc0, 0xbb, 0x8d, 0x50, 0xcf,
+            0x1f, 0x50, 0xd4, 0x76, 0xaa, 0x04, 0x58, 0xbd, 0xab, 0xa8, 0x06, 0xf4, 0x8b, 0xe9,
+            0xdc, 0xb8,
+        ];
+
+        let (drbg, entropy, hmac, client) = setup(DEFAULT_RESEED_INTERVAL);
+        // The seed is the entropy input followed by the nonce, taken from the
+        // source as little endian words.
+        let samples: Vec<u32> = entropy_input
+            .iter()
+            .chain(nonce.iter())
+            .copied()
+            .collect::<Vec<u8>>()
+            .chunks(4)
+            .map(|word| u32::from_le_bytes([word[0], word[1], word[2], word[3]]))
+            .collect();
+
+        // The test generates twice and checks the second output.
+        assert_eq!(drbg.get(), Ok(()));
+        assert_eq!(entropy.supply(&samples), entropy::Continue::Done);
+        hmac.complete_all();
+        assert_eq!(drbg.get(), Ok(()));
+        hmac.complete_all();
+
+        let outputs = client.outputs.take().unwrap();
+        assert_eq!(outputs.len(), 2);
+        assert_eq!(outputs[1], returned_bits);
+        assert!(client.errors.map_or(false, |errors| errors.is_empty()));
+        assert_eq!(entropy.requests.get(), 1);
+    }
+
+    #[test]
+    fn seed_across_callbacks() {
+        let (drbg, entropy, hmac, client) = setup(DEFAULT_RESEED_INTERVAL);
+        let samples = seed_samples(1);
+
+        assert_eq!(drbg.get(), Ok(()));
+        assert_eq!(entropy.supply(&samples[..5]), entropy::Continue::More);
+        assert_eq!(hmac.hmacs.get(), 0);
+        assert_eq!(entropy.supply(&samples[5..]), entropy::Continue::Done);
+        hmac.complete_all();
+        assert_eq!(client.outputs.map(|outputs| outputs.len()), Some(1));
+    }
+
+    #[test]
+    fn repetition_count_test() {
+        let health = HealthTests::new();
+        for _ in 1..RCT_CUTOFF {
+            assert!(health.check(0x1234));
+        }
+        assert!(!health.check(0x1234));
+
+        // A different sample starts a new run.
+        health.reset();
+        for _ in 1..RCT_CUTOFF {
+            assert!(health.check(0x1234));
+        }
+        assert!(health.check(0x5678));
+        assert!(health.check(0x1234));
+    }
+
+    #[test]
+    fn adaptive_proportion_test() {
+        // The first sample of the window comes back between other samples, so
+        // the repetition count test doesn't trip.
+        let health = HealthTests::new();
+        let mut other = 0;
+        for _ in 1..APT_CUTOFF {
+            assert!(health.check(0xaaaa));
+            other += 1;
+            assert!(health.check(other));
+        }
+        assert!(!health.check(0xaaaa));
+
+        // The count starts again in the next window.
+        let health = HealthTests::new();
+        let mut other = 0;
+        for _ in 0..2 {
+            for position in 0..APT_WINDOW {
+                let sample = if position % 2 == 0 && position < 2 * (APT_CUTOFF - 1) {
+                    0xaaaa
+                } else {
+                    other += 1;
+                    other
+                };
+                assert!(health.check(sample));
+            }
+        }
+    }
+
+    #[test]
+    fn stuck_source_fails_closed() {
+        let (drbg, entropy, hmac, client) = setup(DEFAULT_RESEED_INTERVAL);
+
+        assert_eq!(drbg.get(), Ok(()));
+        assert_eq!(entropy.supply(&[7; SEED_LEN / 4]), entropy::Continue::Done);
+        hmac.complete_all();
+
+        // No output is generated from the seed.
+        assert_eq!(hmac.hmacs.get(), 0);
+        assert_eq!(client.outputs.map(|outputs| outputs.len()), Some(0));
+        assert_eq!(client.errors.take(), Some(std::vec![ErrorCode::FAIL]));
+        assert!(!drbg.seeded.get());
+        assert_eq!(drbg.key.get(), [0; DIGEST_LEN]);
+        assert_eq!(drbg.value.get(), [0; DIGEST_LEN]);
+
+        // The next request has to seed the generator again.
+        assert_eq!(drbg.get(), Ok(()));
+        assert_eq!(entropy.requests.get(), 2);
+        assert_eq!(entropy.supply(&seed_samples(1)), entropy::Continue::Done);
+        hmac.complete_all();
+        assert_eq!(client.outputs.map(|outputs| outputs.len()), Some(1));
+    }
+
+    #[test]
+    fn reseed_after_interval() {
+        let (drbg, entropy, hmac, client) = setup(2);
+
+        assert_eq!(drbg.get(), Ok(()));
+        assert_eq!(entropy.supply(&seed_samples(1)), entropy::Continue::Done);
+        hmac.complete_all();
+        assert_eq!(drbg.reseed_counter.get(), 2);
+
+        // The second request still uses the first seed.
+        assert_eq!(drbg.get(), Ok(()));
+        hmac.complete_all();
+        assert_eq!(entropy.requests.get(), 1);
+        assert_eq!(drbg.reseed_counter.get(), 3);
+
+        // The third needs a reseed before any output.
+        assert_eq!(drbg.get(), Ok(()));
+        assert_eq!(entropy.requests.get(), 2);
+        assert_eq!(client.outputs.map(|outputs| outputs.len()), Some(2));
+        assert_eq!(entropy.supply(&seed_samples(100)), entropy::Continue::Done);
+        hmac.complete_all();
+        assert_eq!(drbg.reseed_counter.get(), 2);
+
+        let outputs = client.outputs.take().unwrap();
+        assert_eq!(outputs.len(), 3);
+        assert_ne!(outputs[0], outputs[1]);
+        assert_ne!(outputs[1], outputs[2]);
+    }
+}
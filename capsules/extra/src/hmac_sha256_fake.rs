@@ -0,0 +1,226 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! An HMAC-SHA256 for unit tests of capsules that use the digest HIL.
+//!
+//! `FakeHmacSha256` computes the HMAC in memory, and issues the callback for
+//! an operation when `complete()` is called.
+
+extern crate std;
+
+use core::cell::Cell;
+
+use kernel::hil::digest;
+use kernel::utilities::cells::{MapCell, OptionalCell};
+use kernel::utilities::leasable_buffer::{SubSlice, SubSliceMut};
+use kernel::ErrorCode;
+use std::vec::Vec;
+
+const ROUND_CONSTANTS: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+/// SHA-256 of `data`.
+pub(crate) fn sha256(data: &[u8]) -> [u8; 32] {
+    let mut hash: [u32; 8] = [
+        0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab,
+        0x5be0cd19,
+    ];
+
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&((data.len() as u64) * 8).to_be_bytes());
+
+    for block in message.chunks(64) {
+        let mut w = [0u32; 64];
+        for (word, bytes) in w.iter_mut().zip(block.chunks(4)) {
+            *word = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        }
+        for i in 16..64 {
+            let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+            let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+            w[i] = w[i - 16]
+                .wrapping_add(s0)
+                .wrapping_add(w[i - 7])
+                .wrapping_add(s1);
+        }
+
+        let mut h = hash;
+        for i in 0..64 {
+            let s1 = h[4].rotate_right(6) ^ h[4].rotate_right(11) ^ h[4].rotate_right(25);
+            let ch = (h[4] & h[5]) ^ (!h[4] & h[6]);
+            let temp1 = h[7]
+                .wrapping_add(s1)
+                .wrapping_add(ch)
+                .wrapping_add(ROUND_CONSTANTS[i])
+                .wrapping_add(w[i]);
+            let s0 = h[0].rotate_right(2) ^ h[0].rotate_right(13) ^ h[0].rotate_right(22);
+            let maj = (h[0] & h[1]) ^ (h[0] & h[2]) ^ (h[1] & h[2]);
+            let temp2 = s0.wrapping_add(maj);
+            h = [
+                temp1.wrapping_add(temp2),
+                h[0],
+                h[1],
+                h[2],
+                h[3].wrapping_add(temp1),
+                h[4],
+                h[5],
+                h[6],
+            ];
+        }
+        for (hash, h) in hash.iter_mut().zip(h.iter()) {
+            *hash = hash.wrapping_add(*h);
+        }
+    }
+
+    let mut output = [0; 32];
+    for (bytes, word) in output.chunks_mut(4).zip(hash.iter()) {
+        bytes.copy_from_slice(&word.to_be_bytes());
+    }
+    output
+}
+
+/// HMAC-SHA256 of `data` with `key`, which is at most 64 bytes long.
+pub(crate) fn hmac_sha256(key: &[u8], data: &[u8]) -> [u8; 32] {
+    let mut inner = std::vec![0x36; 64];
+    let mut outer = std::vec![0x5c; 64];
+    for (i, byte) in key.iter().enumerate() {
+        inner[i] ^= byte;
+        outer[i] ^= byte;
+    }
+    inner.extend_from_slice(data);
+    outer.extend_from_slice(&sha256(&inner));
+    sha256(&outer)
+}
+
+pub(crate) struct FakeHmacSha256 {
+    client: OptionalCell<&'static dyn digest::Client<32>>,
+    key: MapCell<Vec<u8>>,
+    data: MapCell<Vec<u8>>,
+    pending_data: OptionalCell<SubSliceMut<'static, u8>>,
+    pending_digest: OptionalCell<&'static mut [u8; 32]>,
+    /// Number of HMACs computed.
+    pub(crate) hmacs: Cell<usize>,
+}
+
+impl FakeHmacSha256 {
+    pub(crate) fn new() -> Self {
+        Self {
+            client: OptionalCell::empty(),
+            key: MapCell::new(Vec::new()),
+            data: MapCell::new(Vec::new()),
+            pending_data: OptionalCell::empty(),
+            pending_digest: OptionalCell::empty(),
+            hmacs: Cell::new(0),
+        }
+    }
+
+    /// Complete the pending operation, returns `false` if there was none.
+    pub(crate) fn complete(&self) -> bool {
+        if let Some(data) = self.pending_data.take() {
+            self.client.map(move |c| c.add_mut_data_done(Ok(()), data));
+        } else if let Some(digest) = self.pending_digest.take() {
+            let key = self.key.map(|key| key.clone()).unwrap_or_default();
+            let data = self.data.map(|data| data.clone()).unwrap_or_default();
+            *digest = hmac_sha256(&key, &data);
+            self.hmacs.set(self.hmacs.get() + 1);
+            self.client.map(move |c| c.hash_done(Ok(()), digest));
+        } else {
+            return false;
+        }
+        true
+    }
+
+    pub(crate) fn complete_all(&self) {
+        while self.complete() {}
+    }
+
+    fn busy(&self) -> bool {
+        self.pending_data.is_some() || self.pending_digest.is_some()
+    }
+}
+
+impl digest::DigestData<'static, 32> for FakeHmacSha256 {
+    fn set_data_client(&'static self, _client: &'static dyn digest::ClientData<32>) {}
+
+    fn add_data(
+        &self,
+        data: SubSlice<'static, u8>,
+    ) -> Result<(), (ErrorCode, SubSlice<'static, u8>)> {
+        Err((ErrorCode::NOSUPPORT, data))
+    }
+
+    fn add_mut_data(
+        &self,
+        mut data: SubSliceMut<'static, u8>,
+    ) -> Result<(), (ErrorCode, SubSliceMut<'static, u8>)> {
+        if self.busy() {
+            return Err((ErrorCode::BUSY, data));
+        }
+        self.data
+            .map(|buffer| buffer.extend_from_slice(data.as_slice()));
+        self.pending_data.set(data);
+        Ok(())
+    }
+
+    fn clear_data(&self) {
+        self.key.map(|key| key.clear());
+        self.data.map(|data| data.clear());
+    }
+}
+
+impl digest::DigestHash<'static, 32> for FakeHmacSha256 {
+    fn set_hash_client(&'static self, _client: &'static dyn digest::ClientHash<32>) {}
+
+    fn run(
+        &'static self,
+        digest: &'static mut [u8; 32],
+    ) -> Result<(), (ErrorCode, &'static mut [u8; 32])> {
+        if self.busy() {
+            return Err((ErrorCode::BUSY, digest));
+        }
+        self.pending_digest.set(digest);
+        Ok(())
+    }
+}
+
+impl digest::DigestVerify<'static, 32> for FakeHmacSha256 {
+    fn set_verify_client(&'static self, _client: &'static dyn digest::ClientVerify<32>) {}
+
+    fn verify(
+        &'static self,
+        compare: &'static mut [u8; 32],
+    ) -> Result<(), (ErrorCode, &'static mut [u8; 32])> {
+        Err((ErrorCode::NOSUPPORT, compare))
+    }
+}
+
+impl digest::Digest<'static, 32> for FakeHmacSha256 {
+    fn set_client(&'static self, client: &'static dyn digest::Client<32>) {
+        self.client.set(client);
+    }
+}
+
+impl digest::HmacSha256 for FakeHmacSha256 {
+    fn set_mode_hmacsha256(&self, key: &[u8]) -> Result<(), ErrorCode> {
+        if key.len() > 64 {
+            return Err(ErrorCode::SIZE);
+        }
+        self.key.map(|k| {
+            k.clear();
+            k.extend_from_slice(key);
+        });
+        Ok(())
+    }
+}
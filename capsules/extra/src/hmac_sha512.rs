@@ -0,0 +1,791 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Software implementation of HMAC-SHA384 and HMAC-SHA512.
+//!
+//! This works the same way as `HmacSha256Software`, with the larger block
+//! size of SHA-384 and SHA-512. The digest length `L` selects the hash
+//! function: 64 for HMAC-SHA512 and 48 for HMAC-SHA384. The underlying hasher
+//! must have the same digest length, such as `Sha512Software` or
+//! `Sha384Software`.
+//!
+//! Keys longer than the 128 byte block size are rejected with `SIZE`. As in
+//! RFC 2104, such a key should be hashed first and its digest used as the key.
+
+use core::cell::Cell;
+
+use kernel::hil;
+use kernel::hil::digest::DigestData;
+use kernel::utilities::cells::{MapCell, OptionalCell, TakeCell};
+use kernel::utilities::leasable_buffer::SubSlice;
+use kernel::utilities::leasable_buffer::SubSliceMut;
+use kernel::utilities::leasable_buffer::SubSliceMutImmut;
+use kernel::ErrorCode;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum State {
+    Idle,
+    InnerHashAddKeyPending,
+    InnerHashAddKey,
+    InnerHashAddData,
+    InnerHash,
+    OuterHashAddKey,
+    OuterHashAddHash,
+    OuterHash,
+}
+
+#[derive(Copy, Clone)]
+pub enum RunMode {
+    Hash,
+    Verify,
+}
+
+/// Value to XOR the key with on the inner hash.
+const INNER_PAD_BYTE: u8 = 0x36;
+/// Value to XOR the key with on the outer hash.
+const OUTER_PAD_BYTE: u8 = 0x5c;
+
+const SHA_BLOCK_LEN_BYTES: usize = 128;
+const SHA_384_OUTPUT_LEN_BYTES: usize = 48;
+const SHA_512_OUTPUT_LEN_BYTES: usize = 64;
+
+/// HMAC-SHA384 in software.
+pub type HmacSha384Software<'a, S> = HmacSha512Software<'a, S, SHA_384_OUTPUT_LEN_BYTES>;
+
+pub struct HmacSha512Software<
+    'a,
+    S: hil::digest::Sha384 + hil::digest::Sha512 + hil::digest::DigestDataHash<'a, L>,
+    const L: usize = SHA_512_OUTPUT_LEN_BYTES,
+> {
+    /// SHA-384 or SHA-512 hasher implementation.
+    sha: &'a S,
+    /// The current operation for the internal state machine in this capsule.
+    state: Cell<State>,
+    /// The current mode of operation as requested by a call to either
+    /// [`DigestHash::run`](kernel::hil::digest::DigestHash::run) or
+    /// [`DigestVerify::verify`](kernel::hil::digest::DigestVerify::verify).
+    mode: Cell<RunMode>,
+    /// Location to store incoming temporarily before we are able to pass it to
+    /// the hasher.
+    input_data: OptionalCell<SubSliceMutImmut<'static, u8>>,
+    /// Static buffer to store the key and to pass to the hasher. This must be
+    /// at least `SHA_BLOCK_LEN_BYTES` bytes.
+    data_buffer: TakeCell<'static, [u8]>,
+    /// Storage buffer to keep a copy of the key. This allows us to keep it
+    /// persistent if the user wants to do multiple HMACs with the same key.
+    key_buffer: MapCell<[u8; SHA_BLOCK_LEN_BYTES]>,
+    /// Holding cell for the output digest buffer while we calculate the HMAC.
+    digest_buffer: MapCell<&'static mut [u8; L]>,
+    /// Buffer-slot used for a _verify_ operation. When not active, this
+    /// contains a buffer to place the current digest in. On a call to `verify`,
+    /// where the digest to compare to is provided in another buffer, this
+    /// buffer is swapped into this TakeCell. When the operation completes, we
+    /// swap them back and compare:
+    verify_buffer: MapCell<&'static mut [u8; L]>,
+    /// Clients for callbacks.
+    client: OptionalCell<&'a dyn hil::digest::Client<L>>,
+}
+
+impl<
+        'a,
+        S: hil::digest::Sha384 + hil::digest::Sha512 + hil::digest::DigestDataHash<'a, L>,
+        const L: usize,
+    > HmacSha512Software<'a, S, L>
+{
+    pub fn new(
+        sha: &'a S,
+        data_buffer: &'static mut [u8],
+        verify_buffer: &'static mut [u8; L],
+    ) -> Self {
+        Self {
+            sha,
+            state: Cell::new(State::Idle),
+            mode: Cell::new(RunMode::Hash),
+            input_data: OptionalCell::empty(),
+            data_buffer: TakeCell::new(data_buffer),
+            key_buffer: MapCell::new([0; SHA_BLOCK_LEN_BYTES]),
+            digest_buffer: MapCell::empty(),
+            verify_buffer: MapCell::new(verify_buffer),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    /// Store the key and put the hasher in the right mode with `set_mode`.
+    fn set_key(
+        &self,
+        key: &[u8],
+        set_mode: impl FnOnce() -> Result<(), ErrorCode>,
+    ) -> Result<(), ErrorCode> {
+        if key.len() > SHA_BLOCK_LEN_BYTES {
+            // Key size must be no longer than the internal block size (which is
+            // 128 bytes).
+            Err(ErrorCode::SIZE)
+        } else {
+            self.key_buffer.map_or(Err(ErrorCode::FAIL), |key_buf| {
+                // Save the key in our key buffer.
+                for i in 0..SHA_BLOCK_LEN_BYTES {
+                    key_buf[i] = *key.get(i).unwrap_or(&0);
+                }
+
+                // Make sure our hasher is in the expected mode.
+                set_mode()?;
+
+                // The key is added to the hasher along with the first data,
+                // as in `HmacSha256Software`.
+                self.state.set(State::InnerHashAddKeyPending);
+                Ok(())
+            })
+        }
+    }
+}
+
+impl<
+        'a,
+        S: hil::digest::Sha384 + hil::digest::Sha512 + hil::digest::DigestDataHash<'a, L>,
+        const L: usize,
+    > hil::digest::DigestData<'a, L> for HmacSha512Software<'a, S, L>
+{
+    fn add_data(
+        &self,
+        data: SubSlice<'static, u8>,
+    ) -> Result<(), (ErrorCode, SubSlice<'static, u8>)> {
+        match self.state.get() {
+            State::InnerHashAddKeyPending => {
+                // We need to write the key before we write the data.
+                if let Some(data_buf) = self.data_buffer.take() {
+                    self.key_buffer.map(|key_buf| {
+                        // Copy the key XOR with inner pad (0x36).
+                        for i in 0..SHA_BLOCK_LEN_BYTES {
+                            data_buf[i] = key_buf[i] ^ INNER_PAD_BYTE;
+                        }
+                    });
+
+                    let mut lease_buf = SubSliceMut::new(data_buf);
+                    lease_buf.slice(0..SHA_BLOCK_LEN_BYTES);
+
+                    match self.sha.add_mut_data(lease_buf) {
+                        Ok(()) => {
+                            self.state.set(State::InnerHashAddKey);
+                            // Save the incoming data to add to the hasher
+                            // on the next iteration.
+                            self.input_data.set(SubSliceMutImmut::Immutable(data));
+                            Ok(())
+                        }
+                        Err((e, leased_data_buf)) => {
+                            self.data_buffer.replace(leased_data_buf.take());
+                            Err((e, data))
+                        }
+                    }
+                } else {
+                    Err((ErrorCode::BUSY, data))
+                }
+            }
+
+            State::InnerHashAddData => {
+                // In this state the hasher is ready to take more input data so
+                // we can provide more input data. This is the only state after
+                // setting the key we can accept new data in.
+                self.sha.add_data(data)
+            }
+
+            State::Idle => {
+                // We need a key before we can accept data, so we must return
+                // error here. `OFF` is the closest error to this issue so we
+                // return that.
+                Err((ErrorCode::OFF, data))
+            }
+
+            _ => {
+                // Any other state we cannot accept new data.
+                Err((ErrorCode::BUSY, data))
+            }
+        }
+    }
+
+    fn add_mut_data(
+        &self,
+        data: SubSliceMut<'static, u8>,
+    ) -> Result<(), (ErrorCode, SubSliceMut<'static, u8>)> {
+        match self.state.get() {
+            State::InnerHashAddKeyPending => {
+                // We need to write the key before we write the data.
+
+                if let Some(data_buf) = self.data_buffer.take() {
+                    // Copy the key XOR with inner pad (0x36).
+                    self.key_buffer.map(|key_buf| {
+                        // Copy the key XOR with inner pad (0x36).
+                        for i in 0..SHA_BLOCK_LEN_BYTES {
+                            data_buf[i] = key_buf[i] ^ INNER_PAD_BYTE;
+                        }
+                    });
+
+                    let mut lease_buf = SubSliceMut::new(data_buf);
+                    lease_buf.slice(0..SHA_BLOCK_LEN_BYTES);
+
+                    match self.sha.add_mut_data(lease_buf) {
+                        Ok(()) => {
+                            self.state.set(State::InnerHashAddKey);
+                            // Save the incoming data to add to the hasher
+                            // on the next iteration.
+                            self.input_data.set(SubSliceMutImmut::Mutable(data));
+                            Ok(())
+                        }
+                        Err((e, leased_data_buf)) => {
+                            self.data_buffer.replace(leased_data_buf.take());
+                            Err((e, data))
+                        }
+                    }
+                } else {
+                    Err((ErrorCode::BUSY, data))
+                }
+            }
+
+            State::InnerHashAddData => {
+                // In this state the hasher is ready to take more input data so
+                // we can provide more input data. This is the only state after
+                // setting the key we can accept new data in.
+                self.sha.add_mut_data(data)
+            }
+
+            State::Idle => {
+                // We need a key before we can accept data, so we must return
+                // error here. `OFF` is the closest error to this issue so we
+                // return that.
+                Err((ErrorCode::OFF, data))
+            }
+
+            _ => {
+                // Any other state we cannot accept new data.
+                Err((ErrorCode::BUSY, data))
+            }
+        }
+    }
+
+    fn clear_data(&self) {
+        self.state.set(State::Idle);
+        self.sha.clear_data();
+    }
+
+    fn set_data_client(&'a self, _client: &'a dyn hil::digest::ClientData<L>) {
+        unimplemented!()
+    }
+}
+
+impl<
+        'a,
+        S: hil::digest::Sha384 + hil::digest::Sha512 + hil::digest::DigestDataHash<'a, L>,
+        const L: usize,
+    > hil::digest::DigestHash<'a, L> for HmacSha512Software<'a, S, L>
+{
+    fn run(
+        &'a self,
+        digest: &'static mut [u8; L],
+    ) -> Result<(), (ErrorCode, &'static mut [u8; L])> {
+        // User called run, we start with the inner hash.
+        self.state.set(State::InnerHash);
+        self.mode.set(RunMode::Hash);
+        self.sha.run(digest)
+    }
+
+    fn set_hash_client(&'a self, _client: &'a dyn hil::digest::ClientHash<L>) {
+        unimplemented!()
+    }
+}
+
+impl<
+        'a,
+        S: hil::digest::Sha384 + hil::digest::Sha512 + hil::digest::DigestDataHash<'a, L>,
+        const L: usize,
+    > hil::digest::DigestVerify<'a, L> for HmacSha512Software<'a, S, L>
+{
+    fn verify(
+        &'a self,
+        compare: &'static mut [u8; L],
+    ) -> Result<(), (ErrorCode, &'static mut [u8; L])> {
+        // User called verify, we start with the inner hash.
+        self.state.set(State::InnerHash);
+        self.mode.set(RunMode::Verify);
+
+        // Swap the `compare` buffer into `self.verify_buffer`, and use that to
+        // perform the actual digest calculation:
+        let digest = self.verify_buffer.replace(compare).unwrap();
+        self.sha.run(digest)
+    }
+
+    fn set_verify_client(&'a self, _client: &'a dyn hil::digest::ClientVerify<L>) {
+        unimplemented!()
+    }
+}
+
+impl<
+        'a,
+        S: hil::digest::Sha384 + hil::digest::Sha512 + hil::digest::DigestDataHash<'a, L>,
+        const L: usize,
+    > hil::digest::DigestDataHash<'a, L> for HmacSha512Software<'a, S, L>
+{
+    fn set_client(&'a self, _client: &'a dyn hil::digest::ClientDataHash<L>) {
+        unimplemented!()
+    }
+}
+
+impl<
+        'a,
+        S: hil::digest::Sha384 + hil::digest::Sha512 + hil::digest::DigestDataHash<'a, L>,
+        const L: usize,
+    > hil::digest::Digest<'a, L> for HmacSha512Software<'a, S, L>
+{
+    fn set_client(&'a self, client: &'a dyn hil::digest::Client<L>) {
+        self.client.set(client);
+    }
+}
+
+impl<
+        'a,
+        S: hil::digest::Sha384 + hil::digest::Sha512 + hil::digest::DigestDataHash<'a, L>,
+        const L: usize,
+    > hil::digest::ClientData<L> for HmacSha512Software<'a, S, L>
+{
+    fn add_data_done(&self, result: Result<(), ErrorCode>, data: SubSlice<'static, u8>) {
+        // This callback is only used for the user to pass in additional data
+        // for the HMAC, we do not use `add_data()` internally in this capsule
+        // so we can just directly issue the callback.
+        self.client.map(|client| {
+            client.add_data_done(result, data);
+        });
+    }
+
+    fn add_mut_data_done(&self, result: Result<(), ErrorCode>, data: SubSliceMut<'static, u8>) {
+        if result.is_err() {
+            self.client.map(|client| {
+                client.add_mut_data_done(result, data);
+            });
+        } else {
+            match self.state.get() {
+                State::InnerHashAddKey => {
+                    self.data_buffer.replace(data.take());
+
+                    // We just added the key, so we can now add the stored data.
+                    self.input_data.take().map(|in_data| match in_data {
+                        SubSliceMutImmut::Mutable(buffer) => match self.sha.add_mut_data(buffer) {
+                            Ok(()) => {
+                                self.state.set(State::InnerHashAddData);
+                            }
+                            Err((e, leased_data_buf)) => {
+                                self.clear_data();
+                                self.client.map(|c| {
+                                    c.add_mut_data_done(Err(e), leased_data_buf);
+                                });
+                            }
+                        },
+                        SubSliceMutImmut::Immutable(buffer) => match self.sha.add_data(buffer) {
+                            Ok(()) => {
+                                self.state.set(State::InnerHashAddData);
+                            }
+                            Err((e, leased_data_buf)) => {
+                                self.clear_data();
+                                self.client.map(|c| {
+                                    c.add_data_done(Err(e), leased_data_buf);
+                                });
+                            }
+                        },
+                    });
+                }
+                State::OuterHashAddKey => {
+                    // We just added the key, now we add the result of the first
+                    // hash.
+                    self.digest_buffer.take().map(|digest_buf| {
+                        let data_buf = data.take();
+
+                        // Copy the digest result into our data buffer. We must
+                        // use our data buffer because it does not have a fixed
+                        // size and we can use it with `SubSliceMut`.
+                        data_buf[..L].copy_from_slice(&digest_buf[..L]);
+
+                        let mut lease_buf = SubSliceMut::new(data_buf);
+                        lease_buf.slice(0..L);
+
+                        match self.sha.add_mut_data(lease_buf) {
+                            Ok(()) => {
+                                self.state.set(State::OuterHashAddHash);
+                                self.digest_buffer.replace(digest_buf);
+                            }
+                            Err((e, leased_data_buf)) => {
+                                self.data_buffer.replace(leased_data_buf.take());
+                                self.clear_data();
+                                self.client.map(|c| {
+                                    c.hash_done(Err(e), digest_buf);
+                                });
+                            }
+                        }
+                    });
+                }
+                State::OuterHashAddHash => {
+                    // We've now added both the key and the result of the first
+                    // hash, so we can run the second hash to get our HMAC.
+                    self.data_buffer.replace(data.take());
+
+                    self.digest_buffer
+                        .take()
+                        .map(|digest_buf| match self.sha.run(digest_buf) {
+                            Ok(()) => {
+                                self.state.set(State::OuterHash);
+                            }
+                            Err((e, digest)) => {
+                                self.clear_data();
+                                self.client.map(|c| {
+                                    c.hash_done(Err(e), digest);
+                                });
+                            }
+                        });
+                }
+                _ => {
+                    // In other states, we can just issue the callback like
+                    // normal.
+                    self.client.map(|client| {
+                        client.add_mut_data_done(Ok(()), data);
+                    });
+                }
+            }
+        }
+    }
+}
+
+impl<
+        'a,
+        S: hil::digest::Sha384 + hil::digest::Sha512 + hil::digest::DigestDataHash<'a, L>,
+        const L: usize,
+    > hil::digest::ClientHash<L> for HmacSha512Software<'a, S, L>
+{
+    fn hash_done(&self, result: Result<(), ErrorCode>, digest: &'static mut [u8; L]) {
+        let hash_done_error = |error: Result<(), ErrorCode>, error_digest: &'static mut [u8; L]| {
+            match self.mode.get() {
+                RunMode::Hash => self.client.map(|c| {
+                    c.hash_done(error, error_digest);
+                }),
+                RunMode::Verify => {
+                    // Also swap back the verify_buffer, and return the original
+                    // buffer to the client:
+                    let compare = self.verify_buffer.replace(error_digest).unwrap();
+                    self.client.map(|c| {
+                        // Convert to Result<bool, ErrorCode>
+                        c.verification_done(error.map(|()| false), compare);
+                    })
+                }
+            }
+        };
+
+        if result.is_err() {
+            // If hashing fails, we have to propagate that error up with a
+            // callback.
+            self.clear_data();
+            hash_done_error(result, digest);
+        } else {
+            match self.state.get() {
+                State::InnerHash => {
+                    // Completed inner hash, now work on outer hash.
+                    self.sha.clear_data();
+
+                    self.data_buffer.take().map(|data_buf| {
+                        self.key_buffer.map(|key_buf| {
+                            // Copy the key XOR with outer pad (0x5c).
+                            for i in 0..SHA_BLOCK_LEN_BYTES {
+                                data_buf[i] = key_buf[i] ^ OUTER_PAD_BYTE;
+                            }
+                        });
+
+                        let mut lease_buf = SubSliceMut::new(data_buf);
+                        lease_buf.slice(0..SHA_BLOCK_LEN_BYTES);
+
+                        match self.sha.add_mut_data(lease_buf) {
+                            Ok(()) => {
+                                self.state.set(State::OuterHashAddKey);
+                                self.digest_buffer.replace(digest);
+                            }
+                            Err((e, leased_data_buf)) => {
+                                // If we cannot add data, we need to replace the
+                                // buffer and issue a callback with an error.
+                                self.data_buffer.replace(leased_data_buf.take());
+                                self.clear_data();
+                                hash_done_error(Err(e), digest);
+                            }
+                        }
+                    });
+                }
+
+                State::OuterHash => match self.mode.get() {
+                    RunMode::Hash => {
+                        self.client.map(|c| {
+                            c.hash_done(Ok(()), digest);
+                        });
+                    }
+
+                    RunMode::Verify => {
+                        let compare = self.verify_buffer.take().unwrap();
+                        let res = compare == digest;
+                        self.verify_buffer.replace(digest);
+                        self.client.map(|c| {
+                            c.verification_done(Ok(res), compare);
+                        });
+                    }
+                },
+                _ => {}
+            }
+        }
+    }
+}
+
+impl<
+        'a,
+        S: hil::digest::Sha384 + hil::digest::Sha512 + hil::digest::DigestDataHash<'a, L>,
+        const L: usize,
+    > hil::digest::ClientVerify<L> for HmacSha512Software<'a, S, L>
+{
+    fn verification_done(&self, _result: Result<bool, ErrorCode>, _compare: &'static mut [u8; L]) {}
+}
+
+impl<
+        'a,
+        S: hil::digest::Sha384 + hil::digest::Sha512 + hil::digest::DigestDataHash<'a, L>,
+        const L: usize,
+    > hil::digest::HmacSha256 for HmacSha512Software<'a, S, L>
+{
+    fn set_mode_hmacsha256(&self, _key: &[u8]) -> Result<(), ErrorCode> {
+        Err(ErrorCode::NOSUPPORT)
+    }
+}
+
+impl<
+        'a,
+        S: hil::digest::Sha384 + hil::digest::Sha512 + hil::digest::DigestDataHash<'a, L>,
+        const L: usize,
+    > hil::digest::HmacSha384 for HmacSha512Software<'a, S, L>
+{
+    fn set_mode_hmacsha384(&self, key: &[u8]) -> Result<(), ErrorCode> {
+        if L != SHA_384_OUTPUT_LEN_BYTES {
+            return Err(ErrorCode::NOSUPPORT);
+        }
+        self.set_key(key, || self.sha.set_mode_sha384())
+    }
+}
+
+impl<
+        'a,
+        S: hil::digest::Sha384 + hil::digest::Sha512 + hil::digest::DigestDataHash<'a, L>,
+        const L: usize,
+    > hil::digest::HmacSha512 for HmacSha512Software<'a, S, L>
+{
+    fn set_mode_hmacsha512(&self, key: &[u8]) -> Result<(), ErrorCode> {
+        if L != SHA_512_OUTPUT_LEN_BYTES {
+            return Err(ErrorCode::NOSUPPORT);
+        }
+        self.set_key(key, || self.sha.set_mode_sha512())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::sha512::Sha512Hasher;
+    use crate::sha512_fake::FakeSha512;
+    use kernel::hil::digest::{DigestHash, DigestVerify, HmacSha384, HmacSha512};
+    use std::boxed::Box;
+
+    /// Keeps the result of the last HMAC.
+    struct TestClient<const L: usize> {
+        digest: OptionalCell<[u8; L]>,
+        verified: OptionalCell<bool>,
+    }
+
+    impl<const L: usize> hil::digest::ClientData<L> for TestClient<L> {
+        fn add_data_done(&self, _result: Result<(), ErrorCode>, _data: SubSlice<'static, u8>) {}
+
+        fn add_mut_data_done(
+            &self,
+            result: Result<(), ErrorCode>,
+            _data: SubSliceMut<'static, u8>,
+        ) {
+            assert_eq!(result, Ok(()));
+        }
+    }
+
+    impl<const L: usize> hil::digest::ClientHash<L> for TestClient<L> {
+        fn hash_done(&self, result: Result<(), ErrorCode>, digest: &'static mut [u8; L]) {
+            assert_eq!(result, Ok(()));
+            self.digest.set(*digest);
+        }
+    }
+
+    impl<const L: usize> hil::digest::ClientVerify<L> for TestClient<L> {
+        fn verification_done(
+            &self,
+            result: Result<bool, ErrorCode>,
+            _compare: &'static mut [u8; L],
+        ) {
+            self.verified.insert(result.ok());
+        }
+    }
+
+    struct TestCase {
+        key: &'static [u8],
+        data: &'static [u8],
+        hmac_sha384: &'static [u8],
+        hmac_sha512: &'static [u8],
+    }
+
+    /// RFC 4231 section 4. Test case 5 is truncated to 128 bits, and test
+    /// cases 6 and 7 have keys longer than the block size.
+    const TEST_CASES: [TestCase; 7] = [
+        // Test case 1.
+        TestCase {
+            key: &[0x0b; 20],
+            data: b"Hi There",
+            hmac_sha384: &[0xaf, 0xd0, 0x39, 0x44, 0xd8, 0x48, 0x95, 0x62, 0x6b, 0x08, 0x25, 0xf4, 0xab, 0x46, 0x90, 0x7f, 0x15, 0xf9, 0xda, 0xdb, 0xe4, 0x10, 0x1e, 0xc6, 0x82, 0xaa, 0x03, 0x4c, 0x7c, 0xeb, 0xc5, 0x9c, 0xfa, 0xea, 0x9e, 0xa9, 0x07, 0x6e, 0xde, 0x7f, 0x4a, 0xf1, 0x52, 0xe8, 0xb2, 0xfa, 0x9c, 0xb6],
+            hmac_sha512: &[0x87, 0xaa, 0x7c, 0xde, 0xa5, 0xef, 0x61, 0x9d, 0x4f, 0xf0, 0xb4, 0x24, 0x1a, 0x1d, 0x6c, 0xb0, 0x23, 0x79, 0xf4, 0xe2, 0xce, 0x4e, 0xc2, 0x78, 0x7a, 0xd0, 0xb3, 0x05, 0x45, 0xe1, 0x7c, 0xde, 0xda, 0xa8, 0x33, 0xb7, 0xd6, 0xb8, 0xa7, 0x02, 0x03, 0x8b, 0x27, 0x4e, 0xae, 0xa3, 0xf4, 0xe4, 0xbe, 0x9d, 0x91, 0x4e, 0xeb, 0x61, 0xf1, 0x70, 0x2e, 0x69, 0x6c, 0x20, 0x3a, 0x12, 0x68, 0x54],
+        },
+        // Test case 2.
+        TestCase {
+            key: b"Jefe",
+            data: b"what do ya want for nothing?",
+            hmac_sha384: &[0xaf, 0x45, 0xd2, 0xe3, 0x76, 0x48, 0x40, 0x31, 0x61, 0x7f, 0x78, 0xd2, 0xb5, 0x8a, 0x6b, 0x1b, 0x9c, 0x7e, 0xf4, 0x64, 0xf5, 0xa0, 0x1b, 0x47, 0xe4, 0x2e, 0xc3, 0x73, 0x63, 0x22, 0x44, 0x5e, 0x8e, 0x22, 0x40, 0xca, 0x5e, 0x69, 0xe2, 0xc7, 0x8b, 0x32, 0x39, 0xec, 0xfa, 0xb2, 0x16, 0x49],
+            hmac_sha512: &[0x16, 0x4b, 0x7a, 0x7b, 0xfc, 0xf8, 0x19, 0xe2, 0xe3, 0x95, 0xfb, 0xe7, 0x3b, 0x56, 0xe0, 0xa3, 0x87, 0xbd, 0x64, 0x22, 0x2e, 0x83, 0x1f, 0xd6, 0x10, 0x27, 0x0c, 0xd7, 0xea, 0x25, 0x05, 0x54, 0x97, 0x58, 0xbf, 0x75, 0xc0, 0x5a, 0x99, 0x4a, 0x6d, 0x03, 0x4f, 0x65, 0xf8, 0xf0, 0xe6, 0xfd, 0xca, 0xea, 0xb1, 0xa3, 0x4d, 0x4a, 0x6b, 0x4b, 0x63, 0x6e, 0x07, 0x0a, 0x38, 0xbc, 0xe7, 0x37],
+        },
+        // Test case 3.
+        TestCase {
+            key: &[0xaa; 20],
+            data: &[0xdd; 50],
+            hmac_sha384: &[0x88, 0x06, 0x26, 0x08, 0xd3, 0xe6, 0xad, 0x8a, 0x0a, 0xa2, 0xac, 0xe0, 0x14, 0xc8, 0xa8, 0x6f, 0x0a, 0xa6, 0x35, 0xd9, 0x47, 0xac, 0x9f, 0xeb, 0xe8, 0x3e, 0xf4, 0xe5, 0x59, 0x66, 0x14, 0x4b, 0x2a, 0x5a, 0xb3, 0x9d, 0xc1, 0x38, 0x14, 0xb9, 0x4e, 0x3a, 0xb6, 0xe1, 0x01, 0xa3, 0x4f, 0x27],
+            hmac_sha512: &[0xfa, 0x73, 0xb0, 0x08, 0x9d, 0x56, 0xa2, 0x84, 0xef, 0xb0, 0xf0, 0x75, 0x6c, 0x89, 0x0b, 0xe9, 0xb1, 0xb5, 0xdb, 0xdd, 0x8e, 0xe8, 0x1a, 0x36, 0x55, 0xf8, 0x3e, 0x33, 0xb2, 0x27, 0x9d, 0x39, 0xbf, 0x3e, 0x84, 0x82, 0x79, 0xa7, 0x22, 0xc8, 0x06, 0xb4, 0x85, 0xa4, 0x7e, 0x67, 0xc8, 0x07, 0xb9, 0x46, 0xa3, 0x37, 0xbe, 0xe8, 0x94, 0x26, 0x74, 0x27, 0x88, 0x59, 0xe1, 0x32, 0x92, 0xfb],
+        },
+        // Test case 4.
+        TestCase {
+            key: &[0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0a, 0x0b, 0x0c, 0x0d, 0x0e, 0x0f, 0x10, 0x11, 0x12, 0x13, 0x14, 0x15, 0x16, 0x17, 0x18, 0x19],
+            data: &[0xcd; 50],
+            hmac_sha384: &[0x3e, 0x8a, 0x69, 0xb7, 0x78, 0x3c, 0x25, 0x85, 0x19, 0x33, 0xab, 0x62, 0x90, 0xaf, 0x6c, 0xa7, 0x7a, 0x99, 0x81, 0x48, 0x08, 0x50, 0x00, 0x9c, 0xc5, 0x57, 0x7c, 0x6e, 0x1f, 0x57, 0x3b, 0x4e, 0x68, 0x01, 0xdd, 0x23, 0xc4, 0xa7, 0xd6, 0x79, 0xcc, 0xf8, 0xa3, 0x86, 0xc6, 0x74, 0xcf, 0xfb],
+            hmac_sha512: &[0xb0, 0xba, 0x46, 0x56, 0x37, 0x45, 0x8c, 0x69, 0x90, 0xe5, 0xa8, 0xc5, 0xf6, 0x1d, 0x4a, 0xf7, 0xe5, 0x76, 0xd9, 0x7f, 0xf9, 0x4b, 0x87, 0x2d, 0xe7, 0x6f, 0x80, 0x50, 0x36, 0x1e, 0xe3, 0xdb, 0xa9, 0x1c, 0xa5, 0xc1, 0x1a, 0xa2, 0x5e, 0xb4, 0xd6, 0x79, 0x27, 0x5c, 0xc5, 0x78, 0x80, 0x63, 0xa5, 0xf1, 0x97, 0x41, 0x12, 0x0c, 0x4f, 0x2d, 0xe2, 0xad, 0xeb, 0xeb, 0x10, 0xa2, 0x98, 0xdd],
+        },
+        // Test case 5.
+        TestCase {
+            key: &[0x0c; 20],
+            data: b"Test With Truncation",
+            hmac_sha384: &[0x3a, 0xbf, 0x34, 0xc3, 0x50, 0x3b, 0x2a, 0x23, 0xa4, 0x6e, 0xfc, 0x61, 0x9b, 0xae, 0xf8, 0x97],
+            hmac_sha512: &[0x41, 0x5f, 0xad, 0x62, 0x71, 0x58, 0x0a, 0x53, 0x1d, 0x41, 0x79, 0xbc, 0x89, 0x1d, 0x87, 0xa6],
+        },
+        // Test case 6.
+        TestCase {
+            key: &[0xaa; 131],
+            data: b"Test Using Larger Than Block-Size Key - Hash Key First",
+            hmac_sha384: &[0x4e, 0xce, 0x08, 0x44, 0x85, 0x81, 0x3e, 0x90, 0x88, 0xd2, 0xc6, 0x3a, 0x04, 0x1b, 0xc5, 0xb4, 0x4f, 0x9e, 0xf1, 0x01, 0x2a, 0x2b, 0x58, 0x8f, 0x3c, 0xd1, 0x1f, 0x05, 0x03, 0x3a, 0xc4, 0xc6, 0x0c, 0x2e, 0xf6, 0xab, 0x40, 0x30, 0xfe, 0x82, 0x96, 0x24, 0x8d, 0xf1, 0x63, 0xf4, 0x49, 0x52],
+            hmac_sha512: &[0x80, 0xb2, 0x42, 0x63, 0xc7, 0xc1, 0xa3, 0xeb, 0xb7, 0x14, 0x93, 0xc1, 0xdd, 0x7b, 0xe8, 0xb4, 0x9b, 0x46, 0xd1, 0xf4, 0x1b, 0x4a, 0xee, 0xc1, 0x12, 0x1b, 0x01, 0x37, 0x83, 0xf8, 0xf3, 0x52, 0x6b, 0x56, 0xd0, 0x37, 0xe0, 0x5f, 0x25, 0x98, 0xbd, 0x0f, 0xd2, 0x21, 0x5d, 0x6a, 0x1e, 0x52, 0x95, 0xe6, 0x4f, 0x73, 0xf6, 0x3f, 0x0a, 0xec, 0x8b, 0x91, 0x5a, 0x98, 0x5d, 0x78, 0x65, 0x98],
+        },
+        // Test case 7.
+        TestCase {
+            key: &[0xaa; 131],
+            data: b"This is a test using a larger than block-size key and a larger than block-size data. The key needs to be hashed before being used by the HMAC algorithm.",
+            hmac_sha384: &[0x66, 0x17, 0x17, 0x8e, 0x94, 0x1f, 0x02, 0x0d, 0x35, 0x1e, 0x2f, 0x25, 0x4e, 0x8f, 0xd3, 0x2c, 0x60, 0x24, 0x20, 0xfe, 0xb0, 0xb8, 0xfb, 0x9a, 0xdc, 0xce, 0xbb, 0x82, 0x46, 0x1e, 0x99, 0xc5, 0xa6, 0x78, 0xcc, 0x31, 0xe7, 0x99, 0x17, 0x6d, 0x38, 0x60, 0xe6, 0x11, 0x0c, 0x46, 0x52, 0x3e],
+            hmac_sha512: &[0xe3, 0x7b, 0x6a, 0x77, 0x5d, 0xc8, 0x7d, 0xba, 0xa4, 0xdf, 0xa9, 0xf9, 0x6e, 0x5e, 0x3f, 0xfd, 0xde, 0xbd, 0x71, 0xf8, 0x86, 0x72, 0x89, 0x86, 0x5d, 0xf5, 0xa3, 0x2d, 0x20, 0xcd, 0xc9, 0x44, 0xb6, 0x02, 0x2c, 0xac, 0x3c, 0x49, 0x82, 0xb1, 0x0d, 0x5e, 0xeb, 0x55, 0xc3, 0xe4, 0xde, 0x15, 0x13, 0x46, 0x76, 0xfb, 0x6d, 0xe0, 0x44, 0x60, 0x65, 0xc9, 0x74, 0x40, 0xfa, 0x8c, 0x6a, 0x58],
+        },
+    ];
+
+    type TestHmac<const L: usize> = HmacSha512Software<'static, FakeSha512<L>, L>;
+
+    fn setup<const L: usize>() -> (
+        &'static TestHmac<L>,
+        &'static FakeSha512<L>,
+        &'static TestClient<L>,
+    ) {
+        let sha = Box::leak(Box::new(FakeSha512::new()));
+        let hmac = Box::leak(Box::new(HmacSha512Software::new(
+            &*sha,
+            Box::leak(Box::new([0; SHA_BLOCK_LEN_BYTES])),
+            Box::leak(Box::new([0; L])),
+        )));
+        let client = Box::leak(Box::new(TestClient {
+            digest: OptionalCell::empty(),
+            verified: OptionalCell::empty(),
+        }));
+        hil::digest::DigestDataHash::set_client(sha, hmac);
+        hil::digest::Digest::set_client(hmac, client);
+        (hmac, sha, client)
+    }
+
+    fn add_data<const L: usize>(hmac: &TestHmac<L>, sha: &FakeSha512<L>, data: &[u8]) {
+        let data = SubSliceMut::new(Box::leak(data.to_vec().into_boxed_slice()));
+        assert!(hmac.add_mut_data(data).is_ok());
+        sha.complete_all();
+    }
+
+    /// Compute an HMAC with a key set by `set_mode`. Keys longer than the
+    /// block size are rejected, and are hashed first instead.
+    fn hmac<const L: usize>(
+        key: &[u8],
+        data: &[u8],
+        set_mode: impl Fn(&TestHmac<L>, &[u8]) -> Result<(), ErrorCode>,
+    ) -> [u8; L] {
+        let (hmac, sha, client) = setup::<L>();
+        let mut hashed_key = [0; L];
+        let key = if key.len() > SHA_BLOCK_LEN_BYTES {
+            assert_eq!(set_mode(hmac, key), Err(ErrorCode::SIZE));
+            let mut hasher = Sha512Hasher::<L>::new();
+            hasher.update(key);
+            hasher.finish(&mut hashed_key);
+            &hashed_key[..]
+        } else {
+            key
+        };
+        assert_eq!(set_mode(hmac, key), Ok(()));
+        add_data(hmac, sha, data);
+        assert!(hmac.run(Box::leak(Box::new([0; L]))).is_ok());
+        sha.complete_all();
+        client.digest.take().unwrap()
+    }
+
+    #[test]
+    fn hmac_sha384() {
+        for test in TEST_CASES.iter() {
+            let digest = hmac::<48>(test.key, test.data, |hmac, key| {
+                hmac.set_mode_hmacsha384(key)
+            });
+            assert_eq!(&digest[..test.hmac_sha384.len()], test.hmac_sha384);
+        }
+    }
+
+    #[test]
+    fn hmac_sha512() {
+        for test in TEST_CASES.iter() {
+            let digest = hmac::<64>(test.key, test.data, |hmac, key| {
+                hmac.set_mode_hmacsha512(key)
+            });
+            assert_eq!(&digest[..test.hmac_sha512.len()], test.hmac_sha512);
+        }
+    }
+
+    #[test]
+    fn wrong_digest_length() {
+        let (hmac, _, _) = setup::<48>();
+        assert_eq!(hmac.set_mode_hmacsha512(b"Jefe"), Err(ErrorCode::NOSUPPORT));
+        let (hmac, _, _) = setup::<64>();
+        assert_eq!(hmac.set_mode_hmacsha384(b"Jefe"), Err(ErrorCode::NOSUPPORT));
+    }
+
+    #[test]
+    fn verify() {
+        let test = &TEST_CASES[1];
+        for corrupt in [false, true] {
+            let (hmac, sha, client) = setup::<64>();
+            assert_eq!(hmac.set_mode_hmacsha512(test.key), Ok(()));
+            add_data(hmac, sha, test.data);
+
+            let mut compare = [0; 64];
+            compare.copy_from_slice(test.hmac_sha512);
+            if corrupt {
+                compare[0] ^= 1;
+            }
+            assert!(hmac.verify(Box::leak(Box::new(compare))).is_ok());
+            sha.complete_all();
+            assert_eq!(client.verified.take(), Some(!corrupt));
+        }
+    }
+}
@@ -0,0 +1,1185 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! ISO-TP (ISO 15765-2) transport over CAN for userspace.
+//!
+//! Lets processes send and receive messages of up to 4095 bytes, such as
+//! diagnostic requests and responses, which ISO-TP splits into single,
+//! first and consecutive frames and paces with flow control frames.
+//!
+//! Each process opens one session, with the identifier its frames are sent
+//! with and the one it receives frames on, so sessions of different
+//! processes run at the same time. Messages are copied frame by frame
+//! between the CAN controller and the buffers the process allowed. Frames
+//! of all sessions share one transmit buffer and are sent in turn, and
+//! frames are padded to 8 bytes.
+//!
+//! The receiving side asks for the block size and separation time each
+//! process sets, none by default; the sending side follows the flow control
+//! frames of its peer. A transfer fails if the peer takes longer than a
+//! second to send the next flow control or consecutive frame.
+//!
+//! This capsule must be the only client of the CAN controller, which it
+//! enables when the first session is opened. The bitrate must be set
+//! before.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! let isotp = components::isotp::IsoTpComponent::new(
+//!     board_kernel,
+//!     capsules_extra::isotp::DRIVER_NUM,
+//!     &peripherals.can1,
+//!     mux_alarm,
+//! )
+//! .finalize(components::isotp_component_static!(
+//!     stm32f429zi::can::Can<'static>,
+//!     stm32f429zi::tim2::Tim2<'static>
+//! ));
+//! ```
+
+use core::cell::Cell;
+use core::cmp;
+
+use kernel::grant::{AllowRoCount, AllowRwCount, Grant, GrantKernelData, UpcallCount};
+use kernel::hil::can;
+use kernel::hil::time::{Alarm, AlarmClient, ConvertTicks, Ticks};
+use kernel::processbuffer::{
+    ReadableProcessBuffer, ReadableProcessSlice, WriteableProcessBuffer, WriteableProcessSlice,
+};
+use kernel::syscall::{CommandReturn, SyscallDriver};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::{ErrorCode, ProcessId};
+
+/// Syscall driver number.
+use capsules_core::driver;
+pub const DRIVER_NUM: usize = driver::NUM::IsoTp as usize;
+
+/// Marks identifiers in command arguments as extended, 29-bit ones.
+pub const EXTENDED_ID: usize = 1 << 31;
+
+/// The longest message, as the length in first frames has 12 bits.
+pub const MAX_MESSAGE_LEN: usize = 4095;
+
+/// How long to wait for a flow control frame (N_Bs) or a consecutive frame
+/// (N_Cr).
+const TIMEOUT_MS: u32 = 1000;
+/// How many wait flow control frames in a row a sender accepts (N_WFTmax).
+const MAX_WAITS: u8 = 10;
+const PADDING: u8 = 0xCC;
+
+const FRAME_LEN: usize = can::STANDARD_CAN_PACKET_SIZE;
+const SINGLE_FRAME: u8 = 0x0;
+const FIRST_FRAME: u8 = 0x1;
+const CONSECUTIVE_FRAME: u8 = 0x2;
+const FLOW_CONTROL_FRAME: u8 = 0x3;
+const CONTINUE: u8 = 0x0;
+const WAIT: u8 = 0x1;
+const OVERFLOW: u8 = 0x2;
+
+/// Ids for read-only allow buffers
+mod ro_allow {
+    /// The message to send.
+    pub const SEND: usize = 0;
+    /// The number of allow buffers the kernel stores for this grant
+    pub const COUNT: u8 = 1;
+}
+
+/// Ids for read-write allow buffers
+mod rw_allow {
+    /// The buffer messages are received in.
+    pub const RECEIVE: usize = 0;
+    /// The number of allow buffers the kernel stores for this grant
+    pub const COUNT: u8 = 1;
+}
+
+mod upcall {
+    /// A message was sent.
+    pub const SENT: usize = 0;
+    /// A message was received.
+    pub const RECEIVED: usize = 1;
+    /// The number of upcalls the kernel stores for this grant
+    pub const COUNT: u8 = 2;
+}
+
+/// The state of the message a process is sending.
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Send {
+    Idle,
+    /// The next frame can be sent.
+    Ready,
+    /// A frame is being sent.
+    InFlight,
+    /// Waiting for a flow control frame.
+    FlowControl,
+    /// Waiting out the separation time before the next consecutive frame.
+    Separation,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Frame {
+    Single,
+    First,
+    Consecutive,
+    FlowControl,
+}
+
+#[derive(Clone, Copy)]
+struct Timer<T: Ticks> {
+    reference: T,
+    dt: T,
+}
+
+impl<T: Ticks> Timer<T> {
+    fn expired(&self, now: T) -> bool {
+        !now.within_range(self.reference, self.reference.wrapping_add(self.dt))
+    }
+
+    fn remaining(&self, now: T) -> T {
+        if self.expired(now) {
+            T::from(0)
+        } else {
+            self.reference.wrapping_add(self.dt).wrapping_sub(now)
+        }
+    }
+}
+
+pub struct App<T: Ticks> {
+    /// The identifiers frames are sent with and received on, with
+    /// `EXTENDED_ID` set for extended ones.
+    tx_id: Option<usize>,
+    rx_id: Option<usize>,
+    /// The block size and separation time asked for when receiving.
+    block_size: u8,
+    st_min: u8,
+    send: Send,
+    send_len: usize,
+    send_offset: usize,
+    send_sequence: u8,
+    /// The consecutive frames left in the block, if the receiver limits it.
+    send_block: Option<u8>,
+    send_st_min: u8,
+    send_waits: u8,
+    send_timer: Option<Timer<T>>,
+    receiving: bool,
+    receive_len: usize,
+    receive_offset: usize,
+    receive_sequence: u8,
+    /// The consecutive frames received in the block.
+    receive_block: u8,
+    receive_timer: Option<Timer<T>>,
+    /// The status of the flow control frame to send.
+    flow_control: Option<u8>,
+}
+
+impl<T: Ticks> Default for App<T> {
+    fn default() -> App<T> {
+        App {
+            tx_id: None,
+            rx_id: None,
+            block_size: 0,
+            st_min: 0,
+            send: Send::Idle,
+            send_len: 0,
+            send_offset: 0,
+            send_sequence: 0,
+            send_block: None,
+            send_st_min: 0,
+            send_waits: 0,
+            send_timer: None,
+            receiving: false,
+            receive_len: 0,
+            receive_offset: 0,
+            receive_sequence: 0,
+            receive_block: 0,
+            receive_timer: None,
+            flow_control: None,
+        }
+    }
+}
+
+fn id_from(id: usize) -> Option<can::Id> {
+    if id & EXTENDED_ID != 0 {
+        let id = id & !EXTENDED_ID;
+        (id < 1 << 29).then_some(can::Id::Extended(id as u32))
+    } else {
+        (id < 1 << 11).then_some(can::Id::Standard(id as u16))
+    }
+}
+
+fn id_into(id: can::Id) -> usize {
+    match id {
+        can::Id::Standard(id) => id as usize,
+        can::Id::Extended(id) => id as usize | EXTENDED_ID,
+    }
+}
+
+/// The separation time `st_min` asks for, in microseconds.
+fn separation_us(st_min: u8) -> u32 {
+    match st_min {
+        0x00..=0x7F => st_min as u32 * 1000,
+        0xF1..=0xF9 => (st_min - 0xF0) as u32 * 100,
+        // Reserved values mean the longest time.
+        _ => 127_000,
+    }
+}
+
+/// Reports the end of a transfer to the process, with the upcall, the
+/// status and the length of the message.
+type Notify<'b> = &'b dyn Fn(usize, Result<(), ErrorCode>, usize);
+
+/// Starts a timer of the given number of microseconds from now.
+type StartTimer<'b, T> = &'b dyn Fn(u32) -> Timer<T>;
+
+impl<T: Ticks> App<T> {
+    /// Start sending the first `len` bytes of the `allowed` bytes the
+    /// process allowed.
+    fn start_send(&mut self, len: usize, allowed: usize) -> Result<(), ErrorCode> {
+        if self.tx_id.is_none() {
+            return Err(ErrorCode::RESERVE);
+        }
+        if self.send != Send::Idle {
+            return Err(ErrorCode::BUSY);
+        }
+        if len == 0 || len > MAX_MESSAGE_LEN || len > allowed {
+            return Err(ErrorCode::SIZE);
+        }
+        self.send = Send::Ready;
+        self.send_len = len;
+        self.send_offset = 0;
+        self.send_waits = 0;
+        Ok(())
+    }
+
+    fn send_done(&mut self, r: Result<(), ErrorCode>, notify: Notify) {
+        self.send = Send::Idle;
+        self.send_timer = None;
+        notify(upcall::SENT, r, self.send_len);
+    }
+
+    fn receive_done(&mut self, r: Result<(), ErrorCode>, len: usize, notify: Notify) {
+        self.receiving = false;
+        self.receive_timer = None;
+        notify(upcall::RECEIVED, r, len);
+    }
+
+    /// Fill `frame` with the next frame of the session, taking data from
+    /// `message`, and return the identifier to send it with and its kind.
+    fn build_frame(
+        &mut self,
+        message: &ReadableProcessSlice,
+        frame: &mut [u8; FRAME_LEN],
+        notify: Notify,
+    ) -> Option<(can::Id, Frame)> {
+        let id = self.tx_id.and_then(id_from)?;
+        frame.fill(PADDING);
+
+        if let Some(status) = self.flow_control.take() {
+            frame[0] = FLOW_CONTROL_FRAME << 4 | status;
+            frame[1] = self.block_size;
+            frame[2] = self.st_min;
+            return Some((id, Frame::FlowControl));
+        }
+
+        let (len, offset) = (self.send_len, self.send_offset);
+        let (kind, header, data_len) = if offset == 0 && len < FRAME_LEN {
+            frame[0] = SINGLE_FRAME << 4 | len as u8;
+            (Frame::Single, 1, len)
+        } else if offset == 0 {
+            frame[0] = FIRST_FRAME << 4 | (len >> 8) as u8;
+            frame[1] = len as u8;
+            self.send_sequence = 1;
+            (Frame::First, 2, FRAME_LEN - 2)
+        } else {
+            frame[0] = CONSECUTIVE_FRAME << 4 | self.send_sequence;
+            self.send_sequence = (self.send_sequence + 1) & 0xF;
+            (Frame::Consecutive, 1, cmp::min(FRAME_LEN - 1, len - offset))
+        };
+        let Some(data) = message.get(offset..offset + data_len) else {
+            // The process allowed a shorter buffer since.
+            self.send_done(Err(ErrorCode::SIZE), notify);
+            return None;
+        };
+        data.copy_to_slice(&mut frame[header..header + data_len]);
+        self.send_offset = offset + data_len;
+        self.send = Send::InFlight;
+        Some((id, kind))
+    }
+
+    /// Handle a frame of kind `kind` sent with `status`.
+    fn frame_sent(
+        &mut self,
+        kind: Frame,
+        status: Result<(), ErrorCode>,
+        start_timer: StartTimer<T>,
+        notify: Notify,
+    ) {
+        if kind == Frame::FlowControl {
+            if status.is_err() && self.receiving {
+                self.receive_done(Err(ErrorCode::FAIL), 0, notify);
+            }
+            return;
+        }
+        if self.send != Send::InFlight {
+            return;
+        }
+        if let Err(e) = status {
+            self.send_done(Err(e), notify);
+            return;
+        }
+        if self.send_offset >= self.send_len {
+            self.send_done(Ok(()), notify);
+            return;
+        }
+        let block_done = match kind {
+            Frame::Consecutive => match self.send_block {
+                Some(left) => {
+                    self.send_block = Some(left - 1);
+                    left == 1
+                }
+                None => false,
+            },
+            _ => true,
+        };
+        if block_done {
+            self.send = Send::FlowControl;
+            self.send_timer = Some(start_timer(TIMEOUT_MS * 1000));
+        } else if self.send_st_min != 0 {
+            self.send = Send::Separation;
+            self.send_timer = Some(start_timer(separation_us(self.send_st_min)));
+        } else {
+            self.send = Send::Ready;
+        }
+    }
+
+    /// Handle a frame received for the session, copying the message into
+    /// `buffer`.
+    fn frame_received(
+        &mut self,
+        frame: &[u8],
+        buffer: &WriteableProcessSlice,
+        start_timer: StartTimer<T>,
+        notify: Notify,
+    ) {
+        let Some(&pci) = frame.first() else {
+            return;
+        };
+        match pci >> 4 {
+            SINGLE_FRAME => {
+                let len = (pci & 0xF) as usize;
+                if len == 0 || len >= frame.len() {
+                    return;
+                }
+                if self.receiving {
+                    self.receive_done(Err(ErrorCode::FAIL), 0, notify);
+                }
+                let r = buffer
+                    .get(0..len)
+                    .ok_or(ErrorCode::SIZE)
+                    .map(|data| data.copy_from_slice(&frame[1..=len]));
+                self.receive_done(r, len, notify);
+            }
+            FIRST_FRAME => {
+                if frame.len() < FRAME_LEN {
+                    return;
+                }
+                let len = ((pci & 0xF) as usize) << 8 | frame[1] as usize;
+                if len < FRAME_LEN {
+                    return;
+                }
+                if self.receiving {
+                    self.receive_done(Err(ErrorCode::FAIL), 0, notify);
+                }
+                if buffer.len() < len {
+                    self.flow_control = Some(OVERFLOW);
+                    self.receive_done(Err(ErrorCode::SIZE), len, notify);
+                    return;
+                }
+                buffer[..FRAME_LEN - 2].copy_from_slice(&frame[2..]);
+                self.receiving = true;
+                self.receive_len = len;
+                self.receive_offset = FRAME_LEN - 2;
+                self.receive_sequence = 1;
+                self.receive_block = 0;
+                self.receive_timer = Some(start_timer(TIMEOUT_MS * 1000));
+                self.flow_control = Some(CONTINUE);
+            }
+            CONSECUTIVE_FRAME => {
+                if !self.receiving {
+                    return;
+                }
+                if pci & 0xF != self.receive_sequence {
+                    self.receive_done(Err(ErrorCode::FAIL), 0, notify);
+                    return;
+                }
+                let offset = self.receive_offset;
+                let len = cmp::min(self.receive_len - offset, frame.len() - 1);
+                let Some(data) = buffer.get(offset..offset + len) else {
+                    self.receive_done(Err(ErrorCode::SIZE), 0, notify);
+                    return;
+                };
+                data.copy_from_slice(&frame[1..=len]);
+                self.receive_offset = offset + len;
+                self.receive_sequence = (self.receive_sequence + 1) & 0xF;
+                if self.receive_offset == self.receive_len {
+                    self.receive_done(Ok(()), self.receive_len, notify);
+                    return;
+                }
+                self.receive_timer = Some(start_timer(TIMEOUT_MS * 1000));
+                if self.block_size != 0 {
+                    self.receive_block += 1;
+                    if self.receive_block == self.block_size {
+                        self.receive_block = 0;
+                        self.flow_control = Some(CONTINUE);
+                    }
+                }
+            }
+            FLOW_CONTROL_FRAME => {
+                if self.send != Send::FlowControl || frame.len() < 3 {
+                    return;
+                }
+                match pci & 0xF {
+                    CONTINUE => {
+                        self.send = Send::Ready;
+                        self.send_block = (frame[1] != 0).then_some(frame[1]);
+                        self.send_st_min = frame[2];
+                        self.send_waits = 0;
+                        self.send_timer = None;
+                    }
+                    WAIT if self.send_waits < MAX_WAITS => {
+                        self.send_waits += 1;
+                        self.send_timer = Some(start_timer(TIMEOUT_MS * 1000));
+                    }
+                    WAIT => self.send_done(Err(ErrorCode::NOACK), notify),
+                    OVERFLOW => self.send_done(Err(ErrorCode::SIZE), notify),
+                    _ => self.send_done(Err(ErrorCode::FAIL), notify),
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Handle the timers that expired by `now`.
+    fn timers_expired(&mut self, now: T, notify: Notify) {
+        if self.send_timer.is_some_and(|timer| timer.expired(now)) {
+            self.send_timer = None;
+            match self.send {
+                Send::Separation => self.send = Send::Ready,
+                _ => self.send_done(Err(ErrorCode::NOACK), notify),
+            }
+        }
+        if self.receive_timer.is_some_and(|timer| timer.expired(now)) {
+            self.receive_done(Err(ErrorCode::FAIL), 0, notify);
+        }
+    }
+}
+
+/// Schedule upcall `upcall` of a process with the status `r` and `len`.
+fn notify(kernel_data: &GrantKernelData, upcall: usize, r: Result<(), ErrorCode>, len: usize) {
+    kernel_data
+        .schedule_upcall(upcall, (kernel::errorcode::into_statuscode(r), len, 0))
+        .ok();
+}
+
+pub struct IsoTp<'a, C: can::Can, A: Alarm<'a>> {
+    can: &'a C,
+    alarm: &'a A,
+    apps: Grant<
+        App<A::Ticks>,
+        UpcallCount<{ upcall::COUNT }>,
+        AllowRoCount<{ ro_allow::COUNT }>,
+        AllowRwCount<{ rw_allow::COUNT }>,
+    >,
+    tx_buffer: TakeCell<'static, [u8; FRAME_LEN]>,
+    rx_buffer: TakeCell<'static, [u8; FRAME_LEN]>,
+    /// The process and kind of the frame being sent.
+    in_flight: OptionalCell<(ProcessId, Frame)>,
+    /// Where to start looking for the next frame to send, so that sessions
+    /// take turns.
+    next_app: Cell<usize>,
+    enabling: Cell<bool>,
+    running: Cell<bool>,
+}
+
+impl<'a, C: can::Can, A: Alarm<'a>> IsoTp<'a, C, A> {
+    pub fn new(
+        can: &'a C,
+        alarm: &'a A,
+        grant: Grant<
+            App<A::Ticks>,
+            UpcallCount<{ upcall::COUNT }>,
+            AllowRoCount<{ ro_allow::COUNT }>,
+            AllowRwCount<{ rw_allow::COUNT }>,
+        >,
+        tx_buffer: &'static mut [u8; FRAME_LEN],
+        rx_buffer: &'static mut [u8; FRAME_LEN],
+    ) -> Self {
+        IsoTp {
+            can,
+            alarm,
+            apps: grant,
+            tx_buffer: TakeCell::new(tx_buffer),
+            rx_buffer: TakeCell::new(rx_buffer),
+            in_flight: OptionalCell::empty(),
+            next_app: Cell::new(0),
+            enabling: Cell::new(false),
+            running: Cell::new(false),
+        }
+    }
+
+    fn timer(&self, us: u32) -> Timer<A::Ticks> {
+        Timer {
+            reference: self.alarm.now(),
+            dt: self.alarm.ticks_from_us(us),
+        }
+    }
+
+    fn open_session(&self, processid: ProcessId, tx_id: usize, rx_id: usize) -> CommandReturn {
+        if id_from(tx_id).is_none() || id_from(rx_id).is_none() {
+            return CommandReturn::failure(ErrorCode::INVAL);
+        }
+        for cntr in self.apps.iter() {
+            if cntr.processid() != processid && cntr.enter(|app, _| app.rx_id == Some(rx_id)) {
+                return CommandReturn::failure(ErrorCode::RESERVE);
+            }
+        }
+        let r = self
+            .apps
+            .enter(processid, |app, _| {
+                if app.send != Send::Idle || app.receiving {
+                    return Err(ErrorCode::BUSY);
+                }
+                app.tx_id = Some(tx_id);
+                app.rx_id = Some(rx_id);
+                Ok(())
+            })
+            .unwrap_or_else(|err| err.into());
+        if r.is_ok() && !self.running.get() && !self.enabling.get() {
+            self.enabling.set(true);
+            if let Err(e) = self.can.enable() {
+                self.enabling.set(false);
+                return CommandReturn::failure(e);
+            }
+        }
+        r.into()
+    }
+
+    fn start_send(&self, processid: ProcessId, len: usize) -> CommandReturn {
+        let r = self
+            .apps
+            .enter(processid, |app, kernel_data| {
+                let allowed = kernel_data
+                    .get_readonly_processbuffer(ro_allow::SEND)
+                    .map_or(0, |buffer| buffer.len());
+                app.start_send(len, allowed)
+            })
+            .unwrap_or_else(|err| err.into());
+        if r.is_ok() {
+            self.send_next_frame();
+        }
+        r.into()
+    }
+
+    /// Send a frame of the next session that has one ready, if the
+    /// transmit buffer is free.
+    fn send_next_frame(&self) {
+        if !self.running.get() || self.in_flight.is_some() || self.tx_buffer.is_none() {
+            return;
+        }
+        let mut first = None;
+        let mut next = None;
+        for (index, cntr) in self.apps.iter().enumerate() {
+            let processid = cntr.processid();
+            let ready = cntr.enter(|app, _| app.flow_control.is_some() || app.send == Send::Ready);
+            if ready {
+                first = first.or(Some((index, processid)));
+                if index >= self.next_app.get() {
+                    next = next.or(Some((index, processid)));
+                }
+            }
+        }
+        let Some((index, processid)) = next.or(first) else {
+            return;
+        };
+        self.next_app.set(index + 1);
+
+        self.tx_buffer.take().map(|frame| {
+            let built = self
+                .apps
+                .enter(processid, |app, kernel_data| {
+                    let notify = |upcall, r, len| notify(kernel_data, upcall, r, len);
+                    kernel_data
+                        .get_readonly_processbuffer(ro_allow::SEND)
+                        .and_then(|buffer| {
+                            buffer.enter(|message| app.build_frame(message, frame, &notify))
+                        })
+                        .unwrap_or_else(|_| app.build_frame((&[][..]).into(), frame, &notify))
+                })
+                .unwrap_or(None);
+            let Some((id, kind)) = built else {
+                self.tx_buffer.replace(frame);
+                return;
+            };
+            match self.can.send(id, frame, FRAME_LEN) {
+                Ok(()) => self.in_flight.set((processid, kind)),
+                Err((e, frame)) => {
+                    self.tx_buffer.replace(frame);
+                    let _ = self.apps.enter(processid, |app, kernel_data| {
+                        let notify = |upcall, r, len| notify(kernel_data, upcall, r, len);
+                        match kind {
+                            Frame::FlowControl => {
+                                if app.receiving {
+                                    app.receive_done(Err(e), 0, &notify);
+                                }
+                            }
+                            _ => app.send_done(Err(e), &notify),
+                        }
+                    });
+                }
+            }
+        });
+    }
+
+    /// Set the alarm for the earliest timer of any session.
+    fn schedule_alarm(&self) {
+        let now = self.alarm.now();
+        let mut earliest: Option<A::Ticks> = None;
+        for cntr in self.apps.iter() {
+            cntr.enter(|app, _| {
+                for timer in [app.send_timer, app.receive_timer].iter().flatten() {
+                    let remaining = timer.remaining(now);
+                    earliest = Some(earliest.map_or(remaining, |e| cmp::min(e, remaining)));
+                }
+            });
+        }
+        match earliest {
+            Some(dt) => self.alarm.set_alarm(now, cmp::max(dt, A::Ticks::from(1))),
+            None => {
+                let _ = self.alarm.disarm();
+            }
+        }
+    }
+}
+
+impl<'a, C: can::Can, A: Alarm<'a>> AlarmClient for IsoTp<'a, C, A> {
+    fn alarm(&self) {
+        let now = self.alarm.now();
+        for cntr in self.apps.iter() {
+            cntr.enter(|app, kernel_data| {
+                app.timers_expired(now, &|upcall, r, len| notify(kernel_data, upcall, r, len));
+            });
+        }
+        self.send_next_frame();
+        self.schedule_alarm();
+    }
+}
+
+impl<'a, C: can::Can, A: Alarm<'a>> can::ControllerClient for IsoTp<'a, C, A> {
+    fn state_changed(&self, state: can::State) {
+        self.running.set(state == can::State::Running);
+    }
+
+    fn enabled(&self, status: Result<(), ErrorCode>) {
+        self.enabling.set(false);
+        if status.is_err() {
+            self.running.set(false);
+            return;
+        }
+        self.rx_buffer.take().map(|buffer| {
+            if let Err((_, buffer)) = self.can.start_receive_process(buffer) {
+                self.rx_buffer.replace(buffer);
+            }
+        });
+        self.send_next_frame();
+    }
+
+    fn disabled(&self, _status: Result<(), ErrorCode>) {
+        self.running.set(false);
+    }
+}
+
+impl<'a, C: can::Can, A: Alarm<'a>> can::TransmitClient<FRAME_LEN> for IsoTp<'a, C, A> {
+    fn transmit_complete(
+        &self,
+        status: Result<(), can::Error>,
+        buffer: &'static mut [u8; FRAME_LEN],
+    ) {
+        self.tx_buffer.replace(buffer);
+        if let Some((processid, kind)) = self.in_flight.take() {
+            let _ = self.apps.enter(processid, |app, kernel_data| {
+                app.frame_sent(
+                    kind,
+                    status.map_err(ErrorCode::from),
+                    &|us| self.timer(us),
+                    &|upcall, r, len| notify(kernel_data, upcall, r, len),
+                );
+            });
+        }
+        self.send_next_frame();
+        self.schedule_alarm();
+    }
+}
+
+impl<'a, C: can::Can, A: Alarm<'a>> can::ReceiveClient<FRAME_LEN> for IsoTp<'a, C, A> {
+    fn message_received(
+        &self,
+        id: can::Id,
+        buffer: &mut [u8; FRAME_LEN],
+        len: usize,
+        status: Result<(), can::Error>,
+    ) {
+        if status.is_err() {
+            return;
+        }
+        let id = id_into(id);
+        let frame = &buffer[..cmp::min(len, FRAME_LEN)];
+        for cntr in self.apps.iter() {
+            cntr.enter(|app, kernel_data| {
+                if app.rx_id != Some(id) {
+                    return;
+                }
+                let start_timer = |us| self.timer(us);
+                let notify = |upcall, r, len| notify(kernel_data, upcall, r, len);
+                kernel_data
+                    .get_readwrite_processbuffer(rw_allow::RECEIVE)
+                    .and_then(|buffer| {
+                        buffer.mut_enter(|data| {
+                            app.frame_received(frame, data, &start_timer, &notify)
+                        })
+                    })
+                    .unwrap_or_else(|_| {
+                        app.frame_received(frame, (&mut [][..]).into(), &start_timer, &notify)
+                    });
+            });
+        }
+        self.send_next_frame();
+        self.schedule_alarm();
+    }
+
+    fn stopped(&self, buffer: &'static mut [u8; FRAME_LEN]) {
+        self.rx_buffer.replace(buffer);
+    }
+}
+
+impl<'a, C: can::Can, A: Alarm<'a>> SyscallDriver for IsoTp<'a, C, A> {
+    /// Command interface.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver existence check.
+    /// - `1`: Open a session sending frames with identifier `data1` and
+    ///   receiving frames with identifier `data2`. Identifiers with
+    ///   `EXTENDED_ID` set are extended ones.
+    /// - `2`: Ask senders for blocks of `data1` consecutive frames, 0 for no
+    ///   limit, separated by `data2`, in the encoding of flow control frames.
+    /// - `3`: Send the first `data1` bytes of the read-only allow buffer.
+    fn command(
+        &self,
+        command_num: usize,
+        data1: usize,
+        data2: usize,
+        processid: ProcessId,
+    ) -> CommandReturn {
+        match command_num {
+            0 => CommandReturn::success(),
+            1 => self.open_session(processid, data1, data2),
+            2 => {
+                let valid = matches!(data2, 0x00..=0x7F | 0xF1..=0xF9);
+                if data1 > u8::MAX as usize || !valid {
+                    return CommandReturn::failure(ErrorCode::INVAL);
+                }
+                self.apps
+                    .enter(processid, |app, _| {
+                        app.block_size = data1 as u8;
+                        app.st_min = data2 as u8;
+                    })
+                    .map_err(ErrorCode::from)
+                    .into()
+            }
+            3 => self.start_send(processid, data1),
+            _ => CommandReturn::failure(ErrorCode::NOSUPPORT),
+        }
+    }
+
+    fn allocate_grant(&self, processid: ProcessId) -> Result<(), kernel::process::Error> {
+        self.apps.enter(processid, |_, _| {})
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use kernel::hil::time::Ticks32;
+    use std::cell::RefCell;
+    use std::vec::Vec;
+
+    type Upcall = (usize, Result<(), ErrorCode>, usize);
+
+    /// Timers count microseconds from 0.
+    fn start_timer(us: u32) -> Timer<Ticks32> {
+        Timer {
+            reference: 0.into(),
+            dt: us.into(),
+        }
+    }
+
+    /// A session of a process, with the buffer it allowed: the message it
+    /// sends or the buffer it receives in.
+    struct Session {
+        app: App<Ticks32>,
+        buffer: Vec<u8>,
+        upcalls: RefCell<Vec<Upcall>>,
+    }
+
+    impl Session {
+        fn new(buffer: &[u8]) -> Session {
+            Session {
+                app: App {
+                    tx_id: Some(0x7E0),
+                    rx_id: Some(0x7E8),
+                    ..App::default()
+                },
+                buffer: buffer.into(),
+                upcalls: RefCell::new(Vec::new()),
+            }
+        }
+
+        fn upcalls(&self) -> Vec<Upcall> {
+            self.upcalls.take()
+        }
+
+        fn build(&mut self) -> Option<(Frame, [u8; FRAME_LEN])> {
+            let mut frame = [0; FRAME_LEN];
+            let notify = |upcall, r, len| self.upcalls.borrow_mut().push((upcall, r, len));
+            let (id, kind) =
+                self.app
+                    .build_frame((&self.buffer[..]).into(), &mut frame, &notify)?;
+            assert_eq!(id_into(id), 0x7E0);
+            Some((kind, frame))
+        }
+
+        fn sent(&mut self, kind: Frame) {
+            let notify = |upcall, r, len| self.upcalls.borrow_mut().push((upcall, r, len));
+            self.app.frame_sent(kind, Ok(()), &start_timer, &notify);
+        }
+
+        /// Build and send the next frame, if there is one ready.
+        fn next_frame(&mut self) -> Option<[u8; FRAME_LEN]> {
+            if self.app.flow_control.is_none() && self.app.send != Send::Ready {
+                return None;
+            }
+            let (kind, frame) = self.build()?;
+            self.sent(kind);
+            Some(frame)
+        }
+
+        fn receive(&mut self, frame: &[u8]) {
+            let notify = |upcall, r, len| self.upcalls.borrow_mut().push((upcall, r, len));
+            self.app
+                .frame_received(frame, (&mut self.buffer[..]).into(), &start_timer, &notify);
+        }
+
+        /// Let `us` microseconds pass since the timers started.
+        fn expire(&mut self, us: u32) {
+            let notify = |upcall, r, len| self.upcalls.borrow_mut().push((upcall, r, len));
+            self.app.timers_expired(us.into(), &notify);
+        }
+    }
+
+    /// Send the message of `sender` to `receiver`, and return the frames
+    /// sent both ways.
+    fn transfer(sender: &mut Session, receiver: &mut Session) -> Vec<[u8; FRAME_LEN]> {
+        let len = sender.buffer.len();
+        assert_eq!(sender.app.start_send(len, len), Ok(()));
+        let mut frames = Vec::new();
+        loop {
+            if let Some(frame) = receiver.next_frame() {
+                sender.receive(&frame);
+                frames.push(frame);
+            } else if let Some(frame) = sender.next_frame() {
+                receiver.receive(&frame);
+                frames.push(frame);
+            } else if sender.app.send == Send::Separation {
+                let timer = sender.app.send_timer.unwrap();
+                sender.expire(timer.dt.into_u32());
+            } else {
+                return frames;
+            }
+        }
+    }
+
+    fn message(len: usize) -> Vec<u8> {
+        (0..len).map(|i| i as u8).collect()
+    }
+
+    #[test]
+    fn single_frame() {
+        let mut sender = Session::new(&[0x3E, 0x00, 0x01]);
+        assert_eq!(sender.app.start_send(3, 3), Ok(()));
+        let (kind, frame) = sender.build().unwrap();
+        assert_eq!(kind, Frame::Single);
+        assert_eq!(frame, [0x03, 0x3E, 0x00, 0x01, 0xCC, 0xCC, 0xCC, 0xCC]);
+        sender.sent(kind);
+        assert_eq!(sender.upcalls(), [(upcall::SENT, Ok(()), 3)]);
+        assert_eq!(sender.app.send, Send::Idle);
+
+        let mut receiver = Session::new(&[0; 8]);
+        receiver.receive(&frame);
+        assert_eq!(receiver.upcalls(), [(upcall::RECEIVED, Ok(()), 3)]);
+        assert_eq!(receiver.buffer[..3], [0x3E, 0x00, 0x01]);
+
+        // The longest single frame.
+        let mut sender = Session::new(&message(7));
+        let frames = transfer(&mut sender, &mut receiver);
+        assert_eq!(frames, [[0x07, 0, 1, 2, 3, 4, 5, 6]]);
+        assert_eq!(receiver.upcalls(), [(upcall::RECEIVED, Ok(()), 7)]);
+
+        // Empty frames, and lengths beyond the frame, are ignored.
+        receiver.receive(&[0x00, 1, 2, 3, 4, 5, 6, 7]);
+        receiver.receive(&[0x03, 1, 2]);
+        receiver.receive(&[]);
+        assert!(receiver.upcalls().is_empty());
+
+        let mut receiver = Session::new(&[0; 2]);
+        receiver.receive(&frame);
+        assert_eq!(
+            receiver.upcalls(),
+            [(upcall::RECEIVED, Err(ErrorCode::SIZE), 3)]
+        );
+    }
+
+    #[test]
+    fn first_and_consecutive_frames() {
+        let mut sender = Session::new(&message(18));
+        assert_eq!(sender.app.start_send(18, 18), Ok(()));
+        let (kind, first) = sender.build().unwrap();
+        assert_eq!(kind, Frame::First);
+        assert_eq!(first, [0x10, 18, 0, 1, 2, 3, 4, 5]);
+        sender.sent(kind);
+        // The sender waits up to N_Bs for flow control.
+        assert_eq!(sender.app.send, Send::FlowControl);
+        assert_eq!(sender.app.send_timer.unwrap().dt.into_u32(), 1_000_000);
+
+        let mut receiver = Session::new(&[0; 32]);
+        receiver.receive(&first);
+        assert!(receiver.app.receiving);
+        let (kind, flow_control) = receiver.build().unwrap();
+        assert_eq!(kind, Frame::FlowControl);
+        assert_eq!(flow_control, [0x30, 0, 0, 0xCC, 0xCC, 0xCC, 0xCC, 0xCC]);
+        receiver.sent(kind);
+
+        sender.receive(&flow_control);
+        assert_eq!(sender.app.send, Send::Ready);
+        let second = sender.next_frame().unwrap();
+        assert_eq!(second, [0x21, 6, 7, 8, 9, 10, 11, 12]);
+        let third = sender.next_frame().unwrap();
+        assert_eq!(third, [0x22, 13, 14, 15, 16, 17, 0xCC, 0xCC]);
+        assert_eq!(sender.upcalls(), [(upcall::SENT, Ok(()), 18)]);
+
+        receiver.receive(&second);
+        assert!(receiver.upcalls().is_empty());
+        receiver.receive(&third);
+        assert_eq!(receiver.upcalls(), [(upcall::RECEIVED, Ok(()), 18)]);
+        assert_eq!(receiver.buffer[..18], message(18));
+        assert!(!receiver.app.receiving);
+    }
+
+    #[test]
+    fn sequence_numbers_wrap() {
+        // A first frame and 42 consecutive frames.
+        let mut sender = Session::new(&message(300));
+        let mut receiver = Session::new(&[0; 300]);
+        let frames = transfer(&mut sender, &mut receiver);
+        let pcis: Vec<u8> = frames.iter().map(|frame| frame[0]).collect();
+        // The length, 0x12C, is split between the first two bytes.
+        assert_eq!(frames[0][..2], [0x11, 0x2C]);
+        let mut expected = [0x11, 0x30].to_vec();
+        expected.extend((1..=42).map(|n| 0x20 | (n % 16)));
+        assert_eq!(pcis, expected);
+        assert_eq!(&pcis[15..19], [0x2E, 0x2F, 0x20, 0x21]);
+        assert_eq!(receiver.buffer, message(300));
+        assert_eq!(receiver.upcalls(), [(upcall::RECEIVED, Ok(()), 300)]);
+        assert_eq!(sender.upcalls(), [(upcall::SENT, Ok(()), 300)]);
+
+        // A consecutive frame out of sequence fails the transfer, and the
+        // frames after it are ignored.
+        let mut receiver = Session::new(&[0; 300]);
+        receiver.receive(&frames[0]);
+        receiver.receive(&frames[2]);
+        receiver.receive(&frames[4]);
+        assert_eq!(
+            receiver.upcalls(),
+            [(upcall::RECEIVED, Err(ErrorCode::FAIL), 0)]
+        );
+        receiver.receive(&frames[5]);
+        assert!(receiver.upcalls().is_empty());
+        assert!(!receiver.app.receiving);
+    }
+
+    #[test]
+    fn first_frame_longer_than_buffer() {
+        let mut sender = Session::new(&message(100));
+        let mut receiver = Session::new(&[0; 50]);
+        let frames = transfer(&mut sender, &mut receiver);
+        // The receiver answers with an overflow, and the sender gives up.
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[0][..2], [0x10, 100]);
+        assert_eq!(frames[1][..3], [0x32, 0, 0]);
+        assert_eq!(
+            receiver.upcalls(),
+            [(upcall::RECEIVED, Err(ErrorCode::SIZE), 100)]
+        );
+        assert_eq!(
+            sender.upcalls(),
+            [(upcall::SENT, Err(ErrorCode::SIZE), 100)]
+        );
+        assert!(!receiver.app.receiving);
+
+        // The longest message the 12-bit length allows.
+        let mut receiver = Session::new(&[0; MAX_MESSAGE_LEN]);
+        receiver.receive(&[0x1F, 0xFF, 0, 1, 2, 3, 4, 5]);
+        assert!(receiver.app.receiving);
+        assert_eq!(receiver.app.receive_len, MAX_MESSAGE_LEN);
+
+        // First frames of messages that fit a single frame, and short first
+        // frames, are ignored.
+        let mut receiver = Session::new(&[0; 50]);
+        receiver.receive(&[0x10, 7, 0, 1, 2, 3, 4, 5]);
+        receiver.receive(&[0x10, 20, 0, 1]);
+        assert!(!receiver.app.receiving);
+        assert!(receiver.app.flow_control.is_none());
+        assert!(receiver.upcalls().is_empty());
+    }
+
+    #[test]
+    fn sender_block_size_and_separation_time() {
+        let mut sender = Session::new(&message(40));
+        assert_eq!(sender.app.start_send(40, 40), Ok(()));
+        sender.next_frame().unwrap();
+        // Blocks of 2 frames, 5 ms apart.
+        sender.receive(&[0x30, 2, 0x05, 0xCC, 0xCC, 0xCC, 0xCC, 0xCC]);
+        assert_eq!(sender.next_frame().unwrap()[0], 0x21);
+        assert_eq!(sender.app.send, Send::Separation);
+        assert_eq!(sender.app.send_timer.unwrap().dt.into_u32(), 5000);
+        sender.expire(4999);
+        assert_eq!(sender.app.send, Send::Separation);
+        sender.expire(5000);
+        assert_eq!(sender.app.send, Send::Ready);
+        assert_eq!(sender.next_frame().unwrap()[0], 0x22);
+        // The block is done.
+        assert_eq!(sender.app.send, Send::FlowControl);
+        assert_eq!(sender.app.send_timer.unwrap().dt.into_u32(), 1_000_000);
+
+        // No limit on the block, 300 us apart.
+        sender.receive(&[0x30, 0, 0xF3, 0xCC, 0xCC, 0xCC, 0xCC, 0xCC]);
+        assert_eq!(sender.next_frame().unwrap()[0], 0x23);
+        assert_eq!(sender.app.send_timer.unwrap().dt.into_u32(), 300);
+        sender.expire(300);
+        assert_eq!(sender.next_frame().unwrap()[0], 0x24);
+        sender.expire(300);
+        assert_eq!(sender.next_frame().unwrap()[0], 0x25);
+        assert_eq!(sender.upcalls(), [(upcall::SENT, Ok(()), 40)]);
+    }
+
+    #[test]
+    fn receiver_block_size_and_separation_time() {
+        let mut sender = Session::new(&message(40));
+        let mut receiver = Session::new(&[0; 40]);
+        receiver.app.block_size = 2;
+        receiver.app.st_min = 0x0A;
+        let frames = transfer(&mut sender, &mut receiver);
+        let pcis: Vec<u8> = frames.iter().map(|frame| frame[0]).collect();
+        assert_eq!(pcis, [0x10, 0x30, 0x21, 0x22, 0x30, 0x23, 0x24, 0x30, 0x25]);
+        assert!(frames
+            .iter()
+            .filter(|frame| frame[0] == 0x30)
+            .all(|frame| frame[1..3] == [2, 0x0A]));
+        assert_eq!(receiver.buffer, message(40));
+        assert_eq!(receiver.upcalls(), [(upcall::RECEIVED, Ok(()), 40)]);
+    }
+
+    #[test]
+    fn separation_times() {
+        assert_eq!(separation_us(0x01), 1000);
+        assert_eq!(separation_us(0x7F), 127_000);
+        assert_eq!(separation_us(0xF1), 100);
+        assert_eq!(separation_us(0xF9), 900);
+        // Reserved values.
+        assert_eq!(separation_us(0x80), 127_000);
+        assert_eq!(separation_us(0xF0), 127_000);
+        assert_eq!(separation_us(0xFA), 127_000);
+    }
+
+    #[test]
+    fn flow_control_wait_and_timeouts() {
+        let mut sender = Session::new(&message(20));
+        assert_eq!(sender.app.start_send(20, 20), Ok(()));
+        sender.next_frame().unwrap();
+        for _ in 0..MAX_WAITS {
+            sender.receive(&[0x31, 0, 0, 0xCC, 0xCC, 0xCC, 0xCC, 0xCC]);
+            assert_eq!(sender.app.send, Send::FlowControl);
+        }
+        assert!(sender.upcalls().is_empty());
+        sender.receive(&[0x31, 0, 0, 0xCC, 0xCC, 0xCC, 0xCC, 0xCC]);
+        assert_eq!(
+            sender.upcalls(),
+            [(upcall::SENT, Err(ErrorCode::NOACK), 20)]
+        );
+
+        // A reserved flow status.
+        assert_eq!(sender.app.start_send(20, 20), Ok(()));
+        sender.next_frame().unwrap();
+        sender.receive(&[0x33, 0, 0]);
+        assert_eq!(sender.upcalls(), [(upcall::SENT, Err(ErrorCode::FAIL), 20)]);
+
+        // No flow control within N_Bs.
+        assert_eq!(sender.app.start_send(20, 20), Ok(()));
+        sender.next_frame().unwrap();
+        sender.expire(999_999);
+        assert!(sender.upcalls().is_empty());
+        sender.expire(1_000_000);
+        assert_eq!(
+            sender.upcalls(),
+            [(upcall::SENT, Err(ErrorCode::NOACK), 20)]
+        );
+
+        // No consecutive frame within N_Cr.
+        let mut receiver = Session::new(&[0; 20]);
+        receiver.receive(&[0x10, 20, 0, 1, 2, 3, 4, 5]);
+        receiver.expire(1_000_000);
+        assert_eq!(
+            receiver.upcalls(),
+            [(upcall::RECEIVED, Err(ErrorCode::FAIL), 0)]
+        );
+        assert!(!receiver.app.receiving);
+    }
+
+    #[test]
+    fn start_send_errors() {
+        let mut app = App::<Ticks32>::default();
+        assert_eq!(app.start_send(1, 1), Err(ErrorCode::RESERVE));
+        app.tx_id = Some(0x7E0);
+        assert_eq!(app.start_send(0, 1), Err(ErrorCode::SIZE));
+        assert_eq!(app.start_send(2, 1), Err(ErrorCode::SIZE));
+        assert_eq!(
+            app.start_send(MAX_MESSAGE_LEN + 1, MAX_MESSAGE_LEN + 1),
+            Err(ErrorCode::SIZE)
+        );
+        assert_eq!(app.start_send(1, 1), Ok(()));
+        assert_eq!(app.start_send(1, 1), Err(ErrorCode::BUSY));
+
+        // The process allows a shorter buffer during the transfer.
+        let mut sender = Session::new(&message(20));
+        assert_eq!(sender.app.start_send(20, 20), Ok(()));
+        sender.next_frame().unwrap();
+        sender.buffer.truncate(10);
+        sender.receive(&[0x30, 0, 0]);
+        assert!(sender.build().is_none());
+        assert_eq!(sender.upcalls(), [(upcall::SENT, Err(ErrorCode::SIZE), 20)]);
+        assert_eq!(sender.app.send, Send::Idle);
+    }
+}
@@ -0,0 +1,313 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Key derivation with HKDF and PBKDF2 for userspace.
+//!
+//! An app passes the input key material (or password), salt and info in
+//! read-only allow buffers and chooses where the derived key goes: either its
+//! `OUTPUT` read-write allow buffer, or a new slot in the kernel keystore. In
+//! the second case the app receives a `KeyHandle` and the key itself never
+//! enters the app.
+//!
+//! The inputs are copied into a kernel buffer, so their total length is
+//! limited by the size of that buffer. Only one derivation runs at a time.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! let kdf = components::kdf::KdfHmacSha256Component::new(hmac)
+//!     .finalize(components::kdf_hmac_sha256_component_static!(HmacType, 256));
+//!
+//! let kdf_driver = components::kdf::KdfDriverComponent::new(
+//!     board_kernel,
+//!     capsules_extra::kdf::DRIVER_NUM,
+//!     kdf,
+//! )
+//! .finalize(components::kdf_driver_component_static!(KdfType, 256, 64));
+//! kdf_driver.set_keystore(keystore);
+//! ```
+
+use capsules_core::driver;
+/// Syscall driver number.
+pub const DRIVER_NUM: usize = driver::NUM::Kdf as usize;
+
+use core::cell::Cell;
+
+use kernel::errorcode::into_statuscode;
+use kernel::grant::{AllowRoCount, AllowRwCount, Grant, UpcallCount};
+use kernel::hil::kdf::{Client, Kdf};
+use kernel::hil::keystore::{KeyStore, KeyUsage};
+use kernel::processbuffer::{ReadableProcessBuffer, WriteableProcessBuffer};
+use kernel::syscall::{CommandReturn, SyscallDriver};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::utilities::leasable_buffer::SubSliceMut;
+use kernel::{ErrorCode, ProcessId};
+
+/// Ids for read-only allow buffers
+mod ro_allow {
+    /// The HKDF input key material, or the PBKDF2 password.
+    pub const IKM: usize = 0;
+    pub const SALT: usize = 1;
+    /// The HKDF info. Not used by PBKDF2.
+    pub const INFO: usize = 2;
+    /// The number of allow buffers the kernel stores for this grant
+    pub const COUNT: u8 = 3;
+}
+
+/// Ids for read-write allow buffers
+mod rw_allow {
+    pub const OUTPUT: usize = 0;
+    /// The number of allow buffers the kernel stores for this grant
+    pub const COUNT: u8 = 1;
+}
+
+mod upcall {
+    pub const DERIVATION_DONE: usize = 0;
+    /// The number of upcalls the kernel stores for this grant
+    pub const COUNT: u8 = 1;
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Algorithm {
+    Hkdf,
+    Pbkdf2,
+}
+
+#[derive(Default)]
+pub struct App {
+    /// The number of PBKDF2 iterations.
+    iterations: u32,
+}
+
+pub struct KdfDriver<'a, K: Kdf<'a>> {
+    kdf: &'a K,
+    keystore: OptionalCell<&'a dyn KeyStore>,
+
+    apps: Grant<
+        App,
+        UpcallCount<{ upcall::COUNT }>,
+        AllowRoCount<{ ro_allow::COUNT }>,
+        AllowRwCount<{ rw_allow::COUNT }>,
+    >,
+    processid: OptionalCell<ProcessId>,
+    /// Where the key in progress goes: `None` for the app's `OUTPUT` buffer,
+    /// or the usage of the new keystore key.
+    destination: Cell<Option<KeyUsage>>,
+
+    /// Holds the inputs while the derivation is started.
+    input: TakeCell<'static, [u8]>,
+    output: TakeCell<'static, [u8]>,
+}
+
+impl<'a, K: Kdf<'a>> KdfDriver<'a, K> {
+    pub fn new(
+        kdf: &'a K,
+        input: &'static mut [u8],
+        output: &'static mut [u8],
+        grant: Grant<
+            App,
+            UpcallCount<{ upcall::COUNT }>,
+            AllowRoCount<{ ro_allow::COUNT }>,
+            AllowRwCount<{ rw_allow::COUNT }>,
+        >,
+    ) -> Self {
+        KdfDriver {
+            kdf,
+            keystore: OptionalCell::empty(),
+            apps: grant,
+            processid: OptionalCell::empty(),
+            destination: Cell::new(None),
+            input: TakeCell::new(input),
+            output: TakeCell::new(output),
+        }
+    }
+
+    /// Allow apps to store derived keys in `keystore`.
+    pub fn set_keystore(&self, keystore: &'a dyn KeyStore) {
+        self.keystore.set(keystore);
+    }
+
+    fn derive(
+        &self,
+        algorithm: Algorithm,
+        destination: usize,
+        len: usize,
+        processid: ProcessId,
+    ) -> Result<(), ErrorCode> {
+        if self.processid.is_some() {
+            return Err(ErrorCode::BUSY);
+        }
+
+        let destination = match destination {
+            0 => None,
+            usage => {
+                if self.keystore.is_none() {
+                    return Err(ErrorCode::NOSUPPORT);
+                }
+                Some(KeyUsage::from_usize(usage).ok_or(ErrorCode::INVAL)?)
+            }
+        };
+
+        let input = self.input.take().ok_or(ErrorCode::BUSY)?;
+
+        // Copy the inputs one after another into `input`.
+        let copied = self
+            .apps
+            .enter(processid, |app, kernel_data| {
+                let mut lens = [0; ro_allow::COUNT as usize];
+                let mut copied = 0;
+                // PBKDF2 doesn't use the info, so it isn't read.
+                let inputs = match algorithm {
+                    Algorithm::Hkdf => lens.len(),
+                    Algorithm::Pbkdf2 => ro_allow::INFO,
+                };
+                for (id, len) in lens.iter_mut().enumerate().take(inputs) {
+                    *len = kernel_data
+                        .get_readonly_processbuffer(id)
+                        .and_then(|buffer| {
+                            buffer.enter(|buffer| {
+                                let dest = input
+                                    .get_mut(copied..copied + buffer.len())
+                                    .ok_or(ErrorCode::SIZE)?;
+                                buffer.copy_to_slice(dest);
+                                Ok(buffer.len())
+                            })
+                        })
+                        .unwrap_or(Err(ErrorCode::RESERVE))?;
+                    copied += *len;
+                }
+                Ok((lens, app.iterations))
+            })
+            .unwrap_or_else(|err| Err(err.into()));
+
+        self.processid.set(processid);
+        self.destination.set(destination);
+        let result = copied.and_then(|(lens, iterations)| {
+            let output = self.output.take().ok_or(ErrorCode::BUSY)?;
+            if len == 0 || len > output.len() {
+                self.output.replace(output);
+                return Err(ErrorCode::SIZE);
+            }
+            let mut output = SubSliceMut::new(output);
+            output.slice(..len);
+
+            let (ikm, rest) = input.split_at(lens[ro_allow::IKM]);
+            let (salt, rest) = rest.split_at(lens[ro_allow::SALT]);
+            let info = &rest[..lens[ro_allow::INFO]];
+
+            match algorithm {
+                Algorithm::Hkdf => self.kdf.hkdf(ikm, salt, info, output),
+                Algorithm::Pbkdf2 => self.kdf.pbkdf2(ikm, salt, iterations, output),
+            }
+            .map_err(|(e, output)| {
+                self.output.replace(output.take());
+                e
+            })
+        });
+
+        // The KDF has its own copy of the inputs by now.
+        input.fill(0);
+        self.input.replace(input);
+
+        if result.is_err() {
+            self.processid.clear();
+        }
+        result
+    }
+}
+
+impl<'a, K: Kdf<'a>> Client for KdfDriver<'a, K> {
+    fn derivation_done(&self, result: Result<(), ErrorCode>, mut output: SubSliceMut<'static, u8>) {
+        let len = output.len();
+
+        if let Some(processid) = self.processid.take() {
+            let _ = self.apps.enter(processid, |_, kernel_data| {
+                let result = result.and_then(|()| match self.destination.get() {
+                    None => kernel_data
+                        .get_readwrite_processbuffer(rw_allow::OUTPUT)
+                        .and_then(|dest| {
+                            dest.mut_enter(|dest| {
+                                if dest.len() < len {
+                                    return Err(ErrorCode::SIZE);
+                                }
+                                dest[..len].copy_from_slice(output.as_slice());
+                                Ok(len)
+                            })
+                        })
+                        .unwrap_or(Err(ErrorCode::RESERVE)),
+                    Some(usage) => self
+                        .keystore
+                        .map_or(Err(ErrorCode::NOSUPPORT), |keystore| {
+                            keystore.import(processid, usage, output.as_slice())
+                        })
+                        .map(|handle| handle.id() as usize),
+                });
+
+                let (status, value) = match result {
+                    Ok(value) => (into_statuscode(Ok(())), value),
+                    Err(e) => (into_statuscode(Err(e)), 0),
+                };
+                kernel_data
+                    .schedule_upcall(upcall::DERIVATION_DONE, (status, value, 0))
+                    .ok();
+            });
+        }
+
+        // Don't leave the key lying around in the kernel.
+        output.reset();
+        output.as_slice().fill(0);
+        self.output.replace(output.take());
+    }
+}
+
+impl<'a, K: Kdf<'a>> SyscallDriver for KdfDriver<'a, K> {
+    /// Control the KDF driver.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver existence check.
+    /// - `1`: Set the number of PBKDF2 iterations to `data1`.
+    /// - `2`: Derive a key with HKDF from the `IKM`, `SALT` and `INFO`
+    ///        read-only allow buffers.
+    /// - `3`: Derive a key with PBKDF2 from the password in the `IKM` and the
+    ///        `SALT` read-only allow buffers.
+    ///
+    /// For commands `2` and `3`, `data1` is where the key goes: `0` for the
+    /// `OUTPUT` read-write allow buffer, or a `KeyUsage` to add the key to the
+    /// keystore with that usage. `data2` is the length of the key.
+    fn command(
+        &self,
+        command_num: usize,
+        data1: usize,
+        data2: usize,
+        processid: ProcessId,
+    ) -> CommandReturn {
+        match command_num {
+            0 => CommandReturn::success(),
+
+            1 => {
+                if data1 == 0 || data1 > u32::MAX as usize {
+                    return CommandReturn::failure(ErrorCode::INVAL);
+                }
+                self.apps
+                    .enter(processid, |app, _| app.iterations = data1 as u32)
+                    .map_err(ErrorCode::from)
+                    .into()
+            }
+
+            2 => self.derive(Algorithm::Hkdf, data1, data2, processid).into(),
+
+            3 => self
+                .derive(Algorithm::Pbkdf2, data1, data2, processid)
+                .into(),
+
+            _ => CommandReturn::failure(ErrorCode::NOSUPPORT),
+        }
+    }
+
+    fn allocate_grant(&self, processid: ProcessId) -> Result<(), kernel::process::Error> {
+        self.apps.enter(processid, |_, _| {})
+    }
+}
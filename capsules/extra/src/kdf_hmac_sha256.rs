@@ -0,0 +1,585 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! HKDF and PBKDF2 with HMAC-SHA256.
+//!
+//! `KdfHmacSha256` implements `hil::kdf::Kdf` on top of any HMAC-SHA256
+//! implementation, and runs all the HMACs of a derivation in the kernel.
+//!
+//! The inputs are copied into `buffer` when a derivation starts, so its length
+//! limits how long they can be. HKDF needs `33 + info.len() + ikm.len()`
+//! bytes, and PBKDF2 needs `36 + salt.len()` bytes. The HKDF salt and the
+//! PBKDF2 password are used as HMAC keys, and can be at most 64 bytes long.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! let kdf = static_init!(
+//!     KdfHmacSha256<'static, HmacSha256Software<'static, Sha256Software<'static>>>,
+//!     KdfHmacSha256::new(hmac, buffer, digest)
+//! );
+//! kernel::hil::digest::Digest::set_client(hmac, kdf);
+//! ```
+
+use core::cell::Cell;
+
+use kernel::hil::digest;
+use kernel::hil::kdf::{Client, Kdf};
+use kernel::utilities::cells::{MapCell, OptionalCell, TakeCell};
+use kernel::utilities::leasable_buffer::{SubSlice, SubSliceMut};
+use kernel::ErrorCode;
+
+/// Length of the HMAC-SHA256 output.
+const DIGEST_LEN: usize = 32;
+/// The longest HMAC key, which is the SHA-256 block size.
+const MAX_KEY_LEN: usize = 64;
+/// HKDF can produce at most 255 blocks of output.
+const HKDF_MAX_OUTPUT_LEN: usize = 255 * DIGEST_LEN;
+
+/// One HMAC computation of a derivation.
+#[derive(Clone, Copy, PartialEq)]
+enum Step {
+    /// `PRK = HMAC(salt, IKM)`.
+    HkdfExtract,
+    /// `T(n) = HMAC(PRK, T(n - 1) || info || n)`.
+    HkdfExpand,
+    /// `U_1 = HMAC(P, S || INT(i))`.
+    Pbkdf2First,
+    /// `U_j = HMAC(P, U_{j-1})`.
+    Pbkdf2Next,
+}
+
+pub struct KdfHmacSha256<'a, H: digest::Digest<'a, DIGEST_LEN> + digest::HmacSha256> {
+    hmac: &'a H,
+    client: OptionalCell<&'a dyn Client>,
+
+    /// The HMAC in progress.
+    step: OptionalCell<Step>,
+
+    /// The key for the next HMAC: the HKDF salt or PRK, or the PBKDF2
+    /// password.
+    key: MapCell<[u8; MAX_KEY_LEN]>,
+    key_len: Cell<usize>,
+
+    /// Holds the previous HMAC output, followed by the HKDF info or the
+    /// PBKDF2 salt, and then the block counter. For HKDF the input key
+    /// material comes after the counter.
+    buffer: TakeCell<'static, [u8]>,
+    /// Length of the info or salt in `buffer`.
+    context_len: Cell<usize>,
+    /// Length of the HKDF input key material in `buffer`.
+    ikm_len: Cell<usize>,
+
+    /// The current output block, counting from 1.
+    block: Cell<u32>,
+    /// PBKDF2 iterations done for the current block, and the total.
+    iteration: Cell<u32>,
+    iterations: Cell<u32>,
+    /// The XOR of the PBKDF2 iterations for the current block.
+    block_output: Cell<[u8; DIGEST_LEN]>,
+
+    output: MapCell<SubSliceMut<'static, u8>>,
+    /// Bytes of `output` written so far.
+    output_len: Cell<usize>,
+    digest: TakeCell<'static, [u8; DIGEST_LEN]>,
+}
+
+impl<'a, H: digest::Digest<'a, DIGEST_LEN> + digest::HmacSha256> KdfHmacSha256<'a, H> {
+    pub fn new(
+        hmac: &'a H,
+        buffer: &'static mut [u8],
+        digest: &'static mut [u8; DIGEST_LEN],
+    ) -> Self {
+        KdfHmacSha256 {
+            hmac,
+            client: OptionalCell::empty(),
+            step: OptionalCell::empty(),
+            key: MapCell::new([0; MAX_KEY_LEN]),
+            key_len: Cell::new(0),
+            buffer: TakeCell::new(buffer),
+            context_len: Cell::new(0),
+            ikm_len: Cell::new(0),
+            block: Cell::new(0),
+            iteration: Cell::new(0),
+            iterations: Cell::new(0),
+            block_output: Cell::new([0; DIGEST_LEN]),
+            output: MapCell::empty(),
+            output_len: Cell::new(0),
+            digest: TakeCell::new(digest),
+        }
+    }
+
+    fn set_key(&self, key: &[u8]) {
+        self.key.map(|k| {
+            k.fill(0);
+            k[..key.len()].copy_from_slice(key);
+        });
+        self.key_len.set(key.len());
+    }
+
+    /// Start an HMAC with the current key over `buffer[range]`.
+    fn start_step(&self, step: Step) -> Result<(), ErrorCode> {
+        let context_end = DIGEST_LEN + self.context_len.get();
+        let range = match step {
+            Step::HkdfExtract => {
+                let start = context_end + 1;
+                start..start + self.ikm_len.get()
+            }
+            // The first block doesn't include a previous output.
+            Step::HkdfExpand if self.block.get() == 1 => DIGEST_LEN..context_end + 1,
+            Step::HkdfExpand => 0..context_end + 1,
+            Step::Pbkdf2First => DIGEST_LEN..context_end + 4,
+            Step::Pbkdf2Next => 0..DIGEST_LEN,
+        };
+
+        self.hmac.clear_data();
+        self.key.map_or(Err(ErrorCode::FAIL), |key| {
+            self.hmac.set_mode_hmacsha256(&key[..self.key_len.get()])
+        })?;
+
+        let buffer = self.buffer.take().ok_or(ErrorCode::BUSY)?;
+        let block = self.block.get();
+        match step {
+            Step::HkdfExpand => buffer[context_end] = block as u8,
+            Step::Pbkdf2First => {
+                buffer[context_end..context_end + 4].copy_from_slice(&block.to_be_bytes())
+            }
+            _ => {}
+        }
+
+        let mut data = SubSliceMut::new(buffer);
+        data.slice(range);
+        self.step.set(step);
+        self.hmac.add_mut_data(data).map_err(|(e, data)| {
+            self.step.clear();
+            self.buffer.replace(data.take());
+            e
+        })
+    }
+
+    /// Append the end of a block to the output. Returns `true` if the output
+    /// is full.
+    fn write_output(&self, block: &[u8; DIGEST_LEN]) -> bool {
+        self.output.map_or(true, |output| {
+            let start = self.output_len.get();
+            let len = core::cmp::min(DIGEST_LEN, output.len() - start);
+            output.as_slice()[start..start + len].copy_from_slice(&block[..len]);
+            self.output_len.set(start + len);
+            start + len == output.len()
+        })
+    }
+
+    /// Handle the result of an HMAC, and start the next one. Returns
+    /// `Ok(true)` once the derivation is complete.
+    fn step_done(&self, step: Step, result: &[u8; DIGEST_LEN]) -> Result<bool, ErrorCode> {
+        match step {
+            Step::HkdfExtract => {
+                // The PRK is the key for the expand step.
+                self.set_key(result);
+                self.block.set(1);
+                self.start_step(Step::HkdfExpand)?;
+            }
+            Step::HkdfExpand => {
+                if self.write_output(result) {
+                    return Ok(true);
+                }
+                self.buffer
+                    .map(|buffer| buffer[..DIGEST_LEN].copy_from_slice(result));
+                self.block.set(self.block.get() + 1);
+                self.start_step(Step::HkdfExpand)?;
+            }
+            Step::Pbkdf2First | Step::Pbkdf2Next => {
+                let mut block_output = if step == Step::Pbkdf2First {
+                    [0; DIGEST_LEN]
+                } else {
+                    self.block_output.get()
+                };
+                for (out, byte) in block_output.iter_mut().zip(result.iter()) {
+                    *out ^= byte;
+                }
+                self.block_output.set(block_output);
+                self.iteration.set(self.iteration.get() + 1);
+
+                if self.iteration.get() < self.iterations.get() {
+                    self.buffer
+                        .map(|buffer| buffer[..DIGEST_LEN].copy_from_slice(result));
+                    self.start_step(Step::Pbkdf2Next)?;
+                } else {
+                    if self.write_output(&block_output) {
+                        return Ok(true);
+                    }
+                    self.block.set(self.block.get() + 1);
+                    self.iteration.set(0);
+                    self.start_step(Step::Pbkdf2First)?;
+                }
+            }
+        }
+        Ok(false)
+    }
+
+    /// Erase the inputs and intermediate values, and return the output to the
+    /// client.
+    fn finish(&self, result: Result<(), ErrorCode>) {
+        self.step.clear();
+        self.key.map(|key| key.fill(0));
+        self.key_len.set(0);
+        self.buffer.map(|buffer| buffer.fill(0));
+        self.block_output.set([0; DIGEST_LEN]);
+
+        if let Some(mut output) = self.output.take() {
+            if result.is_err() {
+                output.as_slice().fill(0);
+            }
+            self.client
+                .map(move |client| client.derivation_done(result, output));
+        }
+    }
+
+    /// Take the buffer for a new derivation, checking it can hold `len` bytes.
+    fn start(
+        &self,
+        len: usize,
+        output: &SubSliceMut<'static, u8>,
+    ) -> Result<&'static mut [u8], ErrorCode> {
+        if self.output.is_some() {
+            return Err(ErrorCode::BUSY);
+        }
+        if output.len() == 0 {
+            return Err(ErrorCode::SIZE);
+        }
+        match self.buffer.take() {
+            Some(buffer) if buffer.len() >= len => Ok(buffer),
+            Some(buffer) => {
+                self.buffer.replace(buffer);
+                Err(ErrorCode::SIZE)
+            }
+            None => Err(ErrorCode::BUSY),
+        }
+    }
+
+    /// Start the first HMAC of a derivation.
+    fn run(
+        &self,
+        step: Step,
+        output: SubSliceMut<'static, u8>,
+    ) -> Result<(), (ErrorCode, SubSliceMut<'static, u8>)> {
+        self.output_len.set(0);
+        self.output.replace(output);
+        self.start_step(step).map_err(|e| {
+            self.key.map(|key| key.fill(0));
+            self.buffer.map(|buffer| buffer.fill(0));
+            (e, self.output.take().unwrap())
+        })
+    }
+}
+
+impl<'a, H: digest::Digest<'a, DIGEST_LEN> + digest::HmacSha256> Kdf<'a> for KdfHmacSha256<'a, H> {
+    fn set_client(&self, client: &'a dyn Client) {
+        self.client.set(client);
+    }
+
+    fn hkdf(
+        &self,
+        ikm: &[u8],
+        salt: &[u8],
+        info: &[u8],
+        output: SubSliceMut<'static, u8>,
+    ) -> Result<(), (ErrorCode, SubSliceMut<'static, u8>)> {
+        if salt.len() > MAX_KEY_LEN || output.len() > HKDF_MAX_OUTPUT_LEN {
+            return Err((ErrorCode::SIZE, output));
+        }
+        let buffer = match self.start(DIGEST_LEN + info.len() + 1 + ikm.len(), &output) {
+            Ok(buffer) => buffer,
+            Err(e) => return Err((e, output)),
+        };
+
+        let ikm_start = DIGEST_LEN + info.len() + 1;
+        buffer[DIGEST_LEN..DIGEST_LEN + info.len()].copy_from_slice(info);
+        buffer[ikm_start..ikm_start + ikm.len()].copy_from_slice(ikm);
+        self.buffer.replace(buffer);
+        self.context_len.set(info.len());
+        self.ikm_len.set(ikm.len());
+        // An empty salt is the same HMAC key as a salt of zeros.
+        self.set_key(salt);
+        self.block.set(0);
+
+        self.run(Step::HkdfExtract, output)
+    }
+
+    fn pbkdf2(
+        &self,
+        password: &[u8],
+        salt: &[u8],
+        iterations: u32,
+        output: SubSliceMut<'static, u8>,
+    ) -> Result<(), (ErrorCode, SubSliceMut<'static, u8>)> {
+        if iterations == 0 {
+            return Err((ErrorCode::INVAL, output));
+        }
+        if password.len() > MAX_KEY_LEN {
+            return Err((ErrorCode::SIZE, output));
+        }
+        let buffer = match self.start(DIGEST_LEN + salt.len() + 4, &output) {
+            Ok(buffer) => buffer,
+            Err(e) => return Err((e, output)),
+        };
+
+        buffer[DIGEST_LEN..DIGEST_LEN + salt.len()].copy_from_slice(salt);
+        self.buffer.replace(buffer);
+        self.context_len.set(salt.len());
+        self.set_key(password);
+        self.block.set(1);
+        self.iteration.set(0);
+        self.iterations.set(iterations);
+
+        self.run(Step::Pbkdf2First, output)
+    }
+}
+
+impl<'a, H: digest::Digest<'a, DIGEST_LEN> + digest::HmacSha256> digest::ClientData<DIGEST_LEN>
+    for KdfHmacSha256<'a, H>
+{
+    fn add_data_done(&self, _result: Result<(), ErrorCode>, _data: SubSlice<'static, u8>) {}
+
+    fn add_mut_data_done(&self, result: Result<(), ErrorCode>, data: SubSliceMut<'static, u8>) {
+        self.buffer.replace(data.take());
+
+        let result = result.and_then(|()| {
+            let digest = self.digest.take().ok_or(ErrorCode::FAIL)?;
+            self.hmac.run(digest).map_err(|(e, digest)| {
+                self.digest.replace(digest);
+                e
+            })
+        });
+        if let Err(e) = result {
+            self.finish(Err(e));
+        }
+    }
+}
+
+impl<'a, H: digest::Digest<'a, DIGEST_LEN> + digest::HmacSha256> digest::ClientHash<DIGEST_LEN>
+    for KdfHmacSha256<'a, H>
+{
+    fn hash_done(&self, result: Result<(), ErrorCode>, digest: &'static mut [u8; DIGEST_LEN]) {
+        let output = *digest;
+        digest.fill(0);
+        self.digest.replace(digest);
+
+        let result = result.and_then(|()| match self.step.take() {
+            Some(step) => self.step_done(step, &output),
+            None => Err(ErrorCode::FAIL),
+        });
+        match result {
+            Ok(false) => {}
+            Ok(true) => self.finish(Ok(())),
+            Err(e) => self.finish(Err(e)),
+        }
+    }
+}
+
+impl<'a, H: digest::Digest<'a, DIGEST_LEN> + digest::HmacSha256> digest::ClientVerify<DIGEST_LEN>
+    for KdfHmacSha256<'a, H>
+{
+    fn verification_done(
+        &self,
+        _result: Result<bool, ErrorCode>,
+        _compare: &'static mut [u8; DIGEST_LEN],
+    ) {
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::hmac_sha256_fake::{sha256, FakeHmacSha256};
+    use std::boxed::Box;
+    use std::vec::Vec;
+
+    /// Keeps the result of the last derivation.
+    struct TestClient {
+        result: OptionalCell<Result<Vec<u8>, ErrorCode>>,
+    }
+
+    impl Client for TestClient {
+        fn derivation_done(
+            &self,
+            result: Result<(), ErrorCode>,
+            mut output: SubSliceMut<'static, u8>,
+        ) {
+            self.result.set(result.map(|()| output.as_slice().to_vec()));
+        }
+    }
+
+    type TestKdf = KdfHmacSha256<'static, FakeHmacSha256>;
+
+    fn setup(
+        buffer_len: usize,
+    ) -> (
+        &'static TestKdf,
+        &'static FakeHmacSha256,
+        &'static TestClient,
+    ) {
+        let hmac = Box::leak(Box::new(FakeHmacSha256::new()));
+        let kdf = Box::leak(Box::new(KdfHmacSha256::new(
+            &*hmac,
+            Box::leak(std::vec![0; buffer_len].into_boxed_slice()),
+            Box::leak(Box::new([0; DIGEST_LEN])),
+        )));
+        let client = Box::leak(Box::new(TestClient {
+            result: OptionalCell::empty(),
+        }));
+        digest::Digest::set_client(hmac, kdf);
+        kdf.set_client(client);
+        (kdf, hmac, client)
+    }
+
+    fn output(len: usize) -> SubSliceMut<'static, u8> {
+        SubSliceMut::new(Box::leak(std::vec![0; len].into_boxed_slice()))
+    }
+
+    fn hkdf(ikm: &[u8], salt: &[u8], info: &[u8], len: usize) -> Result<Vec<u8>, ErrorCode> {
+        let (kdf, hmac, client) = setup(256);
+        kdf.hkdf(ikm, salt, info, output(len)).map_err(|(e, _)| e)?;
+        hmac.complete_all();
+        client.result.take().unwrap()
+    }
+
+    fn pbkdf2(
+        password: &[u8],
+        salt: &[u8],
+        iterations: u32,
+        len: usize,
+    ) -> Result<Vec<u8>, ErrorCode> {
+        let (kdf, hmac, client) = setup(256);
+        kdf.pbkdf2(password, salt, iterations, output(len))
+            .map_err(|(e, _)| e)?;
+        hmac.complete_all();
+        client.result.take().unwrap()
+    }
+
+    #[test]
+    fn hkdf_basic() {
+        // RFC 5869 A.1.
+        let salt: [u8; 13] = core::array::from_fn(|i| i as u8);
+        let info: [u8; 10] = core::array::from_fn(|i| 0xf0 + i as u8);
+        assert_eq!(
+            hkdf(&[0x0b; 22], &salt, &info, 42),
+            Ok(std::vec![
+                0x3c, 0xb2, 0x5f, 0x25, 0xfa, 0xac, 0xd5, 0x7a, 0x90, 0x43, 0x4f, 0x64, 0xd0, 0x36,
+                0x2f, 0x2a, 0x2d, 0x2d, 0x0a, 0x90, 0xcf, 0x1a, 0x5a, 0x4c, 0x5d, 0xb0, 0x2d, 0x56,
+                0xec, 0xc4, 0xc5, 0xbf, 0x34, 0x00, 0x72, 0x08, 0xd5, 0xb8, 0x87, 0x18, 0x58, 0x65
+            ])
+        );
+    }
+
+    #[test]
+    fn hkdf_long_inputs() {
+        // RFC 5869 A.2. The 80 byte salt is longer than an HMAC-SHA256 block,
+        // so it has to be hashed first.
+        let ikm: [u8; 80] = core::array::from_fn(|i| i as u8);
+        let salt: [u8; 80] = core::array::from_fn(|i| 0x60 + i as u8);
+        let info: [u8; 80] = core::array::from_fn(|i| 0xb0 + i as u8);
+        assert_eq!(hkdf(&ikm, &salt, &info, 82), Err(ErrorCode::SIZE));
+        assert_eq!(
+            hkdf(&ikm, &sha256(&salt), &info, 82),
+            Ok(std::vec![
+                0xb1, 0x1e, 0x39, 0x8d, 0xc8, 0x03, 0x27, 0xa1, 0xc8, 0xe7, 0xf7, 0x8c, 0x59, 0x6a,
+                0x49, 0x34, 0x4f, 0x01, 0x2e, 0xda, 0x2d, 0x4e, 0xfa, 0xd8, 0xa0, 0x50, 0xcc, 0x4c,
+                0x19, 0xaf, 0xa9, 0x7c, 0x59, 0x04, 0x5a, 0x99, 0xca, 0xc7, 0x82, 0x72, 0x71, 0xcb,
+                0x41, 0xc6, 0x5e, 0x59, 0x0e, 0x09, 0xda, 0x32, 0x75, 0x60, 0x0c, 0x2f, 0x09, 0xb8,
+                0x36, 0x77, 0x93, 0xa9, 0xac, 0xa3, 0xdb, 0x71, 0xcc, 0x30, 0xc5, 0x81, 0x79, 0xec,
+                0x3e, 0x87, 0xc1, 0x4c, 0x01, 0xd5, 0xc1, 0xf3, 0x43, 0x4f, 0x1d, 0x87
+            ])
+        );
+    }
+
+    #[test]
+    fn hkdf_empty_salt_and_info() {
+        // RFC 5869 A.3.
+        assert_eq!(
+            hkdf(&[0x0b; 22], &[], &[], 42),
+            Ok(std::vec![
+                0x8d, 0xa4, 0xe7, 0x75, 0xa5, 0x63, 0xc1, 0x8f, 0x71, 0x5f, 0x80, 0x2a, 0x06, 0x3c,
+                0x5a, 0x31, 0xb8, 0xa1, 0x1f, 0x5c, 0x5e, 0xe1, 0x87, 0x9e, 0xc3, 0x45, 0x4e, 0x5f,
+                0x3c, 0x73, 0x8d, 0x2d, 0x9d, 0x20, 0x13, 0x95, 0xfa, 0xa4, 0xb6, 0x1a, 0x96, 0xc8
+            ])
+        );
+    }
+
+    #[test]
+    fn pbkdf2_one_iteration() {
+        // RFC 7914 section 11, which takes two blocks of output.
+        assert_eq!(
+            pbkdf2(b"passwd", b"salt", 1, 64),
+            Ok(std::vec![
+                0x55, 0xac, 0x04, 0x6e, 0x56, 0xe3, 0x08, 0x9f, 0xec, 0x16, 0x91, 0xc2, 0x25, 0x44,
+                0xb6, 0x05, 0xf9, 0x41, 0x85, 0x21, 0x6d, 0xde, 0x04, 0x65, 0xe6, 0x8b, 0x9d, 0x57,
+                0xc2, 0x0d, 0xac, 0xbc, 0x49, 0xca, 0x9c, 0xcc, 0xf1, 0x79, 0xb6, 0x45, 0x99, 0x16,
+                0x64, 0xb3, 0x9d, 0x77, 0xef, 0x31, 0x7c, 0x71, 0xb8, 0x45, 0xb1, 0xe3, 0x0b, 0xd5,
+                0x09, 0x11, 0x20, 0x41, 0xd3, 0xa1, 0x97, 0x83
+            ])
+        );
+    }
+
+    #[test]
+    fn pbkdf2_many_iterations() {
+        // The RFC 6070 inputs with HMAC-SHA256, with a partial last block.
+        assert_eq!(
+            pbkdf2(
+                b"passwordPASSWORDpassword",
+                b"saltSALTsaltSALTsaltSALTsaltSALTsalt",
+                4096,
+                40
+            ),
+            Ok(std::vec![
+                0x34, 0x8c, 0x89, 0xdb, 0xcb, 0xd3, 0x2b, 0x2f, 0x32, 0xd8, 0x14, 0xb8, 0x11, 0x6e,
+                0x84, 0xcf, 0x2b, 0x17, 0x34, 0x7e, 0xbc, 0x18, 0x00, 0x18, 0x1c, 0x4e, 0x2a, 0x1f,
+                0xb8, 0xdd, 0x53, 0xe1, 0xc6, 0x35, 0x51, 0x8c, 0x7d, 0xac, 0x47, 0xe9
+            ])
+        );
+    }
+
+    #[test]
+    fn invalid_arguments() {
+        let (kdf, hmac, _) = setup(64);
+        assert_eq!(
+            kdf.pbkdf2(b"passwd", b"salt", 0, output(32))
+                .map_err(|(e, _)| e),
+            Err(ErrorCode::INVAL)
+        );
+        assert_eq!(
+            kdf.pbkdf2(&[0; MAX_KEY_LEN + 1], b"salt", 1, output(32))
+                .map_err(|(e, _)| e),
+            Err(ErrorCode::SIZE)
+        );
+        assert_eq!(
+            kdf.hkdf(b"ikm", b"salt", b"info", output(0))
+                .map_err(|(e, _)| e),
+            Err(ErrorCode::SIZE)
+        );
+        assert_eq!(
+            kdf.hkdf(b"ikm", b"salt", b"info", output(HKDF_MAX_OUTPUT_LEN + 1))
+                .map_err(|(e, _)| e),
+            Err(ErrorCode::SIZE)
+        );
+        // The inputs don't fit in the buffer.
+        assert_eq!(
+            kdf.hkdf(&[0; 32], b"salt", b"info", output(32))
+                .map_err(|(e, _)| e),
+            Err(ErrorCode::SIZE)
+        );
+        assert_eq!(hmac.hmacs.get(), 0);
+
+        // None of the failures left the KDF busy.
+        assert!(kdf.hkdf(b"ikm", b"salt", b"info", output(32)).is_ok());
+        assert_eq!(
+            kdf.hkdf(b"ikm", b"salt", b"info", output(32))
+                .map_err(|(e, _)| e),
+            Err(ErrorCode::BUSY)
+        );
+    }
+}
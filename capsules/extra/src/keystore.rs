@@ -0,0 +1,1364 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Kernel keystore with opaque key handles.
+//!
+//! Keys are held in kernel memory and apps refer to them with a `KeyHandle`.
+//! The crypto drivers (AES and HMAC) accept a handle in place of a key buffer
+//! and get the key through the `hil::keystore::KeyStore` trait, so the key
+//! bytes never enter the app that uses them.
+//!
+//! Keys come from three places:
+//!
+//! - The board provisions keys with `provision()` and grants apps use of them
+//!   by `ShortId` with a list of `KeyPermission`s.
+//! - Apps import keys, which can only be used by apps with the same
+//!   `ShortId`. Imported keys can be kept in a KV store, they are then loaded
+//!   back into the same slot by `restore()` so their handles survive a reboot.
+//! - Apps derive keys with the `hil::keystore::KeyDerivation` hardware, for
+//!   example the OpenTitan key manager. The app's `ShortId` is part of the
+//!   derivation, so apps can't derive each other's keys.
+//!
+//! Other capsules can also add keys for an app with `KeyStore::import()`, for
+//! example to keep the output of a key derivation function in the kernel.
+//!
+//! Keys imported by or derived for an app require the app to have a fixed
+//! `ShortId`. Each app can hold at most `max_app_slots` keys, and keys that
+//! aren't kept in the KV store are freed once the process that added them has
+//! exited.
+//!
+//! Stored Keys
+//! -----------
+//!
+//! Keys kept in the KV store are encrypted with ChaCha20-Poly1305 under a
+//! wrapping key derived from the device secret with `KeyDerivation`, so
+//! storing keys requires key derivation. Each record has a random nonce, and
+//! the usage and owner of the key are authenticated. Records that don't
+//! decrypt are treated as corrupt and skipped by `restore()`.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! let keystore = components::keystore::KeyStoreComponent::new(
+//!     board_kernel,
+//!     capsules_extra::keystore::DRIVER_NUM,
+//!     &KEY_PERMISSIONS,
+//!     4,
+//! )
+//! .finalize(components::keystore_component_static!(8));
+//! keystore.set_key_derivation(&peripherals.keymgr);
+//! keystore.set_kv_store(
+//!     virtual_kv_keystore,
+//!     StoragePermissions::new_kernel(&storage_cap),
+//!     chacha,
+//!     keystore_rng,
+//! );
+//! let _ = keystore.restore();
+//!
+//! aes.set_keystore(keystore);
+//! hmac.set_keystore(keystore);
+//! ```
+
+use core::cell::Cell;
+use core::num::NonZeroU32;
+
+use capsules_core::driver;
+use kernel::errorcode::into_statuscode;
+use kernel::grant::{AllowRoCount, AllowRwCount, Grant, UpcallCount};
+use kernel::hil::keystore::{
+    KeyDerivation, KeyDerivationClient, KeyHandle, KeyStore, KeyUsage, DERIVED_KEY_LEN,
+};
+use kernel::hil::kv;
+use kernel::hil::rng;
+use kernel::hil::symmetric_encryption::{
+    ChaCha20Poly1305, ChaCha20Poly1305Client, CHACHA20_POLY1305_NONCE_SIZE,
+    CHACHA20_POLY1305_TAG_SIZE,
+};
+use kernel::process::ShortId;
+use kernel::processbuffer::ReadableProcessBuffer;
+use kernel::storage_permissions::StoragePermissions;
+use kernel::syscall::{CommandReturn, SyscallDriver};
+use kernel::utilities::cells::{MapCell, OptionalCell, TakeCell};
+use kernel::utilities::leasable_buffer::SubSliceMut;
+use kernel::{ErrorCode, ProcessId};
+
+use crate::kv_store_permissions::HEADER_LENGTH;
+
+/// Syscall driver number.
+pub const DRIVER_NUM: usize = driver::NUM::Keystore as usize;
+
+/// The longest key the keystore can hold.
+pub const MAX_KEY_LEN: usize = 64;
+
+/// The longest salt an app can pass when deriving a key.
+pub const MAX_SALT_LEN: usize = 28;
+
+/// KV keys for stored keys are this prefix followed by the slot index as a
+/// little-endian `u32`.
+const KV_KEY_PREFIX: &[u8] = b"keystore-";
+/// The length of a KV key for a stored key.
+pub const KV_KEY_LEN: usize = KV_KEY_PREFIX.len() + 4;
+
+/// The key header holds the usage, the owner and the key length.
+const STORED_HEADER_LEN: usize = 6;
+
+/// The first byte of an encrypted record.
+const RECORD_VERSION: u8 = 0x80;
+const NONCE_OFFSET: usize = 1;
+const STORED_HEADER_OFFSET: usize = NONCE_OFFSET + CHACHA20_POLY1305_NONCE_SIZE;
+const KEY_OFFSET: usize = STORED_HEADER_OFFSET + STORED_HEADER_LEN;
+/// The length of an encrypted record, not including the key.
+const RECORD_OVERHEAD: usize = KEY_OFFSET + CHACHA20_POLY1305_TAG_SIZE;
+
+/// The length of the buffer for a stored key, including the KV header.
+pub const KV_VALUE_LEN: usize = HEADER_LENGTH + RECORD_OVERHEAD + MAX_KEY_LEN;
+
+/// The version used to derive the wrapping key for stored keys.
+const WRAP_KEY_VERSION: u32 = 0;
+/// Appended to four zero bytes to form the salt for the wrapping key. Apps'
+/// salts start with their non-zero `ShortId`, so apps can't derive it.
+const WRAP_KEY_LABEL: &[u8] = b"tock-keystore-wrap";
+
+/// Ids for read-only allow buffers
+mod ro_allow {
+    pub const KEY: usize = 0;
+    pub const SALT: usize = 1;
+    /// The number of allow buffers the kernel stores for this grant
+    pub const COUNT: u8 = 2;
+}
+
+mod upcall {
+    pub const STORE_DONE: usize = 0;
+    pub const DERIVE_DONE: usize = 1;
+    /// The number of upcalls the kernel stores for this grant
+    pub const COUNT: u8 = 2;
+}
+
+/// Write a record holding `key`, with the usage `usage` and owner `owner`,
+/// into `record`, after the nonce. The key is encrypted and the tag added by
+/// the AEAD, with everything before `KEY_OFFSET` as additional data.
+fn write_record(record: &mut [u8], usage: u8, owner: u32, key: &[u8]) {
+    record[0] = RECORD_VERSION;
+    let header = &mut record[STORED_HEADER_OFFSET..KEY_OFFSET];
+    header[0] = usage;
+    header[1..5].copy_from_slice(&owner.to_le_bytes());
+    header[5] = key.len() as u8;
+    record[KEY_OFFSET..KEY_OFFSET + key.len()].copy_from_slice(key);
+}
+
+/// The length of the key in the encrypted `record`, or `None` if it isn't a
+/// valid record.
+fn record_key_len(record: &[u8]) -> Option<usize> {
+    let key_len = record.len().checked_sub(RECORD_OVERHEAD)?;
+    if record[0] != RECORD_VERSION || key_len != record[KEY_OFFSET - 1] as usize {
+        return None;
+    }
+    Some(key_len)
+}
+
+/// The usage, owner and key in the decrypted `record`.
+fn read_record(record: &[u8]) -> Option<(KeyUsage, ShortId, &[u8])> {
+    let key_len = record_key_len(record)?;
+    let header = &record[STORED_HEADER_OFFSET..KEY_OFFSET];
+    let usage = KeyUsage::from_usize(header[0] as usize)?;
+    let owner = u32::from_le_bytes([header[1], header[2], header[3], header[4]]);
+    let owner = ShortId::Fixed(NonZeroU32::new(owner)?);
+    if key_len == 0 || key_len > MAX_KEY_LEN {
+        return None;
+    }
+    Some((usage, owner, &record[KEY_OFFSET..KEY_OFFSET + key_len]))
+}
+
+/// Free the keys in `slots` added by processes for which `running` returns
+/// `false`, unless they are kept in the KV store or are being stored in slot
+/// `storing`.
+fn reclaim_slots(slots: &[KeySlot], storing: Option<usize>, running: impl Fn(usize) -> bool) {
+    for (index, slot) in slots.iter().enumerate() {
+        if let Some(id) = slot.process.get() {
+            if !slot.stored.get() && storing != Some(index) && !running(id) {
+                slot.clear();
+            }
+        }
+    }
+}
+
+/// Find a free slot in `slots` for a key for `app_id`, which can hold at
+/// most `max_app_slots` keys. `deriving` is the app and slot of the key
+/// derivation in progress, if any.
+///
+/// Returns `NOMEM` if there are no free slots or the app already holds
+/// `max_app_slots` keys.
+fn find_free_slot(
+    slots: &[KeySlot],
+    max_app_slots: usize,
+    app_id: ShortId,
+    deriving: Option<(ShortId, KeyHandle)>,
+) -> Result<KeyHandle, ErrorCode> {
+    let held = slots
+        .iter()
+        .filter(|slot| !slot.is_empty() && slot.owner.get() == Some(app_id))
+        .count();
+    let derive_pending = deriving.map_or(false, |(deriving_app, _)| deriving_app == app_id);
+    if held + derive_pending as usize >= max_app_slots {
+        return Err(ErrorCode::NOMEM);
+    }
+
+    (0..slots.len())
+        .map(|i| KeyHandle::new(i as u32))
+        .find(|handle| {
+            slots[handle.id() as usize].is_empty()
+                && deriving.map_or(true, |(_, pending)| pending != *handle)
+        })
+        .ok_or(ErrorCode::NOMEM)
+}
+
+/// Storage for one key.
+pub struct KeySlot {
+    key: MapCell<[u8; MAX_KEY_LEN]>,
+    len: Cell<usize>,
+    /// The usage of the key, or `None` if the slot is empty.
+    usage: Cell<Option<KeyUsage>>,
+    /// The app the key belongs to, or `None` for keys provisioned by the
+    /// board.
+    owner: Cell<Option<ShortId>>,
+    /// The identifier of the process that added the key, which is freed when
+    /// the process exits unless the key is kept in the KV store.
+    process: OptionalCell<usize>,
+    /// Whether the key is kept in the KV store.
+    stored: Cell<bool>,
+}
+
+impl KeySlot {
+    pub const fn new() -> Self {
+        KeySlot {
+            key: MapCell::new([0; MAX_KEY_LEN]),
+            len: Cell::new(0),
+            usage: Cell::new(None),
+            owner: Cell::new(None),
+            process: OptionalCell::empty(),
+            stored: Cell::new(false),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.usage.get().is_none()
+    }
+
+    fn fill(
+        &self,
+        key: &[u8],
+        usage: KeyUsage,
+        owner: Option<ShortId>,
+        process: Option<ProcessId>,
+    ) {
+        self.fill_with(key.len(), usage, owner, process, |buf| {
+            buf.copy_from_slice(key)
+        });
+    }
+
+    /// Fill the slot with a `len` byte key written by `f`, without copying
+    /// the key anywhere else.
+    fn fill_with(
+        &self,
+        len: usize,
+        usage: KeyUsage,
+        owner: Option<ShortId>,
+        process: Option<ProcessId>,
+        f: impl FnOnce(&mut [u8]),
+    ) {
+        self.key.map(|buf| {
+            buf.fill(0);
+            f(&mut buf[..len]);
+        });
+        self.len.set(len);
+        self.usage.set(Some(usage));
+        self.owner.set(owner);
+        self.process.insert(process.map(|processid| processid.id()));
+        self.stored.set(false);
+    }
+
+    /// Empty the slot, erasing the key.
+    fn clear(&self) {
+        self.key.map(|buf| buf.fill(0));
+        self.len.set(0);
+        self.usage.set(None);
+        self.owner.set(None);
+        self.process.clear();
+        self.stored.set(false);
+    }
+}
+
+impl Default for KeySlot {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Allows the app with `app_id` to use the board provisioned key `handle`.
+pub struct KeyPermission {
+    app_id: ShortId,
+    handle: KeyHandle,
+}
+
+impl KeyPermission {
+    pub const fn new(app_id: ShortId, handle: KeyHandle) -> Self {
+        KeyPermission { app_id, handle }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum KvOperation {
+    /// Loading stored keys, starting at this slot.
+    Restore(usize),
+    /// Storing a key imported by an app.
+    Store(ProcessId, KeyHandle),
+    /// Deleting a stored key.
+    Delete(ProcessId, KeyHandle),
+}
+
+#[derive(Default)]
+pub struct App {}
+
+pub struct KernelKeyStore<'a> {
+    slots: &'a [KeySlot],
+    permissions: &'a [KeyPermission],
+    /// The number of keys each app can hold.
+    max_app_slots: usize,
+
+    apps: Grant<
+        App,
+        UpcallCount<{ upcall::COUNT }>,
+        AllowRoCount<{ ro_allow::COUNT }>,
+        AllowRwCount<0>,
+    >,
+
+    kv: OptionalCell<&'a dyn kv::KVPermissions<'a>>,
+    storage_permissions: OptionalCell<StoragePermissions>,
+    kv_operation: OptionalCell<KvOperation>,
+    kv_key: TakeCell<'static, [u8]>,
+    kv_value: TakeCell<'static, [u8]>,
+
+    aead: OptionalCell<&'a dyn ChaCha20Poly1305<'a>>,
+    rng: OptionalCell<&'a dyn rng::Rng<'a>>,
+    /// The key stored keys are encrypted with, once it has been derived.
+    wrap_key: MapCell<[u8; DERIVED_KEY_LEN]>,
+    /// Whether the wrapping key is being derived.
+    wrap_key_pending: Cell<bool>,
+    /// The number of bytes of the nonce received from the RNG.
+    nonce_len: Cell<usize>,
+    /// The length of the record being encrypted or decrypted.
+    record_len: Cell<usize>,
+
+    derivation: OptionalCell<&'a dyn KeyDerivation<'a>>,
+    /// The app and slot of the derivation in progress.
+    derive_request: OptionalCell<(ProcessId, KeyHandle, KeyUsage)>,
+    derived_key: TakeCell<'static, [u8; DERIVED_KEY_LEN]>,
+}
+
+impl<'a> KernelKeyStore<'a> {
+    pub fn new(
+        slots: &'a [KeySlot],
+        permissions: &'a [KeyPermission],
+        max_app_slots: usize,
+        kv_key: &'static mut [u8],
+        kv_value: &'static mut [u8],
+        derived_key: &'static mut [u8; DERIVED_KEY_LEN],
+        grant: Grant<
+            App,
+            UpcallCount<{ upcall::COUNT }>,
+            AllowRoCount<{ ro_allow::COUNT }>,
+            AllowRwCount<0>,
+        >,
+    ) -> Self {
+        KernelKeyStore {
+            slots,
+            permissions,
+            max_app_slots,
+            apps: grant,
+            kv: OptionalCell::empty(),
+            storage_permissions: OptionalCell::empty(),
+            kv_operation: OptionalCell::empty(),
+            kv_key: TakeCell::new(kv_key),
+            kv_value: TakeCell::new(kv_value),
+            aead: OptionalCell::empty(),
+            rng: OptionalCell::empty(),
+            wrap_key: MapCell::empty(),
+            wrap_key_pending: Cell::new(false),
+            nonce_len: Cell::new(0),
+            record_len: Cell::new(0),
+            derivation: OptionalCell::empty(),
+            derive_request: OptionalCell::empty(),
+            derived_key: TakeCell::new(derived_key),
+        }
+    }
+
+    /// Keep imported keys in `kv` when apps ask for it. Keys are stored with
+    /// `permissions`, which should only allow the kernel to access them.
+    ///
+    /// Stored keys are encrypted with `aead`, using nonces from `rng`, under a
+    /// key from the `KeyDerivation` set with `set_key_derivation()`.
+    pub fn set_kv_store(
+        &'a self,
+        kv: &'a dyn kv::KVPermissions<'a>,
+        permissions: StoragePermissions,
+        aead: &'a dyn ChaCha20Poly1305<'a>,
+        rng: &'a dyn rng::Rng<'a>,
+    ) {
+        kv.set_client(self);
+        self.kv.set(kv);
+        self.storage_permissions.set(permissions);
+        aead.set_client(self);
+        self.aead.set(aead);
+        rng.set_client(self);
+        self.rng.set(rng);
+    }
+
+    /// Allow apps to derive keys with `derivation`.
+    pub fn set_key_derivation(&'a self, derivation: &'a dyn KeyDerivation<'a>) {
+        derivation.set_client(self);
+        self.derivation.set(derivation);
+    }
+
+    /// Put a key in slot `handle`. The key can be used by the apps listed in
+    /// the `KeyPermission`s for `handle`.
+    pub fn provision(
+        &self,
+        handle: KeyHandle,
+        usage: KeyUsage,
+        key: &[u8],
+    ) -> Result<(), ErrorCode> {
+        if key.is_empty() || key.len() > MAX_KEY_LEN {
+            return Err(ErrorCode::SIZE);
+        }
+        let slot = self
+            .slots
+            .get(handle.id() as usize)
+            .ok_or(ErrorCode::INVAL)?;
+        if !slot.is_empty() || self.derive_pending(handle) {
+            return Err(ErrorCode::BUSY);
+        }
+
+        slot.fill(key, usage, None, None);
+        Ok(())
+    }
+
+    /// Load the keys kept in the KV store into their slots.
+    ///
+    /// Stored keys are skipped if their slot is already in use.
+    pub fn restore(&self) -> Result<(), ErrorCode> {
+        if self.kv.is_none() {
+            return Err(ErrorCode::NODEVICE);
+        }
+        if self.kv_operation.is_some() {
+            return Err(ErrorCode::BUSY);
+        }
+
+        // Loading starts once the wrapping key has been derived.
+        self.kv_operation.set(KvOperation::Restore(0));
+        match self.wrap_key_ready() {
+            Ok(true) => self.restore_from(0),
+            Ok(false) => {}
+            Err(e) => {
+                self.kv_operation.clear();
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+
+    /// Start loading the first empty slot from `index` onwards.
+    fn restore_from(&self, index: usize) {
+        self.kv_operation.clear();
+
+        let next = (index..self.slots.len())
+            .find(|i| self.slots[*i].is_empty() && !self.derive_pending(KeyHandle::new(*i as u32)));
+        if let Some(index) = next {
+            self.kv_operation.set(KvOperation::Restore(index));
+            if self.start_kv_get(index).is_err() {
+                self.kv_operation.clear();
+            }
+        }
+    }
+
+    fn derive_pending(&self, handle: KeyHandle) -> bool {
+        self.derive_request
+            .map_or(false, |(_, pending, _)| pending == handle)
+    }
+
+    /// Free the keys added by processes that have exited, unless they are
+    /// kept in the KV store.
+    fn reclaim_slots(&self) {
+        let storing = match self.kv_operation.get() {
+            Some(KvOperation::Store(_, handle)) => Some(handle.id() as usize),
+            _ => None,
+        };
+        reclaim_slots(self.slots, storing, |id| {
+            self.apps.iter().any(|app| app.processid().id() == id)
+        });
+    }
+
+    /// Find a free slot for a key for `app_id`.
+    ///
+    /// Returns `NOMEM` if the keystore is full or the app already holds
+    /// `max_app_slots` keys.
+    fn find_free_slot(&self, app_id: ShortId) -> Result<KeyHandle, ErrorCode> {
+        self.reclaim_slots();
+
+        let deriving = self
+            .derive_request
+            .map(|(processid, handle, _)| (processid.short_app_id(), handle));
+        find_free_slot(self.slots, self.max_app_slots, app_id, deriving)
+    }
+
+    /// Start deriving the wrapping key for stored keys if it isn't known yet.
+    ///
+    /// Returns whether it is available. Otherwise the operation in
+    /// `kv_operation` is continued by `wrap_key_done()` once it has been
+    /// derived.
+    fn wrap_key_ready(&self) -> Result<bool, ErrorCode> {
+        if self.wrap_key.is_some() {
+            return Ok(true);
+        }
+        let derivation = self.derivation.get().ok_or(ErrorCode::NOSUPPORT)?;
+        if self.derive_request.is_some() || self.wrap_key_pending.get() {
+            return Err(ErrorCode::BUSY);
+        }
+
+        let mut salt = [0; 32];
+        salt[4..4 + WRAP_KEY_LABEL.len()].copy_from_slice(WRAP_KEY_LABEL);
+
+        let key = self.derived_key.take().ok_or(ErrorCode::BUSY)?;
+        match derivation.derive(&salt, WRAP_KEY_VERSION, key) {
+            Ok(()) => {
+                self.wrap_key_pending.set(true);
+                Ok(false)
+            }
+            Err((e, key)) => {
+                self.derived_key.replace(key);
+                Err(e)
+            }
+        }
+    }
+
+    /// Continue the KV operation that was waiting for the wrapping key.
+    fn wrap_key_done(&self, result: Result<(), ErrorCode>) {
+        match self.kv_operation.get() {
+            Some(KvOperation::Restore(index)) => {
+                if result.is_ok() {
+                    self.restore_from(index);
+                } else {
+                    self.kv_operation.clear();
+                }
+            }
+            Some(KvOperation::Store(_, _)) => {
+                if let Err(e) = result.and_then(|()| self.start_wrap()) {
+                    self.store_failed(e);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Start encrypting the key for the `Store` operation in progress by
+    /// getting a nonce.
+    fn start_wrap(&self) -> Result<(), ErrorCode> {
+        let rng = self.rng.get().ok_or(ErrorCode::NOSUPPORT)?;
+        self.nonce_len.set(0);
+        rng.get()
+    }
+
+    /// The slot being written to the KV store.
+    fn wrap_index(&self) -> Option<usize> {
+        match self.kv_operation.get() {
+            Some(KvOperation::Store(_, handle)) => Some(handle.id() as usize),
+            _ => None,
+        }
+    }
+
+    /// Encrypt the key being stored into `kv_value`, which already holds the
+    /// nonce.
+    fn start_encrypt(&self) -> Result<(), ErrorCode> {
+        let kv = self.kv.get().ok_or(ErrorCode::NODEVICE)?;
+        let aead = self.aead.get().ok_or(ErrorCode::NODEVICE)?;
+        let index = self.wrap_index().ok_or(ErrorCode::FAIL)?;
+        let slot = &self.slots[index];
+        let owner: u32 = match slot.owner.get() {
+            Some(ShortId::Fixed(id)) => id.into(),
+            _ => return Err(ErrorCode::NOSUPPORT),
+        };
+
+        let value = self.kv_value.take().ok_or(ErrorCode::BUSY)?;
+        let header_size = kv.header_size();
+        let key_len = slot.len.get();
+        let record_len = RECORD_OVERHEAD + key_len;
+        if header_size + record_len > value.len() {
+            self.kv_value.replace(value);
+            return Err(ErrorCode::SIZE);
+        }
+
+        let record = &mut value[header_size..header_size + record_len];
+        let usage = slot.usage.get().map_or(0, |usage| usage as u8);
+        slot.key
+            .map(|key| write_record(record, usage, owner, &key[..key_len]));
+
+        let ret = self
+            .wrap_key
+            .map_or(Err(ErrorCode::FAIL), |key| aead.set_key(key))
+            .and_then(|()| aead.set_nonce(&record[NONCE_OFFSET..STORED_HEADER_OFFSET]));
+        if let Err(e) = ret {
+            value.fill(0);
+            self.kv_value.replace(value);
+            return Err(e);
+        }
+
+        self.record_len.set(record_len);
+        aead.crypt(value, header_size, header_size + KEY_OFFSET, key_len, true)
+            .map_err(|(e, value)| {
+                value.fill(0);
+                self.kv_value.replace(value);
+                e
+            })
+    }
+
+    /// Decrypt the `record_len` byte record in `value` for slot `index`.
+    fn start_decrypt(
+        &self,
+        index: usize,
+        value: &'static mut [u8],
+        record_len: usize,
+    ) -> Result<(), (ErrorCode, &'static mut [u8])> {
+        let header_size = match self.kv.get() {
+            Some(kv) => kv.header_size(),
+            None => return Err((ErrorCode::NODEVICE, value)),
+        };
+        let aead = match self.aead.get() {
+            Some(aead) => aead,
+            None => return Err((ErrorCode::NODEVICE, value)),
+        };
+        let record = &value[header_size..header_size + record_len];
+        let key_len = match record_key_len(record) {
+            Some(key_len) => key_len,
+            None => return Err((ErrorCode::FAIL, value)),
+        };
+
+        let ret = self
+            .wrap_key
+            .map_or(Err(ErrorCode::FAIL), |key| aead.set_key(key))
+            .and_then(|()| aead.set_nonce(&record[NONCE_OFFSET..STORED_HEADER_OFFSET]));
+        if let Err(e) = ret {
+            return Err((e, value));
+        }
+
+        self.kv_operation.set(KvOperation::Restore(index));
+        self.record_len.set(record_len);
+        aead.crypt(value, header_size, header_size + KEY_OFFSET, key_len, false)
+    }
+
+    /// Fail the `Store` operation in progress.
+    fn store_failed(&self, e: ErrorCode) {
+        if let Some(KvOperation::Store(processid, handle)) = self.kv_operation.take() {
+            self.slots[handle.id() as usize].clear();
+            self.store_done(processid, handle, Err(e));
+        }
+    }
+
+    /// Get the slot for `handle` if it holds a key owned by `processid`.
+    fn owned_slot(&self, handle: usize, processid: ProcessId) -> Result<&KeySlot, ErrorCode> {
+        let slot = self.slots.get(handle).ok_or(ErrorCode::INVAL)?;
+        if slot.is_empty() {
+            return Err(ErrorCode::INVAL);
+        }
+        if slot.owner.get() != Some(processid.short_app_id()) {
+            return Err(ErrorCode::NOSUPPORT);
+        }
+        Ok(slot)
+    }
+
+    fn kv_key_for(&self, index: usize) -> Result<SubSliceMut<'static, u8>, ErrorCode> {
+        let key = self.kv_key.take().ok_or(ErrorCode::BUSY)?;
+        key[..KV_KEY_PREFIX.len()].copy_from_slice(KV_KEY_PREFIX);
+        key[KV_KEY_PREFIX.len()..KV_KEY_LEN].copy_from_slice(&(index as u32).to_le_bytes());
+
+        let mut key = SubSliceMut::new(key);
+        key.slice(..KV_KEY_LEN);
+        Ok(key)
+    }
+
+    fn start_kv_get(&self, index: usize) -> Result<(), ErrorCode> {
+        let kv = self.kv.get().ok_or(ErrorCode::NODEVICE)?;
+        let permissions = self.storage_permissions.get().ok_or(ErrorCode::NODEVICE)?;
+        let value = self.kv_value.take().ok_or(ErrorCode::BUSY)?;
+        let key = match self.kv_key_for(index) {
+            Ok(key) => key,
+            Err(e) => {
+                self.kv_value.replace(value);
+                return Err(e);
+            }
+        };
+
+        kv.get(key, SubSliceMut::new(value), permissions)
+            .map_err(|(key, value, e)| {
+                self.kv_key.replace(key.take());
+                self.kv_value.replace(value.take());
+                e
+            })
+    }
+
+    /// Write the encrypted record in `value` for the slot being stored.
+    fn start_kv_set(&self, value: &'static mut [u8]) -> Result<(), ErrorCode> {
+        let ret = match (
+            self.kv.get(),
+            self.storage_permissions.get(),
+            self.wrap_index(),
+        ) {
+            (Some(kv), Some(permissions), Some(index)) => {
+                let value_len = kv.header_size() + self.record_len.get();
+                match self.kv_key_for(index) {
+                    Ok(key) => {
+                        let mut value = SubSliceMut::new(value);
+                        value.slice(..value_len);
+                        kv.set(key, value, permissions).map_err(|(key, value, e)| {
+                            self.kv_key.replace(key.take());
+                            (value.take(), e)
+                        })
+                    }
+                    Err(e) => Err((value, e)),
+                }
+            }
+            _ => Err((value, ErrorCode::NODEVICE)),
+        };
+
+        ret.map_err(|(value, e)| {
+            value.fill(0);
+            self.kv_value.replace(value);
+            e
+        })
+    }
+
+    fn start_kv_delete(&self, index: usize) -> Result<(), ErrorCode> {
+        let kv = self.kv.get().ok_or(ErrorCode::NODEVICE)?;
+        let permissions = self.storage_permissions.get().ok_or(ErrorCode::NODEVICE)?;
+        let key = self.kv_key_for(index)?;
+
+        kv.delete(key, permissions).map_err(|(key, e)| {
+            self.kv_key.replace(key.take());
+            e
+        })
+    }
+
+    /// Load the decrypted `record` into slot `index`.
+    fn load(&self, index: usize, record: &[u8]) {
+        if let Some((usage, owner, key)) = read_record(record) {
+            let slot = &self.slots[index];
+            if slot.is_empty() {
+                slot.fill(key, usage, Some(owner), None);
+                slot.stored.set(true);
+            }
+        }
+    }
+
+    fn import(
+        &self,
+        processid: ProcessId,
+        usage: usize,
+        store: bool,
+    ) -> Result<KeyHandle, ErrorCode> {
+        let usage = KeyUsage::from_usize(usage).ok_or(ErrorCode::INVAL)?;
+        let app_id = processid.short_app_id();
+        if let ShortId::LocallyUnique = app_id {
+            return Err(ErrorCode::NOSUPPORT);
+        }
+        if store {
+            if self.kv.is_none() || self.aead.is_none() || self.derivation.is_none() {
+                return Err(ErrorCode::NOSUPPORT);
+            }
+            if self.kv_operation.is_some() {
+                return Err(ErrorCode::BUSY);
+            }
+        }
+        let handle = self.find_free_slot(app_id)?;
+        let slot = &self.slots[handle.id() as usize];
+
+        self.apps
+            .enter(processid, |_, kernel_data| {
+                kernel_data
+                    .get_readonly_processbuffer(ro_allow::KEY)
+                    .and_then(|key| {
+                        key.enter(|key| {
+                            if key.len() == 0 || key.len() > MAX_KEY_LEN {
+                                return Err(ErrorCode::SIZE);
+                            }
+                            slot.fill_with(
+                                key.len(),
+                                usage,
+                                Some(app_id),
+                                Some(processid),
+                                |buf| key.copy_to_slice(buf),
+                            );
+                            Ok(())
+                        })
+                    })
+                    .unwrap_or(Err(ErrorCode::RESERVE))
+            })
+            .unwrap_or_else(|err| Err(err.into()))?;
+
+        if store {
+            // The key is written once the wrapping key and a nonce are
+            // available.
+            self.kv_operation.set(KvOperation::Store(processid, handle));
+            let ret =
+                self.wrap_key_ready().and_then(
+                    |ready| {
+                        if ready {
+                            self.start_wrap()
+                        } else {
+                            Ok(())
+                        }
+                    },
+                );
+            if let Err(e) = ret {
+                self.kv_operation.clear();
+                slot.clear();
+                return Err(e);
+            }
+        }
+
+        Ok(handle)
+    }
+
+    fn derive(&self, processid: ProcessId, usage: usize, version: usize) -> Result<(), ErrorCode> {
+        let usage = KeyUsage::from_usize(usage).ok_or(ErrorCode::INVAL)?;
+        let derivation = self.derivation.get().ok_or(ErrorCode::NOSUPPORT)?;
+        let app_id: u32 = match processid.short_app_id() {
+            ShortId::Fixed(id) => id.into(),
+            ShortId::LocallyUnique => return Err(ErrorCode::NOSUPPORT),
+        };
+        if self.derive_request.is_some() || self.wrap_key_pending.get() {
+            return Err(ErrorCode::BUSY);
+        }
+        let handle = self.find_free_slot(processid.short_app_id())?;
+
+        // The salt starts with the app's `ShortId`, so apps can only derive
+        // their own keys.
+        let mut salt = [0; 32];
+        salt[..4].copy_from_slice(&app_id.to_le_bytes());
+        self.apps
+            .enter(processid, |_, kernel_data| {
+                kernel_data
+                    .get_readonly_processbuffer(ro_allow::SALT)
+                    .and_then(|app_salt| {
+                        app_salt.enter(|app_salt| {
+                            if app_salt.len() > MAX_SALT_LEN {
+                                return Err(ErrorCode::SIZE);
+                            }
+                            app_salt.copy_to_slice(&mut salt[4..4 + app_salt.len()]);
+                            Ok(())
+                        })
+                    })
+                    .unwrap_or(Err(ErrorCode::RESERVE))
+            })
+            .unwrap_or_else(|err| Err(err.into()))?;
+
+        let key = self.derived_key.take().ok_or(ErrorCode::BUSY)?;
+        match derivation.derive(&salt, version as u32, key) {
+            Ok(()) => {
+                self.derive_request.set((processid, handle, usage));
+                Ok(())
+            }
+            Err((e, key)) => {
+                self.derived_key.replace(key);
+                Err(e)
+            }
+        }
+    }
+
+    fn delete(&self, processid: ProcessId, handle: usize) -> Result<bool, ErrorCode> {
+        let slot = self.owned_slot(handle, processid)?;
+
+        if slot.stored.get() {
+            if self.kv_operation.is_some() {
+                return Err(ErrorCode::BUSY);
+            }
+            self.start_kv_delete(handle)?;
+            self.kv_operation.set(KvOperation::Delete(
+                processid,
+                KeyHandle::new(handle as u32),
+            ));
+            slot.clear();
+            Ok(true)
+        } else {
+            slot.clear();
+            Ok(false)
+        }
+    }
+
+    fn store_done(&self, processid: ProcessId, handle: KeyHandle, result: Result<(), ErrorCode>) {
+        let _ = self.apps.enter(processid, |_, kernel_data| {
+            kernel_data
+                .schedule_upcall(
+                    upcall::STORE_DONE,
+                    (into_statuscode(result), handle.id() as usize, 0),
+                )
+                .ok();
+        });
+    }
+}
+
+impl KeyStore for KernelKeyStore<'_> {
+    fn with_key(
+        &self,
+        handle: KeyHandle,
+        app_id: ShortId,
+        usage: KeyUsage,
+        f: &mut dyn FnMut(&[u8]) -> Result<(), ErrorCode>,
+    ) -> Result<(), ErrorCode> {
+        let slot = self
+            .slots
+            .get(handle.id() as usize)
+            .ok_or(ErrorCode::INVAL)?;
+        let slot_usage = slot.usage.get().ok_or(ErrorCode::INVAL)?;
+
+        let allowed = match slot.owner.get() {
+            Some(owner) => owner == app_id,
+            None => self
+                .permissions
+                .iter()
+                .any(|permission| permission.handle == handle && permission.app_id == app_id),
+        };
+        if !allowed || slot_usage != usage {
+            return Err(ErrorCode::NOSUPPORT);
+        }
+
+        let len = slot.len.get();
+        slot.key.map_or(Err(ErrorCode::BUSY), |key| f(&key[..len]))
+    }
+
+    fn import(
+        &self,
+        processid: ProcessId,
+        usage: KeyUsage,
+        key: &[u8],
+    ) -> Result<KeyHandle, ErrorCode> {
+        let app_id = processid.short_app_id();
+        if let ShortId::LocallyUnique = app_id {
+            return Err(ErrorCode::NOSUPPORT);
+        }
+        if key.is_empty() || key.len() > MAX_KEY_LEN {
+            return Err(ErrorCode::SIZE);
+        }
+        // The key is freed once the process no longer has a grant region.
+        self.apps.enter(processid, |_, _| {})?;
+        let handle = self.find_free_slot(app_id)?;
+        self.slots[handle.id() as usize].fill(key, usage, Some(app_id), Some(processid));
+        Ok(handle)
+    }
+}
+
+impl KeyDerivationClient for KernelKeyStore<'_> {
+    fn derivation_done(
+        &self,
+        result: Result<(), ErrorCode>,
+        key: &'static mut [u8; DERIVED_KEY_LEN],
+    ) {
+        if self.wrap_key_pending.get() {
+            self.wrap_key_pending.set(false);
+            if result.is_ok() {
+                self.wrap_key.replace(*key);
+            }
+            key.fill(0);
+            self.derived_key.replace(key);
+            self.wrap_key_done(result);
+            return;
+        }
+
+        if let Some((processid, handle, usage)) = self.derive_request.take() {
+            let slot = &self.slots[handle.id() as usize];
+            let result = result.map(|()| {
+                slot.fill(
+                    &key[..],
+                    usage,
+                    Some(processid.short_app_id()),
+                    Some(processid),
+                );
+            });
+
+            let _ = self.apps.enter(processid, |_, kernel_data| {
+                kernel_data
+                    .schedule_upcall(
+                        upcall::DERIVE_DONE,
+                        (into_statuscode(result), handle.id() as usize, 0),
+                    )
+                    .ok();
+            });
+        }
+
+        key.fill(0);
+        self.derived_key.replace(key);
+    }
+}
+
+impl rng::Client for KernelKeyStore<'_> {
+    fn randomness_available(
+        &self,
+        randomness: &mut dyn Iterator<Item = u32>,
+        error: Result<(), ErrorCode>,
+    ) -> rng::Continue {
+        if self.wrap_index().is_none() {
+            return rng::Continue::Done;
+        }
+        if let Err(e) = error {
+            self.store_failed(e);
+            return rng::Continue::Done;
+        }
+
+        let offset = self.kv.map_or(0, |kv| kv.header_size()) + NONCE_OFFSET;
+        let more = self.kv_value.map_or(false, |value| {
+            while self.nonce_len.get() < CHACHA20_POLY1305_NONCE_SIZE {
+                match randomness.next() {
+                    Some(r) => {
+                        let start = offset + self.nonce_len.get();
+                        value[start..start + 4].copy_from_slice(&r.to_le_bytes());
+                        self.nonce_len.set(self.nonce_len.get() + 4);
+                    }
+                    None => return true,
+                }
+            }
+            false
+        });
+        if more {
+            return rng::Continue::More;
+        }
+
+        if let Err(e) = self.start_encrypt() {
+            self.store_failed(e);
+        }
+        rng::Continue::Done
+    }
+}
+
+impl ChaCha20Poly1305Client for KernelKeyStore<'_> {
+    fn crypt_done(&self, buf: &'static mut [u8], res: Result<(), ErrorCode>, tag_is_valid: bool) {
+        match self.kv_operation.get() {
+            Some(KvOperation::Restore(index)) => {
+                if res.is_ok() && tag_is_valid {
+                    let offset = self.kv.map_or(0, |kv| kv.header_size());
+                    self.load(index, &buf[offset..offset + self.record_len.get()]);
+                }
+                buf.fill(0);
+                self.kv_value.replace(buf);
+                self.restore_from(index + 1);
+            }
+            Some(KvOperation::Store(_, _)) => {
+                let ret = match res {
+                    Ok(()) => self.start_kv_set(buf),
+                    Err(e) => {
+                        buf.fill(0);
+                        self.kv_value.replace(buf);
+                        Err(e)
+                    }
+                };
+                if let Err(e) = ret {
+                    self.store_failed(e);
+                }
+            }
+            _ => {
+                buf.fill(0);
+                self.kv_value.replace(buf);
+            }
+        }
+    }
+}
+
+impl kv::KVClient for KernelKeyStore<'_> {
+    fn get_complete(
+        &self,
+        result: Result<(), ErrorCode>,
+        key: SubSliceMut<'static, u8>,
+        value: SubSliceMut<'static, u8>,
+    ) {
+        self.kv_key.replace(key.take());
+
+        let index = match self.kv_operation.get() {
+            Some(KvOperation::Restore(index)) => index,
+            _ => {
+                let value = value.take();
+                value.fill(0);
+                self.kv_value.replace(value);
+                return;
+            }
+        };
+
+        if result.is_ok() {
+            let record_len = value.len();
+            match self.start_decrypt(index, value.take(), record_len) {
+                Ok(()) => return,
+                Err((_, value)) => {
+                    value.fill(0);
+                    self.kv_value.replace(value);
+                }
+            }
+        } else {
+            self.kv_value.replace(value.take());
+        }
+
+        self.restore_from(index + 1);
+    }
+
+    fn set_complete(
+        &self,
+        result: Result<(), ErrorCode>,
+        key: SubSliceMut<'static, u8>,
+        value: SubSliceMut<'static, u8>,
+    ) {
+        self.kv_key.replace(key.take());
+        let value = value.take();
+        value.fill(0);
+        self.kv_value.replace(value);
+
+        if let Some(KvOperation::Store(processid, handle)) = self.kv_operation.take() {
+            if result.is_ok() {
+                self.slots[handle.id() as usize].stored.set(true);
+            }
+            self.store_done(processid, handle, result);
+        }
+    }
+
+    fn add_complete(
+        &self,
+        _result: Result<(), ErrorCode>,
+        key: SubSliceMut<'static, u8>,
+        value: SubSliceMut<'static, u8>,
+    ) {
+        self.kv_key.replace(key.take());
+        self.kv_value.replace(value.take());
+    }
+
+    fn update_complete(
+        &self,
+        _result: Result<(), ErrorCode>,
+        key: SubSliceMut<'static, u8>,
+        value: SubSliceMut<'static, u8>,
+    ) {
+        self.kv_key.replace(key.take());
+        self.kv_value.replace(value.take());
+    }
+
+    fn delete_complete(&self, result: Result<(), ErrorCode>, key: SubSliceMut<'static, u8>) {
+        self.kv_key.replace(key.take());
+
+        if let Some(KvOperation::Delete(processid, handle)) = self.kv_operation.take() {
+            self.store_done(processid, handle, result);
+        }
+    }
+}
+
+impl SyscallDriver for KernelKeyStore<'_> {
+    /// Manage keys held by the kernel.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver existence check.
+    /// - `1`: Import the key in the `KEY` read-only allow buffer. `data1` is
+    ///        the key usage (1 for AES, 2 for HMAC). If `data2` is non-zero
+    ///        the key is also kept, encrypted, in the KV store, and the
+    ///        `STORE_DONE` upcall reports the result. Returns the key handle.
+    /// - `2`: Derive a key from the device secret and the salt in the `SALT`
+    ///        read-only allow buffer. `data1` is the key usage and `data2` the
+    ///        key version. The handle is passed to the `DERIVE_DONE` upcall.
+    /// - `3`: Delete the key with handle `data1`. Returns 1 if the key was
+    ///        kept in the KV store, in which case the `STORE_DONE` upcall
+    ///        reports when it has been removed.
+    fn command(
+        &self,
+        command_num: usize,
+        data1: usize,
+        data2: usize,
+        processid: ProcessId,
+    ) -> CommandReturn {
+        match command_num {
+            0 => CommandReturn::success(),
+
+            1 => match self.import(processid, data1, data2 != 0) {
+                Ok(handle) => CommandReturn::success_u32(handle.id()),
+                Err(e) => CommandReturn::failure(e),
+            },
+
+            2 => self.derive(processid, data1, data2).into(),
+
+            3 => match self.delete(processid, data1) {
+                Ok(stored) => CommandReturn::success_u32(stored as u32),
+                Err(e) => CommandReturn::failure(e),
+            },
+
+            _ => CommandReturn::failure(ErrorCode::NOSUPPORT),
+        }
+    }
+
+    fn allocate_grant(&self, processid: ProcessId) -> Result<(), kernel::process::Error> {
+        self.apps.enter(processid, |_, _| {})
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::symmetric_encryption::chacha20_poly1305::aead_crypt;
+
+    const APP: ShortId = ShortId::Fixed(match NonZeroU32::new(7) {
+        Some(id) => id,
+        None => unreachable!(),
+    });
+    const OTHER_APP: ShortId = ShortId::Fixed(match NonZeroU32::new(8) {
+        Some(id) => id,
+        None => unreachable!(),
+    });
+    const KEY: [u8; 16] = [0x5a; 16];
+    const RECORD_LEN: usize = RECORD_OVERHEAD + KEY.len();
+
+    /// Encrypt or decrypt `record` the way the keystore asks the AEAD to,
+    /// returning whether the tag is valid.
+    fn crypt(record: &mut [u8], encrypting: bool) -> bool {
+        let wrap_key = [0x42; DERIVED_KEY_LEN];
+        let key = core::array::from_fn(|i| {
+            u32::from_le_bytes(wrap_key[i * 4..i * 4 + 4].try_into().unwrap())
+        });
+        let nonce = core::array::from_fn(|i| {
+            let start = NONCE_OFFSET + i * 4;
+            u32::from_le_bytes(record[start..start + 4].try_into().unwrap())
+        });
+        let key_len = record.len() - RECORD_OVERHEAD;
+        aead_crypt(&key, &nonce, record, 0, KEY_OFFSET, key_len, encrypting)
+    }
+
+    /// An encrypted record holding `KEY` for `APP`.
+    fn wrapped_record() -> [u8; RECORD_LEN] {
+        let mut record = [0; RECORD_LEN];
+        record[NONCE_OFFSET..STORED_HEADER_OFFSET].copy_from_slice(&[3; 12]);
+        write_record(&mut record, KeyUsage::Aes as u8, 7, &KEY);
+        assert!(crypt(&mut record, true));
+        record
+    }
+
+    fn slots<const N: usize>() -> [KeySlot; N] {
+        core::array::from_fn(|_| KeySlot::new())
+    }
+
+    fn fill(slot: &KeySlot, owner: Option<ShortId>, process: Option<usize>) {
+        slot.fill(&KEY, KeyUsage::Aes, owner, None);
+        slot.process.insert(process);
+    }
+
+    #[test]
+    fn wrap_then_unwrap() {
+        let mut record = wrapped_record();
+        assert_eq!(record[0], RECORD_VERSION);
+        assert_ne!(record[KEY_OFFSET..KEY_OFFSET + KEY.len()], KEY);
+        assert_eq!(record_key_len(&record), Some(KEY.len()));
+
+        assert!(crypt(&mut record, false));
+        let (usage, owner, key) = read_record(&record).unwrap();
+        assert_eq!(usage, KeyUsage::Aes);
+        assert!(owner == APP);
+        assert_eq!(key, KEY);
+    }
+
+    #[test]
+    fn tampered_record_is_rejected() {
+        let tampered = [
+            // The tag.
+            RECORD_LEN - 1,
+            // The nonce.
+            NONCE_OFFSET,
+            // The owner, which is authenticated.
+            STORED_HEADER_OFFSET + 1,
+            // The key.
+            KEY_OFFSET,
+        ];
+
+        for offset in tampered {
+            let mut record = wrapped_record();
+            record[offset] ^= 1;
+            assert!(!crypt(&mut record, false));
+        }
+    }
+
+    #[test]
+    fn unencrypted_record_is_rejected() {
+        // The usage, owner and length of a key, followed by the key, as the
+        // keystore doesn't write them.
+        let mut record = [0; 6 + KEY.len() + RECORD_OVERHEAD];
+        record[..6].copy_from_slice(&[KeyUsage::Aes as u8, 7, 0, 0, 0, KEY.len() as u8]);
+        record[6..6 + KEY.len()].copy_from_slice(&KEY);
+        assert_eq!(record_key_len(&record), None);
+        assert!(read_record(&record).is_none());
+
+        // Records too short to hold a key aren't read past their end.
+        assert_eq!(record_key_len(&record[..RECORD_OVERHEAD - 1]), None);
+    }
+
+    #[test]
+    fn max_app_slots() {
+        let slots = slots::<4>();
+
+        fill(&slots[0], Some(APP), None);
+        assert_eq!(find_free_slot(&slots, 2, APP, None), Ok(KeyHandle::new(1)));
+        fill(&slots[1], Some(APP), None);
+        assert_eq!(find_free_slot(&slots, 2, APP, None), Err(ErrorCode::NOMEM));
+
+        // Other apps have their own limit, and a derivation in progress
+        // counts against it.
+        let deriving = Some((OTHER_APP, KeyHandle::new(2)));
+        assert_eq!(
+            find_free_slot(&slots, 2, OTHER_APP, deriving),
+            Ok(KeyHandle::new(3))
+        );
+        fill(&slots[3], Some(OTHER_APP), None);
+        assert_eq!(
+            find_free_slot(&slots, 2, OTHER_APP, deriving),
+            Err(ErrorCode::NOMEM)
+        );
+
+        slots[0].clear();
+        assert_eq!(find_free_slot(&slots, 2, APP, None), Ok(KeyHandle::new(0)));
+
+        // The keystore being full is reported the same way.
+        fill(&slots[0], None, None);
+        fill(&slots[2], None, None);
+        assert_eq!(
+            find_free_slot(&slots, 4, OTHER_APP, None),
+            Err(ErrorCode::NOMEM)
+        );
+    }
+
+    #[test]
+    fn reclaim_slots_of_exited_process() {
+        let slots = slots::<5>();
+        let running = |id| id == 1;
+
+        fill(&slots[0], Some(APP), Some(2));
+        fill(&slots[1], Some(APP), Some(1));
+        fill(&slots[2], Some(APP), Some(2));
+        slots[2].stored.set(true);
+        fill(&slots[3], None, None);
+        fill(&slots[4], Some(APP), Some(2));
+
+        reclaim_slots(&slots, Some(4), running);
+
+        assert!(slots[0].is_empty());
+        assert!(slots[0]
+            .key
+            .map_or(false, |key| key.iter().all(|b| *b == 0)));
+        // Keys of running processes, stored keys, keys provisioned by the
+        // board and keys being stored are kept.
+        assert!(!slots[1].is_empty());
+        assert!(!slots[2].is_empty());
+        assert!(!slots[3].is_empty());
+        assert!(!slots[4].is_empty());
+
+        reclaim_slots(&slots, None, running);
+        assert!(slots[4].is_empty());
+        assert!(!slots[1].is_empty());
+    }
+}
@@ -39,6 +39,44 @@
 //!
 //!    hil::flash
 //! ```
+//!
+//! Listing Keys
+//! ------------
+//!
+//! Command 6 returns the next key owned by the calling process, starting at the
+//! position passed in `data1` (`0` to start from the beginning). Only keys that
+//! start with the contents of the read-only `KEY` allow buffer are returned, so
+//! an empty buffer will return every key the process owns.
+//!
+//! The key and value are copied to the read-write `KEY` and `VALUE` allow
+//! buffers. The upcall provides the status, the position to pass to the next
+//! command 6 call and the lengths of the key and value as
+//! `(key_length << 16) | value_length`. Once there are no more keys the
+//! upcall returns `NOSUPPORT`.
+//!
+//! Storage Usage
+//! -------------
+//!
+//! Command 7 synchronously returns the number of bytes and the number of
+//! objects stored by the calling process. These count against any
+//! `StorageQuota` in the process's storage permissions. If the usage is still
+//! being calculated the command returns `BUSY` and should be retried.
+//!
+//! Batches
+//! -------
+//!
+//! Command 8 synchronously starts a batch of set operations for the calling
+//! process. Until the batch is committed with command 9 or discarded with
+//! command 10, the values set by the process are staged and are stored
+//! together when the batch is committed. Commands 9 and 10 complete with an
+//! upcall containing only the status. If committing the batch fails with
+//! `NOMEM` the batch is still in progress and should be aborted.
+//!
+//! Only one process can have a batch in progress. While it does, set, add,
+//! update and delete commands from other processes are queued until the batch
+//! has been committed or aborted, and command 8 returns `BUSY` for them. If
+//! the process that started the batch exits, the batch is aborted once the
+//! next command is issued or the running operation completes.
 
 use capsules_core::driver;
 /// Syscall driver number.
@@ -50,6 +88,7 @@ use kernel::grant::Grant;
 use kernel::grant::{AllowRoCount, AllowRwCount, UpcallCount};
 use kernel::hil::kv;
 use kernel::processbuffer::{ReadableProcessBuffer, WriteableProcessBuffer};
+use kernel::storage_permissions::StoragePermissions;
 use kernel::syscall::{CommandReturn, SyscallDriver};
 use kernel::utilities::cells::{OptionalCell, TakeCell};
 use kernel::utilities::leasable_buffer::SubSliceMut;
@@ -67,10 +106,12 @@ mod ro_allow {
 
 /// IDs for read-write allow buffers.
 mod rw_allow {
-    /// Output value for get.
+    /// Output value for get and next key.
     pub const VALUE: usize = 0;
+    /// Output key for next key.
+    pub const KEY: usize = 1;
     /// The number of RW allow buffers the kernel stores for this grant.
-    pub const COUNT: u8 = 1;
+    pub const COUNT: u8 = 2;
 }
 
 /// IDs for upcalls.
@@ -88,6 +129,90 @@ enum UserSpaceOp {
     Delete,
     Add,
     Update,
+    NextKey(usize),
+    CommitBatch,
+    AbortBatch,
+}
+
+impl UserSpaceOp {
+    /// Whether the operation modifies the store, and so has to wait for
+    /// another process's batch to finish.
+    fn modifies_store(&self) -> bool {
+        matches!(
+            self,
+            UserSpaceOp::Set | UserSpaceOp::Delete | UserSpaceOp::Add | UserSpaceOp::Update
+        )
+    }
+}
+
+/// Whether `op` from `caller` has to wait for the batch another app has in
+/// progress to finish.
+fn blocked_by_batch<P: Copy + PartialEq>(
+    batch: &OptionalCell<(P, StoragePermissions)>,
+    caller: P,
+    op: UserSpaceOp,
+) -> bool {
+    op.modifies_store() && batch.map_or(false, |(owner, _)| owner != caller)
+}
+
+/// Whether `op` from `caller` can be started now rather than queued: no app
+/// is `active` and it doesn't have to wait for a batch.
+fn can_start<P: Copy + PartialEq>(
+    active: &OptionalCell<P>,
+    batch: &OptionalCell<(P, StoragePermissions)>,
+    caller: P,
+    op: UserSpaceOp,
+) -> bool {
+    active.is_none() && !blocked_by_batch(batch, caller, op)
+}
+
+/// If no app is `active` and the app that started `batch` is no longer
+/// `running`, start aborting the batch with `abort` so that other apps can
+/// modify the store again.
+///
+/// Returns whether the abort was started, in which case the exited app is
+/// active until it completes.
+fn abort_exited_batch<P: Copy>(
+    active: &OptionalCell<P>,
+    batch: &OptionalCell<(P, StoragePermissions)>,
+    running: impl Fn(P) -> bool,
+    abort: impl FnOnce(StoragePermissions) -> Result<(), ErrorCode>,
+) -> bool {
+    if active.is_some() {
+        return false;
+    }
+    match batch.get() {
+        Some((owner, perms)) if !running(owner) => {
+            active.set(owner);
+            if abort(perms).is_ok() {
+                return true;
+            }
+            active.clear();
+            batch.clear();
+            false
+        }
+        _ => false,
+    }
+}
+
+/// Make the first app in `queued` whose operation doesn't have to wait for a
+/// batch, and which `start` succeeds in starting, the `active` app.
+fn start_queued<P: Copy + PartialEq>(
+    active: &OptionalCell<P>,
+    batch: &OptionalCell<(P, StoragePermissions)>,
+    queued: impl Iterator<Item = (P, UserSpaceOp)>,
+    mut start: impl FnMut() -> bool,
+) {
+    for (caller, op) in queued {
+        if blocked_by_batch(batch, caller, op) {
+            continue;
+        }
+        active.set(caller);
+        if start() {
+            return;
+        }
+        active.clear();
+    }
 }
 
 /// Contents of the grant for each app.
@@ -113,6 +238,9 @@ pub struct KVStoreDriver<'a, V: kv::KVPermissions<'a>> {
     key_buffer: TakeCell<'static, [u8]>,
     /// Value buffer.
     value_buffer: TakeCell<'static, [u8]>,
+    /// App that has a batch in progress, and the permissions it was started
+    /// with so that it can be aborted if the app exits.
+    batch: OptionalCell<(ProcessId, StoragePermissions)>,
 }
 
 impl<'a, V: kv::KVPermissions<'a>> KVStoreDriver<'a, V> {
@@ -133,15 +261,34 @@ impl<'a, V: kv::KVPermissions<'a>> KVStoreDriver<'a, V> {
             processid: OptionalCell::empty(),
             key_buffer: TakeCell::new(key_buffer),
             value_buffer: TakeCell::new(value_buffer),
+            batch: OptionalCell::empty(),
         }
     }
 
+    /// If the app that started the batch has exited, start aborting the
+    /// batch. Returns whether the abort was started.
+    fn abort_exited_batch(&self) -> bool {
+        abort_exited_batch(
+            &self.processid,
+            &self.batch,
+            |owner| self.apps.enter(owner, |_, _| {}).is_ok(),
+            |perms| self.kv.abort_batch(perms),
+        )
+    }
+
     fn run(&self) -> Result<(), ErrorCode> {
         self.processid.map_or(Err(ErrorCode::RESERVE), |processid| {
             self.apps
                 .enter(processid, |app, kernel_data| {
-                    let key_len = if app.op.is_some() {
-                        // For all operations we need to copy in the key.
+                    let key_len = if app.op.is_some()
+                        && !matches!(
+                            app.op.get(),
+                            Some(UserSpaceOp::NextKey(_))
+                                | Some(UserSpaceOp::CommitBatch)
+                                | Some(UserSpaceOp::AbortBatch)
+                        ) {
+                        // For all operations other than next key and the
+                        // batch operations we need to copy in the key.
                         kernel_data
                             .get_readonly_processbuffer(ro_allow::KEY)
                             .and_then(|buffer| {
@@ -266,6 +413,41 @@ impl<'a, V: kv::KVPermissions<'a>> KVStoreDriver<'a, V> {
                                 return e;
                             }
                         }
+                        Some(UserSpaceOp::NextKey(position)) => {
+                            if let Some(Some(e)) = self.key_buffer.take().map(|key_buf| {
+                                self.value_buffer.take().map(|val_buf| {
+                                    let perms = processid
+                                        .get_storage_permissions()
+                                        .ok_or(ErrorCode::INVAL)?;
+
+                                    let key = SubSliceMut::new(key_buf);
+                                    let value = SubSliceMut::new(val_buf);
+
+                                    if let Err((key_ret, val_ret, e)) =
+                                        self.kv.next_key(position, key, value, perms)
+                                    {
+                                        self.key_buffer.replace(key_ret.take());
+                                        self.value_buffer.replace(val_ret.take());
+                                        return Err(e);
+                                    }
+                                    Ok(())
+                                })
+                            }) {
+                                return e;
+                            }
+                        }
+                        Some(UserSpaceOp::CommitBatch) => {
+                            let perms = processid
+                                .get_storage_permissions()
+                                .ok_or(ErrorCode::INVAL)?;
+                            self.kv.commit_batch(perms)?;
+                        }
+                        Some(UserSpaceOp::AbortBatch) => {
+                            let perms = processid
+                                .get_storage_permissions()
+                                .ok_or(ErrorCode::INVAL)?;
+                            self.kv.abort_batch(perms)?;
+                        }
                         _ => {}
                     }
 
@@ -281,28 +463,50 @@ impl<'a, V: kv::KVPermissions<'a>> KVStoreDriver<'a, V> {
             return;
         }
 
-        for appiter in self.apps.iter() {
-            let processid = appiter.processid();
-            let has_pending_op = appiter.enter(|app, _| {
-                // If this app has a pending command let's use it.
-                app.op.is_some()
-            });
-            let started_command = if has_pending_op {
-                // Mark this driver as being in use.
-                self.processid.set(processid);
-                self.run() == Ok(())
-            } else {
-                false
-            };
-            if started_command {
-                break;
-            } else {
-                self.processid.clear();
-            }
+        // If the app that started a batch has exited, abort the batch so that
+        // other apps can modify the store again.
+        if self.abort_exited_batch() {
+            return;
         }
+
+        // Otherwise start the first pending command that doesn't have to wait
+        // for another app's batch.
+        let queued = self.apps.iter().filter_map(|appiter| {
+            let processid = appiter.processid();
+            appiter
+                .enter(|app, _| app.op.get())
+                .map(|op| (processid, op))
+        });
+        start_queued(&self.processid, &self.batch, queued, || {
+            self.run() == Ok(())
+        });
     }
 }
 
+/// Copy `data` into the read-write allow buffer `allow_num`.
+///
+/// Returns `SIZE` if only part of `data` fits in the buffer.
+fn copy_to_process(
+    kernel_data: &kernel::grant::GrantKernelData,
+    allow_num: usize,
+    data: &[u8],
+) -> Result<(), ErrorCode> {
+    kernel_data
+        .get_readwrite_processbuffer(allow_num)
+        .and_then(|buffer| {
+            buffer.mut_enter(|appslice| {
+                let copy_len = cmp::min(data.len(), appslice.len());
+                appslice[..copy_len].copy_from_slice(&data[..copy_len]);
+                if copy_len < data.len() {
+                    Err(ErrorCode::SIZE)
+                } else {
+                    Ok(())
+                }
+            })
+        })
+        .unwrap_or(Err(ErrorCode::RESERVE))
+}
+
 impl<'a, V: kv::KVPermissions<'a>> kv::KVClient for KVStoreDriver<'a, V> {
     fn get_complete(
         &self,
@@ -465,13 +669,149 @@ impl<'a, V: kv::KVPermissions<'a>> kv::KVClient for KVStoreDriver<'a, V> {
         self.processid.clear();
         self.check_queue();
     }
+
+    fn next_key_complete(
+        &self,
+        mut result: Result<(), ErrorCode>,
+        position: usize,
+        mut key: SubSliceMut<'static, u8>,
+        mut value: SubSliceMut<'static, u8>,
+    ) {
+        if result.is_ok() || result == Err(ErrorCode::SIZE) {
+            // Skip keys that don't start with the prefix the app provided.
+            let prefix_matches = self.processid.map_or(true, |id| {
+                self.apps
+                    .enter(id, |_app, kernel_data| {
+                        kernel_data
+                            .get_readonly_processbuffer(ro_allow::KEY)
+                            .and_then(|buffer| {
+                                buffer.enter(|prefix| {
+                                    prefix.len() <= key.len()
+                                        && prefix
+                                            .iter()
+                                            .zip(key[..prefix.len()].iter())
+                                            .all(|(p, k)| p.get() == *k)
+                                })
+                            })
+                            .unwrap_or(true)
+                    })
+                    .unwrap_or(true)
+            });
+
+            if !prefix_matches {
+                if let Some(perms) = self.processid.and_then(|id| id.get_storage_permissions()) {
+                    key.reset();
+                    value.reset();
+                    match self.kv.next_key(position, key, value, perms) {
+                        Ok(()) => return,
+                        Err((key_ret, val_ret, e)) => {
+                            key = key_ret;
+                            value = val_ret;
+                            result = Err(e);
+                        }
+                    }
+                }
+            }
+        }
+
+        self.processid.map(|id| {
+            self.apps.enter(id, |app, upcalls| {
+                if let Some(UserSpaceOp::NextKey(_)) = app.op.get() {
+                    app.op.clear();
+
+                    match result {
+                        Ok(()) | Err(ErrorCode::SIZE) => {
+                            let key_len = key.len();
+                            let value_len = value.len();
+                            let ret = result
+                                .and(copy_to_process(upcalls, rw_allow::KEY, &key[..]))
+                                .and(copy_to_process(upcalls, rw_allow::VALUE, &value[..]));
+
+                            upcalls
+                                .schedule_upcall(
+                                    upcalls::VALUE,
+                                    (
+                                        errorcode::into_statuscode(ret),
+                                        position,
+                                        (key_len << 16) | value_len,
+                                    ),
+                                )
+                                .ok();
+                        }
+                        Err(e) => {
+                            upcalls
+                                .schedule_upcall(
+                                    upcalls::VALUE,
+                                    (errorcode::into_statuscode(Err(e)), 0, 0),
+                                )
+                                .ok();
+                        }
+                    }
+                }
+            })
+        });
+
+        self.key_buffer.replace(key.take());
+        self.value_buffer.replace(value.take());
+
+        // We have completed the operation so see if there is a queued operation
+        // to run next.
+        self.processid.clear();
+        self.check_queue();
+    }
+
+    fn commit_batch_complete(&self, result: Result<(), ErrorCode>) {
+        // If the commit couldn't be started the batch is still in progress
+        // and needs to be aborted.
+        if result != Err(ErrorCode::NOMEM) {
+            self.batch.clear();
+        }
+
+        self.processid.map(move |id| {
+            self.apps.enter(id, move |app, upcalls| {
+                if app.op.contains(&UserSpaceOp::CommitBatch) {
+                    app.op.clear();
+                    upcalls
+                        .schedule_upcall(upcalls::VALUE, (errorcode::into_statuscode(result), 0, 0))
+                        .ok();
+                }
+            })
+        });
+
+        // We have completed the operation so see if there is a queued operation
+        // to run next.
+        self.processid.clear();
+        self.check_queue();
+    }
+
+    fn abort_batch_complete(&self, result: Result<(), ErrorCode>) {
+        self.batch.clear();
+
+        // The app might have exited, in which case the batch was aborted by
+        // the driver and there is no one to notify.
+        self.processid.map(move |id| {
+            self.apps.enter(id, move |app, upcalls| {
+                if app.op.contains(&UserSpaceOp::AbortBatch) {
+                    app.op.clear();
+                    upcalls
+                        .schedule_upcall(upcalls::VALUE, (errorcode::into_statuscode(result), 0, 0))
+                        .ok();
+                }
+            })
+        });
+
+        // We have completed the operation so see if there is a queued operation
+        // to run next.
+        self.processid.clear();
+        self.check_queue();
+    }
 }
 
 impl<'a, V: kv::KVPermissions<'a>> SyscallDriver for KVStoreDriver<'a, V> {
     fn command(
         &self,
         command_num: usize,
-        _data1: usize,
+        data1: usize,
         _data2: usize,
         processid: ProcessId,
     ) -> CommandReturn {
@@ -479,23 +819,39 @@ impl<'a, V: kv::KVPermissions<'a>> SyscallDriver for KVStoreDriver<'a, V> {
             // check if present
             0 => CommandReturn::success(),
 
-            // get, set, delete, add, update
-            1 | 2 | 3 | 4 | 5 => {
-                if self.processid.is_none() {
+            // get, set, delete, add, update, next key, commit batch, abort batch
+            1 | 2 | 3 | 4 | 5 | 6 | 9 | 10 => {
+                if (command_num == 9 || command_num == 10)
+                    && !self.batch.map_or(false, |(owner, _)| owner == processid)
+                {
+                    // This app hasn't started a batch.
+                    return CommandReturn::failure(ErrorCode::INVAL);
+                }
+
+                let op = match command_num {
+                    1 => UserSpaceOp::Get,
+                    2 => UserSpaceOp::Set,
+                    3 => UserSpaceOp::Delete,
+                    4 => UserSpaceOp::Add,
+                    5 => UserSpaceOp::Update,
+                    6 => UserSpaceOp::NextKey(data1),
+                    9 => UserSpaceOp::CommitBatch,
+                    _ => UserSpaceOp::AbortBatch,
+                };
+
+                // A batch left behind by an app that exited would otherwise
+                // hold up this request until some other operation completes.
+                self.abort_exited_batch();
+
+                if can_start(&self.processid, &self.batch, processid, op) {
                     // Nothing is using the KV store, so we can handle this
                     // request.
                     self.processid.set(processid);
-                    let _ = self.apps.enter(processid, |app, _| match command_num {
-                        1 => app.op.set(UserSpaceOp::Get),
-                        2 => app.op.set(UserSpaceOp::Set),
-                        3 => app.op.set(UserSpaceOp::Delete),
-                        4 => app.op.set(UserSpaceOp::Add),
-                        5 => app.op.set(UserSpaceOp::Update),
-                        _ => {}
-                    });
+                    let _ = self.apps.enter(processid, |app, _| app.op.set(op));
                     let ret = self.run();
 
                     if let Err(e) = ret {
+                        let _ = self.apps.enter(processid, |app, _| app.op.clear());
                         self.processid.clear();
                         self.check_queue();
                         CommandReturn::failure(e)
@@ -503,7 +859,8 @@ impl<'a, V: kv::KVPermissions<'a>> SyscallDriver for KVStoreDriver<'a, V> {
                         CommandReturn::success()
                     }
                 } else {
-                    // There is an active app, so queue this request (if
+                    // There is an active app, or this request has to wait for
+                    // another app's batch, so queue this request (if
                     // possible).
                     self.apps
                         .enter(processid, |app, _| {
@@ -514,14 +871,7 @@ impl<'a, V: kv::KVPermissions<'a>> SyscallDriver for KVStoreDriver<'a, V> {
                             } else {
                                 // This app has not already queued a command so
                                 // we can store this.
-                                match command_num {
-                                    1 => app.op.set(UserSpaceOp::Get),
-                                    2 => app.op.set(UserSpaceOp::Set),
-                                    3 => app.op.set(UserSpaceOp::Delete),
-                                    4 => app.op.set(UserSpaceOp::Add),
-                                    5 => app.op.set(UserSpaceOp::Update),
-                                    _ => {}
-                                }
+                                app.op.set(op);
                                 CommandReturn::success()
                             }
                         })
@@ -529,6 +879,37 @@ impl<'a, V: kv::KVPermissions<'a>> SyscallDriver for KVStoreDriver<'a, V> {
                 }
             }
 
+            // storage usage
+            7 => match processid.get_storage_permissions() {
+                Some(perms) => match self.kv.get_usage(perms) {
+                    Ok(usage) => {
+                        CommandReturn::success_u32_u32(usage.bytes as u32, usage.objects as u32)
+                    }
+                    Err(e) => CommandReturn::failure(e),
+                },
+                None => CommandReturn::failure(ErrorCode::INVAL),
+            },
+
+            // begin batch
+            8 => match processid.get_storage_permissions() {
+                Some(_) if self.abort_exited_batch() => CommandReturn::failure(ErrorCode::BUSY),
+                Some(perms) => match self.batch.get() {
+                    Some((owner, _)) if owner == processid => {
+                        CommandReturn::failure(ErrorCode::ALREADY)
+                    }
+                    Some(_) => CommandReturn::failure(ErrorCode::BUSY),
+                    None if self.processid.is_some() => CommandReturn::failure(ErrorCode::BUSY),
+                    None => match self.kv.begin_batch(perms) {
+                        Ok(()) => {
+                            self.batch.set((processid, perms));
+                            CommandReturn::success()
+                        }
+                        Err(e) => CommandReturn::failure(e),
+                    },
+                },
+                None => CommandReturn::failure(ErrorCode::INVAL),
+            },
+
             // default
             _ => CommandReturn::failure(ErrorCode::NOSUPPORT),
         }
@@ -538,3 +919,160 @@ impl<'a, V: kv::KVPermissions<'a>> SyscallDriver for KVStoreDriver<'a, V> {
         self.apps.enter(processid, |_, _| {})
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::cell::Cell;
+
+    const OWNER: usize = 1;
+    const WRITER: usize = 2;
+    const READER: usize = 3;
+
+    /// The apps using the store, identified by index, and the operations
+    /// they have queued.
+    struct Apps {
+        active: OptionalCell<usize>,
+        batch: OptionalCell<(usize, StoragePermissions)>,
+        queued: [Cell<Option<UserSpaceOp>>; 4],
+        exited: Cell<Option<usize>>,
+        aborts: Cell<usize>,
+        started: Cell<Option<(usize, UserSpaceOp)>>,
+    }
+
+    impl Apps {
+        fn new() -> Self {
+            let apps = Apps {
+                active: OptionalCell::empty(),
+                batch: OptionalCell::empty(),
+                queued: Default::default(),
+                exited: Cell::new(None),
+                aborts: Cell::new(0),
+                started: Cell::new(None),
+            };
+            apps.batch.set((OWNER, StoragePermissions::new_null()));
+            apps
+        }
+
+        fn running(&self, app: usize) -> bool {
+            self.exited.get() != Some(app)
+        }
+
+        /// Issue `op` from `app` as the driver's command does, returning
+        /// whether it was started rather than queued.
+        fn command(&self, app: usize, op: UserSpaceOp) -> bool {
+            abort_exited_batch(
+                &self.active,
+                &self.batch,
+                |app| self.running(app),
+                |_| {
+                    self.aborts.set(self.aborts.get() + 1);
+                    Ok(())
+                },
+            );
+            if can_start(&self.active, &self.batch, app, op) {
+                self.active.set(app);
+                self.started.set(Some((app, op)));
+                true
+            } else {
+                self.queued[app].set(Some(op));
+                false
+            }
+        }
+
+        /// Complete the active operation and start the next, as the driver
+        /// does once the store calls back.
+        fn complete(&self, batch_done: bool) {
+            if batch_done {
+                self.batch.clear();
+            }
+            self.active.clear();
+            if abort_exited_batch(
+                &self.active,
+                &self.batch,
+                |app| self.running(app),
+                |_| Ok(()),
+            ) {
+                return;
+            }
+            let queued = self
+                .queued
+                .iter()
+                .enumerate()
+                .filter_map(|(app, op)| op.get().map(|op| (app, op)));
+            start_queued(&self.active, &self.batch, queued, || {
+                let app = self.active.get().unwrap();
+                self.started
+                    .set(self.queued[app].take().map(|op| (app, op)));
+                true
+            });
+        }
+    }
+
+    #[test]
+    fn batch_holds_other_writers() {
+        let apps = Apps::new();
+        assert!(!apps.command(WRITER, UserSpaceOp::Set));
+        // Reads, and the owner's own writes, go ahead.
+        assert!(apps.command(READER, UserSpaceOp::Get));
+        apps.complete(false);
+        assert!(apps.active.is_none());
+        assert!(apps.command(OWNER, UserSpaceOp::Set));
+        apps.complete(false);
+        assert!(apps.active.is_none());
+        assert!(apps.queued[WRITER].get() == Some(UserSpaceOp::Set));
+
+        // Once the batch is committed the write runs.
+        assert!(apps.command(OWNER, UserSpaceOp::CommitBatch));
+        apps.complete(true);
+        assert!(apps.started.get() == Some((WRITER, UserSpaceOp::Set)));
+        assert_eq!(apps.aborts.get(), 0);
+    }
+
+    #[test]
+    fn owner_exits_while_write_queued() {
+        let apps = Apps::new();
+        assert!(!apps.command(WRITER, UserSpaceOp::Set));
+
+        // The next command after the owner exits aborts its batch, and is
+        // queued until the abort completes.
+        apps.exited.set(Some(OWNER));
+        assert!(!apps.command(READER, UserSpaceOp::Get));
+        assert_eq!(apps.aborts.get(), 1);
+        assert_eq!(apps.active.get(), Some(OWNER));
+
+        apps.complete(true);
+        assert!(apps.started.get() == Some((WRITER, UserSpaceOp::Set)));
+        apps.complete(false);
+        assert!(apps.started.get() == Some((READER, UserSpaceOp::Get)));
+        apps.complete(false);
+        assert!(apps.active.is_none());
+        assert_eq!(apps.aborts.get(), 1);
+    }
+
+    #[test]
+    fn write_after_owner_exits() {
+        let apps = Apps::new();
+        apps.exited.set(Some(OWNER));
+        assert!(!apps.command(WRITER, UserSpaceOp::Set));
+        assert_eq!(apps.aborts.get(), 1);
+
+        apps.complete(true);
+        assert!(apps.started.get() == Some((WRITER, UserSpaceOp::Set)));
+    }
+
+    #[test]
+    fn abort_of_exited_batch_fails() {
+        let apps = Apps::new();
+        apps.exited.set(Some(OWNER));
+        // The batch is dropped, so the write can start straight away.
+        assert!(!abort_exited_batch(
+            &apps.active,
+            &apps.batch,
+            |app| apps.running(app),
+            |_| Err(ErrorCode::FAIL),
+        ));
+        assert!(apps.active.is_none() && apps.batch.is_none());
+        assert!(apps.command(WRITER, UserSpaceOp::Set));
+    }
+}
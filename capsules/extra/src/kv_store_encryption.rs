@@ -0,0 +1,1353 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Tock Key-Value store capsule with encryption at rest.
+//!
+//! This capsule encrypts and authenticates every value before it is handed to
+//! the underlying K-V store, so that values never reach flash in plaintext.
+//! It sits between `KVStorePermissions` and the K-V library:
+//!
+//! ```rust,ignore
+//! +-----------------------+
+//! |  KVStorePermissions   |
+//! +-----------------------+
+//!
+//!    hil::kv::KV
+//!
+//! +-----------------------+
+//! | K-V store (this file) |
+//! +-----------------------+
+//!
+//!    hil::kv::KV
+//!
+//! +-----------------------+
+//! |  K-V library          |
+//! +-----------------------+
+//! ```
+//!
+//! Values are protected with AES-128-CCM using a device key. Each stored
+//! record has the following layout:
+//!
+//! ```text
+//! +------------+-------------+--------------------+----------------+---------+
+//! | version 1B | nonce (13B) | permissions header | encrypted data | tag 16B |
+//! +------------+-------------+--------------------+----------------+---------+
+//! ```
+//!
+//! The permissions header is left in plaintext but is authenticated together
+//! with the key as associated data. This means a record cannot be copied to a
+//! different key, and its owner cannot be changed, without the tag check
+//! failing. A fresh random nonce is used for every write.
+//!
+//! Records are always read and authenticated in full using an internal
+//! buffer, so the buffer must be large enough for the longest key and value
+//! (plus `RECORD_OVERHEAD`) that will be stored.
+//!
+//! If a record can't be authenticated while iterating with `next_key()`, for
+//! example because it doesn't fit in that buffer or has been modified, `FAIL`
+//! is returned with the position of the next record so the caller can skip
+//! it. The value holds the permissions header stored with the record, which
+//! isn't authenticated but lets the caller account for the space it uses.
+//!
+//! Value Length
+//! ------------
+//!
+//! AES-CCM implementations such as `VirtualAES128CCM` format the associated
+//! data and the message into their own buffer, which limits the length of the
+//! values that can be stored. The length of that buffer is passed to `new()`
+//! and `set()`, `add()` and `update()` return `SIZE` for a key and value that
+//! would need more than `ccm_buffer_length()` bytes.
+//!
+//! Existing Records
+//! ----------------
+//!
+//! Records written before this layer was added to a store are in plaintext and
+//! can't be authenticated, so by default they are not returned. Reading them
+//! can be enabled with `accept_plaintext_records()` while a store is migrated.
+//! A plaintext record is encrypted the next time its key is written, so a
+//! store can be migrated by iterating over it with `next_key()` and setting
+//! each value again, after which plaintext records should no longer be
+//! accepted.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! let kv_store_encryption = static_init!(
+//!     capsules_extra::kv_store_encryption::KVStoreEncryption<'static, _, _, _>,
+//!     capsules_extra::kv_store_encryption::KVStoreEncryption::new(
+//!         tickv_kv_store,
+//!         aes,
+//!         rng,
+//!         device_key,
+//!         crypt_buffer,
+//!         CCM_CRYPT_BUF_LEN,
+//!     )
+//! );
+//! tickv_kv_store.set_client(kv_store_encryption);
+//! aes.set_client(kv_store_encryption);
+//! rng.set_client(kv_store_encryption);
+//! ```
+
+use crate::kv_store_permissions::{HEADER_LENGTH, HEADER_VERSION};
+use core::cell::Cell;
+use kernel::hil::kv;
+use kernel::hil::rng;
+use kernel::hil::symmetric_encryption::{
+    CCMClient, AES128CCM, AES128_BLOCK_SIZE, AES128_KEY_SIZE, CCM_NONCE_LENGTH,
+};
+use kernel::utilities::cells::{MapCell, OptionalCell, TakeCell};
+use kernel::utilities::leasable_buffer::SubSliceMut;
+use kernel::ErrorCode;
+
+/// Version stored at the start of each encrypted record.
+///
+/// This differs from the version at the start of the permissions header, so
+/// plaintext records written before this layer was added can be told apart.
+pub const RECORD_VERSION: u8 = 1;
+/// Length of the nonce stored after the version.
+pub const NONCE_LENGTH: usize = CCM_NONCE_LENGTH;
+/// Length of the authentication tag stored at the end of each record.
+pub const TAG_LENGTH: usize = 16;
+/// Number of bytes each stored record is longer than the value it holds.
+pub const RECORD_OVERHEAD: usize = 1 + NONCE_LENGTH + TAG_LENGTH;
+
+/// Offset of the nonce in a record.
+const NONCE_OFFSET: usize = 1;
+/// Offset of the associated data (the key and header) while a record is
+/// being encrypted or decrypted.
+const AAD_OFFSET: usize = NONCE_OFFSET + NONCE_LENGTH;
+
+/// Length of the buffer an AES-CCM implementation that formats its input like
+/// `VirtualAES128CCM` needs to protect a value of `value_length` bytes
+/// (excluding the permissions header) stored with a key of `key_length` bytes.
+pub const fn ccm_buffer_length(key_length: usize, value_length: usize) -> usize {
+    // The first block and the encoded length of the associated data are
+    // followed by the associated data, then the message, each padded to a
+    // whole block.
+    let auth_length = AES128_BLOCK_SIZE + 2 + key_length + HEADER_LENGTH;
+    auth_length.div_ceil(AES128_BLOCK_SIZE) * AES128_BLOCK_SIZE
+        + value_length.div_ceil(AES128_BLOCK_SIZE) * AES128_BLOCK_SIZE
+}
+
+/// The permissions header stored in `record`, if it is long enough to hold
+/// one. Plaintext records start with the header.
+fn stored_header(record: &[u8]) -> Option<[u8; HEADER_LENGTH]> {
+    let offset = if record.first() == Some(&HEADER_VERSION) {
+        0
+    } else {
+        AAD_OFFSET
+    };
+    record.get(offset..offset + HEADER_LENGTH)?.try_into().ok()
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Operation {
+    Get,
+    Set,
+    Add,
+    Update,
+    Delete,
+    NextKey,
+    CommitBatch,
+    AbortBatch,
+}
+
+/// Key-Value store wrapper that encrypts and authenticates values.
+///
+/// Implements `KV` on top of `KV`.
+pub struct KVStoreEncryption<'a, K: kv::KV<'a>, A: AES128CCM<'a>, R: rng::Rng<'a>> {
+    kv: &'a K,
+    aes: &'a A,
+    rng: &'a R,
+    device_key: [u8; AES128_KEY_SIZE],
+    /// Length of the buffer used by the AES-CCM implementation.
+    ccm_buffer_length: usize,
+    /// Whether plaintext records from before encryption was enabled are
+    /// returned.
+    accept_plaintext: Cell<bool>,
+
+    client: OptionalCell<&'a dyn kv::KVClient>,
+    operation: OptionalCell<Operation>,
+
+    /// Buffer used to build, encrypt and decrypt records.
+    crypt_buffer: TakeCell<'static, [u8]>,
+    /// Number of bytes of the nonce that have been generated so far.
+    nonce_length: Cell<usize>,
+    /// Length of the value being encrypted.
+    value_length: Cell<usize>,
+    /// Position returned by the underlying store for `next_key()`.
+    position: Cell<usize>,
+    /// Permissions header stored with the record being read, returned if the
+    /// record can't be authenticated during `next_key()`.
+    stored_header: OptionalCell<[u8; HEADER_LENGTH]>,
+
+    key: MapCell<SubSliceMut<'static, u8>>,
+    value: MapCell<SubSliceMut<'static, u8>>,
+}
+
+impl<'a, K: kv::KV<'a>, A: AES128CCM<'a>, R: rng::Rng<'a>> KVStoreEncryption<'a, K, A, R> {
+    pub fn new(
+        kv: &'a K,
+        aes: &'a A,
+        rng: &'a R,
+        device_key: [u8; AES128_KEY_SIZE],
+        crypt_buffer: &'static mut [u8],
+        ccm_buffer_length: usize,
+    ) -> KVStoreEncryption<'a, K, A, R> {
+        Self {
+            kv,
+            aes,
+            rng,
+            device_key,
+            ccm_buffer_length,
+            accept_plaintext: Cell::new(false),
+            client: OptionalCell::empty(),
+            operation: OptionalCell::empty(),
+            crypt_buffer: TakeCell::new(crypt_buffer),
+            nonce_length: Cell::new(0),
+            value_length: Cell::new(0),
+            position: Cell::new(0),
+            stored_header: OptionalCell::empty(),
+            key: MapCell::empty(),
+            value: MapCell::empty(),
+        }
+    }
+
+    /// Return records that were stored in plaintext, before this layer was
+    /// added to the store.
+    ///
+    /// Plaintext records aren't authenticated, so this should only be enabled
+    /// while existing records are being migrated.
+    pub fn accept_plaintext_records(&self, accept: bool) {
+        self.accept_plaintext.set(accept);
+    }
+
+    fn insert(
+        &self,
+        mut key: SubSliceMut<'static, u8>,
+        mut value: SubSliceMut<'static, u8>,
+        operation: Operation,
+    ) -> Result<
+        (),
+        (
+            SubSliceMut<'static, u8>,
+            SubSliceMut<'static, u8>,
+            ErrorCode,
+        ),
+    > {
+        if self.operation.is_some() {
+            return Err((key, value, ErrorCode::BUSY));
+        }
+
+        // The permissions header is authenticated, so it must be present.
+        if value.len() < HEADER_LENGTH {
+            return Err((key, value, ErrorCode::SIZE));
+        }
+
+        let buf = match self.crypt_buffer.take() {
+            Some(buf) => buf,
+            None => return Err((key, value, ErrorCode::FAIL)),
+        };
+
+        let key_length = key.len();
+        let value_length = value.len();
+        if AAD_OFFSET + key_length + value_length + TAG_LENGTH > buf.len()
+            || ccm_buffer_length(key_length, value_length - HEADER_LENGTH) > self.ccm_buffer_length
+        {
+            self.crypt_buffer.replace(buf);
+            return Err((key, value, ErrorCode::SIZE));
+        }
+
+        // Lay out the buffer as `version | nonce | key | header | data | tag`.
+        // The key and header are the associated data and the nonce is filled
+        // in once randomness is available.
+        let offset = AAD_OFFSET + key_length;
+        buf[0] = RECORD_VERSION;
+        buf[AAD_OFFSET..offset].copy_from_slice(key.as_slice());
+        buf[offset..offset + value_length].copy_from_slice(value.as_slice());
+        self.crypt_buffer.replace(buf);
+
+        self.operation.set(operation);
+        self.nonce_length.set(0);
+        self.value_length.set(value_length);
+        self.key.replace(key);
+        self.value.replace(value);
+
+        match self.rng.get() {
+            Ok(()) => Ok(()),
+            Err(e) => {
+                self.release_buffer();
+                self.operation.clear();
+                Err((self.key.take().unwrap(), self.value.take().unwrap(), e))
+            }
+        }
+    }
+
+    /// Start encrypting or decrypting the record in `buf` with the nonce
+    /// stored in the buffer.
+    fn crypt(
+        &self,
+        buf: &'static mut [u8],
+        message_offset: usize,
+        message_length: usize,
+        encrypting: bool,
+    ) -> Result<(), (ErrorCode, &'static mut [u8])> {
+        if let Err(e) = self.aes.set_key(&self.device_key) {
+            return Err((e, buf));
+        }
+        if let Err(e) = self.aes.set_nonce(&buf[NONCE_OFFSET..AAD_OFFSET]) {
+            return Err((e, buf));
+        }
+        self.aes.crypt(
+            buf,
+            AAD_OFFSET,
+            message_offset,
+            message_length,
+            TAG_LENGTH,
+            true,
+            encrypting,
+        )
+    }
+
+    /// Authenticate and decrypt the record returned by the underlying store.
+    ///
+    /// The key is inserted between the nonce and the header so that the
+    /// associated data matches what was used when the record was written.
+    fn decrypt(&self, key: SubSliceMut<'static, u8>, mut record: SubSliceMut<'static, u8>) {
+        let record_length = record.len();
+        let key_length = key.len();
+        self.stored_header.insert(stored_header(record.as_slice()));
+        let buf = record.take();
+        self.key.replace(key);
+
+        if record_length > 0 && buf[0] == HEADER_VERSION {
+            self.plaintext_record(buf, key_length, record_length);
+            return;
+        }
+
+        if record_length < RECORD_OVERHEAD + HEADER_LENGTH
+            || record_length + key_length > buf.len()
+            || buf[0] != RECORD_VERSION
+        {
+            self.crypt_buffer.replace(buf);
+            self.decrypt_done(false);
+            return;
+        }
+
+        buf.copy_within(AAD_OFFSET..record_length, AAD_OFFSET + key_length);
+        self.key.map(|key| {
+            buf[AAD_OFFSET..AAD_OFFSET + key_length].copy_from_slice(key.as_slice());
+        });
+        self.value_length
+            .set(record_length - RECORD_OVERHEAD - HEADER_LENGTH);
+
+        let message_offset = AAD_OFFSET + key_length + HEADER_LENGTH;
+        if let Err((_e, buf)) = self.crypt(buf, message_offset, self.value_length.get(), false) {
+            self.crypt_buffer.replace(buf);
+            self.decrypt_done(false);
+        }
+    }
+
+    /// Return a record that was stored in plaintext, if they are accepted.
+    ///
+    /// The record is moved to where the decrypted value would be so it can be
+    /// returned in the same way.
+    fn plaintext_record(&self, buf: &'static mut [u8], key_length: usize, record_length: usize) {
+        let offset = AAD_OFFSET + key_length;
+        let valid = self.accept_plaintext.get()
+            && record_length >= HEADER_LENGTH
+            && offset + record_length <= buf.len();
+
+        if valid {
+            buf.copy_within(0..record_length, offset);
+            self.value_length.set(record_length - HEADER_LENGTH);
+        }
+
+        self.crypt_buffer.replace(buf);
+        self.decrypt_done(valid);
+    }
+
+    /// Copy the decrypted value to the caller's buffer and finish the `get()`
+    /// or `next_key()` operation.
+    fn decrypt_done(&self, valid: bool) {
+        let key = self.key.take().unwrap();
+        let mut value = self.value.take().unwrap();
+        let mut result = Err(ErrorCode::FAIL);
+
+        if valid {
+            self.crypt_buffer.map(|buf| {
+                let offset = AAD_OFFSET + key.len();
+                let length = HEADER_LENGTH + self.value_length.get();
+                let copy_length = length.min(value.len());
+
+                value.as_slice()[..copy_length].copy_from_slice(&buf[offset..offset + copy_length]);
+                value.slice(0..copy_length);
+
+                result = if copy_length < length {
+                    Err(ErrorCode::SIZE)
+                } else {
+                    Ok(())
+                };
+            });
+        } else if self.operation.get() == Some(Operation::NextKey) {
+            self.return_stored_header(&mut value);
+        } else {
+            // Don't leak anything that was in the caller's buffer.
+            value.as_slice().iter_mut().for_each(|m| *m = 0);
+        }
+
+        self.stored_header.clear();
+        self.release_buffer();
+
+        let operation = self.operation.take();
+        self.client.map(move |cb| match operation {
+            Some(Operation::NextKey) => {
+                cb.next_key_complete(result, self.position.get(), key, value)
+            }
+            _ => cb.get_complete(result, key, value),
+        });
+    }
+
+    /// Replace the contents of `value` with the permissions header stored with
+    /// a record that couldn't be authenticated, if there is one.
+    fn return_stored_header(&self, value: &mut SubSliceMut<'static, u8>) {
+        value.as_slice().iter_mut().for_each(|m| *m = 0);
+        match self.stored_header.take() {
+            Some(header) if value.len() >= HEADER_LENGTH => {
+                value.as_slice()[..HEADER_LENGTH].copy_from_slice(&header);
+                value.slice(0..HEADER_LENGTH);
+            }
+            _ => value.slice(0..0),
+        }
+    }
+
+    /// Write the encrypted record to the underlying store.
+    fn store(&self, buf: &'static mut [u8]) {
+        let key = self.key.take().unwrap();
+        let key_length = key.len();
+        let record_length = RECORD_OVERHEAD + self.value_length.get();
+
+        // Remove the key, leaving `version | nonce | header | ciphertext | tag`.
+        buf.copy_within(
+            AAD_OFFSET + key_length..key_length + record_length,
+            AAD_OFFSET,
+        );
+
+        let mut record = SubSliceMut::new(buf);
+        record.slice(0..record_length);
+
+        let ret = match self.operation.get() {
+            Some(Operation::Add) => self.kv.add(key, record),
+            Some(Operation::Update) => self.kv.update(key, record),
+            _ => self.kv.set(key, record),
+        };
+
+        if let Err((key, record, e)) = ret {
+            self.crypt_buffer.replace(record.take());
+            self.insert_done(Err(e), key);
+        }
+    }
+
+    /// Return the caller's buffers at the end of a `set()`, `add()` or
+    /// `update()` operation.
+    fn insert_done(&self, result: Result<(), ErrorCode>, key: SubSliceMut<'static, u8>) {
+        self.release_buffer();
+
+        let operation = self.operation.take();
+        self.value.take().map(|value| {
+            self.client.map(move |cb| match operation {
+                Some(Operation::Add) => cb.add_complete(result, key, value),
+                Some(Operation::Update) => cb.update_complete(result, key, value),
+                _ => cb.set_complete(result, key, value),
+            });
+        });
+    }
+
+    /// Clear the plaintext out of the internal buffer.
+    fn release_buffer(&self) {
+        self.crypt_buffer.map(|buf| {
+            buf.iter_mut().for_each(|m| *m = 0);
+        });
+    }
+
+    fn crypt_complete(
+        &self,
+        buf: &'static mut [u8],
+        res: Result<(), ErrorCode>,
+        tag_is_valid: bool,
+    ) {
+        match self.operation.get() {
+            Some(Operation::Set) | Some(Operation::Add) | Some(Operation::Update) => {
+                if res.is_ok() {
+                    self.store(buf);
+                } else {
+                    self.crypt_buffer.replace(buf);
+                    let key = self.key.take().unwrap();
+                    self.insert_done(Err(ErrorCode::FAIL), key);
+                }
+            }
+            Some(Operation::Get) | Some(Operation::NextKey) => {
+                self.crypt_buffer.replace(buf);
+                self.decrypt_done(res.is_ok() && tag_is_valid);
+            }
+            _ => {
+                self.crypt_buffer.replace(buf);
+            }
+        }
+    }
+}
+
+impl<'a, K: kv::KV<'a>, A: AES128CCM<'a>, R: rng::Rng<'a>> kv::KV<'a>
+    for KVStoreEncryption<'a, K, A, R>
+{
+    fn set_client(&self, client: &'a dyn kv::KVClient) {
+        self.client.set(client);
+    }
+
+    fn get(
+        &self,
+        key: SubSliceMut<'static, u8>,
+        value: SubSliceMut<'static, u8>,
+    ) -> Result<
+        (),
+        (
+            SubSliceMut<'static, u8>,
+            SubSliceMut<'static, u8>,
+            ErrorCode,
+        ),
+    > {
+        if self.operation.is_some() {
+            return Err((key, value, ErrorCode::BUSY));
+        }
+
+        let buf = match self.crypt_buffer.take() {
+            Some(buf) => buf,
+            None => return Err((key, value, ErrorCode::FAIL)),
+        };
+
+        // Always read the full record into our buffer so it can be
+        // authenticated, even if the caller only wants part of the value.
+        match self.kv.get(key, SubSliceMut::new(buf)) {
+            Ok(()) => {
+                self.operation.set(Operation::Get);
+                self.value.replace(value);
+                Ok(())
+            }
+            Err((key, record, e)) => {
+                self.crypt_buffer.replace(record.take());
+                Err((key, value, e))
+            }
+        }
+    }
+
+    fn set(
+        &self,
+        key: SubSliceMut<'static, u8>,
+        value: SubSliceMut<'static, u8>,
+    ) -> Result<
+        (),
+        (
+            SubSliceMut<'static, u8>,
+            SubSliceMut<'static, u8>,
+            ErrorCode,
+        ),
+    > {
+        self.insert(key, value, Operation::Set)
+    }
+
+    fn add(
+        &self,
+        key: SubSliceMut<'static, u8>,
+        value: SubSliceMut<'static, u8>,
+    ) -> Result<
+        (),
+        (
+            SubSliceMut<'static, u8>,
+            SubSliceMut<'static, u8>,
+            ErrorCode,
+        ),
+    > {
+        self.insert(key, value, Operation::Add)
+    }
+
+    fn update(
+        &self,
+        key: SubSliceMut<'static, u8>,
+        value: SubSliceMut<'static, u8>,
+    ) -> Result<
+        (),
+        (
+            SubSliceMut<'static, u8>,
+            SubSliceMut<'static, u8>,
+            ErrorCode,
+        ),
+    > {
+        self.insert(key, value, Operation::Update)
+    }
+
+    fn delete(
+        &self,
+        key: SubSliceMut<'static, u8>,
+    ) -> Result<(), (SubSliceMut<'static, u8>, ErrorCode)> {
+        if self.operation.is_some() {
+            return Err((key, ErrorCode::BUSY));
+        }
+
+        match self.kv.delete(key) {
+            Ok(()) => {
+                self.operation.set(Operation::Delete);
+                Ok(())
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    fn next_key(
+        &self,
+        position: usize,
+        key: SubSliceMut<'static, u8>,
+        value: SubSliceMut<'static, u8>,
+    ) -> Result<
+        (),
+        (
+            SubSliceMut<'static, u8>,
+            SubSliceMut<'static, u8>,
+            ErrorCode,
+        ),
+    > {
+        if self.operation.is_some() {
+            return Err((key, value, ErrorCode::BUSY));
+        }
+
+        let buf = match self.crypt_buffer.take() {
+            Some(buf) => buf,
+            None => return Err((key, value, ErrorCode::FAIL)),
+        };
+
+        match self.kv.next_key(position, key, SubSliceMut::new(buf)) {
+            Ok(()) => {
+                self.operation.set(Operation::NextKey);
+                self.value.replace(value);
+                Ok(())
+            }
+            Err((key, record, e)) => {
+                self.crypt_buffer.replace(record.take());
+                Err((key, value, e))
+            }
+        }
+    }
+
+    fn begin_batch(&self) -> Result<(), ErrorCode> {
+        if self.operation.is_some() {
+            return Err(ErrorCode::BUSY);
+        }
+
+        self.kv.begin_batch()
+    }
+
+    fn commit_batch(&self) -> Result<(), ErrorCode> {
+        if self.operation.is_some() {
+            return Err(ErrorCode::BUSY);
+        }
+
+        self.kv.commit_batch()?;
+        self.operation.set(Operation::CommitBatch);
+        Ok(())
+    }
+
+    fn abort_batch(&self) -> Result<(), ErrorCode> {
+        if self.operation.is_some() {
+            return Err(ErrorCode::BUSY);
+        }
+
+        self.kv.abort_batch()?;
+        self.operation.set(Operation::AbortBatch);
+        Ok(())
+    }
+}
+
+impl<'a, K: kv::KV<'a>, A: AES128CCM<'a>, R: rng::Rng<'a>> kv::KVClient
+    for KVStoreEncryption<'a, K, A, R>
+{
+    fn get_complete(
+        &self,
+        result: Result<(), ErrorCode>,
+        key: SubSliceMut<'static, u8>,
+        record: SubSliceMut<'static, u8>,
+    ) {
+        match result {
+            Ok(()) => {
+                self.decrypt(key, record);
+            }
+            Err(e) => {
+                self.crypt_buffer.replace(record.take());
+                self.release_buffer();
+                self.operation.clear();
+
+                // A record that doesn't fit in our buffer can't be
+                // authenticated.
+                let e = if e == ErrorCode::NOSUPPORT {
+                    ErrorCode::NOSUPPORT
+                } else {
+                    ErrorCode::FAIL
+                };
+
+                self.value.take().map(|value| {
+                    self.client.map(move |cb| {
+                        cb.get_complete(Err(e), key, value);
+                    });
+                });
+            }
+        }
+    }
+
+    fn set_complete(
+        &self,
+        result: Result<(), ErrorCode>,
+        key: SubSliceMut<'static, u8>,
+        record: SubSliceMut<'static, u8>,
+    ) {
+        self.crypt_buffer.replace(record.take());
+        self.insert_done(result, key);
+    }
+
+    fn add_complete(
+        &self,
+        result: Result<(), ErrorCode>,
+        key: SubSliceMut<'static, u8>,
+        record: SubSliceMut<'static, u8>,
+    ) {
+        self.crypt_buffer.replace(record.take());
+        self.insert_done(result, key);
+    }
+
+    fn update_complete(
+        &self,
+        result: Result<(), ErrorCode>,
+        key: SubSliceMut<'static, u8>,
+        record: SubSliceMut<'static, u8>,
+    ) {
+        self.crypt_buffer.replace(record.take());
+        self.insert_done(result, key);
+    }
+
+    fn delete_complete(&self, result: Result<(), ErrorCode>, key: SubSliceMut<'static, u8>) {
+        self.operation.clear();
+        self.client.map(move |cb| {
+            cb.delete_complete(result, key);
+        });
+    }
+
+    fn next_key_complete(
+        &self,
+        result: Result<(), ErrorCode>,
+        position: usize,
+        key: SubSliceMut<'static, u8>,
+        mut record: SubSliceMut<'static, u8>,
+    ) {
+        self.position.set(position);
+
+        match result {
+            Ok(()) => {
+                self.decrypt(key, record);
+            }
+            Err(e) => {
+                // If the key or record didn't fit the record can't be
+                // authenticated, so it is skipped in the same way as a record
+                // that failed authentication.
+                let result = if e == ErrorCode::SIZE {
+                    self.stored_header.insert(stored_header(record.as_slice()));
+                    Err(ErrorCode::FAIL)
+                } else {
+                    Err(e)
+                };
+
+                self.crypt_buffer.replace(record.take());
+                self.release_buffer();
+                self.operation.clear();
+
+                self.value.take().map(|mut value| {
+                    self.return_stored_header(&mut value);
+                    self.client.map(move |cb| {
+                        cb.next_key_complete(result, position, key, value);
+                    });
+                });
+            }
+        }
+    }
+
+    fn commit_batch_complete(&self, result: Result<(), ErrorCode>) {
+        self.operation.clear();
+        self.client.map(move |cb| {
+            cb.commit_batch_complete(result);
+        });
+    }
+
+    fn abort_batch_complete(&self, result: Result<(), ErrorCode>) {
+        self.operation.clear();
+        self.client.map(move |cb| {
+            cb.abort_batch_complete(result);
+        });
+    }
+}
+
+impl<'a, K: kv::KV<'a>, A: AES128CCM<'a>, R: rng::Rng<'a>> rng::Client
+    for KVStoreEncryption<'a, K, A, R>
+{
+    fn randomness_available(
+        &self,
+        randomness: &mut dyn Iterator<Item = u32>,
+        error: Result<(), ErrorCode>,
+    ) -> rng::Continue {
+        let buf = match self.crypt_buffer.take() {
+            Some(buf) => buf,
+            None => return rng::Continue::Done,
+        };
+
+        if error.is_ok() {
+            while self.nonce_length.get() < NONCE_LENGTH {
+                match randomness.next() {
+                    Some(r) => {
+                        let offset = self.nonce_length.get();
+                        let length = (NONCE_LENGTH - offset).min(4);
+                        buf[NONCE_OFFSET + offset..NONCE_OFFSET + offset + length]
+                            .copy_from_slice(&r.to_le_bytes()[..length]);
+                        self.nonce_length.set(offset + length);
+                    }
+                    None => {
+                        self.crypt_buffer.replace(buf);
+                        return rng::Continue::More;
+                    }
+                }
+            }
+        }
+
+        let key_length = self.key.map_or(0, |key| key.len());
+        let message_offset = AAD_OFFSET + key_length + HEADER_LENGTH;
+        let message_length = self.value_length.get() - HEADER_LENGTH;
+
+        let ret = if error.is_ok() {
+            self.crypt(buf, message_offset, message_length, true)
+        } else {
+            Err((ErrorCode::FAIL, buf))
+        };
+
+        if let Err((e, buf)) = ret {
+            self.crypt_buffer.replace(buf);
+            self.key.take().map(|key| {
+                self.insert_done(Err(e), key);
+            });
+        }
+
+        rng::Continue::Done
+    }
+}
+
+impl<'a, K: kv::KV<'a>, A: AES128CCM<'a>, R: rng::Rng<'a>> CCMClient
+    for KVStoreEncryption<'a, K, A, R>
+{
+    fn crypt_done(&self, buf: &'static mut [u8], res: Result<(), ErrorCode>, tag_is_valid: bool) {
+        self.crypt_complete(buf, res, tag_is_valid);
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    extern crate std;
+
+    use super::*;
+    use core::cell::RefCell;
+    use kernel::hil::kv::KV;
+    use std::boxed::Box;
+    use std::vec::Vec;
+
+    /// A K-V store that keeps records in memory and completes operations
+    /// when `complete()` is called.
+    struct FakeKV {
+        records: RefCell<Vec<(Vec<u8>, Vec<u8>)>>,
+        pending: MapCell<(bool, SubSliceMut<'static, u8>, SubSliceMut<'static, u8>)>,
+        client: OptionalCell<&'static dyn kv::KVClient>,
+    }
+
+    impl FakeKV {
+        fn new() -> Self {
+            Self {
+                records: RefCell::new(Vec::new()),
+                pending: MapCell::empty(),
+                client: OptionalCell::empty(),
+            }
+        }
+
+        fn record(&self, key: &[u8]) -> Option<Vec<u8>> {
+            self.records
+                .borrow()
+                .iter()
+                .find(|(k, _)| k == key)
+                .map(|(_, v)| v.clone())
+        }
+
+        fn insert_record(&self, key: &[u8], record: &[u8]) {
+            let mut records = self.records.borrow_mut();
+            records.retain(|(k, _)| k != key);
+            records.push((key.to_vec(), record.to_vec()));
+        }
+
+        fn start(
+            &self,
+            write: bool,
+            key: SubSliceMut<'static, u8>,
+            value: SubSliceMut<'static, u8>,
+        ) -> Result<
+            (),
+            (
+                SubSliceMut<'static, u8>,
+                SubSliceMut<'static, u8>,
+                ErrorCode,
+            ),
+        > {
+            self.pending.replace((write, key, value));
+            Ok(())
+        }
+
+        fn complete(&self) {
+            let (write, mut key, mut value) = self.pending.take().unwrap();
+            if write {
+                self.insert_record(key.as_slice(), value.as_slice());
+                self.client
+                    .map(move |cb| cb.set_complete(Ok(()), key, value));
+            } else {
+                let result = match self.record(key.as_slice()) {
+                    Some(record) => {
+                        value.as_slice()[..record.len()].copy_from_slice(&record);
+                        value.slice(0..record.len());
+                        Ok(())
+                    }
+                    None => Err(ErrorCode::NOSUPPORT),
+                };
+                self.client
+                    .map(move |cb| cb.get_complete(result, key, value));
+            }
+        }
+    }
+
+    impl kv::KV<'static> for FakeKV {
+        fn set_client(&self, client: &'static dyn kv::KVClient) {
+            self.client.set(client);
+        }
+
+        fn get(
+            &self,
+            key: SubSliceMut<'static, u8>,
+            value: SubSliceMut<'static, u8>,
+        ) -> Result<
+            (),
+            (
+                SubSliceMut<'static, u8>,
+                SubSliceMut<'static, u8>,
+                ErrorCode,
+            ),
+        > {
+            self.start(false, key, value)
+        }
+
+        fn set(
+            &self,
+            key: SubSliceMut<'static, u8>,
+            value: SubSliceMut<'static, u8>,
+        ) -> Result<
+            (),
+            (
+                SubSliceMut<'static, u8>,
+                SubSliceMut<'static, u8>,
+                ErrorCode,
+            ),
+        > {
+            self.start(true, key, value)
+        }
+
+        fn add(
+            &self,
+            key: SubSliceMut<'static, u8>,
+            value: SubSliceMut<'static, u8>,
+        ) -> Result<
+            (),
+            (
+                SubSliceMut<'static, u8>,
+                SubSliceMut<'static, u8>,
+                ErrorCode,
+            ),
+        > {
+            self.start(true, key, value)
+        }
+
+        fn update(
+            &self,
+            key: SubSliceMut<'static, u8>,
+            value: SubSliceMut<'static, u8>,
+        ) -> Result<
+            (),
+            (
+                SubSliceMut<'static, u8>,
+                SubSliceMut<'static, u8>,
+                ErrorCode,
+            ),
+        > {
+            self.start(true, key, value)
+        }
+
+        fn delete(
+            &self,
+            key: SubSliceMut<'static, u8>,
+        ) -> Result<(), (SubSliceMut<'static, u8>, ErrorCode)> {
+            Err((key, ErrorCode::NOSUPPORT))
+        }
+    }
+
+    /// A stand in for AES-CCM. The message is XOR-ed with a key stream and the
+    /// tag mixes the nonce, associated data and ciphertext, which is enough to
+    /// check that records are protected and authenticated.
+    pub(crate) struct FakeCCM {
+        key: RefCell<Vec<u8>>,
+        nonce: RefCell<Vec<u8>>,
+        pending: MapCell<(&'static mut [u8], usize, usize, usize, bool)>,
+        client: OptionalCell<&'static dyn CCMClient>,
+    }
+
+    impl FakeCCM {
+        pub(crate) fn new() -> Self {
+            Self {
+                key: RefCell::new(Vec::new()),
+                nonce: RefCell::new(Vec::new()),
+                pending: MapCell::empty(),
+                client: OptionalCell::empty(),
+            }
+        }
+
+        fn tag(&self, data: &[u8]) -> [u8; TAG_LENGTH] {
+            let mut tag = [0u8; TAG_LENGTH];
+            let nonce = self.nonce.borrow();
+            for (i, b) in nonce.iter().chain(data.iter()).enumerate() {
+                tag[i % TAG_LENGTH] = tag[i % TAG_LENGTH].rotate_left(3) ^ b ^ (i as u8);
+            }
+            tag
+        }
+
+        fn apply_key_stream(&self, message: &mut [u8]) {
+            let key = self.key.borrow();
+            let nonce = self.nonce.borrow();
+            for (i, b) in message.iter_mut().enumerate() {
+                *b ^= key[i % key.len()] ^ nonce[i % nonce.len()] ^ (i as u8);
+            }
+        }
+
+        /// Complete the pending operation, returns `false` if there was none.
+        pub(crate) fn complete(&self) -> bool {
+            let (buf, a_off, m_off, m_len, encrypting) = match self.pending.take() {
+                Some(pending) => pending,
+                None => return false,
+            };
+            let tag_offset = m_off + m_len;
+
+            let valid = if encrypting {
+                self.apply_key_stream(&mut buf[m_off..tag_offset]);
+                let tag = self.tag(&buf[a_off..tag_offset]);
+                buf[tag_offset..tag_offset + TAG_LENGTH].copy_from_slice(&tag);
+                true
+            } else {
+                let valid =
+                    self.tag(&buf[a_off..tag_offset]) == buf[tag_offset..tag_offset + TAG_LENGTH];
+                self.apply_key_stream(&mut buf[m_off..tag_offset]);
+                valid
+            };
+
+            self.client.map(move |cb| cb.crypt_done(buf, Ok(()), valid));
+            true
+        }
+    }
+
+    impl AES128CCM<'static> for FakeCCM {
+        fn set_client(&'static self, client: &'static dyn CCMClient) {
+            self.client.set(client);
+        }
+
+        fn set_key(&self, key: &[u8]) -> Result<(), ErrorCode> {
+            *self.key.borrow_mut() = key.to_vec();
+            Ok(())
+        }
+
+        fn set_nonce(&self, nonce: &[u8]) -> Result<(), ErrorCode> {
+            *self.nonce.borrow_mut() = nonce.to_vec();
+            Ok(())
+        }
+
+        fn crypt(
+            &self,
+            buf: &'static mut [u8],
+            a_off: usize,
+            m_off: usize,
+            m_len: usize,
+            mic_len: usize,
+            confidential: bool,
+            encrypting: bool,
+        ) -> Result<(), (ErrorCode, &'static mut [u8])> {
+            assert_eq!(mic_len, TAG_LENGTH);
+            assert!(confidential);
+            self.pending.replace((buf, a_off, m_off, m_len, encrypting));
+            Ok(())
+        }
+    }
+
+    /// An RNG that returns a counter when `complete()` is called.
+    pub(crate) struct FakeRng {
+        counter: Cell<u32>,
+        client: OptionalCell<&'static dyn rng::Client>,
+    }
+
+    impl rng::Rng<'static> for FakeRng {
+        fn get(&self) -> Result<(), ErrorCode> {
+            Ok(())
+        }
+
+        fn cancel(&self) -> Result<(), ErrorCode> {
+            Ok(())
+        }
+
+        fn set_client(&'static self, client: &'static dyn rng::Client) {
+            self.client.set(client);
+        }
+    }
+
+    impl FakeRng {
+        pub(crate) fn new() -> Self {
+            Self {
+                counter: Cell::new(1),
+                client: OptionalCell::empty(),
+            }
+        }
+
+        pub(crate) fn complete(&self) {
+            let start = self.counter.get();
+            self.counter.set(start + 4);
+            self.client.map(|cb| {
+                cb.randomness_available(&mut (start..start + 4), Ok(()));
+            });
+        }
+    }
+
+    /// Records the result of the last operation.
+    struct Client {
+        result: Cell<Option<Result<(), ErrorCode>>>,
+        value: RefCell<Vec<u8>>,
+    }
+
+    impl kv::KVClient for Client {
+        fn get_complete(
+            &self,
+            result: Result<(), ErrorCode>,
+            _key: SubSliceMut<'static, u8>,
+            mut value: SubSliceMut<'static, u8>,
+        ) {
+            self.result.set(Some(result));
+            *self.value.borrow_mut() = value.as_slice().to_vec();
+        }
+
+        fn set_complete(
+            &self,
+            result: Result<(), ErrorCode>,
+            _key: SubSliceMut<'static, u8>,
+            _value: SubSliceMut<'static, u8>,
+        ) {
+            self.result.set(Some(result));
+        }
+
+        fn add_complete(
+            &self,
+            result: Result<(), ErrorCode>,
+            _key: SubSliceMut<'static, u8>,
+            _value: SubSliceMut<'static, u8>,
+        ) {
+            self.result.set(Some(result));
+        }
+
+        fn update_complete(
+            &self,
+            result: Result<(), ErrorCode>,
+            _key: SubSliceMut<'static, u8>,
+            _value: SubSliceMut<'static, u8>,
+        ) {
+            self.result.set(Some(result));
+        }
+
+        fn delete_complete(&self, result: Result<(), ErrorCode>, _key: SubSliceMut<'static, u8>) {
+            self.result.set(Some(result));
+        }
+    }
+
+    type Store = KVStoreEncryption<'static, FakeKV, FakeCCM, FakeRng>;
+
+    struct Test {
+        store: &'static Store,
+        kv: &'static FakeKV,
+        ccm: &'static FakeCCM,
+        rng: &'static FakeRng,
+        client: &'static Client,
+    }
+
+    pub(crate) const DEVICE_KEY: [u8; AES128_KEY_SIZE] = [0x42; AES128_KEY_SIZE];
+
+    fn buffer(contents: &[u8]) -> SubSliceMut<'static, u8> {
+        SubSliceMut::new(Box::leak(contents.to_vec().into_boxed_slice()))
+    }
+
+    /// A value with the permissions header in front of `data`.
+    fn value_with_header(data: &[u8]) -> Vec<u8> {
+        let mut value = Vec::from([HEADER_VERSION]);
+        value.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        value.extend_from_slice(&7u32.to_le_bytes());
+        value.extend_from_slice(data);
+        value
+    }
+
+    fn setup(ccm_buffer: usize) -> Test {
+        let kv = Box::leak(Box::new(FakeKV::new()));
+        let ccm = Box::leak(Box::new(FakeCCM::new()));
+        let rng = Box::leak(Box::new(FakeRng::new()));
+        let client = Box::leak(Box::new(Client {
+            result: Cell::new(None),
+            value: RefCell::new(Vec::new()),
+        }));
+        let crypt_buffer = Box::leak(Box::new([0; 256]));
+        let store: &'static Store = Box::leak(Box::new(KVStoreEncryption::new(
+            kv,
+            ccm,
+            rng,
+            DEVICE_KEY,
+            crypt_buffer,
+            ccm_buffer,
+        )));
+
+        kv.set_client(store);
+        ccm.set_client(store);
+        rng::Rng::set_client(rng, store);
+        store.set_client(client);
+
+        Test {
+            store,
+            kv,
+            ccm,
+            rng,
+            client,
+        }
+    }
+
+    impl Test {
+        fn set(&self, key: &[u8], data: &[u8]) -> Result<(), ErrorCode> {
+            self.client.result.set(None);
+            self.store
+                .set(buffer(key), buffer(&value_with_header(data)))
+                .map_err(|(_, _, e)| e)?;
+            self.rng.complete();
+            self.ccm.complete();
+            self.kv.complete();
+            self.client.result.get().unwrap()
+        }
+
+        fn get(&self, key: &[u8]) -> (Result<(), ErrorCode>, Vec<u8>) {
+            self.client.result.set(None);
+            if let Err((_, _, e)) = self.store.get(buffer(key), buffer(&[0xAA; 64])) {
+                return (Err(e), Vec::new());
+            }
+            self.kv.complete();
+            self.ccm.complete();
+            (
+                self.client.result.get().unwrap(),
+                self.client.value.borrow().clone(),
+            )
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let test = setup(128);
+
+        assert_eq!(test.set(b"key", b"secret value"), Ok(()));
+
+        let record = test.kv.record(b"key").unwrap();
+        assert_eq!(record[0], RECORD_VERSION);
+        assert_eq!(
+            record.len(),
+            RECORD_OVERHEAD + HEADER_LENGTH + b"secret value".len()
+        );
+        assert!(!record.windows(6).any(|w| w == b"secret"));
+
+        let (result, value) = test.get(b"key");
+        assert_eq!(result, Ok(()));
+        assert_eq!(value, value_with_header(b"secret value"));
+    }
+
+    #[test]
+    fn tampered_record_is_rejected() {
+        let test = setup(128);
+        assert_eq!(test.set(b"key", b"secret value"), Ok(()));
+
+        let mut record = test.kv.record(b"key").unwrap();
+        let last = record.len() - TAG_LENGTH - 1;
+        record[last] ^= 1;
+        test.kv.insert_record(b"key", &record);
+
+        let (result, value) = test.get(b"key");
+        assert_eq!(result, Err(ErrorCode::FAIL));
+        assert!(value.iter().all(|b| *b == 0));
+    }
+
+    #[test]
+    fn record_copied_to_another_key_is_rejected() {
+        let test = setup(128);
+        assert_eq!(test.set(b"key", b"secret value"), Ok(()));
+
+        let record = test.kv.record(b"key").unwrap();
+        test.kv.insert_record(b"other", &record);
+
+        assert_eq!(test.get(b"other").0, Err(ErrorCode::FAIL));
+    }
+
+    #[test]
+    fn value_longer_than_ccm_buffer_is_rejected() {
+        let data = [0x11; 40];
+        let test = setup(ccm_buffer_length(3, data.len()) - 1);
+
+        assert_eq!(test.set(b"key", &data), Err(ErrorCode::SIZE));
+        assert_eq!(test.set(b"key", &data[..32]), Ok(()));
+    }
+
+    #[test]
+    fn ccm_buffer_length_matches_virtual_aes_ccm() {
+        // B_0, the length of the associated data, an 8 byte key and the
+        // header fill three blocks, and the message two more.
+        assert_eq!(ccm_buffer_length(8, 32), 5 * AES128_BLOCK_SIZE);
+        assert_eq!(ccm_buffer_length(8, 33), 6 * AES128_BLOCK_SIZE);
+        assert_eq!(ccm_buffer_length(0, 0), 2 * AES128_BLOCK_SIZE);
+    }
+
+    #[test]
+    fn plaintext_records_are_only_accepted_when_enabled() {
+        let test = setup(128);
+        let plaintext = value_with_header(b"old value");
+        test.kv.insert_record(b"key", &plaintext);
+
+        assert_eq!(test.get(b"key").0, Err(ErrorCode::FAIL));
+
+        test.store.accept_plaintext_records(true);
+        let (result, value) = test.get(b"key");
+        assert_eq!(result, Ok(()));
+        assert_eq!(value, plaintext);
+
+        // Writing the value again encrypts it.
+        assert_eq!(test.set(b"key", b"old value"), Ok(()));
+        assert_eq!(test.kv.record(b"key").unwrap()[0], RECORD_VERSION);
+        test.store.accept_plaintext_records(false);
+        assert_eq!(test.get(b"key"), (Ok(()), plaintext));
+    }
+}
@@ -25,10 +25,42 @@
 //!
 //!    hil::flash
 //! ```
+//!
+//! Storage Quotas
+//! --------------
+//!
+//! If the `StoragePermissions` used to store an object include a
+//! `StorageQuota`, the number of objects and bytes of values owned by the
+//! write ID are limited to that quota. Operations that would exceed the quota
+//! fail with `NOMEM`.
+//!
+//! Usage is tracked per write ID in a fixed size table. The existing usage is
+//! calculated by iterating over the store the first time it is needed. If the
+//! table is full, objects written by additional write IDs are not tracked and
+//! writers with a quota that can't be tracked are not able to store objects.
+//!
+//! Every object returned while iterating is counted against the write ID in
+//! its header, including objects that were stored without their key. Objects
+//! whose owner can't be determined, because their header is missing or can't
+//! be authenticated, are counted against every quota instead. Objects that
+//! can't be read at all are skipped, as long as the iteration can continue
+//! past them. If the iteration fails it is retried, and if it keeps failing the store is
+//! rejected with the error. If the underlying store can't be iterated over at
+//! all, usage can't be known, so writers with a quota are not able to store
+//! objects while writers without a quota are unaffected.
+//!
+//! Batches
+//! -------
+//!
+//! A batch started with `begin_batch()` belongs to the write ID of the caller.
+//! Until it is committed or aborted, `set()`, `add()`, `update()` and
+//! `delete()` from other write IDs fail with `BUSY`, so their objects aren't
+//! staged in a batch they don't control.
 
+use core::cell::Cell;
 use core::mem;
 use kernel::hil::kv;
-use kernel::storage_permissions::StoragePermissions;
+use kernel::storage_permissions::{StoragePermissions, StorageQuota};
 use kernel::utilities::cells::{MapCell, OptionalCell, TakeCell};
 use kernel::utilities::leasable_buffer::SubSliceMut;
 use kernel::ErrorCode;
@@ -40,12 +72,59 @@ enum Operation {
     Add,
     Update,
     Delete,
+    NextKey,
+    CommitBatch,
+    AbortBatch,
+    /// Counting the existing objects to calculate usage.
+    Scan,
+}
+
+/// Whether the usage table reflects the contents of the store.
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum UsageState {
+    Unknown,
+    Scanning,
+    Known,
+    /// The store can't be iterated over, so usage can't be calculated.
+    Unavailable,
+}
+
+/// Storage used by objects written with a single write ID.
+#[derive(Clone, Copy)]
+pub struct UsageEntry {
+    write_id: u32,
+    usage: kv::StorageUsage,
+}
+
+/// The change in usage to apply once the current operation succeeds.
+#[derive(Clone, Copy)]
+struct UsageChange {
+    /// The write ID and length of the object being replaced or deleted.
+    removed: Option<(u32, usize)>,
+    /// The write ID and length of the object being stored.
+    added: Option<(u32, usize)>,
 }
 
 /// Current version of the Tock K-V header.
-const HEADER_VERSION: u8 = 0;
+pub const HEADER_VERSION: u8 = 0;
 pub const HEADER_LENGTH: usize = mem::size_of::<KeyHeader>();
 
+/// Length of the key buffer used when calculating usage.
+pub const SCAN_KEY_LENGTH: usize = 64;
+
+/// Default number of write IDs that usage is tracked for.
+pub const USAGE_TABLE_LENGTH: usize = 8;
+
+/// Number of times calculating usage is attempted before an insert waiting for
+/// it fails.
+const MAX_SCAN_ATTEMPTS: u8 = 3;
+
+/// Whether the object returned by `next_key()` couldn't be read, but the
+/// iteration can continue past it.
+fn skippable(result: Result<(), ErrorCode>, position: usize) -> bool {
+    result == Err(ErrorCode::FAIL) && position != 0
+}
+
 /// This is the header used for KV stores.
 #[repr(packed)]
 struct KeyHeader {
@@ -85,12 +164,33 @@ pub struct KVStorePermissions<'a, K: kv::KV<'a>> {
 
     value: MapCell<SubSliceMut<'static, u8>>,
     valid_ids: OptionalCell<StoragePermissions>,
+
+    /// The key of an insert that is waiting for usage to be calculated.
+    key: MapCell<SubSliceMut<'static, u8>>,
+    /// The insert operation waiting for usage to be calculated.
+    deferred: OptionalCell<Operation>,
+    /// Buffer for the keys returned while calculating usage.
+    scan_key: TakeCell<'static, [u8]>,
+    usage: TakeCell<'static, [Option<UsageEntry>]>,
+    /// Storage used by objects whose owner isn't known, which counts against
+    /// every quota.
+    unknown_usage: Cell<kv::StorageUsage>,
+    usage_state: Cell<UsageState>,
+    usage_change: OptionalCell<UsageChange>,
+    /// How many times calculating usage has been attempted for the pending
+    /// insert.
+    scan_attempts: Cell<u8>,
+
+    /// The write ID that started the batch in progress.
+    batch_owner: OptionalCell<u32>,
 }
 
 impl<'a, K: kv::KV<'a>> KVStorePermissions<'a, K> {
     pub fn new(
         kv: &'a K,
         header_value: &'static mut [u8; HEADER_LENGTH],
+        scan_key: &'static mut [u8],
+        usage: &'static mut [Option<UsageEntry>],
     ) -> KVStorePermissions<'a, K> {
         Self {
             kv,
@@ -99,9 +199,306 @@ impl<'a, K: kv::KV<'a>> KVStorePermissions<'a, K> {
             operation: OptionalCell::empty(),
             value: MapCell::empty(),
             valid_ids: OptionalCell::empty(),
+            key: MapCell::empty(),
+            deferred: OptionalCell::empty(),
+            scan_key: TakeCell::new(scan_key),
+            usage: TakeCell::new(usage),
+            unknown_usage: Cell::new(kv::StorageUsage::default()),
+            usage_state: Cell::new(UsageState::Unknown),
+            usage_change: OptionalCell::empty(),
+            scan_attempts: Cell::new(0),
+            batch_owner: OptionalCell::empty(),
+        }
+    }
+
+    /// Return the usage of `write_id`, or `None` if it isn't tracked and
+    /// there is no room to track it or usage can't be calculated.
+    fn usage_of(&self, write_id: u32) -> Option<kv::StorageUsage> {
+        if self.usage_state.get() == UsageState::Unavailable {
+            return None;
+        }
+
+        self.usage.map_or(None, |table| {
+            table
+                .iter()
+                .flatten()
+                .find(|entry| entry.write_id == write_id)
+                .map(|entry| entry.usage)
+                .or_else(|| {
+                    table
+                        .iter()
+                        .any(|entry| entry.is_none())
+                        .then(kv::StorageUsage::default)
+                })
+        })
+    }
+
+    /// Add or remove an object of `length` bytes owned by `write_id` from the
+    /// usage table.
+    fn account(&self, write_id: u32, length: usize, add: bool) {
+        self.usage.map(|table| {
+            let index = table
+                .iter()
+                .position(|entry| entry.is_some_and(|entry| entry.write_id == write_id))
+                .or_else(|| table.iter().position(|entry| entry.is_none()));
+
+            if let Some(entry) = index.and_then(|i| table.get_mut(i)) {
+                let entry = entry.get_or_insert(UsageEntry {
+                    write_id,
+                    usage: kv::StorageUsage::default(),
+                });
+
+                if add {
+                    entry.usage.bytes += length;
+                    entry.usage.objects += 1;
+                } else {
+                    entry.usage.bytes = entry.usage.bytes.saturating_sub(length);
+                    entry.usage.objects = entry.usage.objects.saturating_sub(1);
+                }
+            }
+        });
+    }
+
+    /// Check that storing an object of `length` bytes, replacing the
+    /// `removed` object, keeps `write_id` within `quota`.
+    fn check_quota(
+        &self,
+        quota: Option<StorageQuota>,
+        write_id: u32,
+        removed: Option<(u32, usize)>,
+        length: usize,
+    ) -> Result<(), ErrorCode> {
+        let quota = match quota {
+            Some(quota) => quota,
+            None => return Ok(()),
+        };
+
+        let mut usage = self.usage_of(write_id).ok_or(ErrorCode::NOMEM)?;
+        if let Some((owner, removed_length)) = removed {
+            if owner == write_id {
+                usage.bytes = usage.bytes.saturating_sub(removed_length);
+                usage.objects = usage.objects.saturating_sub(1);
+            }
+        }
+
+        let unknown = self.unknown_usage.get();
+        usage.bytes += unknown.bytes;
+        usage.objects += unknown.objects;
+
+        if usage.bytes + length > quota.bytes || usage.objects + 1 > quota.objects {
+            Err(ErrorCode::NOMEM)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Check that storing `value`, replacing the `removed` object, is within
+    /// the caller's quota and record the change in usage to apply once the
+    /// object has been stored.
+    fn start_usage_change(
+        &self,
+        removed: Option<(u32, usize)>,
+        value: &mut SubSliceMut<'static, u8>,
+    ) -> Result<(), ErrorCode> {
+        let header = KeyHeader::new_from_buf(value.as_slice());
+        let added = (header.write_id, header.length as usize);
+
+        let quota = self.valid_ids.get().and_then(|perms| perms.get_quota());
+        self.check_quota(quota, added.0, removed, added.1)?;
+
+        self.usage_change.set(UsageChange {
+            removed,
+            added: Some(added),
+        });
+        Ok(())
+    }
+
+    /// Update the usage table once an operation has completed.
+    fn complete_usage_change(&self, result: Result<(), ErrorCode>) {
+        if let Some(change) = self.usage_change.take() {
+            if result.is_ok() && self.usage_state.get() == UsageState::Known {
+                if let Some((write_id, length)) = change.removed {
+                    self.account(write_id, length, false);
+                }
+                if let Some((write_id, length)) = change.added {
+                    self.account(write_id, length, true);
+                }
+            }
+        }
+    }
+
+    /// Recalculate usage before the next insert, unless it can't be
+    /// calculated at all.
+    fn invalidate_usage(&self) {
+        if self.usage_state.get() != UsageState::Unavailable {
+            self.usage_state.set(UsageState::Unknown);
+        }
+    }
+
+    /// Check that a caller with `write_id` can modify the store, which isn't
+    /// the case while another caller's batch is in progress.
+    fn check_batch_owner(&self, write_id: Option<u32>) -> Result<(), ErrorCode> {
+        match self.batch_owner.get() {
+            Some(owner) if Some(owner) != write_id => Err(ErrorCode::BUSY),
+            _ => Ok(()),
+        }
+    }
+
+    /// Start iterating over the store to calculate the existing usage.
+    ///
+    /// Returns `NOSUPPORT` if the store can't be iterated over, in which case
+    /// usage is unavailable and only writers without a quota can store
+    /// objects.
+    fn start_scan(&self) -> Result<(), ErrorCode> {
+        let key = self.scan_key.take().ok_or(ErrorCode::FAIL)?;
+        let value = match self.header_value.take() {
+            Some(value) => value,
+            None => {
+                self.scan_key.replace(key);
+                return Err(ErrorCode::FAIL);
+            }
+        };
+
+        self.usage
+            .map(|table| table.iter_mut().for_each(|entry| *entry = None));
+        self.unknown_usage.set(kv::StorageUsage::default());
+
+        match self
+            .kv
+            .next_key(0, SubSliceMut::new(key), SubSliceMut::new(value))
+        {
+            Ok(()) => {
+                self.usage_state.set(UsageState::Scanning);
+                Ok(())
+            }
+            Err((key, value, e)) => {
+                self.scan_key.replace(key.take());
+                self.header_value.replace(value.take());
+                if e == ErrorCode::NOSUPPORT {
+                    self.usage_state.set(UsageState::Unavailable);
+                }
+                Err(e)
+            }
+        }
+    }
+
+    /// Count the object returned while calculating usage and move on to the
+    /// next one.
+    ///
+    /// An object that couldn't be read, but can be skipped, is charged to the
+    /// unknown owner using the length in its header, if there is one.
+    fn scan_next(
+        &self,
+        result: Result<(), ErrorCode>,
+        position: usize,
+        mut key: SubSliceMut<'static, u8>,
+        mut value: SubSliceMut<'static, u8>,
+    ) {
+        let readable = result.is_ok() || result.err() == Some(ErrorCode::SIZE);
+
+        if readable || skippable(result, position) {
+            let header = (value.len() >= HEADER_LENGTH)
+                .then(|| KeyHeader::new_from_buf(value.as_slice()))
+                .filter(|header| header.version == HEADER_VERSION);
+
+            match header {
+                Some(header) if readable => {
+                    self.account(header.write_id, header.length as usize, true)
+                }
+                _ => {
+                    let mut unknown = self.unknown_usage.get();
+                    unknown.bytes += header.map_or(0, |header| header.length as usize);
+                    unknown.objects += 1;
+                    self.unknown_usage.set(unknown);
+                }
+            }
+
+            key.reset();
+            value.reset();
+            match self.kv.next_key(position, key, value) {
+                Ok(()) => {}
+                Err((key, value, e)) => {
+                    self.scan_key.replace(key.take());
+                    self.header_value.replace(value.take());
+                    self.scan_done(Err(e));
+                }
+            }
+        } else {
+            self.scan_key.replace(key.take());
+            self.header_value.replace(value.take());
+
+            // `NOSUPPORT` means there are no more objects.
+            if result.err() == Some(ErrorCode::NOSUPPORT) {
+                self.scan_done(Ok(()));
+            } else {
+                self.scan_done(result);
+            }
+        }
+    }
+
+    /// Finish calculating usage and start any insert that was waiting for it.
+    ///
+    /// If calculating usage failed it is retried, an insert waiting for it
+    /// only fails once `MAX_SCAN_ATTEMPTS` have failed.
+    fn scan_done(&self, mut result: Result<(), ErrorCode>) {
+        self.operation.clear();
+        self.usage_state.set(if result.is_ok() {
+            UsageState::Known
+        } else {
+            UsageState::Unknown
+        });
+
+        self.scan_attempts.set(self.scan_attempts.get() + 1);
+        while result.is_err() && self.scan_attempts.get() < MAX_SCAN_ATTEMPTS {
+            self.operation.set(Operation::Scan);
+            match self.start_scan() {
+                Ok(()) => return,
+                Err(ErrorCode::NOSUPPORT) => {
+                    self.operation.clear();
+                    result = Ok(());
+                }
+                Err(e) => {
+                    self.operation.clear();
+                    self.scan_attempts.set(self.scan_attempts.get() + 1);
+                    result = Err(e);
+                }
+            }
+        }
+        self.scan_attempts.set(0);
+
+        if let Some(operation) = self.deferred.take() {
+            if let (Some(key), Some(value)) = (self.key.take(), self.value.take()) {
+                let ret = match result {
+                    Ok(()) => {
+                        self.operation.set(operation);
+                        self.start_insert(key, value)
+                    }
+                    Err(e) => Err((key, value, e)),
+                };
+
+                if let Err((key, value, e)) = ret {
+                    self.operation.clear();
+                    self.insert_complete(operation, Err(e), key, value);
+                }
+            }
         }
     }
 
+    /// Issue the callback for a `set()`, `add()` or `update()`.
+    fn insert_complete(
+        &self,
+        operation: Operation,
+        result: Result<(), ErrorCode>,
+        key: SubSliceMut<'static, u8>,
+        value: SubSliceMut<'static, u8>,
+    ) {
+        self.client.map(move |cb| match operation {
+            Operation::Add => cb.add_complete(result, key, value),
+            Operation::Update => cb.update_complete(result, key, value),
+            _ => cb.set_complete(result, key, value),
+        });
+    }
+
     fn insert(
         &self,
         key: SubSliceMut<'static, u8>,
@@ -125,6 +522,10 @@ impl<'a, K: kv::KV<'a>> KVStorePermissions<'a, K> {
             return Err((key, value, ErrorCode::BUSY));
         }
 
+        if let Err(e) = self.check_batch_owner(Some(write_id)) {
+            return Err((key, value, e));
+        }
+
         // The caller must ensure there is space for the header.
         if value.len() < HEADER_LENGTH {
             return Err((key, value, ErrorCode::SIZE));
@@ -140,12 +541,45 @@ impl<'a, K: kv::KV<'a>> KVStorePermissions<'a, K> {
         // Copy in the header to the buffer.
         header.copy_to_buf(value.as_slice());
 
-        self.operation.set(operation);
+        self.valid_ids.set(permissions);
 
-        match operation {
-            Operation::Set | Operation::Update => {
-                self.valid_ids.set(permissions);
+        if self.usage_state.get() == UsageState::Unknown {
+            // We need to know the existing usage before we can store anything.
+            self.operation.set(Operation::Scan);
+            match self.start_scan() {
+                Ok(()) => {
+                    self.deferred.set(operation);
+                    self.key.replace(key);
+                    self.value.replace(value);
+                    return Ok(());
+                }
+                Err(ErrorCode::NOSUPPORT) => {}
+                Err(e) => {
+                    self.operation.clear();
+                    return Err((key, value, e));
+                }
+            }
+        }
+
+        self.operation.set(operation);
+        self.start_insert(key, value)
+    }
 
+    /// Start the `set()`, `add()` or `update()` in `self.operation`.
+    fn start_insert(
+        &self,
+        key: SubSliceMut<'static, u8>,
+        mut value: SubSliceMut<'static, u8>,
+    ) -> Result<
+        (),
+        (
+            SubSliceMut<'static, u8>,
+            SubSliceMut<'static, u8>,
+            ErrorCode,
+        ),
+    > {
+        match self.operation.get() {
+            Some(Operation::Set) | Some(Operation::Update) => {
                 // We first read the key to see if we are allowed to overwrite it.
                 match self.header_value.take() {
                     Some(header_value) => match self.kv.get(key, SubSliceMut::new(header_value)) {
@@ -163,14 +597,20 @@ impl<'a, K: kv::KV<'a>> KVStorePermissions<'a, K> {
                 }
             }
 
-            Operation::Add => {
+            Some(Operation::Add) => {
                 // Since add will only succeed if the key is not already there,
                 // we do not have to worry about overwriting and do not need to
                 // check permissions.
+                if let Err(e) = self.start_usage_change(None, &mut value) {
+                    self.operation.clear();
+                    return Err((key, value, e));
+                }
+
                 match self.kv.add(key, value) {
                     Ok(()) => Ok(()),
                     Err((key, val, e)) => {
                         self.operation.clear();
+                        self.usage_change.clear();
                         Err((key, val, e))
                     }
                 }
@@ -272,6 +712,10 @@ impl<'a, K: kv::KV<'a>> kv::KVPermissions<'a> for KVStorePermissions<'a, K> {
             return Err((key, ErrorCode::BUSY));
         }
 
+        if let Err(e) = self.check_batch_owner(permissions.get_write_id()) {
+            return Err((key, e));
+        }
+
         self.operation.set(Operation::Delete);
         self.valid_ids.set(permissions);
 
@@ -288,9 +732,119 @@ impl<'a, K: kv::KV<'a>> kv::KVPermissions<'a> for KVStorePermissions<'a, K> {
         }
     }
 
+    fn next_key(
+        &self,
+        position: usize,
+        key: SubSliceMut<'static, u8>,
+        value: SubSliceMut<'static, u8>,
+        permissions: StoragePermissions,
+    ) -> Result<
+        (),
+        (
+            SubSliceMut<'static, u8>,
+            SubSliceMut<'static, u8>,
+            ErrorCode,
+        ),
+    > {
+        if self.operation.is_some() {
+            return Err((key, value, ErrorCode::BUSY));
+        }
+
+        // Only objects owned by the caller are returned, so a caller without
+        // a write ID can't own any objects.
+        if permissions.get_write_id().is_none() {
+            return Err((key, value, ErrorCode::NOSUPPORT));
+        }
+
+        self.operation.set(Operation::NextKey);
+        self.valid_ids.set(permissions);
+
+        match self.kv.next_key(position, key, value) {
+            Ok(()) => Ok(()),
+            Err((key, val, e)) => {
+                self.operation.clear();
+                Err((key, val, e))
+            }
+        }
+    }
+
+    fn begin_batch(&self, permissions: StoragePermissions) -> Result<(), ErrorCode> {
+        let write_id = permissions.get_write_id().ok_or(ErrorCode::INVAL)?;
+
+        if self.operation.is_some() {
+            return Err(ErrorCode::BUSY);
+        }
+
+        match self.batch_owner.get() {
+            Some(owner) if owner == write_id => return Err(ErrorCode::ALREADY),
+            Some(_) => return Err(ErrorCode::BUSY),
+            None => {}
+        }
+
+        self.kv.begin_batch()?;
+        self.batch_owner.set(write_id);
+        Ok(())
+    }
+
+    fn commit_batch(&self, permissions: StoragePermissions) -> Result<(), ErrorCode> {
+        if self.operation.is_some() {
+            return Err(ErrorCode::BUSY);
+        }
+
+        if self.batch_owner.is_none() || self.check_batch_owner(permissions.get_write_id()).is_err()
+        {
+            return Err(ErrorCode::INVAL);
+        }
+
+        self.operation.set(Operation::CommitBatch);
+        self.kv
+            .commit_batch()
+            .inspect_err(|_| self.operation.clear())
+    }
+
+    fn abort_batch(&self, permissions: StoragePermissions) -> Result<(), ErrorCode> {
+        if self.operation.is_some() {
+            return Err(ErrorCode::BUSY);
+        }
+
+        if self.batch_owner.is_none() || self.check_batch_owner(permissions.get_write_id()).is_err()
+        {
+            return Err(ErrorCode::INVAL);
+        }
+
+        self.operation.set(Operation::AbortBatch);
+        self.kv
+            .abort_batch()
+            .inspect_err(|_| self.operation.clear())
+    }
+
     fn header_size(&self) -> usize {
         HEADER_LENGTH
     }
+
+    fn get_usage(&self, permissions: StoragePermissions) -> Result<kv::StorageUsage, ErrorCode> {
+        let write_id = permissions.get_write_id().ok_or(ErrorCode::INVAL)?;
+
+        match self.usage_state.get() {
+            UsageState::Known => self.usage_of(write_id).ok_or(ErrorCode::FAIL),
+            UsageState::Scanning => Err(ErrorCode::BUSY),
+            UsageState::Unavailable => Err(ErrorCode::NOSUPPORT),
+            UsageState::Unknown => {
+                // Start calculating the usage, the caller can try again once
+                // it is done.
+                if self.operation.is_none() {
+                    self.operation.set(Operation::Scan);
+                    if let Err(e) = self.start_scan() {
+                        self.operation.clear();
+                        if e == ErrorCode::NOSUPPORT {
+                            return self.get_usage(permissions);
+                        }
+                    }
+                }
+                Err(ErrorCode::BUSY)
+            }
+        }
+    }
 }
 
 impl<'a, K: kv::KV<'a>> kv::KVClient for KVStorePermissions<'a, K> {
@@ -305,6 +859,7 @@ impl<'a, K: kv::KV<'a>> kv::KVClient for KVStorePermissions<'a, K> {
                 Operation::Set => {
                     // Need to determine if we have permission to set this key.
                     let mut access_allowed = false;
+                    let mut removed = None;
 
                     if result.is_ok() || result.err() == Some(ErrorCode::SIZE) {
                         let header = KeyHeader::new_from_buf(value.as_slice());
@@ -313,6 +868,7 @@ impl<'a, K: kv::KV<'a>> kv::KVClient for KVStorePermissions<'a, K> {
                             self.valid_ids.map(|perms| {
                                 access_allowed = perms.check_modify_permission(header.write_id);
                             });
+                            removed = Some((header.write_id, header.length as usize));
                         }
                     } else if result.err() == Some(ErrorCode::NOSUPPORT) {
                         // Key wasn't found, so we can create it fresh.
@@ -321,31 +877,46 @@ impl<'a, K: kv::KV<'a>> kv::KVClient for KVStorePermissions<'a, K> {
 
                     self.header_value.replace(value.take());
 
-                    if access_allowed {
-                        self.value
-                            .take()
-                            .map(|set_value| match self.kv.set(key, set_value) {
-                                Ok(()) => {}
-
-                                Err((key, set_value, e)) => {
-                                    self.operation.clear();
-                                    self.client.map(move |cb| {
-                                        cb.set_complete(Err(e), key, set_value);
-                                    });
-                                }
-                            });
+                    // Objects that would take the caller over quota can't be
+                    // stored.
+                    let ret = if access_allowed {
+                        self.value.map_or(Err(ErrorCode::FAIL), |set_value| {
+                            self.start_usage_change(removed, set_value)
+                        })
                     } else {
-                        self.operation.clear();
-                        self.value.take().map(|set_value| {
-                            self.client.map(move |cb| {
-                                cb.set_complete(Err(ErrorCode::NOSUPPORT), key, set_value);
+                        Err(ErrorCode::NOSUPPORT)
+                    };
+
+                    match ret {
+                        Ok(()) => {
+                            self.value
+                                .take()
+                                .map(|set_value| match self.kv.set(key, set_value) {
+                                    Ok(()) => {}
+
+                                    Err((key, set_value, e)) => {
+                                        self.operation.clear();
+                                        self.usage_change.clear();
+                                        self.client.map(move |cb| {
+                                            cb.set_complete(Err(e), key, set_value);
+                                        });
+                                    }
+                                });
+                        }
+                        Err(e) => {
+                            self.operation.clear();
+                            self.value.take().map(|set_value| {
+                                self.client.map(move |cb| {
+                                    cb.set_complete(Err(e), key, set_value);
+                                });
                             });
-                        });
+                        }
                     }
                 }
                 Operation::Update => {
                     // Need to determine if we have permission to set this key.
                     let mut access_allowed = false;
+                    let mut removed = None;
 
                     if result.is_ok() || result.err() == Some(ErrorCode::SIZE) {
                         let header = KeyHeader::new_from_buf(value.as_slice());
@@ -354,31 +925,46 @@ impl<'a, K: kv::KV<'a>> kv::KVClient for KVStorePermissions<'a, K> {
                             self.valid_ids.map(|perms| {
                                 access_allowed = perms.check_modify_permission(header.write_id);
                             });
+                            removed = Some((header.write_id, header.length as usize));
                         }
                     }
 
                     self.header_value.replace(value.take());
 
-                    if access_allowed {
-                        self.value
-                            .take()
-                            .map(|set_value| match self.kv.update(key, set_value) {
-                                Ok(()) => {}
-
-                                Err((key, set_value, e)) => {
-                                    self.operation.clear();
-                                    self.client.map(move |cb| {
-                                        cb.update_complete(Err(e), key, set_value);
-                                    });
+                    // Objects that would take the caller over quota can't be
+                    // stored.
+                    let ret = if access_allowed {
+                        self.value.map_or(Err(ErrorCode::FAIL), |set_value| {
+                            self.start_usage_change(removed, set_value)
+                        })
+                    } else {
+                        Err(ErrorCode::NOSUPPORT)
+                    };
+
+                    match ret {
+                        Ok(()) => {
+                            self.value.take().map(|set_value| {
+                                match self.kv.update(key, set_value) {
+                                    Ok(()) => {}
+
+                                    Err((key, set_value, e)) => {
+                                        self.operation.clear();
+                                        self.usage_change.clear();
+                                        self.client.map(move |cb| {
+                                            cb.update_complete(Err(e), key, set_value);
+                                        });
+                                    }
                                 }
                             });
-                    } else {
-                        self.operation.clear();
-                        self.value.take().map(|set_value| {
-                            self.client.map(move |cb| {
-                                cb.update_complete(Err(ErrorCode::NOSUPPORT), key, set_value);
+                        }
+                        Err(e) => {
+                            self.operation.clear();
+                            self.value.take().map(|set_value| {
+                                self.client.map(move |cb| {
+                                    cb.update_complete(Err(e), key, set_value);
+                                });
                             });
-                        });
+                        }
                     }
                 }
                 Operation::Delete => {
@@ -396,6 +982,10 @@ impl<'a, K: kv::KV<'a>> kv::KVClient for KVStorePermissions<'a, K> {
                             self.valid_ids.map(|perms| {
                                 access_allowed = perms.check_modify_permission(header.write_id);
                             });
+                            self.usage_change.set(UsageChange {
+                                removed: Some((header.write_id, header.length as usize)),
+                                added: None,
+                            });
                         }
                     }
 
@@ -407,6 +997,7 @@ impl<'a, K: kv::KV<'a>> kv::KVClient for KVStorePermissions<'a, K> {
 
                             Err((key, e)) => {
                                 self.operation.clear();
+                                self.usage_change.clear();
                                 self.client.map(move |cb| {
                                     cb.delete_complete(Err(e), key);
                                 });
@@ -414,6 +1005,7 @@ impl<'a, K: kv::KV<'a>> kv::KVClient for KVStorePermissions<'a, K> {
                         }
                     } else {
                         self.operation.clear();
+                        self.usage_change.clear();
                         self.client.map(move |cb| {
                             cb.delete_complete(Err(ErrorCode::NOSUPPORT), key);
                         });
@@ -468,6 +1060,7 @@ impl<'a, K: kv::KV<'a>> kv::KVClient for KVStorePermissions<'a, K> {
         value: SubSliceMut<'static, u8>,
     ) {
         self.operation.clear();
+        self.complete_usage_change(result);
         self.client.map(move |cb| {
             cb.set_complete(result, key, value);
         });
@@ -480,6 +1073,7 @@ impl<'a, K: kv::KV<'a>> kv::KVClient for KVStorePermissions<'a, K> {
         value: SubSliceMut<'static, u8>,
     ) {
         self.operation.clear();
+        self.complete_usage_change(result);
         self.client.map(move |cb| {
             cb.add_complete(result, key, value);
         });
@@ -492,6 +1086,7 @@ impl<'a, K: kv::KV<'a>> kv::KVClient for KVStorePermissions<'a, K> {
         value: SubSliceMut<'static, u8>,
     ) {
         self.operation.clear();
+        self.complete_usage_change(result);
         self.client.map(move |cb| {
             cb.update_complete(result, key, value);
         });
@@ -499,8 +1094,692 @@ impl<'a, K: kv::KV<'a>> kv::KVClient for KVStorePermissions<'a, K> {
 
     fn delete_complete(&self, result: Result<(), ErrorCode>, key: SubSliceMut<'static, u8>) {
         self.operation.clear();
+        self.complete_usage_change(result);
         self.client.map(move |cb| {
             cb.delete_complete(result, key);
         });
     }
+
+    fn commit_batch_complete(&self, result: Result<(), ErrorCode>) {
+        self.operation.clear();
+
+        // If the commit couldn't be started the batch is still in progress
+        // and needs to be aborted.
+        if result != Err(ErrorCode::NOMEM) {
+            self.batch_owner.clear();
+        }
+
+        // The usage of the staged objects was accounted for when they were
+        // set, recalculate it if they might not have been stored.
+        if result.is_err() {
+            self.invalidate_usage();
+        }
+
+        self.client.map(move |cb| {
+            cb.commit_batch_complete(result);
+        });
+    }
+
+    fn abort_batch_complete(&self, result: Result<(), ErrorCode>) {
+        self.operation.clear();
+        self.batch_owner.clear();
+
+        // The staged objects were accounted for when they were set, so the
+        // usage needs to be recalculated.
+        self.invalidate_usage();
+
+        self.client.map(move |cb| {
+            cb.abort_batch_complete(result);
+        });
+    }
+
+    fn next_key_complete(
+        &self,
+        result: Result<(), ErrorCode>,
+        position: usize,
+        mut key: SubSliceMut<'static, u8>,
+        mut value: SubSliceMut<'static, u8>,
+    ) {
+        if self.operation.get() == Some(Operation::Scan) {
+            self.scan_next(result, position, key, value);
+            return;
+        }
+
+        let readable = result.is_ok() || result.err() == Some(ErrorCode::SIZE);
+
+        if readable || skippable(result, position) {
+            // Only return objects that were written by the caller.
+            let mut owned = false;
+
+            if readable && value.len() >= HEADER_LENGTH {
+                let header = KeyHeader::new_from_buf(value.as_slice());
+
+                if header.version == HEADER_VERSION {
+                    self.valid_ids.map(|perms| {
+                        owned = perms.get_write_id() == Some(header.write_id);
+                    });
+                }
+            }
+
+            if !owned {
+                // Skip this object and move on to the next one.
+                key.reset();
+                value.reset();
+                match self.kv.next_key(position, key, value) {
+                    Ok(()) => {}
+                    Err((key, value, e)) => {
+                        self.operation.clear();
+                        self.client.map(move |cb| {
+                            cb.next_key_complete(Err(e), 0, key, value);
+                        });
+                    }
+                }
+                return;
+            }
+
+            // Remove the header from the accessible portion of the buffer.
+            value.slice(HEADER_LENGTH..);
+        }
+
+        self.operation.clear();
+        self.client.map(move |cb| {
+            cb.next_key_complete(result, position, key, value);
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::kv_store_encryption::tests::{FakeCCM, FakeRng, DEVICE_KEY};
+    use crate::kv_store_encryption::{
+        KVStoreEncryption, NONCE_LENGTH, RECORD_OVERHEAD, RECORD_VERSION, TAG_LENGTH,
+    };
+    use kernel::hil::rng;
+    use kernel::hil::symmetric_encryption::AES128CCM;
+    use std::boxed::Box;
+    use std::vec::Vec;
+
+    enum Pending {
+        Get(SubSliceMut<'static, u8>, SubSliceMut<'static, u8>),
+        Set(SubSliceMut<'static, u8>, SubSliceMut<'static, u8>),
+        NextKey(usize, SubSliceMut<'static, u8>, SubSliceMut<'static, u8>),
+    }
+
+    /// A K-V store that keeps objects in memory and completes operations
+    /// when `complete()` is called.
+    struct FakeKV {
+        objects: MapCell<Vec<(Vec<u8>, Vec<u8>)>>,
+        pending: MapCell<Pending>,
+        iterable: bool,
+        /// Number of iterations to fail part way through.
+        failures: Cell<usize>,
+        client: OptionalCell<&'static dyn kv::KVClient>,
+    }
+
+    impl FakeKV {
+        fn new(iterable: bool) -> Self {
+            Self {
+                objects: MapCell::new(Vec::new()),
+                pending: MapCell::empty(),
+                iterable,
+                failures: Cell::new(0),
+                client: OptionalCell::empty(),
+            }
+        }
+
+        fn insert_object(&self, key: &[u8], write_id: u32, length: usize) {
+            let mut value = std::vec![0; HEADER_LENGTH + length];
+            KeyHeader {
+                version: HEADER_VERSION,
+                length: length as u32,
+                write_id,
+            }
+            .copy_to_buf(&mut value);
+            self.objects
+                .map(|objects| objects.push((key.to_vec(), value)));
+        }
+
+        /// Store `value` as it is, replacing any object with the same key.
+        fn insert_value(&self, key: &[u8], value: Vec<u8>) {
+            self.objects
+                .map(|objects| match objects.iter_mut().find(|(k, _)| k == key) {
+                    Some(object) => object.1 = value,
+                    None => objects.push((key.to_vec(), value)),
+                });
+        }
+
+        fn object(&self, key: &[u8]) -> Option<Vec<u8>> {
+            self.objects
+                .map(|objects| {
+                    objects
+                        .iter()
+                        .find(|(k, _)| k == key)
+                        .map(|(_, v)| v.clone())
+                })
+                .flatten()
+        }
+
+        /// Copy `src` into `dst`, returning `SIZE` if it didn't fit.
+        fn copy(src: &[u8], dst: &mut SubSliceMut<'static, u8>) -> Result<(), ErrorCode> {
+            let length = src.len().min(dst.len());
+            dst.as_slice()[..length].copy_from_slice(&src[..length]);
+            dst.slice(0..length);
+            if length < src.len() {
+                Err(ErrorCode::SIZE)
+            } else {
+                Ok(())
+            }
+        }
+
+        /// Complete the pending operation, returns `false` if there was none.
+        fn complete(&self) -> bool {
+            match self.pending.take() {
+                Some(Pending::Get(mut key, mut value)) => {
+                    let result = match self.object(key.as_slice()) {
+                        Some(object) => Self::copy(&object, &mut value),
+                        None => Err(ErrorCode::NOSUPPORT),
+                    };
+                    self.client
+                        .map(move |cb| cb.get_complete(result, key, value));
+                }
+                Some(Pending::Set(mut key, mut value)) => {
+                    self.objects.map(|objects| {
+                        objects.retain(|(k, _)| k != key.as_slice());
+                        objects.push((key.as_slice().to_vec(), value.as_slice().to_vec()));
+                    });
+                    self.client
+                        .map(move |cb| cb.set_complete(Ok(()), key, value));
+                }
+                Some(Pending::NextKey(position, mut key, mut value)) => {
+                    let object = self
+                        .objects
+                        .map(|objects| objects.get(position).cloned())
+                        .flatten();
+                    let (result, next) = match object {
+                        // Like TicKV, a failure doesn't say where to continue.
+                        _ if position == 1 && self.failures.get() > 0 => {
+                            self.failures.set(self.failures.get() - 1);
+                            (Err(ErrorCode::FAIL), 0)
+                        }
+                        Some((k, v)) => (
+                            Self::copy(&k, &mut key).and(Self::copy(&v, &mut value)),
+                            position + 1,
+                        ),
+                        None => (Err(ErrorCode::NOSUPPORT), position + 1),
+                    };
+                    self.client
+                        .map(move |cb| cb.next_key_complete(result, next, key, value));
+                }
+                None => return false,
+            }
+            true
+        }
+
+        fn complete_all(&self) {
+            while self.complete() {}
+        }
+    }
+
+    impl kv::KV<'static> for FakeKV {
+        fn set_client(&self, client: &'static dyn kv::KVClient) {
+            self.client.set(client);
+        }
+
+        fn get(
+            &self,
+            key: SubSliceMut<'static, u8>,
+            value: SubSliceMut<'static, u8>,
+        ) -> Result<
+            (),
+            (
+                SubSliceMut<'static, u8>,
+                SubSliceMut<'static, u8>,
+                ErrorCode,
+            ),
+        > {
+            self.pending.replace(Pending::Get(key, value));
+            Ok(())
+        }
+
+        fn set(
+            &self,
+            key: SubSliceMut<'static, u8>,
+            value: SubSliceMut<'static, u8>,
+        ) -> Result<
+            (),
+            (
+                SubSliceMut<'static, u8>,
+                SubSliceMut<'static, u8>,
+                ErrorCode,
+            ),
+        > {
+            self.pending.replace(Pending::Set(key, value));
+            Ok(())
+        }
+
+        fn add(
+            &self,
+            key: SubSliceMut<'static, u8>,
+            value: SubSliceMut<'static, u8>,
+        ) -> Result<
+            (),
+            (
+                SubSliceMut<'static, u8>,
+                SubSliceMut<'static, u8>,
+                ErrorCode,
+            ),
+        > {
+            self.set(key, value)
+        }
+
+        fn update(
+            &self,
+            key: SubSliceMut<'static, u8>,
+            value: SubSliceMut<'static, u8>,
+        ) -> Result<
+            (),
+            (
+                SubSliceMut<'static, u8>,
+                SubSliceMut<'static, u8>,
+                ErrorCode,
+            ),
+        > {
+            self.set(key, value)
+        }
+
+        fn delete(
+            &self,
+            key: SubSliceMut<'static, u8>,
+        ) -> Result<(), (SubSliceMut<'static, u8>, ErrorCode)> {
+            Err((key, ErrorCode::NOSUPPORT))
+        }
+
+        fn next_key(
+            &self,
+            position: usize,
+            key: SubSliceMut<'static, u8>,
+            value: SubSliceMut<'static, u8>,
+        ) -> Result<
+            (),
+            (
+                SubSliceMut<'static, u8>,
+                SubSliceMut<'static, u8>,
+                ErrorCode,
+            ),
+        > {
+            if !self.iterable {
+                return Err((key, value, ErrorCode::NOSUPPORT));
+            }
+            self.pending.replace(Pending::NextKey(position, key, value));
+            Ok(())
+        }
+    }
+
+    /// Records the result of the last `set()`.
+    struct Client {
+        result: OptionalCell<Result<(), ErrorCode>>,
+    }
+
+    impl kv::KVClient for Client {
+        fn get_complete(
+            &self,
+            _result: Result<(), ErrorCode>,
+            _key: SubSliceMut<'static, u8>,
+            _value: SubSliceMut<'static, u8>,
+        ) {
+        }
+
+        fn set_complete(
+            &self,
+            result: Result<(), ErrorCode>,
+            _key: SubSliceMut<'static, u8>,
+            _value: SubSliceMut<'static, u8>,
+        ) {
+            self.result.set(result);
+        }
+
+        fn add_complete(
+            &self,
+            _result: Result<(), ErrorCode>,
+            _key: SubSliceMut<'static, u8>,
+            _value: SubSliceMut<'static, u8>,
+        ) {
+        }
+
+        fn update_complete(
+            &self,
+            _result: Result<(), ErrorCode>,
+            _key: SubSliceMut<'static, u8>,
+            _value: SubSliceMut<'static, u8>,
+        ) {
+        }
+
+        fn delete_complete(&self, _result: Result<(), ErrorCode>, _key: SubSliceMut<'static, u8>) {}
+    }
+
+    const QUOTA: StorageQuota = StorageQuota {
+        bytes: 16,
+        objects: 3,
+    };
+
+    fn setup(
+        kv: FakeKV,
+    ) -> (
+        &'static FakeKV,
+        &'static KVStorePermissions<'static, FakeKV>,
+        &'static Client,
+    ) {
+        let kv = Box::leak(Box::new(kv));
+        let store = Box::leak(Box::new(KVStorePermissions::new(
+            kv,
+            Box::leak(Box::new([0; HEADER_LENGTH])),
+            Box::leak(Box::new([0; SCAN_KEY_LENGTH])),
+            Box::leak(Box::new([None; USAGE_TABLE_LENGTH])),
+        )));
+        let client = Box::leak(Box::new(Client {
+            result: OptionalCell::empty(),
+        }));
+        kv::KV::set_client(kv, store);
+        kv::KVPermissions::set_client(store, client);
+        (kv, store, client)
+    }
+
+    fn scan<K: kv::KV<'static>>(store: &KVStorePermissions<'static, K>) -> Result<(), ErrorCode> {
+        store.operation.set(Operation::Scan);
+        let ret = store.start_scan();
+        if ret.is_err() {
+            store.operation.clear();
+        }
+        ret
+    }
+
+    /// An object of `length` bytes owned by `write_id` to pass to
+    /// `start_usage_change()`.
+    fn value(write_id: u32, length: usize) -> SubSliceMut<'static, u8> {
+        let value = Box::leak(std::vec![0; HEADER_LENGTH + length].into_boxed_slice());
+        KeyHeader {
+            version: HEADER_VERSION,
+            length: length as u32,
+            write_id,
+        }
+        .copy_to_buf(value);
+        SubSliceMut::new(value)
+    }
+
+    fn usage(bytes: usize, objects: usize) -> Option<kv::StorageUsage> {
+        Some(kv::StorageUsage { bytes, objects })
+    }
+
+    type EncryptedKV = KVStoreEncryption<'static, FakeKV, FakeCCM, FakeRng>;
+
+    /// Put `KVStoreEncryption` with a buffer of `crypt_buffer_length` bytes
+    /// between the store and `kv`, and store `objects` through it.
+    fn setup_encrypted(
+        kv: FakeKV,
+        crypt_buffer_length: usize,
+        objects: &[(&[u8], u32, usize)],
+    ) -> (
+        &'static FakeKV,
+        &'static FakeCCM,
+        &'static KVStorePermissions<'static, EncryptedKV>,
+    ) {
+        let kv = Box::leak(Box::new(kv));
+        let ccm = Box::leak(Box::new(FakeCCM::new()));
+        let rng = Box::leak(Box::new(FakeRng::new()));
+        let encryption: &'static EncryptedKV = Box::leak(Box::new(KVStoreEncryption::new(
+            kv,
+            ccm,
+            rng,
+            DEVICE_KEY,
+            Box::leak(std::vec![0; crypt_buffer_length].into_boxed_slice()),
+            128,
+        )));
+        let client = Box::leak(Box::new(Client {
+            result: OptionalCell::empty(),
+        }));
+        kv::KV::set_client(kv, encryption);
+        ccm.set_client(encryption);
+        rng::Rng::set_client(rng, encryption);
+        kv::KV::set_client(encryption, client);
+
+        for (key, write_id, length) in objects {
+            let key = SubSliceMut::new(Box::leak(key.to_vec().into_boxed_slice()));
+            assert!(kv::KV::set(encryption, key, value(*write_id, *length)).is_ok());
+            rng.complete();
+            ccm.complete();
+            kv.complete();
+            assert_eq!(client.result.take(), Some(Ok(())));
+        }
+
+        let store = Box::leak(Box::new(KVStorePermissions::new(
+            encryption,
+            Box::leak(Box::new([0; HEADER_LENGTH])),
+            Box::leak(Box::new([0; SCAN_KEY_LENGTH])),
+            Box::leak(Box::new([None; USAGE_TABLE_LENGTH])),
+        )));
+        kv::KV::set_client(encryption, store);
+        kv::KVPermissions::set_client(store, client);
+        (kv, ccm, store)
+    }
+
+    #[test]
+    fn scan_counts_existing_objects() {
+        let kv = FakeKV::new(true);
+        kv.insert_object(b"a", 1, 4);
+        // Objects stored without their key are counted too.
+        kv.insert_object(b"", 1, 6);
+        kv.insert_object(b"b", 2, 100);
+        kv.objects
+            .map(|objects| objects.push((b"c".to_vec(), std::vec![0xff; 20])));
+        let (kv, store, _) = setup(kv);
+
+        assert_eq!(scan(store), Ok(()));
+        kv.complete_all();
+
+        assert_eq!(store.usage_state.get(), UsageState::Known);
+        assert_eq!(store.usage_of(1), usage(10, 2));
+        assert_eq!(store.usage_of(2), usage(100, 1));
+        assert_eq!(store.usage_of(3), usage(0, 0));
+    }
+
+    #[test]
+    fn quota_limits_objects_and_bytes() {
+        let kv = FakeKV::new(true);
+        kv.insert_object(b"a", 1, 4);
+        kv.insert_object(b"b", 1, 4);
+        let (kv, store, _) = setup(kv);
+        scan(store).unwrap();
+        kv.complete_all();
+
+        assert_eq!(store.check_quota(None, 1, None, 100), Ok(()));
+        assert_eq!(store.check_quota(Some(QUOTA), 1, None, 8), Ok(()));
+        assert_eq!(
+            store.check_quota(Some(QUOTA), 1, None, 9),
+            Err(ErrorCode::NOMEM)
+        );
+
+        // Overwriting an object doesn't count it twice.
+        store.account(1, 4, true);
+        assert_eq!(
+            store.check_quota(Some(QUOTA), 1, None, 1),
+            Err(ErrorCode::NOMEM)
+        );
+        assert_eq!(store.check_quota(Some(QUOTA), 1, Some((1, 4)), 8), Ok(()));
+        // Replacing an object owned by someone else does.
+        assert_eq!(
+            store.check_quota(Some(QUOTA), 1, Some((2, 4)), 1),
+            Err(ErrorCode::NOMEM)
+        );
+    }
+
+    #[test]
+    fn usage_changes_once_stored() {
+        let (kv, store, _) = setup(FakeKV::new(true));
+        scan(store).unwrap();
+        kv.complete_all();
+        store
+            .valid_ids
+            .set(StoragePermissions::new_null().with_quota(QUOTA));
+
+        // Storing an object over the quota is rejected.
+        assert_eq!(
+            store.start_usage_change(None, &mut value(1, 17)),
+            Err(ErrorCode::NOMEM)
+        );
+
+        assert_eq!(store.start_usage_change(None, &mut value(1, 8)), Ok(()));
+        store.complete_usage_change(Ok(()));
+        assert_eq!(store.usage_of(1), usage(8, 1));
+
+        // Failed operations don't change usage.
+        assert_eq!(store.start_usage_change(None, &mut value(1, 2)), Ok(()));
+        store.complete_usage_change(Err(ErrorCode::FAIL));
+        assert_eq!(store.usage_of(1), usage(8, 1));
+
+        // Overwriting an object replaces its usage.
+        assert_eq!(
+            store.start_usage_change(Some((1, 8)), &mut value(1, 16)),
+            Ok(())
+        );
+        store.complete_usage_change(Ok(()));
+        assert_eq!(store.usage_of(1), usage(16, 1));
+
+        // Deleting an object frees its usage.
+        store.usage_change.set(UsageChange {
+            removed: Some((1, 16)),
+            added: None,
+        });
+        store.complete_usage_change(Ok(()));
+        assert_eq!(store.usage_of(1), usage(0, 0));
+    }
+
+    #[test]
+    fn failed_scan_is_retried() {
+        let kv = FakeKV::new(true);
+        kv.insert_object(b"a", 1, 4);
+        kv.insert_object(b"b", 1, 4);
+        kv.failures.set(MAX_SCAN_ATTEMPTS as usize - 1);
+        let (kv, store, client) = setup(kv);
+
+        store.deferred.set(Operation::Set);
+        store
+            .key
+            .replace(SubSliceMut::new(Box::leak(Box::new(*b"c"))));
+        store.value.replace(value(1, 4));
+        store
+            .valid_ids
+            .set(StoragePermissions::new_null().with_quota(QUOTA));
+
+        scan(store).unwrap();
+        kv.complete_all();
+
+        assert_eq!(client.result.get(), Some(Ok(())));
+        assert_eq!(store.usage_of(1), usage(12, 3));
+    }
+
+    #[test]
+    fn insert_fails_if_scan_keeps_failing() {
+        let kv = FakeKV::new(true);
+        kv.insert_object(b"a", 1, 4);
+        kv.insert_object(b"b", 1, 4);
+        kv.failures.set(MAX_SCAN_ATTEMPTS as usize);
+        let (kv, store, client) = setup(kv);
+
+        store.deferred.set(Operation::Set);
+        store
+            .key
+            .replace(SubSliceMut::new(Box::leak(Box::new(*b"c"))));
+        store.value.replace(value(1, 4));
+
+        scan(store).unwrap();
+        kv.complete_all();
+
+        assert_eq!(client.result.get(), Some(Err(ErrorCode::FAIL)));
+        assert_eq!(store.usage_state.get(), UsageState::Unknown);
+        assert!(kv.object(b"c").is_none());
+    }
+
+    #[test]
+    fn non_iterable_store_fails_closed() {
+        let (_, store, _) = setup(FakeKV::new(false));
+
+        assert_eq!(scan(store), Err(ErrorCode::NOSUPPORT));
+        assert_eq!(store.usage_state.get(), UsageState::Unavailable);
+
+        assert_eq!(
+            store.check_quota(Some(QUOTA), 1, None, 1),
+            Err(ErrorCode::NOMEM)
+        );
+        assert_eq!(store.check_quota(None, 1, None, 1), Ok(()));
+
+        // Usage stays unavailable after a batch is aborted.
+        store.invalidate_usage();
+        assert_eq!(store.usage_state.get(), UsageState::Unavailable);
+    }
+
+    #[test]
+    fn scan_charges_records_too_long_to_authenticate() {
+        // A record that doesn't fit in the encryption buffer, owned by 2.
+        let mut record = std::vec![0; RECORD_OVERHEAD + HEADER_LENGTH + 40];
+        record[0] = RECORD_VERSION;
+        KeyHeader {
+            version: HEADER_VERSION,
+            length: 40,
+            write_id: 2,
+        }
+        .copy_to_buf(&mut record[1 + NONCE_LENGTH..]);
+        let kv = FakeKV::new(true);
+        kv.insert_value(b"big", record);
+
+        let (kv, ccm, store) = setup_encrypted(kv, 64, &[(b"a", 1, 4), (b"c", 1, 2)]);
+
+        scan(store).unwrap();
+        while kv.complete() {
+            ccm.complete();
+        }
+
+        assert_eq!(store.usage_state.get(), UsageState::Known);
+        assert_eq!(store.usage_of(1), usage(6, 2));
+        assert_eq!(store.usage_of(2), usage(0, 0));
+        assert_eq!(Some(store.unknown_usage.get()), usage(40, 1));
+
+        // The record counts against every quota.
+        assert_eq!(
+            store.check_quota(Some(QUOTA), 3, None, 1),
+            Err(ErrorCode::NOMEM)
+        );
+    }
+
+    #[test]
+    fn scan_skips_unauthenticated_records() {
+        let (kv, ccm, store) = setup_encrypted(
+            FakeKV::new(true),
+            128,
+            &[(b"a", 1, 4), (b"b", 2, 6), (b"c", 1, 2)],
+        );
+
+        let mut record = kv.object(b"b").unwrap();
+        let last = record.len() - TAG_LENGTH - 1;
+        record[last] ^= 1;
+        kv.insert_value(b"b", record);
+
+        scan(store).unwrap();
+        while kv.complete() {
+            ccm.complete();
+        }
+
+        assert_eq!(store.usage_state.get(), UsageState::Known);
+        assert_eq!(store.usage_of(1), usage(6, 2));
+        assert_eq!(store.usage_of(2), usage(0, 0));
+        assert_eq!(Some(store.unknown_usage.get()), usage(6, 1));
+
+        assert_eq!(
+            store.check_quota(Some(QUOTA), 1, None, 2),
+            Err(ErrorCode::NOMEM)
+        );
+        assert_eq!(store.check_quota(Some(QUOTA), 3, None, 2), Ok(()));
+    }
 }
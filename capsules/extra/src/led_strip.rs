@@ -0,0 +1,303 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Provides userspace access to a strip of addressable RGB LEDs.
+//!
+//! The capsule keeps the colour of each LED in a frame buffer shared by all
+//! processes. Processes set LEDs one at a time or in runs from an allowed
+//! buffer, then show the frame. Colours are scaled by a global brightness
+//! when frames are shown, so the frame buffer keeps full colours.
+//!
+//! A show asked for while a frame is being sent is done once it completes,
+//! and each process gets an upcall when a frame including its changes was
+//! shown.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! let led_strip = components::led_strip::LedStripComponent::new(
+//!     board_kernel,
+//!     capsules_extra::led_strip::DRIVER_NUM,
+//!     ws2812,
+//! )
+//! .finalize(components::led_strip_component_static!(
+//!     components::ws2812::Ws2812ComponentType<rp2040::spi::Spi<'static>>,
+//!     180
+//! ));
+//! ```
+
+use core::cell::Cell;
+use core::cmp;
+
+use kernel::grant::{AllowRoCount, AllowRwCount, Grant, UpcallCount};
+use kernel::hil::led_strip::{LedStrip, LedStripClient};
+use kernel::processbuffer::ReadableProcessBuffer;
+use kernel::syscall::{CommandReturn, SyscallDriver};
+use kernel::utilities::cells::TakeCell;
+use kernel::{ErrorCode, ProcessId};
+
+/// Syscall driver number.
+use capsules_core::driver;
+pub const DRIVER_NUM: usize = driver::NUM::LedStrip as usize;
+
+/// Ids for read-only allow buffers
+mod ro_allow {
+    /// Colours of LEDs to set, with a byte per channel.
+    pub const PIXELS: usize = 0;
+    /// The number of allow buffers the kernel stores for this grant
+    pub const COUNT: u8 = 1;
+}
+
+mod upcall {
+    /// A frame was shown.
+    pub const SHOWN: usize = 0;
+    /// The number of upcalls the kernel stores for this grant
+    pub const COUNT: u8 = 1;
+}
+
+#[derive(Default)]
+pub struct App {
+    /// The process asked to show the frame.
+    show: bool,
+    /// The frame being sent includes the changes of the process.
+    showing: bool,
+}
+
+/// The bytes of a colour given as `0xWWRRGGBB`, in the RGBW order of frames.
+fn rgbw(colour: usize) -> [u8; 4] {
+    let bytes = (colour as u32).to_be_bytes();
+    [bytes[1], bytes[2], bytes[3], bytes[0]]
+}
+
+/// A channel scaled by `brightness`, where 255 is full brightness.
+fn dim(value: u8, brightness: u8) -> u8 {
+    (value as u16 * brightness as u16 / u8::MAX as u16) as u8
+}
+
+pub struct LedStripDriver<'a, L: LedStrip<'a>> {
+    strip: &'a L,
+    apps: Grant<
+        App,
+        UpcallCount<{ upcall::COUNT }>,
+        AllowRoCount<{ ro_allow::COUNT }>,
+        AllowRwCount<0>,
+    >,
+    /// The colours set by processes.
+    frame: TakeCell<'static, [u8]>,
+    /// The colours scaled by the brightness, while they are shown.
+    output: TakeCell<'static, [u8]>,
+    brightness: Cell<u8>,
+}
+
+impl<'a, L: LedStrip<'a>> LedStripDriver<'a, L> {
+    /// Create the driver. The buffers should have room for a byte per
+    /// channel of each LED; LEDs beyond them are left dark.
+    pub fn new(
+        strip: &'a L,
+        grant: Grant<
+            App,
+            UpcallCount<{ upcall::COUNT }>,
+            AllowRoCount<{ ro_allow::COUNT }>,
+            AllowRwCount<0>,
+        >,
+        frame: &'static mut [u8],
+        output: &'static mut [u8],
+    ) -> Self {
+        frame.fill(0);
+        LedStripDriver {
+            strip,
+            apps: grant,
+            frame: TakeCell::new(frame),
+            output: TakeCell::new(output),
+            brightness: Cell::new(u8::MAX),
+        }
+    }
+
+    /// The number of LEDs the frame buffer holds.
+    fn pixels(&self) -> usize {
+        let frame_len = cmp::min(
+            self.frame.map_or(0, |frame| frame.len()),
+            self.output.map_or(usize::MAX, |output| output.len()),
+        );
+        cmp::min(self.strip.pixels(), frame_len / self.strip.channels())
+    }
+
+    fn set_pixel(&self, index: usize, colour: usize) -> Result<(), ErrorCode> {
+        if index >= self.pixels() {
+            return Err(ErrorCode::INVAL);
+        }
+        let channels = self.strip.channels();
+        let rgbw = rgbw(colour);
+        self.frame.map(|frame| {
+            frame[index * channels..(index + 1) * channels].copy_from_slice(&rgbw[..channels]);
+        });
+        Ok(())
+    }
+
+    fn set_pixels(
+        &self,
+        processid: ProcessId,
+        first: usize,
+        count: usize,
+    ) -> Result<(), ErrorCode> {
+        if first
+            .checked_add(count)
+            .map_or(true, |end| end > self.pixels())
+        {
+            return Err(ErrorCode::INVAL);
+        }
+        let channels = self.strip.channels();
+        self.apps
+            .enter(processid, |_, kernel_data| {
+                kernel_data
+                    .get_readonly_processbuffer(ro_allow::PIXELS)
+                    .and_then(|buffer| {
+                        buffer.enter(|data| {
+                            let len = count * channels;
+                            let data = data.get(0..len).ok_or(ErrorCode::SIZE)?;
+                            self.frame.map(|frame| {
+                                let start = first * channels;
+                                data.copy_to_slice(&mut frame[start..start + len]);
+                            });
+                            Ok(())
+                        })
+                    })
+                    .unwrap_or(Err(ErrorCode::RESERVE))
+            })
+            .unwrap_or_else(|err| Err(err.into()))
+    }
+
+    /// Send the frame, scaled by the brightness, if a process asked for it
+    /// and no frame is being sent.
+    fn show(&self) {
+        let Some(output) = self.output.take() else {
+            return;
+        };
+        let mut requested = false;
+        for cntr in self.apps.iter() {
+            cntr.enter(|app, _| {
+                if app.show {
+                    app.show = false;
+                    app.showing = true;
+                    requested = true;
+                }
+            });
+        }
+        if !requested {
+            self.output.replace(output);
+            return;
+        }
+
+        let len = self.pixels() * self.strip.channels();
+        let brightness = self.brightness.get();
+        self.frame.map(|frame| {
+            for (out, &colour) in output[..len].iter_mut().zip(frame[..len].iter()) {
+                *out = dim(colour, brightness);
+            }
+        });
+        if let Err((e, output)) = self.strip.show(output, len) {
+            self.output.replace(output);
+            self.shown(Err(e));
+        }
+    }
+
+    /// Tell the processes whose changes were in the frame that it was
+    /// shown.
+    fn shown(&self, result: Result<(), ErrorCode>) {
+        for cntr in self.apps.iter() {
+            cntr.enter(|app, kernel_data| {
+                if app.showing {
+                    app.showing = false;
+                    kernel_data
+                        .schedule_upcall(
+                            upcall::SHOWN,
+                            (kernel::errorcode::into_statuscode(result), 0, 0),
+                        )
+                        .ok();
+                }
+            });
+        }
+    }
+}
+
+impl<'a, L: LedStrip<'a>> LedStripClient for LedStripDriver<'a, L> {
+    fn show_done(&self, frame: &'static mut [u8], result: Result<(), ErrorCode>) {
+        self.output.replace(frame);
+        self.shown(result);
+        self.show();
+    }
+}
+
+impl<'a, L: LedStrip<'a>> SyscallDriver for LedStripDriver<'a, L> {
+    /// Command interface.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver existence check.
+    /// - `1`: Return the number of LEDs and of colour channels per LED, 3
+    ///   for RGB or 4 for RGBW.
+    /// - `2`: Set LED `data1` to colour `data2`, as `0xWWRRGGBB`.
+    /// - `3`: Set `data2` LEDs from LED `data1` to the colours in the
+    ///   read-only allow buffer, with a byte per channel in red, green, blue
+    ///   and white order.
+    /// - `4`: Set the brightness colours are shown at to `data1` out of 255.
+    /// - `5`: Show the frame on the LEDs.
+    fn command(
+        &self,
+        command_num: usize,
+        data1: usize,
+        data2: usize,
+        processid: ProcessId,
+    ) -> CommandReturn {
+        match command_num {
+            0 => CommandReturn::success(),
+            1 => CommandReturn::success_u32_u32(self.pixels() as u32, self.strip.channels() as u32),
+            2 => self.set_pixel(data1, data2).into(),
+            3 => self.set_pixels(processid, data1, data2).into(),
+            4 => {
+                if data1 > u8::MAX as usize {
+                    return CommandReturn::failure(ErrorCode::INVAL);
+                }
+                self.brightness.set(data1 as u8);
+                CommandReturn::success()
+            }
+            5 => {
+                let r = self.apps.enter(processid, |app, _| {
+                    app.show = true;
+                });
+                if r.is_ok() {
+                    self.show();
+                }
+                r.map_err(ErrorCode::from).into()
+            }
+            _ => CommandReturn::failure(ErrorCode::NOSUPPORT),
+        }
+    }
+
+    fn allocate_grant(&self, processid: ProcessId) -> Result<(), kernel::process::Error> {
+        self.apps.enter(processid, |_, _| {})
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn colours() {
+        assert_eq!(rgbw(0x40FF_8001), [0xFF, 0x80, 0x01, 0x40]);
+        assert_eq!(rgbw(0x00_12_34_56), [0x12, 0x34, 0x56, 0x00]);
+    }
+
+    #[test]
+    fn brightness() {
+        assert_eq!(dim(0xFF, 0xFF), 0xFF);
+        assert_eq!(dim(0x80, 0xFF), 0x80);
+        assert_eq!(dim(0xFF, 0x80), 0x80);
+        assert_eq!(dim(0x80, 0x80), 0x40);
+        assert_eq!(dim(0xFF, 0), 0);
+        assert_eq!(dim(1, 0xFE), 0);
+    }
+}
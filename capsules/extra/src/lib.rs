@@ -13,6 +13,8 @@ pub mod net;
 
 pub mod adc_microphone;
 pub mod air_quality;
+#[cfg(test)]
+mod alarm_fake;
 pub mod ambient_light;
 pub mod analog_comparator;
 pub mod analog_sensor;
@@ -20,6 +22,9 @@ pub mod apds9960;
 pub mod app_flash_driver;
 pub mod at24c_eeprom;
 pub mod atecc508a;
+pub mod bitbang_i2c;
+pub mod bitbang_onewire;
+pub mod bitbang_spi;
 pub mod ble_advertising_driver;
 pub mod bme280;
 pub mod bmm150;
@@ -34,23 +39,40 @@ pub mod cycle_count;
 pub mod dac;
 pub mod date_time;
 pub mod debug_process_restart;
+pub mod ds18b20;
+pub mod ecdh;
 pub mod eui64;
+pub mod fido;
 pub mod fm25cl;
 pub mod ft6x06;
 pub mod fxos8700cq;
 pub mod gpio_async;
+#[cfg(test)]
+mod gpio_fake;
+pub mod graphics;
+pub mod graphics_driver;
 pub mod hd44780;
 pub mod hmac;
+pub mod hmac_drbg;
 pub mod hmac_sha256;
+#[cfg(test)]
+mod hmac_sha256_fake;
+pub mod hmac_sha512;
 pub mod hs3003;
 pub mod hts221;
 pub mod humidity;
 pub mod ieee802154;
 pub mod isl29035;
+pub mod isotp;
+pub mod kdf;
+pub mod kdf_hmac_sha256;
+pub mod keystore;
 pub mod kv_driver;
+pub mod kv_store_encryption;
 pub mod kv_store_permissions;
 pub mod l3gd20;
 pub mod led_matrix;
+pub mod led_strip;
 pub mod log;
 pub mod lpm013m126;
 pub mod lps22hb;
@@ -63,11 +85,13 @@ pub mod ltc294x;
 pub mod max17205;
 pub mod mcp230xx;
 pub mod mlx90614;
+pub mod modbus;
 pub mod mx25r6435f;
 pub mod ninedof;
 pub mod nonvolatile_storage_driver;
 pub mod nonvolatile_to_pages;
 pub mod nrf51822_serialization;
+pub mod orientation;
 pub mod panic_button;
 pub mod pca9544a;
 pub mod pressure;
@@ -79,15 +103,22 @@ pub mod rf233;
 pub mod rf233_const;
 pub mod screen;
 pub mod screen_shared;
+pub mod screen_text;
 pub mod sdcard;
+pub mod sensor_hub;
 pub mod seven_segment;
 pub mod sh1106;
 pub mod sha;
 pub mod sha256;
+pub mod sha512;
+#[cfg(test)]
+mod sha512_fake;
 pub mod sht3x;
 pub mod sht4x;
 pub mod si7021;
+pub mod signature_verify;
 pub mod sip_hash;
+pub mod software_date_time;
 pub mod sound_pressure;
 pub mod ssd1306;
 pub mod st77xx;
@@ -102,4 +133,9 @@ pub mod touch;
 pub mod tsl2561;
 pub mod usb;
 pub mod usb_hid_driver;
+pub mod virtual_humidity;
 pub mod virtual_kv;
+pub mod virtual_ninedof;
+pub mod virtual_pressure;
+pub mod virtual_temperature;
+pub mod ws2812;
@@ -0,0 +1,1127 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Modbus RTU master and slave over a UART, typically an RS-485 bus.
+//!
+//! The capsule frames Modbus PDUs with the slave address and CRC16 and keeps
+//! the silent intervals of RTU: frames end after 3.5 character times without
+//! data, frames with a gap longer than 1.5 character times are dropped, and
+//! frames are only sent after 3.5 character times of silence. Above 19200
+//! baud these are fixed at 750 us and 1.75 ms.
+//!
+//! As a master, processes send requests to slaves and get the responses back,
+//! one transaction on the bus at a time. A transaction fails if the slave
+//! doesn't respond within its timeout, 1 s by default. Broadcasts complete
+//! once sent, and the bus is then left silent for 100 ms so that slaves can
+//! act on them.
+//!
+//! As a slave, one process gives the bus an address and a register map:
+//! holding registers, which the master reads and writes, and input
+//! registers, which it only reads. The capsule answers the read holding
+//! registers (0x03), read input registers (0x04), write single register
+//! (0x06) and write multiple registers (0x10) functions from the buffers of
+//! the process, with exceptions for other functions and registers outside
+//! them, and tells the process which registers were written. Registers are
+//! big-endian, as on the bus.
+//!
+//! The driver enable pin of an RS-485 transceiver, if given, is set while
+//! frames are sent and for a character time after the UART completes, so
+//! that the last byte leaves the shift register. Bytes received meanwhile
+//! are taken to be the echo of the frame and dropped.
+//!
+//! The board configures the UART, usually with 8 data bits, even parity and
+//! one stop bit, at the baud rate given to the capsule.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! let modbus = components::modbus::ModbusComponent::new(
+//!     board_kernel,
+//!     capsules_extra::modbus::DRIVER_NUM,
+//!     &peripherals.usart2,
+//!     mux_alarm,
+//!     Some(&peripherals.gpio_ports.pins[0][1].as_ref().unwrap()),
+//!     19200,
+//! )
+//! .finalize(components::modbus_component_static!(
+//!     stm32f429zi::usart::Usart<'static>,
+//!     stm32f429zi::tim2::Tim2<'static>
+//! ));
+//! ```
+
+use core::cell::Cell;
+use core::cmp;
+use core::ops::Range;
+
+use kernel::grant::{AllowRoCount, AllowRwCount, Grant, GrantKernelData, UpcallCount};
+use kernel::hil::gpio;
+use kernel::hil::time::{Alarm, AlarmClient, ConvertTicks, Ticks};
+use kernel::hil::uart;
+use kernel::processbuffer::{ReadableProcessBuffer, WriteableProcessBuffer};
+use kernel::syscall::{CommandReturn, SyscallDriver};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::{ErrorCode, ProcessId};
+
+/// Syscall driver number.
+use capsules_core::driver;
+pub const DRIVER_NUM: usize = driver::NUM::Modbus as usize;
+
+/// The longest RTU frame: an address, a PDU of up to 253 bytes and the CRC.
+pub const MAX_FRAME_LEN: usize = 256;
+const MAX_PDU_LEN: usize = MAX_FRAME_LEN - 3;
+
+const BROADCAST: u8 = 0;
+const MAX_ADDRESS: usize = 247;
+
+const DEFAULT_TIMEOUT_MS: u32 = 1000;
+/// How long the bus is left silent after a broadcast.
+const TURNAROUND_MS: u32 = 100;
+/// Bits per character: start, 8 data, parity and stop bits.
+const CHARACTER_BITS: u32 = 11;
+/// Above this rate the silent intervals are fixed.
+const FIXED_TIMING_BAUD: u32 = 19200;
+const FIXED_T15_US: u32 = 750;
+const FIXED_T35_US: u32 = 1750;
+
+const READ_HOLDING_REGISTERS: u8 = 0x03;
+const READ_INPUT_REGISTERS: u8 = 0x04;
+const WRITE_SINGLE_REGISTER: u8 = 0x06;
+const WRITE_MULTIPLE_REGISTERS: u8 = 0x10;
+const EXCEPTION: u8 = 0x80;
+const MAX_READ_REGISTERS: usize = 125;
+const MAX_WRITE_REGISTERS: usize = 123;
+
+const ILLEGAL_FUNCTION: u8 = 0x01;
+const ILLEGAL_DATA_ADDRESS: u8 = 0x02;
+const ILLEGAL_DATA_VALUE: u8 = 0x03;
+
+/// The Modbus CRC16 of `data`, sent low byte first.
+pub fn crc16(data: &[u8]) -> u16 {
+    let mut crc: u16 = 0xFFFF;
+    for &byte in data {
+        crc ^= byte as u16;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xA001
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    crc
+}
+
+/// The address and PDU of a received frame, if it is long enough and its
+/// CRC is right.
+fn check_frame(frame: &[u8]) -> Option<&[u8]> {
+    let len = frame.len();
+    if len < 4 {
+        return None;
+    }
+    let crc = u16::from_le_bytes([frame[len - 2], frame[len - 1]]);
+    (crc == crc16(&frame[..len - 2])).then(|| &frame[..len - 2])
+}
+
+/// The PDU of a response from the slave at `address` to `function`, and
+/// its exception code, or 0 if it isn't an exception.
+fn parse_response(frame: &[u8], (address, function): (u8, u8)) -> Option<(&[u8], u8)> {
+    if frame[0] != address || frame[1] & !EXCEPTION != function {
+        return None;
+    }
+    let exception = if frame[1] & EXCEPTION != 0 {
+        *frame.get(2)?
+    } else {
+        0
+    };
+    Some((&frame[1..], exception))
+}
+
+/// The process serving the register map and its address, forgetting it if
+/// the process is no longer `running`.
+fn live_slave<P: Copy>(
+    slave: &OptionalCell<(P, u8)>,
+    running: impl Fn(P) -> bool,
+) -> Option<(P, u8)> {
+    match slave.get() {
+        Some((owner, _)) if !running(owner) => {
+            slave.clear();
+            None
+        }
+        slave => slave,
+    }
+}
+
+/// Copies the registers in a range of bytes of the register map read by a
+/// function into a buffer, returning `false` if they are outside the map.
+type ReadRegisters<'b> = &'b dyn Fn(u8, Range<usize>, &mut [u8]) -> bool;
+/// Writes values to the holding registers in a range of bytes, returning
+/// `false` if they are outside the map.
+type WriteRegisters<'b> = &'b dyn Fn(Range<usize>, &[u8]) -> bool;
+
+/// Carry out a request, writing the response after the address and
+/// returning its length with the address, or the exception code.
+fn serve(
+    frame: &[u8],
+    response: &mut [u8],
+    read: ReadRegisters,
+    write: WriteRegisters,
+) -> Result<usize, u8> {
+    let function = frame[1];
+    let data = &frame[2..];
+    let register = |i: usize| u16::from_be_bytes([data[i], data[i + 1]]) as usize;
+    match function {
+        READ_HOLDING_REGISTERS | READ_INPUT_REGISTERS => {
+            if data.len() != 4 {
+                return Err(ILLEGAL_DATA_VALUE);
+            }
+            let (first, count) = (register(0), register(2));
+            if count == 0 || count > MAX_READ_REGISTERS {
+                return Err(ILLEGAL_DATA_VALUE);
+            }
+            if !read(
+                function,
+                first * 2..(first + count) * 2,
+                &mut response[3..3 + count * 2],
+            ) {
+                return Err(ILLEGAL_DATA_ADDRESS);
+            }
+            response[1] = function;
+            response[2] = (count * 2) as u8;
+            Ok(3 + count * 2)
+        }
+        WRITE_SINGLE_REGISTER | WRITE_MULTIPLE_REGISTERS => {
+            let (first, count, values) = if function == WRITE_SINGLE_REGISTER {
+                if data.len() != 4 {
+                    return Err(ILLEGAL_DATA_VALUE);
+                }
+                (register(0), 1, &data[2..4])
+            } else {
+                if data.len() < 5 {
+                    return Err(ILLEGAL_DATA_VALUE);
+                }
+                let count = register(2);
+                if count == 0
+                    || count > MAX_WRITE_REGISTERS
+                    || data[4] as usize != count * 2
+                    || data.len() != 5 + count * 2
+                {
+                    return Err(ILLEGAL_DATA_VALUE);
+                }
+                (register(0), count, &data[5..])
+            };
+            if !write(first * 2..(first + count) * 2, values) {
+                return Err(ILLEGAL_DATA_ADDRESS);
+            }
+            // Both responses echo the address and the register and value or
+            // count.
+            response[1..6].copy_from_slice(&frame[1..6]);
+            Ok(6)
+        }
+        _ => Err(ILLEGAL_FUNCTION),
+    }
+}
+
+/// Ids for read-only allow buffers
+mod ro_allow {
+    /// The request PDU to send: the function code and its data.
+    pub const REQUEST: usize = 0;
+    /// The input registers of the slave.
+    pub const INPUT_REGISTERS: usize = 1;
+    /// The number of allow buffers the kernel stores for this grant
+    pub const COUNT: u8 = 2;
+}
+
+/// Ids for read-write allow buffers
+mod rw_allow {
+    /// The buffer response PDUs are received in.
+    pub const RESPONSE: usize = 0;
+    /// The holding registers of the slave.
+    pub const HOLDING_REGISTERS: usize = 1;
+    /// The number of allow buffers the kernel stores for this grant
+    pub const COUNT: u8 = 2;
+}
+
+mod upcall {
+    /// A transaction finished.
+    pub const TRANSACTION: usize = 0;
+    /// The master wrote holding registers of the slave.
+    pub const WRITTEN: usize = 1;
+    /// The number of upcalls the kernel stores for this grant
+    pub const COUNT: u8 = 2;
+}
+
+/// What the bus is doing.
+#[derive(Clone, Copy, PartialEq)]
+enum Bus {
+    /// Listening for frames.
+    Idle,
+    /// Waiting for the bus to be silent long enough to send a frame.
+    Silence,
+    Transmitting,
+    /// Holding the driver enable pin until the last byte is sent.
+    Draining,
+    /// Waiting for the response of a slave.
+    Response,
+}
+
+#[derive(Clone, Copy)]
+struct Timer<T: Ticks> {
+    reference: T,
+    dt: T,
+}
+
+impl<T: Ticks> Timer<T> {
+    fn expired(&self, now: T) -> bool {
+        !now.within_range(self.reference, self.reference.wrapping_add(self.dt))
+    }
+
+    fn remaining(&self, now: T) -> T {
+        if self.expired(now) {
+            T::from(0)
+        } else {
+            self.reference.wrapping_add(self.dt).wrapping_sub(now)
+        }
+    }
+}
+
+pub struct App {
+    /// The slave address and PDU length of the request to send.
+    request: Option<(u8, usize)>,
+    timeout_ms: u32,
+}
+
+impl Default for App {
+    fn default() -> App {
+        App {
+            request: None,
+            timeout_ms: DEFAULT_TIMEOUT_MS,
+        }
+    }
+}
+
+pub struct Modbus<'a, U: uart::UartData<'a>, A: Alarm<'a>> {
+    uart: &'a U,
+    alarm: &'a A,
+    driver_enable: Option<&'a dyn gpio::Pin>,
+    apps: Grant<
+        App,
+        UpcallCount<{ upcall::COUNT }>,
+        AllowRoCount<{ ro_allow::COUNT }>,
+        AllowRwCount<{ rw_allow::COUNT }>,
+    >,
+    /// The character time and the silent intervals, in microseconds.
+    character_us: u32,
+    t15_us: u32,
+    t35_us: u32,
+    bus: Cell<Bus>,
+    /// The timer of the bus state, if it has one.
+    timer: Cell<Option<Timer<A::Ticks>>>,
+    /// When the bus was last active.
+    last_activity: Cell<A::Ticks>,
+    /// How long the bus must be silent before the next frame is sent.
+    silence_us: Cell<u32>,
+    tx_buffer: TakeCell<'static, [u8]>,
+    tx_len: Cell<usize>,
+    /// The process whose transaction is in progress, with the address and
+    /// function the response must have.
+    master: OptionalCell<ProcessId>,
+    expected: Cell<(u8, u8)>,
+    /// The process serving the register map, and its address.
+    slave: OptionalCell<(ProcessId, u8)>,
+    rx_byte: TakeCell<'static, [u8]>,
+    rx_buffer: TakeCell<'static, [u8]>,
+    rx_len: Cell<usize>,
+    rx_corrupt: Cell<bool>,
+    /// Ends the frame being received after 3.5 character times of silence.
+    frame_timer: Cell<Option<Timer<A::Ticks>>>,
+}
+
+impl<'a, U: uart::UartData<'a>, A: Alarm<'a>> Modbus<'a, U, A> {
+    /// Create the capsule for a UART running at `baud_rate`. The frame
+    /// buffers must hold `MAX_FRAME_LEN` bytes.
+    pub fn new(
+        uart: &'a U,
+        alarm: &'a A,
+        driver_enable: Option<&'a dyn gpio::Pin>,
+        grant: Grant<
+            App,
+            UpcallCount<{ upcall::COUNT }>,
+            AllowRoCount<{ ro_allow::COUNT }>,
+            AllowRwCount<{ rw_allow::COUNT }>,
+        >,
+        baud_rate: u32,
+        tx_buffer: &'static mut [u8],
+        rx_buffer: &'static mut [u8],
+        rx_byte: &'static mut [u8],
+    ) -> Self {
+        let character_us = (CHARACTER_BITS * 1_000_000).div_ceil(baud_rate);
+        let (t15_us, t35_us) = if baud_rate > FIXED_TIMING_BAUD {
+            (FIXED_T15_US, FIXED_T35_US)
+        } else {
+            (character_us * 3 / 2, character_us * 7 / 2)
+        };
+        Modbus {
+            uart,
+            alarm,
+            driver_enable,
+            apps: grant,
+            character_us,
+            t15_us,
+            t35_us,
+            bus: Cell::new(Bus::Idle),
+            timer: Cell::new(None),
+            last_activity: Cell::new(A::Ticks::from(0)),
+            silence_us: Cell::new(t35_us),
+            tx_buffer: TakeCell::new(tx_buffer),
+            tx_len: Cell::new(0),
+            master: OptionalCell::empty(),
+            expected: Cell::new((0, 0)),
+            slave: OptionalCell::empty(),
+            rx_byte: TakeCell::new(rx_byte),
+            rx_buffer: TakeCell::new(rx_buffer),
+            rx_len: Cell::new(0),
+            rx_corrupt: Cell::new(false),
+            frame_timer: Cell::new(None),
+        }
+    }
+
+    /// Release the bus and start listening.
+    pub fn start(&self) {
+        self.driver_enable.map(|pin| {
+            pin.make_output();
+            pin.clear();
+        });
+        self.last_activity.set(self.alarm.now());
+        self.receive();
+    }
+
+    fn receive(&self) {
+        if let Some(buffer) = self.rx_byte.take() {
+            if let Err((_, buffer)) = self.uart.receive_buffer(buffer, 1) {
+                self.rx_byte.replace(buffer);
+            }
+        }
+    }
+
+    fn timer(&self, reference: A::Ticks, us: u32) -> Timer<A::Ticks> {
+        Timer {
+            reference,
+            dt: self.alarm.ticks_from_us(us),
+        }
+    }
+
+    /// Send the `len` bytes of the transmit buffer, adding the CRC, once the
+    /// bus has been silent long enough.
+    fn transmit(&self, len: usize) {
+        self.tx_buffer.map(|buffer| {
+            let crc = crc16(&buffer[..len]);
+            buffer[len..len + 2].copy_from_slice(&crc.to_le_bytes());
+        });
+        self.tx_len.set(len + 2);
+        self.bus.set(Bus::Silence);
+        self.timer.set(Some(
+            self.timer(self.last_activity.get(), self.silence_us.get()),
+        ));
+    }
+
+    fn send_frame(&self) {
+        let Some(buffer) = self.tx_buffer.take() else {
+            return;
+        };
+        self.driver_enable.map(|pin| pin.set());
+        self.bus.set(Bus::Transmitting);
+        if let Err((e, buffer)) = self.uart.transmit_buffer(buffer, self.tx_len.get()) {
+            self.tx_buffer.replace(buffer);
+            self.driver_enable.map(|pin| pin.clear());
+            self.sent(Err(e));
+        }
+    }
+
+    /// The frame left the transceiver, or couldn't be sent.
+    fn sent(&self, result: Result<(), ErrorCode>) {
+        self.last_activity.set(self.alarm.now());
+        self.silence_us.set(self.t35_us);
+        self.bus.set(Bus::Idle);
+        if self.master.is_none() {
+            // A slave response.
+            return;
+        }
+        let (address, _) = self.expected.get();
+        match result {
+            Err(e) => self.complete(Err(e), 0, 0),
+            Ok(()) if address == BROADCAST => {
+                self.silence_us.set(TURNAROUND_MS * 1000);
+                self.complete(Ok(()), 0, 0);
+            }
+            Ok(()) => {
+                let timeout_ms = self.master.map_or(DEFAULT_TIMEOUT_MS, |processid| {
+                    self.apps
+                        .enter(processid, |app, _| app.timeout_ms)
+                        .unwrap_or(DEFAULT_TIMEOUT_MS)
+                });
+                self.bus.set(Bus::Response);
+                self.timer
+                    .set(Some(self.timer(self.alarm.now(), timeout_ms * 1000)));
+            }
+        }
+    }
+
+    /// Finish the transaction in progress and start the next one.
+    fn complete(&self, result: Result<(), ErrorCode>, len: usize, exception: u8) {
+        self.bus.set(Bus::Idle);
+        self.timer.set(None);
+        self.master.take().map(|processid| {
+            let _ = self.apps.enter(processid, |_, kernel_data| {
+                kernel_data
+                    .schedule_upcall(
+                        upcall::TRANSACTION,
+                        (
+                            kernel::errorcode::into_statuscode(result),
+                            len,
+                            exception as usize,
+                        ),
+                    )
+                    .ok();
+            });
+        });
+        self.start_next();
+    }
+
+    /// Send the next request a process asked for, if the bus is free.
+    fn start_next(&self) {
+        if self.bus.get() != Bus::Idle || self.master.is_some() {
+            return;
+        }
+        for cntr in self.apps.iter() {
+            let processid = cntr.processid();
+            let started = cntr.enter(|app, kernel_data| {
+                let Some((address, len)) = app.request.take() else {
+                    return false;
+                };
+                match self.load_request(kernel_data, address, len) {
+                    Ok(function) => {
+                        self.expected.set((address, function));
+                        true
+                    }
+                    Err(e) => {
+                        kernel_data
+                            .schedule_upcall(
+                                upcall::TRANSACTION,
+                                (kernel::errorcode::into_statuscode(Err(e)), 0, 0),
+                            )
+                            .ok();
+                        false
+                    }
+                }
+            });
+            if started {
+                self.master.set(processid);
+                self.transmit(self.tx_len.get());
+                return;
+            }
+        }
+    }
+
+    /// Copy the request PDU of a process after the address, returning its
+    /// function code.
+    fn load_request(
+        &self,
+        kernel_data: &GrantKernelData,
+        address: u8,
+        len: usize,
+    ) -> Result<u8, ErrorCode> {
+        let buffer = self.tx_buffer.take().ok_or(ErrorCode::BUSY)?;
+        let result = kernel_data
+            .get_readonly_processbuffer(ro_allow::REQUEST)
+            .and_then(|request| {
+                request.enter(|data| {
+                    let data = data.get(0..len).ok_or(ErrorCode::SIZE)?;
+                    buffer[0] = address;
+                    data.copy_to_slice(&mut buffer[1..1 + len]);
+                    Ok(buffer[1])
+                })
+            })
+            .unwrap_or(Err(ErrorCode::RESERVE));
+        self.tx_buffer.replace(buffer);
+        self.tx_len.set(1 + len);
+        result
+    }
+
+    /// Take a received byte, `valid` if the UART reported no error.
+    fn byte_received(&self, byte: u8, valid: bool) {
+        let now = self.alarm.now();
+        if matches!(self.bus.get(), Bus::Transmitting | Bus::Draining) {
+            // The echo of our own frame.
+            return;
+        }
+        if self.frame_timer.get().is_some() {
+            let gap_us = self
+                .alarm
+                .ticks_to_us(now.wrapping_sub(self.last_activity.get()));
+            if gap_us > self.character_us + self.t15_us {
+                self.rx_corrupt.set(true);
+            }
+        } else {
+            self.rx_len.set(0);
+            self.rx_corrupt.set(false);
+        }
+        let len = self.rx_len.get();
+        if len < MAX_FRAME_LEN {
+            self.rx_buffer.map(|buffer| buffer[len] = byte);
+            self.rx_len.set(len + 1);
+        } else {
+            self.rx_corrupt.set(true);
+        }
+        if !valid {
+            self.rx_corrupt.set(true);
+        }
+        self.last_activity.set(now);
+        self.frame_timer.set(Some(self.timer(now, self.t35_us)));
+        if self.bus.get() == Bus::Silence {
+            self.timer.set(Some(self.timer(now, self.silence_us.get())));
+        }
+    }
+
+    /// Handle a frame that ended, if it is intact.
+    fn frame_received(&self) {
+        if self.rx_corrupt.get() {
+            return;
+        }
+        let Some(buffer) = self.rx_buffer.take() else {
+            return;
+        };
+        if let Some(frame) = check_frame(&buffer[..self.rx_len.get()]) {
+            match self.bus.get() {
+                Bus::Response => self.response_received(frame),
+                Bus::Idle => self.request_received(frame),
+                _ => {}
+            }
+        }
+        self.rx_buffer.replace(buffer);
+    }
+
+    /// Give a response for the transaction in progress to its process.
+    fn response_received(&self, frame: &[u8]) {
+        let Some((pdu, exception)) = parse_response(frame, self.expected.get()) else {
+            return;
+        };
+        let result = self.master.map_or(Err(ErrorCode::FAIL), |processid| {
+            self.apps
+                .enter(processid, |_, kernel_data| {
+                    kernel_data
+                        .get_readwrite_processbuffer(rw_allow::RESPONSE)
+                        .and_then(|response| {
+                            response.mut_enter(|data| {
+                                let data = data.get(0..pdu.len()).ok_or(ErrorCode::SIZE)?;
+                                data.copy_from_slice(pdu);
+                                Ok(())
+                            })
+                        })
+                        .unwrap_or(Err(ErrorCode::RESERVE))
+                })
+                .unwrap_or_else(|err| Err(err.into()))
+        });
+        self.complete(result, pdu.len(), exception);
+    }
+
+    /// Answer a request for the slave from the register map.
+    fn request_received(&self, frame: &[u8]) {
+        let Some((processid, own_address)) = self.slave.get() else {
+            return;
+        };
+        let address = frame[0];
+        if address != own_address && address != BROADCAST {
+            return;
+        }
+        let Some(buffer) = self.tx_buffer.take() else {
+            return;
+        };
+        let served = self.apps.enter(processid, |_, kernel_data| {
+            let read = |function, range: Range<usize>, out: &mut [u8]| {
+                let copied = if function == READ_HOLDING_REGISTERS {
+                    kernel_data
+                        .get_readwrite_processbuffer(rw_allow::HOLDING_REGISTERS)
+                        .and_then(|registers| {
+                            registers.enter(|registers| {
+                                registers.get(range).map(|r| r.copy_to_slice(out))
+                            })
+                        })
+                } else {
+                    kernel_data
+                        .get_readonly_processbuffer(ro_allow::INPUT_REGISTERS)
+                        .and_then(|registers| {
+                            registers.enter(|registers| {
+                                registers.get(range).map(|r| r.copy_to_slice(out))
+                            })
+                        })
+                };
+                copied.ok().flatten().is_some()
+            };
+            let write = |range: Range<usize>, values: &[u8]| {
+                let (first, count) = (range.start / 2, range.len() / 2);
+                let written = kernel_data
+                    .get_readwrite_processbuffer(rw_allow::HOLDING_REGISTERS)
+                    .and_then(|registers| {
+                        registers.mut_enter(|registers| {
+                            registers.get(range).map(|r| r.copy_from_slice(values))
+                        })
+                    })
+                    .ok()
+                    .flatten()
+                    .is_some();
+                if written {
+                    kernel_data
+                        .schedule_upcall(upcall::WRITTEN, (first, count, 0))
+                        .ok();
+                }
+                written
+            };
+            let len = match serve(frame, buffer, &read, &write) {
+                Ok(len) => len,
+                Err(exception) => {
+                    buffer[1] = frame[1] | EXCEPTION;
+                    buffer[2] = exception;
+                    3
+                }
+            };
+            buffer[0] = own_address;
+            len
+        });
+        self.tx_buffer.replace(buffer);
+        match served {
+            // Broadcasts get no response.
+            Ok(len) if address != BROADCAST => self.transmit(len),
+            Ok(_) => {}
+            Err(_) => self.slave.clear(),
+        }
+    }
+
+    /// Set the alarm for the end of the frame being received or the timer
+    /// of the bus, whichever is earlier.
+    fn schedule_alarm(&self) {
+        let now = self.alarm.now();
+        let earliest = [self.frame_timer.get(), self.timer.get()]
+            .iter()
+            .flatten()
+            .map(|timer| timer.remaining(now))
+            .min();
+        match earliest {
+            Some(dt) => self.alarm.set_alarm(now, cmp::max(dt, A::Ticks::from(1))),
+            None => {
+                let _ = self.alarm.disarm();
+            }
+        }
+    }
+}
+
+impl<'a, U: uart::UartData<'a>, A: Alarm<'a>> AlarmClient for Modbus<'a, U, A> {
+    fn alarm(&self) {
+        let now = self.alarm.now();
+        if self
+            .frame_timer
+            .get()
+            .is_some_and(|timer| timer.expired(now))
+        {
+            self.frame_timer.set(None);
+            self.frame_received();
+        }
+        // Frames being received hold off sending and timeouts until they end.
+        if self.frame_timer.get().is_none()
+            && self.timer.get().is_some_and(|timer| timer.expired(now))
+        {
+            self.timer.set(None);
+            match self.bus.get() {
+                Bus::Silence => self.send_frame(),
+                Bus::Draining => {
+                    self.driver_enable.map(|pin| pin.clear());
+                    self.sent(Ok(()));
+                }
+                Bus::Response => self.complete(Err(ErrorCode::NOACK), 0, 0),
+                Bus::Idle | Bus::Transmitting => {}
+            }
+        }
+        self.schedule_alarm();
+    }
+}
+
+impl<'a, U: uart::UartData<'a>, A: Alarm<'a>> uart::TransmitClient for Modbus<'a, U, A> {
+    fn transmitted_buffer(
+        &self,
+        tx_buffer: &'static mut [u8],
+        _tx_len: usize,
+        rval: Result<(), ErrorCode>,
+    ) {
+        self.tx_buffer.replace(tx_buffer);
+        match rval {
+            Ok(()) => {
+                self.bus.set(Bus::Draining);
+                self.timer
+                    .set(Some(self.timer(self.alarm.now(), self.character_us)));
+            }
+            Err(e) => {
+                self.driver_enable.map(|pin| pin.clear());
+                self.sent(Err(e));
+            }
+        }
+        self.schedule_alarm();
+    }
+}
+
+impl<'a, U: uart::UartData<'a>, A: Alarm<'a>> uart::ReceiveClient for Modbus<'a, U, A> {
+    fn received_buffer(
+        &self,
+        rx_buffer: &'static mut [u8],
+        rx_len: usize,
+        rval: Result<(), ErrorCode>,
+        error: uart::Error,
+    ) {
+        let byte = rx_buffer[0];
+        self.rx_byte.replace(rx_buffer);
+        if rx_len == 1 {
+            self.byte_received(byte, rval.is_ok() && error == uart::Error::None);
+        }
+        self.receive();
+        self.schedule_alarm();
+    }
+}
+
+impl<'a, U: uart::UartData<'a>, A: Alarm<'a>> SyscallDriver for Modbus<'a, U, A> {
+    /// Command interface.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver existence check.
+    /// - `1`: Send the request PDU in the read-only allow buffer, of `data2`
+    ///   bytes, to the slave at address `data1`, or to all slaves if it is
+    ///   0. Fails with `RESERVE` while a process is the slave.
+    /// - `2`: Set how long to wait for responses to `data1` ms.
+    /// - `3`: Make the process the slave at address `data1`, serving the
+    ///   register map it allowed.
+    /// - `4`: Stop being the slave.
+    fn command(
+        &self,
+        command_num: usize,
+        data1: usize,
+        data2: usize,
+        processid: ProcessId,
+    ) -> CommandReturn {
+        match command_num {
+            0 => CommandReturn::success(),
+            1 => {
+                if live_slave(&self.slave, |owner| {
+                    self.apps.enter(owner, |_, _| {}).is_ok()
+                })
+                .is_some()
+                {
+                    return CommandReturn::failure(ErrorCode::RESERVE);
+                }
+                if data1 > MAX_ADDRESS {
+                    return CommandReturn::failure(ErrorCode::INVAL);
+                }
+                if data2 == 0 || data2 > MAX_PDU_LEN {
+                    return CommandReturn::failure(ErrorCode::SIZE);
+                }
+                let in_progress = self.master.contains(&processid);
+                let r = self
+                    .apps
+                    .enter(processid, |app, _| {
+                        if in_progress || app.request.is_some() {
+                            return Err(ErrorCode::BUSY);
+                        }
+                        app.request = Some((data1 as u8, data2));
+                        Ok(())
+                    })
+                    .unwrap_or_else(|err| Err(err.into()));
+                if r.is_ok() {
+                    self.start_next();
+                    self.schedule_alarm();
+                }
+                r.into()
+            }
+            2 => {
+                if data1 == 0 || data1 > u32::MAX as usize / 1000 {
+                    return CommandReturn::failure(ErrorCode::INVAL);
+                }
+                self.apps
+                    .enter(processid, |app, _| {
+                        app.timeout_ms = data1 as u32;
+                    })
+                    .map_err(ErrorCode::from)
+                    .into()
+            }
+            3 => {
+                if data1 == BROADCAST as usize || data1 > MAX_ADDRESS {
+                    return CommandReturn::failure(ErrorCode::INVAL);
+                }
+                let taken = live_slave(&self.slave, |owner| {
+                    self.apps.enter(owner, |_, _| {}).is_ok()
+                })
+                .map_or(false, |(owner, _)| owner != processid);
+                if taken {
+                    return CommandReturn::failure(ErrorCode::RESERVE);
+                }
+                if self.master.is_some() {
+                    return CommandReturn::failure(ErrorCode::BUSY);
+                }
+                let r = self.apps.enter(processid, |_, _| {});
+                if r.is_ok() {
+                    self.slave.set((processid, data1 as u8));
+                }
+                r.map_err(ErrorCode::from).into()
+            }
+            4 => match self.slave.get() {
+                Some((owner, _)) if owner == processid => {
+                    self.slave.clear();
+                    CommandReturn::success()
+                }
+                Some(_) => CommandReturn::failure(ErrorCode::RESERVE),
+                None => CommandReturn::failure(ErrorCode::ALREADY),
+            },
+            _ => CommandReturn::failure(ErrorCode::NOSUPPORT),
+        }
+    }
+
+    fn allocate_grant(&self, processid: ProcessId) -> Result<(), kernel::process::Error> {
+        self.apps.enter(processid, |_, _| {})
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use core::cell::RefCell;
+    use std::vec::Vec;
+
+    /// A frame with its CRC appended.
+    fn frame(bytes: &[u8]) -> Vec<u8> {
+        let mut frame = bytes.to_vec();
+        frame.extend_from_slice(&crc16(bytes).to_le_bytes());
+        frame
+    }
+
+    /// A register map of 4 holding and 2 input registers.
+    struct Map {
+        holding: RefCell<[u8; 8]>,
+        input: [u8; 4],
+        written: Cell<Option<(usize, usize)>>,
+    }
+
+    impl Map {
+        fn new() -> Self {
+            Map {
+                holding: RefCell::new([0x00, 0x01, 0x00, 0x02, 0x00, 0x03, 0x00, 0x04]),
+                input: [0x12, 0x34, 0x56, 0x78],
+                written: Cell::new(None),
+            }
+        }
+
+        /// Serve the request in `frame`, without its CRC, returning the
+        /// response after the address.
+        fn serve(&self, frame: &[u8]) -> Result<Vec<u8>, u8> {
+            let read = |function, range: Range<usize>, out: &mut [u8]| {
+                let holding = self.holding.borrow();
+                let registers: &[u8] = if function == READ_HOLDING_REGISTERS {
+                    &*holding
+                } else {
+                    &self.input
+                };
+                registers
+                    .get(range)
+                    .map(|r| out.copy_from_slice(r))
+                    .is_some()
+            };
+            let write = |range: Range<usize>, values: &[u8]| {
+                let (first, count) = (range.start / 2, range.len() / 2);
+                let written = self
+                    .holding
+                    .borrow_mut()
+                    .get_mut(range)
+                    .map(|r| r.copy_from_slice(values))
+                    .is_some();
+                if written {
+                    self.written.set(Some((first, count)));
+                }
+                written
+            };
+            let mut response = [0; MAX_FRAME_LEN];
+            serve(frame, &mut response, &read, &write).map(|len| response[1..len].to_vec())
+        }
+    }
+
+    #[test]
+    fn slave_exits() {
+        let slave = OptionalCell::new((1, 0x11));
+        assert_eq!(live_slave(&slave, |_| true), Some((1, 0x11)));
+        assert_eq!(slave.get(), Some((1, 0x11)));
+        // Once the process has exited the bus is free for masters again.
+        assert_eq!(live_slave(&slave, |id| id != 1), None);
+        assert_eq!(slave.get(), None);
+        assert_eq!(live_slave(&slave, |_| true), None);
+    }
+
+    #[test]
+    fn crc16_vectors() {
+        // The check value of CRC-16/MODBUS.
+        assert_eq!(crc16(b"123456789"), 0x4B37);
+        // Read one holding register of slave 1, sent as 01 03 00 00 00 01 84 0A.
+        assert_eq!(crc16(&[0x01, 0x03, 0x00, 0x00, 0x00, 0x01]), 0x0A84);
+        assert_eq!(
+            frame(&[0x01, 0x03, 0x00, 0x00, 0x00, 0x01]),
+            [0x01, 0x03, 0x00, 0x00, 0x00, 0x01, 0x84, 0x0A]
+        );
+        // The illegal data address exception of the Modbus over serial line
+        // specification, 01 83 02 C0 F1.
+        assert_eq!(crc16(&[0x01, 0x83, 0x02]), 0xF1C0);
+        assert_eq!(crc16(&[]), 0xFFFF);
+    }
+
+    #[test]
+    fn truncated_and_bad_crc_frames() {
+        let good = frame(&[0x01, 0x03, 0x02, 0x01, 0x02]);
+        assert_eq!(check_frame(&good), Some(&good[..5]));
+        // The shortest frame is an address, a function and the CRC.
+        let short = frame(&[0x01, 0x03]);
+        assert_eq!(check_frame(&short), Some(&short[..2]));
+        assert_eq!(check_frame(&short[..3]), None);
+        assert_eq!(check_frame(&frame(&[0x01])), None);
+        assert_eq!(check_frame(&[]), None);
+        // A frame cut short, and flipped bits in the data or the CRC.
+        assert_eq!(check_frame(&good[..6]), None);
+        for i in 0..good.len() {
+            let mut bad = good.clone();
+            bad[i] ^= 0x10;
+            assert_eq!(check_frame(&bad), None);
+        }
+    }
+
+    #[test]
+    fn responses() {
+        let response = frame(&[0x01, 0x03, 0x02, 0x01, 0x02]);
+        let response = check_frame(&response).unwrap();
+        assert_eq!(
+            parse_response(response, (0x01, READ_HOLDING_REGISTERS)),
+            Some((&[0x03, 0x02, 0x01, 0x02][..], 0))
+        );
+        // From another slave, or to another function.
+        assert_eq!(
+            parse_response(response, (0x02, READ_HOLDING_REGISTERS)),
+            None
+        );
+        assert_eq!(parse_response(response, (0x01, READ_INPUT_REGISTERS)), None);
+    }
+
+    #[test]
+    fn exception_responses() {
+        let illegal_function = frame(&[0x01, 0x85, ILLEGAL_FUNCTION]);
+        let illegal_function = check_frame(&illegal_function).unwrap();
+        assert_eq!(
+            parse_response(illegal_function, (0x01, 0x05)),
+            Some((&[0x85, ILLEGAL_FUNCTION][..], ILLEGAL_FUNCTION))
+        );
+        let illegal_address = [0x01, 0x83, 0x02, 0xC0, 0xF1];
+        let illegal_address = check_frame(&illegal_address).unwrap();
+        assert_eq!(
+            parse_response(illegal_address, (0x01, READ_HOLDING_REGISTERS)),
+            Some((&[0x83, ILLEGAL_DATA_ADDRESS][..], ILLEGAL_DATA_ADDRESS))
+        );
+        assert_eq!(parse_response(illegal_address, (0x01, 0x05)), None);
+        // An exception without its code.
+        let truncated = frame(&[0x01, 0x83]);
+        let truncated = check_frame(&truncated).unwrap();
+        assert_eq!(
+            parse_response(truncated, (0x01, READ_HOLDING_REGISTERS)),
+            None
+        );
+    }
+
+    #[test]
+    fn read_registers() {
+        let map = Map::new();
+        assert_eq!(
+            map.serve(&[0x01, 0x03, 0x00, 0x01, 0x00, 0x02]),
+            Ok([0x03, 0x04, 0x00, 0x02, 0x00, 0x03].to_vec())
+        );
+        assert_eq!(
+            map.serve(&[0x01, 0x04, 0x00, 0x00, 0x00, 0x02]),
+            Ok([0x04, 0x04, 0x12, 0x34, 0x56, 0x78].to_vec())
+        );
+        assert_eq!(map.written.get(), None);
+    }
+
+    #[test]
+    fn write_registers() {
+        let map = Map::new();
+        let request = [0x01, 0x06, 0x00, 0x03, 0xAB, 0xCD];
+        assert_eq!(map.serve(&request), Ok(request[1..].to_vec()));
+        assert_eq!(map.written.get(), Some((3, 1)));
+        let request = [
+            0x01, 0x10, 0x00, 0x00, 0x00, 0x02, 0x04, 0x11, 0x22, 0x33, 0x44,
+        ];
+        assert_eq!(map.serve(&request), Ok(request[1..6].to_vec()));
+        assert_eq!(map.written.get(), Some((0, 2)));
+        assert_eq!(
+            *map.holding.borrow(),
+            [0x11, 0x22, 0x33, 0x44, 0x00, 0x03, 0xAB, 0xCD]
+        );
+    }
+
+    #[test]
+    fn illegal_function_and_address() {
+        let map = Map::new();
+        // Read coils and read exception status aren't served.
+        assert_eq!(
+            map.serve(&[0x01, 0x01, 0x00, 0x00, 0x00, 0x01]),
+            Err(ILLEGAL_FUNCTION)
+        );
+        assert_eq!(map.serve(&[0x01, 0x07]), Err(ILLEGAL_FUNCTION));
+        // Registers past the end of the map.
+        assert_eq!(
+            map.serve(&[0x01, 0x03, 0x00, 0x03, 0x00, 0x02]),
+            Err(ILLEGAL_DATA_ADDRESS)
+        );
+        assert_eq!(
+            map.serve(&[0x01, 0x04, 0x00, 0x02, 0x00, 0x01]),
+            Err(ILLEGAL_DATA_ADDRESS)
+        );
+        assert_eq!(
+            map.serve(&[0x01, 0x06, 0x00, 0x04, 0x00, 0x00]),
+            Err(ILLEGAL_DATA_ADDRESS)
+        );
+        assert_eq!(
+            map.serve(&[0x01, 0x10, 0x00, 0x03, 0x00, 0x02, 0x04, 0, 0, 0, 0]),
+            Err(ILLEGAL_DATA_ADDRESS)
+        );
+        assert_eq!(map.written.get(), None);
+        assert_eq!(map.holding.borrow()[6..], [0x00, 0x04]);
+    }
+
+    #[test]
+    fn illegal_data_values() {
+        let map = Map::new();
+        for request in [
+            // Truncated requests.
+            &[0x01, 0x03][..],
+            &[0x01, 0x03, 0x00, 0x00, 0x00],
+            &[0x01, 0x06, 0x00, 0x00, 0x00],
+            &[0x01, 0x10, 0x00, 0x00, 0x00],
+            &[0x01, 0x10, 0x00, 0x00, 0x00, 0x01, 0x02, 0x00],
+            // No registers, or too many.
+            &[0x01, 0x03, 0x00, 0x00, 0x00, 0x00],
+            &[0x01, 0x04, 0x00, 0x00, 0x00, 126],
+            &[0x01, 0x10, 0x00, 0x00, 0x00, 0x00, 0x00],
+            // A byte count that doesn't match the registers.
+            &[0x01, 0x10, 0x00, 0x00, 0x00, 0x01, 0x04, 0x00, 0x00],
+        ] {
+            assert_eq!(
+                map.serve(request),
+                Err(ILLEGAL_DATA_VALUE),
+                "{request:02x?}"
+            );
+        }
+        assert_eq!(map.written.get(), None);
+    }
+}
@@ -13,6 +13,7 @@ pub mod icmpv6;
 pub mod ieee802154;
 pub mod ipv6;
 pub mod network_capabilities;
+pub mod sntp;
 pub mod tcp;
 pub mod thread;
 pub mod udp;
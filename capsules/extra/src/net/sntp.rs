@@ -0,0 +1,554 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! SNTP (RFC 4330) client keeping a software clock in time.
+//!
+//! Queries an NTP server over UDP at a fixed interval and corrects a
+//! `SoftwareDateTime` clock by the offset measured: offsets above 128 ms
+//! step the clock, as the first one usually does, and smaller ones are
+//! slewed in so that the time doesn't jump. Failed queries are retried
+//! after a minute.
+//!
+//! Replies are only taken from the configured server and port, and must
+//! echo the transmit timestamp of the request. Replies from unsynchronized
+//! servers and kiss-of-death replies fail the query; after the latter the
+//! client waits a whole interval before asking again.
+//!
+//! Processes can read the time with microseconds and how well it is
+//! synchronized, to timestamp data, and ask for a query.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! let sntp = components::sntp::SntpComponent::new(
+//!     board_kernel,
+//!     capsules_extra::net::sntp::DRIVER_NUM,
+//!     udp_send_mux,
+//!     udp_recv_mux,
+//!     udp_port_table,
+//!     mux_alarm,
+//!     clock,
+//!     SERVER_ADDR,
+//!     SNTP_LOCAL_PORT,
+//!     1024,
+//! )
+//! .finalize(components::sntp_component_static!(nrf52840::rtc::Rtc));
+//! ```
+
+use core::cell::Cell;
+
+use crate::net::ipv6::ip_utils::IPAddr;
+use crate::net::network_capabilities::NetworkCapability;
+use crate::net::udp::udp_recv::UDPRecvClient;
+use crate::net::udp::udp_send::{UDPSendClient, UDPSender};
+use crate::software_date_time::SoftwareDateTime;
+
+use kernel::grant::{AllowRoCount, AllowRwCount, Grant, UpcallCount};
+use kernel::hil::time::{Alarm, AlarmClient, ConvertTicks};
+use kernel::syscall::{CommandReturn, SyscallDriver};
+use kernel::utilities::cells::MapCell;
+use kernel::utilities::leasable_buffer::SubSliceMut;
+use kernel::{ErrorCode, ProcessId};
+
+/// Syscall driver number.
+use capsules_core::driver;
+pub const DRIVER_NUM: usize = driver::NUM::Sntp as usize;
+
+/// The UDP port of NTP servers.
+pub const NTP_PORT: u16 = 123;
+/// The size of NTP packets without extensions.
+pub const PACKET_LEN: usize = 48;
+
+/// How long to wait for a reply.
+const REPLY_TIMEOUT_MS: u32 = 2000;
+/// How long to wait before asking again after a failed query.
+const RETRY_INTERVAL_S: u32 = 60;
+/// Offsets above this step the clock rather than being slewed in.
+const STEP_THRESHOLD_US: u64 = 128_000;
+/// The time is no longer synchronized after this many intervals without a
+/// successful query.
+const STALE_INTERVALS: u64 = 4;
+/// The longest alarm, so that fast alarms don't wrap while waiting.
+const MAX_STEP_S: u32 = 8;
+
+/// Seconds from the NTP epoch, 1900, to the Unix epoch.
+const NTP_UNIX_OFFSET_S: u64 = 2_208_988_800;
+const US_PER_SECOND: u64 = 1_000_000;
+
+/// Version 4, client mode.
+const CLIENT_REQUEST: u8 = 4 << 3 | 3;
+const MODE_SERVER: u8 = 4;
+/// The versions of replies accepted, as RFC 4330 clients do.
+const MIN_VERSION: u8 = 1;
+const MAX_VERSION: u8 = 4;
+const LEAP_UNSYNCHRONIZED: u8 = 3;
+
+mod upcall {
+    /// A query finished.
+    pub const SYNCED: usize = 0;
+    /// The number of upcalls the kernel stores for this grant
+    pub const COUNT: u8 = 1;
+}
+
+#[derive(Default)]
+pub struct App {}
+
+#[derive(Clone, Copy, PartialEq)]
+enum State {
+    /// Waiting for the next query.
+    Idle,
+    Sending,
+    /// Waiting for the reply.
+    Waiting,
+}
+
+/// The NTP timestamp of `us` microseconds since the Unix epoch.
+fn ntp_from_unix_us(us: u64) -> [u8; 8] {
+    let seconds = (us / US_PER_SECOND + NTP_UNIX_OFFSET_S) as u32;
+    let fraction = (((us % US_PER_SECOND) << 32) / US_PER_SECOND) as u32;
+    let mut timestamp = [0; 8];
+    timestamp[..4].copy_from_slice(&seconds.to_be_bytes());
+    timestamp[4..].copy_from_slice(&fraction.to_be_bytes());
+    timestamp
+}
+
+/// The microseconds since the Unix epoch of an NTP timestamp.
+fn unix_us_from_ntp(timestamp: &[u8]) -> u64 {
+    let seconds = u32::from_be_bytes([timestamp[0], timestamp[1], timestamp[2], timestamp[3]]);
+    let fraction = u32::from_be_bytes([timestamp[4], timestamp[5], timestamp[6], timestamp[7]]);
+    // Seconds wrap in 2036; timestamps before 1968 are taken to be after,
+    // and ones from 1968 to 1970 are the Unix epoch.
+    let seconds = if seconds & (1 << 31) == 0 {
+        seconds as u64 + (1 << 32)
+    } else {
+        seconds as u64
+    };
+    match seconds.checked_sub(NTP_UNIX_OFFSET_S) {
+        Some(seconds) => seconds * US_PER_SECOND + ((fraction as u64 * US_PER_SECOND) >> 32),
+        None => 0,
+    }
+}
+
+/// Check a reply to the request with transmit timestamp `origin`,
+/// returning the receive and transmit timestamps of the server, or `None`
+/// if the packet isn't a reply to it.
+fn parse_reply(packet: &[u8], origin: [u8; 8]) -> Option<Result<(u64, u64), ErrorCode>> {
+    let version = packet.first().map_or(0, |byte| byte >> 3 & 0x7);
+    if packet.len() < PACKET_LEN
+        || packet[0] & 0x7 != MODE_SERVER
+        || !(MIN_VERSION..=MAX_VERSION).contains(&version)
+        || packet[24..32] != origin
+    {
+        return None;
+    }
+    let stratum = packet[1];
+    if stratum == 0 {
+        // Kiss of death: the server wants fewer queries.
+        return Some(Err(ErrorCode::BUSY));
+    }
+    if packet[0] >> 6 == LEAP_UNSYNCHRONIZED || stratum > 15 || packet[40..48] == [0; 8] {
+        return Some(Err(ErrorCode::FAIL));
+    }
+    Some(Ok((
+        unix_us_from_ntp(&packet[32..40]),
+        unix_us_from_ntp(&packet[40..48]),
+    )))
+}
+
+/// The offset of the clock and the round trip delay of a query sent at
+/// `t1`, received by the server at `t2`, answered at `t3` and whose reply
+/// arrived at `t4`.
+fn offset_and_delay(t1: u64, t2: u64, t3: u64, t4: u64) -> (i128, u64) {
+    let (t1, t2, t3, t4) = (t1 as i128, t2 as i128, t3 as i128, t4 as i128);
+    let offset = ((t2 - t1) + (t3 - t4)) / 2;
+    let delay = ((t4 - t1) - (t3 - t2)).max(0);
+    (offset, delay as u64)
+}
+
+pub struct SntpClient<'a, A: Alarm<'a>> {
+    sender: &'a dyn UDPSender<'a>,
+    alarm: &'a A,
+    clock: &'a SoftwareDateTime<'a, A>,
+    apps: Grant<App, UpcallCount<{ upcall::COUNT }>, AllowRoCount<0>, AllowRwCount<0>>,
+    net_cap: &'static NetworkCapability,
+    server: Cell<IPAddr>,
+    poll_interval_s: u32,
+    buffer: MapCell<SubSliceMut<'static, u8>>,
+    state: Cell<State>,
+    /// The seconds left before the next query.
+    wait_s: Cell<u32>,
+    /// The transmit timestamp of the request, which replies echo.
+    origin: Cell<[u8; 8]>,
+    /// The time the request was sent at.
+    sent_us: Cell<u64>,
+    /// The time of the last successful query.
+    synced_us: Cell<Option<u64>>,
+    /// The round trip delay of the last successful query.
+    delay_us: Cell<u64>,
+}
+
+impl<'a, A: Alarm<'a>> SntpClient<'a, A> {
+    pub fn new(
+        sender: &'a dyn UDPSender<'a>,
+        alarm: &'a A,
+        clock: &'a SoftwareDateTime<'a, A>,
+        grant: Grant<App, UpcallCount<{ upcall::COUNT }>, AllowRoCount<0>, AllowRwCount<0>>,
+        net_cap: &'static NetworkCapability,
+        server: IPAddr,
+        poll_interval_s: u32,
+        buffer: SubSliceMut<'static, u8>,
+    ) -> Self {
+        SntpClient {
+            sender,
+            alarm,
+            clock,
+            apps: grant,
+            net_cap,
+            server: Cell::new(server),
+            poll_interval_s,
+            buffer: MapCell::new(buffer),
+            state: Cell::new(State::Idle),
+            wait_s: Cell::new(0),
+            origin: Cell::new([0; 8]),
+            sent_us: Cell::new(0),
+            synced_us: Cell::new(None),
+            delay_us: Cell::new(0),
+        }
+    }
+
+    /// Send the first query.
+    pub fn start(&self) {
+        self.wait(0);
+    }
+
+    /// Query `server` from the next query on.
+    pub fn set_server(&self, server: IPAddr) {
+        self.server.set(server);
+    }
+
+    /// Wait `seconds` before the next query.
+    fn wait(&self, seconds: u32) {
+        self.state.set(State::Idle);
+        self.wait_s.set(seconds);
+        self.next_step();
+    }
+
+    fn next_step(&self) {
+        let step = self.wait_s.get().min(MAX_STEP_S);
+        if step == 0 {
+            self.query();
+            return;
+        }
+        self.wait_s.set(self.wait_s.get() - step);
+        self.alarm
+            .set_alarm(self.alarm.now(), self.alarm.ticks_from_seconds(step));
+    }
+
+    fn query(&self) {
+        let Some(mut buffer) = self.buffer.take() else {
+            self.wait(RETRY_INTERVAL_S);
+            return;
+        };
+        let sent_us = self.clock.now_us();
+        let origin = ntp_from_unix_us(sent_us);
+        buffer.reset();
+        buffer.slice(0..PACKET_LEN);
+        let packet = buffer.as_slice();
+        packet.fill(0);
+        packet[0] = CLIENT_REQUEST;
+        packet[40..48].copy_from_slice(&origin);
+        self.origin.set(origin);
+        self.sent_us.set(sent_us);
+
+        self.state.set(State::Sending);
+        if let Err(buffer) = self
+            .sender
+            .send_to(self.server.get(), NTP_PORT, buffer, self.net_cap)
+        {
+            self.buffer.replace(buffer);
+            self.finish(Err(ErrorCode::FAIL), RETRY_INTERVAL_S);
+        }
+    }
+
+    fn finish(&self, result: Result<(), ErrorCode>, wait_s: u32) {
+        self.wait(wait_s);
+        let delay_ms = (self.delay_us.get() / 1000) as usize;
+        for cntr in self.apps.iter() {
+            cntr.enter(|_, kernel_data| {
+                kernel_data
+                    .schedule_upcall(
+                        upcall::SYNCED,
+                        (kernel::errorcode::into_statuscode(result), delay_ms, 0),
+                    )
+                    .ok();
+            });
+        }
+    }
+
+    /// Correct the clock from the timestamps of a query.
+    fn synchronize(&self, server_receive_us: u64, server_transmit_us: u64, received_us: u64) {
+        let (offset, delay) = offset_and_delay(
+            self.sent_us.get(),
+            server_receive_us,
+            server_transmit_us,
+            received_us,
+        );
+
+        if offset.unsigned_abs() > STEP_THRESHOLD_US as u128 {
+            let now = self.clock.now_us() as i128;
+            self.clock.step((now + offset).max(0) as u64);
+        } else {
+            self.clock.slew(offset as i64);
+        }
+        self.synced_us.set(Some(self.clock.now_us()));
+        self.delay_us.set(delay);
+    }
+
+    /// 0 if the clock was never synchronized, 1 if it is and 2 if the last
+    /// successful query is too old, with the seconds since it.
+    fn status(&self) -> (u32, u32) {
+        match self.synced_us.get() {
+            None => (0, u32::MAX),
+            Some(synced_us) => {
+                let since_s = self.clock.now_us().saturating_sub(synced_us) / US_PER_SECOND;
+                let stale = since_s > STALE_INTERVALS * self.poll_interval_s as u64;
+                (
+                    if stale { 2 } else { 1 },
+                    since_s.min(u32::MAX as u64) as u32,
+                )
+            }
+        }
+    }
+}
+
+impl<'a, A: Alarm<'a>> AlarmClient for SntpClient<'a, A> {
+    fn alarm(&self) {
+        match self.state.get() {
+            State::Idle => self.next_step(),
+            State::Waiting => self.finish(Err(ErrorCode::NOACK), RETRY_INTERVAL_S),
+            State::Sending => {}
+        }
+    }
+}
+
+impl<'a, A: Alarm<'a>> UDPSendClient for SntpClient<'a, A> {
+    fn send_done(&self, result: Result<(), ErrorCode>, dgram: SubSliceMut<'static, u8>) {
+        self.buffer.replace(dgram);
+        if self.state.get() != State::Sending {
+            return;
+        }
+        match result {
+            Ok(()) => {
+                self.state.set(State::Waiting);
+                self.alarm
+                    .set_alarm(self.alarm.now(), self.alarm.ticks_from_ms(REPLY_TIMEOUT_MS));
+            }
+            Err(e) => self.finish(Err(e), RETRY_INTERVAL_S),
+        }
+    }
+}
+
+impl<'a, A: Alarm<'a>> UDPRecvClient for SntpClient<'a, A> {
+    fn receive(
+        &self,
+        src_addr: IPAddr,
+        _dst_addr: IPAddr,
+        src_port: u16,
+        _dst_port: u16,
+        payload: &[u8],
+    ) {
+        let received_us = self.clock.now_us();
+        if self.state.get() != State::Waiting
+            || src_port != NTP_PORT
+            || src_addr != self.server.get()
+        {
+            return;
+        }
+        match parse_reply(payload, self.origin.get()) {
+            None => {}
+            Some(Ok((server_receive_us, server_transmit_us))) => {
+                let _ = self.alarm.disarm();
+                self.synchronize(server_receive_us, server_transmit_us, received_us);
+                self.finish(Ok(()), self.poll_interval_s);
+            }
+            Some(Err(ErrorCode::BUSY)) => {
+                let _ = self.alarm.disarm();
+                self.finish(Err(ErrorCode::BUSY), self.poll_interval_s);
+            }
+            Some(Err(e)) => {
+                let _ = self.alarm.disarm();
+                self.finish(Err(e), RETRY_INTERVAL_S);
+            }
+        }
+    }
+}
+
+impl<'a, A: Alarm<'a>> SyscallDriver for SntpClient<'a, A> {
+    /// Command interface.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver existence check.
+    /// - `1`: Return whether the clock is synchronized: 0 if it never was, 1
+    ///   if it is and 2 if the last successful query is too old; the seconds
+    ///   since the last successful query; and its round trip delay in ms,
+    ///   which bounds the error of the time.
+    /// - `2`: Return the time, in seconds since the Unix epoch and
+    ///   microseconds.
+    /// - `3`: Query the server now.
+    fn command(
+        &self,
+        command_num: usize,
+        _data1: usize,
+        _data2: usize,
+        _processid: ProcessId,
+    ) -> CommandReturn {
+        match command_num {
+            0 => CommandReturn::success(),
+            1 => {
+                let (state, since_s) = self.status();
+                let delay_ms = (self.delay_us.get() / 1000).min(u32::MAX as u64) as u32;
+                CommandReturn::success_u32_u32_u32(state, since_s, delay_ms)
+            }
+            2 => {
+                let now_us = self.clock.now_us();
+                CommandReturn::success_u32_u32(
+                    (now_us / US_PER_SECOND) as u32,
+                    (now_us % US_PER_SECOND) as u32,
+                )
+            }
+            3 => {
+                if self.state.get() != State::Idle {
+                    return CommandReturn::failure(ErrorCode::ALREADY);
+                }
+                let _ = self.alarm.disarm();
+                self.wait(0);
+                CommandReturn::success()
+            }
+            _ => CommandReturn::failure(ErrorCode::NOSUPPORT),
+        }
+    }
+
+    fn allocate_grant(&self, processid: ProcessId) -> Result<(), kernel::process::Error> {
+        self.apps.enter(processid, |_, _| {})
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 2036-02-07T06:28:16Z, when NTP seconds wrap to era 1.
+    const ERA_1_UNIX_S: u64 = 2_085_978_496;
+
+    const ORIGIN: [u8; 8] = [0xE9, 0x1D, 0x3C, 0x00, 0x80, 0x00, 0x00, 0x00];
+
+    /// A synchronized stratum 2, version 4 reply to `ORIGIN`.
+    fn reply() -> [u8; PACKET_LEN] {
+        let mut packet = [0; PACKET_LEN];
+        packet[0] = 4 << 3 | MODE_SERVER;
+        packet[1] = 2;
+        packet[24..32].copy_from_slice(&ORIGIN);
+        packet[32..40].copy_from_slice(&ntp_from_unix_us(1_700_000_000_250_000));
+        packet[40..48].copy_from_slice(&ntp_from_unix_us(1_700_000_000_500_000));
+        packet
+    }
+
+    #[test]
+    fn epoch_conversion() {
+        let epoch = [0x83, 0xAA, 0x7E, 0x80, 0, 0, 0, 0];
+        assert_eq!(ntp_from_unix_us(0), epoch);
+        assert_eq!(unix_us_from_ntp(&epoch), 0);
+        // Half a second is half the fraction.
+        let half = [0x83, 0xAA, 0x7E, 0x80, 0x80, 0, 0, 0];
+        assert_eq!(ntp_from_unix_us(500_000), half);
+        assert_eq!(unix_us_from_ntp(&half), 500_000);
+        // 1968 to 1970 can't be represented, so is the Unix epoch.
+        assert_eq!(unix_us_from_ntp(&[0x80, 0, 0, 0, 0, 0, 0, 0]), 0);
+    }
+
+    #[test]
+    fn era_boundary() {
+        let last = [0xFF, 0xFF, 0xFF, 0xFF, 0, 0, 0, 0];
+        let first = [0; 8];
+        let last_us = (ERA_1_UNIX_S - 1) * US_PER_SECOND;
+        let first_us = ERA_1_UNIX_S * US_PER_SECOND;
+        assert_eq!(ntp_from_unix_us(last_us), last);
+        assert_eq!(unix_us_from_ntp(&last), last_us);
+        assert_eq!(ntp_from_unix_us(first_us), first);
+        assert_eq!(unix_us_from_ntp(&first), first_us);
+        let later_us = first_us + 86_400 * US_PER_SECOND + 250_000;
+        assert_eq!(unix_us_from_ntp(&ntp_from_unix_us(later_us)), later_us);
+    }
+
+    #[test]
+    fn valid_reply() {
+        assert_eq!(
+            parse_reply(&reply(), ORIGIN),
+            Some(Ok((1_700_000_000_250_000, 1_700_000_000_500_000)))
+        );
+        // Older versions are answered too.
+        let mut packet = reply();
+        packet[0] = 3 << 3 | MODE_SERVER;
+        assert!(matches!(parse_reply(&packet, ORIGIN), Some(Ok(_))));
+    }
+
+    #[test]
+    fn ignored_replies() {
+        assert_eq!(parse_reply(&reply()[..PACKET_LEN - 1], ORIGIN), None);
+        assert_eq!(parse_reply(&[], ORIGIN), None);
+        for mode in [3, 5] {
+            let mut packet = reply();
+            packet[0] = 4 << 3 | mode;
+            assert_eq!(parse_reply(&packet, ORIGIN), None);
+        }
+        for version in [0, 5, 7] {
+            let mut packet = reply();
+            packet[0] = version << 3 | MODE_SERVER;
+            assert_eq!(parse_reply(&packet, ORIGIN), None);
+        }
+        let mut packet = reply();
+        packet[31] ^= 1;
+        assert_eq!(parse_reply(&packet, ORIGIN), None);
+    }
+
+    #[test]
+    fn kiss_of_death() {
+        let mut packet = reply();
+        packet[1] = 0;
+        packet[12..16].copy_from_slice(b"RATE");
+        assert_eq!(parse_reply(&packet, ORIGIN), Some(Err(ErrorCode::BUSY)));
+    }
+
+    #[test]
+    fn unusable_replies() {
+        let mut packet = reply();
+        packet[0] |= LEAP_UNSYNCHRONIZED << 6;
+        assert_eq!(parse_reply(&packet, ORIGIN), Some(Err(ErrorCode::FAIL)));
+        let mut packet = reply();
+        packet[1] = 16;
+        assert_eq!(parse_reply(&packet, ORIGIN), Some(Err(ErrorCode::FAIL)));
+        let mut packet = reply();
+        packet[1] = 15;
+        assert!(matches!(parse_reply(&packet, ORIGIN), Some(Ok(_))));
+        packet[40..48].fill(0);
+        assert_eq!(parse_reply(&packet, ORIGIN), Some(Err(ErrorCode::FAIL)));
+    }
+
+    #[test]
+    fn offset_and_delay_of_exchange() {
+        // The server is 1 s ahead, with 10 ms each way and 2 ms to answer.
+        let t1 = 5_000_000;
+        let t2 = t1 + 10_000 + 1_000_000;
+        let t3 = t2 + 2_000;
+        let t4 = t3 + 10_000 - 1_000_000;
+        assert_eq!(offset_and_delay(t1, t2, t3, t4), (1_000_000, 20_000));
+        // And 1 s behind.
+        let t2 = t1 + 10_000 - 1_000_000;
+        let t3 = t2 + 2_000;
+        let t4 = t3 + 10_000 + 1_000_000;
+        assert_eq!(offset_and_delay(t1, t2, t3, t4), (-1_000_000, 20_000));
+    }
+}
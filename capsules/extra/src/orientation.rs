@@ -0,0 +1,1261 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Orientation estimation from a 9DOF sensor, for userspace.
+//!
+//! The ninedof driver only returns raw readings, leaving every app to filter
+//! them. This capsule samples the accelerometer, magnetometer and gyroscope
+//! at a fixed rate while an app uses it, and fuses the readings with
+//! Madgwick's or Mahony's filter into an orientation quaternion. Apps can read
+//! the quaternion, the Euler angles and the tilt-compensated compass heading.
+//!
+//! The filters run in fixed-point, with 24 fractional bits. The sensor's axes
+//! must form a right-handed frame shared by the three sensors. The estimate is
+//! relative to an Earth frame with x pointing to magnetic north and z up. A
+//! sensor without a magnetometer still gets roll and pitch, but its yaw is
+//! only integrated from the gyroscope and there is no heading.
+//!
+//! Magnetometer Calibration
+//! ------------------------
+//!
+//! Magnetometer readings are corrected for hard and soft iron distortion
+//! with an offset and a matrix: `m = matrix * (raw - offset)`. Apps can set
+//! the calibration directly, or have the capsule estimate it from the range
+//! of readings while the device is turned in every direction. The
+//! calibration is kept in the key-value store and loaded when sampling first
+//! starts.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! let orientation = components::orientation::OrientationComponent::new(
+//!     board_kernel,
+//!     capsules_extra::orientation::DRIVER_NUM,
+//!     mux_alarm,
+//!     ninedof_user,
+//!     virtual_kv,
+//!     StoragePermissions::new_kernel(&storage_cap),
+//!     capsules_extra::orientation::MADGWICK,
+//!     20,
+//!     1000,
+//! )
+//! .finalize(components::orientation_component_static!(
+//!     nrf52840::rtc::Rtc<'static>
+//! ));
+//! ```
+
+use core::cell::Cell;
+
+use kernel::grant::{AllowRoCount, AllowRwCount, Grant, UpcallCount};
+use kernel::hil::kv;
+use kernel::hil::sensors::{NineDof, NineDofClient};
+use kernel::hil::time::{self, Alarm, ConvertTicks};
+use kernel::processbuffer::{ReadableProcessBuffer, WriteableProcessBuffer};
+use kernel::storage_permissions::StoragePermissions;
+use kernel::syscall::{CommandReturn, SyscallDriver};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::utilities::leasable_buffer::SubSliceMut;
+use kernel::{ErrorCode, ProcessId};
+
+use crate::kv_store_permissions::HEADER_LENGTH;
+
+/// Syscall driver number.
+use capsules_core::driver;
+pub const DRIVER_NUM: usize = driver::NUM::Orientation as usize;
+
+/// Ids for read-only allow buffers
+mod ro_allow {
+    pub const CALIBRATION: usize = 0;
+    /// The number of allow buffers the kernel stores for this grant
+    pub const COUNT: u8 = 1;
+}
+
+/// Ids for read-write allow buffers
+mod rw_allow {
+    pub const ORIENTATION: usize = 0;
+    /// The number of allow buffers the kernel stores for this grant
+    pub const COUNT: u8 = 1;
+}
+
+mod upcall {
+    pub const CALIBRATION_DONE: usize = 0;
+    /// The number of upcalls the kernel stores for this grant
+    pub const COUNT: u8 = 1;
+}
+
+/// The key the magnetometer calibration is stored under.
+const CALIBRATION_KEY: &[u8] = b"orientation-mag-calibration";
+/// The format of the stored calibration.
+const CALIBRATION_VERSION: u8 = 1;
+/// The length of the calibration set by apps: the offset and the matrix, as
+/// 12 `i32`s.
+const CALIBRATION_LEN: usize = 48;
+
+/// The length of the buffer for the key of the calibration.
+pub const KV_KEY_LEN: usize = CALIBRATION_KEY.len();
+/// The length of the buffer for the stored calibration.
+pub const KV_VALUE_LEN: usize = HEADER_LENGTH + 1 + CALIBRATION_LEN;
+
+/// The length of the orientation copied to apps.
+const ORIENTATION_LEN: usize = 32;
+
+/// Fixed-point numbers with 24 fractional bits.
+type Fixed = i32;
+
+const FRACTION_BITS: u32 = 24;
+const ONE: Fixed = 1 << FRACTION_BITS;
+const HALF: Fixed = ONE / 2;
+/// pi / 180.
+const DEGREES_TO_RADIANS: Fixed = 292818;
+const IDENTITY: [Fixed; 4] = [ONE, 0, 0, 0];
+
+/// The orientation filter, and its gains in fixed-point with 24 fractional
+/// bits.
+#[derive(Clone, Copy)]
+pub enum Filter {
+    /// Madgwick's gradient descent filter. `beta` trades the gyroscope's
+    /// drift against the noise of the accelerometer and magnetometer.
+    Madgwick { beta: i32 },
+    /// Mahony's complementary filter, with proportional and integral gains
+    /// `kp` and `ki`. A non-zero `ki` corrects for the gyroscope's bias.
+    Mahony { kp: i32, ki: i32 },
+}
+
+/// Madgwick's filter with a `beta` of 0.1.
+pub const MADGWICK: Filter = Filter::Madgwick { beta: ONE / 10 };
+/// Mahony's filter with a `kp` of 0.5 and no integral feedback.
+pub const MAHONY: Filter = Filter::Mahony { kp: HALF, ki: 0 };
+
+/// Hard and soft iron correction for the magnetometer.
+#[derive(Clone, Copy)]
+struct Calibration {
+    offset: [i32; 3],
+    matrix: [[Fixed; 3]; 3],
+}
+
+impl Calibration {
+    const IDENTITY: Calibration = Calibration {
+        offset: [0; 3],
+        matrix: [[ONE, 0, 0], [0, ONE, 0], [0, 0, ONE]],
+    };
+
+    fn encode(&self, buf: &mut [u8]) {
+        let values = self.offset.iter().chain(self.matrix.iter().flatten());
+        for (chunk, value) in buf.chunks_exact_mut(4).zip(values) {
+            chunk.copy_from_slice(&value.to_le_bytes());
+        }
+    }
+
+    fn decode(buf: &[u8]) -> Calibration {
+        let mut values = [0; 12];
+        for (value, chunk) in values.iter_mut().zip(buf.chunks_exact(4)) {
+            *value = i32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+        }
+        Calibration {
+            offset: [values[0], values[1], values[2]],
+            matrix: [
+                [values[3], values[4], values[5]],
+                [values[6], values[7], values[8]],
+                [values[9], values[10], values[11]],
+            ],
+        }
+    }
+
+    /// Estimate the calibration from the range of readings on each axis.
+    ///
+    /// The offset is the middle of the range, and the matrix scales each
+    /// axis to the average range, which corrects soft iron distortion along
+    /// the axes.
+    fn from_range(min: [i32; 3], max: [i32; 3]) -> Option<Calibration> {
+        let mut radius = [0; 3];
+        for i in 0..3 {
+            radius[i] = (max[i] as i64 - min[i] as i64) / 2;
+            if radius[i] <= 0 {
+                return None;
+            }
+        }
+        let average = radius.iter().sum::<i64>() / 3;
+
+        let mut calibration = Calibration::IDENTITY;
+        for i in 0..3 {
+            calibration.offset[i] = ((max[i] as i64 + min[i] as i64) / 2) as i32;
+            calibration.matrix[i][i] = ((average << FRACTION_BITS) / radius[i]) as Fixed;
+        }
+        Some(calibration)
+    }
+
+    fn apply(&self, raw: [i32; 3]) -> [i32; 3] {
+        let offset = [0, 1, 2].map(|i| raw[i] as i64 - self.offset[i] as i64);
+        self.matrix.map(|row| {
+            let sum = (0..3).map(|i| row[i] as i64 * offset[i]).sum::<i64>();
+            // Keep 12 fractional bits so readings in coarse units keep
+            // their direction.
+            (sum >> (FRACTION_BITS - 12)).clamp(i32::MIN as i64, i32::MAX as i64) as i32
+        })
+    }
+}
+
+fn mul(a: Fixed, b: Fixed) -> Fixed {
+    ((a as i64 * b as i64) >> FRACTION_BITS) as Fixed
+}
+
+fn isqrt(mut n: u64) -> u64 {
+    let mut root = 0;
+    let mut bit = 1 << 62;
+    while bit > n {
+        bit >>= 2;
+    }
+    while bit != 0 {
+        if n >= root + bit {
+            n -= root + bit;
+            root = (root >> 1) + bit;
+        } else {
+            root >>= 1;
+        }
+        bit >>= 2;
+    }
+    root
+}
+
+fn sqrt(a: Fixed) -> Fixed {
+    isqrt((a.max(0) as u64) << FRACTION_BITS) as Fixed
+}
+
+/// Scale `v` to unit length, or return `None` if it is zero.
+fn normalize<const L: usize>(v: [i32; L]) -> Option<[Fixed; L]> {
+    let max = v.iter().map(|x| x.unsigned_abs()).max().unwrap_or(0);
+    if max == 0 {
+        return None;
+    }
+
+    // Scale the vector so its largest element has 22 bits, which keeps the
+    // precision of short vectors without overflowing the sum of squares.
+    let bits = 32 - max.leading_zeros() as i32;
+    let v = v.map(|x| {
+        if bits > 22 {
+            (x as i64) >> (bits - 22)
+        } else {
+            (x as i64) << (22 - bits)
+        }
+    });
+    let norm = isqrt(v.iter().map(|x| (x * x) as u64).sum()) as i64;
+    Some(v.map(|x| ((x << FRACTION_BITS) / norm) as Fixed))
+}
+
+fn cross(a: [Fixed; 3], b: [Fixed; 3]) -> [Fixed; 3] {
+    [
+        mul(a[1], b[2]) - mul(a[2], b[1]),
+        mul(a[2], b[0]) - mul(a[0], b[2]),
+        mul(a[0], b[1]) - mul(a[1], b[0]),
+    ]
+}
+
+/// Rotate `v` from the sensor frame to the Earth frame.
+fn rotate(q: [Fixed; 4], v: [Fixed; 3]) -> [Fixed; 3] {
+    let [q0, q1, q2, q3] = q;
+    [
+        2 * (mul(v[0], HALF - mul(q2, q2) - mul(q3, q3))
+            + mul(v[1], mul(q1, q2) - mul(q0, q3))
+            + mul(v[2], mul(q1, q3) + mul(q0, q2))),
+        2 * (mul(v[0], mul(q1, q2) + mul(q0, q3))
+            + mul(v[1], HALF - mul(q1, q1) - mul(q3, q3))
+            + mul(v[2], mul(q2, q3) - mul(q0, q1))),
+        2 * (mul(v[0], mul(q1, q3) - mul(q0, q2))
+            + mul(v[1], mul(q2, q3) + mul(q0, q1))
+            + mul(v[2], HALF - mul(q1, q1) - mul(q2, q2))),
+    ]
+}
+
+/// Integrate the rate of rotation `g`, in radians per second, over `dt`
+/// seconds.
+fn integrate(q: [Fixed; 4], g: [Fixed; 3], dt: Fixed) -> [Fixed; 4] {
+    let [q0, q1, q2, q3] = q;
+    let g = g.map(|x| mul(x, dt / 2));
+    let q = [
+        q0 - mul(q1, g[0]) - mul(q2, g[1]) - mul(q3, g[2]),
+        q1 + mul(q0, g[0]) + mul(q2, g[2]) - mul(q3, g[1]),
+        q2 + mul(q0, g[1]) - mul(q1, g[2]) + mul(q3, g[0]),
+        q3 + mul(q0, g[2]) + mul(q1, g[1]) - mul(q2, g[0]),
+    ];
+    normalize(q).unwrap_or(IDENTITY)
+}
+
+/// Update `q` with Madgwick's filter.
+///
+/// The accelerometer and magnetometer readings `a` and `m` must be unit
+/// vectors.
+// The filters keep the names used in the papers describing them.
+#[allow(clippy::many_single_char_names)]
+fn madgwick(
+    q: [Fixed; 4],
+    g: [Fixed; 3],
+    a: Option<[Fixed; 3]>,
+    m: Option<[Fixed; 3]>,
+    beta: Fixed,
+    dt: Fixed,
+) -> [Fixed; 4] {
+    let [q0, q1, q2, q3] = q;
+    let Some([ax, ay, az]) = a else {
+        return integrate(q, g, dt);
+    };
+
+    // The gradient of the difference between the direction of gravity
+    // measured and predicted from `q`.
+    let f1 = 2 * (mul(q1, q3) - mul(q0, q2)) - ax;
+    let f2 = 2 * (mul(q0, q1) + mul(q2, q3)) - ay;
+    let f3 = ONE - 2 * (mul(q1, q1) + mul(q2, q2)) - az;
+    let mut s = [
+        2 * (mul(q1, f2) - mul(q2, f1)),
+        2 * (mul(q3, f1) + mul(q0, f2)) - 4 * mul(q1, f3),
+        2 * (mul(q3, f2) - mul(q0, f1)) - 4 * mul(q2, f3),
+        2 * (mul(q1, f1) + mul(q2, f2)),
+    ];
+
+    if let Some([mx, my, mz]) = m {
+        // The Earth's magnetic field in the Earth frame, with its horizontal
+        // part along x.
+        let h = rotate(q, [mx, my, mz]);
+        let bx = sqrt(mul(h[0], h[0]) + mul(h[1], h[1]));
+        let bz = h[2];
+
+        // The gradient of the difference between the direction of the
+        // magnetic field measured and predicted from `q`.
+        let f4 = mul(bx, ONE - 2 * (mul(q2, q2) + mul(q3, q3)))
+            + 2 * mul(bz, mul(q1, q3) - mul(q0, q2))
+            - mx;
+        let f5 = 2 * (mul(bx, mul(q1, q2) - mul(q0, q3)) + mul(bz, mul(q0, q1) + mul(q2, q3))) - my;
+        let f6 = 2 * mul(bx, mul(q0, q2) + mul(q1, q3))
+            + mul(bz, ONE - 2 * (mul(q1, q1) + mul(q2, q2)))
+            - mz;
+        let (bx2, bz2) = (2 * bx, 2 * bz);
+        s[0] +=
+            -mul(mul(bz2, q2), f4) + mul(mul(bz2, q1) - mul(bx2, q3), f5) + mul(mul(bx2, q2), f6);
+        s[1] += mul(mul(bz2, q3), f4)
+            + mul(mul(bx2, q2) + mul(bz2, q0), f5)
+            + mul(mul(bx2, q3) - 2 * mul(bz2, q1), f6);
+        s[2] += mul(-2 * mul(bx2, q2) - mul(bz2, q0), f4)
+            + mul(mul(bx2, q1) + mul(bz2, q3), f5)
+            + mul(mul(bx2, q0) - 2 * mul(bz2, q2), f6);
+        s[3] += mul(mul(bz2, q1) - 2 * mul(bx2, q3), f4)
+            + mul(mul(bz2, q2) - mul(bx2, q0), f5)
+            + mul(mul(bx2, q1), f6);
+    }
+
+    // Step along the rotation measured by the gyroscope, and down the
+    // gradient.
+    let Some(s) = normalize(s) else {
+        return integrate(q, g, dt);
+    };
+    let q_dot = [
+        (-mul(q1, g[0]) - mul(q2, g[1]) - mul(q3, g[2])) / 2 - mul(beta, s[0]),
+        (mul(q0, g[0]) + mul(q2, g[2]) - mul(q3, g[1])) / 2 - mul(beta, s[1]),
+        (mul(q0, g[1]) - mul(q1, g[2]) + mul(q3, g[0])) / 2 - mul(beta, s[2]),
+        (mul(q0, g[2]) + mul(q1, g[1]) - mul(q2, g[0])) / 2 - mul(beta, s[3]),
+    ];
+    let q = [0, 1, 2, 3].map(|i| q[i] + mul(q_dot[i], dt));
+    normalize(q).unwrap_or(IDENTITY)
+}
+
+/// Update `q` with Mahony's filter.
+///
+/// The accelerometer and magnetometer readings `a` and `m` must be unit
+/// vectors. `integral` holds the integral feedback between updates.
+#[allow(clippy::many_single_char_names, clippy::too_many_arguments)]
+fn mahony(
+    q: [Fixed; 4],
+    mut g: [Fixed; 3],
+    a: Option<[Fixed; 3]>,
+    m: Option<[Fixed; 3]>,
+    kp: Fixed,
+    ki: Fixed,
+    integral: &mut [Fixed; 3],
+    dt: Fixed,
+) -> [Fixed; 4] {
+    let [q0, q1, q2, q3] = q;
+    if let Some(a) = a {
+        // Half the direction of gravity predicted from `q`, and the error to
+        // the measured direction.
+        let v = [
+            mul(q1, q3) - mul(q0, q2),
+            mul(q0, q1) + mul(q2, q3),
+            mul(q0, q0) - HALF + mul(q3, q3),
+        ];
+        let mut e = cross(a, v);
+
+        if let Some(m) = m {
+            let h = rotate(q, m);
+            let bx = sqrt(mul(h[0], h[0]) + mul(h[1], h[1]));
+            let bz = h[2];
+
+            // Half the direction of the magnetic field predicted from `q`.
+            let w = [
+                mul(bx, HALF - mul(q2, q2) - mul(q3, q3)) + mul(bz, mul(q1, q3) - mul(q0, q2)),
+                mul(bx, mul(q1, q2) - mul(q0, q3)) + mul(bz, mul(q0, q1) + mul(q2, q3)),
+                mul(bx, mul(q0, q2) + mul(q1, q3)) + mul(bz, HALF - mul(q1, q1) - mul(q2, q2)),
+            ];
+            let em = cross(m, w);
+            e = [0, 1, 2].map(|i| e[i] + em[i]);
+        }
+
+        for i in 0..3 {
+            if ki > 0 {
+                integral[i] += mul(mul(2 * ki, e[i]), dt);
+                g[i] += integral[i];
+            } else {
+                integral[i] = 0;
+            }
+            g[i] += mul(2 * kp, e[i]);
+        }
+    }
+    integrate(q, g, dt)
+}
+
+/// Arctangent of `y / x` in hundredths of a degree, from -18000 to 18000.
+fn atan2(y: Fixed, x: Fixed) -> i32 {
+    /// atan(2^-i) in millionths of a degree.
+    const ATAN: [i64; 24] = [
+        45000000, 26565051, 14036243, 7125016, 3576334, 1789911, 895174, 447614, 223811, 111906,
+        55953, 27976, 13988, 6994, 3497, 1749, 874, 437, 219, 109, 55, 27, 14, 7,
+    ];
+
+    if x == 0 && y == 0 {
+        return 0;
+    }
+
+    let (mut x, mut y) = (x as i64, y as i64);
+    let mut angle = 0;
+    // Rotate into the right half-plane, where the CORDIC iterations
+    // converge.
+    if x < 0 {
+        (x, y, angle) = if y >= 0 {
+            (y, -x, 90_000_000)
+        } else {
+            (-y, x, -90_000_000)
+        };
+    }
+    for (i, atan) in ATAN.iter().enumerate() {
+        let (dx, dy) = (x >> i, y >> i);
+        if y > 0 {
+            (x, y, angle) = (x + dy, y - dx, angle + atan);
+        } else {
+            (x, y, angle) = (x - dy, y + dx, angle - atan);
+        }
+    }
+    (angle + 5_000).div_euclid(10_000) as i32
+}
+
+/// Roll, pitch and yaw of `q` in hundredths of a degree.
+fn euler_angles(q: [Fixed; 4]) -> [i32; 3] {
+    let [q0, q1, q2, q3] = q;
+    let roll = atan2(
+        2 * (mul(q0, q1) + mul(q2, q3)),
+        ONE - 2 * (mul(q1, q1) + mul(q2, q2)),
+    );
+    let sin_pitch = (2 * (mul(q0, q2) - mul(q1, q3))).clamp(-ONE, ONE);
+    let pitch = atan2(sin_pitch, sqrt(ONE - mul(sin_pitch, sin_pitch)));
+    let yaw = atan2(
+        2 * (mul(q0, q3) + mul(q1, q2)),
+        ONE - 2 * (mul(q2, q2) + mul(q3, q3)),
+    );
+    [roll, pitch, yaw]
+}
+
+/// The compass heading for a yaw, clockwise from magnetic north.
+fn heading(yaw: i32) -> u32 {
+    (36000 - yaw).rem_euclid(36000) as u32
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Step {
+    Idle,
+    Accelerometer,
+    Magnetometer,
+    Gyroscope,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum KvOperation {
+    Load,
+    Store,
+    Delete,
+}
+
+#[derive(Default)]
+pub struct App {
+    enabled: bool,
+}
+
+pub struct Orientation<'a, A: Alarm<'a>> {
+    alarm: &'a A,
+    ninedof: &'a dyn NineDof<'a>,
+    kv: &'a dyn kv::KVPermissions<'a>,
+    storage_permissions: StoragePermissions,
+
+    apps: Grant<
+        App,
+        UpcallCount<{ upcall::COUNT }>,
+        AllowRoCount<{ ro_allow::COUNT }>,
+        AllowRwCount<{ rw_allow::COUNT }>,
+    >,
+
+    filter: Filter,
+    period: A::Ticks,
+    /// The sampling period in seconds.
+    dt: Fixed,
+    /// Gyroscope readings per degree per second.
+    gyro_scale: i64,
+    running: Cell<bool>,
+    step: Cell<Step>,
+
+    accelerometer: Cell<Option<[Fixed; 3]>>,
+    magnetometer: Cell<Option<[Fixed; 3]>>,
+    quaternion: Cell<[Fixed; 4]>,
+    /// Mahony's integral feedback.
+    integral: Cell<[Fixed; 3]>,
+    /// Whether the last update used the magnetometer.
+    heading_valid: Cell<bool>,
+
+    calibration: Cell<Calibration>,
+    calibration_loaded: Cell<bool>,
+    /// The range of magnetometer readings while estimating the calibration.
+    calibration_range: Cell<Option<([i32; 3], [i32; 3])>>,
+    /// The app to notify when the calibration is stored.
+    calibration_app: OptionalCell<ProcessId>,
+
+    kv_operation: OptionalCell<KvOperation>,
+    kv_key: TakeCell<'static, [u8]>,
+    kv_value: TakeCell<'static, [u8]>,
+}
+
+impl<'a, A: Alarm<'a>> Orientation<'a, A> {
+    /// Create the capsule. The sensor is sampled every `period_ms`, and its
+    /// gyroscope reports `gyro_scale` units per degree per second.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        alarm: &'a A,
+        ninedof: &'a dyn NineDof<'a>,
+        kv: &'a dyn kv::KVPermissions<'a>,
+        storage_permissions: StoragePermissions,
+        filter: Filter,
+        period_ms: u32,
+        gyro_scale: u32,
+        kv_key: &'static mut [u8; KV_KEY_LEN],
+        kv_value: &'static mut [u8; KV_VALUE_LEN],
+        grant: Grant<
+            App,
+            UpcallCount<{ upcall::COUNT }>,
+            AllowRoCount<{ ro_allow::COUNT }>,
+            AllowRwCount<{ rw_allow::COUNT }>,
+        >,
+    ) -> Self {
+        Orientation {
+            alarm,
+            ninedof,
+            kv,
+            storage_permissions,
+            apps: grant,
+            filter,
+            period: alarm.ticks_from_ms(period_ms),
+            dt: (((period_ms as i64) << FRACTION_BITS) / 1000) as Fixed,
+            gyro_scale: gyro_scale.max(1) as i64,
+            running: Cell::new(false),
+            step: Cell::new(Step::Idle),
+            accelerometer: Cell::new(None),
+            magnetometer: Cell::new(None),
+            quaternion: Cell::new(IDENTITY),
+            integral: Cell::new([0; 3]),
+            heading_valid: Cell::new(false),
+            calibration: Cell::new(Calibration::IDENTITY),
+            calibration_loaded: Cell::new(false),
+            calibration_range: Cell::new(None),
+            calibration_app: OptionalCell::empty(),
+            kv_operation: OptionalCell::empty(),
+            kv_key: TakeCell::new(kv_key),
+            kv_value: TakeCell::new(kv_value),
+        }
+    }
+
+    /// Start or stop sampling, depending on whether any app uses the filter
+    /// or a calibration is being estimated.
+    fn update_sampling(&self) {
+        let mut wanted = self.calibration_range.get().is_some();
+        self.apps.each(|_, app, _| wanted |= app.enabled);
+
+        if wanted && !self.running.get() {
+            self.running.set(true);
+            if !self.calibration_loaded.get() {
+                let _ = self.start_kv(KvOperation::Load);
+            }
+            self.alarm.set_alarm(self.alarm.now(), self.period);
+        } else if !wanted && self.running.get() {
+            self.running.set(false);
+            let _ = self.alarm.disarm();
+        }
+    }
+
+    fn set_enabled(&self, processid: ProcessId, enabled: bool) -> Result<(), ErrorCode> {
+        self.apps
+            .enter(processid, |app, _| app.enabled = enabled)
+            .map_err(ErrorCode::from)?;
+        self.update_sampling();
+        Ok(())
+    }
+
+    /// Start the next read of the sampling sequence: the accelerometer, the
+    /// magnetometer and then the gyroscope.
+    fn read(&self, step: Step) {
+        let result = match step {
+            Step::Accelerometer => self.ninedof.read_accelerometer(),
+            Step::Magnetometer => self.ninedof.read_magnetometer(),
+            Step::Gyroscope => self.ninedof.read_gyroscope(),
+            Step::Idle => Ok(()),
+        };
+        match result {
+            Ok(()) => self.step.set(step),
+            // Without a magnetometer, go on to the gyroscope.
+            Err(_) if step == Step::Magnetometer => {
+                self.magnetometer.set(None);
+                self.read(Step::Gyroscope);
+            }
+            // Skip this sample.
+            Err(_) => self.step.set(Step::Idle),
+        }
+    }
+
+    fn magnetometer_done(&self, raw: [i32; 3]) {
+        if raw == [0; 3] {
+            // The driver failed to read the sensor.
+            self.magnetometer.set(None);
+            return;
+        }
+
+        if let Some((mut min, mut max)) = self.calibration_range.get() {
+            for i in 0..3 {
+                min[i] = min[i].min(raw[i]);
+                max[i] = max[i].max(raw[i]);
+            }
+            self.calibration_range.set(Some((min, max)));
+        }
+        self.magnetometer
+            .set(normalize(self.calibration.get().apply(raw)));
+    }
+
+    fn gyroscope_done(&self, raw: [i32; 3]) {
+        let g = raw.map(|x| {
+            (x as i64 * DEGREES_TO_RADIANS as i64 / self.gyro_scale)
+                .clamp(i32::MIN as i64, i32::MAX as i64) as Fixed
+        });
+        let a = self.accelerometer.get();
+        let m = self.magnetometer.get().filter(|_| a.is_some());
+
+        let q = match self.filter {
+            Filter::Madgwick { beta } => madgwick(self.quaternion.get(), g, a, m, beta, self.dt),
+            Filter::Mahony { kp, ki } => {
+                let mut integral = self.integral.get();
+                let q = mahony(
+                    self.quaternion.get(),
+                    g,
+                    a,
+                    m,
+                    kp,
+                    ki,
+                    &mut integral,
+                    self.dt,
+                );
+                self.integral.set(integral);
+                q
+            }
+        };
+        self.quaternion.set(q);
+        self.heading_valid.set(m.is_some());
+    }
+
+    /// Copy the quaternion, the Euler angles and the heading to the app.
+    fn copy_orientation(&self, processid: ProcessId) -> Result<(), ErrorCode> {
+        let q = self.quaternion.get();
+        let angles = euler_angles(q);
+        let heading = if self.heading_valid.get() {
+            heading(angles[2])
+        } else {
+            u32::MAX
+        };
+
+        let mut orientation = [0; ORIENTATION_LEN];
+        // The quaternion has 30 fractional bits for apps.
+        let values = q.iter().map(|x| (x << 6) as u32);
+        let values = values.chain(angles.iter().map(|x| *x as u32));
+        for (chunk, value) in orientation.chunks_exact_mut(4).zip(values.chain([heading])) {
+            chunk.copy_from_slice(&value.to_le_bytes());
+        }
+
+        self.apps
+            .enter(processid, |_, kernel_data| {
+                kernel_data
+                    .get_readwrite_processbuffer(rw_allow::ORIENTATION)
+                    .and_then(|buffer| {
+                        buffer.mut_enter(|buffer| {
+                            if buffer.len() < ORIENTATION_LEN {
+                                return Err(ErrorCode::SIZE);
+                            }
+                            buffer[..ORIENTATION_LEN].copy_from_slice(&orientation);
+                            Ok(())
+                        })
+                    })
+                    .unwrap_or(Err(ErrorCode::RESERVE))
+            })
+            .unwrap_or_else(|err| Err(err.into()))
+    }
+
+    fn start_calibration(&self) -> Result<(), ErrorCode> {
+        if self.calibration_range.get().is_some() {
+            return Err(ErrorCode::ALREADY);
+        }
+        self.calibration_range
+            .set(Some(([i32::MAX; 3], [i32::MIN; 3])));
+        self.update_sampling();
+        Ok(())
+    }
+
+    fn finish_calibration(&self, processid: ProcessId) -> Result<(), ErrorCode> {
+        let (min, max) = self.calibration_range.get().ok_or(ErrorCode::OFF)?;
+        if self.kv_operation.is_some() {
+            return Err(ErrorCode::BUSY);
+        }
+        let calibration = Calibration::from_range(min, max).ok_or(ErrorCode::INVAL)?;
+
+        self.calibration_range.set(None);
+        self.update_sampling();
+        self.store_calibration(processid, calibration)
+    }
+
+    fn set_calibration(&self, processid: ProcessId) -> Result<(), ErrorCode> {
+        if self.kv_operation.is_some() {
+            return Err(ErrorCode::BUSY);
+        }
+        let calibration = self
+            .apps
+            .enter(processid, |_, kernel_data| {
+                kernel_data
+                    .get_readonly_processbuffer(ro_allow::CALIBRATION)
+                    .and_then(|buffer| {
+                        buffer.enter(|buffer| {
+                            if buffer.len() < CALIBRATION_LEN {
+                                return Err(ErrorCode::SIZE);
+                            }
+                            let mut calibration = [0; CALIBRATION_LEN];
+                            buffer[..CALIBRATION_LEN].copy_to_slice(&mut calibration);
+                            Ok(Calibration::decode(&calibration))
+                        })
+                    })
+                    .unwrap_or(Err(ErrorCode::RESERVE))
+            })
+            .unwrap_or_else(|err| Err(err.into()))?;
+
+        self.store_calibration(processid, calibration)
+    }
+
+    fn store_calibration(
+        &self,
+        processid: ProcessId,
+        calibration: Calibration,
+    ) -> Result<(), ErrorCode> {
+        self.calibration.set(calibration);
+        self.calibration_loaded.set(true);
+        self.start_kv(KvOperation::Store)?;
+        self.calibration_app.set(processid);
+        Ok(())
+    }
+
+    fn clear_calibration(&self, processid: ProcessId) -> Result<(), ErrorCode> {
+        if self.kv_operation.is_some() {
+            return Err(ErrorCode::BUSY);
+        }
+        self.calibration.set(Calibration::IDENTITY);
+        self.calibration_loaded.set(true);
+        self.start_kv(KvOperation::Delete)?;
+        self.calibration_app.set(processid);
+        Ok(())
+    }
+
+    fn start_kv(&self, operation: KvOperation) -> Result<(), ErrorCode> {
+        let (key, value) = match (self.kv_key.take(), self.kv_value.take()) {
+            (Some(key), Some(value)) => (key, value),
+            (key, value) => {
+                key.map(|key| self.kv_key.replace(key));
+                value.map(|value| self.kv_value.replace(value));
+                return Err(ErrorCode::BUSY);
+            }
+        };
+        key[..CALIBRATION_KEY.len()].copy_from_slice(CALIBRATION_KEY);
+        let mut key = SubSliceMut::new(key);
+        key.slice(..CALIBRATION_KEY.len());
+
+        let result = match operation {
+            KvOperation::Load => self
+                .kv
+                .get(key, SubSliceMut::new(value), self.storage_permissions)
+                .map_err(|(key, value, e)| {
+                    self.kv_value.replace(value.take());
+                    (key, e)
+                }),
+            KvOperation::Store => {
+                let header_size = self.kv.header_size();
+                value[header_size] = CALIBRATION_VERSION;
+                self.calibration.get().encode(&mut value[header_size + 1..]);
+                let mut value = SubSliceMut::new(value);
+                value.slice(..header_size + 1 + CALIBRATION_LEN);
+                self.kv
+                    .set(key, value, self.storage_permissions)
+                    .map_err(|(key, value, e)| {
+                        self.kv_value.replace(value.take());
+                        (key, e)
+                    })
+            }
+            KvOperation::Delete => {
+                self.kv_value.replace(value);
+                self.kv.delete(key, self.storage_permissions)
+            }
+        };
+
+        match result {
+            Ok(()) => {
+                self.kv_operation.set(operation);
+                Ok(())
+            }
+            Err((key, e)) => {
+                self.kv_key.replace(key.take());
+                Err(e)
+            }
+        }
+    }
+
+    /// Notify the app that changed the calibration that it was stored.
+    fn calibration_stored(&self, result: Result<(), ErrorCode>) {
+        self.calibration_app.take().map(|processid| {
+            let _ = self.apps.enter(processid, |_, kernel_data| {
+                kernel_data
+                    .schedule_upcall(
+                        upcall::CALIBRATION_DONE,
+                        (kernel::errorcode::into_statuscode(result), 0, 0),
+                    )
+                    .ok();
+            });
+        });
+    }
+}
+
+impl<'a, A: Alarm<'a>> time::AlarmClient for Orientation<'a, A> {
+    fn alarm(&self) {
+        if !self.running.get() {
+            return;
+        }
+        self.alarm.set_alarm(self.alarm.get_alarm(), self.period);
+
+        // If the sensor is slower than the sampling rate, skip a sample.
+        if self.step.get() == Step::Idle {
+            self.read(Step::Accelerometer);
+        }
+    }
+}
+
+impl<'a, A: Alarm<'a>> NineDofClient for Orientation<'a, A> {
+    fn callback(&self, arg1: usize, arg2: usize, arg3: usize) {
+        let raw = [arg1 as i32, arg2 as i32, arg3 as i32];
+        match self.step.get() {
+            Step::Idle => {}
+            Step::Accelerometer => {
+                self.accelerometer.set(normalize(raw));
+                self.read(Step::Magnetometer);
+            }
+            Step::Magnetometer => {
+                self.magnetometer_done(raw);
+                self.read(Step::Gyroscope);
+            }
+            Step::Gyroscope => {
+                self.step.set(Step::Idle);
+                self.gyroscope_done(raw);
+            }
+        }
+    }
+}
+
+impl<'a, A: Alarm<'a>> kv::KVClient for Orientation<'a, A> {
+    fn get_complete(
+        &self,
+        result: Result<(), ErrorCode>,
+        key: SubSliceMut<'static, u8>,
+        mut value: SubSliceMut<'static, u8>,
+    ) {
+        self.kv_key.replace(key.take());
+        self.kv_operation.clear();
+
+        let stored = value.as_slice();
+        // Keep the calibration if an app set it while it was being loaded.
+        if result.is_ok()
+            && !self.calibration_loaded.get()
+            && stored.len() > CALIBRATION_LEN
+            && stored[0] == CALIBRATION_VERSION
+        {
+            self.calibration
+                .set(Calibration::decode(&stored[1..1 + CALIBRATION_LEN]));
+        }
+        self.calibration_loaded.set(true);
+        self.kv_value.replace(value.take());
+    }
+
+    fn set_complete(
+        &self,
+        result: Result<(), ErrorCode>,
+        key: SubSliceMut<'static, u8>,
+        value: SubSliceMut<'static, u8>,
+    ) {
+        self.kv_key.replace(key.take());
+        self.kv_value.replace(value.take());
+        self.kv_operation.clear();
+        self.calibration_stored(result);
+    }
+
+    fn add_complete(
+        &self,
+        result: Result<(), ErrorCode>,
+        key: SubSliceMut<'static, u8>,
+        value: SubSliceMut<'static, u8>,
+    ) {
+        self.set_complete(result, key, value);
+    }
+
+    fn update_complete(
+        &self,
+        result: Result<(), ErrorCode>,
+        key: SubSliceMut<'static, u8>,
+        value: SubSliceMut<'static, u8>,
+    ) {
+        self.set_complete(result, key, value);
+    }
+
+    fn delete_complete(&self, result: Result<(), ErrorCode>, key: SubSliceMut<'static, u8>) {
+        self.kv_key.replace(key.take());
+        self.kv_operation.clear();
+        // `NOSUPPORT` means there was no stored calibration.
+        self.calibration_stored(match result {
+            Err(ErrorCode::NOSUPPORT) => Ok(()),
+            result => result,
+        });
+    }
+
+    fn next_key_complete(
+        &self,
+        _result: Result<(), ErrorCode>,
+        _position: usize,
+        key: SubSliceMut<'static, u8>,
+        value: SubSliceMut<'static, u8>,
+    ) {
+        self.kv_key.replace(key.take());
+        self.kv_value.replace(value.take());
+        self.kv_operation.clear();
+    }
+}
+
+impl<'a, A: Alarm<'a>> SyscallDriver for Orientation<'a, A> {
+    /// Control the orientation filter.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver existence check.
+    /// - `1`: Start sampling the sensor for this app.
+    /// - `2`: Stop sampling the sensor for this app.
+    /// - `3`: Copy the orientation to the `ORIENTATION` read-write allow
+    ///        buffer.
+    /// - `4`: Return the roll, pitch and yaw, in hundredths of a degree.
+    /// - `5`: Return the compass heading, in hundredths of a degree.
+    /// - `6`: Start estimating the magnetometer calibration.
+    /// - `7`: Finish estimating the magnetometer calibration and store it.
+    /// - `8`: Store the magnetometer calibration in the `CALIBRATION`
+    ///        read-only allow buffer.
+    /// - `9`: Clear the stored magnetometer calibration.
+    fn command(
+        &self,
+        command_num: usize,
+        _data1: usize,
+        _data2: usize,
+        processid: ProcessId,
+    ) -> CommandReturn {
+        match command_num {
+            0 => CommandReturn::success(),
+
+            1 => self.set_enabled(processid, true).into(),
+
+            2 => self.set_enabled(processid, false).into(),
+
+            3 => self.copy_orientation(processid).into(),
+
+            4 => {
+                let [roll, pitch, yaw] = euler_angles(self.quaternion.get());
+                CommandReturn::success_u32_u32_u32(roll as u32, pitch as u32, yaw as u32)
+            }
+
+            5 => {
+                if self.heading_valid.get() {
+                    let [_, _, yaw] = euler_angles(self.quaternion.get());
+                    CommandReturn::success_u32(heading(yaw))
+                } else {
+                    CommandReturn::failure(ErrorCode::NODEVICE)
+                }
+            }
+
+            6 => self.start_calibration().into(),
+
+            7 => self.finish_calibration(processid).into(),
+
+            8 => self.set_calibration(processid).into(),
+
+            9 => self.clear_calibration(processid).into(),
+
+            _ => CommandReturn::failure(ErrorCode::NOSUPPORT),
+        }
+    }
+
+    fn allocate_grant(&self, processid: ProcessId) -> Result<(), kernel::process::Error> {
+        self.apps.enter(processid, |_, _| {})
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `x` in fixed-point.
+    fn fixed(x: f64) -> Fixed {
+        (x * ONE as f64) as Fixed
+    }
+
+    /// The quaternion for a rotation of `degrees` about the unit `axis`.
+    fn rotation(degrees: f64, axis: [f64; 3]) -> [Fixed; 4] {
+        let half = degrees.to_radians() / 2.0;
+        let s = half.sin();
+        [
+            fixed(half.cos()),
+            fixed(axis[0] * s),
+            fixed(axis[1] * s),
+            fixed(axis[2] * s),
+        ]
+    }
+
+    fn assert_close<const L: usize>(actual: [i32; L], expected: [i32; L], tolerance: i32) {
+        for (a, e) in actual.iter().zip(expected.iter()) {
+            assert!(
+                (a - e).abs() <= tolerance,
+                "{:?} is not within {} of {:?}",
+                actual,
+                tolerance,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn fixed_point() {
+        assert_eq!(mul(HALF, HALF), ONE / 4);
+        assert_eq!(mul(-ONE, 3 * ONE), -3 * ONE);
+        assert_eq!(sqrt(ONE / 4), HALF);
+        assert_eq!(sqrt(4 * ONE), 2 * ONE);
+        assert_eq!(sqrt(-ONE), 0);
+
+        assert_eq!(normalize([0; 3]), None);
+        assert_close(
+            normalize([3, -4, 0]).unwrap(),
+            [fixed(0.6), fixed(-0.8), 0],
+            4,
+        );
+        // Large and small readings keep their direction.
+        assert_close(
+            normalize([i32::MAX, i32::MAX, 0]).unwrap(),
+            [fixed(0.5f64.sqrt()), fixed(0.5f64.sqrt()), 0],
+            4,
+        );
+        assert_close(
+            normalize([i32::MIN, 0, i32::MAX]).unwrap(),
+            [-fixed(0.5f64.sqrt()), 0, fixed(0.5f64.sqrt())],
+            4,
+        );
+        assert_close(normalize([0, 0, 1]).unwrap(), [0, 0, ONE], 0);
+    }
+
+    #[test]
+    fn atan2_quadrants() {
+        assert_eq!(atan2(0, 0), 0);
+        assert_eq!(atan2(0, ONE), 0);
+        assert_eq!(atan2(ONE, ONE), 4500);
+        assert_eq!(atan2(ONE, 0), 9000);
+        assert_eq!(atan2(ONE, -ONE), 13500);
+        assert_eq!(atan2(0, -ONE), 18000);
+        assert_eq!(atan2(-ONE, -ONE), -13500);
+        assert_eq!(atan2(-ONE, 0), -9000);
+        assert_eq!(atan2(-ONE, ONE), -4500);
+        // atan(0.5) = 26.565 degrees.
+        assert_eq!(atan2(HALF, ONE), 2657);
+        assert_eq!(atan2(-HALF, ONE), -2657);
+    }
+
+    #[test]
+    fn euler_angles_and_heading() {
+        assert_eq!(euler_angles(IDENTITY), [0, 0, 0]);
+        assert_close(
+            euler_angles(rotation(30.0, [1.0, 0.0, 0.0])),
+            [3000, 0, 0],
+            1,
+        );
+        assert_close(
+            euler_angles(rotation(-20.0, [0.0, 1.0, 0.0])),
+            [0, -2000, 0],
+            1,
+        );
+        assert_close(
+            euler_angles(rotation(90.0, [0.0, 0.0, 1.0])),
+            [0, 0, 9000],
+            1,
+        );
+
+        assert_eq!(heading(0), 0);
+        // Turning anticlockwise, seen from above, is turning west.
+        assert_eq!(heading(9000), 27000);
+        assert_eq!(heading(-9000), 9000);
+        assert_eq!(heading(18000), 18000);
+        assert_eq!(heading(-18000), 18000);
+    }
+
+    #[test]
+    fn rotate_to_earth_frame() {
+        assert_eq!(rotate(IDENTITY, [ONE, 0, 0]), [ONE, 0, 0]);
+        let q = rotation(90.0, [0.0, 0.0, 1.0]);
+        assert_close(rotate(q, [ONE, 0, 0]), [0, ONE, 0], 4);
+        assert_close(rotate(q, [0, 0, ONE]), [0, 0, ONE], 4);
+    }
+
+    #[test]
+    fn integrate_gyroscope() {
+        // 90 degrees per second about z for one second.
+        let mut q = IDENTITY;
+        let g = [0, 0, fixed(90f64.to_radians())];
+        for _ in 0..100 {
+            q = integrate(q, g, fixed(0.01));
+        }
+        assert_close(euler_angles(q), [0, 0, 9000], 50);
+
+        // Without a gyroscope reading the orientation doesn't change.
+        assert_close(integrate(q, [0; 3], fixed(0.01)), q, 2);
+    }
+
+    /// The unit accelerometer and magnetometer readings of a sensor rolled by
+    /// `roll` degrees and then turned `yaw` degrees anticlockwise from north.
+    fn readings(roll: f64, yaw: f64) -> ([Fixed; 3], [Fixed; 3]) {
+        let (roll, yaw) = (roll.to_radians(), yaw.to_radians());
+        let a = [0.0, roll.sin(), roll.cos()];
+        // A field pointing north and down at 60 degrees, turned into the
+        // frame of the sensor.
+        let (bx, bz) = (0.5, -(0.75f64.sqrt()));
+        let m = [bx * yaw.cos(), -bx * yaw.sin(), bz];
+        let m = [
+            m[0],
+            m[1] * roll.cos() + m[2] * roll.sin(),
+            -m[1] * roll.sin() + m[2] * roll.cos(),
+        ];
+        (a.map(fixed), m.map(fixed))
+    }
+
+    #[test]
+    fn madgwick_converges() {
+        let (a, m) = readings(30.0, 45.0);
+        let mut q = IDENTITY;
+        for _ in 0..2000 {
+            q = madgwick(q, [0; 3], Some(a), Some(m), ONE / 10, fixed(0.02));
+        }
+        assert_close(euler_angles(q), [3000, 0, 4500], 50);
+
+        // Without a magnetometer only the tilt is corrected.
+        let mut q = rotation(10.0, [0.0, 0.0, 1.0]);
+        for _ in 0..2000 {
+            q = madgwick(q, [0; 3], Some(a), None, ONE / 10, fixed(0.02));
+        }
+        assert_close(euler_angles(q), [3000, 0, 1000], 50);
+    }
+
+    #[test]
+    fn mahony_converges() {
+        let (a, m) = readings(-45.0, -120.0);
+        let mut q = IDENTITY;
+        let mut integral = [0; 3];
+        for _ in 0..10000 {
+            q = mahony(
+                q,
+                [0; 3],
+                Some(a),
+                Some(m),
+                HALF,
+                0,
+                &mut integral,
+                fixed(0.02),
+            );
+        }
+        assert_close(euler_angles(q), [-4500, 0, -12000], 50);
+        assert_eq!(integral, [0; 3]);
+    }
+
+    #[test]
+    fn mahony_integral_corrects_gyroscope_bias() {
+        let (a, _) = readings(0.0, 0.0);
+        let bias = [fixed(0.02), fixed(-0.01), 0];
+        let mut q = IDENTITY;
+        let mut integral = [0; 3];
+        for _ in 0..5000 {
+            q = mahony(
+                q,
+                bias,
+                Some(a),
+                None,
+                HALF,
+                ONE / 10,
+                &mut integral,
+                fixed(0.02),
+            );
+        }
+        assert_close(euler_angles(q)[..2].try_into().unwrap(), [0, 0], 20);
+        assert_close(
+            integral[..2].try_into().unwrap(),
+            [-bias[0], -bias[1]],
+            fixed(0.002),
+        );
+    }
+
+    #[test]
+    fn calibration() {
+        assert!(Calibration::from_range([0, -5, 3], [10, 5, 3]).is_none());
+
+        // A field offset by (0, 50, 300), and twice as strong along x.
+        let calibration = Calibration::from_range([-200, -50, 200], [200, 150, 400]).unwrap();
+        assert_eq!(calibration.offset, [0, 50, 300]);
+        assert_eq!(
+            calibration.matrix,
+            [
+                [((133i64 << FRACTION_BITS) / 200) as Fixed, 0, 0],
+                [0, ((133i64 << FRACTION_BITS) / 100) as Fixed, 0],
+                [0, 0, ((133i64 << FRACTION_BITS) / 100) as Fixed],
+            ]
+        );
+        // Readings at the ends of each axis have the same length.
+        let x = calibration.apply([200, 50, 300]);
+        let y = calibration.apply([0, 150, 300]);
+        let z = calibration.apply([0, 50, 200]);
+        assert_close(x, [133 << 12, 0, 0], 1);
+        assert_close(y, [0, 133 << 12, 0], 1);
+        assert_close(z, [0, 0, -133 << 12], 1);
+
+        let mut buf = [0; CALIBRATION_LEN];
+        calibration.encode(&mut buf);
+        let decoded = Calibration::decode(&buf);
+        assert_eq!(decoded.offset, calibration.offset);
+        assert_eq!(decoded.matrix, calibration.matrix);
+    }
+}
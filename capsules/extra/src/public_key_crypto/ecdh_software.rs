@@ -0,0 +1,231 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Software implementation of Elliptic-curve Diffie-Hellman.
+//!
+//! `EcdhSoftware` implements the `Ecdh` HIL on top of the arithmetic for a
+//! single curve, provided by a `Curve` implementation. The supported curves
+//! are:
+//!
+//! - X25519, see [`X25519Software`](super::x25519::X25519Software).
+//! - NIST P-256, see [`P256EcdhSoftware`](super::p256::P256EcdhSoftware).
+//!
+//! Key generation completes from the entropy callback. Computing the shared
+//! secret is done in `shared_secret()`, the result is returned to the client
+//! from a deferred call.
+
+use core::cell::Cell;
+use core::marker::PhantomData;
+
+use kernel::deferred_call::{DeferredCall, DeferredCallClient};
+use kernel::hil::entropy::{self, Continue};
+use kernel::hil::public_key_crypto::ecdh::{Ecdh, EcdhClient};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::ErrorCode;
+
+/// The length of a private key, for all supported curves.
+pub const PRIVATE_KEY_LEN: usize = 32;
+
+/// The arithmetic for a curve used by `EcdhSoftware`.
+///
+/// - `PKL`: The length in bytes of a public key.
+/// - `SSL`: The length in bytes of the shared secret.
+pub trait Curve<const PKL: usize, const SSL: usize> {
+    /// Turn uniformly random bytes into a private key. Returns `None` if the
+    /// bytes can't be used, in which case new random bytes are requested.
+    fn private_key(random: &[u8; PRIVATE_KEY_LEN]) -> Option<[u8; PRIVATE_KEY_LEN]>;
+
+    /// Calculate the public key for `private_key`.
+    fn public_key(private_key: &[u8; PRIVATE_KEY_LEN], public_key: &mut [u8; PKL]);
+
+    /// Calculate the shared secret for `private_key` and `peer_public_key`.
+    ///
+    /// Returns `INVAL` if `peer_public_key` isn't a valid public key.
+    fn shared_secret(
+        private_key: &[u8; PRIVATE_KEY_LEN],
+        peer_public_key: &[u8; PKL],
+        shared_secret: &mut [u8; SSL],
+    ) -> Result<(), ErrorCode>;
+}
+
+pub struct EcdhSoftware<'a, C: Curve<PKL, SSL>, const PKL: usize, const SSL: usize> {
+    client: OptionalCell<&'a dyn EcdhClient<PKL, SSL>>,
+    deferred_call: DeferredCall,
+
+    private_key: OptionalCell<[u8; PRIVATE_KEY_LEN]>,
+    random: Cell<[u8; PRIVATE_KEY_LEN]>,
+    random_len: Cell<usize>,
+
+    public_key: TakeCell<'static, [u8; PKL]>,
+    peer_public_key: TakeCell<'static, [u8; PKL]>,
+    shared_secret: TakeCell<'static, [u8; SSL]>,
+    result: Cell<Result<(), ErrorCode>>,
+
+    _curve: PhantomData<C>,
+}
+
+impl<'a, C: Curve<PKL, SSL>, const PKL: usize, const SSL: usize> EcdhSoftware<'a, C, PKL, SSL> {
+    pub fn new() -> Self {
+        EcdhSoftware {
+            client: OptionalCell::empty(),
+            deferred_call: DeferredCall::new(),
+            private_key: OptionalCell::empty(),
+            random: Cell::new([0; PRIVATE_KEY_LEN]),
+            random_len: Cell::new(0),
+            public_key: TakeCell::empty(),
+            peer_public_key: TakeCell::empty(),
+            shared_secret: TakeCell::empty(),
+            result: Cell::new(Ok(())),
+            _curve: PhantomData,
+        }
+    }
+
+    fn busy(&self) -> bool {
+        self.public_key.is_some() || self.shared_secret.is_some()
+    }
+
+    /// Generate the key pair from the collected random bytes, returning
+    /// `false` if the random bytes couldn't be used.
+    fn finish_generation(&self, public_key: &mut [u8; PKL]) -> bool {
+        let random = self.random.get();
+        self.random.set([0; PRIVATE_KEY_LEN]);
+        self.random_len.set(0);
+
+        match C::private_key(&random) {
+            Some(private_key) => {
+                C::public_key(&private_key, public_key);
+                self.private_key.set(private_key);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+impl<'a, C: Curve<PKL, SSL>, const PKL: usize, const SSL: usize> Ecdh<'a, PKL, SSL>
+    for EcdhSoftware<'a, C, PKL, SSL>
+{
+    fn set_client(&self, client: &'a dyn EcdhClient<PKL, SSL>) {
+        self.client.set(client);
+    }
+
+    fn generate(
+        &'a self,
+        trng: &'a dyn entropy::Entropy32<'a>,
+        public_key: &'static mut [u8; PKL],
+    ) -> Result<(), (ErrorCode, &'static mut [u8; PKL])> {
+        if self.busy() {
+            return Err((ErrorCode::BUSY, public_key));
+        }
+
+        self.private_key.clear();
+        self.random_len.set(0);
+
+        trng.set_client(self);
+        if let Err(e) = trng.get() {
+            return Err((e, public_key));
+        }
+
+        self.public_key.replace(public_key);
+        Ok(())
+    }
+
+    fn shared_secret(
+        &self,
+        peer_public_key: &'static mut [u8; PKL],
+        shared_secret: &'static mut [u8; SSL],
+    ) -> Result<(), (ErrorCode, &'static mut [u8; PKL], &'static mut [u8; SSL])> {
+        if self.busy() {
+            return Err((ErrorCode::BUSY, peer_public_key, shared_secret));
+        }
+
+        let private_key = match self.private_key.get() {
+            Some(private_key) => private_key,
+            None => return Err((ErrorCode::NODEVICE, peer_public_key, shared_secret)),
+        };
+
+        self.result.set(C::shared_secret(
+            &private_key,
+            peer_public_key,
+            shared_secret,
+        ));
+        if self.result.get().is_err() {
+            shared_secret.fill(0);
+        }
+
+        self.peer_public_key.replace(peer_public_key);
+        self.shared_secret.replace(shared_secret);
+        self.deferred_call.set();
+
+        Ok(())
+    }
+
+    fn clear_key(&self) {
+        self.private_key.clear();
+    }
+}
+
+impl<C: Curve<PKL, SSL>, const PKL: usize, const SSL: usize> entropy::Client32
+    for EcdhSoftware<'_, C, PKL, SSL>
+{
+    fn entropy_available(
+        &self,
+        entropy: &mut dyn Iterator<Item = u32>,
+        error: Result<(), ErrorCode>,
+    ) -> Continue {
+        let public_key = match self.public_key.take() {
+            Some(public_key) => public_key,
+            None => return Continue::Done,
+        };
+
+        if let Err(e) = error {
+            self.random_len.set(0);
+            self.client
+                .map(move |client| client.generation_done(Err(e), public_key));
+            return Continue::Done;
+        }
+
+        let mut random = self.random.get();
+        let mut len = self.random_len.get();
+        while len < PRIVATE_KEY_LEN {
+            match entropy.next() {
+                Some(word) => {
+                    random[len..len + 4].copy_from_slice(&word.to_le_bytes());
+                    len += 4;
+                }
+                None => break,
+            }
+        }
+        self.random.set(random);
+        self.random_len.set(len);
+
+        if len < PRIVATE_KEY_LEN || !self.finish_generation(public_key) {
+            self.public_key.replace(public_key);
+            return Continue::More;
+        }
+
+        self.client
+            .map(move |client| client.generation_done(Ok(()), public_key));
+        Continue::Done
+    }
+}
+
+impl<C: Curve<PKL, SSL>, const PKL: usize, const SSL: usize> DeferredCallClient
+    for EcdhSoftware<'_, C, PKL, SSL>
+{
+    fn register(&'static self) {
+        self.deferred_call.register(self);
+    }
+
+    fn handle_deferred_call(&self) {
+        if let Some(shared_secret) = self.shared_secret.take() {
+            if let Some(peer_public_key) = self.peer_public_key.take() {
+                let result = self.result.get();
+                self.client.map(move |client| {
+                    client.shared_secret_done(result, peer_public_key, shared_secret)
+                });
+            }
+        }
+    }
+}
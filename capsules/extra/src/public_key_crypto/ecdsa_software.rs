@@ -0,0 +1,362 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Software ECDSA signing with the NIST P-256 curve.
+//!
+//! The private key is loaded with `SetPrivateKey`, and `sign()` signs a 32
+//! byte hash with it. The signature is the 64 byte concatenation of the
+//! big-endian `r` and `s` values, callers that need the DER encoding have to
+//! convert it.
+//!
+//! The per-signature secret `k` comes from a random number generator, and
+//! the signature is calculated and returned from its callback. As for any
+//! ECDSA implementation, a predictable `k` reveals the private key, so the
+//! random number generator must be cryptographically secure.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! let ecdsa = components::ecdsa::P256EcdsaSoftwareComponent::new(mux_rng)
+//!     .finalize(components::p256_ecdsa_software_component_static!());
+//! ```
+
+use core::cell::Cell;
+
+use kernel::hil::public_key_crypto::keys::SetPrivateKey;
+use kernel::hil::public_key_crypto::signature::{ClientSign, SignatureSign};
+use kernel::hil::rng::{self, Continue, Rng};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::ErrorCode;
+
+use super::p256;
+
+/// The length of the hash that is signed.
+pub const P256_HASH_LEN: usize = 32;
+/// The length of a P-256 ECDSA signature.
+pub const P256_SIGNATURE_LEN: usize = 64;
+/// The length of a P-256 private key.
+pub const P256_PRIVATE_KEY_LEN: usize = 32;
+
+pub struct P256EcdsaSoftware<'a> {
+    rng: &'a dyn Rng<'a>,
+    client: OptionalCell<&'a dyn ClientSign<P256_HASH_LEN, P256_SIGNATURE_LEN>>,
+
+    private_key: OptionalCell<[u8; P256_PRIVATE_KEY_LEN]>,
+    k: Cell<[u8; 32]>,
+    k_len: Cell<usize>,
+
+    hash: TakeCell<'static, [u8; P256_HASH_LEN]>,
+    signature: TakeCell<'static, [u8; P256_SIGNATURE_LEN]>,
+}
+
+impl<'a> P256EcdsaSoftware<'a> {
+    pub fn new(rng: &'a dyn Rng<'a>) -> Self {
+        P256EcdsaSoftware {
+            rng,
+            client: OptionalCell::empty(),
+            private_key: OptionalCell::empty(),
+            k: Cell::new([0; 32]),
+            k_len: Cell::new(0),
+            hash: TakeCell::empty(),
+            signature: TakeCell::empty(),
+        }
+    }
+
+    /// Sign with the collected `k`, returning `false` if it couldn't be used.
+    fn finish_signing(&self, hash: &[u8; P256_HASH_LEN], signature: &mut [u8; 64]) -> bool {
+        let k = self.k.get();
+        self.k.set([0; 32]);
+        self.k_len.set(0);
+
+        self.private_key.map_or(false, |private_key| {
+            p256::ecdsa_sign(&private_key, hash, &k, signature)
+        })
+    }
+}
+
+impl<'a> SetPrivateKey<P256_PRIVATE_KEY_LEN, { p256::P256_PUBLIC_KEY_LEN }>
+    for P256EcdsaSoftware<'a>
+{
+    fn set_private_key(
+        &self,
+        key: &[u8; P256_PRIVATE_KEY_LEN],
+        public_key: &mut [u8; p256::P256_PUBLIC_KEY_LEN],
+    ) -> Result<(), ErrorCode> {
+        if self.hash.is_some() {
+            return Err(ErrorCode::BUSY);
+        }
+        if !p256::valid_scalar(key) {
+            return Err(ErrorCode::INVAL);
+        }
+
+        p256::public_key(key, public_key);
+        self.private_key.set(*key);
+        Ok(())
+    }
+
+    fn clear_private_key(&self) {
+        self.private_key.clear();
+    }
+}
+
+impl<'a> SignatureSign<'a, P256_HASH_LEN, P256_SIGNATURE_LEN> for P256EcdsaSoftware<'a> {
+    fn set_sign_client(&self, client: &'a dyn ClientSign<P256_HASH_LEN, P256_SIGNATURE_LEN>) {
+        self.client.set(client);
+    }
+
+    fn sign(
+        &self,
+        hash: &'static mut [u8; P256_HASH_LEN],
+        signature: &'static mut [u8; P256_SIGNATURE_LEN],
+    ) -> Result<
+        (),
+        (
+            ErrorCode,
+            &'static mut [u8; P256_HASH_LEN],
+            &'static mut [u8; P256_SIGNATURE_LEN],
+        ),
+    > {
+        if self.hash.is_some() {
+            return Err((ErrorCode::BUSY, hash, signature));
+        }
+        if self.private_key.is_none() {
+            return Err((ErrorCode::RESERVE, hash, signature));
+        }
+
+        self.k_len.set(0);
+        if let Err(e) = self.rng.get() {
+            return Err((e, hash, signature));
+        }
+
+        self.hash.replace(hash);
+        self.signature.replace(signature);
+        Ok(())
+    }
+}
+
+impl rng::Client for P256EcdsaSoftware<'_> {
+    fn randomness_available(
+        &self,
+        randomness: &mut dyn Iterator<Item = u32>,
+        error: Result<(), ErrorCode>,
+    ) -> Continue {
+        let (hash, signature) = match (self.hash.take(), self.signature.take()) {
+            (Some(hash), Some(signature)) => (hash, signature),
+            (hash, signature) => {
+                hash.map(|hash| self.hash.replace(hash));
+                signature.map(|signature| self.signature.replace(signature));
+                return Continue::Done;
+            }
+        };
+
+        if let Err(e) = error {
+            self.k.set([0; 32]);
+            self.k_len.set(0);
+            self.client
+                .map(move |client| client.signing_done(Err(e), hash, signature));
+            return Continue::Done;
+        }
+
+        let mut k = self.k.get();
+        let mut len = self.k_len.get();
+        while len < k.len() {
+            match randomness.next() {
+                Some(word) => {
+                    k[len..len + 4].copy_from_slice(&word.to_le_bytes());
+                    len += 4;
+                }
+                None => break,
+            }
+        }
+        self.k.set(k);
+        self.k_len.set(len);
+
+        if len < k.len() || !self.finish_signing(hash, signature) {
+            self.hash.replace(hash);
+            self.signature.replace(signature);
+            return Continue::More;
+        }
+
+        self.client
+            .map(move |client| client.signing_done(Ok(()), hash, signature));
+        Continue::Done
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::p256::tests::*;
+    use super::*;
+    use kernel::hil::rng::Client;
+    use std::boxed::Box;
+
+    /// An RNG that only records requests; the tests supply the randomness.
+    struct FakeRng {
+        requests: Cell<usize>,
+    }
+
+    impl<'a> Rng<'a> for FakeRng {
+        fn get(&self) -> Result<(), ErrorCode> {
+            self.requests.set(self.requests.get() + 1);
+            Ok(())
+        }
+
+        fn cancel(&self) -> Result<(), ErrorCode> {
+            Ok(())
+        }
+
+        fn set_client(&'a self, _client: &'a dyn rng::Client) {}
+    }
+
+    /// Records the result of the last signature.
+    struct TestClient {
+        result: Cell<Option<Result<(), ErrorCode>>>,
+        signature: Cell<[u8; P256_SIGNATURE_LEN]>,
+    }
+
+    impl ClientSign<P256_HASH_LEN, P256_SIGNATURE_LEN> for TestClient {
+        fn signing_done(
+            &self,
+            result: Result<(), ErrorCode>,
+            _hash: &'static mut [u8; P256_HASH_LEN],
+            signature: &'static mut [u8; P256_SIGNATURE_LEN],
+        ) {
+            self.result.set(Some(result));
+            self.signature.set(*signature);
+        }
+    }
+
+    fn setup() -> (
+        &'static P256EcdsaSoftware<'static>,
+        &'static FakeRng,
+        &'static TestClient,
+    ) {
+        let rng = Box::leak(Box::new(FakeRng {
+            requests: Cell::new(0),
+        }));
+        let ecdsa = Box::leak(Box::new(P256EcdsaSoftware::new(rng)));
+        let client = Box::leak(Box::new(TestClient {
+            result: Cell::new(None),
+            signature: Cell::new([0; P256_SIGNATURE_LEN]),
+        }));
+        ecdsa.set_sign_client(client);
+        (ecdsa, rng, client)
+    }
+
+    /// The RNG words that make up the big-endian `k`.
+    fn words(k: &[u8; 32]) -> [u32; 8] {
+        let mut words = [0; 8];
+        for (word, bytes) in words.iter_mut().zip(k.chunks(4)) {
+            *word = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        }
+        words
+    }
+
+    fn sign(ecdsa: &P256EcdsaSoftware<'static>) -> Result<(), ErrorCode> {
+        let hash = Box::leak(Box::new(RFC6979_HASH));
+        let signature = Box::leak(Box::new([0; P256_SIGNATURE_LEN]));
+        ecdsa.sign(hash, signature).map_err(|(e, _, _)| e)
+    }
+
+    fn set_key(ecdsa: &P256EcdsaSoftware<'static>) {
+        let mut public_key = [0; p256::P256_PUBLIC_KEY_LEN];
+        assert_eq!(
+            ecdsa.set_private_key(&RFC6979_PRIVATE_KEY, &mut public_key),
+            Ok(())
+        );
+        assert_eq!(public_key, RFC6979_PUBLIC_KEY);
+    }
+
+    #[test]
+    fn rfc6979_vector() {
+        let (ecdsa, rng, client) = setup();
+        assert_eq!(sign(ecdsa), Err(ErrorCode::RESERVE));
+        set_key(ecdsa);
+
+        assert_eq!(sign(ecdsa), Ok(()));
+        assert_eq!(rng.requests.get(), 1);
+        assert_eq!(sign(ecdsa), Err(ErrorCode::BUSY));
+
+        // The RNG may deliver `k` over several callbacks.
+        let k = words(&RFC6979_K);
+        assert_eq!(
+            ecdsa.randomness_available(&mut k[..5].iter().copied(), Ok(())),
+            Continue::More
+        );
+        assert_eq!(client.result.get(), None);
+        assert_eq!(
+            ecdsa.randomness_available(&mut k[5..].iter().copied(), Ok(())),
+            Continue::Done
+        );
+        assert_eq!(client.result.get(), Some(Ok(())));
+        assert_eq!(client.signature.get(), RFC6979_SIGNATURE);
+        assert!(p256::tests::ecdsa_verify(
+            &RFC6979_PUBLIC_KEY,
+            &RFC6979_HASH,
+            &client.signature.get()
+        ));
+    }
+
+    #[test]
+    fn out_of_range_k_is_rejected() {
+        let (ecdsa, _rng, client) = setup();
+        set_key(ecdsa);
+        assert_eq!(sign(ecdsa), Ok(()));
+
+        // Zero and values of at least n are retried with more randomness.
+        for k in [[0; 32], [0xff; 32]] {
+            assert_eq!(
+                ecdsa.randomness_available(&mut words(&k).into_iter(), Ok(())),
+                Continue::More
+            );
+            assert_eq!(client.result.get(), None);
+        }
+
+        assert_eq!(
+            ecdsa.randomness_available(&mut words(&RFC6979_K).into_iter(), Ok(())),
+            Continue::Done
+        );
+        assert_eq!(client.signature.get(), RFC6979_SIGNATURE);
+    }
+
+    #[test]
+    fn rng_error() {
+        let (ecdsa, _rng, client) = setup();
+        set_key(ecdsa);
+        assert_eq!(sign(ecdsa), Ok(()));
+
+        assert_eq!(
+            ecdsa.randomness_available(&mut core::iter::empty(), Err(ErrorCode::FAIL)),
+            Continue::Done
+        );
+        assert_eq!(client.result.get(), Some(Err(ErrorCode::FAIL)));
+
+        // The partial `k` from an earlier request isn't reused.
+        assert_eq!(sign(ecdsa), Ok(()));
+        let k = words(&RFC6979_K);
+        ecdsa.randomness_available(&mut k[..4].iter().copied(), Ok(()));
+        ecdsa.randomness_available(&mut core::iter::empty(), Err(ErrorCode::FAIL));
+        assert_eq!(sign(ecdsa), Ok(()));
+        ecdsa.randomness_available(&mut k[4..].iter().copied(), Ok(()));
+        assert_eq!(client.result.get(), Some(Err(ErrorCode::FAIL)));
+        ecdsa.randomness_available(&mut k[4..].iter().copied(), Ok(()));
+        assert_eq!(client.result.get(), Some(Ok(())));
+        assert_ne!(client.signature.get(), RFC6979_SIGNATURE);
+    }
+
+    #[test]
+    fn private_key_range() {
+        let (ecdsa, _rng, _client) = setup();
+        let mut public_key = [0; p256::P256_PUBLIC_KEY_LEN];
+        for key in [[0; 32], [0xff; 32]] {
+            assert_eq!(
+                ecdsa.set_private_key(&key, &mut public_key),
+                Err(ErrorCode::INVAL)
+            );
+        }
+    }
+}
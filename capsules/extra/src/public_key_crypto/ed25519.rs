@@ -0,0 +1,584 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Software Ed25519 signature verification, as specified in RFC 8032.
+//!
+//! `SignatureVerify` passes the hash of the message rather than the message
+//! itself, so this verifies Ed25519ph signatures: the hash is the SHA-512
+//! digest of the message, and the signature is over that digest with an empty
+//! context. That is the same hash an app credentials checker computes over a
+//! process binary, so this can check `Ed25519` TBF credentials with
+//! `AppCheckerSignature`.
+//!
+//! Only Ed25519ph is supported. A plain Ed25519 signature hashes the whole
+//! message together with `R` and the public key, so it can't be checked from
+//! the message hash, and verifying one here always fails.
+//!
+//! The public key is set with `SetPublicKey`. Verifying a signature hashes
+//! `R`, the public key and the message hash with the SHA-512 engine `S`, and
+//! then checks the signature equation. The curve arithmetic follows TweetNaCl
+//! and uses the same field arithmetic as X25519. Only public values are
+//! involved, so it doesn't need to run in constant time.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! let sha512 = components::sha::ShaSoftware512Component::new()
+//!     .finalize(components::sha_software_512_component_static!(64));
+//! let ed25519 = components::ed25519::Ed25519SoftwareComponent::new(sha512)
+//!     .finalize(components::ed25519_software_component_static!(
+//!         capsules_extra::sha512::Sha512Software<'static>
+//!     ));
+//! ed25519.set_public_key(&APP_SIGNING_KEY).unwrap();
+//! ```
+
+use kernel::hil::digest::{self, DigestDataHash, Sha512};
+use kernel::hil::public_key_crypto::keys::SetPublicKey;
+use kernel::hil::public_key_crypto::signature::{ClientVerify, SignatureVerify};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::utilities::leasable_buffer::{SubSlice, SubSliceMut};
+use kernel::ErrorCode;
+
+use super::x25519::{add, invert, mul, pack, square, sub, swap, unpack, Fe};
+
+/// The length of the message hash, a SHA-512 digest.
+pub const ED25519_HASH_LEN: usize = 64;
+/// The length of an Ed25519 signature.
+pub const ED25519_SIGNATURE_LEN: usize = 64;
+/// The length of an Ed25519 public key.
+pub const ED25519_PUBLIC_KEY_LEN: usize = 32;
+
+/// `dom2(1, "")`, which starts the hashed data for Ed25519ph with an empty
+/// context.
+const DOM2: &[u8; 34] = b"SigEd25519 no Ed25519 collisions\x01\x00";
+
+/// The length of the data that is hashed: `dom2`, `R`, the public key and
+/// the message hash.
+pub const DATA_LEN: usize = DOM2.len() + 32 + ED25519_PUBLIC_KEY_LEN + ED25519_HASH_LEN;
+
+/// A point in extended coordinates `(X, Y, Z, T)`.
+type Point = [Fe; 4];
+
+const GF0: Fe = [0; 16];
+const GF1: Fe = [1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+
+/// The curve constant d.
+const D: Fe = [
+    0x78a3, 0x1359, 0x4dca, 0x75eb, 0xd8ab, 0x4141, 0x0a4d, 0x0070, 0xe898, 0x7779, 0x4079, 0x8cc7,
+    0xfe73, 0x2b6f, 0x6cee, 0x5203,
+];
+/// 2 * d.
+const D2: Fe = [
+    0xf159, 0x26b2, 0x9b94, 0xebd6, 0xb156, 0x8283, 0x149a, 0x00e0, 0xd130, 0xeef3, 0x80f2, 0x198e,
+    0xfce7, 0x56df, 0xd9dc, 0x2406,
+];
+/// The x coordinate of the base point.
+const BASE_X: Fe = [
+    0xd51a, 0x8f25, 0x2d60, 0xc956, 0xa7b2, 0x9525, 0xc760, 0x692c, 0xdc5c, 0xfdd6, 0xe231, 0xc0a4,
+    0x53fe, 0xcd6e, 0x36d3, 0x2169,
+];
+/// The y coordinate of the base point.
+const BASE_Y: Fe = [
+    0x6658, 0x6666, 0x6666, 0x6666, 0x6666, 0x6666, 0x6666, 0x6666, 0x6666, 0x6666, 0x6666, 0x6666,
+    0x6666, 0x6666, 0x6666, 0x6666,
+];
+/// A square root of -1.
+const SQRT_M1: Fe = [
+    0xa0b0, 0x4a0e, 0x1b27, 0xc4ee, 0xe478, 0xad2f, 0x1806, 0x2f43, 0xd7a7, 0x3dfb, 0x0099, 0x2b4d,
+    0xdf0b, 0x4fc1, 0x2480, 0x2b83,
+];
+
+/// The group order, little-endian.
+const ORDER: [i64; 32] = [
+    0xed, 0xd3, 0xf5, 0x5c, 0x1a, 0x63, 0x12, 0x58, 0xd6, 0x9c, 0xf7, 0xa2, 0xde, 0xf9, 0xde, 0x14,
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x10,
+];
+
+fn equal(a: &Fe, b: &Fe) -> bool {
+    let mut a_bytes = [0; 32];
+    let mut b_bytes = [0; 32];
+    pack(&mut a_bytes, a);
+    pack(&mut b_bytes, b);
+    a_bytes == b_bytes
+}
+
+fn parity(a: &Fe) -> u8 {
+    let mut bytes = [0; 32];
+    pack(&mut bytes, a);
+    bytes[0] & 1
+}
+
+/// Calculate `i^((p - 5) / 8)`, used to take square roots.
+fn pow2523(i: &Fe) -> Fe {
+    let mut c = *i;
+    for a in (0..=250).rev() {
+        c = square(&c);
+        if a != 1 {
+            c = mul(&c, i);
+        }
+    }
+    c
+}
+
+/// Calculate `p + q`.
+// This keeps TweetNaCl's variable names so it can be compared against it.
+#[allow(clippy::many_single_char_names)]
+fn point_add(p: &Point, q: &Point) -> Point {
+    let a = mul(&sub(&p[1], &p[0]), &sub(&q[1], &q[0]));
+    let b = mul(&add(&p[0], &p[1]), &add(&q[0], &q[1]));
+    let c = mul(&mul(&p[3], &q[3]), &D2);
+    let d = mul(&p[2], &q[2]);
+    let d = add(&d, &d);
+    let e = sub(&b, &a);
+    let f = sub(&d, &c);
+    let g = add(&d, &c);
+    let h = add(&b, &a);
+    [mul(&e, &f), mul(&h, &g), mul(&g, &f), mul(&e, &h)]
+}
+
+fn point_swap(p: &mut Point, q: &mut Point, b: i64) {
+    for (p, q) in p.iter_mut().zip(q.iter_mut()) {
+        swap(p, q, b);
+    }
+}
+
+/// Calculate `scalar * q` for the little-endian `scalar`.
+fn scalar_mult(q: &Point, scalar: &[u8; 32]) -> Point {
+    let mut p = [GF0, GF1, GF1, GF0];
+    let mut q = *q;
+    for i in (0..256).rev() {
+        let b = ((scalar[i / 8] >> (i & 7)) & 1) as i64;
+        point_swap(&mut p, &mut q, b);
+        q = point_add(&q, &p);
+        p = point_add(&p, &p);
+        point_swap(&mut p, &mut q, b);
+    }
+    p
+}
+
+fn base_point() -> Point {
+    [BASE_X, BASE_Y, GF1, mul(&BASE_X, &BASE_Y)]
+}
+
+/// Encode a point as its y coordinate and the sign of its x coordinate.
+fn pack_point(p: &Point) -> [u8; 32] {
+    let z_inv = invert(&p[2]);
+    let x = mul(&p[0], &z_inv);
+    let y = mul(&p[1], &z_inv);
+    let mut out = [0; 32];
+    pack(&mut out, &y);
+    out[31] ^= parity(&x) << 7;
+    out
+}
+
+/// Decode a public key to the negation of its point, or `None` if it isn't
+/// a point on the curve.
+fn unpack_neg(public_key: &[u8; ED25519_PUBLIC_KEY_LEN]) -> Option<Point> {
+    let y = unpack(public_key);
+    let y2 = square(&y);
+    // x^2 = (y^2 - 1) / (d y^2 + 1)
+    let num = sub(&y2, &GF1);
+    let den = add(&GF1, &mul(&y2, &D));
+
+    let den2 = square(&den);
+    let den4 = square(&den2);
+    let den6 = mul(&den4, &den2);
+    let mut t = mul(&mul(&den6, &num), &den);
+    t = pow2523(&t);
+    t = mul(&mul(&mul(&t, &num), &den), &den);
+    let mut x = mul(&t, &den);
+
+    if !equal(&mul(&square(&x), &den), &num) {
+        x = mul(&x, &SQRT_M1);
+    }
+    if !equal(&mul(&square(&x), &den), &num) {
+        return None;
+    }
+
+    // Pick the root with the other sign, to get -x.
+    if parity(&x) == public_key[31] >> 7 {
+        x = sub(&GF0, &x);
+    }
+    Some([x, y, GF1, mul(&x, &y)])
+}
+
+/// Reduce the little-endian `hash` modulo the group order.
+fn reduce(hash: &[u8; 64]) -> [u8; 32] {
+    let mut x = [0i64; 64];
+    for (x, byte) in x.iter_mut().zip(hash) {
+        *x = *byte as i64;
+    }
+
+    for i in (32..64).rev() {
+        let mut carry = 0;
+        for j in (i - 32)..(i - 12) {
+            x[j] += carry - 16 * x[i] * ORDER[j - (i - 32)];
+            carry = (x[j] + 128) >> 8;
+            x[j] -= carry << 8;
+        }
+        x[i - 12] += carry;
+        x[i] = 0;
+    }
+
+    let mut carry = 0;
+    for j in 0..32 {
+        x[j] += carry - (x[31] >> 4) * ORDER[j];
+        carry = x[j] >> 8;
+        x[j] &= 255;
+    }
+    for j in 0..32 {
+        x[j] -= carry * ORDER[j];
+    }
+
+    let mut out = [0; 32];
+    for i in 0..32 {
+        x[i + 1] += x[i] >> 8;
+        out[i] = x[i] as u8;
+    }
+    out
+}
+
+/// Check that the little-endian scalar is less than the group order, which
+/// RFC 8032 requires of `S` so signatures aren't malleable.
+fn below_order(scalar: &[u8; 32]) -> bool {
+    for i in (0..32).rev() {
+        let byte = scalar[i] as i64;
+        if byte != ORDER[i] {
+            return byte < ORDER[i];
+        }
+    }
+    false
+}
+
+pub struct Ed25519Software<'a, S: Sha512 + DigestDataHash<'a, ED25519_HASH_LEN>> {
+    sha: &'a S,
+    client: OptionalCell<&'a dyn ClientVerify<ED25519_HASH_LEN, ED25519_SIGNATURE_LEN>>,
+
+    public_key: OptionalCell<[u8; ED25519_PUBLIC_KEY_LEN]>,
+
+    /// Holds the data to hash, must be at least `DATA_LEN` bytes.
+    data: TakeCell<'static, [u8]>,
+    /// The hash of `data`.
+    challenge: TakeCell<'static, [u8; 64]>,
+
+    hash: TakeCell<'static, [u8; ED25519_HASH_LEN]>,
+    signature: TakeCell<'static, [u8; ED25519_SIGNATURE_LEN]>,
+}
+
+impl<'a, S: Sha512 + DigestDataHash<'a, ED25519_HASH_LEN>> Ed25519Software<'a, S> {
+    pub fn new(
+        sha: &'a S,
+        data: &'static mut [u8],
+        challenge: &'static mut [u8; 64],
+    ) -> Ed25519Software<'a, S> {
+        Ed25519Software {
+            sha,
+            client: OptionalCell::empty(),
+            public_key: OptionalCell::empty(),
+            data: TakeCell::new(data),
+            challenge: TakeCell::new(challenge),
+            hash: TakeCell::empty(),
+            signature: TakeCell::empty(),
+        }
+    }
+
+    /// Check the signature equation `[S]B = R + [k]A`, where `k` is the
+    /// reduced `challenge`.
+    fn check(&self, challenge: &[u8; 64], signature: &[u8; ED25519_SIGNATURE_LEN]) -> bool {
+        let neg_public_key = match self.public_key.get().and_then(|key| unpack_neg(&key)) {
+            Some(point) => point,
+            None => return false,
+        };
+        let mut s = [0; 32];
+        s.copy_from_slice(&signature[32..]);
+        if !below_order(&s) {
+            return false;
+        }
+
+        let r = point_add(
+            &scalar_mult(&base_point(), &s),
+            &scalar_mult(&neg_public_key, &reduce(challenge)),
+        );
+        pack_point(&r)[..] == signature[..32]
+    }
+
+    fn finish(&self, result: Result<bool, ErrorCode>) {
+        if let (Some(hash), Some(signature)) = (self.hash.take(), self.signature.take()) {
+            self.client
+                .map(move |client| client.verification_done(result, hash, signature));
+        }
+    }
+}
+
+impl<'a, S: Sha512 + DigestDataHash<'a, ED25519_HASH_LEN>> SetPublicKey<ED25519_PUBLIC_KEY_LEN>
+    for Ed25519Software<'a, S>
+{
+    fn set_public_key(&self, public_key: &[u8; ED25519_PUBLIC_KEY_LEN]) -> Result<(), ErrorCode> {
+        if self.hash.is_some() {
+            return Err(ErrorCode::BUSY);
+        }
+        if unpack_neg(public_key).is_none() {
+            return Err(ErrorCode::INVAL);
+        }
+
+        self.public_key.set(*public_key);
+        Ok(())
+    }
+}
+
+impl<'a, S: Sha512 + DigestDataHash<'a, ED25519_HASH_LEN>>
+    SignatureVerify<'a, ED25519_HASH_LEN, ED25519_SIGNATURE_LEN> for Ed25519Software<'a, S>
+{
+    fn set_verify_client(
+        &self,
+        client: &'a dyn ClientVerify<ED25519_HASH_LEN, ED25519_SIGNATURE_LEN>,
+    ) {
+        self.client.set(client);
+    }
+
+    fn verify(
+        &self,
+        hash: &'static mut [u8; ED25519_HASH_LEN],
+        signature: &'static mut [u8; ED25519_SIGNATURE_LEN],
+    ) -> Result<
+        (),
+        (
+            ErrorCode,
+            &'static mut [u8; ED25519_HASH_LEN],
+            &'static mut [u8; ED25519_SIGNATURE_LEN],
+        ),
+    > {
+        if self.hash.is_some() {
+            return Err((ErrorCode::BUSY, hash, signature));
+        }
+        let public_key = match self.public_key.get() {
+            Some(public_key) => public_key,
+            None => return Err((ErrorCode::RESERVE, hash, signature)),
+        };
+        let data = match self.data.take() {
+            Some(data) => data,
+            None => return Err((ErrorCode::BUSY, hash, signature)),
+        };
+
+        let (prefix, rest) = data[..DATA_LEN].split_at_mut(DOM2.len());
+        prefix.copy_from_slice(DOM2);
+        rest[..32].copy_from_slice(&signature[..32]);
+        rest[32..64].copy_from_slice(&public_key);
+        rest[64..].copy_from_slice(hash);
+
+        self.sha.clear_data();
+        if let Err(e) = self.sha.set_mode_sha512() {
+            self.data.replace(data);
+            return Err((e, hash, signature));
+        }
+        let mut data = SubSliceMut::new(data);
+        data.slice(..DATA_LEN);
+        if let Err((e, data)) = self.sha.add_mut_data(data) {
+            self.data.replace(data.take());
+            return Err((e, hash, signature));
+        }
+
+        self.hash.replace(hash);
+        self.signature.replace(signature);
+        Ok(())
+    }
+}
+
+impl<'a, S: Sha512 + DigestDataHash<'a, ED25519_HASH_LEN>> digest::ClientData<ED25519_HASH_LEN>
+    for Ed25519Software<'a, S>
+{
+    fn add_data_done(&self, _result: Result<(), ErrorCode>, _data: SubSlice<'static, u8>) {}
+
+    fn add_mut_data_done(&self, result: Result<(), ErrorCode>, data: SubSliceMut<'static, u8>) {
+        self.data.replace(data.take());
+
+        if let Err(e) = result {
+            self.finish(Err(e));
+            return;
+        }
+        if let Some(challenge) = self.challenge.take() {
+            if let Err((e, challenge)) = self.sha.run(challenge) {
+                self.challenge.replace(challenge);
+                self.finish(Err(e));
+            }
+        } else {
+            self.finish(Err(ErrorCode::FAIL));
+        }
+    }
+}
+
+impl<'a, S: Sha512 + DigestDataHash<'a, ED25519_HASH_LEN>> digest::ClientHash<ED25519_HASH_LEN>
+    for Ed25519Software<'a, S>
+{
+    fn hash_done(&self, result: Result<(), ErrorCode>, digest: &'static mut [u8; 64]) {
+        let result = result.map(|()| {
+            self.signature
+                .map_or(false, |signature| self.check(digest, signature))
+        });
+        self.challenge.replace(digest);
+        self.finish(result);
+    }
+}
+
+impl<'a, S: Sha512 + DigestDataHash<'a, ED25519_HASH_LEN>> digest::ClientVerify<ED25519_HASH_LEN>
+    for Ed25519Software<'a, S>
+{
+    // Only needed so this can be the client of engines that implement `Digest`.
+    fn verification_done(&self, _result: Result<bool, ErrorCode>, _compare: &'static mut [u8; 64]) {
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::sha512_fake::FakeSha512;
+    use core::cell::Cell;
+    use kernel::hil::digest::DigestDataHash;
+    use std::boxed::Box;
+
+    // RFC 8032 section 7.3, Ed25519ph of the message "abc".
+    const PUBLIC_KEY: [u8; 32] = [
+        0xec, 0x17, 0x2b, 0x93, 0xad, 0x5e, 0x56, 0x3b, 0xf4, 0x93, 0x2c, 0x70, 0xe1, 0x24, 0x50,
+        0x34, 0xc3, 0x54, 0x67, 0xef, 0x2e, 0xfd, 0x4d, 0x64, 0xeb, 0xf8, 0x19, 0x68, 0x34, 0x67,
+        0xe2, 0xbf,
+    ];
+    /// SHA-512("abc").
+    const HASH: [u8; 64] = [
+        0xdd, 0xaf, 0x35, 0xa1, 0x93, 0x61, 0x7a, 0xba, 0xcc, 0x41, 0x73, 0x49, 0xae, 0x20, 0x41,
+        0x31, 0x12, 0xe6, 0xfa, 0x4e, 0x89, 0xa9, 0x7e, 0xa2, 0x0a, 0x9e, 0xee, 0xe6, 0x4b, 0x55,
+        0xd3, 0x9a, 0x21, 0x92, 0x99, 0x2a, 0x27, 0x4f, 0xc1, 0xa8, 0x36, 0xba, 0x3c, 0x23, 0xa3,
+        0xfe, 0xeb, 0xbd, 0x45, 0x4d, 0x44, 0x23, 0x64, 0x3c, 0xe8, 0x0e, 0x2a, 0x9a, 0xc9, 0x4f,
+        0xa5, 0x4c, 0xa4, 0x9f,
+    ];
+    const SIGNATURE: [u8; 64] = [
+        0x98, 0xa7, 0x02, 0x22, 0xf0, 0xb8, 0x12, 0x1a, 0xa9, 0xd3, 0x0f, 0x81, 0x3d, 0x68, 0x3f,
+        0x80, 0x9e, 0x46, 0x2b, 0x46, 0x9c, 0x7f, 0xf8, 0x76, 0x39, 0x49, 0x9b, 0xb9, 0x4e, 0x6d,
+        0xae, 0x41, 0x31, 0xf8, 0x50, 0x42, 0x46, 0x3c, 0x2a, 0x35, 0x5a, 0x20, 0x03, 0xd0, 0x62,
+        0xad, 0xf5, 0xaa, 0xa1, 0x0b, 0x8c, 0x61, 0xe6, 0x36, 0x06, 0x2a, 0xaa, 0xd1, 0x1c, 0x2a,
+        0x26, 0x08, 0x34, 0x06,
+    ];
+
+    /// Records the result of the last verification.
+    struct TestClient {
+        result: Cell<Option<Result<bool, ErrorCode>>>,
+    }
+
+    impl ClientVerify<ED25519_HASH_LEN, ED25519_SIGNATURE_LEN> for TestClient {
+        fn verification_done(
+            &self,
+            result: Result<bool, ErrorCode>,
+            _hash: &'static mut [u8; ED25519_HASH_LEN],
+            _signature: &'static mut [u8; ED25519_SIGNATURE_LEN],
+        ) {
+            self.result.set(Some(result));
+        }
+    }
+
+    type TestEd25519 = Ed25519Software<'static, FakeSha512<64>>;
+
+    fn setup() -> (
+        &'static TestEd25519,
+        &'static FakeSha512<64>,
+        &'static TestClient,
+    ) {
+        let sha = Box::leak(Box::new(FakeSha512::new()));
+        let ed25519 = Box::leak(Box::new(Ed25519Software::new(
+            &*sha,
+            Box::leak(Box::new([0; DATA_LEN])),
+            Box::leak(Box::new([0; 64])),
+        )));
+        sha.set_client(ed25519);
+        let client = Box::leak(Box::new(TestClient {
+            result: Cell::new(None),
+        }));
+        ed25519.set_verify_client(client);
+        (ed25519, sha, client)
+    }
+
+    fn verify(hash: &[u8; 64], signature: &[u8; 64]) -> Result<bool, ErrorCode> {
+        let (ed25519, sha, client) = setup();
+        assert_eq!(ed25519.set_public_key(&PUBLIC_KEY), Ok(()));
+        let hash = Box::leak(Box::new(*hash));
+        let signature = Box::leak(Box::new(*signature));
+        assert!(ed25519.verify(hash, signature).is_ok());
+        sha.complete_all();
+        client.result.get().unwrap()
+    }
+
+    #[test]
+    fn rfc8032_ed25519ph() {
+        assert_eq!(verify(&HASH, &SIGNATURE), Ok(true));
+    }
+
+    #[test]
+    fn tampered_signature() {
+        // R.
+        let mut signature = SIGNATURE;
+        signature[0] ^= 0x01;
+        assert_eq!(verify(&HASH, &signature), Ok(false));
+
+        // S.
+        let mut signature = SIGNATURE;
+        signature[32] ^= 0x01;
+        assert_eq!(verify(&HASH, &signature), Ok(false));
+
+        // S + L is the same scalar, but RFC 8032 requires S < L.
+        let mut signature = SIGNATURE;
+        let mut carry = 0;
+        for (byte, order) in signature[32..].iter_mut().zip(ORDER.iter()) {
+            let sum = *byte as i64 + order + carry;
+            *byte = sum as u8;
+            carry = sum >> 8;
+        }
+        assert_eq!(carry, 0);
+        assert_eq!(verify(&HASH, &signature), Ok(false));
+
+        // The hash.
+        let mut hash = HASH;
+        hash[63] ^= 0x80;
+        assert_eq!(verify(&hash, &SIGNATURE), Ok(false));
+    }
+
+    #[test]
+    fn plain_ed25519_is_rejected() {
+        // A plain Ed25519 signature, with the same key, of the SHA-512 digest
+        // of "abc" as the message.
+        let signature = [
+            0xdc, 0x2a, 0x44, 0x59, 0xe7, 0x36, 0x96, 0x33, 0xa5, 0x2b, 0x1b, 0xf2, 0x77, 0x83,
+            0x9a, 0x00, 0x20, 0x10, 0x09, 0xa3, 0xef, 0xbf, 0x3e, 0xcb, 0x69, 0xbe, 0xa2, 0x18,
+            0x6c, 0x26, 0xb5, 0x89, 0x09, 0x35, 0x1f, 0xc9, 0xac, 0x90, 0xb3, 0xec, 0xfd, 0xfb,
+            0xc7, 0xc6, 0x64, 0x31, 0xe0, 0x30, 0x3d, 0xca, 0x17, 0x9c, 0x13, 0x8a, 0xc1, 0x7a,
+            0xd9, 0xbe, 0xf1, 0x17, 0x73, 0x31, 0xa7, 0x04,
+        ];
+        assert_eq!(verify(&HASH, &signature), Ok(false));
+    }
+
+    #[test]
+    fn public_key_and_busy() {
+        let (ed25519, sha, client) = setup();
+        let hash = Box::leak(Box::new(HASH));
+        let signature = Box::leak(Box::new(SIGNATURE));
+        let (e, hash, signature) = ed25519.verify(hash, signature).unwrap_err();
+        assert_eq!(e, ErrorCode::RESERVE);
+
+        // y = 2 isn't the y coordinate of a point on the curve.
+        let mut invalid = [0; 32];
+        invalid[0] = 2;
+        assert_eq!(ed25519.set_public_key(&invalid), Err(ErrorCode::INVAL));
+
+        assert_eq!(ed25519.set_public_key(&PUBLIC_KEY), Ok(()));
+        assert!(ed25519.verify(hash, signature).is_ok());
+        let (e, _, _) = ed25519
+            .verify(Box::leak(Box::new(HASH)), Box::leak(Box::new(SIGNATURE)))
+            .unwrap_err();
+        assert_eq!(e, ErrorCode::BUSY);
+        assert_eq!(ed25519.set_public_key(&PUBLIC_KEY), Err(ErrorCode::BUSY));
+
+        sha.complete_all();
+        assert_eq!(client.result.get(), Some(Ok(true)));
+    }
+}
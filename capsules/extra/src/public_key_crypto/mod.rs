@@ -4,4 +4,9 @@
 
 //! Provides capsules for asymmetric encryption
 
+pub mod ecdh_software;
+pub mod ecdsa_software;
+pub mod ed25519;
+pub mod p256;
 pub mod rsa_keys;
+pub mod x25519;
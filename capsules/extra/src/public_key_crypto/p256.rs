@@ -0,0 +1,741 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! ECDH and ECDSA signing using the NIST P-256 curve.
+//!
+//! Field elements are stored as eight 32-bit limbs in Montgomery form. Points
+//! use projective coordinates with the complete addition formula from "Complete
+//! addition formulas for prime order elliptic curves" (Renes, Costello and
+//! Batina), so the scalar multiplication, a Montgomery ladder, has no special
+//! cases and runs in constant time.
+//!
+//! Public keys are the 64 byte concatenation of the big-endian X and Y
+//! coordinates, without the `0x04` prefix used by SEC1. The shared secret is
+//! the big-endian X coordinate of the shared point.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! let p256 = components::ecdh::P256EcdhSoftwareComponent::new()
+//!     .finalize(components::p256_ecdh_software_component_static!());
+//! ```
+
+use kernel::ErrorCode;
+
+use super::ecdh_software::{Curve, EcdhSoftware, PRIVATE_KEY_LEN};
+
+/// The length of a P-256 public key.
+pub const P256_PUBLIC_KEY_LEN: usize = 64;
+/// The length of a P-256 shared secret.
+pub const P256_SHARED_SECRET_LEN: usize = 32;
+
+/// ECDH using P-256.
+pub type P256EcdhSoftware<'a> = EcdhSoftware<'a, P256, P256_PUBLIC_KEY_LEN, P256_SHARED_SECRET_LEN>;
+
+/// A field element, or scalar, with the least significant limb first.
+type Fe = [u32; 8];
+
+/// A modulus for Montgomery arithmetic.
+struct Modulus {
+    m: Fe,
+    /// `-m^-1 mod 2^32`.
+    m0_inv: u32,
+    /// `2^512 mod m`, used to convert into Montgomery form.
+    r2: Fe,
+}
+
+/// The field prime.
+const FIELD: Modulus = Modulus {
+    m: [
+        0xffffffff, 0xffffffff, 0xffffffff, 0x00000000, 0x00000000, 0x00000000, 0x00000001,
+        0xffffffff,
+    ],
+    m0_inv: 1,
+    r2: [
+        0x00000003, 0x00000000, 0xffffffff, 0xfffffffb, 0xfffffffe, 0xffffffff, 0xfffffffd,
+        0x00000004,
+    ],
+};
+
+/// The order of the base point.
+const ORDER: Modulus = Modulus {
+    m: [
+        0xfc632551, 0xf3b9cac2, 0xa7179e84, 0xbce6faad, 0xffffffff, 0xffffffff, 0x00000000,
+        0xffffffff,
+    ],
+    m0_inv: 0xee00bc4f,
+    r2: [
+        0xbe79eea2, 0x83244c95, 0x49bd6fa6, 0x4699799c, 0x2b6bec59, 0x2845b239, 0xf3d95620,
+        0x66e12d94,
+    ],
+};
+
+const P: Fe = FIELD.m;
+const N: Fe = ORDER.m;
+
+const B: Fe = [
+    0x27d2604b, 0x3bce3c3e, 0xcc53b0f6, 0x651d06b0, 0x769886bc, 0xb3ebbd55, 0xaa3a93e7, 0x5ac635d8,
+];
+
+const GX: Fe = [
+    0xd898c296, 0xf4a13945, 0x2deb33a0, 0x77037d81, 0x63a440f2, 0xf8bce6e5, 0xe12c4247, 0x6b17d1f2,
+];
+
+const GY: Fe = [
+    0x37bf51f5, 0xcbb64068, 0x6b315ece, 0x2bce3357, 0x7c0f9e16, 0x8ee7eb4a, 0xfe1a7f9b, 0x4fe342e2,
+];
+
+const ONE: Fe = [1, 0, 0, 0, 0, 0, 0, 0];
+
+fn from_bytes(bytes: &[u8]) -> Fe {
+    let mut a = [0; 8];
+    for (i, limb) in a.iter_mut().enumerate() {
+        let j = 28 - 4 * i;
+        *limb = u32::from_be_bytes([bytes[j], bytes[j + 1], bytes[j + 2], bytes[j + 3]]);
+    }
+    a
+}
+
+fn to_bytes(a: &Fe, bytes: &mut [u8]) {
+    for (i, limb) in a.iter().enumerate() {
+        let j = 28 - 4 * i;
+        bytes[j..j + 4].copy_from_slice(&limb.to_be_bytes());
+    }
+}
+
+/// Return `a - b` and the borrow out.
+fn sub_borrow(a: &Fe, b: &Fe) -> (Fe, u32) {
+    let mut o = [0; 8];
+    let mut borrow = 0;
+    for i in 0..8 {
+        let d = (a[i] as u64).wrapping_sub(b[i] as u64 + borrow);
+        o[i] = d as u32;
+        borrow = d >> 63;
+    }
+    (o, borrow as u32)
+}
+
+/// Select `b` if `mask` is all ones, or `a` if it is zero.
+fn select(a: &Fe, b: &Fe, mask: u32) -> Fe {
+    let mut o = [0; 8];
+    for i in 0..8 {
+        o[i] = (a[i] & !mask) | (b[i] & mask);
+    }
+    o
+}
+
+impl Modulus {
+    /// Reduce `a + carry * 2^256`, which is less than `2m`.
+    fn reduce_once(&self, a: &Fe, carry: u32) -> Fe {
+        let (d, borrow) = sub_borrow(a, &self.m);
+        // Keep `d` unless subtracting went negative without a carry to absorb
+        // it.
+        let use_a = borrow & !carry & 1;
+        select(&d, a, use_a.wrapping_neg())
+    }
+
+    fn add(&self, a: &Fe, b: &Fe) -> Fe {
+        let mut o = [0; 8];
+        let mut carry = 0;
+        for i in 0..8 {
+            let s = a[i] as u64 + b[i] as u64 + carry;
+            o[i] = s as u32;
+            carry = s >> 32;
+        }
+        self.reduce_once(&o, carry as u32)
+    }
+
+    fn sub(&self, a: &Fe, b: &Fe) -> Fe {
+        let (diff, borrow) = sub_borrow(a, b);
+        // Add m back if the subtraction borrowed.
+        let modulus = select(&[0; 8], &self.m, borrow.wrapping_neg());
+
+        let mut o = [0; 8];
+        let mut carry = 0;
+        for i in 0..8 {
+            let sum = diff[i] as u64 + modulus[i] as u64 + carry;
+            o[i] = sum as u32;
+            carry = sum >> 32;
+        }
+        o
+    }
+
+    /// Montgomery multiplication, calculates `a * b / 2^256 mod m`.
+    fn mul(&self, a: &Fe, b: &Fe) -> Fe {
+        let mut t = [0u32; 10];
+        for i in 0..8 {
+            let mut carry = 0;
+            for j in 0..8 {
+                let sum = t[j] as u64 + a[j] as u64 * b[i] as u64 + carry;
+                t[j] = sum as u32;
+                carry = sum >> 32;
+            }
+            let sum = t[8] as u64 + carry;
+            t[8] = sum as u32;
+            t[9] = (sum >> 32) as u32;
+
+            let q = t[0].wrapping_mul(self.m0_inv) as u64;
+            let mut carry = (t[0] as u64 + q * self.m[0] as u64) >> 32;
+            for j in 1..8 {
+                let sum = t[j] as u64 + q * self.m[j] as u64 + carry;
+                t[j - 1] = sum as u32;
+                carry = sum >> 32;
+            }
+            let sum = t[8] as u64 + carry;
+            t[7] = sum as u32;
+            t[8] = t[9] + (sum >> 32) as u32;
+        }
+
+        let mut o = [0; 8];
+        o.copy_from_slice(&t[..8]);
+        self.reduce_once(&o, t[8])
+    }
+
+    fn to_mont(&self, a: &Fe) -> Fe {
+        self.mul(a, &self.r2)
+    }
+
+    fn out_of_mont(&self, a: &Fe) -> Fe {
+        self.mul(a, &ONE)
+    }
+
+    /// Calculate `a^(m - 2)`, the inverse of `a` for a prime `m`.
+    fn invert(&self, a: &Fe) -> Fe {
+        let (exponent, _) = sub_borrow(&self.m, &[2, 0, 0, 0, 0, 0, 0, 0]);
+        let mut o = self.to_mont(&ONE);
+        for i in (0..256).rev() {
+            o = self.mul(&o, &o);
+            if (exponent[i / 32] >> (i % 32)) & 1 == 1 {
+                o = self.mul(&o, a);
+            }
+        }
+        o
+    }
+}
+
+fn add(a: &Fe, b: &Fe) -> Fe {
+    FIELD.add(a, b)
+}
+
+fn sub(a: &Fe, b: &Fe) -> Fe {
+    FIELD.sub(a, b)
+}
+
+fn mul(a: &Fe, b: &Fe) -> Fe {
+    FIELD.mul(a, b)
+}
+
+fn to_mont(a: &Fe) -> Fe {
+    FIELD.to_mont(a)
+}
+
+fn from_mont(a: &Fe) -> Fe {
+    FIELD.out_of_mont(a)
+}
+
+fn invert(a: &Fe) -> Fe {
+    FIELD.invert(a)
+}
+
+fn is_zero(a: &Fe) -> bool {
+    a.iter().fold(0, |acc, limb| acc | limb) == 0
+}
+
+/// Check if `a` is less than `b`.
+fn less_than(a: &Fe, b: &Fe) -> bool {
+    sub_borrow(a, b).1 == 1
+}
+
+/// A point in projective coordinates, in Montgomery form.
+#[derive(Clone, Copy)]
+struct Point {
+    x: Fe,
+    y: Fe,
+    z: Fe,
+}
+
+impl Point {
+    fn identity() -> Self {
+        Point {
+            x: [0; 8],
+            y: to_mont(&ONE),
+            z: [0; 8],
+        }
+    }
+
+    fn from_affine(x: &Fe, y: &Fe) -> Self {
+        Point {
+            x: to_mont(x),
+            y: to_mont(y),
+            z: to_mont(&ONE),
+        }
+    }
+
+    /// Return the affine coordinates, or `None` for the point at infinity.
+    fn to_affine(self) -> Option<(Fe, Fe)> {
+        if is_zero(&self.z) {
+            return None;
+        }
+
+        let z_inv = invert(&self.z);
+        Some((
+            from_mont(&mul(&self.x, &z_inv)),
+            from_mont(&mul(&self.y, &z_inv)),
+        ))
+    }
+
+    /// Check if the affine point `(x, y)` is on the curve.
+    fn on_curve(x: &Fe, y: &Fe) -> bool {
+        if !less_than(x, &P) || !less_than(y, &P) {
+            return false;
+        }
+
+        // y^2 = x^3 - 3x + b
+        let x = to_mont(x);
+        let y = to_mont(y);
+        let x3 = mul(&mul(&x, &x), &x);
+        let three_x = add(&add(&x, &x), &x);
+        let rhs = add(&sub(&x3, &three_x), &to_mont(&B));
+        mul(&y, &y) == rhs
+    }
+
+    /// Complete point addition for `a = -3`, algorithm 4 of Renes, Costello
+    /// and Batina. This also works for doubling.
+    fn add(&self, other: &Point) -> Point {
+        let b = to_mont(&B);
+        let (x1, y1, z1) = (&self.x, &self.y, &self.z);
+        let (x2, y2, z2) = (&other.x, &other.y, &other.z);
+
+        let mut t0 = mul(x1, x2);
+        let mut t1 = mul(y1, y2);
+        let mut t2 = mul(z1, z2);
+        let mut t3 = add(x1, y1);
+        let mut t4 = add(x2, y2);
+        t3 = mul(&t3, &t4);
+        t4 = add(&t0, &t1);
+        t3 = sub(&t3, &t4);
+        t4 = add(y1, z1);
+        let mut x3 = add(y2, z2);
+        t4 = mul(&t4, &x3);
+        x3 = add(&t1, &t2);
+        t4 = sub(&t4, &x3);
+        x3 = add(x1, z1);
+        let mut y3 = add(x2, z2);
+        x3 = mul(&x3, &y3);
+        y3 = add(&t0, &t2);
+        y3 = sub(&x3, &y3);
+        let mut z3 = mul(&b, &t2);
+        x3 = sub(&y3, &z3);
+        z3 = add(&x3, &x3);
+        x3 = add(&x3, &z3);
+        z3 = sub(&t1, &x3);
+        x3 = add(&t1, &x3);
+        y3 = mul(&b, &y3);
+        t1 = add(&t2, &t2);
+        t2 = add(&t1, &t2);
+        y3 = sub(&y3, &t2);
+        y3 = sub(&y3, &t0);
+        t1 = add(&y3, &y3);
+        y3 = add(&t1, &y3);
+        t1 = add(&t0, &t0);
+        t0 = add(&t1, &t0);
+        t0 = sub(&t0, &t2);
+        t1 = mul(&t4, &y3);
+        t2 = mul(&t0, &y3);
+        y3 = mul(&x3, &z3);
+        y3 = add(&y3, &t2);
+        x3 = mul(&t3, &x3);
+        x3 = sub(&x3, &t1);
+        z3 = mul(&t4, &z3);
+        t1 = mul(&t3, &t0);
+        z3 = add(&z3, &t1);
+
+        Point {
+            x: x3,
+            y: y3,
+            z: z3,
+        }
+    }
+
+    /// Swap `a` and `b` if `bit` is 1, in constant time.
+    fn swap(a: &mut Point, b: &mut Point, bit: u32) {
+        let mask = bit.wrapping_neg();
+        let (ax, ay, az) = (a.x, a.y, a.z);
+        a.x = select(&a.x, &b.x, mask);
+        a.y = select(&a.y, &b.y, mask);
+        a.z = select(&a.z, &b.z, mask);
+        b.x = select(&b.x, &ax, mask);
+        b.y = select(&b.y, &ay, mask);
+        b.z = select(&b.z, &az, mask);
+    }
+
+    /// Multiply the point by the big-endian `scalar`.
+    fn mul(&self, scalar: &[u8; 32]) -> Point {
+        let mut r0 = Point::identity();
+        let mut r1 = *self;
+        for i in 0..256 {
+            let bit = ((scalar[i / 8] >> (7 - i % 8)) & 1) as u32;
+            Point::swap(&mut r0, &mut r1, bit);
+            r1 = r0.add(&r1);
+            r0 = r0.add(&r0);
+            Point::swap(&mut r0, &mut r1, bit);
+        }
+        r0
+    }
+}
+
+/// Check if the big-endian `scalar` is in `[1, n - 1]`, so it can be used as
+/// a private key.
+pub(super) fn valid_scalar(scalar: &[u8; 32]) -> bool {
+    let d = from_bytes(scalar);
+    !is_zero(&d) && less_than(&d, &N)
+}
+
+/// Calculate the ECDSA signature `r | s` of `hash` with `private_key` and the
+/// per-signature secret `nonce`, all big-endian.
+///
+/// Returns `false` if `nonce` can't be used, in which case the signature must be
+/// retried with a new nonce.
+pub(super) fn ecdsa_sign(
+    private_key: &[u8; 32],
+    hash: &[u8; 32],
+    nonce: &[u8; 32],
+    signature: &mut [u8; 64],
+) -> bool {
+    if !valid_scalar(nonce) {
+        return false;
+    }
+
+    let (rx, _) = match Point::from_affine(&GX, &GY).mul(nonce).to_affine() {
+        Some(point) => point,
+        None => return false,
+    };
+    // rx < p < 2n, and the hash < 2^256 < 2n.
+    let r = ORDER.reduce_once(&rx, 0);
+    let digest = ORDER.reduce_once(&from_bytes(hash), 0);
+    if is_zero(&r) {
+        return false;
+    }
+
+    // s = k^-1 (digest + r key) mod n
+    let k = ORDER.to_mont(&from_bytes(nonce));
+    let key = ORDER.to_mont(&from_bytes(private_key));
+    let rd = ORDER.mul(&ORDER.to_mont(&r), &key);
+    let sum = ORDER.add(&ORDER.to_mont(&digest), &rd);
+    let s = ORDER.out_of_mont(&ORDER.mul(&ORDER.invert(&k), &sum));
+    if is_zero(&s) {
+        return false;
+    }
+
+    to_bytes(&r, &mut signature[..32]);
+    to_bytes(&s, &mut signature[32..]);
+    true
+}
+
+/// Calculate the public key for `private_key`.
+pub(super) fn public_key(private_key: &[u8; 32], public_key: &mut [u8; 64]) {
+    let g = Point::from_affine(&GX, &GY);
+    // The private key is in [1, n - 1], so this can't be the point at
+    // infinity.
+    if let Some((x, y)) = g.mul(private_key).to_affine() {
+        to_bytes(&x, &mut public_key[..32]);
+        to_bytes(&y, &mut public_key[32..]);
+    }
+}
+
+/// The P-256 curve arithmetic.
+pub struct P256;
+
+impl Curve<P256_PUBLIC_KEY_LEN, P256_SHARED_SECRET_LEN> for P256 {
+    fn private_key(random: &[u8; PRIVATE_KEY_LEN]) -> Option<[u8; PRIVATE_KEY_LEN]> {
+        // Use rejection sampling to get a key in [1, n - 1].
+        if !valid_scalar(random) {
+            return None;
+        }
+        Some(*random)
+    }
+
+    fn public_key(private_key: &[u8; PRIVATE_KEY_LEN], public_key: &mut [u8; P256_PUBLIC_KEY_LEN]) {
+        self::public_key(private_key, public_key)
+    }
+
+    fn shared_secret(
+        private_key: &[u8; PRIVATE_KEY_LEN],
+        peer_public_key: &[u8; P256_PUBLIC_KEY_LEN],
+        shared_secret: &mut [u8; P256_SHARED_SECRET_LEN],
+    ) -> Result<(), ErrorCode> {
+        let x = from_bytes(&peer_public_key[..32]);
+        let y = from_bytes(&peer_public_key[32..]);
+        if !Point::on_curve(&x, &y) {
+            return Err(ErrorCode::INVAL);
+        }
+
+        let (x, _) = Point::from_affine(&x, &y)
+            .mul(private_key)
+            .to_affine()
+            .ok_or(ErrorCode::INVAL)?;
+        to_bytes(&x, shared_secret);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub(super) mod tests {
+    use super::*;
+
+    // RFC 6979 A.2.5, P-256 with SHA-256 and the message "sample".
+    pub(in crate::public_key_crypto) const RFC6979_PRIVATE_KEY: [u8; 32] = [
+        0xc9, 0xaf, 0xa9, 0xd8, 0x45, 0xba, 0x75, 0x16, 0x6b, 0x5c, 0x21, 0x57, 0x67, 0xb1, 0xd6,
+        0x93, 0x4e, 0x50, 0xc3, 0xdb, 0x36, 0xe8, 0x9b, 0x12, 0x7b, 0x8a, 0x62, 0x2b, 0x12, 0x0f,
+        0x67, 0x21,
+    ];
+    pub(in crate::public_key_crypto) const RFC6979_PUBLIC_KEY: [u8; 64] = [
+        0x60, 0xfe, 0xd4, 0xba, 0x25, 0x5a, 0x9d, 0x31, 0xc9, 0x61, 0xeb, 0x74, 0xc6, 0x35, 0x6d,
+        0x68, 0xc0, 0x49, 0xb8, 0x92, 0x3b, 0x61, 0xfa, 0x6c, 0xe6, 0x69, 0x62, 0x2e, 0x60, 0xf2,
+        0x9f, 0xb6, 0x79, 0x03, 0xfe, 0x10, 0x08, 0xb8, 0xbc, 0x99, 0xa4, 0x1a, 0xe9, 0xe9, 0x56,
+        0x28, 0xbc, 0x64, 0xf2, 0xf1, 0xb2, 0x0c, 0x2d, 0x7e, 0x9f, 0x51, 0x77, 0xa3, 0xc2, 0x94,
+        0xd4, 0x46, 0x22, 0x99,
+    ];
+    /// SHA-256("sample").
+    pub(in crate::public_key_crypto) const RFC6979_HASH: [u8; 32] = [
+        0xaf, 0x2b, 0xdb, 0xe1, 0xaa, 0x9b, 0x6e, 0xc1, 0xe2, 0xad, 0xe1, 0xd6, 0x94, 0xf4, 0x1f,
+        0xc7, 0x1a, 0x83, 0x1d, 0x02, 0x68, 0xe9, 0x89, 0x15, 0x62, 0x11, 0x3d, 0x8a, 0x62, 0xad,
+        0xd1, 0xbf,
+    ];
+    pub(in crate::public_key_crypto) const RFC6979_K: [u8; 32] = [
+        0xa6, 0xe3, 0xc5, 0x7d, 0xd0, 0x1a, 0xbe, 0x90, 0x08, 0x65, 0x38, 0x39, 0x83, 0x55, 0xdd,
+        0x4c, 0x3b, 0x17, 0xaa, 0x87, 0x33, 0x82, 0xb0, 0xf2, 0x4d, 0x61, 0x29, 0x49, 0x3d, 0x8a,
+        0xad, 0x60,
+    ];
+    pub(in crate::public_key_crypto) const RFC6979_SIGNATURE: [u8; 64] = [
+        0xef, 0xd4, 0x8b, 0x2a, 0xac, 0xb6, 0xa8, 0xfd, 0x11, 0x40, 0xdd, 0x9c, 0xd4, 0x5e, 0x81,
+        0xd6, 0x9d, 0x2c, 0x87, 0x7b, 0x56, 0xaa, 0xf9, 0x91, 0xc3, 0x4d, 0x0e, 0xa8, 0x4e, 0xaf,
+        0x37, 0x16, 0xf7, 0xcb, 0x1c, 0x94, 0x2d, 0x65, 0x7c, 0x41, 0xd4, 0x36, 0xc7, 0xa1, 0xb6,
+        0xe2, 0x9f, 0x65, 0xf3, 0xe9, 0x00, 0xdb, 0xb9, 0xaf, 0xf4, 0x06, 0x4d, 0xc4, 0xab, 0x2f,
+        0x84, 0x3a, 0xcd, 0xa8,
+    ];
+
+    /// Verify an ECDSA signature `r | s` of `hash`. The module only signs, so
+    /// this is just for checking signatures in the tests.
+    pub(in crate::public_key_crypto) fn ecdsa_verify(
+        public_key: &[u8; 64],
+        hash: &[u8; 32],
+        signature: &[u8; 64],
+    ) -> bool {
+        let r = from_bytes(&signature[..32]);
+        let s = from_bytes(&signature[32..]);
+        if is_zero(&r) || !less_than(&r, &N) || is_zero(&s) || !less_than(&s, &N) {
+            return false;
+        }
+        let px = from_bytes(&public_key[..32]);
+        let py = from_bytes(&public_key[32..]);
+        if !Point::on_curve(&px, &py) {
+            return false;
+        }
+
+        // u1 = digest s^-1 mod n, u2 = r s^-1 mod n
+        let digest = ORDER.reduce_once(&from_bytes(hash), 0);
+        let s_inv = ORDER.invert(&ORDER.to_mont(&s));
+        let (mut u1, mut u2) = ([0; 32], [0; 32]);
+        to_bytes(
+            &ORDER.out_of_mont(&ORDER.mul(&ORDER.to_mont(&digest), &s_inv)),
+            &mut u1,
+        );
+        to_bytes(
+            &ORDER.out_of_mont(&ORDER.mul(&ORDER.to_mont(&r), &s_inv)),
+            &mut u2,
+        );
+
+        let point = Point::from_affine(&GX, &GY)
+            .mul(&u1)
+            .add(&Point::from_affine(&px, &py).mul(&u2));
+        match point.to_affine() {
+            Some((rx, _)) => ORDER.reduce_once(&rx, 0) == r,
+            None => false,
+        }
+    }
+
+    /// Check a NIST CAVS ECC CDH primitive test vector.
+    fn check_vector(
+        peer_public_key: &[u8; P256_PUBLIC_KEY_LEN],
+        private_key: &[u8; PRIVATE_KEY_LEN],
+        public_key: Option<&[u8; P256_PUBLIC_KEY_LEN]>,
+        expected: &[u8; P256_SHARED_SECRET_LEN],
+    ) {
+        assert_eq!(P256::private_key(private_key), Some(*private_key));
+
+        if let Some(expected_public_key) = public_key {
+            let mut public_key = [0; P256_PUBLIC_KEY_LEN];
+            P256::public_key(private_key, &mut public_key);
+            assert_eq!(&public_key, expected_public_key);
+        }
+
+        let mut secret = [0; P256_SHARED_SECRET_LEN];
+        assert_eq!(
+            P256::shared_secret(private_key, peer_public_key, &mut secret),
+            Ok(())
+        );
+        assert_eq!(&secret, expected);
+    }
+
+    #[test]
+    fn cavs_count_0() {
+        check_vector(
+            &[
+                0x70, 0x0c, 0x48, 0xf7, 0x7f, 0x56, 0x58, 0x4c, 0x5c, 0xc6, 0x32, 0xca, 0x65, 0x64,
+                0x0d, 0xb9, 0x1b, 0x6b, 0xac, 0xce, 0x3a, 0x4d, 0xf6, 0xb4, 0x2c, 0xe7, 0xcc, 0x83,
+                0x88, 0x33, 0xd2, 0x87, 0xdb, 0x71, 0xe5, 0x09, 0xe3, 0xfd, 0x9b, 0x06, 0x0d, 0xdb,
+                0x20, 0xba, 0x5c, 0x51, 0xdc, 0xc5, 0x94, 0x8d, 0x46, 0xfb, 0xf6, 0x40, 0xdf, 0xe0,
+                0x44, 0x17, 0x82, 0xca, 0xb8, 0x5f, 0xa4, 0xac,
+            ],
+            &[
+                0x7d, 0x7d, 0xc5, 0xf7, 0x1e, 0xb2, 0x9d, 0xda, 0xf8, 0x0d, 0x62, 0x14, 0x63, 0x2e,
+                0xea, 0xe0, 0x3d, 0x90, 0x58, 0xaf, 0x1f, 0xb6, 0xd2, 0x2e, 0xd8, 0x0b, 0xad, 0xb6,
+                0x2b, 0xc1, 0xa5, 0x34,
+            ],
+            Some(&[
+                0xea, 0xd2, 0x18, 0x59, 0x01, 0x19, 0xe8, 0x87, 0x6b, 0x29, 0x14, 0x6f, 0xf8, 0x9c,
+                0xa6, 0x17, 0x70, 0xc4, 0xed, 0xbb, 0xf9, 0x7d, 0x38, 0xce, 0x38, 0x5e, 0xd2, 0x81,
+                0xd8, 0xa6, 0xb2, 0x30, 0x28, 0xaf, 0x61, 0x28, 0x1f, 0xd3, 0x5e, 0x2f, 0xa7, 0x00,
+                0x25, 0x23, 0xac, 0xc8, 0x5a, 0x42, 0x9c, 0xb0, 0x6e, 0xe6, 0x64, 0x83, 0x25, 0x38,
+                0x9f, 0x59, 0xed, 0xfc, 0xe1, 0x40, 0x51, 0x41,
+            ]),
+            &[
+                0x46, 0xfc, 0x62, 0x10, 0x64, 0x20, 0xff, 0x01, 0x2e, 0x54, 0xa4, 0x34, 0xfb, 0xdd,
+                0x2d, 0x25, 0xcc, 0xc5, 0x85, 0x20, 0x60, 0x56, 0x1e, 0x68, 0x04, 0x0d, 0xd7, 0x77,
+                0x89, 0x97, 0xbd, 0x7b,
+            ],
+        );
+    }
+
+    #[test]
+    fn cavs_count_1() {
+        check_vector(
+            &[
+                0x80, 0x9f, 0x04, 0x28, 0x9c, 0x64, 0x34, 0x8c, 0x01, 0x51, 0x5e, 0xb0, 0x3d, 0x5c,
+                0xe7, 0xac, 0x1a, 0x8c, 0xb9, 0x49, 0x8f, 0x5c, 0xaa, 0x50, 0x19, 0x7e, 0x58, 0xd4,
+                0x3a, 0x86, 0xa7, 0xae, 0xb2, 0x9d, 0x84, 0xe8, 0x11, 0x19, 0x7f, 0x25, 0xeb, 0xa8,
+                0xf5, 0x19, 0x40, 0x92, 0xcb, 0x6f, 0xf4, 0x40, 0xe2, 0x6d, 0x44, 0x21, 0x01, 0x13,
+                0x72, 0x46, 0x1f, 0x57, 0x92, 0x71, 0xcd, 0xa3,
+            ],
+            &[
+                0x38, 0xf6, 0x5d, 0x6d, 0xce, 0x47, 0x67, 0x60, 0x44, 0xd5, 0x8c, 0xe5, 0x13, 0x95,
+                0x82, 0xd5, 0x68, 0xf6, 0x4b, 0xb1, 0x60, 0x98, 0xd1, 0x79, 0xdb, 0xab, 0x07, 0x74,
+                0x1d, 0xd5, 0xca, 0xf5,
+            ],
+            None,
+            &[
+                0x05, 0x7d, 0x63, 0x60, 0x96, 0xcb, 0x80, 0xb6, 0x7a, 0x8c, 0x03, 0x8c, 0x89, 0x0e,
+                0x88, 0x7d, 0x1a, 0xdf, 0xa4, 0x19, 0x5e, 0x9b, 0x3c, 0xe2, 0x41, 0xc8, 0xa7, 0x78,
+                0xc5, 0x9c, 0xda, 0x67,
+            ],
+        );
+    }
+
+    #[test]
+    fn invalid_points_are_rejected() {
+        let private_key = [
+            0x7d, 0x7d, 0xc5, 0xf7, 0x1e, 0xb2, 0x9d, 0xda, 0xf8, 0x0d, 0x62, 0x14, 0x63, 0x2e,
+            0xea, 0xe0, 0x3d, 0x90, 0x58, 0xaf, 0x1f, 0xb6, 0xd2, 0x2e, 0xd8, 0x0b, 0xad, 0xb6,
+            0x2b, 0xc1, 0xa5, 0x34,
+        ];
+        let mut peer_public_key = [
+            0x70, 0x0c, 0x48, 0xf7, 0x7f, 0x56, 0x58, 0x4c, 0x5c, 0xc6, 0x32, 0xca, 0x65, 0x64,
+            0x0d, 0xb9, 0x1b, 0x6b, 0xac, 0xce, 0x3a, 0x4d, 0xf6, 0xb4, 0x2c, 0xe7, 0xcc, 0x83,
+            0x88, 0x33, 0xd2, 0x87, 0xdb, 0x71, 0xe5, 0x09, 0xe3, 0xfd, 0x9b, 0x06, 0x0d, 0xdb,
+            0x20, 0xba, 0x5c, 0x51, 0xdc, 0xc5, 0x94, 0x8d, 0x46, 0xfb, 0xf6, 0x40, 0xdf, 0xe0,
+            0x44, 0x17, 0x82, 0xca, 0xb8, 0x5f, 0xa4, 0xac,
+        ];
+        peer_public_key[P256_PUBLIC_KEY_LEN - 1] ^= 0x01;
+
+        let mut secret = [0; P256_SHARED_SECRET_LEN];
+        assert_eq!(
+            P256::shared_secret(&private_key, &peer_public_key, &mut secret),
+            Err(ErrorCode::INVAL)
+        );
+        assert_eq!(
+            P256::shared_secret(&private_key, &[0; P256_PUBLIC_KEY_LEN], &mut secret),
+            Err(ErrorCode::INVAL)
+        );
+    }
+
+    #[test]
+    fn private_key_range() {
+        assert_eq!(P256::private_key(&[0; PRIVATE_KEY_LEN]), None);
+        assert_eq!(P256::private_key(&[0xff; PRIVATE_KEY_LEN]), None);
+    }
+
+    #[test]
+    fn ecdsa_rfc6979_vector() {
+        let mut public_key = [0; P256_PUBLIC_KEY_LEN];
+        self::public_key(&RFC6979_PRIVATE_KEY, &mut public_key);
+        assert_eq!(public_key, RFC6979_PUBLIC_KEY);
+
+        let mut signature = [0; 64];
+        assert!(ecdsa_sign(
+            &RFC6979_PRIVATE_KEY,
+            &RFC6979_HASH,
+            &RFC6979_K,
+            &mut signature
+        ));
+        assert_eq!(signature, RFC6979_SIGNATURE);
+        assert!(ecdsa_verify(&public_key, &RFC6979_HASH, &signature));
+    }
+
+    #[test]
+    fn ecdsa_sign_then_verify() {
+        let private_key = [0x5a; 32];
+        let mut public_key = [0; P256_PUBLIC_KEY_LEN];
+        self::public_key(&private_key, &mut public_key);
+
+        let hash = [0xff; 32];
+        let mut signature = [0; 64];
+        assert!(ecdsa_sign(&private_key, &hash, &[0x17; 32], &mut signature));
+        assert!(ecdsa_verify(&public_key, &hash, &signature));
+
+        // A different nonce gives a different, but also valid, signature.
+        let mut other = [0; 64];
+        assert!(ecdsa_sign(&private_key, &hash, &[0x71; 32], &mut other));
+        assert_ne!(signature, other);
+        assert!(ecdsa_verify(&public_key, &hash, &other));
+
+        let mut tampered = signature;
+        tampered[63] ^= 0x01;
+        assert!(!ecdsa_verify(&public_key, &hash, &tampered));
+        let mut tampered_hash = hash;
+        tampered_hash[0] ^= 0x01;
+        assert!(!ecdsa_verify(&public_key, &tampered_hash, &signature));
+    }
+
+    #[test]
+    fn ecdsa_nonce_range() {
+        // n, the order of the curve.
+        let n = [
+            0xff, 0xff, 0xff, 0xff, 0x00, 0x00, 0x00, 0x00, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+            0xff, 0xff, 0xbc, 0xe6, 0xfa, 0xad, 0xa7, 0x17, 0x9e, 0x84, 0xf3, 0xb9, 0xca, 0xc2,
+            0xfc, 0x63, 0x25, 0x51,
+        ];
+        let mut n_minus_one = n;
+        n_minus_one[31] -= 1;
+
+        let mut signature = [0; 64];
+        for nonce in [[0; 32], n, [0xff; 32]] {
+            assert!(!ecdsa_sign(
+                &RFC6979_PRIVATE_KEY,
+                &RFC6979_HASH,
+                &nonce,
+                &mut signature
+            ));
+            assert_eq!(signature, [0; 64]);
+        }
+        assert!(ecdsa_sign(
+            &RFC6979_PRIVATE_KEY,
+            &RFC6979_HASH,
+            &n_minus_one,
+            &mut signature
+        ));
+        assert!(ecdsa_verify(&RFC6979_PUBLIC_KEY, &RFC6979_HASH, &signature));
+    }
+}
@@ -0,0 +1,370 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! X25519 key agreement, as specified in RFC 7748.
+//!
+//! The field arithmetic follows TweetNaCl: elements of GF(2^255 - 19) are
+//! stored as sixteen 16-bit limbs in `i64`s, and the scalar multiplication is
+//! a Montgomery ladder using constant-time swaps. The field arithmetic is
+//! shared with the Ed25519 verifier.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! let x25519 = components::ecdh::X25519SoftwareComponent::new()
+//!     .finalize(components::x25519_software_component_static!());
+//! ```
+
+use kernel::ErrorCode;
+
+use super::ecdh_software::{Curve, EcdhSoftware, PRIVATE_KEY_LEN};
+
+/// The length of an X25519 public key.
+pub const X25519_PUBLIC_KEY_LEN: usize = 32;
+/// The length of an X25519 shared secret.
+pub const X25519_SHARED_SECRET_LEN: usize = 32;
+
+/// ECDH using X25519.
+pub type X25519Software<'a> =
+    EcdhSoftware<'a, X25519, X25519_PUBLIC_KEY_LEN, X25519_SHARED_SECRET_LEN>;
+
+pub(super) type Fe = [i64; 16];
+
+/// (A - 2) / 4 for curve25519.
+const A24: Fe = [0xdb41, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+
+const BASE_POINT: [u8; 32] = [
+    9, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+];
+
+fn carry(o: &mut Fe) {
+    for i in 0..16 {
+        o[i] += 1 << 16;
+        let c = o[i] >> 16;
+        if i < 15 {
+            o[i + 1] += c - 1;
+        } else {
+            o[0] += 38 * (c - 1);
+        }
+        o[i] -= c << 16;
+    }
+}
+
+/// Swap `p` and `q` if `b` is 1, in constant time.
+pub(super) fn swap(p: &mut Fe, q: &mut Fe, b: i64) {
+    let mask = !(b - 1);
+    for i in 0..16 {
+        let t = mask & (p[i] ^ q[i]);
+        p[i] ^= t;
+        q[i] ^= t;
+    }
+}
+
+pub(super) fn pack(o: &mut [u8; 32], n: &Fe) {
+    let mut t = *n;
+    carry(&mut t);
+    carry(&mut t);
+    carry(&mut t);
+
+    // Subtract p, twice, keeping the result if it didn't go negative.
+    for _ in 0..2 {
+        let mut minus_p = [0; 16];
+        minus_p[0] = t[0] - 0xffed;
+        for i in 1..15 {
+            minus_p[i] = t[i] - 0xffff - ((minus_p[i - 1] >> 16) & 1);
+            minus_p[i - 1] &= 0xffff;
+        }
+        minus_p[15] = t[15] - 0x7fff - ((minus_p[14] >> 16) & 1);
+        let borrow = (minus_p[15] >> 16) & 1;
+        minus_p[14] &= 0xffff;
+        swap(&mut t, &mut minus_p, 1 - borrow);
+    }
+
+    for i in 0..16 {
+        o[2 * i] = t[i] as u8;
+        o[2 * i + 1] = (t[i] >> 8) as u8;
+    }
+}
+
+pub(super) fn unpack(n: &[u8; 32]) -> Fe {
+    let mut o = [0; 16];
+    for i in 0..16 {
+        o[i] = n[2 * i] as i64 + ((n[2 * i + 1] as i64) << 8);
+    }
+    o[15] &= 0x7fff;
+    o
+}
+
+pub(super) fn add(a: &Fe, b: &Fe) -> Fe {
+    let mut o = [0; 16];
+    for i in 0..16 {
+        o[i] = a[i] + b[i];
+    }
+    o
+}
+
+pub(super) fn sub(a: &Fe, b: &Fe) -> Fe {
+    let mut o = [0; 16];
+    for i in 0..16 {
+        o[i] = a[i] - b[i];
+    }
+    o
+}
+
+pub(super) fn mul(a: &Fe, b: &Fe) -> Fe {
+    let mut t = [0; 31];
+    for i in 0..16 {
+        for j in 0..16 {
+            t[i + j] += a[i] * b[j];
+        }
+    }
+    for i in 0..15 {
+        t[i] += 38 * t[i + 16];
+    }
+
+    let mut o = [0; 16];
+    o.copy_from_slice(&t[..16]);
+    carry(&mut o);
+    carry(&mut o);
+    o
+}
+
+pub(super) fn square(a: &Fe) -> Fe {
+    mul(a, a)
+}
+
+/// Calculate `i^(p - 2)`, the inverse of `i`.
+pub(super) fn invert(i: &Fe) -> Fe {
+    let mut c = *i;
+    for a in (0..=253).rev() {
+        c = square(&c);
+        if a != 2 && a != 4 {
+            c = mul(&c, i);
+        }
+    }
+    c
+}
+
+/// Calculate the X25519 function of `scalar` and the point `u`.
+// The ladder keeps TweetNaCl's variable names so it can be compared against it.
+#[allow(clippy::many_single_char_names)]
+fn scalar_mult(scalar: &[u8; 32], u: &[u8; 32]) -> [u8; 32] {
+    let mut z = *scalar;
+    z[31] = (z[31] & 127) | 64;
+    z[0] &= 248;
+
+    let x = unpack(u);
+    let mut a = [0; 16];
+    let mut b = x;
+    let mut c = [0; 16];
+    let mut d = [0; 16];
+    a[0] = 1;
+    d[0] = 1;
+
+    for i in (0..=254).rev() {
+        let r = ((z[i >> 3] >> (i & 7)) & 1) as i64;
+        swap(&mut a, &mut b, r);
+        swap(&mut c, &mut d, r);
+
+        let e = add(&a, &c);
+        a = sub(&a, &c);
+        c = add(&b, &d);
+        b = sub(&b, &d);
+        d = square(&e);
+        let f = square(&a);
+        a = mul(&c, &a);
+        c = mul(&b, &e);
+        let e = add(&a, &c);
+        a = sub(&a, &c);
+        b = square(&a);
+        c = sub(&d, &f);
+        a = mul(&c, &A24);
+        a = add(&a, &d);
+        c = mul(&c, &a);
+        a = mul(&d, &f);
+        d = mul(&b, &x);
+        b = square(&e);
+
+        swap(&mut a, &mut b, r);
+        swap(&mut c, &mut d, r);
+    }
+
+    let c = invert(&c);
+    let a = mul(&a, &c);
+    let mut out = [0; 32];
+    pack(&mut out, &a);
+    out
+}
+
+/// The X25519 curve arithmetic.
+pub struct X25519;
+
+impl Curve<X25519_PUBLIC_KEY_LEN, X25519_SHARED_SECRET_LEN> for X25519 {
+    fn private_key(random: &[u8; PRIVATE_KEY_LEN]) -> Option<[u8; PRIVATE_KEY_LEN]> {
+        // Every 32 byte string is a valid private key, the clamping is done in
+        // `scalar_mult()`.
+        Some(*random)
+    }
+
+    fn public_key(
+        private_key: &[u8; PRIVATE_KEY_LEN],
+        public_key: &mut [u8; X25519_PUBLIC_KEY_LEN],
+    ) {
+        *public_key = scalar_mult(private_key, &BASE_POINT);
+    }
+
+    fn shared_secret(
+        private_key: &[u8; PRIVATE_KEY_LEN],
+        peer_public_key: &[u8; X25519_PUBLIC_KEY_LEN],
+        shared_secret: &mut [u8; X25519_SHARED_SECRET_LEN],
+    ) -> Result<(), ErrorCode> {
+        *shared_secret = scalar_mult(private_key, peer_public_key);
+
+        // A low order peer public key results in an all zero shared secret,
+        // which RFC 7748 says to reject.
+        if shared_secret.iter().fold(0, |acc, b| acc | b) == 0 {
+            return Err(ErrorCode::INVAL);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn shared_secret(scalar: &[u8; 32], u: &[u8; 32]) -> Result<[u8; 32], ErrorCode> {
+        let mut secret = [0; X25519_SHARED_SECRET_LEN];
+        X25519::shared_secret(scalar, u, &mut secret).map(|()| secret)
+    }
+
+    #[test]
+    fn rfc7748_vectors() {
+        // RFC 7748 section 5.2.
+        assert_eq!(
+            shared_secret(
+                &[
+                    0xa5, 0x46, 0xe3, 0x6b, 0xf0, 0x52, 0x7c, 0x9d, 0x3b, 0x16, 0x15, 0x4b, 0x82,
+                    0x46, 0x5e, 0xdd, 0x62, 0x14, 0x4c, 0x0a, 0xc1, 0xfc, 0x5a, 0x18, 0x50, 0x6a,
+                    0x22, 0x44, 0xba, 0x44, 0x9a, 0xc4
+                ],
+                &[
+                    0xe6, 0xdb, 0x68, 0x67, 0x58, 0x30, 0x30, 0xdb, 0x35, 0x94, 0xc1, 0xa4, 0x24,
+                    0xb1, 0x5f, 0x7c, 0x72, 0x66, 0x24, 0xec, 0x26, 0xb3, 0x35, 0x3b, 0x10, 0xa9,
+                    0x03, 0xa6, 0xd0, 0xab, 0x1c, 0x4c
+                ],
+            ),
+            Ok([
+                0xc3, 0xda, 0x55, 0x37, 0x9d, 0xe9, 0xc6, 0x90, 0x8e, 0x94, 0xea, 0x4d, 0xf2, 0x8d,
+                0x08, 0x4f, 0x32, 0xec, 0xcf, 0x03, 0x49, 0x1c, 0x71, 0xf7, 0x54, 0xb4, 0x07, 0x55,
+                0x77, 0xa2, 0x85, 0x52
+            ])
+        );
+        assert_eq!(
+            shared_secret(
+                &[
+                    0x4b, 0x66, 0xe9, 0xd4, 0xd1, 0xb4, 0x67, 0x3c, 0x5a, 0xd2, 0x26, 0x91, 0x95,
+                    0x7d, 0x6a, 0xf5, 0xc1, 0x1b, 0x64, 0x21, 0xe0, 0xea, 0x01, 0xd4, 0x2c, 0xa4,
+                    0x16, 0x9e, 0x79, 0x18, 0xba, 0x0d
+                ],
+                &[
+                    0xe5, 0x21, 0x0f, 0x12, 0x78, 0x68, 0x11, 0xd3, 0xf4, 0xb7, 0x95, 0x9d, 0x05,
+                    0x38, 0xae, 0x2c, 0x31, 0xdb, 0xe7, 0x10, 0x6f, 0xc0, 0x3c, 0x3e, 0xfc, 0x4c,
+                    0xd5, 0x49, 0xc7, 0x15, 0xa4, 0x93
+                ],
+            ),
+            Ok([
+                0x95, 0xcb, 0xde, 0x94, 0x76, 0xe8, 0x90, 0x7d, 0x7a, 0xad, 0xe4, 0x5c, 0xb4, 0xb8,
+                0x73, 0xf8, 0x8b, 0x59, 0x5a, 0x68, 0x79, 0x9f, 0xa1, 0x52, 0xe6, 0xf8, 0xf7, 0x64,
+                0x7a, 0xac, 0x79, 0x57
+            ])
+        );
+    }
+
+    #[test]
+    fn rfc7748_iterated() {
+        // RFC 7748 section 5.2, after 1 and 1,000 iterations.
+        let mut k = BASE_POINT;
+        let mut u = BASE_POINT;
+        for i in 1..=1000 {
+            let result = scalar_mult(&k, &u);
+            u = k;
+            k = result;
+
+            if i == 1 {
+                assert_eq!(
+                    k,
+                    [
+                        0x42, 0x2c, 0x8e, 0x7a, 0x62, 0x27, 0xd7, 0xbc, 0xa1, 0x35, 0x0b, 0x3e,
+                        0x2b, 0xb7, 0x27, 0x9f, 0x78, 0x97, 0xb8, 0x7b, 0xb6, 0x85, 0x4b, 0x78,
+                        0x3c, 0x60, 0xe8, 0x03, 0x11, 0xae, 0x30, 0x79
+                    ]
+                );
+            }
+        }
+        assert_eq!(
+            k,
+            [
+                0x68, 0x4c, 0xf5, 0x9b, 0xa8, 0x33, 0x09, 0x55, 0x28, 0x00, 0xef, 0x56, 0x6f, 0x2f,
+                0x4d, 0x3c, 0x1c, 0x38, 0x87, 0xc4, 0x93, 0x60, 0xe3, 0x87, 0x5f, 0x2e, 0xb9, 0x4d,
+                0x99, 0x53, 0x2c, 0x51
+            ]
+        );
+    }
+
+    #[test]
+    fn rfc7748_diffie_hellman() {
+        // RFC 7748 section 6.1.
+        let alice_private = [
+            0x77, 0x07, 0x6d, 0x0a, 0x73, 0x18, 0xa5, 0x7d, 0x3c, 0x16, 0xc1, 0x72, 0x51, 0xb2,
+            0x66, 0x45, 0xdf, 0x4c, 0x2f, 0x87, 0xeb, 0xc0, 0x99, 0x2a, 0xb1, 0x77, 0xfb, 0xa5,
+            0x1d, 0xb9, 0x2c, 0x2a,
+        ];
+        let bob_private = [
+            0x5d, 0xab, 0x08, 0x7e, 0x62, 0x4a, 0x8a, 0x4b, 0x79, 0xe1, 0x7f, 0x8b, 0x83, 0x80,
+            0x0e, 0xe6, 0x6f, 0x3b, 0xb1, 0x29, 0x26, 0x18, 0xb6, 0xfd, 0x1c, 0x2f, 0x8b, 0x27,
+            0xff, 0x88, 0xe0, 0xeb,
+        ];
+
+        let mut alice_public = [0; X25519_PUBLIC_KEY_LEN];
+        let mut bob_public = [0; X25519_PUBLIC_KEY_LEN];
+        X25519::public_key(&alice_private, &mut alice_public);
+        X25519::public_key(&bob_private, &mut bob_public);
+        assert_eq!(
+            alice_public,
+            [
+                0x85, 0x20, 0xf0, 0x09, 0x89, 0x30, 0xa7, 0x54, 0x74, 0x8b, 0x7d, 0xdc, 0xb4, 0x3e,
+                0xf7, 0x5a, 0x0d, 0xbf, 0x3a, 0x0d, 0x26, 0x38, 0x1a, 0xf4, 0xeb, 0xa4, 0xa9, 0x8e,
+                0xaa, 0x9b, 0x4e, 0x6a
+            ]
+        );
+        assert_eq!(
+            bob_public,
+            [
+                0xde, 0x9e, 0xdb, 0x7d, 0x7b, 0x7d, 0xc1, 0xb4, 0xd3, 0x5b, 0x61, 0xc2, 0xec, 0xe4,
+                0x35, 0x37, 0x3f, 0x83, 0x43, 0xc8, 0x5b, 0x78, 0x67, 0x4d, 0xad, 0xfc, 0x7e, 0x14,
+                0x6f, 0x88, 0x2b, 0x4f
+            ]
+        );
+
+        let secret = [
+            0x4a, 0x5d, 0x9d, 0x5b, 0xa4, 0xce, 0x2d, 0xe1, 0x72, 0x8e, 0x3b, 0xf4, 0x80, 0x35,
+            0x0f, 0x25, 0xe0, 0x7e, 0x21, 0xc9, 0x47, 0xd1, 0x9e, 0x33, 0x76, 0xf0, 0x9b, 0x3c,
+            0x1e, 0x16, 0x17, 0x42,
+        ];
+        assert_eq!(shared_secret(&alice_private, &bob_public), Ok(secret));
+        assert_eq!(shared_secret(&bob_private, &alice_public), Ok(secret));
+    }
+
+    #[test]
+    fn low_order_point_is_rejected() {
+        let scalar = [0x42; PRIVATE_KEY_LEN];
+        assert_eq!(shared_secret(&scalar, &[0; 32]), Err(ErrorCode::INVAL));
+
+        let mut one = [0; 32];
+        one[0] = 1;
+        assert_eq!(shared_secret(&scalar, &one), Err(ErrorCode::INVAL));
+    }
+}
@@ -0,0 +1,396 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! A text screen on a pixel screen.
+//!
+//! Implements `hil::text_screen::TextScreen` on any `hil::screen::Screen`
+//! through `Graphics`, drawing characters in a bitmap font. The characters
+//! on the screen are kept in a buffer, and the rows a command changes are
+//! redrawn whole, so that characters on monochrome screens are not cut off
+//! at byte boundaries.
+//!
+//! Printing a newline moves the cursor to the start of the next row, and
+//! text that runs past the last row continues on the first one. The cursor
+//! is drawn as an underline, or as an inverted cell while blinking is on;
+//! it does not actually blink, as rows are only redrawn by commands.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! let screen_text = components::graphics::ScreenTextComponent::new(
+//!     graphics,
+//!     capsules_extra::graphics::TextStyle {
+//!         font: &capsules_extra::graphics::FONT_5X8,
+//!         scale: 1,
+//!         foreground: 0xFFFFFFFF,
+//!         background: 0xFF000000,
+//!     },
+//! )
+//! .finalize(components::screen_text_component_static!(168));
+//!
+//! let text_screen = components::text_screen::TextScreenComponent::new(
+//!     board_kernel,
+//!     capsules_extra::text_screen::DRIVER_NUM,
+//!     screen_text,
+//! )
+//! .finalize(components::text_screen_component_static!(64));
+//! ```
+
+use core::cell::Cell;
+use core::cmp;
+
+use kernel::deferred_call::{DeferredCall, DeferredCallClient};
+use kernel::hil::text_screen::{TextScreen, TextScreenClient};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::utilities::leasable_buffer::SubSliceMut;
+use kernel::ErrorCode;
+
+use crate::graphics::{Graphics, GraphicsClient, PixelSource, Rect, TextStyle};
+
+/// Write `input` into `text`, a screen of `rows` rows of `columns`
+/// characters, starting at the `(column, row)` `cursor`. Returns the new
+/// cursor, and whether the text ran past the last row.
+fn lay_out<'b>(
+    text: &mut [u8],
+    columns: usize,
+    rows: usize,
+    cursor: (usize, usize),
+    input: impl Iterator<Item = &'b u8>,
+) -> ((usize, usize), bool) {
+    let (mut column, mut row) = cursor;
+    let mut wrapped = false;
+    for c in input {
+        if *c == b'\n' {
+            column = columns;
+        } else {
+            text[row * columns + column] = *c;
+            column += 1;
+        }
+        if column == columns {
+            column = 0;
+            row += 1;
+            if row == rows {
+                row = 0;
+                wrapped = true;
+            }
+        }
+    }
+    ((column, row), wrapped)
+}
+
+pub struct ScreenText<'a> {
+    graphics: &'a Graphics<'a>,
+    client: OptionalCell<&'a dyn TextScreenClient>,
+    style: TextStyle,
+    columns: usize,
+    rows: usize,
+    /// The characters on the screen, row by row.
+    text: TakeCell<'static, [u8]>,
+    /// The column and row of the cursor.
+    cursor: Cell<(usize, usize)>,
+    cursor_visible: Cell<bool>,
+    blink: Cell<bool>,
+    display: Cell<bool>,
+    busy: Cell<bool>,
+    /// The row being drawn and the last row to draw.
+    row: Cell<usize>,
+    last_row: Cell<usize>,
+    /// The buffer of the print in progress, and its length.
+    print_buffer: TakeCell<'static, [u8]>,
+    print_len: Cell<usize>,
+    deferred_call: DeferredCall,
+}
+
+impl<'a> ScreenText<'a> {
+    /// Create a text screen with as many rows and columns of characters in
+    /// `style` as fit on the screen and in `text`.
+    pub fn new(
+        graphics: &'a Graphics<'a>,
+        style: TextStyle,
+        text: &'static mut [u8],
+    ) -> ScreenText<'a> {
+        let (width, height) = graphics.get_resolution();
+        let (cell_width, cell_height) = style.font.cell_size(style.scale);
+        let columns = width / cell_width;
+        let rows = if columns > 0 {
+            cmp::min(height / cell_height, text.len() / columns)
+        } else {
+            0
+        };
+        text.fill(b' ');
+
+        ScreenText {
+            graphics,
+            client: OptionalCell::empty(),
+            style,
+            columns,
+            rows,
+            text: TakeCell::new(text),
+            cursor: Cell::new((0, 0)),
+            cursor_visible: Cell::new(false),
+            blink: Cell::new(false),
+            display: Cell::new(true),
+            busy: Cell::new(false),
+            row: Cell::new(0),
+            last_row: Cell::new(0),
+            print_buffer: TakeCell::empty(),
+            print_len: Cell::new(0),
+            deferred_call: DeferredCall::new(),
+        }
+    }
+
+    /// Redraw rows `first` to `last`, or just complete the command if the
+    /// display is off.
+    fn update(&self, first: usize, last: usize) -> Result<(), ErrorCode> {
+        if self.busy.get() {
+            return Err(ErrorCode::BUSY);
+        }
+        if self.display.get() {
+            self.draw_rows(first, last)?;
+        } else {
+            self.deferred_call.set();
+        }
+        self.busy.set(true);
+        Ok(())
+    }
+
+    fn draw_rows(&self, first: usize, last: usize) -> Result<(), ErrorCode> {
+        self.row.set(first);
+        self.last_row.set(last);
+        self.draw_row(first)
+    }
+
+    fn draw_row(&self, row: usize) -> Result<(), ErrorCode> {
+        let (width, height) = self.style.font.cell_size(self.style.scale);
+        self.graphics
+            .draw(Rect::new(0, row * height, self.columns * width, height))
+    }
+
+    fn complete(&self, r: Result<(), ErrorCode>) {
+        self.busy.set(false);
+        match self.print_buffer.take() {
+            Some(buffer) => self.client.map(|client| {
+                client.write_complete(buffer, self.print_len.get(), r);
+            }),
+            None => self.client.map(|client| client.command_complete(r)),
+        };
+    }
+
+    /// Redraw the row of the cursor after changing how it is shown.
+    fn update_cursor(&self) -> Result<(), ErrorCode> {
+        let (_, row) = self.cursor.get();
+        self.update(row, row)
+    }
+}
+
+impl<'a> TextScreen<'a> for ScreenText<'a> {
+    fn set_client(&self, client: Option<&'a dyn TextScreenClient>) {
+        match client {
+            Some(client) => self.client.set(client),
+            None => self.client.clear(),
+        }
+    }
+
+    fn get_size(&self) -> (usize, usize) {
+        (self.columns, self.rows)
+    }
+
+    fn print(
+        &self,
+        buffer: &'static mut [u8],
+        len: usize,
+    ) -> Result<(), (ErrorCode, &'static mut [u8])> {
+        if self.busy.get() {
+            return Err((ErrorCode::BUSY, buffer));
+        }
+        if self.rows == 0 {
+            return Err((ErrorCode::NOSUPPORT, buffer));
+        }
+
+        let cursor = self.cursor.get();
+        let first = cursor.1;
+        let (cursor, wrapped) = self.text.map_or((cursor, false), |text| {
+            lay_out(
+                text,
+                self.columns,
+                self.rows,
+                cursor,
+                buffer.iter().take(len),
+            )
+        });
+        self.cursor.set(cursor);
+        let row = cursor.1;
+
+        let (first, last) = if wrapped {
+            (0, self.rows - 1)
+        } else {
+            (first, row)
+        };
+        match self.update(first, last) {
+            Ok(()) => {
+                self.print_len.set(cmp::min(len, buffer.len()));
+                self.print_buffer.replace(buffer);
+                Ok(())
+            }
+            Err(e) => Err((e, buffer)),
+        }
+    }
+
+    fn set_cursor(&self, x_position: usize, y_position: usize) -> Result<(), ErrorCode> {
+        if x_position >= self.columns || y_position >= self.rows {
+            return Err(ErrorCode::INVAL);
+        }
+        if self.busy.get() {
+            return Err(ErrorCode::BUSY);
+        }
+        let (_, row) = self.cursor.get();
+        self.cursor.set((x_position, y_position));
+        self.update(cmp::min(row, y_position), cmp::max(row, y_position))
+    }
+
+    fn hide_cursor(&self) -> Result<(), ErrorCode> {
+        self.update_cursor()
+            .inspect(|()| self.cursor_visible.set(false))
+    }
+
+    fn show_cursor(&self) -> Result<(), ErrorCode> {
+        self.update_cursor()
+            .inspect(|()| self.cursor_visible.set(true))
+    }
+
+    fn blink_cursor_on(&self) -> Result<(), ErrorCode> {
+        self.update_cursor().inspect(|()| self.blink.set(true))
+    }
+
+    fn blink_cursor_off(&self) -> Result<(), ErrorCode> {
+        self.update_cursor().inspect(|()| self.blink.set(false))
+    }
+
+    fn display_on(&self) -> Result<(), ErrorCode> {
+        if self.busy.get() {
+            return Err(ErrorCode::BUSY);
+        }
+        self.display.set(true);
+        self.update(0, self.rows.saturating_sub(1))
+    }
+
+    fn display_off(&self) -> Result<(), ErrorCode> {
+        if self.busy.get() {
+            return Err(ErrorCode::BUSY);
+        }
+        // Draw the rows blank before turning the display off.
+        self.display.set(false);
+        self.draw_rows(0, self.rows.saturating_sub(1))?;
+        self.busy.set(true);
+        Ok(())
+    }
+
+    fn clear(&self) -> Result<(), ErrorCode> {
+        if self.busy.get() {
+            return Err(ErrorCode::BUSY);
+        }
+        self.text.map(|text| text.fill(b' '));
+        self.cursor.set((0, 0));
+        self.update(0, self.rows.saturating_sub(1))
+    }
+}
+
+impl<'a> PixelSource for ScreenText<'a> {
+    fn pixel(&self, x: usize, y: usize) -> u32 {
+        let style = self.style;
+        if !self.display.get() {
+            return style.background;
+        }
+
+        let (width, height) = style.font.cell_size(style.scale);
+        let (column, row) = (x / width, self.row.get());
+        let c = self
+            .text
+            .map_or(b' ', |text| text[row * self.columns + column]);
+        let mut set = style
+            .font
+            .pixel(c, x % width / style.scale, y / style.scale);
+        if self.cursor_visible.get() && self.cursor.get() == (column, row) {
+            if self.blink.get() {
+                set = !set;
+            } else if y == height - 1 {
+                set = true;
+            }
+        }
+        if set {
+            style.foreground
+        } else {
+            style.background
+        }
+    }
+}
+
+impl<'a> GraphicsClient for ScreenText<'a> {
+    fn command_complete(&self, r: Result<(), ErrorCode>) {
+        let row = self.row.get() + 1;
+        if r.is_ok() && row <= self.last_row.get() {
+            self.row.set(row);
+            if let Err(e) = self.draw_row(row) {
+                self.complete(Err(e));
+            }
+        } else {
+            self.complete(r);
+        }
+    }
+
+    fn write_complete(&self, _buffer: SubSliceMut<'static, u8>, _r: Result<(), ErrorCode>) {}
+}
+
+impl<'a> DeferredCallClient for ScreenText<'a> {
+    fn handle_deferred_call(&self) {
+        self.complete(Ok(()));
+    }
+
+    fn register(&'static self) {
+        self.deferred_call.register(self);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Lay out `input` on a screen of 2 rows of 4 characters.
+    fn print(text: &mut [u8; 8], cursor: (usize, usize), input: &[u8]) -> ((usize, usize), bool) {
+        lay_out(text, 4, 2, cursor, input.iter())
+    }
+
+    #[test]
+    fn fills_rows() {
+        let mut text = [b' '; 8];
+        assert_eq!(print(&mut text, (0, 0), b"abc"), ((3, 0), false));
+        assert_eq!(&text, b"abc     ");
+        // Filling a row moves the cursor to the start of the next one.
+        assert_eq!(print(&mut text, (3, 0), b"de"), ((1, 1), false));
+        assert_eq!(&text, b"abcde   ");
+    }
+
+    #[test]
+    fn newline() {
+        let mut text = [b' '; 8];
+        assert_eq!(print(&mut text, (0, 0), b"a\nb"), ((1, 1), false));
+        assert_eq!(&text, b"a   b   ");
+        // A newline on the last row wraps to the first.
+        let mut text = [b' '; 8];
+        assert_eq!(print(&mut text, (0, 0), b"abcd"), ((0, 1), false));
+        assert_eq!(print(&mut text, (0, 1), b"\n"), ((0, 0), true));
+    }
+
+    #[test]
+    fn wraps_to_first_row() {
+        let mut text = [b' '; 8];
+        assert_eq!(print(&mut text, (2, 1), b"xyz"), ((1, 0), true));
+        assert_eq!(&text, b"z     xy");
+        // Text longer than the screen overwrites what it printed first.
+        let mut text = [b' '; 8];
+        assert_eq!(print(&mut text, (0, 0), b"0123456789"), ((2, 0), true));
+        assert_eq!(&text, b"89234567");
+    }
+}
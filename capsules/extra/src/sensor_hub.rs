@@ -0,0 +1,714 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Periodic, batched sampling of sensors for userspace.
+//!
+//! The single-shot sensor drivers wake an app for every reading. This capsule
+//! instead samples the sensors at a period each app configures, and collects
+//! timestamped readings in a ring buffer in the app's grant. The app is only
+//! woken when a batch is complete, or when a reading leaves the range the app
+//! set for that sensor.
+//!
+//! The sensors are the temperature, humidity and pressure sensors, and the
+//! accelerometer, magnetometer and gyroscope of a 9DOF sensor. A board can
+//! leave out any of them. Readings are shared: if several apps are due a
+//! reading from the same sensor, the sensor is only read once.
+//!
+//! Every sensor is read with the same virtual alarm, and only one sensor is
+//! read at a time.
+//!
+//! Userspace Interface
+//! -------------------
+//!
+//! Readings are copied to the read-write allow buffer 0 when they are
+//! delivered, as 20 byte records of little-endian values:
+//!
+//! ```text
+//! 0: u32 timestamp, the low 32 bits of the alarm ticks
+//! 4: u32 sensor
+//! 8: i32 value, or x for 3-axis sensors
+//! 12: i32 y, 0 for single value sensors
+//! 16: i32 z, 0 for single value sensors
+//! ```
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! let sensor_hub = components::sensor_hub::SensorHubComponent::new(
+//!     board_kernel,
+//!     capsules_extra::sensor_hub::DRIVER_NUM,
+//!     mux_alarm,
+//!     Some(temperature),
+//!     Some(humidity),
+//!     None,
+//!     Some(ninedof),
+//! )
+//! .finalize(components::sensor_hub_component_static!(
+//!     nrf52840::rtc::Rtc<'static>,
+//!     16
+//! ));
+//! ```
+
+use core::cell::Cell;
+
+use kernel::grant::{AllowRoCount, AllowRwCount, Grant, GrantKernelData, UpcallCount};
+use kernel::hil::sensors;
+use kernel::hil::time::{self, Alarm, ConvertTicks, Frequency, Ticks};
+use kernel::processbuffer::{WriteableProcessBuffer, WriteableProcessSlice};
+use kernel::syscall::{CommandReturn, SyscallDriver};
+use kernel::utilities::cells::OptionalCell;
+use kernel::{ErrorCode, ProcessId};
+
+/// Syscall driver number.
+use capsules_core::driver;
+pub const DRIVER_NUM: usize = driver::NUM::SensorHub as usize;
+
+/// Ids for read-write allow buffers
+mod rw_allow {
+    pub const SAMPLES: usize = 0;
+    /// The number of allow buffers the kernel stores for this grant
+    pub const COUNT: u8 = 1;
+}
+
+/// The length of a reading in the samples buffer.
+const SAMPLE_LEN: usize = 20;
+
+/// The number of sensors that can be sampled.
+const SENSORS: usize = 6;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Sensor {
+    Temperature = 0,
+    Humidity = 1,
+    Pressure = 2,
+    Accelerometer = 3,
+    Magnetometer = 4,
+    Gyroscope = 5,
+}
+
+impl Sensor {
+    fn from_index(index: usize) -> Option<Sensor> {
+        match index {
+            0 => Some(Sensor::Temperature),
+            1 => Some(Sensor::Humidity),
+            2 => Some(Sensor::Pressure),
+            3 => Some(Sensor::Accelerometer),
+            4 => Some(Sensor::Magnetometer),
+            5 => Some(Sensor::Gyroscope),
+            _ => None,
+        }
+    }
+
+    fn bit(self) -> u8 {
+        1 << self as u8
+    }
+}
+
+/// Why the readings were delivered, passed as the first upcall argument.
+#[derive(Clone, Copy)]
+enum Reason {
+    /// The batch is complete.
+    Batch = 0,
+    /// A reading left the range set by the app.
+    Threshold = 1,
+    /// The app asked for the readings.
+    Flush = 2,
+}
+
+#[derive(Clone, Copy, Default)]
+struct Sample {
+    timestamp: u32,
+    sensor: u8,
+    values: [i32; 3],
+}
+
+pub struct App<T: Ticks, const N: usize> {
+    /// The sensors this app samples.
+    enabled: u8,
+    /// The sensors this app is waiting for a reading from.
+    pending: u8,
+    /// The sampling period of each sensor.
+    period: [T; SENSORS],
+    /// When each sensor was last due to be sampled.
+    reference: [T; SENSORS],
+
+    low: [i32; SENSORS],
+    high: [i32; SENSORS],
+    /// The sensors whose last reading was outside of `low..=high`.
+    outside: u8,
+
+    /// The number of readings that complete a batch.
+    batch: usize,
+    samples: [Sample; N],
+    start: usize,
+    len: usize,
+    /// The number of readings overwritten since the last delivery.
+    dropped: usize,
+}
+
+impl<T: Ticks, const N: usize> Default for App<T, N> {
+    fn default() -> Self {
+        App {
+            enabled: 0,
+            pending: 0,
+            period: [T::from(0); SENSORS],
+            reference: [T::from(0); SENSORS],
+            low: [i32::MIN; SENSORS],
+            high: [i32::MAX; SENSORS],
+            outside: 0,
+            batch: N,
+            samples: [Sample::default(); N],
+            start: 0,
+            len: 0,
+            dropped: 0,
+        }
+    }
+}
+
+impl<T: Ticks, const N: usize> App<T, N> {
+    /// Add a reading, overwriting the oldest one if the ring is full.
+    fn push(&mut self, sample: Sample) {
+        if self.len < N {
+            self.samples[(self.start + self.len) % N] = sample;
+            self.len += 1;
+        } else {
+            self.samples[self.start] = sample;
+            self.start = (self.start + 1) % N;
+            self.dropped = self.dropped.saturating_add(1);
+        }
+    }
+
+    /// Check whether a reading of `sensor` left the app's range.
+    fn crossed_threshold(&mut self, sensor: Sensor, values: &[i32; 3]) -> bool {
+        let (low, high) = (self.low[sensor as usize], self.high[sensor as usize]);
+        let outside = values.iter().any(|value| *value < low || *value > high);
+        let was_outside = self.outside & sensor.bit() != 0;
+        if outside {
+            self.outside |= sensor.bit();
+        } else {
+            self.outside &= !sensor.bit();
+        }
+        outside && !was_outside
+    }
+
+    /// Copy as many readings as fit to `buffer`, oldest first, and remove
+    /// them from the ring. Returns the number of readings copied.
+    fn drain(&mut self, buffer: &WriteableProcessSlice) -> usize {
+        let count = core::cmp::min(self.len, buffer.len() / SAMPLE_LEN);
+        for (i, record) in buffer.chunks(SAMPLE_LEN).take(count).enumerate() {
+            let sample = &self.samples[(self.start + i) % N];
+            record[0..4].copy_from_slice(&sample.timestamp.to_le_bytes());
+            record[4..8].copy_from_slice(&(sample.sensor as u32).to_le_bytes());
+            for (value, dest) in sample.values.iter().zip(record[8..].chunks(4)) {
+                dest.copy_from_slice(&value.to_le_bytes());
+            }
+        }
+
+        self.start = (self.start + count) % N;
+        self.len -= count;
+        count
+    }
+
+    /// Copy the readings to the app and notify it.
+    fn deliver(&mut self, kernel_data: &GrantKernelData, reason: Reason) {
+        let count = kernel_data
+            .get_readwrite_processbuffer(rw_allow::SAMPLES)
+            .and_then(|buffer| buffer.mut_enter(|buffer| self.drain(buffer)))
+            .unwrap_or(0);
+
+        let dropped = core::mem::replace(&mut self.dropped, 0);
+        kernel_data
+            .schedule_upcall(0, (reason as usize, count, dropped))
+            .ok();
+    }
+
+    /// Mark the sensors that are due to be sampled at `now` as pending, and
+    /// return them.
+    fn due(&mut self, now: T) -> u8 {
+        let mut due = 0;
+        for sensor in 0..SENSORS {
+            let period = self.period[sensor];
+            let reference = self.reference[sensor];
+            if self.enabled & (1 << sensor) == 0
+                || now.within_range(reference, reference.wrapping_add(period))
+            {
+                continue;
+            }
+
+            self.pending |= 1 << sensor;
+            due |= 1 << sensor;
+            // If we fell behind by more than a period, skip the readings
+            // that were missed.
+            let reference = reference.wrapping_add(period);
+            self.reference[sensor] = if now.within_range(reference, reference.wrapping_add(period))
+            {
+                reference
+            } else {
+                now
+            };
+        }
+        due
+    }
+
+    /// The time from `now` until the next sensor is due, if any are enabled.
+    fn next_due(&self, now: T) -> Option<T> {
+        let mut next: Option<T> = None;
+        for sensor in 0..SENSORS {
+            if self.enabled & (1 << sensor) == 0 {
+                continue;
+            }
+            let end = self.reference[sensor].wrapping_add(self.period[sensor]);
+            let remaining = if now.within_range(self.reference[sensor], end) {
+                end.wrapping_sub(now)
+            } else {
+                T::from(0)
+            };
+            next = Some(next.map_or(remaining, |next| core::cmp::min(next, remaining)));
+        }
+        next
+    }
+}
+
+pub struct SensorHub<'a, A: Alarm<'a>, const N: usize> {
+    alarm: &'a A,
+    temperature: Option<&'a dyn sensors::TemperatureDriver<'a>>,
+    humidity: Option<&'a dyn sensors::HumidityDriver<'a>>,
+    pressure: Option<&'a dyn sensors::PressureDriver<'a>>,
+    ninedof: Option<&'a dyn sensors::NineDof<'a>>,
+
+    apps:
+        Grant<App<A::Ticks, N>, UpcallCount<1>, AllowRoCount<0>, AllowRwCount<{ rw_allow::COUNT }>>,
+    /// Sensors an app is due a reading from, that haven't been read yet.
+    pending: Cell<u8>,
+    /// The sensor being read.
+    reading: OptionalCell<Sensor>,
+}
+
+impl<'a, A: Alarm<'a>, const N: usize> SensorHub<'a, A, N> {
+    pub fn new(
+        alarm: &'a A,
+        temperature: Option<&'a dyn sensors::TemperatureDriver<'a>>,
+        humidity: Option<&'a dyn sensors::HumidityDriver<'a>>,
+        pressure: Option<&'a dyn sensors::PressureDriver<'a>>,
+        ninedof: Option<&'a dyn sensors::NineDof<'a>>,
+        grant: Grant<
+            App<A::Ticks, N>,
+            UpcallCount<1>,
+            AllowRoCount<0>,
+            AllowRwCount<{ rw_allow::COUNT }>,
+        >,
+    ) -> SensorHub<'a, A, N> {
+        SensorHub {
+            alarm,
+            temperature,
+            humidity,
+            pressure,
+            ninedof,
+            apps: grant,
+            pending: Cell::new(0),
+            reading: OptionalCell::empty(),
+        }
+    }
+
+    fn available(&self, sensor: Sensor) -> bool {
+        match sensor {
+            Sensor::Temperature => self.temperature.is_some(),
+            Sensor::Humidity => self.humidity.is_some(),
+            Sensor::Pressure => self.pressure.is_some(),
+            Sensor::Accelerometer | Sensor::Magnetometer | Sensor::Gyroscope => {
+                self.ninedof.is_some()
+            }
+        }
+    }
+
+    fn read(&self, sensor: Sensor) -> Result<(), ErrorCode> {
+        match sensor {
+            Sensor::Temperature => self
+                .temperature
+                .map_or(Err(ErrorCode::NODEVICE), |t| t.read_temperature()),
+            Sensor::Humidity => self
+                .humidity
+                .map_or(Err(ErrorCode::NODEVICE), |h| h.read_humidity()),
+            Sensor::Pressure => self
+                .pressure
+                .map_or(Err(ErrorCode::NODEVICE), |p| p.read_atmospheric_pressure()),
+            Sensor::Accelerometer => self
+                .ninedof
+                .map_or(Err(ErrorCode::NODEVICE), |n| n.read_accelerometer()),
+            Sensor::Magnetometer => self
+                .ninedof
+                .map_or(Err(ErrorCode::NODEVICE), |n| n.read_magnetometer()),
+            Sensor::Gyroscope => self
+                .ninedof
+                .map_or(Err(ErrorCode::NODEVICE), |n| n.read_gyroscope()),
+        }
+    }
+
+    /// Start reading the next pending sensor, unless a read is in progress.
+    fn start_read(&self) {
+        while self.reading.is_none() && self.pending.get() != 0 {
+            let pending = self.pending.get();
+            let sensor = match Sensor::from_index(pending.trailing_zeros() as usize) {
+                Some(sensor) => sensor,
+                None => {
+                    self.pending.set(0);
+                    return;
+                }
+            };
+            self.pending.set(pending & !sensor.bit());
+
+            match self.read(sensor) {
+                Ok(()) => self.reading.set(sensor),
+                // Skip this reading.
+                Err(_) => self.apps.each(|_, app, _| app.pending &= !sensor.bit()),
+            }
+        }
+    }
+
+    /// Set the alarm for the next time a sensor is due to be sampled.
+    fn schedule(&self) {
+        let now = self.alarm.now();
+        let mut next: Option<A::Ticks> = None;
+        self.apps.each(|_, app, _| {
+            if let Some(remaining) = app.next_due(now) {
+                next = Some(next.map_or(remaining, |next| core::cmp::min(next, remaining)));
+            }
+        });
+
+        match next {
+            Some(dt) => self.alarm.set_alarm(now, dt),
+            None => {
+                let _ = self.alarm.disarm();
+            }
+        }
+    }
+
+    /// Hand a reading to every app waiting for it.
+    fn reading_done(&self, values: Option<[i32; 3]>) {
+        let sensor = match self.reading.take() {
+            Some(sensor) => sensor,
+            None => return,
+        };
+        let timestamp = self.alarm.now().into_u32();
+
+        self.apps.each(|_, app, kernel_data| {
+            if app.pending & sensor.bit() == 0 {
+                return;
+            }
+            app.pending &= !sensor.bit();
+
+            if let Some(values) = values {
+                app.push(Sample {
+                    timestamp,
+                    sensor: sensor as u8,
+                    values,
+                });
+                if app.crossed_threshold(sensor, &values) {
+                    app.deliver(kernel_data, Reason::Threshold);
+                } else if app.len >= app.batch {
+                    app.deliver(kernel_data, Reason::Batch);
+                }
+            }
+        });
+
+        self.start_read();
+    }
+
+    fn configure(&self, sensor: Sensor, period_ms: usize, processid: ProcessId) -> CommandReturn {
+        if !self.available(sensor) {
+            return CommandReturn::failure(ErrorCode::NODEVICE);
+        }
+
+        let result = self.apps.enter(processid, |app, _| {
+            if period_ms == 0 {
+                app.enabled &= !sensor.bit();
+                app.pending &= !sensor.bit();
+            } else {
+                let period = self.alarm.ticks_from_ms(period_ms as u32);
+                // Always wait at least one tick between readings.
+                app.period[sensor as usize] = core::cmp::max(period, A::Ticks::from(1));
+                app.reference[sensor as usize] = self.alarm.now();
+                app.enabled |= sensor.bit();
+            }
+        });
+
+        match result {
+            Ok(()) => {
+                self.schedule();
+                CommandReturn::success()
+            }
+            Err(err) => CommandReturn::failure(err.into()),
+        }
+    }
+}
+
+impl<'a, A: Alarm<'a>, const N: usize> time::AlarmClient for SensorHub<'a, A, N> {
+    fn alarm(&self) {
+        let now = self.alarm.now();
+        let mut due = 0;
+        self.apps.each(|_, app, _| due |= app.due(now));
+
+        self.pending.set(self.pending.get() | due);
+        self.start_read();
+        self.schedule();
+    }
+}
+
+impl<'a, A: Alarm<'a>, const N: usize> sensors::TemperatureClient for SensorHub<'a, A, N> {
+    fn callback(&self, value: Result<i32, ErrorCode>) {
+        self.reading_done(value.ok().map(|value| [value, 0, 0]));
+    }
+}
+
+impl<'a, A: Alarm<'a>, const N: usize> sensors::HumidityClient for SensorHub<'a, A, N> {
+    fn callback(&self, value: usize) {
+        self.reading_done(Some([value as i32, 0, 0]));
+    }
+}
+
+impl<'a, A: Alarm<'a>, const N: usize> sensors::PressureClient for SensorHub<'a, A, N> {
+    fn callback(&self, value: Result<u32, ErrorCode>) {
+        self.reading_done(value.ok().map(|value| [value as i32, 0, 0]));
+    }
+}
+
+impl<'a, A: Alarm<'a>, const N: usize> sensors::NineDofClient for SensorHub<'a, A, N> {
+    fn callback(&self, x: usize, y: usize, z: usize) {
+        self.reading_done(Some([x as i32, y as i32, z as i32]));
+    }
+}
+
+impl<'a, A: Alarm<'a>, const N: usize> SyscallDriver for SensorHub<'a, A, N> {
+    /// Control the sensor hub.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver existence check.
+    /// - `1`: Sample sensor `data1` every `data2` milliseconds, or stop
+    ///        sampling it if `data2` is 0.
+    /// - `2`: Deliver the readings once `data1` of them are buffered.
+    /// - `3`: Deliver the readings as soon as a reading of sensor `data1` is
+    ///        below `data2`, interpreted as an `i32`.
+    /// - `4`: Deliver the readings as soon as a reading of sensor `data1` is
+    ///        above `data2`, interpreted as an `i32`.
+    /// - `5`: Remove the thresholds of sensor `data1`.
+    /// - `6`: Deliver the buffered readings now.
+    /// - `7`: Return the frequency of the timestamps in Hz.
+    fn command(
+        &self,
+        command_num: usize,
+        data1: usize,
+        data2: usize,
+        processid: ProcessId,
+    ) -> CommandReturn {
+        match command_num {
+            0 => CommandReturn::success(),
+
+            1 => match Sensor::from_index(data1) {
+                Some(sensor) => self.configure(sensor, data2, processid),
+                None => CommandReturn::failure(ErrorCode::INVAL),
+            },
+
+            2 => {
+                if data1 == 0 || data1 > N {
+                    return CommandReturn::failure(ErrorCode::INVAL);
+                }
+                self.apps
+                    .enter(processid, |app, _| app.batch = data1)
+                    .map_err(ErrorCode::from)
+                    .into()
+            }
+
+            3..=5 => match Sensor::from_index(data1) {
+                Some(sensor) => self
+                    .apps
+                    .enter(processid, |app, _| {
+                        let index = sensor as usize;
+                        match command_num {
+                            3 => app.low[index] = data2 as i32,
+                            4 => app.high[index] = data2 as i32,
+                            _ => {
+                                app.low[index] = i32::MIN;
+                                app.high[index] = i32::MAX;
+                            }
+                        }
+                        app.outside &= !sensor.bit();
+                    })
+                    .map_err(ErrorCode::from)
+                    .into(),
+                None => CommandReturn::failure(ErrorCode::INVAL),
+            },
+
+            6 => self
+                .apps
+                .enter(processid, |app, kernel_data| {
+                    app.deliver(kernel_data, Reason::Flush)
+                })
+                .map_err(ErrorCode::from)
+                .into(),
+
+            7 => CommandReturn::success_u32(A::Frequency::frequency()),
+
+            _ => CommandReturn::failure(ErrorCode::NOSUPPORT),
+        }
+    }
+
+    fn allocate_grant(&self, processid: ProcessId) -> Result<(), kernel::process::Error> {
+        self.apps.enter(processid, |_, _| {})
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kernel::hil::time::Ticks32;
+
+    type TestApp = App<Ticks32, 4>;
+
+    fn sample(timestamp: u32, sensor: Sensor, value: i32) -> Sample {
+        Sample {
+            timestamp,
+            sensor: sensor as u8,
+            values: [value, 0, 0],
+        }
+    }
+
+    fn enable(app: &mut TestApp, sensor: Sensor, period: u32, now: u32) {
+        app.enabled |= sensor.bit();
+        app.period[sensor as usize] = Ticks32::from(period);
+        app.reference[sensor as usize] = Ticks32::from(now);
+    }
+
+    /// The timestamp, sensor and first value of each record in `buffer`.
+    fn records(buffer: &[u8], count: usize) -> [(u32, u32, i32); 4] {
+        let mut records = [(0, 0, 0); 4];
+        for (record, bytes) in records
+            .iter_mut()
+            .zip(buffer.chunks(SAMPLE_LEN))
+            .take(count)
+        {
+            let word = |i: usize| [bytes[i], bytes[i + 1], bytes[i + 2], bytes[i + 3]];
+            *record = (
+                u32::from_le_bytes(word(0)),
+                u32::from_le_bytes(word(4)),
+                i32::from_le_bytes(word(8)),
+            );
+        }
+        records
+    }
+
+    #[test]
+    fn ring_drops_oldest() {
+        let mut app = TestApp::default();
+        for i in 0..6 {
+            app.push(sample(i, Sensor::Temperature, i as i32 * 10));
+        }
+        assert_eq!(app.len, 4);
+        assert_eq!(app.dropped, 2);
+
+        let mut buffer = [0; 4 * SAMPLE_LEN];
+        assert_eq!(app.drain((&mut buffer[..]).into()), 4);
+        assert_eq!(
+            records(&buffer, 4),
+            [(2, 0, 20), (3, 0, 30), (4, 0, 40), (5, 0, 50)]
+        );
+        assert_eq!(app.len, 0);
+    }
+
+    #[test]
+    fn drain_fits_buffer() {
+        let mut app = TestApp::default();
+        app.push(sample(1, Sensor::Humidity, 4000));
+        app.push(Sample {
+            timestamp: 2,
+            sensor: Sensor::Accelerometer as u8,
+            values: [-1, 2, -3],
+        });
+        app.push(sample(3, Sensor::Pressure, 1013));
+
+        // Room for one record and a bit.
+        let mut buffer = [0; SAMPLE_LEN + 7];
+        assert_eq!(app.drain((&mut buffer[..]).into()), 1);
+        assert_eq!(records(&buffer, 1)[0], (1, 1, 4000));
+        assert_eq!(buffer[SAMPLE_LEN..], [0; 7]);
+
+        let mut buffer = [0; 4 * SAMPLE_LEN];
+        assert_eq!(app.drain((&mut buffer[..]).into()), 2);
+        assert_eq!(records(&buffer, 2)[..2], [(2, 3, -1), (3, 2, 1013)]);
+        let mut axes = [0; 12];
+        axes.copy_from_slice(&buffer[8..20]);
+        assert_eq!(
+            axes,
+            [0xff, 0xff, 0xff, 0xff, 2, 0, 0, 0, 0xfd, 0xff, 0xff, 0xff]
+        );
+
+        // Nothing is left to copy.
+        assert_eq!(app.drain((&mut buffer[..]).into()), 0);
+    }
+
+    #[test]
+    fn threshold_is_edge_triggered() {
+        let mut app = TestApp::default();
+        app.low[Sensor::Temperature as usize] = 0;
+        app.high[Sensor::Temperature as usize] = 3000;
+
+        assert!(!app.crossed_threshold(Sensor::Temperature, &[2000, 0, 0]));
+        assert!(app.crossed_threshold(Sensor::Temperature, &[3001, 0, 0]));
+        // Still outside the range, so the app isn't woken again.
+        assert!(!app.crossed_threshold(Sensor::Temperature, &[-5, 0, 0]));
+        assert!(!app.crossed_threshold(Sensor::Temperature, &[3000, 0, 0]));
+        assert!(app.crossed_threshold(Sensor::Temperature, &[-1, 0, 0]));
+
+        // Any axis of a 3-axis sensor can cross the threshold, and the
+        // sensors are tracked separately.
+        app.high[Sensor::Gyroscope as usize] = 100;
+        assert!(app.crossed_threshold(Sensor::Gyroscope, &[0, 0, 101]));
+        assert_eq!(
+            app.outside,
+            Sensor::Temperature.bit() | Sensor::Gyroscope.bit()
+        );
+    }
+
+    #[test]
+    fn due_sensors() {
+        let mut app = TestApp::default();
+        assert_eq!(app.next_due(Ticks32::from(0)), None);
+
+        enable(&mut app, Sensor::Temperature, 100, 0);
+        enable(&mut app, Sensor::Pressure, 30, 0);
+        assert_eq!(app.next_due(Ticks32::from(10)), Some(Ticks32::from(20)));
+
+        assert_eq!(app.due(Ticks32::from(29)), 0);
+        assert_eq!(app.due(Ticks32::from(30)), Sensor::Pressure.bit());
+        assert_eq!(app.pending, Sensor::Pressure.bit());
+        assert_eq!(app.next_due(Ticks32::from(30)), Some(Ticks32::from(30)));
+
+        // Pressure falls behind by more than a period, so the missed readings
+        // are skipped and its period restarts now.
+        assert_eq!(
+            app.due(Ticks32::from(100)),
+            Sensor::Temperature.bit() | Sensor::Pressure.bit()
+        );
+        assert_eq!(
+            app.reference[Sensor::Temperature as usize],
+            Ticks32::from(100)
+        );
+        assert_eq!(app.reference[Sensor::Pressure as usize], Ticks32::from(100));
+        assert_eq!(app.next_due(Ticks32::from(100)), Some(Ticks32::from(30)));
+    }
+
+    #[test]
+    fn due_across_wraparound() {
+        let mut app = TestApp::default();
+        enable(&mut app, Sensor::Humidity, 100, u32::MAX - 49);
+        assert_eq!(
+            app.next_due(Ticks32::from(u32::MAX)),
+            Some(Ticks32::from(51))
+        );
+        assert_eq!(app.due(Ticks32::from(49)), 0);
+        assert_eq!(app.due(Ticks32::from(50)), Sensor::Humidity.bit());
+        assert_eq!(app.reference[Sensor::Humidity as usize], Ticks32::from(50));
+    }
+}
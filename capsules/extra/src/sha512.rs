@@ -0,0 +1,644 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Software implementation of SHA-384 and SHA-512.
+//!
+//! SHA-384 is SHA-512 with different initial hash values and the output
+//! truncated to 48 bytes, so both are implemented by `Sha512Software`. The
+//! digest length `L` selects the algorithm: 64 for SHA-512 and 48 for
+//! SHA-384. `Sha384Software` is the SHA-384 variant.
+//!
+//! Like `Sha256Software`, the hash is computed synchronously when data is
+//! added and callbacks are issued from a deferred call.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! let sha512 = static_init!(Sha512Software<'static>, Sha512Software::new());
+//! kernel::deferred_call::DeferredCallClient::register(sha512);
+//! sha512.set_mode_sha512();
+//! ```
+
+use core::cell::Cell;
+use kernel::deferred_call::{DeferredCall, DeferredCallClient};
+
+use kernel::hil::digest::{Client, ClientData, ClientHash, ClientVerify};
+use kernel::hil::digest::{ClientDataHash, ClientDataVerify, DigestDataHash, DigestDataVerify};
+use kernel::hil::digest::{Digest, DigestData, DigestHash, DigestVerify};
+use kernel::hil::digest::{Sha256, Sha384, Sha512};
+use kernel::utilities::cells::{MapCell, OptionalCell};
+use kernel::utilities::leasable_buffer::SubSlice;
+use kernel::utilities::leasable_buffer::SubSliceMut;
+use kernel::utilities::leasable_buffer::SubSliceMutImmut;
+use kernel::ErrorCode;
+
+#[derive(Clone, Copy, PartialEq)]
+enum State {
+    Idle,
+    Data,
+    Hash,
+    Verify,
+    CancelData,
+    CancelHash,
+    CancelVerify,
+}
+
+const SHA_BLOCK_LEN_BYTES: usize = 128;
+/// Bytes at the end of the last block holding the message length.
+const SHA_LENGTH_LEN_BYTES: usize = 16;
+const SHA_384_OUTPUT_LEN_BYTES: usize = 48;
+const SHA_512_OUTPUT_LEN_BYTES: usize = 64;
+const NUM_ROUND_CONSTANTS: usize = 80;
+
+const ROUND_CONSTANTS: [u64; NUM_ROUND_CONSTANTS] = [
+    0x428a2f98d728ae22,
+    0x7137449123ef65cd,
+    0xb5c0fbcfec4d3b2f,
+    0xe9b5dba58189dbbc,
+    0x3956c25bf348b538,
+    0x59f111f1b605d019,
+    0x923f82a4af194f9b,
+    0xab1c5ed5da6d8118,
+    0xd807aa98a3030242,
+    0x12835b0145706fbe,
+    0x243185be4ee4b28c,
+    0x550c7dc3d5ffb4e2,
+    0x72be5d74f27b896f,
+    0x80deb1fe3b1696b1,
+    0x9bdc06a725c71235,
+    0xc19bf174cf692694,
+    0xe49b69c19ef14ad2,
+    0xefbe4786384f25e3,
+    0x0fc19dc68b8cd5b5,
+    0x240ca1cc77ac9c65,
+    0x2de92c6f592b0275,
+    0x4a7484aa6ea6e483,
+    0x5cb0a9dcbd41fbd4,
+    0x76f988da831153b5,
+    0x983e5152ee66dfab,
+    0xa831c66d2db43210,
+    0xb00327c898fb213f,
+    0xbf597fc7beef0ee4,
+    0xc6e00bf33da88fc2,
+    0xd5a79147930aa725,
+    0x06ca6351e003826f,
+    0x142929670a0e6e70,
+    0x27b70a8546d22ffc,
+    0x2e1b21385c26c926,
+    0x4d2c6dfc5ac42aed,
+    0x53380d139d95b3df,
+    0x650a73548baf63de,
+    0x766a0abb3c77b2a8,
+    0x81c2c92e47edaee6,
+    0x92722c851482353b,
+    0xa2bfe8a14cf10364,
+    0xa81a664bbc423001,
+    0xc24b8b70d0f89791,
+    0xc76c51a30654be30,
+    0xd192e819d6ef5218,
+    0xd69906245565a910,
+    0xf40e35855771202a,
+    0x106aa07032bbd1b8,
+    0x19a4c116b8d2d0c8,
+    0x1e376c085141ab53,
+    0x2748774cdf8eeb99,
+    0x34b0bcb5e19b48a8,
+    0x391c0cb3c5c95a63,
+    0x4ed8aa4ae3418acb,
+    0x5b9cca4f7763e373,
+    0x682e6ff3d6b2b8a3,
+    0x748f82ee5defb2fc,
+    0x78a5636f43172f60,
+    0x84c87814a1f0ab72,
+    0x8cc702081a6439ec,
+    0x90befffa23631e28,
+    0xa4506cebde82bde9,
+    0xbef9a3f7b2c67915,
+    0xc67178f2e372532b,
+    0xca273eceea26619c,
+    0xd186b8c721c0c207,
+    0xeada7dd6cde0eb1e,
+    0xf57d4f7fee6ed178,
+    0x06f067aa72176fba,
+    0x0a637dc5a2c898a6,
+    0x113f9804bef90dae,
+    0x1b710b35131c471b,
+    0x28db77f523047d84,
+    0x32caab7b40c72493,
+    0x3c9ebe0a15c9bebc,
+    0x431d67c49c100d4c,
+    0x4cc5d4becb3e42b6,
+    0x597f299cfc657e2a,
+    0x5fcb6fab3ad6faec,
+    0x6c44198c4a475817,
+];
+
+const SHA_384_INITIAL_HASH_VALUES: [u64; 8] = [
+    0xcbbb9d5dc1059ed8,
+    0x629a292a367cd507,
+    0x9159015a3070dd17,
+    0x152fecd8f70e5939,
+    0x67332667ffc00b31,
+    0x8eb44a8768581511,
+    0xdb0c2e0d64f98fa7,
+    0x47b5481dbefa4fa4,
+];
+
+const SHA_512_INITIAL_HASH_VALUES: [u64; 8] = [
+    0x6a09e667f3bcc908,
+    0xbb67ae8584caa73b,
+    0x3c6ef372fe94f82b,
+    0xa54ff53a5f1d36f1,
+    0x510e527fade682d1,
+    0x9b05688c2b3e6c1f,
+    0x1f83d9abfb41bd6b,
+    0x5be0cd19137e2179,
+];
+
+/// The SHA-384 or SHA-512 hash state, which is updated synchronously as data
+/// is added.
+pub(crate) struct Sha512Hasher<const L: usize> {
+    hash_values: [u64; 8],
+    /// Data that didn't fill a block.
+    buffer: [u8; SHA_BLOCK_LEN_BYTES],
+    buffered_length: usize,
+    total_length: u128,
+}
+
+impl<const L: usize> Sha512Hasher<L> {
+    pub(crate) const fn new() -> Self {
+        Self {
+            hash_values: if L == SHA_384_OUTPUT_LEN_BYTES {
+                SHA_384_INITIAL_HASH_VALUES
+            } else {
+                SHA_512_INITIAL_HASH_VALUES
+            },
+            buffer: [0; SHA_BLOCK_LEN_BYTES],
+            buffered_length: 0,
+            total_length: 0,
+        }
+    }
+
+    /// Hash `data`. The buffered data is completed first, and any data left
+    /// over that doesn't fill a block is buffered.
+    pub(crate) fn update(&mut self, mut data: &[u8]) {
+        self.total_length += data.len() as u128;
+        if self.buffered_length != 0 {
+            let copy_len = core::cmp::min(data.len(), SHA_BLOCK_LEN_BYTES - self.buffered_length);
+            self.buffer[self.buffered_length..self.buffered_length + copy_len]
+                .copy_from_slice(&data[..copy_len]);
+            data = &data[copy_len..];
+            self.buffered_length += copy_len;
+
+            if self.buffered_length == SHA_BLOCK_LEN_BYTES {
+                compress(&mut self.hash_values, &self.buffer);
+                self.buffered_length = 0;
+            }
+        }
+        // Process blocks
+        while data.len() >= SHA_BLOCK_LEN_BYTES {
+            compress(&mut self.hash_values, &data[..SHA_BLOCK_LEN_BYTES]);
+            data = &data[SHA_BLOCK_LEN_BYTES..];
+        }
+        // Keep the tail end for the next block
+        if !data.is_empty() {
+            self.buffer[..data.len()].copy_from_slice(data);
+            self.buffered_length = data.len();
+        }
+    }
+
+    /// Pad the buffered data, hash the final block and write the digest to
+    /// `output`. The hasher has to be reset before it is used again.
+    pub(crate) fn finish(&mut self, output: &mut [u8; L]) {
+        let buffered_length = self.buffered_length;
+        let length_bits = self.total_length * 8;
+
+        let b = &mut self.buffer;
+        b[buffered_length..].fill(0);
+        b[buffered_length] = 0x80;
+        // If the length doesn't fit after the padding byte it goes in an extra
+        // block.
+        if buffered_length + 1 > SHA_BLOCK_LEN_BYTES - SHA_LENGTH_LEN_BYTES {
+            compress(&mut self.hash_values, b);
+            b.fill(0);
+        }
+        b[SHA_BLOCK_LEN_BYTES - SHA_LENGTH_LEN_BYTES..].copy_from_slice(&length_bits.to_be_bytes());
+        compress(&mut self.hash_values, b);
+
+        for (chunk, value) in output.chunks_mut(8).zip(self.hash_values.iter()) {
+            chunk.copy_from_slice(&value.to_be_bytes()[..chunk.len()]);
+        }
+    }
+}
+
+/// Hash one block into `hash_values`.
+///
+/// Note: `block` MUST be at least `SHA_BLOCK_LEN_BYTES` long.
+fn compress(hash_values: &mut [u64; 8], block: &[u8]) {
+    let mut message_schedule = [0u64; NUM_ROUND_CONSTANTS];
+    for (word, bytes) in message_schedule.iter_mut().zip(block.chunks(8).take(16)) {
+        *word = u64::from_be_bytes([
+            bytes[0], bytes[1], bytes[2], bytes[3], bytes[4], bytes[5], bytes[6], bytes[7],
+        ]);
+    }
+    for i in 16..NUM_ROUND_CONSTANTS {
+        let w15 = message_schedule[i - 15];
+        let w2 = message_schedule[i - 2];
+        let s0 = w15.rotate_right(1) ^ w15.rotate_right(8) ^ (w15 >> 7);
+        let s1 = w2.rotate_right(19) ^ w2.rotate_right(61) ^ (w2 >> 6);
+        message_schedule[i] = message_schedule[i - 16]
+            .wrapping_add(s0)
+            .wrapping_add(message_schedule[i - 7])
+            .wrapping_add(s1);
+    }
+
+    // Compression
+    let mut hashes = *hash_values;
+    for i in 0..NUM_ROUND_CONSTANTS {
+        let s1 =
+            hashes[4].rotate_right(14) ^ hashes[4].rotate_right(18) ^ hashes[4].rotate_right(41);
+        let ch = (hashes[4] & hashes[5]) ^ ((!hashes[4]) & hashes[6]);
+        let temp1 = hashes[7]
+            .wrapping_add(s1)
+            .wrapping_add(ch)
+            .wrapping_add(ROUND_CONSTANTS[i])
+            .wrapping_add(message_schedule[i]);
+        let s0 =
+            hashes[0].rotate_right(28) ^ hashes[0].rotate_right(34) ^ hashes[0].rotate_right(39);
+        let maj = (hashes[0] & hashes[1]) ^ (hashes[0] & hashes[2]) ^ (hashes[1] & hashes[2]);
+        let temp2 = s0.wrapping_add(maj);
+
+        hashes[7] = hashes[6];
+        hashes[6] = hashes[5];
+        hashes[5] = hashes[4];
+        hashes[4] = hashes[3].wrapping_add(temp1);
+        hashes[3] = hashes[2];
+        hashes[2] = hashes[1];
+        hashes[1] = hashes[0];
+        hashes[0] = temp1.wrapping_add(temp2);
+    }
+
+    for (new, hash) in hash_values.iter_mut().zip(hashes.iter()) {
+        *new = new.wrapping_add(*hash);
+    }
+}
+
+/// SHA-384 in software.
+pub type Sha384Software<'a> = Sha512Software<'a, SHA_384_OUTPUT_LEN_BYTES>;
+
+pub struct Sha512Software<'a, const L: usize = SHA_512_OUTPUT_LEN_BYTES> {
+    state: Cell<State>,
+
+    client: OptionalCell<&'a dyn Client<L>>,
+    input_data: OptionalCell<SubSliceMutImmut<'static, u8>>,
+    hasher: MapCell<Sha512Hasher<L>>,
+
+    // Used to store the hash or the hash to compare against with verify
+    output_data: Cell<Option<&'static mut [u8; L]>>,
+
+    deferred_call: DeferredCall,
+}
+
+impl<'a, const L: usize> Sha512Software<'a, L> {
+    pub fn new() -> Self {
+        Self {
+            state: Cell::new(State::Idle),
+            client: OptionalCell::empty(),
+            input_data: OptionalCell::empty(),
+            hasher: MapCell::new(Sha512Hasher::new()),
+
+            output_data: Cell::new(None),
+
+            deferred_call: DeferredCall::new(),
+        }
+    }
+
+    pub fn busy(&self) -> bool {
+        self.state.get() != State::Idle
+    }
+
+    fn initialize(&self) {
+        let new_state = match self.state.get() {
+            State::Idle => State::Idle,
+            State::Data | State::CancelData => State::CancelData,
+            State::Hash | State::CancelHash => State::CancelHash,
+            State::Verify | State::CancelVerify => State::CancelVerify,
+        };
+        self.state.set(new_state);
+
+        self.hasher.map(|hasher| *hasher = Sha512Hasher::new());
+    }
+
+    /// Pad the buffered data, hash the final block and write the digest to
+    /// `output`.
+    fn complete_sha512(&self, output: &mut [u8; L]) {
+        self.hasher.map(|hasher| hasher.finish(output));
+    }
+
+    // Hash the data in `input_data`, updating the internal hash state.
+    fn compute_sha512(&self) {
+        if let Some(mut data) = self.input_data.take() {
+            self.hasher.map(|hasher| hasher.update(&data[..]));
+            // Go to end of data.
+            data.slice(data.len()..data.len());
+            self.input_data.set(data);
+        }
+    }
+}
+
+impl<'a, const L: usize> DigestData<'a, L> for Sha512Software<'a, L> {
+    fn add_data(
+        &self,
+        data: SubSlice<'static, u8>,
+    ) -> Result<(), (ErrorCode, SubSlice<'static, u8>)> {
+        if self.busy() {
+            Err((ErrorCode::BUSY, data))
+        } else {
+            self.state.set(State::Data);
+            self.deferred_call.set();
+            self.input_data.set(SubSliceMutImmut::Immutable(data));
+            self.compute_sha512();
+            Ok(())
+        }
+    }
+
+    fn add_mut_data(
+        &self,
+        data: SubSliceMut<'static, u8>,
+    ) -> Result<(), (ErrorCode, SubSliceMut<'static, u8>)> {
+        if self.busy() {
+            Err((ErrorCode::BUSY, data))
+        } else {
+            self.state.set(State::Data);
+            self.deferred_call.set();
+            self.input_data.set(SubSliceMutImmut::Mutable(data));
+            self.compute_sha512();
+            Ok(())
+        }
+    }
+
+    fn clear_data(&self) {
+        self.initialize();
+    }
+
+    fn set_data_client(&'a self, _client: &'a (dyn ClientData<L> + 'a)) {
+        unimplemented!()
+    }
+}
+
+impl<'a, const L: usize> DigestHash<'a, L> for Sha512Software<'a, L> {
+    fn run(
+        &'a self,
+        digest: &'static mut [u8; L],
+    ) -> Result<(), (ErrorCode, &'static mut [u8; L])> {
+        if self.busy() {
+            Err((ErrorCode::BUSY, digest))
+        } else {
+            self.state.set(State::Hash);
+            self.complete_sha512(digest);
+            self.output_data.set(Some(digest));
+            self.deferred_call.set();
+            Ok(())
+        }
+    }
+
+    fn set_hash_client(&'a self, _client: &'a (dyn ClientHash<L> + 'a)) {
+        unimplemented!()
+    }
+}
+
+impl<'a, const L: usize> DigestVerify<'a, L> for Sha512Software<'a, L> {
+    fn verify(
+        &'a self,
+        compare: &'static mut [u8; L],
+    ) -> Result<(), (ErrorCode, &'static mut [u8; L])> {
+        if self.busy() {
+            Err((ErrorCode::BUSY, compare))
+        } else {
+            self.state.set(State::Verify);
+            self.output_data.set(Some(compare));
+            self.deferred_call.set();
+            Ok(())
+        }
+    }
+
+    fn set_verify_client(&'a self, _client: &'a (dyn ClientVerify<L> + 'a)) {
+        unimplemented!()
+    }
+}
+
+impl<'a, const L: usize> Digest<'a, L> for Sha512Software<'a, L> {
+    fn set_client(&'a self, client: &'a dyn Client<L>) {
+        self.client.set(client);
+    }
+}
+
+impl<'a, const L: usize> DeferredCallClient for Sha512Software<'a, L> {
+    fn handle_deferred_call(&self) {
+        let prior = self.state.get();
+        self.state.set(State::Idle);
+        match prior {
+            State::Idle => {}
+            State::Verify => {
+                // Do the verification here so we don't have to store
+                // the result across the callback.
+                let compare = self.output_data.replace(None).unwrap();
+                let mut digest = [0; L];
+                self.complete_sha512(&mut digest);
+                let pass = digest == *compare;
+                self.clear_data();
+                self.client.map(|c| {
+                    c.verification_done(Ok(pass), compare);
+                });
+            }
+            State::Data => {
+                // Data already computed in method call
+                let data = self.input_data.take().unwrap();
+                match data {
+                    SubSliceMutImmut::Mutable(buffer) => {
+                        self.client.map(|client| {
+                            client.add_mut_data_done(Ok(()), buffer);
+                        });
+                    }
+                    SubSliceMutImmut::Immutable(buffer) => {
+                        self.client.map(|client| {
+                            client.add_data_done(Ok(()), buffer);
+                        });
+                    }
+                }
+            }
+            State::Hash => {
+                // Hash already copied in method call.
+                let output = self.output_data.replace(None).unwrap();
+                self.clear_data();
+                self.client.map(|c| {
+                    c.hash_done(Ok(()), output);
+                });
+            }
+            State::CancelData => {
+                self.clear_data();
+                let data = self.input_data.take().unwrap();
+                match data {
+                    SubSliceMutImmut::Mutable(buffer) => {
+                        self.client.map(|client| {
+                            client.add_mut_data_done(Err(ErrorCode::CANCEL), buffer);
+                        });
+                    }
+                    SubSliceMutImmut::Immutable(buffer) => {
+                        self.client.map(|client| {
+                            client.add_data_done(Err(ErrorCode::CANCEL), buffer);
+                        });
+                    }
+                }
+            }
+            State::CancelVerify => {
+                self.clear_data();
+                let output = self.output_data.replace(None).unwrap();
+                self.client.map(|client| {
+                    client.verification_done(Err(ErrorCode::CANCEL), output);
+                });
+            }
+            State::CancelHash => {
+                self.clear_data();
+                let output = self.output_data.replace(None).unwrap();
+                self.client.map(|client| {
+                    client.hash_done(Err(ErrorCode::CANCEL), output);
+                });
+            }
+        }
+    }
+
+    fn register(&'static self) {
+        self.deferred_call.register(self);
+    }
+}
+
+impl<const L: usize> Sha256 for Sha512Software<'_, L> {
+    fn set_mode_sha256(&self) -> Result<(), ErrorCode> {
+        Err(ErrorCode::NOSUPPORT)
+    }
+}
+
+impl<const L: usize> Sha384 for Sha512Software<'_, L> {
+    /// Call before adding data to perform Sha384
+    fn set_mode_sha384(&self) -> Result<(), ErrorCode> {
+        if L == SHA_384_OUTPUT_LEN_BYTES {
+            Ok(())
+        } else {
+            Err(ErrorCode::NOSUPPORT)
+        }
+    }
+}
+
+impl<const L: usize> Sha512 for Sha512Software<'_, L> {
+    /// Call before adding data to perform Sha512
+    fn set_mode_sha512(&self) -> Result<(), ErrorCode> {
+        if L == SHA_512_OUTPUT_LEN_BYTES {
+            Ok(())
+        } else {
+            Err(ErrorCode::NOSUPPORT)
+        }
+    }
+}
+
+impl<'a, const L: usize> DigestDataHash<'a, L> for Sha512Software<'a, L> {
+    fn set_client(&'a self, _client: &'a dyn ClientDataHash<L>) {
+        unimplemented!()
+    }
+}
+
+impl<'a, const L: usize> DigestDataVerify<'a, L> for Sha512Software<'a, L> {
+    fn set_client(&'a self, _client: &'a dyn ClientDataVerify<L>) {
+        unimplemented!()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TWO_BLOCK_MESSAGE: &[u8] = b"abcdefghbcdefghicdefghijdefghijkefghijklfghijklmghijklmnhijklmnoijklmnopjklmnopqklmnopqrlmnopqrsmnopqrstnopqrstu";
+
+    fn hash<const L: usize>(data: &[u8]) -> [u8; L] {
+        let mut hasher = Sha512Hasher::<L>::new();
+        hasher.update(data);
+        let mut output = [0; L];
+        hasher.finish(&mut output);
+        output
+    }
+
+    #[test]
+    fn sha512_one_block() {
+        // FIPS 180-4 example, SHA-512 of "abc".
+        assert_eq!(
+            hash::<64>(b"abc"),
+            [
+                0xdd, 0xaf, 0x35, 0xa1, 0x93, 0x61, 0x7a, 0xba, 0xcc, 0x41, 0x73, 0x49, 0xae, 0x20,
+                0x41, 0x31, 0x12, 0xe6, 0xfa, 0x4e, 0x89, 0xa9, 0x7e, 0xa2, 0x0a, 0x9e, 0xee, 0xe6,
+                0x4b, 0x55, 0xd3, 0x9a, 0x21, 0x92, 0x99, 0x2a, 0x27, 0x4f, 0xc1, 0xa8, 0x36, 0xba,
+                0x3c, 0x23, 0xa3, 0xfe, 0xeb, 0xbd, 0x45, 0x4d, 0x44, 0x23, 0x64, 0x3c, 0xe8, 0x0e,
+                0x2a, 0x9a, 0xc9, 0x4f, 0xa5, 0x4c, 0xa4, 0x9f
+            ]
+        );
+    }
+
+    #[test]
+    fn sha512_two_blocks() {
+        // FIPS 180-4 example, SHA-512 of a 112 byte message.
+        assert_eq!(
+            hash::<64>(TWO_BLOCK_MESSAGE),
+            [
+                0x8e, 0x95, 0x9b, 0x75, 0xda, 0xe3, 0x13, 0xda, 0x8c, 0xf4, 0xf7, 0x28, 0x14, 0xfc,
+                0x14, 0x3f, 0x8f, 0x77, 0x79, 0xc6, 0xeb, 0x9f, 0x7f, 0xa1, 0x72, 0x99, 0xae, 0xad,
+                0xb6, 0x88, 0x90, 0x18, 0x50, 0x1d, 0x28, 0x9e, 0x49, 0x00, 0xf7, 0xe4, 0x33, 0x1b,
+                0x99, 0xde, 0xc4, 0xb5, 0x43, 0x3a, 0xc7, 0xd3, 0x29, 0xee, 0xb6, 0xdd, 0x26, 0x54,
+                0x5e, 0x96, 0xe5, 0x5b, 0x87, 0x4b, 0xe9, 0x09
+            ]
+        );
+    }
+
+    #[test]
+    fn sha384_one_block() {
+        // FIPS 180-4 example, SHA-384 of "abc".
+        assert_eq!(
+            hash::<48>(b"abc"),
+            [
+                0xcb, 0x00, 0x75, 0x3f, 0x45, 0xa3, 0x5e, 0x8b, 0xb5, 0xa0, 0x3d, 0x69, 0x9a, 0xc6,
+                0x50, 0x07, 0x27, 0x2c, 0x32, 0xab, 0x0e, 0xde, 0xd1, 0x63, 0x1a, 0x8b, 0x60, 0x5a,
+                0x43, 0xff, 0x5b, 0xed, 0x80, 0x86, 0x07, 0x2b, 0xa1, 0xe7, 0xcc, 0x23, 0x58, 0xba,
+                0xec, 0xa1, 0x34, 0xc8, 0x25, 0xa7
+            ]
+        );
+    }
+
+    #[test]
+    fn sha384_two_blocks() {
+        // FIPS 180-4 example, SHA-384 of a 112 byte message.
+        assert_eq!(
+            hash::<48>(TWO_BLOCK_MESSAGE),
+            [
+                0x09, 0x33, 0x0c, 0x33, 0xf7, 0x11, 0x47, 0xe8, 0x3d, 0x19, 0x2f, 0xc7, 0x82, 0xcd,
+                0x1b, 0x47, 0x53, 0x11, 0x1b, 0x17, 0x3b, 0x3b, 0x05, 0xd2, 0x2f, 0xa0, 0x80, 0x86,
+                0xe3, 0xb0, 0xf7, 0x12, 0xfc, 0xc7, 0xc7, 0x1a, 0x55, 0x7e, 0x2d, 0xb9, 0x66, 0xc3,
+                0xe9, 0xfa, 0x91, 0x74, 0x60, 0x39
+            ]
+        );
+    }
+
+    #[test]
+    fn split_data() {
+        // Adding the data in pieces that don't line up with the blocks gives
+        // the same digest.
+        let data: [u8; 300] = core::array::from_fn(|i| i as u8);
+        for split in [1, 111, 112, 127, 128, 129, 256] {
+            let mut hasher = Sha512Hasher::<64>::new();
+            hasher.update(&data[..split]);
+            hasher.update(&data[split..]);
+            let mut output = [0; 64];
+            hasher.finish(&mut output);
+            assert_eq!(output, hash::<64>(&data));
+        }
+    }
+}
@@ -0,0 +1,116 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! A SHA-384 or SHA-512 engine for unit tests of capsules that use the digest
+//! HIL.
+//!
+//! `FakeSha512` hashes with `Sha512Hasher`, and issues the callback for an
+//! operation when `complete()` is called.
+
+use kernel::hil::digest;
+use kernel::utilities::cells::{MapCell, OptionalCell};
+use kernel::utilities::leasable_buffer::{SubSlice, SubSliceMut};
+use kernel::ErrorCode;
+
+use crate::sha512::Sha512Hasher;
+
+enum Pending<const L: usize> {
+    Data(SubSliceMut<'static, u8>),
+    Hash(&'static mut [u8; L]),
+}
+
+pub(crate) struct FakeSha512<const L: usize> {
+    hasher: MapCell<Sha512Hasher<L>>,
+    pending: MapCell<Pending<L>>,
+    client: OptionalCell<&'static dyn digest::ClientDataHash<L>>,
+}
+
+impl<const L: usize> FakeSha512<L> {
+    pub(crate) fn new() -> Self {
+        Self {
+            hasher: MapCell::new(Sha512Hasher::new()),
+            pending: MapCell::empty(),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    /// Complete the pending operation, returns `false` if there was none.
+    pub(crate) fn complete(&self) -> bool {
+        match self.pending.take() {
+            Some(Pending::Data(data)) => {
+                self.client.map(move |c| c.add_mut_data_done(Ok(()), data));
+            }
+            Some(Pending::Hash(digest)) => {
+                self.hasher.map(|hasher| hasher.finish(digest));
+                self.client.map(move |c| c.hash_done(Ok(()), digest));
+            }
+            None => return false,
+        }
+        true
+    }
+
+    pub(crate) fn complete_all(&self) {
+        while self.complete() {}
+    }
+}
+
+impl<const L: usize> digest::DigestData<'static, L> for FakeSha512<L> {
+    fn set_data_client(&'static self, _client: &'static dyn digest::ClientData<L>) {}
+
+    fn add_data(
+        &self,
+        data: SubSlice<'static, u8>,
+    ) -> Result<(), (ErrorCode, SubSlice<'static, u8>)> {
+        Err((ErrorCode::NOSUPPORT, data))
+    }
+
+    fn add_mut_data(
+        &self,
+        mut data: SubSliceMut<'static, u8>,
+    ) -> Result<(), (ErrorCode, SubSliceMut<'static, u8>)> {
+        if self.pending.is_some() {
+            return Err((ErrorCode::BUSY, data));
+        }
+        self.hasher.map(|hasher| hasher.update(data.as_slice()));
+        self.pending.replace(Pending::Data(data));
+        Ok(())
+    }
+
+    fn clear_data(&self) {
+        self.hasher.replace(Sha512Hasher::new());
+    }
+}
+
+impl<const L: usize> digest::DigestHash<'static, L> for FakeSha512<L> {
+    fn set_hash_client(&'static self, _client: &'static dyn digest::ClientHash<L>) {}
+
+    fn run(
+        &'static self,
+        digest: &'static mut [u8; L],
+    ) -> Result<(), (ErrorCode, &'static mut [u8; L])> {
+        if self.pending.is_some() {
+            return Err((ErrorCode::BUSY, digest));
+        }
+        self.pending.replace(Pending::Hash(digest));
+        Ok(())
+    }
+}
+
+impl<const L: usize> digest::DigestDataHash<'static, L> for FakeSha512<L> {
+    fn set_client(&'static self, client: &'static dyn digest::ClientDataHash<L>) {
+        self.client.set(client);
+    }
+}
+
+impl<const L: usize> digest::Sha384 for FakeSha512<L> {
+    fn set_mode_sha384(&self) -> Result<(), ErrorCode> {
+        Ok(())
+    }
+}
+
+impl<const L: usize> digest::Sha512 for FakeSha512<L> {
+    fn set_mode_sha512(&self) -> Result<(), ErrorCode> {
+        Ok(())
+    }
+}
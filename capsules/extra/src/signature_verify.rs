@@ -0,0 +1,409 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Signature verification for userspace.
+//!
+//! An app passes the hash of a message, the signature and the public key of
+//! the signer, and the driver reports whether the signature is valid. The
+//! signature algorithm, and how the hash is computed, is selected by the
+//! board. For Ed25519 the hash is the SHA-512 digest of the message, which
+//! apps can compute with the SHA driver, and the signature must be an
+//! Ed25519ph signature (RFC 8032 section 5.1 with an empty context). Plain
+//! Ed25519 signatures are reported as invalid.
+//!
+//! The driver loads the app's public key into the verifier for each request,
+//! so the verifier must not be shared with anything else that sets its key,
+//! such as an app credentials checker.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! let ed25519 = components::ed25519::Ed25519SoftwareComponent::new(sha512)
+//!     .finalize(components::ed25519_software_component_static!(
+//!         capsules_extra::sha512::Sha512Software<'static>
+//!     ));
+//!
+//! let signature_verify = components::signature_verify::SignatureVerifyDriverComponent::new(
+//!     board_kernel,
+//!     capsules_extra::signature_verify::DRIVER_NUM,
+//!     ed25519,
+//! )
+//! .finalize(components::signature_verify_driver_component_static!(
+//!     capsules_extra::public_key_crypto::ed25519::Ed25519Software<
+//!         'static,
+//!         capsules_extra::sha512::Sha512Software<'static>,
+//!     >,
+//!     64,
+//!     64,
+//!     32
+//! ));
+//! ```
+
+use capsules_core::driver;
+/// Syscall driver number.
+pub const DRIVER_NUM: usize = driver::NUM::SignatureVerify as usize;
+
+use kernel::errorcode::into_statuscode;
+use kernel::grant::{AllowRoCount, AllowRwCount, Grant, UpcallCount};
+use kernel::hil::public_key_crypto::keys::SetPublicKey;
+use kernel::hil::public_key_crypto::signature::{ClientVerify, SignatureVerify};
+use kernel::processbuffer::{ReadableProcessBuffer, ReadableProcessSlice};
+use kernel::syscall::{CommandReturn, SyscallDriver};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::{ErrorCode, ProcessId};
+
+/// Ids for read-only allow buffers
+mod ro_allow {
+    pub const HASH: usize = 0;
+    pub const SIGNATURE: usize = 1;
+    pub const PUBLIC_KEY: usize = 2;
+    /// The number of allow buffers the kernel stores for this grant
+    pub const COUNT: u8 = 3;
+}
+
+/// Ids for read-write allow buffers
+mod rw_allow {
+    /// The number of allow buffers the kernel stores for this grant
+    pub const COUNT: u8 = 0;
+}
+
+mod upcall {
+    pub const VERIFICATION_DONE: usize = 0;
+    /// The number of upcalls the kernel stores for this grant
+    pub const COUNT: u8 = 1;
+}
+
+#[derive(Default)]
+pub struct App {}
+
+pub struct SignatureVerifyDriver<
+    'a,
+    V: SignatureVerify<'a, HL, SL> + SetPublicKey<PKL>,
+    const HL: usize,
+    const SL: usize,
+    const PKL: usize,
+> {
+    verifier: &'a V,
+
+    apps: Grant<
+        App,
+        UpcallCount<{ upcall::COUNT }>,
+        AllowRoCount<{ ro_allow::COUNT }>,
+        AllowRwCount<{ rw_allow::COUNT }>,
+    >,
+    /// The app with a verification in progress.
+    processid: OptionalCell<ProcessId>,
+
+    hash: TakeCell<'static, [u8; HL]>,
+    signature: TakeCell<'static, [u8; SL]>,
+}
+
+impl<
+        'a,
+        V: SignatureVerify<'a, HL, SL> + SetPublicKey<PKL>,
+        const HL: usize,
+        const SL: usize,
+        const PKL: usize,
+    > SignatureVerifyDriver<'a, V, HL, SL, PKL>
+{
+    pub fn new(
+        verifier: &'a V,
+        hash: &'static mut [u8; HL],
+        signature: &'static mut [u8; SL],
+        grant: Grant<
+            App,
+            UpcallCount<{ upcall::COUNT }>,
+            AllowRoCount<{ ro_allow::COUNT }>,
+            AllowRwCount<{ rw_allow::COUNT }>,
+        >,
+    ) -> Self {
+        SignatureVerifyDriver {
+            verifier,
+            apps: grant,
+            processid: OptionalCell::empty(),
+            hash: TakeCell::new(hash),
+            signature: TakeCell::new(signature),
+        }
+    }
+
+    fn verify(&self, processid: ProcessId) -> Result<(), ErrorCode> {
+        if self.processid.is_some() {
+            return Err(ErrorCode::BUSY);
+        }
+
+        let (hash, signature) = match (self.hash.take(), self.signature.take()) {
+            (Some(hash), Some(signature)) => (hash, signature),
+            (hash, signature) => {
+                hash.map(|hash| self.hash.replace(hash));
+                signature.map(|signature| self.signature.replace(signature));
+                return Err(ErrorCode::BUSY);
+            }
+        };
+
+        let copy = |num: usize, dest: &mut [u8]| {
+            self.apps
+                .enter(processid, |_, kernel_data| {
+                    kernel_data
+                        .get_readonly_processbuffer(num)
+                        .and_then(|src| src.enter(|src| copy_allowed(src, dest)))
+                        .unwrap_or(Err(ErrorCode::RESERVE))
+                })
+                .unwrap_or_else(|err| Err(err.into()))
+        };
+        match start_verify(self.verifier, hash, signature, copy) {
+            Ok(()) => {
+                self.processid.set(processid);
+                Ok(())
+            }
+            Err((e, hash, signature)) => {
+                self.hash.replace(hash);
+                self.signature.replace(signature);
+                Err(e)
+            }
+        }
+    }
+}
+
+/// Copy an allow buffer into `dest`, which it has to fill exactly.
+fn copy_allowed(src: &ReadableProcessSlice, dest: &mut [u8]) -> Result<(), ErrorCode> {
+    if src.len() != dest.len() {
+        return Err(ErrorCode::SIZE);
+    }
+    src.copy_to_slice(dest);
+    Ok(())
+}
+
+/// Load the public key into `verifier` and start verifying. `copy` fills a
+/// buffer from the read-only allow buffer with the given number.
+fn start_verify<
+    'a,
+    V: SignatureVerify<'a, HL, SL> + SetPublicKey<PKL>,
+    const HL: usize,
+    const SL: usize,
+    const PKL: usize,
+>(
+    verifier: &V,
+    hash: &'static mut [u8; HL],
+    signature: &'static mut [u8; SL],
+    copy: impl Fn(usize, &mut [u8]) -> Result<(), ErrorCode>,
+) -> Result<(), (ErrorCode, &'static mut [u8; HL], &'static mut [u8; SL])> {
+    let mut public_key = [0; PKL];
+    let copied = copy(ro_allow::HASH, &mut hash[..])
+        .and_then(|()| copy(ro_allow::SIGNATURE, &mut signature[..]))
+        .and_then(|()| copy(ro_allow::PUBLIC_KEY, &mut public_key))
+        .and_then(|()| verifier.set_public_key(&public_key));
+    if let Err(e) = copied {
+        return Err((e, hash, signature));
+    }
+
+    verifier.verify(hash, signature)
+}
+
+/// The arguments of the upcall for the verification `result`.
+fn upcall_args(result: Result<bool, ErrorCode>) -> (usize, usize, usize) {
+    let verified = result.unwrap_or(false);
+    (into_statuscode(result.map(|_| ())), verified as usize, 0)
+}
+
+impl<
+        'a,
+        V: SignatureVerify<'a, HL, SL> + SetPublicKey<PKL>,
+        const HL: usize,
+        const SL: usize,
+        const PKL: usize,
+    > ClientVerify<HL, SL> for SignatureVerifyDriver<'a, V, HL, SL, PKL>
+{
+    fn verification_done(
+        &self,
+        result: Result<bool, ErrorCode>,
+        hash: &'static mut [u8; HL],
+        signature: &'static mut [u8; SL],
+    ) {
+        self.hash.replace(hash);
+        self.signature.replace(signature);
+
+        if let Some(processid) = self.processid.take() {
+            let _ = self.apps.enter(processid, |_, kernel_data| {
+                kernel_data
+                    .schedule_upcall(upcall::VERIFICATION_DONE, upcall_args(result))
+                    .ok();
+            });
+        }
+    }
+}
+
+impl<
+        'a,
+        V: SignatureVerify<'a, HL, SL> + SetPublicKey<PKL>,
+        const HL: usize,
+        const SL: usize,
+        const PKL: usize,
+    > SyscallDriver for SignatureVerifyDriver<'a, V, HL, SL, PKL>
+{
+    /// Control the signature verification driver.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver existence check.
+    /// - `1`: Verify the signature in the `SIGNATURE` read-only allow buffer
+    ///        over the hash in the `HASH` buffer, with the public key in the
+    ///        `PUBLIC_KEY` buffer.
+    /// - `2`: Return the length of the hash, of a signature and of a public
+    ///        key.
+    fn command(
+        &self,
+        command_num: usize,
+        _data1: usize,
+        _data2: usize,
+        processid: ProcessId,
+    ) -> CommandReturn {
+        match command_num {
+            0 => CommandReturn::success(),
+
+            1 => self.verify(processid).into(),
+
+            2 => CommandReturn::success_u32_u32_u32(HL as u32, SL as u32, PKL as u32),
+
+            _ => CommandReturn::failure(ErrorCode::NOSUPPORT),
+        }
+    }
+
+    fn allocate_grant(&self, processid: ProcessId) -> Result<(), kernel::process::Error> {
+        self.apps.enter(processid, |_, _| {})
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use core::cell::Cell;
+    use std::boxed::Box;
+
+    /// A verifier that records its inputs, and rejects an all-zero key.
+    struct FakeVerifier {
+        public_key: Cell<Option<[u8; 2]>>,
+        request: Cell<Option<([u8; 4], [u8; 8])>>,
+    }
+
+    impl SetPublicKey<2> for FakeVerifier {
+        fn set_public_key(&self, public_key: &[u8; 2]) -> Result<(), ErrorCode> {
+            if *public_key == [0; 2] {
+                return Err(ErrorCode::INVAL);
+            }
+            self.public_key.set(Some(*public_key));
+            Ok(())
+        }
+    }
+
+    impl SignatureVerify<'static, 4, 8> for FakeVerifier {
+        fn set_verify_client(&self, _client: &'static dyn ClientVerify<4, 8>) {}
+
+        fn verify(
+            &self,
+            hash: &'static mut [u8; 4],
+            signature: &'static mut [u8; 8],
+        ) -> Result<(), (ErrorCode, &'static mut [u8; 4], &'static mut [u8; 8])> {
+            if self.request.get().is_some() {
+                return Err((ErrorCode::BUSY, hash, signature));
+            }
+            self.request.set(Some((*hash, *signature)));
+            Ok(())
+        }
+    }
+
+    fn verifier() -> FakeVerifier {
+        FakeVerifier {
+            public_key: Cell::new(None),
+            request: Cell::new(None),
+        }
+    }
+
+    /// Start verifying with the allow buffers `allowed`, returning the error
+    /// if it fails.
+    fn start(verifier: &FakeVerifier, allowed: [Option<&[u8]>; 3]) -> Result<(), ErrorCode> {
+        let copy = |num: usize, dest: &mut [u8]| match allowed[num] {
+            Some(src) => copy_allowed(src.into(), dest),
+            None => Err(ErrorCode::RESERVE),
+        };
+        let hash = Box::leak(Box::new([0; 4]));
+        let signature = Box::leak(Box::new([0; 8]));
+        start_verify(verifier, hash, signature, copy).map_err(|(e, _, _)| e)
+    }
+
+    const HASH: &[u8] = &[1, 2, 3, 4];
+    const SIGNATURE: &[u8] = &[5, 6, 7, 8, 9, 10, 11, 12];
+    const PUBLIC_KEY: &[u8] = &[13, 14];
+
+    #[test]
+    fn copies_allow_buffers() {
+        let verifier = verifier();
+        assert_eq!(
+            start(&verifier, [Some(HASH), Some(SIGNATURE), Some(PUBLIC_KEY)]),
+            Ok(())
+        );
+        assert_eq!(verifier.public_key.get(), Some([13, 14]));
+        assert_eq!(
+            verifier.request.get(),
+            Some(([1, 2, 3, 4], [5, 6, 7, 8, 9, 10, 11, 12]))
+        );
+
+        // The verifier is still busy with the first request.
+        assert_eq!(
+            start(&verifier, [Some(HASH), Some(SIGNATURE), Some(PUBLIC_KEY)]),
+            Err(ErrorCode::BUSY)
+        );
+    }
+
+    #[test]
+    fn missing_allow_buffer() {
+        for missing in 0..3 {
+            let verifier = verifier();
+            let mut allowed = [Some(HASH), Some(SIGNATURE), Some(PUBLIC_KEY)];
+            allowed[missing] = None;
+            assert_eq!(start(&verifier, allowed), Err(ErrorCode::RESERVE));
+            assert_eq!(verifier.public_key.get(), None);
+            assert_eq!(verifier.request.get(), None);
+        }
+    }
+
+    #[test]
+    fn wrong_allow_buffer_length() {
+        let verifier = verifier();
+        let long_hash: &[u8] = &[1, 2, 3, 4, 5];
+        let short_signature: &[u8] = &[5, 6, 7];
+        let long_key: &[u8] = &[13, 14, 15];
+        for allowed in [
+            [Some(long_hash), Some(SIGNATURE), Some(PUBLIC_KEY)],
+            [Some(HASH), Some(short_signature), Some(PUBLIC_KEY)],
+            [Some(HASH), Some(SIGNATURE), Some(long_key)],
+            [Some(&[][..]), Some(SIGNATURE), Some(PUBLIC_KEY)],
+        ] {
+            assert_eq!(start(&verifier, allowed), Err(ErrorCode::SIZE));
+        }
+        assert_eq!(verifier.request.get(), None);
+    }
+
+    #[test]
+    fn invalid_public_key() {
+        let verifier = verifier();
+        assert_eq!(
+            start(&verifier, [Some(HASH), Some(SIGNATURE), Some(&[0, 0])]),
+            Err(ErrorCode::INVAL)
+        );
+        assert_eq!(verifier.request.get(), None);
+    }
+
+    #[test]
+    fn upcall() {
+        assert_eq!(upcall_args(Ok(true)), (0, 1, 0));
+        assert_eq!(upcall_args(Ok(false)), (0, 0, 0));
+        assert_eq!(
+            upcall_args(Err(ErrorCode::FAIL)),
+            (into_statuscode(Err(ErrorCode::FAIL)), 0, 0)
+        );
+    }
+}
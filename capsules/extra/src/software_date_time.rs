@@ -0,0 +1,477 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! A date and time clock kept in software on top of an alarm.
+//!
+//! Implements `hil::date_time::DateTime` for boards without a real-time
+//! clock, or whose clock drifts, so that the date and time driver and other
+//! capsules can read it. The time is counted in microseconds since the Unix
+//! epoch, from 1970 until it is set.
+//!
+//! Time sources such as the SNTP client correct the clock with `step`, which
+//! jumps to a new time, or `slew`, which speeds the clock up or slows it
+//! down by at most 500 ppm until the offset given is made up, so that the
+//! time never goes backwards over small corrections.
+//!
+//! The alarm fires every few seconds to fold the elapsed ticks into the
+//! time before the tick counter wraps.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! let clock = components::software_date_time::SoftwareDateTimeComponent::new(mux_alarm)
+//!     .finalize(components::software_date_time_component_static!(
+//!         nrf52840::rtc::Rtc<'static>
+//!     ));
+//! let date_time = components::date_time::DateTimeComponent::new(
+//!     board_kernel,
+//!     capsules_extra::date_time::DRIVER_NUM,
+//!     clock,
+//! )
+//! .finalize(components::date_time_component_static!(
+//!     components::software_date_time::SoftwareDateTimeComponentType<
+//!         nrf52840::rtc::Rtc<'static>,
+//!     >
+//! ));
+//! ```
+
+use core::cell::Cell;
+
+use kernel::deferred_call::{DeferredCall, DeferredCallClient};
+use kernel::hil::date_time::{DateTime, DateTimeClient, DateTimeValues, DayOfWeek, Month};
+use kernel::hil::time::{Alarm, AlarmClient, ConvertTicks, Ticks};
+use kernel::utilities::cells::OptionalCell;
+use kernel::ErrorCode;
+
+/// How often elapsed ticks are folded into the time.
+const FOLD_INTERVAL_S: u32 = 8;
+/// The fastest rate offsets are slewed in, in microseconds per second.
+const MAX_SLEW_PPM: u64 = 500;
+
+const US_PER_SECOND: u64 = 1_000_000;
+const SECONDS_PER_DAY: u64 = 86_400;
+
+const MONTHS: [Month; 12] = [
+    Month::January,
+    Month::February,
+    Month::March,
+    Month::April,
+    Month::May,
+    Month::June,
+    Month::July,
+    Month::August,
+    Month::September,
+    Month::October,
+    Month::November,
+    Month::December,
+];
+
+const DAYS_OF_WEEK: [DayOfWeek; 7] = [
+    DayOfWeek::Sunday,
+    DayOfWeek::Monday,
+    DayOfWeek::Tuesday,
+    DayOfWeek::Wednesday,
+    DayOfWeek::Thursday,
+    DayOfWeek::Friday,
+    DayOfWeek::Saturday,
+];
+
+/// The days from 1970-01-01 to a date of the proleptic Gregorian calendar,
+/// with months from 1.
+fn days_from_civil(year: u64, month: u64, day: u64) -> u64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year / 400;
+    let year_of_era = year - era * 400;
+    let month_from_march = (month + 9) % 12;
+    let day_of_year = (153 * month_from_march + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// The date, with months from 1, of a number of days since 1970-01-01.
+fn civil_from_days(days: u64) -> (u64, u64, u64) {
+    let days = days + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_from_march = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_from_march + 2) / 5 + 1;
+    let month = (month_from_march + 2) % 12 + 1;
+    let year = year_of_era + era * 400;
+    (if month <= 2 { year + 1 } else { year }, month, day)
+}
+
+/// The days in a month, from 1, of a year.
+fn days_in_month(year: u64, month: u64) -> u64 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+fn values_from_unix(seconds: u64) -> DateTimeValues {
+    let days = seconds / SECONDS_PER_DAY;
+    let time = seconds % SECONDS_PER_DAY;
+    let (year, month, day) = civil_from_days(days);
+    DateTimeValues {
+        year: year as u16,
+        month: MONTHS[month as usize - 1],
+        day: day as u8,
+        // 1970-01-01 was a Thursday.
+        day_of_week: DAYS_OF_WEEK[((days + 4) % 7) as usize],
+        hour: (time / 3600) as u8,
+        minute: (time / 60 % 60) as u8,
+        seconds: (time % 60) as u8,
+    }
+}
+
+fn unix_from_values(values: &DateTimeValues) -> Option<u64> {
+    let month = MONTHS.iter().position(|&m| m == values.month)? as u64 + 1;
+    if values.year < 1970
+        || !(1..=days_in_month(values.year as u64, month)).contains(&(values.day as u64))
+        || values.hour > 23
+        || values.minute > 59
+        || values.seconds > 59
+    {
+        return None;
+    }
+    let days = days_from_civil(values.year as u64, month, values.day as u64);
+    Some(
+        days * SECONDS_PER_DAY
+            + values.hour as u64 * 3600
+            + values.minute as u64 * 60
+            + values.seconds as u64,
+    )
+}
+
+#[derive(Clone, Copy)]
+enum Request {
+    Get,
+    Set,
+}
+
+pub struct SoftwareDateTime<'a, A: Alarm<'a>> {
+    alarm: &'a A,
+    client: OptionalCell<&'a dyn DateTimeClient>,
+    deferred_call: DeferredCall,
+    request: OptionalCell<Request>,
+    /// The time at `reference`, in microseconds since the Unix epoch.
+    base_us: Cell<u64>,
+    reference: Cell<A::Ticks>,
+    /// The part of the last slewed offset not made up yet.
+    slew_us: Cell<i64>,
+}
+
+impl<'a, A: Alarm<'a>> SoftwareDateTime<'a, A> {
+    pub fn new(alarm: &'a A) -> Self {
+        SoftwareDateTime {
+            alarm,
+            client: OptionalCell::empty(),
+            deferred_call: DeferredCall::new(),
+            request: OptionalCell::empty(),
+            base_us: Cell::new(0),
+            reference: Cell::new(A::Ticks::from(0)),
+            slew_us: Cell::new(0),
+        }
+    }
+
+    /// Start counting from the Unix epoch.
+    pub fn init(&self) {
+        self.reference.set(self.alarm.now());
+        self.schedule();
+    }
+
+    /// The current time, in microseconds since the Unix epoch.
+    pub fn now_us(&self) -> u64 {
+        let (elapsed, slewed) = self.elapsed(self.alarm.now());
+        self.base_us
+            .get()
+            .saturating_add_signed(elapsed as i64 + slewed)
+    }
+
+    /// Set the time to `unix_us` microseconds since the Unix epoch,
+    /// dropping the correction being slewed in.
+    pub fn step(&self, unix_us: u64) {
+        self.reference.set(self.alarm.now());
+        self.base_us.set(unix_us);
+        self.slew_us.set(0);
+    }
+
+    /// Correct the time by `offset_us` microseconds, gradually. Replaces
+    /// the correction being slewed in, as offsets are measured against the
+    /// time already corrected.
+    pub fn slew(&self, offset_us: i64) {
+        self.fold();
+        self.slew_us.set(offset_us);
+    }
+
+    /// The microseconds elapsed from `reference` to `now`, and the part of
+    /// the slew made up in them.
+    fn elapsed(&self, now: A::Ticks) -> (u64, i64) {
+        let ticks = now.wrapping_sub(self.reference.get());
+        let elapsed = self.alarm.ticks_to_us(ticks) as u64;
+        let max = (elapsed * MAX_SLEW_PPM / US_PER_SECOND) as i64;
+        (elapsed, self.slew_us.get().clamp(-max, max))
+    }
+
+    /// Move `reference` to now.
+    fn fold(&self) {
+        let now = self.alarm.now();
+        let (elapsed, slewed) = self.elapsed(now);
+        self.base_us.set(
+            self.base_us
+                .get()
+                .saturating_add_signed(elapsed as i64 + slewed),
+        );
+        self.slew_us.set(self.slew_us.get() - slewed);
+        self.reference.set(now);
+    }
+
+    fn schedule(&self) {
+        self.alarm.set_alarm(
+            self.reference.get(),
+            self.alarm.ticks_from_seconds(FOLD_INTERVAL_S),
+        );
+    }
+}
+
+impl<'a, A: Alarm<'a>> AlarmClient for SoftwareDateTime<'a, A> {
+    fn alarm(&self) {
+        self.fold();
+        self.schedule();
+    }
+}
+
+impl<'a, A: Alarm<'a>> DateTime<'a> for SoftwareDateTime<'a, A> {
+    fn get_date_time(&self) -> Result<(), ErrorCode> {
+        if self.request.is_some() {
+            return Err(ErrorCode::BUSY);
+        }
+        self.request.set(Request::Get);
+        self.deferred_call.set();
+        Ok(())
+    }
+
+    fn set_date_time(&self, date_time: DateTimeValues) -> Result<(), ErrorCode> {
+        if self.request.is_some() {
+            return Err(ErrorCode::BUSY);
+        }
+        let seconds = unix_from_values(&date_time).ok_or(ErrorCode::INVAL)?;
+        self.step(seconds * US_PER_SECOND);
+        self.request.set(Request::Set);
+        self.deferred_call.set();
+        Ok(())
+    }
+
+    fn set_client(&self, client: &'a dyn DateTimeClient) {
+        self.client.set(client);
+    }
+}
+
+impl<'a, A: Alarm<'a>> DeferredCallClient for SoftwareDateTime<'a, A> {
+    fn handle_deferred_call(&self) {
+        let Some(request) = self.request.take() else {
+            return;
+        };
+        self.client.map(|client| match request {
+            Request::Get => {
+                let seconds = self.now_us() / US_PER_SECOND;
+                client.get_date_time_done(Ok(values_from_unix(seconds)));
+            }
+            Request::Set => client.set_date_time_done(Ok(())),
+        });
+    }
+
+    fn register(&'static self) {
+        self.deferred_call.register(self);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::alarm_fake::FakeAlarm;
+    use kernel::hil::time::Freq1MHz;
+    use std::boxed::Box;
+
+    fn values(
+        year: u16,
+        month: Month,
+        day: u8,
+        hour: u8,
+        minute: u8,
+        seconds: u8,
+    ) -> DateTimeValues {
+        DateTimeValues {
+            year,
+            month,
+            day,
+            // Ignored when converting to Unix time.
+            day_of_week: DayOfWeek::Sunday,
+            hour,
+            minute,
+            seconds,
+        }
+    }
+
+    /// The time `seconds` after `from`.
+    fn after(from: &DateTimeValues, seconds: u64) -> DateTimeValues {
+        values_from_unix(unix_from_values(from).unwrap() + seconds)
+    }
+
+    fn assert_date(
+        values: &DateTimeValues,
+        year: u16,
+        month: Month,
+        day: u8,
+        day_of_week: DayOfWeek,
+    ) {
+        assert_eq!((values.year, values.month, values.day), (year, month, day));
+        assert_eq!(values.day_of_week, day_of_week);
+    }
+
+    #[test]
+    fn unix_time() {
+        let epoch = values_from_unix(0);
+        assert_date(&epoch, 1970, Month::January, 1, DayOfWeek::Thursday);
+        assert_eq!((epoch.hour, epoch.minute, epoch.seconds), (0, 0, 0));
+        // Computed with Python's calendar.timegm.
+        let leap_eve = values(2024, Month::February, 28, 23, 59, 59);
+        assert_eq!(unix_from_values(&leap_eve), Some(1_709_164_799));
+        let end = values_from_unix(946_684_799);
+        assert_date(&end, 1999, Month::December, 31, DayOfWeek::Friday);
+        assert_eq!((end.hour, end.minute, end.seconds), (23, 59, 59));
+    }
+
+    #[test]
+    fn rollover() {
+        let end = values(1999, Month::December, 31, 23, 59, 59);
+        let next = after(&end, 1);
+        assert_date(&next, 2000, Month::January, 1, DayOfWeek::Saturday);
+        assert_eq!((next.hour, next.minute, next.seconds), (0, 0, 0));
+        let next = after(&values(2023, Month::April, 30, 23, 59, 59), 1);
+        assert_date(&next, 2023, Month::May, 1, DayOfWeek::Monday);
+        let next = after(&values(2023, Month::July, 14, 9, 59, 59), 1);
+        assert_eq!(
+            (next.day, next.hour, next.minute, next.seconds),
+            (14, 10, 0, 0)
+        );
+    }
+
+    #[test]
+    fn leap_years() {
+        let leap_eve = values(2024, Month::February, 28, 23, 59, 59);
+        assert_date(
+            &after(&leap_eve, 1),
+            2024,
+            Month::February,
+            29,
+            DayOfWeek::Thursday,
+        );
+        let leap_day = values(2024, Month::February, 29, 23, 59, 59);
+        assert_date(
+            &after(&leap_day, 1),
+            2024,
+            Month::March,
+            1,
+            DayOfWeek::Friday,
+        );
+        let next = after(&values(2023, Month::February, 28, 23, 59, 59), 1);
+        assert_date(&next, 2023, Month::March, 1, DayOfWeek::Wednesday);
+        // Centuries are leap years only every 400 years.
+        let next = after(
+            &values(2000, Month::February, 28, 12, 0, 0),
+            SECONDS_PER_DAY,
+        );
+        assert_date(&next, 2000, Month::February, 29, DayOfWeek::Tuesday);
+        let next = after(&values(2100, Month::February, 28, 0, 0, 0), SECONDS_PER_DAY);
+        assert_date(&next, 2100, Month::March, 1, DayOfWeek::Monday);
+        let next = after(&values(2024, Month::December, 31, 0, 0, 0), SECONDS_PER_DAY);
+        assert_date(&next, 2025, Month::January, 1, DayOfWeek::Wednesday);
+    }
+
+    #[test]
+    fn invalid_values() {
+        assert!(unix_from_values(&values(2024, Month::February, 29, 0, 0, 0)).is_some());
+        assert_eq!(
+            unix_from_values(&values(2023, Month::February, 29, 0, 0, 0)),
+            None
+        );
+        assert_eq!(
+            unix_from_values(&values(2100, Month::February, 29, 0, 0, 0)),
+            None
+        );
+        assert_eq!(
+            unix_from_values(&values(2024, Month::February, 30, 0, 0, 0)),
+            None
+        );
+        assert_eq!(
+            unix_from_values(&values(2024, Month::April, 31, 0, 0, 0)),
+            None
+        );
+        assert_eq!(
+            unix_from_values(&values(2024, Month::May, 0, 0, 0, 0)),
+            None
+        );
+        assert_eq!(
+            unix_from_values(&values(1969, Month::December, 31, 0, 0, 0)),
+            None
+        );
+        assert_eq!(
+            unix_from_values(&values(2024, Month::May, 1, 24, 0, 0)),
+            None
+        );
+        assert_eq!(
+            unix_from_values(&values(2024, Month::May, 1, 0, 60, 0)),
+            None
+        );
+        assert_eq!(
+            unix_from_values(&values(2024, Month::May, 1, 0, 0, 60)),
+            None
+        );
+    }
+
+    #[test]
+    fn step_and_slew() {
+        let alarm = Box::leak(Box::new(FakeAlarm::<Freq1MHz>::new()));
+        let clock = Box::leak(Box::new(SoftwareDateTime::new(&*alarm)));
+        alarm.set_alarm_client(clock);
+        clock.init();
+        assert_eq!(clock.now_us(), 0);
+
+        // Folding across midnight of a leap day.
+        let start_us = 1_709_164_799 * US_PER_SECOND;
+        clock.step(start_us);
+        assert!(alarm.fire());
+        let now_us = start_us + FOLD_INTERVAL_S as u64 * US_PER_SECOND;
+        assert_eq!(clock.now_us(), now_us);
+        let now = values_from_unix(clock.now_us() / US_PER_SECOND);
+        assert_date(&now, 2024, Month::February, 29, DayOfWeek::Thursday);
+        assert_eq!((now.hour, now.minute, now.seconds), (0, 0, 7));
+
+        // Offsets are made up at 500 ppm, then the clock runs at its rate.
+        clock.slew(6_000);
+        assert!(alarm.fire());
+        let now_us = now_us + 8_004_000;
+        assert_eq!(clock.now_us(), now_us);
+        assert!(alarm.fire());
+        let now_us = now_us + 8_002_000;
+        assert_eq!(clock.now_us(), now_us);
+        assert!(alarm.fire());
+        let now_us = now_us + 8_000_000;
+        assert_eq!(clock.now_us(), now_us);
+
+        // Slowing down never goes backwards.
+        clock.slew(-1_000_000);
+        assert!(alarm.fire());
+        assert_eq!(clock.now_us(), now_us + 7_996_000);
+    }
+}
@@ -3,6 +3,20 @@
 // Copyright Tock Contributors 2022.
 
 //! AES.
+//!
+//! The key is passed in the `KEY` read-only allow buffer, its length selects
+//! between AES-128, AES-192 and AES-256. The key length has to be supported by
+//! the underlying AES implementation.
+//!
+//! Besides the AES modes, the driver provides ChaCha20-Poly1305 (algorithm 5)
+//! through a separate `ChaCha20Poly1305` implementation. It uses the same
+//! buffers and offsets as AES GCM: the key must be 32 bytes long, the first 12
+//! bytes of the `IV` buffer are used as the nonce and the tag follows the
+//! message.
+//!
+//! If the board provides a keystore, apps can use a key held by the kernel
+//! instead of the `KEY` buffer by passing its handle with command 9. The key
+//! must have the `KeyUsage::Aes` usage.
 
 use capsules_core::driver;
 /// Syscall driver number.
@@ -11,9 +25,11 @@ pub const DRIVER_NUM: usize = driver::NUM::Aes as usize;
 use core::cell::Cell;
 
 use kernel::grant::{AllowRoCount, AllowRwCount, Grant, UpcallCount};
+use kernel::hil::keystore::{KeyHandle, KeyStore, KeyUsage};
 use kernel::hil::symmetric_encryption::{
-    AES128Ctr, CCMClient, Client, GCMClient, AES128, AES128CBC, AES128CCM, AES128ECB, AES128GCM,
-    AES128_BLOCK_SIZE,
+    AES128Ctr, CCMClient, ChaCha20Poly1305, ChaCha20Poly1305Client, Client, GCMClient, AES128,
+    AES128CBC, AES128CCM, AES128ECB, AES128GCM, AES128_BLOCK_SIZE, AES_MAX_KEY_SIZE,
+    CHACHA20_POLY1305_NONCE_SIZE,
 };
 use kernel::processbuffer::{ReadableProcessBuffer, WriteableProcessBuffer};
 use kernel::syscall::{CommandReturn, SyscallDriver};
@@ -36,8 +52,13 @@ mod rw_allow {
     pub const COUNT: u8 = 1;
 }
 
-pub struct AesDriver<'a, A: AES128<'a> + AES128CCM<'static> + AES128GCM<'static>> {
+pub struct AesDriver<
+    'a,
+    A: AES128<'a> + AES128CCM<'static> + AES128GCM<'static>,
+    C: ChaCha20Poly1305<'static>,
+> {
     aes: &'a A,
+    chacha: &'a C,
 
     active: Cell<bool>,
 
@@ -52,6 +73,8 @@ pub struct AesDriver<'a, A: AES128<'a> + AES128CCM<'static> + AES128GCM<'static>
     source_buffer: TakeCell<'static, [u8]>,
     data_copied: Cell<usize>,
     dest_buffer: TakeCell<'static, [u8]>,
+    key_buffer: TakeCell<'static, [u8]>,
+    keystore: OptionalCell<&'a dyn KeyStore>,
 }
 
 impl<
@@ -61,27 +84,49 @@ impl<
             + AES128ECB
             + AES128CCM<'static>
             + AES128GCM<'static>,
-    > AesDriver<'static, A>
+        C: ChaCha20Poly1305<'static>,
+    > AesDriver<'static, A, C>
 {
     pub fn new(
         aes: &'static A,
+        chacha: &'static C,
         source_buffer: &'static mut [u8],
         dest_buffer: &'static mut [u8],
+        key_buffer: &'static mut [u8; AES_MAX_KEY_SIZE],
         grant: Grant<
             App,
             UpcallCount<1>,
             AllowRoCount<{ ro_allow::COUNT }>,
             AllowRwCount<{ rw_allow::COUNT }>,
         >,
-    ) -> AesDriver<'static, A> {
+    ) -> AesDriver<'static, A, C> {
         AesDriver {
             aes,
+            chacha,
             active: Cell::new(false),
             apps: grant,
             processid: OptionalCell::empty(),
             source_buffer: TakeCell::new(source_buffer),
             data_copied: Cell::new(0),
             dest_buffer: TakeCell::new(dest_buffer),
+            key_buffer: TakeCell::new(key_buffer),
+            keystore: OptionalCell::empty(),
+        }
+    }
+
+    /// Use `keystore` for apps that pass a key handle instead of a key.
+    pub fn set_keystore(&self, keystore: &'static dyn KeyStore) {
+        self.keystore.set(keystore);
+    }
+
+    fn set_key(&self, op: &AesOperation, key: &[u8]) -> Result<(), ErrorCode> {
+        match op {
+            AesOperation::AES128Ctr(_)
+            | AesOperation::AES128CBC(_)
+            | AesOperation::AES128ECB(_) => AES128::set_key(self.aes, key),
+            AesOperation::AES128CCM(_) => AES128CCM::set_key(self.aes, key),
+            AesOperation::AES128GCM(_) => AES128GCM::set_key(self.aes, key),
+            AesOperation::ChaCha20Poly1305(_) => ChaCha20Poly1305::set_key(self.chacha, key),
         }
     }
 
@@ -102,50 +147,39 @@ impl<
                         }
                         Some(AesOperation::AES128CCM(_encrypt)) => {}
                         Some(AesOperation::AES128GCM(_encrypt)) => {}
+                        Some(AesOperation::ChaCha20Poly1305(_encrypt)) => {}
                         _ => return Err(ErrorCode::INVAL),
                     }
 
-                    kernel_data
-                        .get_readonly_processbuffer(ro_allow::KEY)
-                        .and_then(|key| {
-                            key.enter(|key| {
-                                let mut static_buffer_len = 0;
-                                self.source_buffer.map_or(Err(ErrorCode::NOMEM), |buf| {
-                                    // Determine the size of the static buffer we have
-                                    static_buffer_len = buf.len();
-
-                                    if static_buffer_len > key.len() {
-                                        static_buffer_len = key.len()
-                                    }
-
-                                    // Copy the data into the static buffer
-                                    key[..static_buffer_len]
-                                        .copy_to_slice(&mut buf[..static_buffer_len]);
-
-                                    if let Some(op) = app.aes_operation.as_ref() {
-                                        match op {
-                                            AesOperation::AES128Ctr(_)
-                                            | AesOperation::AES128CBC(_)
-                                            | AesOperation::AES128ECB(_) => {
-                                                AES128::set_key(self.aes, buf)?;
-                                                Ok(())
-                                            }
-                                            AesOperation::AES128CCM(_) => {
-                                                AES128CCM::set_key(self.aes, buf)?;
-                                                Ok(())
-                                            }
-                                            AesOperation::AES128GCM(_) => {
-                                                AES128GCM::set_key(self.aes, buf)?;
-                                                Ok(())
-                                            }
+                    let op = app.aes_operation.as_ref().ok_or(ErrorCode::FAIL)?;
+                    match (app.key_handle, self.keystore.get()) {
+                        (Some(handle), Some(keystore)) => keystore.with_key(
+                            handle,
+                            processid.short_app_id(),
+                            KeyUsage::Aes,
+                            &mut |key| self.set_key(op, key),
+                        )?,
+                        (Some(_), None) => return Err(ErrorCode::NOSUPPORT),
+                        (None, _) => kernel_data
+                            .get_readonly_processbuffer(ro_allow::KEY)
+                            .and_then(|key| {
+                                key.enter(|key| {
+                                    self.key_buffer.map_or(Err(ErrorCode::NOMEM), |buf| {
+                                        // The length of the key selects the key
+                                        // size, so it has to fit exactly.
+                                        let key_len = key.len();
+                                        if key_len > buf.len() {
+                                            return Err(ErrorCode::INVAL);
                                         }
-                                    } else {
-                                        Err(ErrorCode::FAIL)
-                                    }
+
+                                        // Copy the key into the static buffer
+                                        key.copy_to_slice(&mut buf[..key_len]);
+                                        self.set_key(op, &buf[..key_len])
+                                    })
                                 })
                             })
-                        })
-                        .unwrap_or(Err(ErrorCode::RESERVE))?;
+                            .unwrap_or(Err(ErrorCode::RESERVE))?,
+                    }
 
                     kernel_data
                         .get_readonly_processbuffer(ro_allow::IV)
@@ -180,6 +214,13 @@ impl<
                                                 AES128GCM::set_iv(self.aes, &buf[0..13])?;
                                                 Ok(())
                                             }
+                                            AesOperation::ChaCha20Poly1305(_) => {
+                                                ChaCha20Poly1305::set_nonce(
+                                                    self.chacha,
+                                                    &buf[0..CHACHA20_POLY1305_NONCE_SIZE],
+                                                )?;
+                                                Ok(())
+                                            }
                                         }
                                     } else {
                                         Err(ErrorCode::FAIL)
@@ -243,7 +284,8 @@ impl<
                                                 },
                                             )?;
                                         }
-                                        AesOperation::AES128GCM(_) => {
+                                        AesOperation::AES128GCM(_)
+                                        | AesOperation::ChaCha20Poly1305(_) => {
                                             self.dest_buffer.map_or(
                                                 Err(ErrorCode::NOMEM),
                                                 |buf| {
@@ -363,6 +405,22 @@ impl<
                     return Err(ErrorCode::FAIL);
                 }
             }
+            AesOperation::ChaCha20Poly1305(encrypting) => {
+                if let Some(buf) = self.dest_buffer.take() {
+                    if let Err((e, dest)) =
+                        ChaCha20Poly1305::crypt(self.chacha, buf, aoff, moff, mlen, *encrypting)
+                    {
+                        // Error, clear the appid and data
+                        self.aes.disable();
+                        self.processid.clear();
+                        self.dest_buffer.replace(dest);
+
+                        return Err(e);
+                    }
+                } else {
+                    return Err(ErrorCode::FAIL);
+                }
+            }
         }
 
         Ok(())
@@ -399,7 +457,8 @@ impl<
             + AES128ECB
             + AES128CCM<'static>
             + AES128GCM<'static>,
-    > Client<'static> for AesDriver<'static, A>
+        C: ChaCha20Poly1305<'static>,
+    > Client<'static> for AesDriver<'static, A, C>
 {
     fn crypt_done(&'a self, source: Option<&'static mut [u8]>, destination: &'static mut [u8]) {
         if let Some(source_buf) = source {
@@ -556,7 +615,8 @@ impl<
             + AES128ECB
             + AES128CCM<'static>
             + AES128GCM<'static>,
-    > CCMClient for AesDriver<'static, A>
+        C: ChaCha20Poly1305<'static>,
+    > CCMClient for AesDriver<'static, A, C>
 {
     fn crypt_done(&self, buf: &'static mut [u8], res: Result<(), ErrorCode>, tag_is_valid: bool) {
         self.dest_buffer.replace(buf);
@@ -633,7 +693,8 @@ impl<
             + AES128ECB
             + AES128CCM<'static>
             + AES128GCM<'static>,
-    > GCMClient for AesDriver<'static, A>
+        C: ChaCha20Poly1305<'static>,
+    > GCMClient for AesDriver<'static, A, C>
 {
     fn crypt_done(&self, buf: &'static mut [u8], res: Result<(), ErrorCode>, tag_is_valid: bool) {
         self.dest_buffer.replace(buf);
@@ -710,7 +771,25 @@ impl<
             + AES128ECB
             + AES128CCM<'static>
             + AES128GCM<'static>,
-    > SyscallDriver for AesDriver<'static, A>
+        C: ChaCha20Poly1305<'static>,
+    > ChaCha20Poly1305Client for AesDriver<'static, A, C>
+{
+    fn crypt_done(&self, buf: &'static mut [u8], res: Result<(), ErrorCode>, tag_is_valid: bool) {
+        // ChaCha20-Poly1305 uses the same buffer layout as AES GCM, so the
+        // result is reported to the app in the same way.
+        GCMClient::crypt_done(self, buf, res, tag_is_valid);
+    }
+}
+
+impl<
+        A: AES128<'static>
+            + AES128Ctr
+            + AES128CBC
+            + AES128ECB
+            + AES128CCM<'static>
+            + AES128GCM<'static>,
+        C: ChaCha20Poly1305<'static>,
+    > SyscallDriver for AesDriver<'static, A, C>
 {
     fn command(
         &self,
@@ -812,6 +891,10 @@ impl<
                             app.aes_operation = Some(AesOperation::AES128GCM(data2 != 0));
                             CommandReturn::success()
                         }
+                        5 => {
+                            app.aes_operation = Some(AesOperation::ChaCha20Poly1305(data2 != 0));
+                            CommandReturn::success()
+                        }
                         _ => CommandReturn::failure(ErrorCode::NOSUPPORT),
                     },
 
@@ -938,6 +1021,25 @@ impl<
                         CommandReturn::success()
                     }
 
+                    // Use the keystore key with handle `data1` instead of
+                    // the `KEY` buffer
+                    // This will not trigger a callback and will not process any data from userspace
+                    9 => {
+                        if self.keystore.is_some() {
+                            app.key_handle = Some(KeyHandle::new(data1 as u32));
+                            CommandReturn::success()
+                        } else {
+                            CommandReturn::failure(ErrorCode::NOSUPPORT)
+                        }
+                    }
+
+                    // Use the key in the `KEY` buffer again
+                    // This will not trigger a callback and will not process any data from userspace
+                    10 => {
+                        app.key_handle = None;
+                        CommandReturn::success()
+                    }
+
                     // default
                     _ => CommandReturn::failure(ErrorCode::NOSUPPORT),
                 }
@@ -967,12 +1069,15 @@ enum AesOperation {
     AES128ECB(bool),
     AES128CCM(bool),
     AES128GCM(bool),
+    ChaCha20Poly1305(bool),
 }
 
 #[derive(Default)]
 pub struct App {
     pending_run_app: Option<ProcessId>,
     aes_operation: Option<AesOperation>,
+    /// Use this keystore key instead of the `KEY` buffer.
+    key_handle: Option<KeyHandle>,
 
     aoff: Cell<usize>,
     moff: Cell<usize>,
@@ -0,0 +1,530 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Software implementation of AES.
+//!
+//! Provides `AES128` with ECB, CBC and CTR modes, and 128, 192 and 256-bit
+//! keys, for chips without an AES peripheral, so that `VirtualAES128CCM`,
+//! `Aes128Gcm` and the userspace AES driver can be used on any board.
+//!
+//! The implementation avoids secret dependent table lookups and branches.
+//! Instead of using an S-box table, `SubBytes` computes the multiplicative
+//! inverse in GF(2^8) followed by the affine transformation. This makes it
+//! constant-time, but slow: encrypting a block takes in the order of ten
+//! thousand simple operations.
+//!
+//! All of the work is done in `crypt()`, the result is returned to the client
+//! from a deferred call.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! let aes = components::aes::AesSoftwareComponent::new()
+//!     .finalize(components::aes_software_component_static!());
+//!
+//! let aes_mux = static_init!(
+//!     MuxAES128CCM<'static, Aes128Software<'static>>,
+//!     MuxAES128CCM::new(aes)
+//! );
+//! aes.set_client(aes_mux);
+//! ```
+
+use core::cell::Cell;
+
+use kernel::deferred_call::{DeferredCall, DeferredCallClient};
+use kernel::hil::symmetric_encryption::{
+    aes_key_size_valid, AES128Ctr, Client, AES128, AES128CBC, AES128ECB, AES128_BLOCK_SIZE,
+};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::ErrorCode;
+
+/// Number of rounds for AES-256, the most used by any key length.
+const MAX_ROUNDS: usize = 14;
+
+type Block = [u8; AES128_BLOCK_SIZE];
+
+#[derive(Clone, Copy, PartialEq)]
+enum Mode {
+    Ecb,
+    Cbc,
+    Ctr,
+}
+
+/// Multiply two elements of GF(2^8) using the AES polynomial.
+fn gf_mul(mut a: u8, mut b: u8) -> u8 {
+    let mut p = 0;
+    for _ in 0..8 {
+        p ^= a & (b & 1).wrapping_neg();
+        let carry = (a >> 7).wrapping_neg();
+        a = (a << 1) ^ (0x1b & carry);
+        b >>= 1;
+    }
+    p
+}
+
+/// Multiply by `x` in GF(2^8).
+fn xtime(a: u8) -> u8 {
+    (a << 1) ^ (0x1b & (a >> 7).wrapping_neg())
+}
+
+/// Calculate `a^254`, which is the multiplicative inverse of `a` (and maps 0
+/// to 0).
+fn gf_inv(a: u8) -> u8 {
+    let a2 = gf_mul(a, a);
+    let a3 = gf_mul(a2, a);
+    let a6 = gf_mul(a3, a3);
+    let a12 = gf_mul(a6, a6);
+    let a15 = gf_mul(a12, a3);
+    let a30 = gf_mul(a15, a15);
+    let a60 = gf_mul(a30, a30);
+    let a120 = gf_mul(a60, a60);
+    let a240 = gf_mul(a120, a120);
+    let a252 = gf_mul(a240, a12);
+    gf_mul(a252, a2)
+}
+
+fn sub_byte(a: u8) -> u8 {
+    let b = gf_inv(a);
+    b ^ b.rotate_left(1) ^ b.rotate_left(2) ^ b.rotate_left(3) ^ b.rotate_left(4) ^ 0x63
+}
+
+fn inv_sub_byte(a: u8) -> u8 {
+    gf_inv(a.rotate_left(1) ^ a.rotate_left(3) ^ a.rotate_left(6) ^ 0x05)
+}
+
+fn xor_block(block: &mut Block, other: &Block) {
+    block
+        .iter_mut()
+        .zip(other.iter())
+        .for_each(|(b, o)| *b ^= o);
+}
+
+fn shift_rows(state: &mut Block) {
+    let s = *state;
+    for c in 0..4 {
+        for r in 0..4 {
+            state[4 * c + r] = s[4 * ((c + r) % 4) + r];
+        }
+    }
+}
+
+fn inv_shift_rows(state: &mut Block) {
+    let s = *state;
+    for c in 0..4 {
+        for r in 0..4 {
+            state[4 * ((c + r) % 4) + r] = s[4 * c + r];
+        }
+    }
+}
+
+fn mix_columns(state: &mut Block) {
+    for column in state.chunks_exact_mut(4) {
+        let [a0, a1, a2, a3] = [column[0], column[1], column[2], column[3]];
+        let all = a0 ^ a1 ^ a2 ^ a3;
+        column[0] ^= all ^ xtime(a0 ^ a1);
+        column[1] ^= all ^ xtime(a1 ^ a2);
+        column[2] ^= all ^ xtime(a2 ^ a3);
+        column[3] ^= all ^ xtime(a3 ^ a0);
+    }
+}
+
+fn inv_mix_columns(state: &mut Block) {
+    for column in state.chunks_exact_mut(4) {
+        let a = [column[0], column[1], column[2], column[3]];
+        for (i, c) in column.iter_mut().enumerate() {
+            *c = gf_mul(a[i], 0x0e)
+                ^ gf_mul(a[(i + 1) % 4], 0x0b)
+                ^ gf_mul(a[(i + 2) % 4], 0x0d)
+                ^ gf_mul(a[(i + 3) % 4], 0x09);
+        }
+    }
+}
+
+/// Expand `key` into the round keys, returning them and the number of rounds.
+///
+/// `key` must be 16, 24 or 32 bytes long.
+fn expand_key(key: &[u8]) -> ([Block; MAX_ROUNDS + 1], usize) {
+    let key_words = key.len() / 4;
+    let rounds = key_words + 6;
+
+    let mut words = [[0; 4]; 4 * (MAX_ROUNDS + 1)];
+    for (word, k) in words.iter_mut().zip(key.chunks_exact(4)) {
+        word.copy_from_slice(k);
+    }
+
+    let mut rcon = 1;
+    for i in key_words..4 * (rounds + 1) {
+        let mut temp = words[i - 1];
+        if i % key_words == 0 {
+            temp = [
+                sub_byte(temp[1]) ^ rcon,
+                sub_byte(temp[2]),
+                sub_byte(temp[3]),
+                sub_byte(temp[0]),
+            ];
+            rcon = xtime(rcon);
+        } else if key_words > 6 && i % key_words == 4 {
+            temp = temp.map(sub_byte);
+        }
+
+        for j in 0..4 {
+            words[i][j] = words[i - key_words][j] ^ temp[j];
+        }
+    }
+
+    let mut round_keys = [[0; AES128_BLOCK_SIZE]; MAX_ROUNDS + 1];
+    for (round_key, round_words) in round_keys.iter_mut().zip(words.chunks_exact(4)) {
+        for (k, word) in round_key.chunks_exact_mut(4).zip(round_words) {
+            k.copy_from_slice(word);
+        }
+    }
+
+    (round_keys, rounds)
+}
+
+/// Encrypt `state` with the `rounds + 1` round keys in `round_keys`.
+fn encrypt_block(round_keys: &[Block], rounds: usize, state: &mut Block) {
+    xor_block(state, &round_keys[0]);
+    for (round, round_key) in round_keys.iter().enumerate().take(rounds + 1).skip(1) {
+        state.iter_mut().for_each(|b| *b = sub_byte(*b));
+        shift_rows(state);
+        if round != rounds {
+            mix_columns(state);
+        }
+        xor_block(state, round_key);
+    }
+}
+
+/// Decrypt `state` with the `rounds + 1` round keys in `round_keys`.
+fn decrypt_block(round_keys: &[Block], rounds: usize, state: &mut Block) {
+    xor_block(state, &round_keys[rounds]);
+    for (round, round_key) in round_keys.iter().enumerate().take(rounds).rev() {
+        inv_shift_rows(state);
+        state.iter_mut().for_each(|b| *b = inv_sub_byte(*b));
+        xor_block(state, round_key);
+        if round != 0 {
+            inv_mix_columns(state);
+        }
+    }
+}
+
+/// Software AES engine.
+pub struct Aes128Software<'a> {
+    client: OptionalCell<&'a dyn Client<'a>>,
+    deferred_call: DeferredCall,
+
+    round_keys: Cell<[Block; MAX_ROUNDS + 1]>,
+    rounds: Cell<usize>,
+    /// The IV set by `set_iv()`, restored by `start_message()`.
+    iv: Cell<Block>,
+    /// The chaining value for CBC, or the counter for CTR.
+    chain: Cell<Block>,
+    mode: Cell<Mode>,
+    encrypting: Cell<bool>,
+
+    source: TakeCell<'static, [u8]>,
+    dest: TakeCell<'static, [u8]>,
+}
+
+impl<'a> Aes128Software<'a> {
+    pub fn new() -> Self {
+        Self {
+            client: OptionalCell::empty(),
+            deferred_call: DeferredCall::new(),
+
+            round_keys: Cell::new([[0; AES128_BLOCK_SIZE]; MAX_ROUNDS + 1]),
+            rounds: Cell::new(0),
+            iv: Cell::new([0; AES128_BLOCK_SIZE]),
+            chain: Cell::new([0; AES128_BLOCK_SIZE]),
+            mode: Cell::new(Mode::Ecb),
+            encrypting: Cell::new(true),
+
+            source: TakeCell::empty(),
+            dest: TakeCell::empty(),
+        }
+    }
+
+    /// Encrypt or decrypt a single block in the current mode.
+    fn crypt_block(&self, block: &mut Block) {
+        let round_keys = self.round_keys.get();
+        let rounds = self.rounds.get();
+        let mut chain = self.chain.get();
+
+        match (self.mode.get(), self.encrypting.get()) {
+            (Mode::Ecb, true) => encrypt_block(&round_keys, rounds, block),
+            (Mode::Ecb, false) => decrypt_block(&round_keys, rounds, block),
+            (Mode::Cbc, true) => {
+                xor_block(block, &chain);
+                encrypt_block(&round_keys, rounds, block);
+                chain = *block;
+            }
+            (Mode::Cbc, false) => {
+                let ciphertext = *block;
+                decrypt_block(&round_keys, rounds, block);
+                xor_block(block, &chain);
+                chain = ciphertext;
+            }
+            (Mode::Ctr, _) => {
+                let mut keystream = chain;
+                encrypt_block(&round_keys, rounds, &mut keystream);
+                xor_block(block, &keystream);
+
+                // Increment the big-endian counter without branching on it.
+                let mut carry = 1u16;
+                for b in chain.iter_mut().rev() {
+                    let sum = *b as u16 + carry;
+                    *b = sum as u8;
+                    carry = sum >> 8;
+                }
+            }
+        }
+
+        self.chain.set(chain);
+    }
+
+    /// Process the blocks in `dest[start_index..stop_index]`, reading the
+    /// input from `source` if it is provided.
+    fn do_crypt(
+        &self,
+        source: Option<&[u8]>,
+        dest: &mut [u8],
+        start_index: usize,
+        stop_index: usize,
+    ) -> Result<(), ErrorCode> {
+        let len = stop_index
+            .checked_sub(start_index)
+            .ok_or(ErrorCode::INVAL)?;
+        if len % AES128_BLOCK_SIZE != 0
+            || stop_index > dest.len()
+            || source.is_some_and(|source| source.len() != len)
+        {
+            return Err(ErrorCode::INVAL);
+        }
+
+        for (i, out) in dest[start_index..stop_index]
+            .chunks_exact_mut(AES128_BLOCK_SIZE)
+            .enumerate()
+        {
+            let mut block = [0; AES128_BLOCK_SIZE];
+            match source {
+                Some(source) => block
+                    .copy_from_slice(&source[i * AES128_BLOCK_SIZE..(i + 1) * AES128_BLOCK_SIZE]),
+                None => block.copy_from_slice(out),
+            }
+
+            self.crypt_block(&mut block);
+            out.copy_from_slice(&block);
+        }
+
+        Ok(())
+    }
+}
+
+impl<'a> AES128<'a> for Aes128Software<'a> {
+    fn enable(&self) {}
+
+    fn disable(&self) {}
+
+    fn set_client(&'a self, client: &'a dyn Client<'a>) {
+        self.client.set(client);
+    }
+
+    fn set_key(&self, key: &[u8]) -> Result<(), ErrorCode> {
+        if !aes_key_size_valid(key.len()) {
+            return Err(ErrorCode::INVAL);
+        }
+
+        let (round_keys, rounds) = expand_key(key);
+        self.round_keys.set(round_keys);
+        self.rounds.set(rounds);
+        Ok(())
+    }
+
+    fn set_iv(&self, iv: &[u8]) -> Result<(), ErrorCode> {
+        let iv: Block = iv.try_into().or(Err(ErrorCode::INVAL))?;
+        self.iv.set(iv);
+        self.chain.set(iv);
+        Ok(())
+    }
+
+    fn start_message(&self) {
+        if self.dest.is_none() {
+            self.chain.set(self.iv.get());
+        }
+    }
+
+    fn crypt(
+        &self,
+        source: Option<&'static mut [u8]>,
+        dest: &'static mut [u8],
+        start_index: usize,
+        stop_index: usize,
+    ) -> Option<(
+        Result<(), ErrorCode>,
+        Option<&'static mut [u8]>,
+        &'static mut [u8],
+    )> {
+        if self.dest.is_some() {
+            return Some((Err(ErrorCode::BUSY), source, dest));
+        }
+
+        match self.do_crypt(source.as_deref(), dest, start_index, stop_index) {
+            Ok(()) => {
+                self.source.put(source);
+                self.dest.replace(dest);
+                self.deferred_call.set();
+                None
+            }
+            Err(e) => Some((Err(e), source, dest)),
+        }
+    }
+}
+
+impl AES128ECB for Aes128Software<'_> {
+    fn set_mode_aes128ecb(&self, encrypting: bool) -> Result<(), ErrorCode> {
+        self.mode.set(Mode::Ecb);
+        self.encrypting.set(encrypting);
+        Ok(())
+    }
+}
+
+impl AES128CBC for Aes128Software<'_> {
+    fn set_mode_aes128cbc(&self, encrypting: bool) -> Result<(), ErrorCode> {
+        self.mode.set(Mode::Cbc);
+        self.encrypting.set(encrypting);
+        Ok(())
+    }
+}
+
+impl AES128Ctr for Aes128Software<'_> {
+    fn set_mode_aes128ctr(&self, encrypting: bool) -> Result<(), ErrorCode> {
+        self.mode.set(Mode::Ctr);
+        self.encrypting.set(encrypting);
+        Ok(())
+    }
+}
+
+impl DeferredCallClient for Aes128Software<'_> {
+    fn register(&'static self) {
+        self.deferred_call.register(self);
+    }
+
+    fn handle_deferred_call(&self) {
+        if let Some(dest) = self.dest.take() {
+            let source = self.source.take();
+            self.client
+                .map(move |client| client.crypt_done(source, dest));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PLAINTEXT: Block = [
+        0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88, 0x99, 0xaa, 0xbb, 0xcc, 0xdd, 0xee,
+        0xff,
+    ];
+
+    /// Check a FIPS-197 Appendix C example vector for a key of `key_length`
+    /// bytes `00 01 02 ...`.
+    fn check_vector(key_length: usize, ciphertext: Block) {
+        let key: [u8; 32] = core::array::from_fn(|i| i as u8);
+        let (round_keys, rounds) = expand_key(&key[..key_length]);
+        assert_eq!(rounds, key_length / 4 + 6);
+
+        let mut block = PLAINTEXT;
+        encrypt_block(&round_keys, rounds, &mut block);
+        assert_eq!(block, ciphertext);
+
+        decrypt_block(&round_keys, rounds, &mut block);
+        assert_eq!(block, PLAINTEXT);
+    }
+
+    #[test]
+    fn sub_bytes() {
+        // FIPS-197 Figure 7.
+        assert_eq!(sub_byte(0x00), 0x63);
+        assert_eq!(sub_byte(0x53), 0xed);
+        assert_eq!(sub_byte(0xff), 0x16);
+        for a in 0..=255 {
+            assert_eq!(inv_sub_byte(sub_byte(a)), a);
+        }
+    }
+
+    #[test]
+    fn key_expansion_128() {
+        // FIPS-197 Appendix A.1.
+        let key = [
+            0x2b, 0x7e, 0x15, 0x16, 0x28, 0xae, 0xd2, 0xa6, 0xab, 0xf7, 0x15, 0x88, 0x09, 0xcf,
+            0x4f, 0x3c,
+        ];
+        let (round_keys, rounds) = expand_key(&key);
+        assert_eq!(rounds, 10);
+        assert_eq!(
+            round_keys[10],
+            [
+                0xd0, 0x14, 0xf9, 0xa8, 0xc9, 0xee, 0x25, 0x89, 0xe1, 0x3f, 0x0c, 0xc8, 0xb6, 0x63,
+                0x0c, 0xa6
+            ]
+        );
+    }
+
+    #[test]
+    fn key_expansion_256() {
+        // FIPS-197 Appendix A.3.
+        let key = [
+            0x60, 0x3d, 0xeb, 0x10, 0x15, 0xca, 0x71, 0xbe, 0x2b, 0x73, 0xae, 0xf0, 0x85, 0x7d,
+            0x77, 0x81, 0x1f, 0x35, 0x2c, 0x07, 0x3b, 0x61, 0x08, 0xd7, 0x2d, 0x98, 0x10, 0xa3,
+            0x09, 0x14, 0xdf, 0xf4,
+        ];
+        let (round_keys, rounds) = expand_key(&key);
+        assert_eq!(rounds, 14);
+        assert_eq!(
+            round_keys[14],
+            [
+                0xfe, 0x48, 0x90, 0xd1, 0xe6, 0x18, 0x8d, 0x0b, 0x04, 0x6d, 0xf3, 0x44, 0x70, 0x6c,
+                0x63, 0x1e
+            ]
+        );
+    }
+
+    #[test]
+    fn aes_128() {
+        // FIPS-197 Appendix C.1.
+        check_vector(
+            16,
+            [
+                0x69, 0xc4, 0xe0, 0xd8, 0x6a, 0x7b, 0x04, 0x30, 0xd8, 0xcd, 0xb7, 0x80, 0x70, 0xb4,
+                0xc5, 0x5a,
+            ],
+        );
+    }
+
+    #[test]
+    fn aes_192() {
+        // FIPS-197 Appendix C.2.
+        check_vector(
+            24,
+            [
+                0xdd, 0xa9, 0x7c, 0xa4, 0x86, 0x4c, 0xdf, 0xe0, 0x6e, 0xaf, 0x70, 0xa0, 0xec, 0x0d,
+                0x71, 0x91,
+            ],
+        );
+    }
+
+    #[test]
+    fn aes_256() {
+        // FIPS-197 Appendix C.3.
+        check_vector(
+            32,
+            [
+                0x8e, 0xa2, 0xb7, 0xca, 0x51, 0x67, 0x45, 0xbf, 0xea, 0xfc, 0x49, 0x90, 0x4b, 0x49,
+                0x60, 0x89,
+            ],
+        );
+    }
+}
@@ -0,0 +1,454 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Software implementation of ChaCha20-Poly1305.
+//!
+//! Provides the `ChaCha20Poly1305` AEAD, as specified in RFC 8439. Neither
+//! ChaCha20 nor Poly1305 use any secret dependent table lookups or branches,
+//! and the tag is compared in constant time.
+//!
+//! All of the work is done in `crypt()`, the result is returned to the client
+//! from a deferred call. When decrypting, the message is only decrypted if the
+//! tag is valid, otherwise the buffer is returned unmodified.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! let chacha = components::chacha20_poly1305::ChaCha20Poly1305SoftwareComponent::new()
+//!     .finalize(components::chacha20_poly1305_software_component_static!());
+//!
+//! chacha.set_client(client);
+//! ```
+
+use core::cell::Cell;
+
+use kernel::deferred_call::{DeferredCall, DeferredCallClient};
+use kernel::hil::symmetric_encryption::{
+    ChaCha20Poly1305, ChaCha20Poly1305Client, CHACHA20_POLY1305_KEY_SIZE,
+    CHACHA20_POLY1305_NONCE_SIZE, CHACHA20_POLY1305_TAG_SIZE,
+};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::ErrorCode;
+
+const CHACHA20_BLOCK_SIZE: usize = 64;
+const POLY1305_BLOCK_SIZE: usize = 16;
+
+fn read_u32(bytes: &[u8]) -> u32 {
+    u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+fn quarter_round(state: &mut [u32; 16], a: usize, b: usize, c: usize, d: usize) {
+    state[a] = state[a].wrapping_add(state[b]);
+    state[d] = (state[d] ^ state[a]).rotate_left(16);
+    state[c] = state[c].wrapping_add(state[d]);
+    state[b] = (state[b] ^ state[c]).rotate_left(12);
+    state[a] = state[a].wrapping_add(state[b]);
+    state[d] = (state[d] ^ state[a]).rotate_left(8);
+    state[c] = state[c].wrapping_add(state[d]);
+    state[b] = (state[b] ^ state[c]).rotate_left(7);
+}
+
+/// Generate the ChaCha20 key stream block for `counter`.
+fn chacha20_block(key: &[u32; 8], nonce: &[u32; 3], counter: u32) -> [u8; CHACHA20_BLOCK_SIZE] {
+    let mut initial = [0; 16];
+    initial[0] = 0x61707865;
+    initial[1] = 0x3320646e;
+    initial[2] = 0x79622d32;
+    initial[3] = 0x6b206574;
+    initial[4..12].copy_from_slice(key);
+    initial[12] = counter;
+    initial[13..16].copy_from_slice(nonce);
+
+    let mut state = initial;
+    for _ in 0..10 {
+        quarter_round(&mut state, 0, 4, 8, 12);
+        quarter_round(&mut state, 1, 5, 9, 13);
+        quarter_round(&mut state, 2, 6, 10, 14);
+        quarter_round(&mut state, 3, 7, 11, 15);
+        quarter_round(&mut state, 0, 5, 10, 15);
+        quarter_round(&mut state, 1, 6, 11, 12);
+        quarter_round(&mut state, 2, 7, 8, 13);
+        quarter_round(&mut state, 3, 4, 9, 14);
+    }
+
+    let mut block = [0; CHACHA20_BLOCK_SIZE];
+    for (i, word) in state.iter().enumerate() {
+        block[i * 4..(i + 1) * 4].copy_from_slice(&word.wrapping_add(initial[i]).to_le_bytes());
+    }
+    block
+}
+
+/// XOR `data` with the ChaCha20 key stream, starting at block `counter`.
+fn chacha20_xor(key: &[u32; 8], nonce: &[u32; 3], counter: u32, data: &mut [u8]) {
+    for (i, chunk) in data.chunks_mut(CHACHA20_BLOCK_SIZE).enumerate() {
+        let stream = chacha20_block(key, nonce, counter.wrapping_add(i as u32));
+        for (byte, s) in chunk.iter_mut().zip(stream.iter()) {
+            *byte ^= s;
+        }
+    }
+}
+
+/// Poly1305, using 26-bit limbs so that the products fit in a `u64`.
+struct Poly1305 {
+    r: [u32; 5],
+    h: [u32; 5],
+    pad: [u32; 4],
+}
+
+impl Poly1305 {
+    fn new(key: &[u8]) -> Self {
+        Poly1305 {
+            r: [
+                read_u32(&key[0..]) & 0x3ffffff,
+                (read_u32(&key[3..]) >> 2) & 0x3ffff03,
+                (read_u32(&key[6..]) >> 4) & 0x3ffc0ff,
+                (read_u32(&key[9..]) >> 6) & 0x3f03fff,
+                (read_u32(&key[12..]) >> 8) & 0x00fffff,
+            ],
+            h: [0; 5],
+            pad: [
+                read_u32(&key[16..]),
+                read_u32(&key[20..]),
+                read_u32(&key[24..]),
+                read_u32(&key[28..]),
+            ],
+        }
+    }
+
+    fn block(&mut self, m: &[u8; POLY1305_BLOCK_SIZE]) {
+        let [r0, r1, r2, r3, r4] = self.r.map(|r| r as u64);
+        let (s1, s2, s3, s4) = (r1 * 5, r2 * 5, r3 * 5, r4 * 5);
+
+        let h0 = (self.h[0] + (read_u32(&m[0..]) & 0x3ffffff)) as u64;
+        let h1 = (self.h[1] + ((read_u32(&m[3..]) >> 2) & 0x3ffffff)) as u64;
+        let h2 = (self.h[2] + ((read_u32(&m[6..]) >> 4) & 0x3ffffff)) as u64;
+        let h3 = (self.h[3] + ((read_u32(&m[9..]) >> 6) & 0x3ffffff)) as u64;
+        let h4 = (self.h[4] + ((read_u32(&m[12..]) >> 8) | (1 << 24))) as u64;
+
+        let d0 = h0 * r0 + h1 * s4 + h2 * s3 + h3 * s2 + h4 * s1;
+        let mut d1 = h0 * r1 + h1 * r0 + h2 * s4 + h3 * s3 + h4 * s2;
+        let mut d2 = h0 * r2 + h1 * r1 + h2 * r0 + h3 * s4 + h4 * s3;
+        let mut d3 = h0 * r3 + h1 * r2 + h2 * r1 + h3 * r0 + h4 * s4;
+        let mut d4 = h0 * r4 + h1 * r3 + h2 * r2 + h3 * r1 + h4 * r0;
+
+        d1 += d0 >> 26;
+        d2 += d1 >> 26;
+        d3 += d2 >> 26;
+        d4 += d3 >> 26;
+        let mut h0 = (d0 as u32 & 0x3ffffff) + (d4 >> 26) as u32 * 5;
+        let h1 = (d1 as u32 & 0x3ffffff) + (h0 >> 26);
+        h0 &= 0x3ffffff;
+
+        self.h = [
+            h0,
+            h1,
+            d2 as u32 & 0x3ffffff,
+            d3 as u32 & 0x3ffffff,
+            d4 as u32 & 0x3ffffff,
+        ];
+    }
+
+    /// Process `data`, padded with zeros to a multiple of the block size.
+    fn update_padded(&mut self, data: &[u8]) {
+        for chunk in data.chunks(POLY1305_BLOCK_SIZE) {
+            let mut m = [0; POLY1305_BLOCK_SIZE];
+            m[..chunk.len()].copy_from_slice(chunk);
+            self.block(&m);
+        }
+    }
+
+    fn finalize(self) -> [u8; CHACHA20_POLY1305_TAG_SIZE] {
+        let mut h = self.h;
+
+        // Fully carry h.
+        for i in 1..5 {
+            h[i] += h[i - 1] >> 26;
+            h[i - 1] &= 0x3ffffff;
+        }
+        h[0] += (h[4] >> 26) * 5;
+        h[4] &= 0x3ffffff;
+        h[1] += h[0] >> 26;
+        h[0] &= 0x3ffffff;
+
+        // Compute g = h + 5 - 2^130 and select it if it isn't negative.
+        let mut g = [0; 5];
+        let mut carry = 5;
+        for i in 0..5 {
+            g[i] = h[i] + carry;
+            carry = g[i] >> 26;
+            g[i] &= 0x3ffffff;
+        }
+        g[4] = g[4].wrapping_add(carry << 26).wrapping_sub(1 << 26);
+        let mask = (g[4] >> 31).wrapping_sub(1);
+        for i in 0..5 {
+            h[i] = (h[i] & !mask) | (g[i] & mask);
+        }
+
+        // h = (h + pad) mod 2^128
+        let words = [
+            h[0] | (h[1] << 26),
+            (h[1] >> 6) | (h[2] << 20),
+            (h[2] >> 12) | (h[3] << 14),
+            (h[3] >> 18) | (h[4] << 8),
+        ];
+        let mut tag = [0; CHACHA20_POLY1305_TAG_SIZE];
+        let mut f = 0u64;
+        for i in 0..4 {
+            f = words[i] as u64 + self.pad[i] as u64 + (f >> 32);
+            tag[i * 4..(i + 1) * 4].copy_from_slice(&(f as u32).to_le_bytes());
+        }
+        tag
+    }
+}
+
+/// Calculate the tag over the additional data and the ciphertext.
+fn aead_tag(
+    key: &[u32; 8],
+    nonce: &[u32; 3],
+    aad: &[u8],
+    ciphertext: &[u8],
+) -> [u8; CHACHA20_POLY1305_TAG_SIZE] {
+    let poly_key = chacha20_block(key, nonce, 0);
+    let mut poly = Poly1305::new(&poly_key[..32]);
+
+    poly.update_padded(aad);
+    poly.update_padded(ciphertext);
+
+    let mut lengths = [0; POLY1305_BLOCK_SIZE];
+    lengths[..8].copy_from_slice(&(aad.len() as u64).to_le_bytes());
+    lengths[8..].copy_from_slice(&(ciphertext.len() as u64).to_le_bytes());
+    poly.block(&lengths);
+
+    poly.finalize()
+}
+
+/// Encrypt or decrypt the message in `buf` in place, returning whether the
+/// tag is valid.
+pub(crate) fn aead_crypt(
+    key: &[u32; 8],
+    nonce: &[u32; 3],
+    buf: &mut [u8],
+    aad_offset: usize,
+    message_offset: usize,
+    message_len: usize,
+    encrypting: bool,
+) -> bool {
+    let (aad, rest) = buf[aad_offset..].split_at_mut(message_offset - aad_offset);
+    let (message, rest) = rest.split_at_mut(message_len);
+    let tag = &mut rest[..CHACHA20_POLY1305_TAG_SIZE];
+
+    if encrypting {
+        chacha20_xor(key, nonce, 1, message);
+        tag.copy_from_slice(&aead_tag(key, nonce, aad, message));
+        true
+    } else {
+        // Compare all of the tag, without returning early.
+        let diff = aead_tag(key, nonce, aad, message)
+            .iter()
+            .zip(tag.iter())
+            .fold(0, |diff, (a, b)| diff | (a ^ b));
+
+        if diff == 0 {
+            chacha20_xor(key, nonce, 1, message);
+        }
+        diff == 0
+    }
+}
+
+pub struct ChaCha20Poly1305Software<'a> {
+    client: OptionalCell<&'a dyn ChaCha20Poly1305Client>,
+    deferred_call: DeferredCall,
+
+    key: Cell<[u32; 8]>,
+    nonce: Cell<[u32; 3]>,
+
+    buf: TakeCell<'static, [u8]>,
+    tag_is_valid: Cell<bool>,
+}
+
+impl<'a> ChaCha20Poly1305Software<'a> {
+    pub fn new() -> Self {
+        ChaCha20Poly1305Software {
+            client: OptionalCell::empty(),
+            deferred_call: DeferredCall::new(),
+            key: Cell::new([0; 8]),
+            nonce: Cell::new([0; 3]),
+            buf: TakeCell::empty(),
+            tag_is_valid: Cell::new(false),
+        }
+    }
+}
+
+impl<'a> ChaCha20Poly1305<'a> for ChaCha20Poly1305Software<'a> {
+    fn set_client(&'a self, client: &'a dyn ChaCha20Poly1305Client) {
+        self.client.set(client);
+    }
+
+    fn set_key(&self, key: &[u8]) -> Result<(), ErrorCode> {
+        if key.len() != CHACHA20_POLY1305_KEY_SIZE {
+            return Err(ErrorCode::INVAL);
+        }
+
+        let mut words = [0; 8];
+        for (word, bytes) in words.iter_mut().zip(key.chunks(4)) {
+            *word = read_u32(bytes);
+        }
+        self.key.set(words);
+        Ok(())
+    }
+
+    fn set_nonce(&self, nonce: &[u8]) -> Result<(), ErrorCode> {
+        if nonce.len() != CHACHA20_POLY1305_NONCE_SIZE {
+            return Err(ErrorCode::INVAL);
+        }
+
+        let mut words = [0; 3];
+        for (word, bytes) in words.iter_mut().zip(nonce.chunks(4)) {
+            *word = read_u32(bytes);
+        }
+        self.nonce.set(words);
+        Ok(())
+    }
+
+    fn crypt(
+        &self,
+        buf: &'static mut [u8],
+        aad_offset: usize,
+        message_offset: usize,
+        message_len: usize,
+        encrypting: bool,
+    ) -> Result<(), (ErrorCode, &'static mut [u8])> {
+        if self.buf.is_some() {
+            return Err((ErrorCode::BUSY, buf));
+        }
+        if aad_offset > message_offset {
+            return Err((ErrorCode::INVAL, buf));
+        }
+        match message_offset
+            .checked_add(message_len)
+            .and_then(|end| end.checked_add(CHACHA20_POLY1305_TAG_SIZE))
+        {
+            Some(end) if end <= buf.len() => {}
+            _ => return Err((ErrorCode::SIZE, buf)),
+        }
+
+        let tag_is_valid = aead_crypt(
+            &self.key.get(),
+            &self.nonce.get(),
+            buf,
+            aad_offset,
+            message_offset,
+            message_len,
+            encrypting,
+        );
+        self.tag_is_valid.set(tag_is_valid);
+        self.buf.replace(buf);
+        self.deferred_call.set();
+
+        Ok(())
+    }
+}
+
+impl DeferredCallClient for ChaCha20Poly1305Software<'_> {
+    fn register(&'static self) {
+        self.deferred_call.register(self);
+    }
+
+    fn handle_deferred_call(&self) {
+        if let Some(buf) = self.buf.take() {
+            let tag_is_valid = self.tag_is_valid.get();
+            self.client
+                .map(move |client| client.crypt_done(buf, Ok(()), tag_is_valid));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const AAD: [u8; 12] = [
+        0x50, 0x51, 0x52, 0x53, 0xc0, 0xc1, 0xc2, 0xc3, 0xc4, 0xc5, 0xc6, 0xc7,
+    ];
+    const PLAINTEXT: &[u8; 114] = b"Ladies and Gentlemen of the class of '99: If I could offer you only one tip for the future, sunscreen would be it.";
+    const CIPHERTEXT: [u8; 114] = [
+        0xd3, 0x1a, 0x8d, 0x34, 0x64, 0x8e, 0x60, 0xdb, 0x7b, 0x86, 0xaf, 0xbc, 0x53, 0xef, 0x7e,
+        0xc2, 0xa4, 0xad, 0xed, 0x51, 0x29, 0x6e, 0x08, 0xfe, 0xa9, 0xe2, 0xb5, 0xa7, 0x36, 0xee,
+        0x62, 0xd6, 0x3d, 0xbe, 0xa4, 0x5e, 0x8c, 0xa9, 0x67, 0x12, 0x82, 0xfa, 0xfb, 0x69, 0xda,
+        0x92, 0x72, 0x8b, 0x1a, 0x71, 0xde, 0x0a, 0x9e, 0x06, 0x0b, 0x29, 0x05, 0xd6, 0xa5, 0xb6,
+        0x7e, 0xcd, 0x3b, 0x36, 0x92, 0xdd, 0xbd, 0x7f, 0x2d, 0x77, 0x8b, 0x8c, 0x98, 0x03, 0xae,
+        0xe3, 0x28, 0x09, 0x1b, 0x58, 0xfa, 0xb3, 0x24, 0xe4, 0xfa, 0xd6, 0x75, 0x94, 0x55, 0x85,
+        0x80, 0x8b, 0x48, 0x31, 0xd7, 0xbc, 0x3f, 0xf4, 0xde, 0xf0, 0x8e, 0x4b, 0x7a, 0x9d, 0xe5,
+        0x76, 0xd2, 0x65, 0x86, 0xce, 0xc6, 0x4b, 0x61, 0x16,
+    ];
+    const TAG: [u8; CHACHA20_POLY1305_TAG_SIZE] = [
+        0x1a, 0xe1, 0x0b, 0x59, 0x4f, 0x09, 0xe2, 0x6a, 0x7e, 0x90, 0x2e, 0xcb, 0xd0, 0x60, 0x06,
+        0x91,
+    ];
+
+    const BUF_LEN: usize = AAD.len() + PLAINTEXT.len() + CHACHA20_POLY1305_TAG_SIZE;
+    const TAG_OFFSET: usize = AAD.len() + PLAINTEXT.len();
+
+    /// The key and nonce from RFC 8439 section 2.8.2.
+    fn key_and_nonce() -> ([u32; 8], [u32; 3]) {
+        let key: [u8; CHACHA20_POLY1305_KEY_SIZE] = core::array::from_fn(|i| 0x80 + i as u8);
+        let nonce = [
+            0x07, 0x00, 0x00, 0x00, 0x40, 0x41, 0x42, 0x43, 0x44, 0x45, 0x46, 0x47,
+        ];
+        (
+            core::array::from_fn(|i| read_u32(&key[i * 4..])),
+            core::array::from_fn(|i| read_u32(&nonce[i * 4..])),
+        )
+    }
+
+    /// A buffer holding the additional data followed by `message` and `tag`.
+    fn buffer(message: &[u8], tag: &[u8]) -> [u8; BUF_LEN] {
+        let mut buf = [0; BUF_LEN];
+        buf[..AAD.len()].copy_from_slice(&AAD);
+        buf[AAD.len()..TAG_OFFSET].copy_from_slice(message);
+        buf[TAG_OFFSET..].copy_from_slice(tag);
+        buf
+    }
+
+    fn crypt(buf: &mut [u8], encrypting: bool) -> bool {
+        let (key, nonce) = key_and_nonce();
+        aead_crypt(&key, &nonce, buf, 0, AAD.len(), PLAINTEXT.len(), encrypting)
+    }
+
+    #[test]
+    fn rfc8439_encrypt() {
+        let mut buf = buffer(PLAINTEXT, &[0; CHACHA20_POLY1305_TAG_SIZE]);
+        assert!(crypt(&mut buf, true));
+        assert_eq!(buf, buffer(&CIPHERTEXT, &TAG));
+    }
+
+    #[test]
+    fn rfc8439_decrypt() {
+        let mut buf = buffer(&CIPHERTEXT, &TAG);
+        assert!(crypt(&mut buf, false));
+        assert_eq!(buf[AAD.len()..TAG_OFFSET], PLAINTEXT[..]);
+    }
+
+    #[test]
+    fn tampered_tag_is_rejected() {
+        let mut tag = TAG;
+        tag[CHACHA20_POLY1305_TAG_SIZE - 1] ^= 0x01;
+        let mut buf = buffer(&CIPHERTEXT, &tag);
+        assert!(!crypt(&mut buf, false));
+        // The message isn't decrypted.
+        assert_eq!(buf, buffer(&CIPHERTEXT, &tag));
+    }
+
+    #[test]
+    fn tampered_message_is_rejected() {
+        let mut buf = buffer(&CIPHERTEXT, &TAG);
+        buf[AAD.len()] ^= 0x80;
+        assert!(!crypt(&mut buf, false));
+
+        let mut buf = buffer(&CIPHERTEXT, &TAG);
+        buf[0] ^= 0x01;
+        assert!(!crypt(&mut buf, false));
+    }
+}
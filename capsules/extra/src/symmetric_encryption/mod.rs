@@ -3,3 +3,5 @@
 // Copyright Tock Contributors 2022.
 
 pub mod aes;
+pub mod aes_software;
+pub mod chacha20_poly1305;
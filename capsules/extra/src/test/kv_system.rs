@@ -168,4 +168,35 @@ impl<'a, S: KVSystem<'static, K = T>, T: KeyType + core::fmt::Debug> KVSystemCli
             }
         }
     }
+
+    fn append_named_key_complete(
+        &self,
+        result: Result<(), ErrorCode>,
+        key: &'static mut T,
+        _unhashed_key: SubSliceMut<'static, u8>,
+        value: SubSliceMut<'static, u8>,
+    ) {
+        self.append_key_complete(result, key, value);
+    }
+
+    fn next_key_complete(
+        &self,
+        result: Result<(), ErrorCode>,
+        position: usize,
+        unhashed_key: SubSliceMut<'static, u8>,
+        value: SubSliceMut<'static, u8>,
+    ) {
+        debug!(
+            "Next key {:?}: {:?} with value {:?}, next position {}",
+            result, unhashed_key, value, position
+        );
+    }
+
+    fn commit_transaction_complete(&self, result: Result<(), ErrorCode>) {
+        debug!("Committed transaction: {:?}", result);
+    }
+
+    fn abort_transaction_complete(&self, result: Result<(), ErrorCode>) {
+        debug!("Aborted transaction: {:?}", result);
+    }
 }
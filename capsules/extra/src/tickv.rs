@@ -95,6 +95,54 @@ pub trait KVSystemClient<K: KeyType> {
     ///
     /// - `result`: Nothing on success, 'ErrorCode' on error
     fn garbage_collect_complete(&self, result: Result<(), ErrorCode>);
+
+    /// This callback is called when the append_named_key operation completes.
+    ///
+    /// - `result`: Nothing on success, 'ErrorCode' on error
+    /// - `key`: The key buffer
+    /// - `unhashed_key`: The unhashed_key buffer
+    /// - `value`: The value buffer
+    fn append_named_key_complete(
+        &self,
+        result: Result<(), ErrorCode>,
+        key: &'static mut K,
+        unhashed_key: SubSliceMut<'static, u8>,
+        value: SubSliceMut<'static, u8>,
+    ) {
+        let _ = (result, key, unhashed_key, value);
+    }
+
+    /// This callback is called when the next_key operation completes.
+    ///
+    /// - `result`: Nothing on success, 'ErrorCode' on error
+    /// - `position`: The position to continue the iteration from
+    /// - `unhashed_key`: The unhashed_key buffer, sliced to the key length
+    /// - `value`: The value buffer, sliced to the value length
+    fn next_key_complete(
+        &self,
+        result: Result<(), ErrorCode>,
+        position: usize,
+        unhashed_key: SubSliceMut<'static, u8>,
+        value: SubSliceMut<'static, u8>,
+    ) {
+        let _ = (result, position, unhashed_key, value);
+    }
+
+    /// This callback is called when the commit_transaction operation
+    /// completes.
+    ///
+    /// - `result`: Nothing on success, 'ErrorCode' on error
+    fn commit_transaction_complete(&self, result: Result<(), ErrorCode>) {
+        let _ = result;
+    }
+
+    /// This callback is called when the abort_transaction operation
+    /// completes.
+    ///
+    /// - `result`: Nothing on success, 'ErrorCode' on error
+    fn abort_transaction_complete(&self, result: Result<(), ErrorCode>) {
+        let _ = result;
+    }
 }
 
 pub trait KVSystem<'a> {
@@ -142,6 +190,47 @@ pub trait KVSystem<'a> {
         value: SubSliceMut<'static, u8>,
     ) -> Result<(), (&'static mut Self::K, SubSliceMut<'static, u8>, ErrorCode)>;
 
+    /// Appends the key/value pair, storing the unhashed key alongside the
+    /// value so that it can be returned by `next_key()`.
+    ///
+    /// This is otherwise identical to `append_key()`.
+    ///
+    /// - `key`: A hashed key. This key will be used in future to retrieve
+    ///          or remove the `value`.
+    /// - `unhashed_key`: The key that was hashed to generate `key`.
+    /// - `value`: A buffer containing the data to be stored to flash.
+    ///
+    /// On success nothing will be returned.
+    /// On error the key, unhashed_key, value and a `Result<(), ErrorCode>`
+    /// will be returned.
+    ///
+    /// The possible `Result<(), ErrorCode>`s are:
+    /// - `BUSY`: An operation is already in progress
+    /// - `INVAL`: An invalid parameter was passed
+    /// - `NODEVICE`: No KV store was setup
+    /// - `NOSUPPORT`: The key could not be added due to a collision.
+    /// - `NOMEM`: The key could not be added due to no more space.
+    /// - `SIZE`: The unhashed key or value is too long.
+    ///
+    /// The default implementation returns `NOSUPPORT`, for KV systems that
+    /// can't store the unhashed key.
+    fn append_named_key(
+        &self,
+        key: &'static mut Self::K,
+        unhashed_key: SubSliceMut<'static, u8>,
+        value: SubSliceMut<'static, u8>,
+    ) -> Result<
+        (),
+        (
+            &'static mut Self::K,
+            SubSliceMut<'static, u8>,
+            SubSliceMut<'static, u8>,
+            ErrorCode,
+        ),
+    > {
+        Err((key, unhashed_key, value, ErrorCode::NOSUPPORT))
+    }
+
     /// Retrieves the value from a specified key.
     ///
     /// - `key`: A hashed key. This key will be used to retrieve the `value`.
@@ -193,6 +282,94 @@ pub trait KVSystem<'a> {
     /// - `INVAL`: An invalid parameter was passed.
     /// - `NODEVICE`: No KV store was setup.
     fn garbage_collect(&self) -> Result<(), ErrorCode>;
+
+    /// Retrieves the next valid key/value pair, starting at `position`.
+    ///
+    /// Only keys added with `append_named_key()` have their unhashed key
+    /// stored, for other keys `unhashed_key` will be empty.
+    ///
+    /// - `position`: Where to start searching from, `0` for the start of the
+    ///               store or the `position` from the previous
+    ///               `next_key_complete()` callback.
+    /// - `unhashed_key`: A buffer to store the unhashed key to.
+    /// - `value`: A buffer to store the value to.
+    ///
+    /// On success nothing will be returned.
+    /// On error the unhashed_key, value and a `Result<(), ErrorCode>` will be
+    /// returned.
+    ///
+    /// The possible `Result<(), ErrorCode>`s are:
+    /// - `BUSY`: An operation is already in progress
+    /// - `NODEVICE`: No KV store was setup
+    /// - `NOSUPPORT`: There are no more keys.
+    /// - `SIZE`: The key or value is longer than the provided buffer.
+    ///
+    /// The default implementation returns `NOSUPPORT`, for KV systems that
+    /// can't be iterated.
+    fn next_key(
+        &self,
+        position: usize,
+        unhashed_key: SubSliceMut<'static, u8>,
+        value: SubSliceMut<'static, u8>,
+    ) -> Result<
+        (),
+        (
+            SubSliceMut<'static, u8>,
+            SubSliceMut<'static, u8>,
+            ErrorCode,
+        ),
+    > {
+        let _ = position;
+        Err((unhashed_key, value, ErrorCode::NOSUPPORT))
+    }
+
+    /// Start a transaction.
+    ///
+    /// Until the transaction is committed all appended keys are staged. They
+    /// are not returned by `get_value()` or `next_key()`, and don't collide
+    /// with existing keys. Once committed each staged key replaces any
+    /// existing value for that key. If power is lost before the transaction
+    /// is committed the staged keys are discarded.
+    ///
+    /// This completes synchronously, no callback will be triggered.
+    ///
+    /// The possible `ErrorCode`s are:
+    /// - `BUSY`: An operation is already in progress.
+    /// - `ALREADY`: A transaction has already been started.
+    /// - `FAIL`: The transaction could not be started.
+    /// - `NOSUPPORT`: This KV system doesn't support transactions.
+    fn begin_transaction(&self) -> Result<(), ErrorCode> {
+        Err(ErrorCode::NOSUPPORT)
+    }
+
+    /// Commit the current transaction.
+    ///
+    /// On success nothing will be returned.
+    /// On error a `Result<(), ErrorCode>` will be returned.
+    ///
+    /// The possible `ErrorCode`s are:
+    /// - `BUSY`: An operation is already in progress.
+    /// - `INVAL`: No transaction has been started.
+    /// - `FAIL`: The transaction could not be committed.
+    ///
+    /// If `commit_transaction_complete()` reports `NOMEM` the commit could
+    /// not be started and the transaction should be aborted.
+    fn commit_transaction(&self) -> Result<(), ErrorCode> {
+        Err(ErrorCode::NOSUPPORT)
+    }
+
+    /// Abort the current transaction, discarding all of the staged keys.
+    ///
+    /// On success nothing will be returned.
+    /// On error a `Result<(), ErrorCode>` will be returned.
+    ///
+    /// The possible `ErrorCode`s are:
+    /// - `BUSY`: An operation is already in progress.
+    /// - `INVAL`: No transaction has been started.
+    /// - `FAIL`: The transaction could not be aborted.
+    fn abort_transaction(&self) -> Result<(), ErrorCode> {
+        Err(ErrorCode::NOSUPPORT)
+    }
 }
 
 #[derive(Clone, Copy, PartialEq, Debug)]
@@ -201,8 +378,12 @@ enum Operation {
     Init,
     GetKey,
     AppendKey,
+    AppendNamedKey,
     InvalidateKey,
     GarbageCollect,
+    NextKey,
+    CommitTransaction,
+    AbortTransaction,
 }
 
 /// Wrapper object that provides the flash interface TicKV expects using the
@@ -302,6 +483,10 @@ pub struct TicKVSystem<'a, F: Flash + 'static, H: Hasher<'a, 8>, const PAGE_SIZE
     /// Holder for a buffer containing a value being read from or written to the
     /// key-value store.
     value_buffer: MapCell<SubSliceMut<'static, u8>>,
+    /// Length of the unhashed key while it is held by TicKV.
+    unhashed_key_length: Cell<usize>,
+    /// The position to start a `next_key()` operation from.
+    position: Cell<usize>,
     /// Callback client when the `KVSystem` operation completes.
     client: OptionalCell<&'a dyn KVSystemClient<TicKVKeyType>>,
 }
@@ -329,6 +514,8 @@ impl<'a, F: Flash, H: Hasher<'a, 8>, const PAGE_SIZE: usize> TicKVSystem<'a, F,
             unhashed_key_buffer: MapCell::empty(),
             key_buffer: TakeCell::empty(),
             value_buffer: MapCell::empty(),
+            unhashed_key_length: Cell::new(0),
+            position: Cell::new(0),
             client: OptionalCell::empty(),
         }
     }
@@ -341,7 +528,10 @@ impl<'a, F: Flash, H: Hasher<'a, 8>, const PAGE_SIZE: usize> TicKVSystem<'a, F,
     fn complete_init(&self) {
         self.operation.set(Operation::None);
         match self.next_operation.get() {
-            Operation::None | Operation::Init => {}
+            Operation::None
+            | Operation::Init
+            | Operation::CommitTransaction
+            | Operation::AbortTransaction => {}
             Operation::GetKey => {
                 match self.get_value(
                     self.key_buffer.take().unwrap(),
@@ -368,6 +558,20 @@ impl<'a, F: Flash, H: Hasher<'a, 8>, const PAGE_SIZE: usize> TicKVSystem<'a, F,
                     _ => {}
                 }
             }
+            Operation::AppendNamedKey => {
+                match self.append_named_key(
+                    self.key_buffer.take().unwrap(),
+                    self.unhashed_key_buffer.take().unwrap(),
+                    self.value_buffer.take().unwrap(),
+                ) {
+                    Err((key, unhashed_key, value, error)) => {
+                        self.client.map(move |cb| {
+                            cb.append_named_key_complete(Err(error), key, unhashed_key, value);
+                        });
+                    }
+                    _ => {}
+                }
+            }
             Operation::InvalidateKey => {
                 match self.invalidate_key(self.key_buffer.take().unwrap()) {
                     Err((key, error)) => {
@@ -386,40 +590,95 @@ impl<'a, F: Flash, H: Hasher<'a, 8>, const PAGE_SIZE: usize> TicKVSystem<'a, F,
                 }
                 _ => {}
             },
+            Operation::NextKey => {
+                match self.next_key(
+                    self.position.get(),
+                    self.unhashed_key_buffer.take().unwrap(),
+                    self.value_buffer.take().unwrap(),
+                ) {
+                    Err((unhashed_key, value, error)) => {
+                        self.client.map(move |cb| {
+                            cb.next_key_complete(Err(error), 0, unhashed_key, value);
+                        });
+                    }
+                    _ => {}
+                }
+            }
         }
         self.next_operation.set(Operation::None);
     }
-}
 
-impl<'a, F: Flash, H: Hasher<'a, 8>, const PAGE_SIZE: usize> hasher::Client<8>
-    for TicKVSystem<'a, F, H, PAGE_SIZE>
-{
-    fn add_mut_data_done(&self, _result: Result<(), ErrorCode>, data: SubSliceMut<'static, u8>) {
-        self.unhashed_key_buffer.replace(data);
-        self.hasher.run(self.key_buffer.take().unwrap()).unwrap();
+    /// Call the correct append complete callback, returning the unhashed key
+    /// for named appends.
+    fn append_complete(&self, result: Result<(), ErrorCode>) {
+        let key = self.key_buffer.take().unwrap();
+        let value = self.value_buffer.take().unwrap();
+
+        match self.tickv.take_unhashed_key() {
+            Some(buf) => {
+                let mut unhashed_key = SubSliceMut::new(buf);
+                unhashed_key.slice(0..self.unhashed_key_length.get());
+                self.client.map(move |cb| {
+                    cb.append_named_key_complete(result, key, unhashed_key, value);
+                });
+            }
+            None => {
+                self.client.map(move |cb| {
+                    cb.append_key_complete(result, key, value);
+                });
+            }
+        }
     }
 
-    fn add_data_done(&self, _result: Result<(), ErrorCode>, _data: SubSlice<'static, u8>) {}
+    /// Call the next key complete callback once the TicKV operation has
+    /// finished.
+    fn next_key_done(&self, ret: Result<tickv::success_codes::SuccessCode, tickv::ErrorCode>) {
+        self.operation.set(Operation::None);
+
+        let mut unhashed_key = SubSliceMut::new(self.tickv.take_unhashed_key().unwrap());
+        let mut value = self.value_buffer.take().unwrap();
+        // Undo the slicing applied to the returned buffer, we know the
+        // length from the key entry.
+        value.reset();
+
+        let (result, position) = match (ret, self.tickv.key_entry()) {
+            (Ok(_), Some(entry)) => {
+                let result =
+                    if entry.key_length > unhashed_key.len() || entry.value_length > value.len() {
+                        Err(ErrorCode::SIZE)
+                    } else {
+                        Ok(())
+                    };
+                unhashed_key.slice(0..entry.key_length.min(unhashed_key.len()));
+                value.slice(0..entry.value_length.min(value.len()));
+                (result, entry.next_position)
+            }
+            (Err(tickv::ErrorCode::KeyNotFound), _) => (Err(ErrorCode::NOSUPPORT), 0),
+            _ => (Err(ErrorCode::FAIL), 0),
+        };
 
-    fn hash_done(&self, _result: Result<(), ErrorCode>, digest: &'static mut [u8; 8]) {
         self.client.map(move |cb| {
-            cb.generate_key_complete(Ok(()), self.unhashed_key_buffer.take().unwrap(), digest);
+            cb.next_key_complete(result, position, unhashed_key, value);
         });
+    }
 
-        self.hasher.clear_data();
+    /// Call the commit or abort complete callback once the TicKV operation
+    /// has finished.
+    fn transaction_done(&self, result: Result<(), ErrorCode>) {
+        let operation = self.operation.get();
+        self.operation.set(Operation::None);
+
+        self.client.map(move |cb| {
+            if operation == Operation::CommitTransaction {
+                cb.commit_transaction_complete(result);
+            } else {
+                cb.abort_transaction_complete(result);
+            }
+        });
     }
-}
 
-impl<'a, F: Flash, H: Hasher<'a, 8>, const PAGE_SIZE: usize> flash::Client<F>
-    for TicKVSystem<'a, F, H, PAGE_SIZE>
-{
-    fn read_complete(&self, pagebuffer: &'static mut F::Page, _result: Result<(), flash::Error>) {
-        self.tickv.set_read_buffer(pagebuffer.as_mut());
-        self.tickv
-            .tickv
-            .controller
-            .flash_read_buffer
-            .replace(pagebuffer);
+    /// Continue the current operation after a flash operation has completed.
+    fn continue_operation(&self) {
         let (ret, tickv_buf, tickv_buf_len) = self.tickv.continue_operation();
 
         // If we got the buffer back from TicKV then store it.
@@ -494,7 +753,7 @@ impl<'a, F: Flash, H: Hasher<'a, 8>, const PAGE_SIZE: usize> flash::Client<F>
                     }
                 }
             }
-            Operation::AppendKey => {
+            Operation::AppendKey | Operation::AppendNamedKey => {
                 match ret {
                     Ok(tickv::success_codes::SuccessCode::Complete)
                     | Ok(tickv::success_codes::SuccessCode::Written) => {
@@ -517,13 +776,7 @@ impl<'a, F: Flash, H: Hasher<'a, 8>, const PAGE_SIZE: usize> flash::Client<F>
                             tickv::error_codes::ErrorCode::FlashFull => ErrorCode::NOMEM,
                             _ => ErrorCode::FAIL,
                         };
-                        self.client.map(|cb| {
-                            cb.append_key_complete(
-                                Err(tock_hil_error),
-                                self.key_buffer.take().unwrap(),
-                                self.value_buffer.take().unwrap(),
-                            );
-                        });
+                        self.append_complete(Err(tock_hil_error));
                     }
                 }
             }
@@ -564,9 +817,70 @@ impl<'a, F: Flash, H: Hasher<'a, 8>, const PAGE_SIZE: usize> flash::Client<F>
                 }
                 _ => {}
             },
+            Operation::NextKey => match ret {
+                Err(tickv::error_codes::ErrorCode::ReadNotReady(_)) => {
+                    // Need to do another flash read.
+                }
+                _ => self.next_key_done(ret),
+            },
+            Operation::CommitTransaction | Operation::AbortTransaction => match ret {
+                Ok(tickv::success_codes::SuccessCode::Complete)
+                | Ok(tickv::success_codes::SuccessCode::Written) => {
+                    self.transaction_done(Ok(()));
+                }
+                Ok(tickv::success_codes::SuccessCode::Queued) => {
+                    // Need to wait for the final flash write to complete.
+                }
+                Err(tickv::error_codes::ErrorCode::ReadNotReady(_))
+                | Err(tickv::error_codes::ErrorCode::WriteNotReady(_))
+                | Err(tickv::error_codes::ErrorCode::EraseNotReady(_)) => {
+                    // Need to do another flash operation.
+                }
+                Err(e) => {
+                    let tock_hil_error = match e {
+                        tickv::error_codes::ErrorCode::RegionFull => ErrorCode::NOMEM,
+                        tickv::error_codes::ErrorCode::FlashFull => ErrorCode::NOMEM,
+                        _ => ErrorCode::FAIL,
+                    };
+                    self.transaction_done(Err(tock_hil_error));
+                }
+            },
             _ => unreachable!(),
         }
     }
+}
+
+impl<'a, F: Flash, H: Hasher<'a, 8>, const PAGE_SIZE: usize> hasher::Client<8>
+    for TicKVSystem<'a, F, H, PAGE_SIZE>
+{
+    fn add_mut_data_done(&self, _result: Result<(), ErrorCode>, data: SubSliceMut<'static, u8>) {
+        self.unhashed_key_buffer.replace(data);
+        self.hasher.run(self.key_buffer.take().unwrap()).unwrap();
+    }
+
+    fn add_data_done(&self, _result: Result<(), ErrorCode>, _data: SubSlice<'static, u8>) {}
+
+    fn hash_done(&self, _result: Result<(), ErrorCode>, digest: &'static mut [u8; 8]) {
+        self.client.map(move |cb| {
+            cb.generate_key_complete(Ok(()), self.unhashed_key_buffer.take().unwrap(), digest);
+        });
+
+        self.hasher.clear_data();
+    }
+}
+
+impl<'a, F: Flash, H: Hasher<'a, 8>, const PAGE_SIZE: usize> flash::Client<F>
+    for TicKVSystem<'a, F, H, PAGE_SIZE>
+{
+    fn read_complete(&self, pagebuffer: &'static mut F::Page, _result: Result<(), flash::Error>) {
+        self.tickv.set_read_buffer(pagebuffer.as_mut());
+        self.tickv
+            .tickv
+            .controller
+            .flash_read_buffer
+            .replace(pagebuffer);
+        self.continue_operation();
+    }
 
     fn write_complete(&self, pagebuffer: &'static mut F::Page, _result: Result<(), flash::Error>) {
         self.tickv
@@ -575,19 +889,20 @@ impl<'a, F: Flash, H: Hasher<'a, 8>, const PAGE_SIZE: usize> flash::Client<F>
             .flash_read_buffer
             .replace(pagebuffer);
 
+        if self.tickv.write_continues() {
+            // The operation requires more flash operations, for example when
+            // committing a transaction.
+            self.continue_operation();
+            return;
+        }
+
         match self.operation.get() {
             Operation::Init => {
                 self.complete_init();
             }
-            Operation::AppendKey => {
+            Operation::AppendKey | Operation::AppendNamedKey => {
                 self.operation.set(Operation::None);
-                self.client.map(|cb| {
-                    cb.append_key_complete(
-                        Ok(()),
-                        self.key_buffer.take().unwrap(),
-                        self.value_buffer.take().unwrap(),
-                    );
-                });
+                self.append_complete(Ok(()));
             }
             Operation::InvalidateKey => {
                 self.operation.set(Operation::None);
@@ -595,6 +910,9 @@ impl<'a, F: Flash, H: Hasher<'a, 8>, const PAGE_SIZE: usize> flash::Client<F>
                     cb.invalidate_key_complete(Ok(()), self.key_buffer.take().unwrap());
                 });
             }
+            Operation::CommitTransaction | Operation::AbortTransaction => {
+                self.transaction_done(Ok(()));
+            }
             _ => unreachable!(),
         }
     }
@@ -701,6 +1019,68 @@ impl<'a, F: Flash, H: Hasher<'a, 8>, const PAGE_SIZE: usize> KVSystem<'a>
         }
     }
 
+    fn append_named_key(
+        &self,
+        key: &'static mut Self::K,
+        unhashed_key: SubSliceMut<'static, u8>,
+        value: SubSliceMut<'static, u8>,
+    ) -> Result<
+        (),
+        (
+            &'static mut [u8; 8],
+            SubSliceMut<'static, u8>,
+            SubSliceMut<'static, u8>,
+            ErrorCode,
+        ),
+    > {
+        match self.operation.get() {
+            Operation::None => {
+                self.operation.set(Operation::AppendNamedKey);
+
+                let key_length = unhashed_key.len();
+                let length = value.len();
+                match self.tickv.append_named_key(
+                    u64::from_be_bytes(*key),
+                    unhashed_key.take(),
+                    key_length,
+                    value.take(),
+                    length,
+                ) {
+                    Ok(_ret) => {
+                        self.key_buffer.replace(key);
+                        self.unhashed_key_length.set(key_length);
+                        Ok(())
+                    }
+                    Err((key_buf, buf, e)) => {
+                        self.operation.set(Operation::None);
+                        let tock_error = match e {
+                            tickv::error_codes::ErrorCode::ObjectTooLarge => ErrorCode::SIZE,
+                            _ => ErrorCode::FAIL,
+                        };
+                        let mut unhashed_key = SubSliceMut::new(key_buf);
+                        unhashed_key.slice(0..key_length);
+                        let mut value = SubSliceMut::new(buf);
+                        value.slice(0..length);
+                        Err((key, unhashed_key, value, tock_error))
+                    }
+                }
+            }
+            Operation::Init => {
+                // The init process is still occurring.
+                // We can save this request and start it after init
+                self.next_operation.set(Operation::AppendNamedKey);
+                self.key_buffer.replace(key);
+                self.unhashed_key_buffer.replace(unhashed_key);
+                self.value_buffer.replace(value);
+                Ok(())
+            }
+            _ => {
+                // An operation is already in process.
+                Err((key, unhashed_key, value, ErrorCode::BUSY))
+            }
+        }
+    }
+
     fn get_value(
         &self,
         key: &'static mut Self::K,
@@ -787,4 +1167,116 @@ impl<'a, F: Flash, H: Hasher<'a, 8>, const PAGE_SIZE: usize> KVSystem<'a>
             }
         }
     }
+
+    fn next_key(
+        &self,
+        position: usize,
+        unhashed_key: SubSliceMut<'static, u8>,
+        value: SubSliceMut<'static, u8>,
+    ) -> Result<
+        (),
+        (
+            SubSliceMut<'static, u8>,
+            SubSliceMut<'static, u8>,
+            ErrorCode,
+        ),
+    > {
+        if unhashed_key.is_sliced() || value.is_sliced() {
+            return Err((unhashed_key, value, ErrorCode::SIZE));
+        }
+        match self.operation.get() {
+            Operation::None => {
+                self.operation.set(Operation::NextKey);
+
+                match self
+                    .tickv
+                    .next_key(position, unhashed_key.take(), value.take())
+                {
+                    Ok(_ret) => Ok(()),
+                    Err((key_buf, buf, e)) => {
+                        self.operation.set(Operation::None);
+                        let tock_error = match e {
+                            tickv::error_codes::ErrorCode::KeyNotFound => ErrorCode::NOSUPPORT,
+                            _ => ErrorCode::FAIL,
+                        };
+                        Err((SubSliceMut::new(key_buf), SubSliceMut::new(buf), tock_error))
+                    }
+                }
+            }
+            Operation::Init => {
+                // The init process is still occurring.
+                // We can save this request and start it after init.
+                self.next_operation.set(Operation::NextKey);
+                self.position.set(position);
+                self.unhashed_key_buffer.replace(unhashed_key);
+                self.value_buffer.replace(value);
+                Ok(())
+            }
+            _ => {
+                // An operation is already in process.
+                Err((unhashed_key, value, ErrorCode::BUSY))
+            }
+        }
+    }
+    fn begin_transaction(&self) -> Result<(), ErrorCode> {
+        match self.operation.get() {
+            Operation::None => match self.tickv.begin_transaction() {
+                Ok(_ret) => Ok(()),
+                Err(tickv::error_codes::ErrorCode::TransactionInProgress) => {
+                    Err(ErrorCode::ALREADY)
+                }
+                Err(_e) => Err(ErrorCode::FAIL),
+            },
+            _ => {
+                // An operation is already in process.
+                Err(ErrorCode::BUSY)
+            }
+        }
+    }
+
+    fn commit_transaction(&self) -> Result<(), ErrorCode> {
+        match self.operation.get() {
+            Operation::None => {
+                self.operation.set(Operation::CommitTransaction);
+
+                match self.tickv.commit_transaction() {
+                    Ok(_ret) => Ok(()),
+                    Err(e) => {
+                        self.operation.set(Operation::None);
+                        match e {
+                            tickv::error_codes::ErrorCode::NoTransaction => Err(ErrorCode::INVAL),
+                            _ => Err(ErrorCode::FAIL),
+                        }
+                    }
+                }
+            }
+            _ => {
+                // An operation is already in process.
+                Err(ErrorCode::BUSY)
+            }
+        }
+    }
+
+    fn abort_transaction(&self) -> Result<(), ErrorCode> {
+        match self.operation.get() {
+            Operation::None => {
+                self.operation.set(Operation::AbortTransaction);
+
+                match self.tickv.abort_transaction() {
+                    Ok(_ret) => Ok(()),
+                    Err(e) => {
+                        self.operation.set(Operation::None);
+                        match e {
+                            tickv::error_codes::ErrorCode::NoTransaction => Err(ErrorCode::INVAL),
+                            _ => Err(ErrorCode::FAIL),
+                        }
+                    }
+                }
+            }
+            _ => {
+                // An operation is already in process.
+                Err(ErrorCode::BUSY)
+            }
+        }
+    }
 }
@@ -28,6 +28,7 @@
 //! ```
 
 use crate::tickv::{KVSystem, KVSystemClient, KeyType};
+use core::cell::Cell;
 use kernel::hil::kv;
 use kernel::utilities::cells::{MapCell, OptionalCell, TakeCell};
 use kernel::utilities::leasable_buffer::SubSliceMut;
@@ -40,6 +41,9 @@ enum Operation {
     Add,
     Update,
     Delete,
+    NextKey,
+    CommitBatch,
+    AbortBatch,
 }
 
 /// `TicKVKVStore` implements the KV interface using the TicKV KVSystem
@@ -53,6 +57,13 @@ pub struct TicKVKVStore<'a, K: KVSystem<'a> + KVSystem<'a, K = T>, T: 'static +
 
     unhashed_key: MapCell<SubSliceMut<'static, u8>>,
     value: MapCell<SubSliceMut<'static, u8>>,
+
+    /// Set while a batch of `set()` operations is being staged.
+    ///
+    /// The batch belongs to the single client of this store. Stores shared by
+    /// several users are accessed through `KVStorePermissions`, which only
+    /// lets the owner of the batch `set()` values while it is in progress.
+    batch: Cell<bool>,
 }
 
 impl<'a, K: KVSystem<'a, K = T>, T: KeyType> TicKVKVStore<'a, K, T> {
@@ -64,6 +75,7 @@ impl<'a, K: KVSystem<'a, K = T>, T: KeyType> TicKVKVStore<'a, K, T> {
             operation: OptionalCell::empty(),
             unhashed_key: MapCell::empty(),
             value: MapCell::empty(),
+            batch: Cell::new(false),
         }
     }
 
@@ -84,6 +96,11 @@ impl<'a, K: KVSystem<'a, K = T>, T: KeyType> TicKVKVStore<'a, K, T> {
             return Err((key, value, ErrorCode::BUSY));
         }
 
+        if self.batch.get() && operation != Operation::Set {
+            // Only `set()` can be staged in a batch
+            return Err((key, value, ErrorCode::INVAL));
+        }
+
         self.operation.set(operation);
 
         match self.hashed_key.take() {
@@ -195,6 +212,11 @@ impl<'a, K: KVSystem<'a, K = T>, T: KeyType> kv::KV<'a> for TicKVKVStore<'a, K,
             return Err((key, ErrorCode::BUSY));
         }
 
+        if self.batch.get() {
+            // Only `set()` can be staged in a batch
+            return Err((key, ErrorCode::INVAL));
+        }
+
         self.operation.set(Operation::Delete);
 
         match self.hashed_key.take() {
@@ -209,6 +231,91 @@ impl<'a, K: KVSystem<'a, K = T>, T: KeyType> kv::KV<'a> for TicKVKVStore<'a, K,
             None => Err((key, ErrorCode::FAIL)),
         }
     }
+
+    fn next_key(
+        &self,
+        position: usize,
+        key: SubSliceMut<'static, u8>,
+        value: SubSliceMut<'static, u8>,
+    ) -> Result<
+        (),
+        (
+            SubSliceMut<'static, u8>,
+            SubSliceMut<'static, u8>,
+            ErrorCode,
+        ),
+    > {
+        if self.operation.is_some() {
+            return Err((key, value, ErrorCode::BUSY));
+        }
+
+        self.operation.set(Operation::NextKey);
+
+        match self.kv.next_key(position, key, value) {
+            Ok(()) => Ok(()),
+            Err((key, value, e)) => {
+                self.operation.clear();
+                Err((key, value, e))
+            }
+        }
+    }
+
+    fn begin_batch(&self) -> Result<(), ErrorCode> {
+        if self.operation.is_some() {
+            return Err(ErrorCode::BUSY);
+        }
+
+        if self.batch.get() {
+            return Err(ErrorCode::ALREADY);
+        }
+
+        self.kv.begin_transaction()?;
+        self.batch.set(true);
+        Ok(())
+    }
+
+    fn commit_batch(&self) -> Result<(), ErrorCode> {
+        if self.operation.is_some() {
+            return Err(ErrorCode::BUSY);
+        }
+
+        if !self.batch.get() {
+            return Err(ErrorCode::INVAL);
+        }
+
+        self.operation.set(Operation::CommitBatch);
+
+        match self.kv.commit_transaction() {
+            Ok(()) => Ok(()),
+            Err(e) => {
+                self.operation.clear();
+                Err(e)
+            }
+        }
+    }
+
+    fn abort_batch(&self) -> Result<(), ErrorCode> {
+        if self.operation.is_some() {
+            return Err(ErrorCode::BUSY);
+        }
+
+        if !self.batch.get() {
+            return Err(ErrorCode::INVAL);
+        }
+
+        self.operation.set(Operation::AbortBatch);
+
+        match self.kv.abort_transaction() {
+            Ok(()) => {
+                self.batch.set(false);
+                Ok(())
+            }
+            Err(e) => {
+                self.operation.clear();
+                Err(e)
+            }
+        }
+    }
 }
 
 impl<'a, K: KVSystem<'a, K = T>, T: KeyType> KVSystemClient<T> for TicKVKVStore<'a, K, T> {
@@ -260,6 +367,7 @@ impl<'a, K: KVSystem<'a, K = T>, T: KeyType> KVSystemClient<T> for TicKVKVStore<
                             cb.delete_complete(Err(ErrorCode::FAIL), unhashed_key);
                         });
                     }
+                    Operation::NextKey | Operation::CommitBatch | Operation::AbortBatch => {}
                 }
             } else {
                 match op {
@@ -282,11 +390,9 @@ impl<'a, K: KVSystem<'a, K = T>, T: KeyType> KVSystemClient<T> for TicKVKVStore<
                     Operation::Set => {
                         self.value.take().map(|value| {
                             // Try to append which will work if the key is new.
-                            match self.kv.append_key(hashed_key, value) {
-                                Ok(()) => {
-                                    self.unhashed_key.replace(unhashed_key);
-                                }
-                                Err((key, value, e)) => {
+                            match self.kv.append_named_key(hashed_key, unhashed_key, value) {
+                                Ok(()) => {}
+                                Err((key, unhashed_key, value, e)) => {
                                     self.hashed_key.replace(key);
                                     self.operation.clear();
                                     self.client.map(move |cb| {
@@ -300,11 +406,9 @@ impl<'a, K: KVSystem<'a, K = T>, T: KeyType> KVSystemClient<T> for TicKVKVStore<
                         self.value.take().map(|value| {
                             // Add only works if the key does not exist, so we
                             // can go right to append.
-                            match self.kv.append_key(hashed_key, value) {
-                                Ok(()) => {
-                                    self.unhashed_key.replace(unhashed_key);
-                                }
-                                Err((key, value, e)) => {
+                            match self.kv.append_named_key(hashed_key, unhashed_key, value) {
+                                Ok(()) => {}
+                                Err((key, unhashed_key, value, e)) => {
                                     self.hashed_key.replace(key);
                                     self.operation.clear();
                                     self.client.map(move |cb| {
@@ -350,6 +454,7 @@ impl<'a, K: KVSystem<'a, K = T>, T: KeyType> KVSystemClient<T> for TicKVKVStore<
                             }
                         };
                     }
+                    Operation::NextKey | Operation::CommitBatch | Operation::AbortBatch => {}
                 }
             }
         });
@@ -364,10 +469,14 @@ impl<'a, K: KVSystem<'a, K = T>, T: KeyType> KVSystemClient<T> for TicKVKVStore<
         self.hashed_key.replace(key);
 
         self.operation.map(|op| match op {
-            Operation::Get | Operation::Delete => {}
+            Operation::Get
+            | Operation::Delete
+            | Operation::NextKey
+            | Operation::CommitBatch
+            | Operation::AbortBatch => {}
             Operation::Set => {
                 match result {
-                    Err(ErrorCode::NOSUPPORT) => {
+                    Err(ErrorCode::NOSUPPORT) if !self.batch.get() => {
                         // We could not append because of a collision. So now we
                         // need to delete the existing key.
                         self.hashed_key.take().map(|hashed_key| {
@@ -477,26 +586,31 @@ impl<'a, K: KVSystem<'a, K = T>, T: KeyType> KVSystemClient<T> for TicKVKVStore<
         self.hashed_key.replace(key);
 
         self.operation.map(|op| match op {
-            Operation::Get | Operation::Add => {}
+            Operation::Get
+            | Operation::Add
+            | Operation::NextKey
+            | Operation::CommitBatch
+            | Operation::AbortBatch => {}
             Operation::Set => {
                 // Now that we have deleted the existing key-value we can store
                 // our new key and value.
                 match result {
                     Ok(()) => {
                         self.hashed_key.take().map(|hashed_key| {
-                            self.value.take().map(|value| {
-                                match self.kv.append_key(hashed_key, value) {
-                                    Ok(()) => {}
-                                    Err((key, value, e)) => {
-                                        self.hashed_key.replace(key);
-                                        self.operation.clear();
-                                        self.unhashed_key.take().map(|unhashed_key| {
+                            self.unhashed_key.take().map(|unhashed_key| {
+                                self.value.take().map(|value| {
+                                    match self.kv.append_named_key(hashed_key, unhashed_key, value)
+                                    {
+                                        Ok(()) => {}
+                                        Err((key, unhashed_key, value, e)) => {
+                                            self.hashed_key.replace(key);
+                                            self.operation.clear();
                                             self.client.map(move |cb| {
                                                 cb.set_complete(Err(e), unhashed_key, value);
                                             });
-                                        });
+                                        }
                                     }
-                                }
+                                });
                             });
                         });
                     }
@@ -519,13 +633,14 @@ impl<'a, K: KVSystem<'a, K = T>, T: KeyType> KVSystemClient<T> for TicKVKVStore<
                 match result {
                     Ok(()) => {
                         self.hashed_key.take().map(|hashed_key| {
-                            self.value.take().map(|value| {
-                                match self.kv.append_key(hashed_key, value) {
-                                    Ok(()) => {}
-                                    Err((key, value, _e)) => {
-                                        self.hashed_key.replace(key);
-                                        self.operation.clear();
-                                        self.unhashed_key.take().map(|unhashed_key| {
+                            self.unhashed_key.take().map(|unhashed_key| {
+                                self.value.take().map(|value| {
+                                    match self.kv.append_named_key(hashed_key, unhashed_key, value)
+                                    {
+                                        Ok(()) => {}
+                                        Err((key, unhashed_key, value, _e)) => {
+                                            self.hashed_key.replace(key);
+                                            self.operation.clear();
                                             self.client.map(move |cb| {
                                                 cb.update_complete(
                                                     Err(ErrorCode::FAIL),
@@ -533,9 +648,9 @@ impl<'a, K: KVSystem<'a, K = T>, T: KeyType> KVSystemClient<T> for TicKVKVStore<
                                                     value,
                                                 );
                                             });
-                                        });
+                                        }
                                     }
-                                }
+                                });
                             });
                         });
                     }
@@ -568,4 +683,61 @@ impl<'a, K: KVSystem<'a, K = T>, T: KeyType> KVSystemClient<T> for TicKVKVStore<
     }
 
     fn garbage_collect_complete(&self, _result: Result<(), ErrorCode>) {}
+
+    fn append_named_key_complete(
+        &self,
+        result: Result<(), ErrorCode>,
+        key: &'static mut T,
+        unhashed_key: SubSliceMut<'static, u8>,
+        value: SubSliceMut<'static, u8>,
+    ) {
+        // The unhashed key was held by the KV system while the value was
+        // written, restore it so it can be returned to the caller.
+        self.unhashed_key.replace(unhashed_key);
+        self.append_key_complete(result, key, value);
+    }
+
+    fn next_key_complete(
+        &self,
+        result: Result<(), ErrorCode>,
+        position: usize,
+        unhashed_key: SubSliceMut<'static, u8>,
+        value: SubSliceMut<'static, u8>,
+    ) {
+        // Objects that were stored without their key are returned with an
+        // empty key, so that they can still be enumerated.
+        self.operation.clear();
+        self.client.map(move |cb| {
+            cb.next_key_complete(
+                result.map_err(|e| match e {
+                    ErrorCode::SIZE => ErrorCode::SIZE,
+                    ErrorCode::NOSUPPORT => ErrorCode::NOSUPPORT,
+                    _ => ErrorCode::FAIL,
+                }),
+                position,
+                unhashed_key,
+                value,
+            );
+        });
+    }
+    fn commit_transaction_complete(&self, result: Result<(), ErrorCode>) {
+        self.operation.clear();
+
+        // If the commit couldn't be started the batch is still in progress
+        // and needs to be aborted.
+        if result != Err(ErrorCode::NOMEM) {
+            self.batch.set(false);
+        }
+
+        self.client.map(move |cb| {
+            cb.commit_batch_complete(result);
+        });
+    }
+
+    fn abort_transaction_complete(&self, result: Result<(), ErrorCode>) {
+        self.operation.clear();
+        self.client.map(move |cb| {
+            cb.abort_batch_complete(result);
+        });
+    }
 }
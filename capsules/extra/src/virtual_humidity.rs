@@ -0,0 +1,198 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Virtualize a humidity sensor for multiple clients.
+//!
+//! Humidity sensor capsules accept a single client. This capsule lets
+//! several clients, such as the userspace humidity driver and an in-kernel
+//! logger, share one sensor.
+//!
+//! Reads are coalesced: a read requested while the sensor is already reading
+//! for another client does not start a second read, and the result of the
+//! read in progress is passed to every client that was waiting on it.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! let mux_humidity = components::humidity::HumidityMuxComponent::new(sht4x)
+//!     .finalize(components::humidity_mux_component_static!(SHT4xSensor));
+//!
+//! let humidity_user = components::humidity::VirtualHumidityComponent::new(mux_humidity)
+//!     .finalize(components::virtual_humidity_component_static!(SHT4xSensor));
+//! ```
+
+use core::cell::Cell;
+
+use kernel::collections::list::{List, ListLink, ListNode};
+use kernel::hil::sensors::{HumidityClient, HumidityDriver};
+use kernel::utilities::cells::OptionalCell;
+use kernel::ErrorCode;
+
+/// A client of a shared humidity sensor.
+pub struct VirtualHumidity<'a, T: HumidityDriver<'a>> {
+    mux: &'a MuxHumidity<'a, T>,
+    next: ListLink<'a, VirtualHumidity<'a, T>>,
+    client: OptionalCell<&'a dyn HumidityClient>,
+    /// Whether this client is waiting on a read.
+    pending: Cell<bool>,
+    /// Whether this client gets the result of the read that just finished.
+    notify: Cell<bool>,
+}
+
+impl<'a, T: HumidityDriver<'a>> ListNode<'a, VirtualHumidity<'a, T>> for VirtualHumidity<'a, T> {
+    fn next(&self) -> &'a ListLink<VirtualHumidity<'a, T>> {
+        &self.next
+    }
+}
+
+impl<'a, T: HumidityDriver<'a>> VirtualHumidity<'a, T> {
+    pub fn new(mux: &'a MuxHumidity<'a, T>) -> VirtualHumidity<'a, T> {
+        VirtualHumidity {
+            mux,
+            next: ListLink::empty(),
+            client: OptionalCell::empty(),
+            pending: Cell::new(false),
+            notify: Cell::new(false),
+        }
+    }
+
+    pub fn setup(&'a self) {
+        self.mux.users.push_head(self);
+    }
+}
+
+impl<'a, T: HumidityDriver<'a>> HumidityDriver<'a> for VirtualHumidity<'a, T> {
+    fn set_client(&self, client: &'a dyn HumidityClient) {
+        self.client.set(client);
+    }
+
+    fn read_humidity(&self) -> Result<(), ErrorCode> {
+        if self.pending.get() {
+            return Err(ErrorCode::BUSY);
+        }
+
+        self.pending.set(true);
+        self.mux.read().inspect_err(|_| self.pending.set(false))
+    }
+}
+
+/// Shares a humidity sensor between `VirtualHumidity` clients.
+pub struct MuxHumidity<'a, T: HumidityDriver<'a>> {
+    sensor: &'a T,
+    users: List<'a, VirtualHumidity<'a, T>>,
+    /// Whether the sensor is reading.
+    busy: Cell<bool>,
+}
+
+impl<'a, T: HumidityDriver<'a>> MuxHumidity<'a, T> {
+    pub fn new(sensor: &'a T) -> MuxHumidity<'a, T> {
+        MuxHumidity {
+            sensor,
+            users: List::new(),
+            busy: Cell::new(false),
+        }
+    }
+
+    /// Start a read, unless one is already in progress.
+    fn read(&self) -> Result<(), ErrorCode> {
+        if self.busy.get() {
+            return Ok(());
+        }
+
+        self.busy.set(true);
+        self.sensor
+            .read_humidity()
+            .inspect_err(|_| self.busy.set(false))
+    }
+}
+
+impl<'a, T: HumidityDriver<'a>> HumidityClient for MuxHumidity<'a, T> {
+    fn callback(&self, value: usize) {
+        self.busy.set(false);
+
+        // Pick the clients to notify before calling any of them, so a read
+        // started from a callback waits for a new value.
+        for user in self.users.iter() {
+            user.notify.set(user.pending.take());
+        }
+        for user in self.users.iter() {
+            if user.notify.take() {
+                user.client.map(|client| client.callback(value));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use core::cell::RefCell;
+    use std::boxed::Box;
+    use std::vec::Vec;
+
+    /// A sensor that counts its reads.
+    struct FakeSensor {
+        reads: Cell<usize>,
+    }
+
+    impl<'a> HumidityDriver<'a> for FakeSensor {
+        fn set_client(&self, _client: &'a dyn HumidityClient) {}
+
+        fn read_humidity(&self) -> Result<(), ErrorCode> {
+            self.reads.set(self.reads.get() + 1);
+            Ok(())
+        }
+    }
+
+    /// Records the values it gets.
+    struct TestClient {
+        values: RefCell<Vec<usize>>,
+    }
+
+    impl HumidityClient for TestClient {
+        fn callback(&self, value: usize) {
+            self.values.borrow_mut().push(value);
+        }
+    }
+
+    #[test]
+    fn reads_are_coalesced() {
+        let sensor = Box::leak(Box::new(FakeSensor {
+            reads: Cell::new(0),
+        }));
+        let mux = Box::leak(Box::new(MuxHumidity::new(&*sensor)));
+        let clients: [&'static TestClient; 3] = core::array::from_fn(|_| {
+            &*Box::leak(Box::new(TestClient {
+                values: RefCell::new(Vec::new()),
+            }))
+        });
+        let [a, b, c] = clients.map(|client| {
+            let user: &'static VirtualHumidity<'static, FakeSensor> =
+                Box::leak(Box::new(VirtualHumidity::new(mux)));
+            user.setup();
+            user.set_client(client);
+            user
+        });
+
+        assert_eq!(a.read_humidity(), Ok(()));
+        assert_eq!(a.read_humidity(), Err(ErrorCode::BUSY));
+        assert_eq!(b.read_humidity(), Ok(()));
+        assert_eq!(sensor.reads.get(), 1);
+
+        mux.callback(4520);
+        assert_eq!(*clients[0].values.borrow(), [4520]);
+        assert_eq!(*clients[1].values.borrow(), [4520]);
+        assert!(clients[2].values.borrow().is_empty());
+
+        // The next read starts a new one.
+        assert_eq!(c.read_humidity(), Ok(()));
+        assert_eq!(sensor.reads.get(), 2);
+        mux.callback(4710);
+        assert_eq!(*clients[2].values.borrow(), [4710]);
+        assert_eq!(clients[0].values.borrow().len(), 1);
+    }
+}
@@ -31,7 +31,12 @@
 //!
 //!    hil::flash
 //! ```
+//!
+//! While a user has a batch in progress the `set()`, `add()`, `update()` and
+//! `delete()` operations of other users are queued until the batch has been
+//! committed or aborted.
 
+use core::cell::Cell;
 use kernel::collections::list::{List, ListLink, ListNode};
 
 use kernel::hil::kv;
@@ -48,6 +53,20 @@ enum Operation {
     Delete,
     Add,
     Update,
+    NextKey(usize),
+    CommitBatch,
+    AbortBatch,
+}
+
+impl Operation {
+    /// Whether the operation modifies the store, and so can't run while
+    /// another user's batch is in progress.
+    fn modifies_store(&self) -> bool {
+        matches!(
+            self,
+            Operation::Set | Operation::Delete | Operation::Add | Operation::Update
+        )
+    }
 }
 
 pub struct VirtualKVPermissions<'a, V: kv::KVPermissions<'a>> {
@@ -60,6 +79,8 @@ pub struct VirtualKVPermissions<'a, V: kv::KVPermissions<'a>> {
     key: MapCell<SubSliceMut<'static, u8>>,
     value: MapCell<SubSliceMut<'static, u8>>,
     valid_ids: OptionalCell<StoragePermissions>,
+    /// Set while this user has a batch in progress.
+    batch: Cell<bool>,
 }
 
 impl<'a, V: kv::KVPermissions<'a>> ListNode<'a, VirtualKVPermissions<'a, V>>
@@ -80,6 +101,7 @@ impl<'a, V: kv::KVPermissions<'a>> VirtualKVPermissions<'a, V> {
             key: MapCell::empty(),
             value: MapCell::empty(),
             valid_ids: OptionalCell::empty(),
+            batch: Cell::new(false),
         }
     }
 
@@ -124,6 +146,26 @@ impl<'a, V: kv::KVPermissions<'a>> VirtualKVPermissions<'a, V> {
             .do_next_op(false)
             .map_err(|e| (self.key.take().unwrap(), self.value.take().unwrap(), e))
     }
+
+    /// Queue committing or aborting this user's batch.
+    fn batch_operation(
+        &self,
+        permissions: StoragePermissions,
+        operation: Operation,
+    ) -> Result<(), ErrorCode> {
+        if self.operation.is_some() {
+            return Err(ErrorCode::BUSY);
+        }
+
+        if !self.batch.get() {
+            return Err(ErrorCode::INVAL);
+        }
+
+        self.operation.set(operation);
+        self.valid_ids.set(permissions);
+
+        self.mux_kv.do_next_op(false)
+    }
 }
 
 impl<'a, V: kv::KVPermissions<'a>> kv::KVPermissions<'a> for VirtualKVPermissions<'a, V> {
@@ -224,9 +266,67 @@ impl<'a, V: kv::KVPermissions<'a>> kv::KVPermissions<'a> for VirtualKVPermission
             .map_err(|e| (self.key.take().unwrap(), e))
     }
 
+    fn next_key(
+        &self,
+        position: usize,
+        key: SubSliceMut<'static, u8>,
+        value: SubSliceMut<'static, u8>,
+        permissions: StoragePermissions,
+    ) -> Result<
+        (),
+        (
+            SubSliceMut<'static, u8>,
+            SubSliceMut<'static, u8>,
+            ErrorCode,
+        ),
+    > {
+        if self.operation.is_some() {
+            return Err((key, value, ErrorCode::BUSY));
+        }
+
+        self.operation.set(Operation::NextKey(position));
+        self.valid_ids.set(permissions);
+        self.key.replace(key);
+        self.value.replace(value);
+
+        self.mux_kv
+            .do_next_op(false)
+            .map_err(|e| (self.key.take().unwrap(), self.value.take().unwrap(), e))
+    }
+
+    fn begin_batch(&self, permissions: StoragePermissions) -> Result<(), ErrorCode> {
+        if self.operation.is_some() || self.mux_kv.inflight.is_some() {
+            return Err(ErrorCode::BUSY);
+        }
+
+        if self.batch.get() {
+            return Err(ErrorCode::ALREADY);
+        }
+
+        if self.mux_kv.batch_in_progress() {
+            return Err(ErrorCode::BUSY);
+        }
+
+        self.mux_kv.kv.begin_batch(permissions)?;
+        self.batch.set(true);
+        Ok(())
+    }
+
+    fn commit_batch(&self, permissions: StoragePermissions) -> Result<(), ErrorCode> {
+        self.batch_operation(permissions, Operation::CommitBatch)
+    }
+
+    fn abort_batch(&self, permissions: StoragePermissions) -> Result<(), ErrorCode> {
+        self.batch_operation(permissions, Operation::AbortBatch)
+    }
+
     fn header_size(&self) -> usize {
         self.mux_kv.kv.header_size()
     }
+
+    fn get_usage(&self, permissions: StoragePermissions) -> Result<kv::StorageUsage, ErrorCode> {
+        self.mux_kv.kv.get_usage(permissions)
+    }
 }
 
 pub struct MuxKVPermissions<'a, V: kv::KVPermissions<'a>> {
@@ -244,13 +344,60 @@ impl<'a, V: kv::KVPermissions<'a>> MuxKVPermissions<'a, V> {
         }
     }
 
+    /// Whether any user has a batch in progress.
+    fn batch_in_progress(&self) -> bool {
+        self.users.iter().any(|node| node.batch.get())
+    }
+
     fn do_next_op(&self, async_op: bool) -> Result<(), ErrorCode> {
-        // Find a virtual device which has pending work.
-        let mnode = self.users.iter().find(|node| node.operation.is_some());
+        // The next operation is started once the current one completes.
+        if self.inflight.is_some() {
+            return Ok(());
+        }
+
+        // Find a virtual device which has pending work. Operations that
+        // modify the store wait for another user's batch to finish.
+        let batch_in_progress = self.batch_in_progress();
+        let mnode = self.users.iter().find(|node| {
+            node.operation.map_or(false, |op| {
+                !op.modifies_store() || node.batch.get() || !batch_in_progress
+            })
+        });
 
         mnode.map_or(Ok(()), |node| {
-            node.operation.map_or(Ok(()), |op| {
-                node.key.take().map_or(Ok(()), |key| match op {
+            node.operation.map_or(Ok(()), |op| match op {
+                Operation::CommitBatch | Operation::AbortBatch => {
+                    node.valid_ids.map_or(Ok(()), |perms| {
+                        let ret = if op == Operation::CommitBatch {
+                            self.kv.commit_batch(perms)
+                        } else {
+                            self.kv.abort_batch(perms)
+                        };
+
+                        match ret {
+                            Ok(()) => {
+                                self.inflight.set(node);
+                                Ok(())
+                            }
+                            Err(e) => {
+                                node.operation.clear();
+                                if async_op {
+                                    node.client.map(move |cb| {
+                                        if op == Operation::CommitBatch {
+                                            cb.commit_batch_complete(Err(e));
+                                        } else {
+                                            cb.abort_batch_complete(Err(e));
+                                        }
+                                    });
+                                    Ok(())
+                                } else {
+                                    Err(e)
+                                }
+                            }
+                        }
+                    })
+                }
+                _ => node.key.take().map_or(Ok(()), |key| match op {
                     Operation::Get => node.value.take().map_or(Ok(()), |value| {
                         node.valid_ids.map_or(Ok(()), |perms| {
                             match self.kv.get(key, value, perms) {
@@ -364,7 +511,31 @@ impl<'a, V: kv::KVPermissions<'a>> MuxKVPermissions<'a, V> {
                                 }
                             })
                     }
-                })
+                    Operation::NextKey(position) => node.value.take().map_or(Ok(()), |value| {
+                        node.valid_ids.map_or(Ok(()), |perms| {
+                            match self.kv.next_key(position, key, value, perms) {
+                                Ok(()) => {
+                                    self.inflight.set(node);
+                                    Ok(())
+                                }
+                                Err((key, value, e)) => {
+                                    node.operation.clear();
+                                    if async_op {
+                                        node.client.map(move |cb| {
+                                            cb.next_key_complete(Err(e), 0, key, value);
+                                        });
+                                        Ok(())
+                                    } else {
+                                        node.key.replace(key);
+                                        node.value.replace(value);
+                                        Err(e)
+                                    }
+                                }
+                            }
+                        })
+                    }),
+                    Operation::CommitBatch | Operation::AbortBatch => Ok(()),
+                }),
             })
         })
     }
@@ -445,4 +616,49 @@ impl<'a, V: kv::KVPermissions<'a>> kv::KVClient for MuxKVPermissions<'a, V> {
 
         let _ = self.do_next_op(true);
     }
+
+    fn next_key_complete(
+        &self,
+        result: Result<(), ErrorCode>,
+        position: usize,
+        key: SubSliceMut<'static, u8>,
+        value: SubSliceMut<'static, u8>,
+    ) {
+        self.inflight.take().map(|node| {
+            node.operation.clear();
+            node.client.map(move |cb| {
+                cb.next_key_complete(result, position, key, value);
+            });
+        });
+
+        let _ = self.do_next_op(true);
+    }
+
+    fn commit_batch_complete(&self, result: Result<(), ErrorCode>) {
+        self.inflight.take().map(|node| {
+            // If the commit couldn't be started the batch is still in
+            // progress and needs to be aborted.
+            if result != Err(ErrorCode::NOMEM) {
+                node.batch.set(false);
+            }
+            node.operation.clear();
+            node.client.map(move |cb| {
+                cb.commit_batch_complete(result);
+            });
+        });
+
+        let _ = self.do_next_op(true);
+    }
+
+    fn abort_batch_complete(&self, result: Result<(), ErrorCode>) {
+        self.inflight.take().map(|node| {
+            node.batch.set(false);
+            node.operation.clear();
+            node.client.map(move |cb| {
+                cb.abort_batch_complete(result);
+            });
+        });
+
+        let _ = self.do_next_op(true);
+    }
 }
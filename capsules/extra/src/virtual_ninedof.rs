@@ -0,0 +1,313 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Virtualize a 9DOF sensor for multiple clients.
+//!
+//! 9DOF sensor capsules accept a single client. This capsule lets several
+//! clients, such as the userspace ninedof driver and an in-kernel orientation
+//! filter, share one sensor.
+//!
+//! Reads are coalesced: a read requested while the sensor is already reading
+//! the same quantity for another client does not start a second read, and the
+//! result is passed to every client that was waiting on it. Reads of other
+//! quantities are queued and started in turn once the sensor is idle.
+//!
+//! `NineDofClient` can't report errors, so if the sensor refuses to start a
+//! queued read, the clients waiting on that read are not called back, as with
+//! the ninedof driver.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! let mux_ninedof = components::ninedof::NineDofMuxComponent::new(lsm6dsoxtr)
+//!     .finalize(components::ninedof_mux_component_static!(Lsm6dsoxtrSensor));
+//!
+//! let ninedof_user = components::ninedof::VirtualNineDofComponent::new(mux_ninedof)
+//!     .finalize(components::virtual_ninedof_component_static!(Lsm6dsoxtrSensor));
+//! ```
+
+use core::cell::Cell;
+
+use kernel::collections::list::{List, ListLink, ListNode};
+use kernel::hil::sensors::{NineDof, NineDofClient};
+use kernel::utilities::cells::OptionalCell;
+use kernel::ErrorCode;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Reading {
+    Accelerometer,
+    Magnetometer,
+    Gyroscope,
+}
+
+/// A client of a shared 9DOF sensor.
+pub struct VirtualNineDof<'a, N: NineDof<'a>> {
+    mux: &'a MuxNineDof<'a, N>,
+    next: ListLink<'a, VirtualNineDof<'a, N>>,
+    client: OptionalCell<&'a dyn NineDofClient>,
+    /// The read this client is waiting on.
+    pending: OptionalCell<Reading>,
+    /// Whether this client gets the result of the read that just finished.
+    notify: Cell<bool>,
+}
+
+impl<'a, N: NineDof<'a>> ListNode<'a, VirtualNineDof<'a, N>> for VirtualNineDof<'a, N> {
+    fn next(&self) -> &'a ListLink<VirtualNineDof<'a, N>> {
+        &self.next
+    }
+}
+
+impl<'a, N: NineDof<'a>> VirtualNineDof<'a, N> {
+    pub fn new(mux: &'a MuxNineDof<'a, N>) -> VirtualNineDof<'a, N> {
+        VirtualNineDof {
+            mux,
+            next: ListLink::empty(),
+            client: OptionalCell::empty(),
+            pending: OptionalCell::empty(),
+            notify: Cell::new(false),
+        }
+    }
+
+    pub fn setup(&'a self) {
+        self.mux.users.push_head(self);
+    }
+
+    fn read(&self, reading: Reading) -> Result<(), ErrorCode> {
+        if self.pending.is_some() {
+            return Err(ErrorCode::BUSY);
+        }
+
+        self.pending.set(reading);
+        self.mux.read(reading).inspect_err(|_| self.pending.clear())
+    }
+}
+
+impl<'a, N: NineDof<'a>> NineDof<'a> for VirtualNineDof<'a, N> {
+    fn set_client(&self, client: &'a dyn NineDofClient) {
+        self.client.set(client);
+    }
+
+    fn read_accelerometer(&self) -> Result<(), ErrorCode> {
+        self.read(Reading::Accelerometer)
+    }
+
+    fn read_magnetometer(&self) -> Result<(), ErrorCode> {
+        self.read(Reading::Magnetometer)
+    }
+
+    fn read_gyroscope(&self) -> Result<(), ErrorCode> {
+        self.read(Reading::Gyroscope)
+    }
+}
+
+/// Shares a 9DOF sensor between `VirtualNineDof` clients.
+pub struct MuxNineDof<'a, N: NineDof<'a>> {
+    sensor: &'a N,
+    users: List<'a, VirtualNineDof<'a, N>>,
+    /// The read the sensor is doing.
+    inflight: OptionalCell<Reading>,
+}
+
+impl<'a, N: NineDof<'a>> MuxNineDof<'a, N> {
+    pub fn new(sensor: &'a N) -> MuxNineDof<'a, N> {
+        MuxNineDof {
+            sensor,
+            users: List::new(),
+            inflight: OptionalCell::empty(),
+        }
+    }
+
+    /// Start a read, unless the sensor is busy. If it is, the read is either
+    /// in progress or started when the sensor is done.
+    fn read(&self, reading: Reading) -> Result<(), ErrorCode> {
+        if self.inflight.is_some() {
+            return Ok(());
+        }
+
+        self.inflight.set(reading);
+        match reading {
+            Reading::Accelerometer => self.sensor.read_accelerometer(),
+            Reading::Magnetometer => self.sensor.read_magnetometer(),
+            Reading::Gyroscope => self.sensor.read_gyroscope(),
+        }
+        .inspect_err(|_| self.inflight.clear())
+    }
+
+    /// Start the first read a client is waiting on, dropping the reads the
+    /// sensor refuses.
+    fn do_next_read(&self) {
+        while let Some(reading) = self.users.iter().find_map(|user| user.pending.get()) {
+            if self.read(reading).is_ok() {
+                return;
+            }
+            for user in self.users.iter() {
+                if user.pending.contains(&reading) {
+                    user.pending.clear();
+                }
+            }
+        }
+    }
+}
+
+impl<'a, N: NineDof<'a>> NineDofClient for MuxNineDof<'a, N> {
+    fn callback(&self, arg1: usize, arg2: usize, arg3: usize) {
+        let Some(finished) = self.inflight.take() else {
+            return;
+        };
+
+        // Pick the clients to notify before calling any of them, so a read
+        // started from a callback waits for a new value.
+        for user in self.users.iter() {
+            if user.pending.contains(&finished) {
+                user.pending.clear();
+                user.notify.set(true);
+            }
+        }
+
+        // Queued reads of other quantities go before reads started from the
+        // callbacks.
+        self.do_next_read();
+
+        for user in self.users.iter() {
+            if user.notify.take() {
+                user.client.map(|client| client.callback(arg1, arg2, arg3));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use core::cell::RefCell;
+    use std::boxed::Box;
+    use std::vec::Vec;
+
+    /// A sensor that records its reads, and refuses gyroscope reads when
+    /// `refuse_gyroscope` is set.
+    struct FakeSensor {
+        reads: RefCell<Vec<Reading>>,
+        refuse_gyroscope: Cell<bool>,
+    }
+
+    impl FakeSensor {
+        fn read(&self, reading: Reading) -> Result<(), ErrorCode> {
+            if reading == Reading::Gyroscope && self.refuse_gyroscope.get() {
+                return Err(ErrorCode::NOSUPPORT);
+            }
+            self.reads.borrow_mut().push(reading);
+            Ok(())
+        }
+    }
+
+    impl<'a> NineDof<'a> for FakeSensor {
+        fn set_client(&self, _client: &'a dyn NineDofClient) {}
+
+        fn read_accelerometer(&self) -> Result<(), ErrorCode> {
+            self.read(Reading::Accelerometer)
+        }
+
+        fn read_magnetometer(&self) -> Result<(), ErrorCode> {
+            self.read(Reading::Magnetometer)
+        }
+
+        fn read_gyroscope(&self) -> Result<(), ErrorCode> {
+            self.read(Reading::Gyroscope)
+        }
+    }
+
+    /// Records the values it gets.
+    struct TestClient {
+        values: RefCell<Vec<(usize, usize, usize)>>,
+    }
+
+    impl NineDofClient for TestClient {
+        fn callback(&self, arg1: usize, arg2: usize, arg3: usize) {
+            self.values.borrow_mut().push((arg1, arg2, arg3));
+        }
+    }
+
+    type Virtual = VirtualNineDof<'static, FakeSensor>;
+
+    fn setup<const N: usize>() -> (
+        &'static MuxNineDof<'static, FakeSensor>,
+        &'static FakeSensor,
+        [(&'static Virtual, &'static TestClient); N],
+    ) {
+        let sensor = Box::leak(Box::new(FakeSensor {
+            reads: RefCell::new(Vec::new()),
+            refuse_gyroscope: Cell::new(false),
+        }));
+        let mux = Box::leak(Box::new(MuxNineDof::new(&*sensor)));
+        let users = core::array::from_fn(|_| {
+            let user: &'static Virtual = Box::leak(Box::new(VirtualNineDof::new(mux)));
+            user.setup();
+            let client = Box::leak(Box::new(TestClient {
+                values: RefCell::new(Vec::new()),
+            }));
+            user.set_client(client);
+            (user, &*client)
+        });
+        (mux, sensor, users)
+    }
+
+    #[test]
+    fn reads_are_queued() {
+        let (mux, sensor, [(a, client_a), (b, client_b), (c, client_c)]) = setup::<3>();
+        assert_eq!(a.read_accelerometer(), Ok(()));
+        assert_eq!(b.read_gyroscope(), Ok(()));
+        assert_eq!(c.read_accelerometer(), Ok(()));
+        assert_eq!(a.read_magnetometer(), Err(ErrorCode::BUSY));
+        assert_eq!(*sensor.reads.borrow(), [Reading::Accelerometer]);
+
+        // The accelerometer value goes to both clients that asked for it, and
+        // the queued gyroscope read starts.
+        mux.callback(1, 2, 3);
+        assert_eq!(*client_a.values.borrow(), [(1, 2, 3)]);
+        assert_eq!(*client_c.values.borrow(), [(1, 2, 3)]);
+        assert!(client_b.values.borrow().is_empty());
+        assert_eq!(
+            *sensor.reads.borrow(),
+            [Reading::Accelerometer, Reading::Gyroscope]
+        );
+
+        mux.callback(4, 5, 6);
+        assert_eq!(*client_b.values.borrow(), [(4, 5, 6)]);
+        assert_eq!(client_a.values.borrow().len(), 1);
+
+        // With nothing queued, the sensor stays idle.
+        assert_eq!(sensor.reads.borrow().len(), 2);
+        mux.callback(7, 8, 9);
+        assert_eq!(client_b.values.borrow().len(), 1);
+    }
+
+    #[test]
+    fn refused_queued_read_is_dropped() {
+        let (mux, sensor, [(a, _), (b, client_b), (c, client_c)]) = setup::<3>();
+        sensor.refuse_gyroscope.set(true);
+        assert_eq!(a.read_accelerometer(), Ok(()));
+        assert_eq!(b.read_gyroscope(), Ok(()));
+        assert_eq!(c.read_magnetometer(), Ok(()));
+
+        // The sensor refuses the queued gyroscope read, so the magnetometer
+        // read starts instead.
+        mux.callback(1, 2, 3);
+        assert_eq!(
+            *sensor.reads.borrow(),
+            [Reading::Accelerometer, Reading::Magnetometer]
+        );
+        mux.callback(4, 5, 6);
+        assert_eq!(*client_c.values.borrow(), [(4, 5, 6)]);
+        assert!(client_b.values.borrow().is_empty());
+
+        // The client whose read was dropped can read again.
+        sensor.refuse_gyroscope.set(false);
+        assert_eq!(b.read_gyroscope(), Ok(()));
+        mux.callback(7, 8, 9);
+        assert_eq!(*client_b.values.borrow(), [(7, 8, 9)]);
+    }
+}
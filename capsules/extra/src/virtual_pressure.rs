@@ -0,0 +1,198 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Virtualize a pressure sensor for multiple clients.
+//!
+//! Pressure sensor capsules accept a single client. This capsule lets
+//! several clients, such as the userspace pressure driver and an in-kernel
+//! altimeter, share one sensor.
+//!
+//! Reads are coalesced: a read requested while the sensor is already reading
+//! for another client does not start a second read, and the result of the
+//! read in progress is passed to every client that was waiting on it.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! let mux_pressure = components::pressure::PressureMuxComponent::new(lps22hb)
+//!     .finalize(components::pressure_mux_component_static!(Lps22hbSensor));
+//!
+//! let pressure_user = components::pressure::VirtualPressureComponent::new(mux_pressure)
+//!     .finalize(components::virtual_pressure_component_static!(Lps22hbSensor));
+//! ```
+
+use core::cell::Cell;
+
+use kernel::collections::list::{List, ListLink, ListNode};
+use kernel::hil::sensors::{PressureClient, PressureDriver};
+use kernel::utilities::cells::OptionalCell;
+use kernel::ErrorCode;
+
+/// A client of a shared pressure sensor.
+pub struct VirtualPressure<'a, T: PressureDriver<'a>> {
+    mux: &'a MuxPressure<'a, T>,
+    next: ListLink<'a, VirtualPressure<'a, T>>,
+    client: OptionalCell<&'a dyn PressureClient>,
+    /// Whether this client is waiting on a read.
+    pending: Cell<bool>,
+    /// Whether this client gets the result of the read that just finished.
+    notify: Cell<bool>,
+}
+
+impl<'a, T: PressureDriver<'a>> ListNode<'a, VirtualPressure<'a, T>> for VirtualPressure<'a, T> {
+    fn next(&self) -> &'a ListLink<VirtualPressure<'a, T>> {
+        &self.next
+    }
+}
+
+impl<'a, T: PressureDriver<'a>> VirtualPressure<'a, T> {
+    pub fn new(mux: &'a MuxPressure<'a, T>) -> VirtualPressure<'a, T> {
+        VirtualPressure {
+            mux,
+            next: ListLink::empty(),
+            client: OptionalCell::empty(),
+            pending: Cell::new(false),
+            notify: Cell::new(false),
+        }
+    }
+
+    pub fn setup(&'a self) {
+        self.mux.users.push_head(self);
+    }
+}
+
+impl<'a, T: PressureDriver<'a>> PressureDriver<'a> for VirtualPressure<'a, T> {
+    fn set_client(&self, client: &'a dyn PressureClient) {
+        self.client.set(client);
+    }
+
+    fn read_atmospheric_pressure(&self) -> Result<(), ErrorCode> {
+        if self.pending.get() {
+            return Err(ErrorCode::BUSY);
+        }
+
+        self.pending.set(true);
+        self.mux.read().inspect_err(|_| self.pending.set(false))
+    }
+}
+
+/// Shares a pressure sensor between `VirtualPressure` clients.
+pub struct MuxPressure<'a, T: PressureDriver<'a>> {
+    sensor: &'a T,
+    users: List<'a, VirtualPressure<'a, T>>,
+    /// Whether the sensor is reading.
+    busy: Cell<bool>,
+}
+
+impl<'a, T: PressureDriver<'a>> MuxPressure<'a, T> {
+    pub fn new(sensor: &'a T) -> MuxPressure<'a, T> {
+        MuxPressure {
+            sensor,
+            users: List::new(),
+            busy: Cell::new(false),
+        }
+    }
+
+    /// Start a read, unless one is already in progress.
+    fn read(&self) -> Result<(), ErrorCode> {
+        if self.busy.get() {
+            return Ok(());
+        }
+
+        self.busy.set(true);
+        self.sensor
+            .read_atmospheric_pressure()
+            .inspect_err(|_| self.busy.set(false))
+    }
+}
+
+impl<'a, T: PressureDriver<'a>> PressureClient for MuxPressure<'a, T> {
+    fn callback(&self, value: Result<u32, ErrorCode>) {
+        self.busy.set(false);
+
+        // Pick the clients to notify before calling any of them, so a read
+        // started from a callback waits for a new value.
+        for user in self.users.iter() {
+            user.notify.set(user.pending.take());
+        }
+        for user in self.users.iter() {
+            if user.notify.take() {
+                user.client.map(|client| client.callback(value));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use core::cell::RefCell;
+    use std::boxed::Box;
+    use std::vec::Vec;
+
+    /// A sensor that counts its reads.
+    struct FakeSensor {
+        reads: Cell<usize>,
+    }
+
+    impl<'a> PressureDriver<'a> for FakeSensor {
+        fn set_client(&self, _client: &'a dyn PressureClient) {}
+
+        fn read_atmospheric_pressure(&self) -> Result<(), ErrorCode> {
+            self.reads.set(self.reads.get() + 1);
+            Ok(())
+        }
+    }
+
+    /// Records the values it gets.
+    struct TestClient {
+        values: RefCell<Vec<Result<u32, ErrorCode>>>,
+    }
+
+    impl PressureClient for TestClient {
+        fn callback(&self, value: Result<u32, ErrorCode>) {
+            self.values.borrow_mut().push(value);
+        }
+    }
+
+    #[test]
+    fn reads_are_coalesced() {
+        let sensor = Box::leak(Box::new(FakeSensor {
+            reads: Cell::new(0),
+        }));
+        let mux = Box::leak(Box::new(MuxPressure::new(&*sensor)));
+        let clients: [&'static TestClient; 3] = core::array::from_fn(|_| {
+            &*Box::leak(Box::new(TestClient {
+                values: RefCell::new(Vec::new()),
+            }))
+        });
+        let [a, b, c] = clients.map(|client| {
+            let user: &'static VirtualPressure<'static, FakeSensor> =
+                Box::leak(Box::new(VirtualPressure::new(mux)));
+            user.setup();
+            user.set_client(client);
+            user
+        });
+
+        assert_eq!(a.read_atmospheric_pressure(), Ok(()));
+        assert_eq!(a.read_atmospheric_pressure(), Err(ErrorCode::BUSY));
+        assert_eq!(b.read_atmospheric_pressure(), Ok(()));
+        assert_eq!(sensor.reads.get(), 1);
+
+        mux.callback(Ok(1013));
+        assert_eq!(*clients[0].values.borrow(), [Ok(1013)]);
+        assert_eq!(*clients[1].values.borrow(), [Ok(1013)]);
+        assert!(clients[2].values.borrow().is_empty());
+
+        // The next read starts a new one.
+        assert_eq!(c.read_atmospheric_pressure(), Ok(()));
+        assert_eq!(sensor.reads.get(), 2);
+        mux.callback(Err(ErrorCode::FAIL));
+        assert_eq!(*clients[2].values.borrow(), [Err(ErrorCode::FAIL)]);
+        assert_eq!(clients[0].values.borrow().len(), 1);
+    }
+}
@@ -0,0 +1,255 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Virtualize a temperature sensor for multiple clients.
+//!
+//! Temperature sensor capsules accept a single client. This capsule lets
+//! several clients, such as the userspace temperature driver and an in-kernel
+//! fan controller, share one sensor.
+//!
+//! Reads are coalesced: a read requested while the sensor is already reading
+//! for another client does not start a second read, and the result of the
+//! read in progress is passed to every client that was waiting on it.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! let mux_temperature = components::temperature::TemperatureMuxComponent::new(sht4x)
+//!     .finalize(components::temperature_mux_component_static!(SHT4xSensor));
+//!
+//! let temperature_user = components::temperature::VirtualTemperatureComponent::new(
+//!     mux_temperature,
+//! )
+//! .finalize(components::virtual_temperature_component_static!(SHT4xSensor));
+//! ```
+
+use core::cell::Cell;
+
+use kernel::collections::list::{List, ListLink, ListNode};
+use kernel::hil::sensors::{TemperatureClient, TemperatureDriver};
+use kernel::utilities::cells::OptionalCell;
+use kernel::ErrorCode;
+
+/// A client of a shared temperature sensor.
+pub struct VirtualTemperature<'a, T: TemperatureDriver<'a>> {
+    mux: &'a MuxTemperature<'a, T>,
+    next: ListLink<'a, VirtualTemperature<'a, T>>,
+    client: OptionalCell<&'a dyn TemperatureClient>,
+    /// Whether this client is waiting on a read.
+    pending: Cell<bool>,
+    /// Whether this client gets the result of the read that just finished.
+    notify: Cell<bool>,
+}
+
+impl<'a, T: TemperatureDriver<'a>> ListNode<'a, VirtualTemperature<'a, T>>
+    for VirtualTemperature<'a, T>
+{
+    fn next(&self) -> &'a ListLink<VirtualTemperature<'a, T>> {
+        &self.next
+    }
+}
+
+impl<'a, T: TemperatureDriver<'a>> VirtualTemperature<'a, T> {
+    pub fn new(mux: &'a MuxTemperature<'a, T>) -> VirtualTemperature<'a, T> {
+        VirtualTemperature {
+            mux,
+            next: ListLink::empty(),
+            client: OptionalCell::empty(),
+            pending: Cell::new(false),
+            notify: Cell::new(false),
+        }
+    }
+
+    pub fn setup(&'a self) {
+        self.mux.users.push_head(self);
+    }
+}
+
+impl<'a, T: TemperatureDriver<'a>> TemperatureDriver<'a> for VirtualTemperature<'a, T> {
+    fn set_client(&self, client: &'a dyn TemperatureClient) {
+        self.client.set(client);
+    }
+
+    fn read_temperature(&self) -> Result<(), ErrorCode> {
+        if self.pending.get() {
+            return Err(ErrorCode::BUSY);
+        }
+
+        self.pending.set(true);
+        self.mux.read().inspect_err(|_| self.pending.set(false))
+    }
+}
+
+/// Shares a temperature sensor between `VirtualTemperature` clients.
+pub struct MuxTemperature<'a, T: TemperatureDriver<'a>> {
+    sensor: &'a T,
+    users: List<'a, VirtualTemperature<'a, T>>,
+    /// Whether the sensor is reading.
+    busy: Cell<bool>,
+}
+
+impl<'a, T: TemperatureDriver<'a>> MuxTemperature<'a, T> {
+    pub fn new(sensor: &'a T) -> MuxTemperature<'a, T> {
+        MuxTemperature {
+            sensor,
+            users: List::new(),
+            busy: Cell::new(false),
+        }
+    }
+
+    /// Start a read, unless one is already in progress.
+    fn read(&self) -> Result<(), ErrorCode> {
+        if self.busy.get() {
+            return Ok(());
+        }
+
+        self.busy.set(true);
+        self.sensor
+            .read_temperature()
+            .inspect_err(|_| self.busy.set(false))
+    }
+}
+
+impl<'a, T: TemperatureDriver<'a>> TemperatureClient for MuxTemperature<'a, T> {
+    fn callback(&self, value: Result<i32, ErrorCode>) {
+        self.busy.set(false);
+
+        // Pick the clients to notify before calling any of them, so a read
+        // started from a callback waits for a new value.
+        for user in self.users.iter() {
+            user.notify.set(user.pending.take());
+        }
+        for user in self.users.iter() {
+            if user.notify.take() {
+                user.client.map(|client| client.callback(value));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use core::cell::RefCell;
+    use std::boxed::Box;
+    use std::vec::Vec;
+
+    /// A sensor that counts its reads, and refuses them when `fail` is set.
+    struct FakeSensor {
+        reads: Cell<usize>,
+        fail: Cell<bool>,
+    }
+
+    impl<'a> TemperatureDriver<'a> for FakeSensor {
+        fn set_client(&self, _client: &'a dyn TemperatureClient) {}
+
+        fn read_temperature(&self) -> Result<(), ErrorCode> {
+            if self.fail.get() {
+                return Err(ErrorCode::FAIL);
+            }
+            self.reads.set(self.reads.get() + 1);
+            Ok(())
+        }
+    }
+
+    type Virtual = VirtualTemperature<'static, FakeSensor>;
+
+    /// Records the values it gets, and reads again from the callback while
+    /// `again` is set.
+    struct TestClient {
+        values: RefCell<Vec<Result<i32, ErrorCode>>>,
+        again: Cell<bool>,
+        user: OptionalCell<&'static Virtual>,
+    }
+
+    impl TemperatureClient for TestClient {
+        fn callback(&self, value: Result<i32, ErrorCode>) {
+            self.values.borrow_mut().push(value);
+            if self.again.take() {
+                self.user
+                    .map(|user| assert_eq!(user.read_temperature(), Ok(())));
+            }
+        }
+    }
+
+    fn setup<const N: usize>() -> (
+        &'static MuxTemperature<'static, FakeSensor>,
+        &'static FakeSensor,
+        [(&'static Virtual, &'static TestClient); N],
+    ) {
+        let sensor = Box::leak(Box::new(FakeSensor {
+            reads: Cell::new(0),
+            fail: Cell::new(false),
+        }));
+        let mux = Box::leak(Box::new(MuxTemperature::new(&*sensor)));
+        let users = core::array::from_fn(|_| {
+            let user: &'static Virtual = Box::leak(Box::new(VirtualTemperature::new(mux)));
+            user.setup();
+            let client = Box::leak(Box::new(TestClient {
+                values: RefCell::new(Vec::new()),
+                again: Cell::new(false),
+                user: OptionalCell::new(user),
+            }));
+            user.set_client(client);
+            (user, &*client)
+        });
+        (mux, sensor, users)
+    }
+
+    #[test]
+    fn reads_are_coalesced() {
+        let (mux, sensor, [(a, client_a), (b, client_b), (_, client_c)]) = setup::<3>();
+        assert_eq!(a.read_temperature(), Ok(()));
+        assert_eq!(a.read_temperature(), Err(ErrorCode::BUSY));
+        assert_eq!(b.read_temperature(), Ok(()));
+        assert_eq!(sensor.reads.get(), 1);
+
+        mux.callback(Ok(2150));
+        assert_eq!(*client_a.values.borrow(), [Ok(2150)]);
+        assert_eq!(*client_b.values.borrow(), [Ok(2150)]);
+        // Only the clients that asked get the value.
+        assert!(client_c.values.borrow().is_empty());
+
+        // Errors are passed on too.
+        assert_eq!(b.read_temperature(), Ok(()));
+        assert_eq!(sensor.reads.get(), 2);
+        mux.callback(Err(ErrorCode::FAIL));
+        assert_eq!(*client_b.values.borrow(), [Ok(2150), Err(ErrorCode::FAIL)]);
+        assert_eq!(client_a.values.borrow().len(), 1);
+    }
+
+    #[test]
+    fn read_from_callback_waits_for_new_value() {
+        let (mux, sensor, [(a, client_a), (b, client_b)]) = setup::<2>();
+        client_a.again.set(true);
+        assert_eq!(a.read_temperature(), Ok(()));
+        assert_eq!(b.read_temperature(), Ok(()));
+
+        mux.callback(Ok(1));
+        assert_eq!(*client_a.values.borrow(), [Ok(1)]);
+        assert_eq!(*client_b.values.borrow(), [Ok(1)]);
+        assert_eq!(sensor.reads.get(), 2);
+
+        mux.callback(Ok(2));
+        assert_eq!(*client_a.values.borrow(), [Ok(1), Ok(2)]);
+        assert_eq!(*client_b.values.borrow(), [Ok(1)]);
+    }
+
+    #[test]
+    fn refused_read() {
+        let (mux, sensor, [(a, client_a)]) = setup::<1>();
+        sensor.fail.set(true);
+        assert_eq!(a.read_temperature(), Err(ErrorCode::FAIL));
+
+        // Neither the client nor the mux are left waiting.
+        sensor.fail.set(false);
+        assert_eq!(a.read_temperature(), Ok(()));
+        assert_eq!(sensor.reads.get(), 1);
+        mux.callback(Ok(3));
+        assert_eq!(*client_a.values.borrow(), [Ok(3)]);
+    }
+}
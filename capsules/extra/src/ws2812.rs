@@ -0,0 +1,416 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Driver for WS2812 and SK6812 addressable LED strips over SPI.
+//!
+//! Implements `hil::led_strip::LedStrip` by sending the data line of the
+//! strip on the data out pin of an SPI bus, without a clock. Each bit for
+//! the LEDs is four SPI bits at 3.2 MHz, `1000` for a 0 and `1110` for a 1,
+//! which gives the high and low times the LEDs expect. Frames start with
+//! 300 us of low data, which latches the previous frame.
+//!
+//! The whole encoded frame is sent in one SPI transfer, so the buffer given
+//! must hold four bytes per byte of LED data; `buffer_len` computes its size. The chip
+//! select of the SPI device is not used by the strip.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! let ws2812 = components::ws2812::Ws2812Component::new(
+//!     mux_spi,
+//!     &peripherals.pins.get_pin(RPGpio::GPIO17),
+//!     PixelOrder::Grb,
+//! )
+//! .finalize(components::ws2812_component_static!(
+//!     rp2040::spi::Spi,
+//!     60,
+//!     PixelOrder::Grb
+//! ));
+//! ```
+
+use core::cell::Cell;
+
+use kernel::hil::led_strip::{LedStrip, LedStripClient};
+use kernel::hil::spi::{ClockPhase, ClockPolarity, SpiMasterClient, SpiMasterDevice};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::ErrorCode;
+
+/// The SPI rate asked for, and the range of rates the LEDs accept.
+const RATE: u32 = 3_200_000;
+const MIN_RATE: u32 = 2_200_000;
+const MAX_RATE: u32 = 4_600_000;
+
+/// The SPI bytes for one byte of LED data.
+const SPI_BYTES: usize = 4;
+/// The low data at the start of frames, 300 us at the highest rate.
+const RESET_LEN: usize = (300 * MAX_RATE as usize / 1_000_000 + 7) / 8;
+
+const ZERO: u8 = 0b1000;
+const ONE: u8 = 0b1110;
+
+/// The order the LEDs take colour channels in.
+#[derive(Clone, Copy, PartialEq)]
+pub enum PixelOrder {
+    /// Green, red and blue, as WS2812 and SK6812 RGB LEDs take them.
+    Grb,
+    /// Green, red, blue and white, as SK6812 RGBW LEDs take them.
+    Grbw,
+}
+
+impl PixelOrder {
+    pub const fn channels(self) -> usize {
+        match self {
+            PixelOrder::Grb => 3,
+            PixelOrder::Grbw => 4,
+        }
+    }
+
+    /// The channel of frames, in RGBW order, sent at each position.
+    fn channel(self, position: usize) -> usize {
+        [1, 0, 2, 3][position]
+    }
+}
+
+/// The SPI buffer needed for a strip of `pixels` LEDs.
+pub const fn buffer_len(pixels: usize, order: PixelOrder) -> usize {
+    RESET_LEN + pixels * order.channels() * SPI_BYTES
+}
+
+pub struct Ws2812<'a, S: SpiMasterDevice<'a>> {
+    spi: &'a S,
+    client: OptionalCell<&'a dyn LedStripClient>,
+    order: PixelOrder,
+    pixels: usize,
+    configured: Cell<bool>,
+    buffer: TakeCell<'static, [u8]>,
+    frame: TakeCell<'static, [u8]>,
+}
+
+impl<'a, S: SpiMasterDevice<'a>> Ws2812<'a, S> {
+    /// Create the driver for as many LEDs as `buffer` has room for.
+    pub fn new(spi: &'a S, order: PixelOrder, buffer: &'static mut [u8]) -> Self {
+        let pixels = buffer.len().saturating_sub(RESET_LEN) / (order.channels() * SPI_BYTES);
+        buffer.fill(0);
+        Ws2812 {
+            spi,
+            client: OptionalCell::empty(),
+            order,
+            pixels,
+            configured: Cell::new(false),
+            buffer: TakeCell::new(buffer),
+            frame: TakeCell::empty(),
+        }
+    }
+
+    /// Set the SPI rate. Frames fail with `OFF` until it succeeds, and it
+    /// fails with `INVAL` if the bus cannot run at a rate the LEDs accept.
+    pub fn init(&self) -> Result<(), ErrorCode> {
+        self.spi
+            .configure(ClockPolarity::IdleLow, ClockPhase::SampleLeading, RATE)?;
+        if !(MIN_RATE..=MAX_RATE).contains(&self.spi.get_rate()) {
+            return Err(ErrorCode::INVAL);
+        }
+        self.configured.set(true);
+        Ok(())
+    }
+
+    /// Encode the pixels in `frame` after the low data.
+    fn encode(&self, frame: &[u8], buffer: &mut [u8]) {
+        let channels = self.order.channels();
+        let out = buffer[RESET_LEN..].chunks_mut(channels * SPI_BYTES);
+        for (pixel, out) in frame.chunks(channels).zip(out) {
+            for (position, out) in out.chunks_mut(SPI_BYTES).enumerate() {
+                let byte = pixel[self.order.channel(position)];
+                for (i, out) in out.iter_mut().enumerate() {
+                    let bits = byte >> (6 - 2 * i);
+                    let high = if bits & 2 != 0 { ONE } else { ZERO };
+                    let low = if bits & 1 != 0 { ONE } else { ZERO };
+                    *out = high << 4 | low;
+                }
+            }
+        }
+    }
+}
+
+impl<'a, S: SpiMasterDevice<'a>> LedStrip<'a> for Ws2812<'a, S> {
+    fn set_client(&self, client: &'a dyn LedStripClient) {
+        self.client.set(client);
+    }
+
+    fn pixels(&self) -> usize {
+        self.pixels
+    }
+
+    fn channels(&self) -> usize {
+        self.order.channels()
+    }
+
+    fn show(
+        &self,
+        frame: &'static mut [u8],
+        len: usize,
+    ) -> Result<(), (ErrorCode, &'static mut [u8])> {
+        let channels = self.order.channels();
+        if len > frame.len() || len % channels != 0 || len / channels > self.pixels {
+            return Err((ErrorCode::SIZE, frame));
+        }
+        if !self.configured.get() {
+            return Err((ErrorCode::OFF, frame));
+        }
+        let Some(buffer) = self.buffer.take() else {
+            return Err((ErrorCode::BUSY, frame));
+        };
+        self.encode(&frame[..len], buffer);
+        match self
+            .spi
+            .read_write_bytes(buffer, None, RESET_LEN + len * SPI_BYTES)
+        {
+            Ok(()) => {
+                self.frame.replace(frame);
+                Ok(())
+            }
+            Err((e, buffer, _)) => {
+                self.buffer.replace(buffer);
+                Err((e, frame))
+            }
+        }
+    }
+}
+
+impl<'a, S: SpiMasterDevice<'a>> SpiMasterClient for Ws2812<'a, S> {
+    fn read_write_done(
+        &self,
+        write_buffer: &'static mut [u8],
+        _read_buffer: Option<&'static mut [u8]>,
+        _len: usize,
+        status: Result<(), ErrorCode>,
+    ) {
+        self.buffer.replace(write_buffer);
+        if let Some(frame) = self.frame.take() {
+            self.client
+                .map(move |client| client.show_done(frame, status));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use kernel::utilities::cells::MapCell;
+    use std::boxed::Box;
+    use std::vec::Vec;
+
+    /// An SPI device that runs at `rate` once configured, and completes a
+    /// transfer when `complete()` is called.
+    struct FakeSpi {
+        client: OptionalCell<&'static dyn SpiMasterClient>,
+        rate: u32,
+        configured_rate: Cell<u32>,
+        pending: MapCell<(&'static mut [u8], usize)>,
+    }
+
+    impl FakeSpi {
+        fn complete(&self) -> Vec<u8> {
+            let (buffer, len) = self.pending.take().unwrap();
+            let sent = buffer[..len].to_vec();
+            self.client
+                .map(move |client| client.read_write_done(buffer, None, len, Ok(())));
+            sent
+        }
+    }
+
+    impl SpiMasterDevice<'static> for FakeSpi {
+        fn set_client(&self, client: &'static dyn SpiMasterClient) {
+            self.client.set(client);
+        }
+
+        fn configure(
+            &self,
+            cpol: ClockPolarity,
+            cpal: ClockPhase,
+            _rate: u32,
+        ) -> Result<(), ErrorCode> {
+            assert!(cpol == ClockPolarity::IdleLow && cpal == ClockPhase::SampleLeading);
+            self.configured_rate.set(self.rate);
+            Ok(())
+        }
+
+        fn read_write_bytes(
+            &self,
+            write_buffer: &'static mut [u8],
+            read_buffer: Option<&'static mut [u8]>,
+            len: usize,
+        ) -> Result<(), (ErrorCode, &'static mut [u8], Option<&'static mut [u8]>)> {
+            assert!(len <= write_buffer.len());
+            self.pending.replace((write_buffer, len));
+            assert!(read_buffer.is_none());
+            Ok(())
+        }
+
+        fn set_rate(&self, rate: u32) -> Result<(), ErrorCode> {
+            self.configured_rate.set(rate);
+            Ok(())
+        }
+
+        fn get_rate(&self) -> u32 {
+            self.configured_rate.get()
+        }
+
+        fn set_polarity(&self, _polarity: ClockPolarity) -> Result<(), ErrorCode> {
+            Ok(())
+        }
+
+        fn get_polarity(&self) -> ClockPolarity {
+            ClockPolarity::IdleLow
+        }
+
+        fn set_phase(&self, _phase: ClockPhase) -> Result<(), ErrorCode> {
+            Ok(())
+        }
+
+        fn get_phase(&self) -> ClockPhase {
+            ClockPhase::SampleLeading
+        }
+    }
+
+    struct FakeClient {
+        result: MapCell<(&'static mut [u8], Result<(), ErrorCode>)>,
+    }
+
+    impl LedStripClient for FakeClient {
+        fn show_done(&self, frame: &'static mut [u8], result: Result<(), ErrorCode>) {
+            self.result.replace((frame, result));
+        }
+    }
+
+    struct Setup {
+        ws2812: &'static Ws2812<'static, FakeSpi>,
+        spi: &'static FakeSpi,
+        client: &'static FakeClient,
+    }
+
+    fn setup(order: PixelOrder, pixels: usize, rate: u32) -> Setup {
+        let spi = Box::leak(Box::new(FakeSpi {
+            client: OptionalCell::empty(),
+            rate,
+            configured_rate: Cell::new(0),
+            pending: MapCell::empty(),
+        }));
+        let buffer = Box::leak(std::vec![0xFF; buffer_len(pixels, order)].into_boxed_slice());
+        let ws2812 = Box::leak(Box::new(Ws2812::new(spi, order, buffer)));
+        let client = Box::leak(Box::new(FakeClient {
+            result: MapCell::empty(),
+        }));
+        spi.set_client(ws2812);
+        ws2812.set_client(client);
+        Setup {
+            ws2812,
+            spi,
+            client,
+        }
+    }
+
+    /// Decode the bits sent to the LEDs after the low data, checking that
+    /// each is four SPI bits of either pattern.
+    fn decode(sent: &[u8]) -> Vec<u8> {
+        assert!(sent[..RESET_LEN].iter().all(|byte| *byte == 0));
+        let nibbles = sent[RESET_LEN..]
+            .iter()
+            .flat_map(|byte| [byte >> 4, byte & 0xF]);
+        let bits: Vec<u8> = nibbles
+            .map(|nibble| match nibble {
+                0b1000 => 0,
+                0b1110 => 1,
+                _ => panic!("invalid bit {:04b}", nibble),
+            })
+            .collect();
+        bits.chunks(8)
+            .map(|bits| bits.iter().fold(0, |byte, bit| byte << 1 | bit))
+            .collect()
+    }
+
+    #[test]
+    fn bit_encoding() {
+        let setup = setup(PixelOrder::Grb, 1, RATE);
+        let mut buffer = [0; RESET_LEN + 12];
+        setup.ws2812.encode(&[0x00, 0xA5, 0xFF], &mut buffer);
+        // Each pair of bits, most significant first, is one SPI byte: the
+        // green byte comes first.
+        assert_eq!(
+            buffer[RESET_LEN..],
+            [
+                0xE8, 0xE8, 0x8E, 0x8E, // 0xA5
+                0x88, 0x88, 0x88, 0x88, // 0x00
+                0xEE, 0xEE, 0xEE, 0xEE, // 0xFF
+            ]
+        );
+    }
+
+    #[test]
+    fn timing() {
+        // A 0 is high for one SPI bit and a 1 for three, 312 and 937 ns,
+        // within the 220-380 ns and 580-1000 ns the LEDs accept.
+        let bit_ns = 1_000_000_000 / RATE;
+        assert!((220..=380).contains(&(bit_ns * ZERO.count_ones())));
+        assert!((580..=1000).contains(&(bit_ns * ONE.count_ones())));
+        // The low data lasts 300 us even at the highest rate.
+        assert!(RESET_LEN as u32 * 8 * 1_000_000 / MAX_RATE >= 300);
+    }
+
+    #[test]
+    fn show_grb() {
+        let setup = setup(PixelOrder::Grb, 3, RATE);
+        assert_eq!(setup.ws2812.pixels(), 3);
+        assert_eq!(setup.ws2812.channels(), 3);
+        assert_eq!(setup.ws2812.init(), Ok(()));
+
+        // Two of the three pixels, in RGB order.
+        let frame = Box::leak(Box::new([0x10, 0x20, 0x30, 0xC1, 0xC2, 0xC3]));
+        assert!(setup.ws2812.show(frame, 6).is_ok());
+        let sent = setup.spi.complete();
+        assert_eq!(sent.len(), RESET_LEN + 6 * SPI_BYTES);
+        assert_eq!(decode(&sent), [0x20, 0x10, 0x30, 0xC2, 0xC1, 0xC3]);
+        let (frame, result) = setup.client.result.take().unwrap();
+        assert_eq!(result, Ok(()));
+        assert_eq!(frame[0], 0x10);
+    }
+
+    #[test]
+    fn show_grbw() {
+        let setup = setup(PixelOrder::Grbw, 1, RATE);
+        assert_eq!(setup.ws2812.channels(), 4);
+        assert_eq!(setup.ws2812.init(), Ok(()));
+        let frame = Box::leak(Box::new([0x01, 0x02, 0x03, 0x04]));
+        assert!(setup.ws2812.show(frame, 4).is_ok());
+        assert_eq!(decode(&setup.spi.complete()), [0x02, 0x01, 0x03, 0x04]);
+    }
+
+    #[test]
+    fn errors() {
+        // The bus can't run fast enough.
+        let setup = self::setup(PixelOrder::Grb, 2, 2_000_000);
+        assert_eq!(setup.ws2812.init(), Err(ErrorCode::INVAL));
+        let frame = Box::leak(Box::new([0; 9]));
+        let (error, frame) = setup.ws2812.show(frame, 6).unwrap_err();
+        assert_eq!(error, ErrorCode::OFF);
+
+        let setup = self::setup(PixelOrder::Grb, 2, 4_000_000);
+        assert_eq!(setup.ws2812.init(), Ok(()));
+        // Not whole pixels, more pixels than the strip has, or more than
+        // the frame holds.
+        let mut frame = frame;
+        for len in [4, 9, 10] {
+            let (error, returned) = setup.ws2812.show(frame, len).unwrap_err();
+            assert_eq!(error, ErrorCode::SIZE);
+            frame = returned;
+        }
+        assert!(setup.ws2812.show(frame, 6).is_ok());
+        let other = Box::leak(Box::new([0; 3]));
+        let (error, _) = setup.ws2812.show(other, 3).unwrap_err();
+        assert_eq!(error, ErrorCode::BUSY);
+    }
+}
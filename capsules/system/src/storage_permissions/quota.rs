@@ -0,0 +1,58 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+use kernel::platform::chip::Chip;
+use kernel::process::Process;
+use kernel::process::ProcessStandardStoragePermissionsPolicy;
+use kernel::process::ShortId;
+use kernel::storage_permissions::{StoragePermissions, StorageQuota};
+
+/// Add storage quotas to the permissions assigned by another policy.
+///
+/// Every process with a fixed ShortId is limited to `default_quota`, unless
+/// its ShortId is listed in `quotas`, in which case that quota is used
+/// instead. Processes without a fixed ShortId are not given a quota, but they
+/// can't write to storage either.
+pub struct QuotaStoragePermissions<'a, C: Chip, P: ProcessStandardStoragePermissionsPolicy<C>> {
+    policy: &'a P,
+    default_quota: StorageQuota,
+    quotas: &'a [(u32, StorageQuota)],
+    _chip: core::marker::PhantomData<C>,
+}
+
+impl<'a, C: Chip, P: ProcessStandardStoragePermissionsPolicy<C>> QuotaStoragePermissions<'a, C, P> {
+    pub fn new(
+        policy: &'a P,
+        default_quota: StorageQuota,
+        quotas: &'a [(u32, StorageQuota)],
+    ) -> Self {
+        Self {
+            policy,
+            default_quota,
+            quotas,
+            _chip: core::marker::PhantomData,
+        }
+    }
+}
+
+impl<C: Chip, P: ProcessStandardStoragePermissionsPolicy<C>>
+    ProcessStandardStoragePermissionsPolicy<C> for QuotaStoragePermissions<'_, C, P>
+{
+    fn get_permissions(&self, process: &kernel::process::ProcessStandard<C>) -> StoragePermissions {
+        let permissions = self.policy.get_permissions(process);
+
+        match process.short_app_id() {
+            ShortId::Fixed(id) => {
+                let quota = self
+                    .quotas
+                    .iter()
+                    .find(|(short_id, _)| *short_id == u32::from(id))
+                    .map_or(self.default_quota, |(_, quota)| *quota);
+
+                permissions.with_quota(quota)
+            }
+            ShortId::LocallyUnique => permissions,
+        }
+    }
+}
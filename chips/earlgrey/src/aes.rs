@@ -11,7 +11,9 @@ use core::cell::Cell;
 use kernel::deferred_call::{DeferredCall, DeferredCallClient};
 use kernel::hil;
 use kernel::hil::symmetric_encryption;
-use kernel::hil::symmetric_encryption::{AES128_BLOCK_SIZE, AES128_KEY_SIZE};
+use kernel::hil::symmetric_encryption::{
+    AES128_BLOCK_SIZE, AES128_KEY_SIZE, AES192_KEY_SIZE, AES256_KEY_SIZE,
+};
 use kernel::utilities::cells::{OptionalCell, TakeCell};
 use kernel::utilities::registers::interfaces::{Readable, Writeable};
 use kernel::utilities::registers::{
@@ -124,6 +126,8 @@ pub struct Aes<'a> {
     source: TakeCell<'static, [u8]>,
     dest: TakeCell<'static, [u8]>,
     mode: Cell<Mode>,
+    encrypting: Cell<bool>,
+    key_len: Cell<usize>,
 
     deferred_call: DeferredCall,
 }
@@ -136,6 +140,8 @@ impl<'a> Aes<'a> {
             source: TakeCell::empty(),
             dest: TakeCell::empty(),
             mode: Cell::new(Mode::IDLE),
+            encrypting: Cell::new(true),
+            key_len: Cell::new(AES128_KEY_SIZE),
             deferred_call: DeferredCall::new(),
         }
     }
@@ -163,6 +169,32 @@ impl<'a> Aes<'a> {
         Err(ErrorCode::BUSY)
     }
 
+    /// Write the control register for the current mode, direction and key
+    /// length.
+    fn configure(&self) {
+        let mut ctrl = if self.encrypting.get() {
+            CTRL::OPERATION::Encrypting
+        } else {
+            CTRL::OPERATION::Decrypting
+        };
+        ctrl += match self.mode.get() {
+            Mode::AES128CTR => CTRL::MODE::AES_CTR,
+            Mode::AES128CBC => CTRL::MODE::AES_CBC,
+            Mode::AES128ECB => CTRL::MODE::AES_ECB,
+            Mode::IDLE => return,
+        };
+        ctrl += match self.key_len.get() {
+            AES192_KEY_SIZE => CTRL::KEY_LEN::Key192,
+            AES256_KEY_SIZE => CTRL::KEY_LEN::Key256,
+            _ => CTRL::KEY_LEN::Key128,
+        };
+        ctrl += CTRL::MANUAL_OPERATION::CLEAR;
+
+        // We need to set the control register twice as it's shadowed
+        self.registers.ctrl.write(ctrl);
+        self.registers.ctrl.write(ctrl);
+    }
+
     fn input_ready(&self) -> bool {
         self.registers.status.is_set(STATUS::INPUT_READY)
     }
@@ -337,50 +369,59 @@ impl<'a> hil::symmetric_encryption::AES128<'a> for Aes<'a> {
     fn set_key(&self, key: &[u8]) -> Result<(), ErrorCode> {
         self.wait_on_idle_ready()?;
 
-        if key.len() != AES128_KEY_SIZE {
+        if !symmetric_encryption::aes_key_size_valid(key.len()) {
             return Err(ErrorCode::INVAL);
         }
 
-        for i in 0..(AES128_KEY_SIZE / 4) {
-            let mut k = key[i * 4 + 0] as u32;
-            k |= (key[i * 4 + 1] as u32) << 8;
-            k |= (key[i * 4 + 2] as u32) << 16;
-            k |= (key[i * 4 + 3] as u32) << 24;
-            match i {
-                0 => {
-                    self.registers.key_share0_0.set(k);
-                    self.registers.key_share1_0.set(0);
-                }
-                1 => {
-                    self.registers.key_share0_1.set(k);
-                    self.registers.key_share1_1.set(0);
-                }
-                2 => {
-                    self.registers.key_share0_2.set(k);
-                    self.registers.key_share1_2.set(0);
-                }
-                3 => {
-                    self.registers.key_share0_3.set(k);
-                    self.registers.key_share1_3.set(0);
+        // The key length is part of the control register, which has to be
+        // configured before the key is written.
+        if self.key_len.get() != key.len() {
+            self.key_len.set(key.len());
+            self.configure();
+        }
+
+        let share0 = [
+            &self.registers.key_share0_0,
+            &self.registers.key_share0_1,
+            &self.registers.key_share0_2,
+            &self.registers.key_share0_3,
+            &self.registers.key_share0_4,
+            &self.registers.key_share0_5,
+            &self.registers.key_share0_6,
+            &self.registers.key_share0_7,
+        ];
+        let share1 = [
+            &self.registers.key_share1_0,
+            &self.registers.key_share1_1,
+            &self.registers.key_share1_2,
+            &self.registers.key_share1_3,
+            &self.registers.key_share1_4,
+            &self.registers.key_share1_5,
+            &self.registers.key_share1_6,
+            &self.registers.key_share1_7,
+        ];
+
+        // We must write the unused registers as well
+        // This should be written with random data, for now this will do
+        let filler = [0x12, 0x34, 0x56, 0x78, 0xAB, 0xCD, 0xEF, 0x00];
+
+        for i in 0..share0.len() {
+            match key.get(i * 4..i * 4 + 4) {
+                Some(word) => {
+                    let mut k = word[0] as u32;
+                    k |= (word[1] as u32) << 8;
+                    k |= (word[2] as u32) << 16;
+                    k |= (word[3] as u32) << 24;
+                    share0[i].set(k);
+                    share1[i].set(0);
                 }
-                _ => {
-                    unreachable!()
+                None => {
+                    share0[i].set(filler[i % 4]);
+                    share1[i].set(filler[4 + i % 4]);
                 }
             }
         }
 
-        // We must write the rest of the registers as well
-        // This should be written with random data, for now this will do
-        self.registers.key_share0_4.set(0x12);
-        self.registers.key_share0_5.set(0x34);
-        self.registers.key_share0_6.set(0x56);
-        self.registers.key_share0_7.set(0x78);
-
-        self.registers.key_share1_4.set(0xAB);
-        self.registers.key_share1_5.set(0xCD);
-        self.registers.key_share1_6.set(0xEF);
-        self.registers.key_share1_7.set(0x00);
-
         Ok(())
     }
 
@@ -440,20 +481,8 @@ impl kernel::hil::symmetric_encryption::AES128Ctr for Aes<'_> {
     fn set_mode_aes128ctr(&self, encrypting: bool) -> Result<(), ErrorCode> {
         self.wait_on_idle_ready()?;
         self.mode.set(Mode::AES128CTR);
-
-        let mut ctrl = if encrypting {
-            CTRL::OPERATION::Encrypting
-        } else {
-            CTRL::OPERATION::Decrypting
-        };
-        ctrl += CTRL::MODE::AES_CTR;
-        // Tock only supports 128-bit keys
-        ctrl += CTRL::KEY_LEN::Key128;
-        ctrl += CTRL::MANUAL_OPERATION::CLEAR;
-
-        // We need to set the control register twice as it's shadowed
-        self.registers.ctrl.write(ctrl);
-        self.registers.ctrl.write(ctrl);
+        self.encrypting.set(encrypting);
+        self.configure();
 
         Ok(())
     }
@@ -463,20 +492,8 @@ impl kernel::hil::symmetric_encryption::AES128ECB for Aes<'_> {
     fn set_mode_aes128ecb(&self, encrypting: bool) -> Result<(), ErrorCode> {
         self.wait_on_idle_ready()?;
         self.mode.set(Mode::AES128ECB);
-
-        let mut ctrl = if encrypting {
-            CTRL::OPERATION::Encrypting
-        } else {
-            CTRL::OPERATION::Decrypting
-        };
-        ctrl += CTRL::MODE::AES_ECB;
-        // Tock only supports 128-bit keys
-        ctrl += CTRL::KEY_LEN::Key128;
-        ctrl += CTRL::MANUAL_OPERATION::CLEAR;
-
-        // We need to set the control register twice as it's shadowed
-        self.registers.ctrl.write(ctrl);
-        self.registers.ctrl.write(ctrl);
+        self.encrypting.set(encrypting);
+        self.configure();
 
         Ok(())
     }
@@ -486,20 +503,8 @@ impl kernel::hil::symmetric_encryption::AES128CBC for Aes<'_> {
     fn set_mode_aes128cbc(&self, encrypting: bool) -> Result<(), ErrorCode> {
         self.wait_on_idle_ready()?;
         self.mode.set(Mode::AES128CBC);
-
-        let mut ctrl = if encrypting {
-            CTRL::OPERATION::Encrypting
-        } else {
-            CTRL::OPERATION::Decrypting
-        };
-        ctrl += CTRL::MODE::AES_CBC;
-        // Tock only supports 128-bit keys
-        ctrl += CTRL::KEY_LEN::Key128;
-        ctrl += CTRL::MANUAL_OPERATION::CLEAR;
-
-        // We need to set the control register twice as it's shadowed
-        self.registers.ctrl.write(ctrl);
-        self.registers.ctrl.write(ctrl);
+        self.encrypting.set(encrypting);
+        self.configure();
 
         Ok(())
     }
@@ -45,6 +45,7 @@ pub struct EarlGreyDefaultPeripherals<'a, CFG: EarlGreyConfig, PINMUX: EarlGreyP
     pub otbn: lowrisc::otbn::Otbn<'a>,
     pub gpio_port: crate::gpio::Port<'a>,
     pub i2c0: lowrisc::i2c::I2c<'a>,
+    pub keymgr: lowrisc::keymgr::Keymgr<'a>,
     pub spi_host0: lowrisc::spi_host::SpiHost<'a>,
     pub spi_host1: lowrisc::spi_host::SpiHost<'a>,
     pub flash_ctrl: lowrisc::flash_ctrl::FlashCtrl<'a>,
@@ -66,6 +67,7 @@ impl<'a, CFG: EarlGreyConfig, PINMUX: EarlGreyPinmuxConfig>
             otbn: lowrisc::otbn::Otbn::new(crate::otbn::OTBN_BASE),
             gpio_port: crate::gpio::Port::new::<PINMUX>(),
             i2c0: lowrisc::i2c::I2c::new(crate::i2c::I2C0_BASE, (1 / CFG::CPU_FREQ) * 1000 * 1000),
+            keymgr: lowrisc::keymgr::Keymgr::new(crate::keymgr::KEYMGR_BASE),
             spi_host0: lowrisc::spi_host::SpiHost::new(
                 crate::spi_host::SPIHOST0_BASE,
                 CFG::CPU_FREQ,
@@ -120,6 +122,7 @@ impl<'a, CFG: EarlGreyConfig, PINMUX: EarlGreyPinmuxConfig> InterruptService
                 self.i2c0.handle_interrupt()
             }
             interrupts::OTBN_DONE => self.otbn.handle_interrupt(),
+            interrupts::KEYMGR_OP_DONE => self.keymgr.handle_interrupt(),
             interrupts::CSRNG_CSCMDREQDONE..=interrupts::CSRNG_CSFATALERR => {
                 self.rng.handle_interrupt()
             }
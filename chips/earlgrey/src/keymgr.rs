@@ -0,0 +1,10 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+use crate::registers::top_earlgrey::KEYMGR_BASE_ADDR;
+use kernel::utilities::StaticRef;
+use lowrisc::keymgr::KeymgrRegisters;
+
+pub const KEYMGR_BASE: StaticRef<KeymgrRegisters> =
+    unsafe { StaticRef::new(KEYMGR_BASE_ADDR as *const KeymgrRegisters) };
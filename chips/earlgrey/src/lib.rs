@@ -25,6 +25,7 @@ pub mod flash_ctrl;
 pub mod gpio;
 pub mod hmac;
 pub mod i2c;
+pub mod keymgr;
 pub mod otbn;
 pub mod pinmux;
 pub mod plic;
@@ -0,0 +1,193 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Support for the key manager on OpenTitan.
+//!
+//! The key manager derives keys from the device secrets. This driver
+//! generates software readable keys from the sealing CDI, so the same salt and
+//! version always produce the same key for the current owner.
+//!
+//! Keys can only be generated once the key manager has advanced to the
+//! creator root key state. If it is still in the reset or init state it is
+//! advanced to the creator root key before generating the key. The ROM
+//! normally does this before the kernel starts.
+//!
+//! <https://opentitan.org/book/hw/ip/keymgr/doc/theory_of_operation.html>
+
+use crate::registers::keymgr_regs::{
+    CONTROL_SHADOWED, ERR_CODE, INTR, KEYMGR_PARAM_NUM_SALT_REG, OP_STATUS, START, WORKING_STATE,
+};
+use core::cell::Cell;
+use kernel::hil::keystore::{KeyDerivation, KeyDerivationClient, DERIVED_KEY_LEN};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::utilities::registers::interfaces::{Readable, Writeable};
+use kernel::utilities::StaticRef;
+use kernel::ErrorCode;
+
+pub use crate::registers::keymgr_regs::KeymgrRegisters;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Operation {
+    Idle,
+    Advance,
+    Generate,
+}
+
+pub struct Keymgr<'a> {
+    registers: StaticRef<KeymgrRegisters>,
+    client: OptionalCell<&'a dyn KeyDerivationClient>,
+
+    operation: Cell<Operation>,
+    salt: Cell<[u32; KEYMGR_PARAM_NUM_SALT_REG as usize]>,
+    version: Cell<u32>,
+    key: TakeCell<'static, [u8; DERIVED_KEY_LEN]>,
+}
+
+impl<'a> Keymgr<'a> {
+    pub fn new(base: StaticRef<KeymgrRegisters>) -> Keymgr<'a> {
+        Keymgr {
+            registers: base,
+            client: OptionalCell::empty(),
+            operation: Cell::new(Operation::Idle),
+            salt: Cell::new([0; KEYMGR_PARAM_NUM_SALT_REG as usize]),
+            version: Cell::new(0),
+            key: TakeCell::empty(),
+        }
+    }
+
+    /// Start the next operation needed to generate the key.
+    fn start_operation(&self) -> Result<(), ErrorCode> {
+        let regs = self.registers;
+
+        let operation = match regs.working_state.read_as_enum(WORKING_STATE::STATE) {
+            Some(WORKING_STATE::STATE::Value::RESET) | Some(WORKING_STATE::STATE::Value::INIT) => {
+                Operation::Advance
+            }
+            Some(WORKING_STATE::STATE::Value::CREATOR_ROOT_KEY)
+            | Some(WORKING_STATE::STATE::Value::OWNER_INTERMEDIATE_KEY)
+            | Some(WORKING_STATE::STATE::Value::OWNER_KEY) => Operation::Generate,
+            _ => return Err(ErrorCode::OFF),
+        };
+
+        if regs.cfg_regwen.get() == 0 {
+            return Err(ErrorCode::BUSY);
+        }
+
+        let control = match operation {
+            Operation::Generate => {
+                for (reg, word) in regs.salt.iter().zip(self.salt.get().iter()) {
+                    reg.set(*word);
+                }
+                regs.key_version[0].set(self.version.get());
+
+                CONTROL_SHADOWED::OPERATION::GENERATE_SW_OUTPUT
+                    + CONTROL_SHADOWED::CDI_SEL::SEALING_CDI
+                    + CONTROL_SHADOWED::DEST_SEL::NONE
+            }
+            _ => CONTROL_SHADOWED::OPERATION::ADVANCE + CONTROL_SHADOWED::DEST_SEL::NONE,
+        };
+        // Shadowed registers have to be written twice.
+        regs.control_shadowed.write(control);
+        regs.control_shadowed.write(control);
+
+        self.operation.set(operation);
+        regs.intr_enable.write(INTR::OP_DONE::SET);
+        regs.start.write(START::EN::VALID_STATE);
+
+        Ok(())
+    }
+
+    fn finish(&self, result: Result<(), ErrorCode>) {
+        self.operation.set(Operation::Idle);
+        self.salt.set([0; KEYMGR_PARAM_NUM_SALT_REG as usize]);
+
+        if let Some(key) = self.key.take() {
+            if result.is_err() {
+                key.fill(0);
+            }
+            self.client
+                .map(move |client| client.derivation_done(result, key));
+        }
+    }
+
+    pub fn handle_interrupt(&self) {
+        let regs = self.registers;
+
+        regs.intr_state.write(INTR::OP_DONE::SET);
+
+        let status = regs.op_status.extract();
+        let errors = regs.err_code.extract();
+        // Both registers are write one to clear.
+        regs.op_status.set(status.get());
+        regs.err_code.set(errors.get());
+
+        let operation = self.operation.get();
+        if operation == Operation::Idle {
+            return;
+        }
+
+        if !status.matches_all(OP_STATUS::STATUS::DONE_SUCCESS) {
+            if errors.is_set(ERR_CODE::INVALID_KMAC_INPUT) && operation == Operation::Generate {
+                // The key manager rejected the input, which for generation
+                // means the version is larger than the maximum allowed.
+                self.finish(Err(ErrorCode::INVAL));
+            } else {
+                self.finish(Err(ErrorCode::FAIL));
+            }
+            return;
+        }
+
+        match operation {
+            Operation::Advance => {
+                if self.start_operation().is_err() {
+                    self.finish(Err(ErrorCode::FAIL));
+                }
+            }
+            Operation::Generate => {
+                self.key.map(|key| {
+                    // The output is split into two shares, the key is their
+                    // XOR.
+                    for (i, chunk) in key.chunks_mut(4).enumerate() {
+                        let word = regs.sw_share0_output[i].get() ^ regs.sw_share1_output[i].get();
+                        chunk.copy_from_slice(&word.to_le_bytes());
+                    }
+                });
+                self.finish(Ok(()));
+            }
+            Operation::Idle => {}
+        }
+    }
+}
+
+impl<'a> KeyDerivation<'a> for Keymgr<'a> {
+    fn set_client(&self, client: &'a dyn KeyDerivationClient) {
+        self.client.set(client);
+    }
+
+    fn derive(
+        &self,
+        salt: &[u8; 32],
+        version: u32,
+        key: &'static mut [u8; DERIVED_KEY_LEN],
+    ) -> Result<(), (ErrorCode, &'static mut [u8; DERIVED_KEY_LEN])> {
+        if self.operation.get() != Operation::Idle {
+            return Err((ErrorCode::BUSY, key));
+        }
+
+        let mut salt_words = [0; KEYMGR_PARAM_NUM_SALT_REG as usize];
+        for (word, chunk) in salt_words.iter_mut().zip(salt.chunks(4)) {
+            *word = u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+        }
+        self.salt.set(salt_words);
+        self.version.set(version);
+
+        if let Err(e) = self.start_operation() {
+            self.salt.set([0; KEYMGR_PARAM_NUM_SALT_REG as usize]);
+            return Err((e, key));
+        }
+
+        self.key.replace(key);
+        Ok(())
+    }
+}
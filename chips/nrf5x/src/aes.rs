@@ -448,7 +448,12 @@ impl<'a> kernel::hil::symmetric_encryption::AES128<'a> for AesECB<'a> {
 
     fn set_key(&self, key: &[u8]) -> Result<(), ErrorCode> {
         if key.len() != symmetric_encryption::AES128_KEY_SIZE {
-            Err(ErrorCode::INVAL)
+            // The ECB peripheral only supports 128-bit keys
+            if symmetric_encryption::aes_key_size_valid(key.len()) {
+                Err(ErrorCode::NOSUPPORT)
+            } else {
+                Err(ErrorCode::INVAL)
+            }
         } else {
             for (i, c) in key.iter().enumerate() {
                 unsafe {
@@ -419,6 +419,10 @@ impl<'a> hil::symmetric_encryption::AES128<'a> for Aes<'a> {
 
     fn set_key(&self, key: &[u8]) -> Result<(), ErrorCode> {
         if key.len() != AES128_KEY_SIZE {
+            // The AESA only supports 128-bit keys
+            if hil::symmetric_encryption::aes_key_size_valid(key.len()) {
+                return Err(ErrorCode::NOSUPPORT);
+            }
             return Err(ErrorCode::INVAL);
         }
 
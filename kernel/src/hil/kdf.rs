@@ -0,0 +1,70 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Interface for key derivation functions.
+//!
+//! A key derivation function produces keys from input key material, such as
+//! a shared secret or a password. The inputs are copied when the derivation
+//! starts, so they only need to live for the duration of the call, and the
+//! derived key is written to the `output` buffer.
+
+use crate::utilities::leasable_buffer::SubSliceMut;
+use crate::ErrorCode;
+
+/// Client for receiving derived keys.
+pub trait Client {
+    /// Called when a key derivation completes.
+    ///
+    /// - `result`: `Ok(())` if `output` holds the derived key, otherwise the
+    ///   error from the underlying hash function.
+    /// - `output`: The buffer passed to `hkdf()` or `pbkdf2()`.
+    fn derivation_done(&self, result: Result<(), ErrorCode>, output: SubSliceMut<'static, u8>);
+}
+
+/// Key derivation with HKDF and PBKDF2.
+///
+/// The derived key fills all of `output`. Only one derivation can be in
+/// progress at a time.
+pub trait Kdf<'a> {
+    /// Set the client to receive derived keys.
+    fn set_client(&self, client: &'a dyn Client);
+
+    /// Derive a key with HKDF (RFC 5869), with the input key material `ikm`,
+    /// `salt` and the context `info`.
+    ///
+    /// ### Return
+    ///
+    /// - `Ok(())`: `derivation_done()` will be called.
+    /// - On error, returns `output` and:
+    ///   - `BUSY`: A derivation is already in progress.
+    ///   - `SIZE`: The inputs are too long, or `output` is empty or longer
+    ///     than HKDF allows.
+    ///   - `NOSUPPORT`: HKDF isn't supported.
+    fn hkdf(
+        &self,
+        ikm: &[u8],
+        salt: &[u8],
+        info: &[u8],
+        output: SubSliceMut<'static, u8>,
+    ) -> Result<(), (ErrorCode, SubSliceMut<'static, u8>)>;
+
+    /// Derive a key with PBKDF2 (RFC 8018) from `password` and `salt`, with
+    /// `iterations` iterations.
+    ///
+    /// ### Return
+    ///
+    /// - `Ok(())`: `derivation_done()` will be called.
+    /// - On error, returns `output` and:
+    ///   - `BUSY`: A derivation is already in progress.
+    ///   - `INVAL`: `iterations` is zero.
+    ///   - `SIZE`: The inputs are too long, or `output` is empty.
+    ///   - `NOSUPPORT`: PBKDF2 isn't supported.
+    fn pbkdf2(
+        &self,
+        password: &[u8],
+        salt: &[u8],
+        iterations: u32,
+        output: SubSliceMut<'static, u8>,
+    ) -> Result<(), (ErrorCode, SubSliceMut<'static, u8>)>;
+}
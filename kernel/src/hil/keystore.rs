@@ -0,0 +1,138 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Interfaces for keys held by the kernel.
+//!
+//! A keystore holds key material in kernel memory. Apps refer to a key with
+//! an opaque [`KeyHandle`] instead of passing the key bytes around, and the
+//! crypto drivers ask the keystore for the key when they need it. The keystore
+//! checks that the app is allowed to use the key for the requested operation.
+//!
+//! [`KeyDerivation`] is implemented by hardware that derives keys from a
+//! device secret, such as the OpenTitan key manager. A keystore can use it to
+//! provide keys that are never stored anywhere.
+
+use crate::process::ShortId;
+use crate::ErrorCode;
+use crate::ProcessId;
+
+/// The length of a key produced by `KeyDerivation`.
+pub const DERIVED_KEY_LEN: usize = 32;
+
+/// An opaque reference to a key held by a keystore.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct KeyHandle(u32);
+
+impl KeyHandle {
+    pub const fn new(id: u32) -> Self {
+        KeyHandle(id)
+    }
+
+    /// The value identifying the key to userspace.
+    pub fn id(&self) -> u32 {
+        self.0
+    }
+}
+
+/// The operation a key may be used for.
+///
+/// Each key has exactly one usage, so the same key bytes can't be used for
+/// different algorithms.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum KeyUsage {
+    /// Encryption with the AES driver, in any of its modes.
+    Aes = 1,
+    /// HMAC, with any of the supported hash functions.
+    Hmac = 2,
+}
+
+impl KeyUsage {
+    pub fn from_usize(usage: usize) -> Option<Self> {
+        match usage {
+            1 => Some(KeyUsage::Aes),
+            2 => Some(KeyUsage::Hmac),
+            _ => None,
+        }
+    }
+}
+
+/// A store of keys that can be used by handle.
+pub trait KeyStore {
+    /// Call `f` with the key referred to by `handle`, if the app identified by
+    /// `app_id` may use it for `usage`.
+    ///
+    /// The key is only valid for the duration of `f`, and must not be copied
+    /// anywhere that outlives the operation it is used for.
+    ///
+    /// ### Return
+    ///
+    /// The result of `f`, or on error:
+    /// - `INVAL`: `handle` doesn't refer to a key.
+    /// - `NOSUPPORT`: The app isn't allowed to use the key, or the key can't
+    ///   be used for `usage`.
+    fn with_key(
+        &self,
+        handle: KeyHandle,
+        app_id: ShortId,
+        usage: KeyUsage,
+        f: &mut dyn FnMut(&[u8]) -> Result<(), ErrorCode>,
+    ) -> Result<(), ErrorCode>;
+
+    /// Add `key` to the keystore on behalf of the process `processid`. Only
+    /// that app can use or delete the key, and it is freed once the process
+    /// has exited.
+    ///
+    /// ### Return
+    ///
+    /// The handle of the new key, or on error:
+    /// - `SIZE`: The key is empty or too long.
+    /// - `NOMEM`: The keystore is full, or the app holds as many keys as it
+    ///   is allowed to.
+    /// - `NOSUPPORT`: The app doesn't have a fixed `ShortId`.
+    fn import(
+        &self,
+        processid: ProcessId,
+        usage: KeyUsage,
+        key: &[u8],
+    ) -> Result<KeyHandle, ErrorCode>;
+}
+
+/// Client for receiving derived keys.
+pub trait KeyDerivationClient {
+    /// Called when a key derivation completes.
+    ///
+    /// - `result`: `Ok(())` if `key` holds the derived key, otherwise:
+    ///   - `INVAL`: The requested version isn't allowed.
+    ///   - `FAIL`: The hardware reported an error.
+    /// - `key`: The buffer passed to `derive()`.
+    fn derivation_done(
+        &self,
+        result: Result<(), ErrorCode>,
+        key: &'static mut [u8; DERIVED_KEY_LEN],
+    );
+}
+
+/// Derive keys from a device secret.
+///
+/// Deriving with the same `salt` and `version` on the same device always
+/// produces the same key, so derived keys don't need to be stored.
+pub trait KeyDerivation<'a> {
+    /// Set the client to receive derived keys.
+    fn set_client(&self, client: &'a dyn KeyDerivationClient);
+
+    /// Derive a key from the device secret, `salt` and `version`.
+    ///
+    /// ### Return
+    ///
+    /// - `Ok(())`: `derivation_done()` will be called.
+    /// - On error, returns the buffer and:
+    ///   - `BUSY`: A derivation is already in progress.
+    ///   - `OFF`: Key derivation has been disabled.
+    fn derive(
+        &self,
+        salt: &[u8; 32],
+        version: u32,
+        key: &'static mut [u8; DERIVED_KEY_LEN],
+    ) -> Result<(), (ErrorCode, &'static mut [u8; DERIVED_KEY_LEN])>;
+}
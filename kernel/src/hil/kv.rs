@@ -39,6 +39,15 @@ use crate::storage_permissions::StoragePermissions;
 use crate::utilities::leasable_buffer::SubSliceMut;
 use crate::ErrorCode;
 
+/// The amount of storage used by objects written with a single write ID.
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub struct StorageUsage {
+    /// The number of bytes of stored values, not including headers.
+    pub bytes: usize,
+    /// The number of stored objects.
+    pub objects: usize,
+}
+
 /// Callback trait for KV stores.
 ///
 /// Implement this trait and use `set_client()` to receive callbacks.
@@ -76,7 +85,8 @@ pub trait KVClient {
     /// - `result`: `Ok(())` on success, `Err(ErrorCode)` on error. Valid
     ///   `ErrorCode`s:
     ///   - `NOSUPPORT`: The caller does not have permission to store this key.
-    ///   - `NOMEM`: The key could not be set because the KV store is full.
+    ///   - `NOMEM`: The key could not be set because the KV store is full or
+    ///     the caller has exceeded its storage quota.
     ///   - `SIZE`: The key could not be set because the key or value is too
     ///     many bytes.
     ///   - `FAIL`: An internal error occurred and the operation cannot be
@@ -97,7 +107,8 @@ pub trait KVClient {
     /// - `result`: `Ok(())` on success, `Err(ErrorCode)` on error. Valid
     ///   `ErrorCode`s:
     ///   - `NOSUPPORT`: The key already exists and cannot be added.
-    ///   - `NOMEM`: The key could not be added because the KV store is full.
+    ///   - `NOMEM`: The key could not be added because the KV store is full or
+    ///     the caller has exceeded its storage quota.
     ///   - `SIZE`: The key could not be set because the key or value is too
     ///     many bytes.
     ///   - `FAIL`: An internal error occurred and the operation cannot be
@@ -119,7 +130,8 @@ pub trait KVClient {
     ///   `ErrorCode`s:
     ///   - `NOSUPPORT`: The key does not already exist and cannot be modified
     ///     or the caller does not have permission to modify this key.
-    ///   - `NOMEM`: The key could not be updated because the KV store is full.
+    ///   - `NOMEM`: The key could not be updated because the KV store is full
+    ///     or the caller has exceeded its storage quota.
     ///   - `SIZE`: The key could not be set because the key or value is too
     ///     many bytes.
     ///   - `FAIL`: An internal error occurred and the operation cannot be
@@ -145,6 +157,62 @@ pub trait KVClient {
     ///     completed.
     /// - `key`: The key buffer.
     fn delete_complete(&self, result: Result<(), ErrorCode>, key: SubSliceMut<'static, u8>);
+
+    /// This callback is called when the next key operation completes.
+    ///
+    /// If there wasn't enough room to store the entire key or value `SIZE`
+    /// will be returned in `result` and the bytes that did fit will be copied
+    /// into the buffers.
+    ///
+    /// ### Return Values
+    ///
+    /// - `result`: `Ok(())` on success
+    /// - `Err(ErrorCode)` on error. Valid `ErrorCode`s:
+    ///   - `SIZE`: The key or value is longer than the provided buffer. The
+    ///     amount of the key and value that fits in the buffers is provided.
+    ///   - `NOSUPPORT`: There are no more keys to iterate over. The data in the
+    ///     `key` and `value` buffers is meaningless.
+    ///   - `FAIL`: An internal error occurred and the operation cannot be
+    ///     completed. If `position` isn't zero only this object couldn't be
+    ///     read, and the iteration can continue past it.
+    /// - `position`: The position to pass to the next call to `next_key()` to
+    ///   continue the iteration. Valid on success, on `SIZE` and on `FAIL`
+    ///   when it isn't zero.
+    /// - `key`: The key buffer, sliced to the length of the key.
+    /// - `value`: The value buffer, sliced to the length of the value.
+    fn next_key_complete(
+        &self,
+        _result: Result<(), ErrorCode>,
+        _position: usize,
+        _key: SubSliceMut<'static, u8>,
+        _value: SubSliceMut<'static, u8>,
+    ) {
+    }
+
+    /// This callback is called when the commit batch operation completes.
+    ///
+    /// ### Return Values
+    ///
+    /// - `result`: `Ok(())` on success, in which case all of the `set()`
+    ///   operations in the batch have been applied. `Err(ErrorCode)` on error.
+    ///   Valid `ErrorCode`s:
+    ///   - `NOMEM`: The batch could not be committed because the KV store is
+    ///     full. None of the `set()` operations were applied, the batch should
+    ///     be aborted.
+    ///   - `FAIL`: An internal error occurred and the operation cannot be
+    ///     completed.
+    fn commit_batch_complete(&self, _result: Result<(), ErrorCode>) {}
+
+    /// This callback is called when the abort batch operation completes.
+    ///
+    /// ### Return Values
+    ///
+    /// - `result`: `Ok(())` on success, in which case none of the `set()`
+    ///   operations in the batch have been applied. `Err(ErrorCode)` on error.
+    ///   Valid `ErrorCode`s:
+    ///   - `FAIL`: An internal error occurred and the operation cannot be
+    ///     completed.
+    fn abort_batch_complete(&self, _result: Result<(), ErrorCode>) {}
 }
 
 /// Key-Value interface with permissions.
@@ -319,11 +387,112 @@ pub trait KVPermissions<'a> {
         permissions: StoragePermissions,
     ) -> Result<(), (SubSliceMut<'static, u8>, ErrorCode)>;
 
+    /// Retrieve the next key-value object that the caller has permission to
+    /// read, starting at `position`.
+    ///
+    /// Only objects written by the `write_id` of `permissions` are returned,
+    /// which allows callers to enumerate the keys they own.
+    ///
+    /// ### Arguments
+    ///
+    /// - `position`: Where to start the search. `0` starts from the beginning
+    ///   of the store, otherwise this should be the `position` provided in the
+    ///   previous `next_key_complete()` callback.
+    /// - `key`: Where the key of the object will be stored.
+    /// - `value`: Where the value of the object will be stored.
+    /// - `permissions`: The read/write/modify permissions for this access.
+    ///
+    /// ### Return
+    ///
+    /// - On success returns `Ok(())`. A callback will be issued.
+    /// - On error, returns the buffers and:
+    ///   - `BUSY`: An operation is already in progress.
+    ///   - `NOSUPPORT`: The underlying store does not support iteration.
+    ///   - `FAIL`: An internal error occurred and the operation cannot be
+    ///     completed.
+    fn next_key(
+        &self,
+        position: usize,
+        key: SubSliceMut<'static, u8>,
+        value: SubSliceMut<'static, u8>,
+        permissions: StoragePermissions,
+    ) -> Result<
+        (),
+        (
+            SubSliceMut<'static, u8>,
+            SubSliceMut<'static, u8>,
+            ErrorCode,
+        ),
+    >;
+
+    /// Start a batch of `set()` operations owned by the caller.
+    ///
+    /// This behaves like `KV::begin_batch()`, except that the batch belongs to
+    /// the write ID of `permissions`. Only `set()` operations with that write
+    /// ID are staged in the batch. While the batch is in progress `set()`,
+    /// `add()`, `update()` and `delete()` operations from other callers return
+    /// `BUSY` or are delayed until the batch has been committed or aborted.
+    ///
+    /// ### Return
+    ///
+    /// - On success returns `Ok(())`. No callback will be issued.
+    /// - On error returns:
+    ///   - `BUSY`: An operation or another caller's batch is in progress.
+    ///   - `ALREADY`: The caller has already started a batch.
+    ///   - `INVAL`: The caller does not have write permissions.
+    ///   - `NOSUPPORT`: This store does not support batches.
+    fn begin_batch(&self, _permissions: StoragePermissions) -> Result<(), ErrorCode> {
+        Err(ErrorCode::NOSUPPORT)
+    }
+
+    /// Atomically apply all of the `set()` operations in the caller's batch.
+    ///
+    /// ### Return
+    ///
+    /// - On success returns `Ok(())`. A callback will be issued.
+    /// - On error returns:
+    ///   - `BUSY`: An operation is already in progress.
+    ///   - `INVAL`: The caller has not started a batch.
+    ///   - `NOSUPPORT`: This store does not support batches.
+    fn commit_batch(&self, _permissions: StoragePermissions) -> Result<(), ErrorCode> {
+        Err(ErrorCode::NOSUPPORT)
+    }
+
+    /// Discard all of the `set()` operations in the caller's batch.
+    ///
+    /// ### Return
+    ///
+    /// - On success returns `Ok(())`. A callback will be issued.
+    /// - On error returns:
+    ///   - `BUSY`: An operation is already in progress.
+    ///   - `INVAL`: The caller has not started a batch.
+    ///   - `NOSUPPORT`: This store does not support batches.
+    fn abort_batch(&self, _permissions: StoragePermissions) -> Result<(), ErrorCode> {
+        Err(ErrorCode::NOSUPPORT)
+    }
+
     /// Returns the length of the key-value store's header in bytes.
     ///
     /// Room for this header must be accommodated in a `set`, `add`, or `update`
     /// operation.
     fn header_size(&self) -> usize;
+
+    /// Returns the storage currently used by objects written with the write ID
+    /// of `permissions`.
+    ///
+    /// This is the usage that is checked against the `StorageQuota` of
+    /// `permissions` when objects are stored.
+    ///
+    /// ### Return
+    ///
+    /// - On success returns the current usage.
+    /// - On error:
+    ///   - `INVAL`: The caller does not have write permissions.
+    ///   - `BUSY`: The usage is still being calculated, try again later.
+    ///   - `NOSUPPORT`: Usage is not tracked by this implementation.
+    fn get_usage(&self, _permissions: StoragePermissions) -> Result<StorageUsage, ErrorCode> {
+        Err(ErrorCode::NOSUPPORT)
+    }
 }
 
 /// Key-Value interface.
@@ -336,6 +505,10 @@ pub trait KVPermissions<'a> {
 /// - `add(key, value)`
 /// - `update(key, value)`
 /// - `delete(key)`
+///
+/// As well as `next_key(position) -> (key, value)` to iterate over all of the
+/// stored objects, and `begin_batch()`, `commit_batch()` and `abort_batch()`
+/// to atomically store multiple values.
 pub trait KV<'a> {
     /// Configure the client for operation callbacks.
     fn set_client(&self, client: &'a dyn KVClient);
@@ -478,4 +651,95 @@ pub trait KV<'a> {
         &self,
         key: SubSliceMut<'static, u8>,
     ) -> Result<(), (SubSliceMut<'static, u8>, ErrorCode)>;
+
+    /// Retrieve the next key-value object stored, starting at `position`.
+    ///
+    /// Implementations that can't iterate over the stored keys should return
+    /// `NOSUPPORT`. Objects that were stored without their key, for example
+    /// by an older version of the store, are returned with an empty key.
+    ///
+    /// ### Arguments
+    ///
+    /// - `position`: Where to start the search. `0` starts from the beginning
+    ///   of the store, otherwise this should be the `position` provided in the
+    ///   previous `next_key_complete()` callback.
+    /// - `key`: Where the key of the object will be stored.
+    /// - `value`: Where the value of the object will be stored.
+    ///
+    /// ### Return
+    ///
+    /// - On success returns `Ok(())`. A callback will be issued.
+    /// - On error, returns the buffers and:
+    ///   - `BUSY`: An operation is already in progress.
+    ///   - `NOSUPPORT`: This store does not support iteration.
+    ///   - `FAIL`: An internal error occurred and the operation cannot be
+    ///     completed.
+    fn next_key(
+        &self,
+        _position: usize,
+        key: SubSliceMut<'static, u8>,
+        value: SubSliceMut<'static, u8>,
+    ) -> Result<
+        (),
+        (
+            SubSliceMut<'static, u8>,
+            SubSliceMut<'static, u8>,
+            ErrorCode,
+        ),
+    > {
+        Err((key, value, ErrorCode::NOSUPPORT))
+    }
+
+    /// Start a batch of `set()` operations.
+    ///
+    /// Until `commit_batch()` or `abort_batch()` is called all `set()`
+    /// operations are staged. Staged values are not returned by `get()` or
+    /// `next_key()`. Once the batch is committed all of the staged values are
+    /// applied together, if power is lost before then none of them are. Each
+    /// key can only be set once in a batch. While a batch is in progress
+    /// `add()`, `update()` and `delete()` return `INVAL`.
+    ///
+    /// The batch applies to every `set()` made through this interface, so
+    /// users that share a store must go through `KVPermissions`, which only
+    /// stages the `set()` operations of the caller that started the batch.
+    ///
+    /// Implementations that can't atomically store multiple values should
+    /// return `NOSUPPORT`.
+    ///
+    /// ### Return
+    ///
+    /// - On success returns `Ok(())`. No callback will be issued.
+    /// - On error returns:
+    ///   - `BUSY`: An operation is already in progress.
+    ///   - `ALREADY`: A batch has already been started.
+    ///   - `NOSUPPORT`: This store does not support batches.
+    fn begin_batch(&self) -> Result<(), ErrorCode> {
+        Err(ErrorCode::NOSUPPORT)
+    }
+
+    /// Atomically apply all of the `set()` operations since `begin_batch()`.
+    ///
+    /// ### Return
+    ///
+    /// - On success returns `Ok(())`. A callback will be issued.
+    /// - On error returns:
+    ///   - `BUSY`: An operation is already in progress.
+    ///   - `INVAL`: No batch has been started.
+    ///   - `NOSUPPORT`: This store does not support batches.
+    fn commit_batch(&self) -> Result<(), ErrorCode> {
+        Err(ErrorCode::NOSUPPORT)
+    }
+
+    /// Discard all of the `set()` operations since `begin_batch()`.
+    ///
+    /// ### Return
+    ///
+    /// - On success returns `Ok(())`. A callback will be issued.
+    /// - On error returns:
+    ///   - `BUSY`: An operation is already in progress.
+    ///   - `INVAL`: No batch has been started.
+    ///   - `NOSUPPORT`: This store does not support batches.
+    fn abort_batch(&self) -> Result<(), ErrorCode> {
+        Err(ErrorCode::NOSUPPORT)
+    }
 }
@@ -0,0 +1,42 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Interface for strips of addressable RGB LEDs, such as WS2812 and SK6812.
+//!
+//! The client keeps a frame buffer with the colour of each LED, and the strip
+//! shows a whole frame at a time. Frames have `channels()` bytes per LED, in
+//! red, green, blue and, for RGBW LEDs, white order, starting with the LED
+//! closest to the controller. Implementations convert them to the channel
+//! order and timing of the LEDs.
+
+use crate::ErrorCode;
+
+pub trait LedStripClient {
+    /// Called when a frame was sent to the LEDs, returning its buffer.
+    fn show_done(&self, frame: &'static mut [u8], result: Result<(), ErrorCode>);
+}
+
+pub trait LedStrip<'a> {
+    fn set_client(&self, client: &'a dyn LedStripClient);
+
+    /// The number of LEDs on the strip.
+    fn pixels(&self) -> usize;
+
+    /// The number of colour channels of each LED: 3 for RGB, 4 for RGBW.
+    fn channels(&self) -> usize;
+
+    /// Show the first `len` bytes of `frame`. If they hold fewer LEDs than
+    /// the strip has, the others keep their colour.
+    ///
+    /// Return values:
+    /// - `Ok(())`: `show_done` will be called.
+    /// - `BUSY`: a frame is being sent.
+    /// - `SIZE`: `len` is larger than `frame`, isn't a whole number of
+    ///   LEDs or holds more LEDs than the strip has.
+    fn show(
+        &self,
+        frame: &'static mut [u8],
+        len: usize,
+    ) -> Result<(), (ErrorCode, &'static mut [u8])>;
+}
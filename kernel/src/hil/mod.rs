@@ -22,10 +22,14 @@ pub mod gpio_async;
 pub mod hasher;
 pub mod hw_debug;
 pub mod i2c;
+pub mod kdf;
+pub mod keystore;
 pub mod kv;
 pub mod led;
+pub mod led_strip;
 pub mod log;
 pub mod nonvolatile_storage;
+pub mod onewire;
 pub mod public_key_crypto;
 pub mod pwm;
 pub mod radio;
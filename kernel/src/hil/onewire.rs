@@ -0,0 +1,109 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Interface for 1-Wire bus controllers.
+//!
+//! A 1-Wire bus carries devices that each have a unique 64-bit ROM code. A
+//! transaction starts with a reset, to which devices answer with a presence
+//! pulse, then a ROM command that selects devices, then the commands of the
+//! device. Bits and bytes go on the bus least significant bit first.
+//!
+//! ROM codes are given as `u64`s with the first byte on the bus in the least
+//! significant byte: the family code is the low byte, then come the 48-bit
+//! serial number and the CRC in the high byte.
+
+use crate::ErrorCode;
+
+/// Select the device with the ROM code that follows.
+pub const MATCH_ROM: u8 = 0x55;
+/// Select all devices.
+pub const SKIP_ROM: u8 = 0xCC;
+/// Read the ROM code of the only device on the bus.
+pub const READ_ROM: u8 = 0x33;
+/// Start a search for the ROM codes of the devices on the bus.
+pub const SEARCH_ROM: u8 = 0xF0;
+
+/// The Dallas/Maxim CRC-8 of `data`, with polynomial x^8 + x^5 + x^4 + 1,
+/// as used for ROM codes and device memory. It is 0 over data followed by
+/// its CRC.
+pub fn crc8(data: &[u8]) -> u8 {
+    data.iter().fold(0, |crc, byte| {
+        (0..8).fold(crc ^ byte, |crc, _| {
+            if crc & 1 != 0 {
+                (crc >> 1) ^ 0x8C
+            } else {
+                crc >> 1
+            }
+        })
+    })
+}
+
+pub trait OneWireClient {
+    /// Called when a reset finishes. `presence` is whether any device
+    /// answered it.
+    fn reset_done(&self, presence: bool);
+
+    /// Called when a bit or byte was written.
+    fn write_done(&self);
+
+    /// Called with the bit read by `read_bit`, as 0 or 1, or the byte read by
+    /// `read_byte`.
+    fn read_done(&self, value: u8);
+
+    /// Called when a search step finishes, with the ROM code found, or
+    /// `None` once all devices were found or if no device answered.
+    /// `Err(FAIL)` means that devices stopped answering during the search or
+    /// the ROM code had a bad CRC, which a noisy bus can cause; the search can
+    /// be started again.
+    fn search_done(&self, rom: Result<Option<u64>, ErrorCode>);
+}
+
+/// A 1-Wire bus controller. One operation runs at a time: the others return
+/// `BUSY` until its callback.
+pub trait OneWire<'a> {
+    fn set_client(&self, client: &'a dyn OneWireClient);
+
+    /// Send a reset pulse and listen for presence pulses.
+    fn reset(&self) -> Result<(), ErrorCode>;
+
+    /// Write one bit.
+    fn write_bit(&self, bit: bool) -> Result<(), ErrorCode>;
+
+    /// Read one bit.
+    fn read_bit(&self) -> Result<(), ErrorCode>;
+
+    /// Write one byte.
+    fn write_byte(&self, byte: u8) -> Result<(), ErrorCode>;
+
+    /// Read one byte.
+    fn read_byte(&self) -> Result<(), ErrorCode>;
+
+    /// Find the ROM code of the next device on the bus, or of the first one
+    /// if `first` is true. Each step resets the bus and runs a whole search
+    /// ROM command, so steps can be interleaved with other transactions.
+    fn search_rom(&self, first: bool) -> Result<(), ErrorCode>;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crc8_check_value() {
+        // The check value of CRC-8/MAXIM.
+        assert_eq!(crc8(b"123456789"), 0xA1);
+        assert_eq!(crc8(&[]), 0);
+    }
+
+    #[test]
+    fn crc8_rom_code() {
+        // The example ROM code of Maxim application note 27.
+        let rom = [0x02, 0x1C, 0xB8, 0x01, 0x00, 0x00, 0x00, 0xA2];
+        assert_eq!(crc8(&rom[..7]), 0xA2);
+        assert_eq!(crc8(&rom), 0);
+        let mut corrupted = rom;
+        corrupted[3] ^= 0x10;
+        assert_ne!(crc8(&corrupted), 0);
+    }
+}
@@ -0,0 +1,89 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Interface for Elliptic-curve Diffie-Hellman key agreement.
+//!
+//! The private key is generated by, and never leaves, the implementation.
+//! Users only ever see the public key and the shared secret.
+
+use crate::hil::entropy;
+use crate::ErrorCode;
+
+/// This trait provides callbacks for when key generation and key agreement
+/// have completed.
+pub trait EcdhClient<const PKL: usize, const SSL: usize> {
+    /// Called when the `generate()` command has completed.
+    ///
+    /// On success `public_key` contains the public key matching the newly
+    /// generated private key.
+    ///
+    /// Valid `ErrorCode`s include:
+    ///
+    /// - `CANCEL`: the entropy source cancelled the operation.
+    /// - `FAIL`: an internal failure, for example of the entropy source.
+    fn generation_done(&self, result: Result<(), ErrorCode>, public_key: &'static mut [u8; PKL]);
+
+    /// Called when the `shared_secret()` command has completed.
+    ///
+    /// On success `shared_secret` contains the shared secret.
+    ///
+    /// Valid `ErrorCode`s include:
+    ///
+    /// - `INVAL`: `peer_public_key` isn't a valid public key.
+    /// - `FAIL`: an internal failure.
+    fn shared_secret_done(
+        &self,
+        result: Result<(), ErrorCode>,
+        peer_public_key: &'static mut [u8; PKL],
+        shared_secret: &'static mut [u8; SSL],
+    );
+}
+
+/// Elliptic-curve Diffie-Hellman key agreement.
+///
+/// The implementation holds a single private key at a time.
+///
+/// - `PKL`: The length in bytes of a public key.
+/// - `SSL`: The length in bytes of the shared secret.
+pub trait Ecdh<'a, const PKL: usize, const SSL: usize> {
+    /// Set the client instance which will receive the `generation_done()` and
+    /// `shared_secret_done()` callbacks.
+    fn set_client(&self, client: &'a dyn EcdhClient<PKL, SSL>);
+
+    /// Generate a new private key using randomness from `trng`, replacing the
+    /// current key. The matching public key is written to `public_key`.
+    ///
+    /// This sets the client of `trng`. If this returns `Ok(())`, then the
+    /// `generation_done()` callback will be called.
+    ///
+    /// The valid `ErrorCode`s that can occur are:
+    ///
+    /// - `BUSY`: there is an outstanding operation already in process.
+    /// - `OFF`: the underlying `trng` is powered down.
+    /// - `FAIL`: the underlying `trng` can't generate entropy.
+    fn generate(
+        &'a self,
+        trng: &'a dyn entropy::Entropy32<'a>,
+        public_key: &'static mut [u8; PKL],
+    ) -> Result<(), (ErrorCode, &'static mut [u8; PKL])>;
+
+    /// Compute the shared secret between the current private key and
+    /// `peer_public_key`.
+    ///
+    /// If this returns `Ok(())`, then the `shared_secret_done()` callback will
+    /// be called.
+    ///
+    /// The valid `ErrorCode`s that can occur are:
+    ///
+    /// - `BUSY`: there is an outstanding operation already in process.
+    /// - `NODEVICE`: there is no private key.
+    fn shared_secret(
+        &self,
+        peer_public_key: &'static mut [u8; PKL],
+        shared_secret: &'static mut [u8; SSL],
+    ) -> Result<(), (ErrorCode, &'static mut [u8; PKL], &'static mut [u8; SSL])>;
+
+    /// Erase the private key.
+    fn clear_key(&self);
+}
@@ -283,3 +283,30 @@ pub trait RsaPrivKeyMut: PubPrivKeyMut + RsaKeyMut {
     /// the output of this function.
     fn take_exponent(&self) -> Option<&'static mut [u8]>;
 }
+
+/// Load a raw private key into an engine that signs or agrees keys with it.
+///
+/// - `KL`: The length in bytes of the private key.
+/// - `PKL`: The length in bytes of the matching public key.
+pub trait SetPrivateKey<const KL: usize, const PKL: usize> {
+    /// Use `key` for subsequent operations and write the matching public key
+    /// into `public_key`.
+    ///
+    /// Returns `INVAL` if `key` is not a valid private key, and `BUSY` if an
+    /// operation using the current key is in progress.
+    fn set_private_key(&self, key: &[u8; KL], public_key: &mut [u8; PKL]) -> Result<(), ErrorCode>;
+
+    /// Forget the current private key.
+    fn clear_private_key(&self);
+}
+
+/// Load a raw public key into an engine that verifies signatures with it.
+///
+/// - `PKL`: The length in bytes of the public key.
+pub trait SetPublicKey<const PKL: usize> {
+    /// Use `public_key` to verify subsequent signatures.
+    ///
+    /// Returns `INVAL` if `public_key` is not a valid public key, and `BUSY`
+    /// if a verification using the current key is in progress.
+    fn set_public_key(&self, public_key: &[u8; PKL]) -> Result<(), ErrorCode>;
+}
@@ -5,6 +5,11 @@
 //! Interface for symmetric-cipher encryption
 //!
 //! see boards/imix/src/aes_test.rs for example usage
+//!
+//! AES always operates on 128-bit blocks, the `128` in the names of these
+//! traits refers to the block size. The key can be 128, 192 or 256 bits long,
+//! selected by the length of the key passed to `set_key()`. Implementations
+//! that don't support a key length return `NOSUPPORT` from `set_key()`.
 
 use crate::ErrorCode;
 
@@ -18,6 +23,15 @@ pub trait Client<'a> {
 /// and encryption/decryption inputs must be have a multiple of this length.
 pub const AES128_BLOCK_SIZE: usize = 16;
 pub const AES128_KEY_SIZE: usize = 16;
+pub const AES192_KEY_SIZE: usize = 24;
+pub const AES256_KEY_SIZE: usize = 32;
+/// The length of the longest supported AES key.
+pub const AES_MAX_KEY_SIZE: usize = AES256_KEY_SIZE;
+
+/// Check if `len` is the length of an AES-128, AES-192 or AES-256 key.
+pub fn aes_key_size_valid(len: usize) -> bool {
+    len == AES128_KEY_SIZE || len == AES192_KEY_SIZE || len == AES256_KEY_SIZE
+}
 
 pub trait AES128<'a> {
     /// Enable the AES hardware.
@@ -31,7 +45,9 @@ pub trait AES128<'a> {
     fn set_client(&'a self, client: &'a dyn Client<'a>);
 
     /// Set the encryption key.
-    /// Returns `INVAL` if length is not `AES128_KEY_SIZE`
+    /// Returns `INVAL` if length is not `AES128_KEY_SIZE`, `AES192_KEY_SIZE`
+    /// or `AES256_KEY_SIZE`, and `NOSUPPORT` if the key length is not
+    /// supported by the implementation.
     fn set_key(&self, key: &[u8]) -> Result<(), ErrorCode>;
 
     /// Set the IV (or initial counter).
@@ -126,6 +142,9 @@ pub trait AES128CCM<'a> {
     fn set_client(&'a self, client: &'a dyn CCMClient);
 
     /// Set the key to be used for CCM encryption
+    /// Returns `INVAL` if length is not `AES128_KEY_SIZE`, `AES192_KEY_SIZE`
+    /// or `AES256_KEY_SIZE`, and `NOSUPPORT` if the key length is not
+    /// supported by the implementation.
     fn set_key(&self, key: &[u8]) -> Result<(), ErrorCode>;
 
     /// Set the nonce (length NONCE_LENGTH) to be used for CCM encryption
@@ -159,7 +178,9 @@ pub trait AES128GCM<'a> {
     fn set_client(&'a self, client: &'a dyn GCMClient);
 
     /// Set the key to be used for GCM encryption
-    /// Returns `INVAL` if length is not `AES128_KEY_SIZE`
+    /// Returns `INVAL` if length is not `AES128_KEY_SIZE`, `AES192_KEY_SIZE`
+    /// or `AES256_KEY_SIZE`, and `NOSUPPORT` if the key length is not
+    /// supported by the implementation.
     fn set_key(&self, key: &[u8]) -> Result<(), ErrorCode>;
 
     /// Set the IV to be used for GCM encryption. The IV should be less
@@ -180,3 +201,56 @@ pub trait AES128GCM<'a> {
         encrypting: bool,
     ) -> Result<(), (ErrorCode, &'static mut [u8])>;
 }
+
+/// The length of a ChaCha20-Poly1305 key.
+pub const CHACHA20_POLY1305_KEY_SIZE: usize = 32;
+/// The length of a ChaCha20-Poly1305 nonce.
+pub const CHACHA20_POLY1305_NONCE_SIZE: usize = 12;
+/// The length of a Poly1305 authentication tag.
+pub const CHACHA20_POLY1305_TAG_SIZE: usize = 16;
+
+pub trait ChaCha20Poly1305Client {
+    /// `res` is Ok(()) if the encryption/decryption process succeeded. This
+    /// does not mean that the message has been verified in the case of
+    /// decryption.
+    /// If we are encrypting: `tag_is_valid` is `true` iff `res` is Ok(()).
+    /// If we are decrypting: `tag_is_valid` is `true` iff `res` is Ok(()) and the
+    /// message authentication tag is valid.
+    fn crypt_done(&self, buf: &'static mut [u8], res: Result<(), ErrorCode>, tag_is_valid: bool);
+}
+
+/// ChaCha20-Poly1305 authenticated encryption, as specified in RFC 8439.
+pub trait ChaCha20Poly1305<'a> {
+    /// Set the client instance which will receive `crypt_done()` callbacks
+    fn set_client(&'a self, client: &'a dyn ChaCha20Poly1305Client);
+
+    /// Set the key to be used for encryption
+    /// Returns `INVAL` if length is not `CHACHA20_POLY1305_KEY_SIZE`
+    fn set_key(&self, key: &[u8]) -> Result<(), ErrorCode>;
+
+    /// Set the nonce to be used for encryption. A nonce must never be reused
+    /// with the same key.
+    /// Returns `INVAL` if length is not `CHACHA20_POLY1305_NONCE_SIZE`
+    fn set_nonce(&self, nonce: &[u8]) -> Result<(), ErrorCode>;
+
+    /// Try to begin the encryption/decryption process
+    ///
+    /// The additional authenticated data is `buf[aad_offset..message_offset]`
+    /// and the message is the `message_len` bytes at `message_offset`, which
+    /// are encrypted or decrypted in place. The `CHACHA20_POLY1305_TAG_SIZE`
+    /// byte tag follows the message: it is written there when encrypting and
+    /// compared against when decrypting.
+    ///
+    /// The possible ErrorCodes are:
+    ///     - `BUSY`: An operation is already in progress
+    ///     - `INVAL`: `aad_offset` is after `message_offset`
+    ///     - `SIZE`: The message and tag don't fit inside the buffer
+    fn crypt(
+        &self,
+        buf: &'static mut [u8],
+        aad_offset: usize,
+        message_offset: usize,
+        message_len: usize,
+        encrypting: bool,
+    ) -> Result<(), (ErrorCode, &'static mut [u8])>;
+}
@@ -21,9 +21,20 @@ use crate::capabilities::KerneluserStorageCapability;
 /// fn StoragePermissions::check_read_permission(&self, stored_id: u32) -> bool;
 /// fn StoragePermissions::check_modify_permission(&self, stored_id: u32) -> bool;
 /// fn StoragePermissions::get_write_id(&self) -> Option<u32>;
+/// fn StoragePermissions::get_quota(&self) -> Option<StorageQuota>;
 /// ```
 #[derive(Clone, Copy)]
-pub struct StoragePermissions(StoragePermissionsPrivate);
+pub struct StoragePermissions(StoragePermissionsPrivate, Option<StorageQuota>);
+
+/// Limits on how much persistent storage may be used by state written with a
+/// given write ID.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct StorageQuota {
+    /// The maximum number of bytes of stored values.
+    pub bytes: usize,
+    /// The maximum number of stored objects.
+    pub objects: usize,
+}
 
 /// Inner enum type for types of permissions.
 ///
@@ -112,7 +123,7 @@ impl StoragePermissions {
         short_id_fixed: core::num::NonZeroU32,
         _cap: &dyn ApplicationStorageCapability,
     ) -> Self {
-        Self(StoragePermissionsPrivate::SelfOnly(short_id_fixed))
+        Self(StoragePermissionsPrivate::SelfOnly(short_id_fixed), None)
     }
 
     pub fn new_fixed_size(
@@ -125,15 +136,18 @@ impl StoragePermissions {
         modify_permissions: [u32; 8],
         _cap: &dyn ApplicationStorageCapability,
     ) -> Self {
-        Self(StoragePermissionsPrivate::FixedSize(FixedSizePermissions {
-            app_id,
-            write_permission,
-            read_modify_self,
-            read_count,
-            read_permissions,
-            modify_count,
-            modify_permissions,
-        }))
+        Self(
+            StoragePermissionsPrivate::FixedSize(FixedSizePermissions {
+                app_id,
+                write_permission,
+                read_modify_self,
+                read_count,
+                read_permissions,
+                modify_count,
+                modify_permissions,
+            }),
+            None,
+        )
     }
 
     pub fn new_listed(
@@ -144,21 +158,24 @@ impl StoragePermissions {
         modify_permissions: &'static [u32],
         _cap: &dyn ApplicationStorageCapability,
     ) -> Self {
-        Self(StoragePermissionsPrivate::Listed(ListedPermissions {
-            app_id,
-            write_permission,
-            read_modify_self,
-            read_permissions,
-            modify_permissions,
-        }))
+        Self(
+            StoragePermissionsPrivate::Listed(ListedPermissions {
+                app_id,
+                write_permission,
+                read_modify_self,
+                read_permissions,
+                modify_permissions,
+            }),
+            None,
+        )
     }
 
     pub fn new_kernel(_cap: &dyn KerneluserStorageCapability) -> Self {
-        Self(StoragePermissionsPrivate::Kernel)
+        Self(StoragePermissionsPrivate::Kernel, None)
     }
 
     pub fn new_null() -> Self {
-        Self(StoragePermissionsPrivate::Null)
+        Self(StoragePermissionsPrivate::Null, None)
     }
 
     /// Check if these storage permissions grant read access to the stored state
@@ -218,4 +235,19 @@ impl StoragePermissions {
             StoragePermissionsPrivate::Null => None,
         }
     }
+
+    /// Limit the storage that can be used by state written with these
+    /// permissions.
+    ///
+    /// A quota only restricts what the permissions allow, so no capability is
+    /// required to add one.
+    pub fn with_quota(self, quota: StorageQuota) -> Self {
+        Self(self.0, Some(quota))
+    }
+
+    /// Retrieve the storage quota for state written with these permissions.
+    /// Returns `None` if there is no limit.
+    pub fn get_quota(&self) -> Option<StorageQuota> {
+        self.1
+    }
 }
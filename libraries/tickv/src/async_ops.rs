@@ -129,7 +129,7 @@
 use crate::error_codes::ErrorCode;
 use crate::flash_controller::FlashController;
 use crate::success_codes::SuccessCode;
-use crate::tickv::{State, TicKV};
+use crate::tickv::{KeyEntry, State, TicKV};
 use core::cell::Cell;
 
 /// The return type from the continue operation
@@ -149,6 +149,10 @@ pub struct AsyncTicKV<'a, C: FlashController<S>, const S: usize> {
     key: Cell<Option<u64>>,
     value: Cell<Option<&'static mut [u8]>>,
     value_length: Cell<usize>,
+    unhashed_key: Cell<Option<&'static mut [u8]>>,
+    unhashed_key_length: Cell<usize>,
+    position: Cell<usize>,
+    key_entry: Cell<Option<KeyEntry>>,
 }
 
 impl<'a, C: FlashController<S>, const S: usize> AsyncTicKV<'a, C, S> {
@@ -164,6 +168,10 @@ impl<'a, C: FlashController<S>, const S: usize> AsyncTicKV<'a, C, S> {
             key: Cell::new(None),
             value: Cell::new(None),
             value_length: Cell::new(0),
+            unhashed_key: Cell::new(None),
+            unhashed_key_length: Cell::new(0),
+            position: Cell::new(0),
+            key_entry: Cell::new(None),
         }
     }
 
@@ -222,6 +230,50 @@ impl<'a, C: FlashController<S>, const S: usize> AsyncTicKV<'a, C, S> {
         }
     }
 
+    /// Appends the key/value pair to flash storage, storing the unhashed
+    /// key alongside the value so it can be returned by `next_key()`.
+    ///
+    /// `hash`: A hashed key. This key will be used in future to retrieve
+    ///         or remove the `value`.
+    /// `key`: A buffer containing the unhashed key.
+    /// `key_length`: The number of valid bytes in `key`.
+    /// `value`: A buffer containing the data to be stored to flash.
+    /// `length`: The number of valid bytes in `value`.
+    ///
+    /// On success nothing will be returned.
+    /// On error the buffers and a `ErrorCode` will be returned.
+    ///
+    /// Once the operation completes the `key` buffer can be retrieved with
+    /// `take_unhashed_key()`.
+    pub fn append_named_key(
+        &self,
+        hash: u64,
+        key: &'static mut [u8],
+        key_length: usize,
+        value: &'static mut [u8],
+        length: usize,
+    ) -> Result<SuccessCode, (&'static mut [u8], &'static mut [u8], ErrorCode)> {
+        match self
+            .tickv
+            .append_named_key(hash, &key[0..key_length], &value[0..length])
+        {
+            Ok(_code) => Err((key, value, ErrorCode::WriteFail)),
+            Err(e) => match e {
+                ErrorCode::ReadNotReady(_)
+                | ErrorCode::EraseNotReady(_)
+                | ErrorCode::WriteNotReady(_) => {
+                    self.key.replace(Some(hash));
+                    self.unhashed_key.replace(Some(key));
+                    self.unhashed_key_length.set(key_length);
+                    self.value.replace(Some(value));
+                    self.value_length.set(length);
+                    Ok(SuccessCode::Queued)
+                }
+                _ => Err((key, value, e)),
+            },
+        }
+    }
+
     /// Retrieves the value from flash storage.
     ///
     /// `hash`: A hashed key.
@@ -297,6 +349,111 @@ impl<'a, C: FlashController<S>, const S: usize> AsyncTicKV<'a, C, S> {
         }
     }
 
+    /// Finds the next valid object in flash storage, starting at `position`.
+    ///
+    /// `position`: The position to start searching from, zero to start
+    ///             from the beginning.
+    /// `key`: A buffer to store the unhashed key to.
+    /// `buf`: A buffer to store the value to.
+    ///
+    /// On success a `SuccessCode` will be returned.
+    /// On error the buffers and a `ErrorCode` will be returned.
+    ///
+    /// Once the operation completes the details of the object found can be
+    /// retrieved with `key_entry()` and the `key` buffer with
+    /// `take_unhashed_key()`.
+    pub fn next_key(
+        &self,
+        position: usize,
+        key: &'static mut [u8],
+        buf: &'static mut [u8],
+    ) -> Result<SuccessCode, (&'static mut [u8], &'static mut [u8], ErrorCode)> {
+        self.key_entry.set(None);
+
+        match self.tickv.next_key(position, key, buf) {
+            Ok(_code) => Err((key, buf, ErrorCode::ReadFail)),
+            Err(e) => match e {
+                ErrorCode::ReadNotReady(_)
+                | ErrorCode::EraseNotReady(_)
+                | ErrorCode::WriteNotReady(_) => {
+                    self.position.set(position);
+                    self.unhashed_key.replace(Some(key));
+                    self.value.replace(Some(buf));
+                    Ok(SuccessCode::Queued)
+                }
+                _ => Err((key, buf, e)),
+            },
+        }
+    }
+
+    /// Return the unhashed key buffer used by the last `append_named_key()`
+    /// or `next_key()` operation, once that operation has completed.
+    pub fn take_unhashed_key(&self) -> Option<&'static mut [u8]> {
+        self.unhashed_key.take()
+    }
+
+    /// Return the details of the object found by the last completed
+    /// `next_key()` operation.
+    pub fn key_entry(&self) -> Option<KeyEntry> {
+        self.key_entry.get()
+    }
+
+    /// Starts a transaction, see `TicKV::begin_transaction()`.
+    ///
+    /// This doesn't access the flash, so no callback will be triggered.
+    ///
+    /// On success a `SuccessCode` will be returned.
+    /// On error a `ErrorCode` will be returned.
+    pub fn begin_transaction(&self) -> Result<SuccessCode, ErrorCode> {
+        self.tickv.begin_transaction()
+    }
+
+    /// Commits the current transaction, see `TicKV::commit_transaction()`.
+    ///
+    /// Committing can require multiple writes, use `write_continues()` from
+    /// the write complete callback to check if `continue_operation()` needs
+    /// to be called.
+    ///
+    /// On success a `SuccessCode` will be returned.
+    /// On error a `ErrorCode` will be returned.
+    pub fn commit_transaction(&self) -> Result<SuccessCode, ErrorCode> {
+        match self.tickv.commit_transaction() {
+            Ok(_code) => Err(ErrorCode::WriteFail),
+            Err(e) => match e {
+                ErrorCode::ReadNotReady(_)
+                | ErrorCode::EraseNotReady(_)
+                | ErrorCode::WriteNotReady(_) => Ok(SuccessCode::Queued),
+                _ => Err(e),
+            },
+        }
+    }
+
+    /// Aborts the current transaction, see `TicKV::abort_transaction()`.
+    ///
+    /// On success a `SuccessCode` will be returned.
+    /// On error a `ErrorCode` will be returned.
+    pub fn abort_transaction(&self) -> Result<SuccessCode, ErrorCode> {
+        match self.tickv.abort_transaction() {
+            Ok(_code) => Err(ErrorCode::WriteFail),
+            Err(e) => match e {
+                ErrorCode::ReadNotReady(_)
+                | ErrorCode::EraseNotReady(_)
+                | ErrorCode::WriteNotReady(_) => Ok(SuccessCode::Queued),
+                _ => Err(e),
+            },
+        }
+    }
+
+    /// Returns true if the current operation continues after the pending
+    /// flash write has completed. In which case `continue_operation()` should
+    /// be called from the write complete callback.
+    ///
+    /// This is only the case for operations that require multiple writes,
+    /// such as committing a transaction or recovering one in `initialise()`.
+    pub fn write_continues(&self) -> bool {
+        matches!(self.tickv.state.get(), State::Transaction(_))
+    }
+
     /// Perform a garbage collection on TicKV
     ///
     /// On success a `SuccessCode` will be returned.
@@ -319,7 +476,8 @@ impl<'a, C: FlashController<S>, const S: usize> AsyncTicKV<'a, C, S> {
     }
 
     /// Continue the last operation after the async operation has completed.
-    /// This should be called from a read/erase complete callback.
+    /// This should be called from a read/erase complete callback, or from a
+    /// write complete callback if `write_continues()` returns true.
     /// NOTE: If called from a read callback, `set_read_buffer` should be
     /// called first to update the data.
     ///
@@ -341,9 +499,20 @@ impl<'a, C: FlashController<S>, const S: usize> AsyncTicKV<'a, C, S> {
             State::AppendKey(_) => {
                 let value = self.value.take().unwrap();
                 let value_length = self.value_length.get();
-                let ret = self
-                    .tickv
-                    .append_key(self.key.get().unwrap(), &value[0..value_length]);
+                let ret = match self.unhashed_key.take() {
+                    Some(key) => {
+                        let ret = self.tickv.append_named_key(
+                            self.key.get().unwrap(),
+                            &key[0..self.unhashed_key_length.get()],
+                            &value[0..value_length],
+                        );
+                        self.unhashed_key.replace(Some(key));
+                        ret
+                    }
+                    None => self
+                        .tickv
+                        .append_key(self.key.get().unwrap(), &value[0..value_length]),
+                };
                 self.value.replace(Some(value));
                 (ret, value_length)
             }
@@ -362,6 +531,22 @@ impl<'a, C: FlashController<S>, const S: usize> AsyncTicKV<'a, C, S> {
                 Ok(bytes_freed) => (Ok(SuccessCode::Complete), bytes_freed),
                 Err(e) => (Err(e), 0),
             },
+            State::Transaction(_) => (self.tickv.continue_transaction(), 0),
+            State::NextKey(_) => {
+                let key = self.unhashed_key.take().unwrap();
+                let buf = self.value.take().unwrap();
+                let ret = self.tickv.next_key(self.position.get(), key, buf);
+                let buf_len = buf.len();
+                self.unhashed_key.replace(Some(key));
+                self.value.replace(Some(buf));
+                match ret {
+                    Ok((s, entry)) => {
+                        self.key_entry.set(Some(entry));
+                        (Ok(s), entry.value_length.min(buf_len))
+                    }
+                    Err(e) => (Err(e), 0),
+                }
+            }
             _ => unreachable!(),
         };
 
@@ -373,7 +558,9 @@ impl<'a, C: FlashController<S>, const S: usize> AsyncTicKV<'a, C, S> {
             Err(e) => match e {
                 ErrorCode::ReadNotReady(_) | ErrorCode::EraseNotReady(_) => (ret, None, 0),
                 ErrorCode::WriteNotReady(_) => {
-                    self.tickv.state.set(State::None);
+                    if !self.write_continues() {
+                        self.tickv.state.set(State::None);
+                    }
                     (ret, None, 0)
                 }
                 _ => {
@@ -493,7 +680,7 @@ mod tests {
             hash_function.finish()
         }
 
-        #[derive(Clone, Copy)]
+        #[derive(Clone, Copy, PartialEq)]
         enum FlashCtrlAction {
             Idle,
             Read,
@@ -1187,5 +1374,170 @@ mod tests {
                 _ => unreachable!("ret: {:?}", ret),
             }
         }
+
+        #[test]
+        fn test_named_key_iteration() {
+            let mut read_buf: [u8; 1024] = [0; 1024];
+            let mut hash_function = DefaultHasher::new();
+            MAIN_KEY.hash(&mut hash_function);
+
+            let tickv = AsyncTicKV::<FlashCtrl<1024>, 1024>::new(
+                FlashCtrl::new(false),
+                &mut read_buf,
+                0x1000,
+            );
+
+            let mut ret = tickv.initialise(hash_function.finish());
+            while ret.is_err() {
+                flash_ctrl_callback(&tickv);
+
+                // There is no actual delay in the test, just continue now
+                let (r, _buf, _len) = tickv.continue_operation();
+                ret = r;
+            }
+
+            static mut KEY_ONE: [u8; 3] = *b"ONE";
+            static mut KEY_TWO: [u8; 3] = *b"TWO";
+            static mut VALUE: [u8; 32] = [0x23; 32];
+            static mut KEY_BUF: [u8; 8] = [0; 8];
+            static mut BUF: [u8; 32] = [0; 32];
+
+            for key in unsafe { [&mut *addr_of_mut!(KEY_ONE), &mut *addr_of_mut!(KEY_TWO)] } {
+                println!("Add named key {:?}", key);
+                let hash = get_hashed_key(key);
+                let ret =
+                    unsafe { tickv.append_named_key(hash, key, 3, &mut *addr_of_mut!(VALUE), 32) };
+                match ret {
+                    Ok(SuccessCode::Queued) => {
+                        // There is no actual delay in the test, just continue now
+                        flash_ctrl_callback(&tickv);
+                        tickv.continue_operation().0.unwrap();
+                        assert!(tickv.take_unhashed_key().is_some());
+                    }
+                    Err((_, _, e)) => panic!("Unable to add named key: {e:?}"),
+                    _ => unreachable!(),
+                }
+            }
+
+            println!("Iterate over keys");
+            let mut position = 0;
+            let mut found = 0;
+            loop {
+                let ret = unsafe {
+                    tickv.next_key(
+                        position,
+                        &mut *addr_of_mut!(KEY_BUF),
+                        &mut *addr_of_mut!(BUF),
+                    )
+                };
+                assert_eq!(ret.ok(), Some(SuccessCode::Queued));
+
+                let (ret, buf, len) = loop {
+                    flash_ctrl_callback(&tickv);
+                    let (r, buf, len) = tickv.continue_operation();
+                    match r {
+                        Err(ErrorCode::ReadNotReady(_)) => {}
+                        _ => break (r, buf, len),
+                    }
+                };
+                let key = tickv.take_unhashed_key().unwrap();
+
+                match ret {
+                    Ok(_) => {
+                        let entry = tickv.key_entry().unwrap();
+                        assert_eq!(entry.key_length, 3);
+                        assert!(&key[0..3] == b"ONE" || &key[0..3] == b"TWO");
+                        assert_eq!(entry.hashed_key, get_hashed_key(&key[0..3]));
+                        assert_eq!(len, 32);
+                        assert_eq!(buf.unwrap()[0..len], [0x23; 32]);
+                        found += 1;
+                        position = entry.next_position;
+                    }
+                    Err(ErrorCode::KeyNotFound) => break,
+                    Err(e) => panic!("Unable to iterate keys: {e:?}"),
+                }
+            }
+            assert_eq!(found, 2);
+        }
+
+        #[test]
+        fn test_transaction() {
+            let mut read_buf: [u8; 1024] = [0; 1024];
+            let mut hash_function = DefaultHasher::new();
+            MAIN_KEY.hash(&mut hash_function);
+
+            let tickv = AsyncTicKV::<FlashCtrl<1024>, 1024>::new(
+                FlashCtrl::new(false),
+                &mut read_buf,
+                0x1000,
+            );
+
+            let mut ret = tickv.initialise(hash_function.finish());
+            while ret.is_err() {
+                flash_ctrl_callback(&tickv);
+
+                // There is no actual delay in the test, just continue now
+                let (r, _buf, _len) = tickv.continue_operation();
+                ret = r;
+            }
+
+            static mut OLD_VALUE: [u8; 32] = [0x23; 32];
+            static mut NEW_VALUE: [u8; 32] = [0x42; 32];
+            static mut BUF: [u8; 32] = [0; 32];
+
+            println!("Add Key ONE");
+            let ret = unsafe {
+                tickv.append_key(get_hashed_key(b"ONE"), &mut *addr_of_mut!(OLD_VALUE), 32)
+            };
+            assert_eq!(ret.ok(), Some(SuccessCode::Queued));
+            flash_ctrl_callback(&tickv);
+            tickv.continue_operation().0.unwrap();
+
+            println!("Stage keys ONE and TWO");
+            tickv.begin_transaction().unwrap();
+            for key in [b"ONE", b"TWO"] {
+                let ret = unsafe {
+                    tickv.append_key(get_hashed_key(key), &mut *addr_of_mut!(NEW_VALUE), 32)
+                };
+                assert_eq!(ret.ok(), Some(SuccessCode::Queued));
+                flash_ctrl_callback(&tickv);
+                tickv.continue_operation().0.unwrap();
+            }
+
+            println!("Commit the transaction");
+            assert_eq!(tickv.commit_transaction(), Ok(SuccessCode::Queued));
+            loop {
+                flash_ctrl_callback(&tickv);
+
+                if tickv.tickv.controller.get_waiting_action() == FlashCtrlAction::Write
+                    && !tickv.write_continues()
+                {
+                    // The final write has completed
+                    break;
+                }
+
+                match tickv.continue_operation().0 {
+                    Ok(SuccessCode::Complete) => break,
+                    Ok(_) | Err(ErrorCode::ReadNotReady(_)) | Err(ErrorCode::WriteNotReady(_)) => {}
+                    Err(e) => panic!("Unable to commit: {e:?}"),
+                }
+            }
+
+            for key in [b"ONE", b"TWO"] {
+                println!("Get key {:?}", key);
+                let ret = unsafe { tickv.get_key(get_hashed_key(key), &mut *addr_of_mut!(BUF)) };
+                assert_eq!(ret.ok(), Some(SuccessCode::Queued));
+                let (ret, buf, _len) = loop {
+                    flash_ctrl_callback(&tickv);
+                    let (r, buf, len) = tickv.continue_operation();
+                    match r {
+                        Err(ErrorCode::ReadNotReady(_)) => {}
+                        _ => break (r, buf, len),
+                    }
+                };
+                ret.unwrap();
+                assert_eq!(buf.unwrap()[0..32], [0x42; 32]);
+            }
+        }
     }
 }
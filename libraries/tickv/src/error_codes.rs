@@ -44,6 +44,10 @@ pub enum ErrorCode {
     WriteNotReady(usize),
     /// Indicates that the flash erase operation is not yet ready.
     EraseNotReady(usize),
+    /// A transaction has already been started
+    TransactionInProgress,
+    /// There is no transaction to commit or abort
+    NoTransaction,
 }
 
 impl From<ErrorCode> for isize {
@@ -64,6 +68,8 @@ impl From<ErrorCode> for isize {
             ErrorCode::ReadNotReady(_) => -13,
             ErrorCode::WriteNotReady(_) => -14,
             ErrorCode::EraseNotReady(_) => -15,
+            ErrorCode::TransactionInProgress => -16,
+            ErrorCode::NoTransaction => -17,
         }
     }
 }
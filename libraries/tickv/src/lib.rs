@@ -199,12 +199,24 @@
 //!
 //! TicKV stores the version when adding objects to the flash storage.
 //!
-//! TicKV is currently version 0.
-//!
-//!  * Version 0
-//!    * Version 0 is a draft version. It should NOT be used for important data!
-//!      Version 0 maintains no backwards compatible support and could change at
-//!      any time.
+//! Objects are written as version 1, unless they are added with
+//! `append_named_key()`, in which case they are written as version 2. Objects
+//! staged by a transaction are written as version 3 until it is committed.
+//! All three versions can be read.
+//!
+//!  * Version 1
+//!    * The original object format.
+//!  * Version 2
+//!    * The same as version 1, except that the `named` flag may be set and the
+//!      value is then prefixed with the unhashed key. Implementations that only
+//!      support version 1 report these objects as an unsupported version
+//!      instead of returning the key as part of the value. No migration is
+//!      needed for existing data, version 1 objects are still read as before.
+//!  * Version 3
+//!    * A version 1 or 2 object staged by a transaction. Implementations
+//!      without transactions report these objects as an unsupported version
+//!      instead of returning values that were never committed. Committing
+//!      the object rewrites its version to 1 or 2.
 //!
 
 #![no_std]
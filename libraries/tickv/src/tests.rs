@@ -4,7 +4,10 @@
 
 use crate::error_codes::ErrorCode;
 use crate::flash_controller::FlashController;
-use crate::tickv::{TicKV, HASH_OFFSET, LEN_OFFSET, MAIN_KEY, VERSION, VERSION_OFFSET};
+use crate::tickv::{
+    TicKV, FLAGS_PENDING, HASH_OFFSET, LEN_OFFSET, MAIN_KEY, NAMED_VERSION, PENDING_VERSION,
+    VERSION, VERSION_OFFSET,
+};
 use core::hash::{Hash, Hasher};
 use std::cell::Cell;
 use std::cell::RefCell;
@@ -534,12 +537,24 @@ mod no_check_store_flast_ctrl {
     // An example FlashCtrl implementation
     struct FlashCtrl {
         buf: RefCell<[[u8; 256]; 2]>,
+        // The number of writes that succeed before simulating a power loss
+        writes_left: Cell<usize>,
     }
 
     impl FlashCtrl {
         fn new() -> Self {
             Self {
                 buf: RefCell::new([[0xFF; 256]; 2]),
+                writes_left: Cell::new(usize::MAX),
+            }
+        }
+
+        // Create a flash controller with the same contents, as if the
+        // device was rebooted
+        fn reboot(&self) -> Self {
+            Self {
+                buf: RefCell::new(*self.buf.borrow()),
+                writes_left: Cell::new(usize::MAX),
             }
         }
     }
@@ -562,6 +577,11 @@ mod no_check_store_flast_ctrl {
                 address / 256
             );
 
+            if self.writes_left.get() == 0 {
+                return Err(ErrorCode::WriteFail);
+            }
+            self.writes_left.set(self.writes_left.get() - 1);
+
             for (i, d) in buf.iter().enumerate() {
                 self.buf.borrow_mut()[address / 256][(address % 256) + i] = *d;
             }
@@ -665,4 +685,454 @@ mod no_check_store_flast_ctrl {
             Err(ErrorCode::KeyNotFound)
         );
     }
+
+    #[test]
+    fn test_named_key_iteration() {
+        let mut read_buf: [u8; 256] = [0; 256];
+        let mut hash_function = DefaultHasher::new();
+        MAIN_KEY.hash(&mut hash_function);
+        let hash = hash_function.finish();
+
+        let tickv = TicKV::<FlashCtrl, 256>::new(FlashCtrl::new(), &mut read_buf, 0x200);
+        tickv.initialise(hash).unwrap();
+
+        let value: [u8; 32] = [0x23; 32];
+        let mut key_buf: [u8; 8] = [0; 8];
+        let mut buf: [u8; 32] = [0; 32];
+
+        println!("Iterate over empty flash");
+        assert_eq!(
+            tickv.next_key(0, &mut key_buf, &mut buf),
+            Err(ErrorCode::KeyNotFound)
+        );
+
+        println!("Add named keys ONE, TWO and THREE");
+        tickv
+            .append_named_key(get_hashed_key(b"ONE"), b"ONE", &value)
+            .unwrap();
+        tickv
+            .append_named_key(get_hashed_key(b"TWO"), b"TWO", &value)
+            .unwrap();
+        tickv
+            .append_named_key(get_hashed_key(b"THREE"), b"THREE", &value)
+            .unwrap();
+
+        println!("Add unnamed key FOUR");
+        tickv.append_key(get_hashed_key(b"FOUR"), &value).unwrap();
+
+        println!("Named keys can still be retrieved by hash");
+        tickv.get_key(get_hashed_key(b"TWO"), &mut buf).unwrap();
+        assert_eq!(buf, value);
+
+        println!("Delete Key TWO");
+        tickv.invalidate_key(get_hashed_key(b"TWO")).unwrap();
+
+        println!("Iterate over keys");
+        let mut position = 0;
+        let mut found = std::vec::Vec::new();
+        loop {
+            match tickv.next_key(position, &mut key_buf, &mut buf) {
+                Ok((_, entry)) => {
+                    assert_eq!(entry.value_length, 32);
+                    assert_eq!(buf, value);
+                    found.push((
+                        std::vec::Vec::from(&key_buf[0..entry.key_length.min(8)]),
+                        entry.hashed_key,
+                    ));
+                    position = entry.next_position;
+                }
+                Err(ErrorCode::KeyNotFound) => break,
+                Err(e) => panic!("Unable to iterate keys: {e:?}"),
+            }
+        }
+
+        found.sort();
+        let mut expected = std::vec![
+            (std::vec::Vec::new(), get_hashed_key(b"FOUR")),
+            (std::vec::Vec::from(&b"ONE"[..]), get_hashed_key(b"ONE")),
+            (std::vec::Vec::from(&b"THREE"[..]), get_hashed_key(b"THREE")),
+        ];
+        expected.sort();
+        assert_eq!(found, expected);
+    }
+
+    #[test]
+    fn test_named_key_version() {
+        let mut read_buf: [u8; 256] = [0; 256];
+        let mut hash_function = DefaultHasher::new();
+        MAIN_KEY.hash(&mut hash_function);
+        let hash = hash_function.finish();
+
+        let tickv = TicKV::<FlashCtrl, 256>::new(FlashCtrl::new(), &mut read_buf, 0x200);
+        tickv.initialise(hash).unwrap();
+
+        let value: [u8; 32] = [0x23; 32];
+
+        println!("Add named key ONE and unnamed key FOUR");
+        tickv
+            .append_named_key(get_hashed_key(b"ONE"), b"ONE", &value)
+            .unwrap();
+        tickv.append_key(get_hashed_key(b"FOUR"), &value).unwrap();
+
+        // Find the version the object with `hashed_key` was written with
+        let version_of = |hashed_key: u64| {
+            let flash = tickv.controller.buf.borrow();
+            flash
+                .iter()
+                .find_map(|region| {
+                    region
+                        .windows(HASH_OFFSET + 8)
+                        .find(|header| header[HASH_OFFSET..] == hashed_key.to_be_bytes())
+                        .map(|header| header[VERSION_OFFSET])
+                })
+                .unwrap()
+        };
+
+        println!("Named objects are written as the named version");
+        assert_eq!(version_of(get_hashed_key(b"ONE")), NAMED_VERSION);
+        assert_eq!(version_of(get_hashed_key(b"FOUR")), VERSION);
+    }
+
+    #[test]
+    fn test_named_key_too_long() {
+        let mut read_buf: [u8; 256] = [0; 256];
+        let mut hash_function = DefaultHasher::new();
+        MAIN_KEY.hash(&mut hash_function);
+        let hash = hash_function.finish();
+
+        let tickv = TicKV::<FlashCtrl, 256>::new(FlashCtrl::new(), &mut read_buf, 0x200);
+        tickv.initialise(hash).unwrap();
+
+        let key: [u8; 256] = [0x41; 256];
+        let value: [u8; 32] = [0x23; 32];
+
+        assert_eq!(
+            tickv.append_named_key(get_hashed_key(&key), &key, &value),
+            Err(ErrorCode::ObjectTooLarge)
+        );
+    }
+
+    #[test]
+    fn test_transaction_commit() {
+        let mut read_buf: [u8; 256] = [0; 256];
+        let mut hash_function = DefaultHasher::new();
+        MAIN_KEY.hash(&mut hash_function);
+        let hash = hash_function.finish();
+
+        let tickv = TicKV::<FlashCtrl, 256>::new(FlashCtrl::new(), &mut read_buf, 0x200);
+        tickv.initialise(hash).unwrap();
+
+        let old_value: [u8; 8] = [0x23; 8];
+        let new_value: [u8; 8] = [0x42; 8];
+        let mut key_buf: [u8; 8] = [0; 8];
+        let mut buf: [u8; 8] = [0; 8];
+
+        println!("Add Key ONE");
+        tickv
+            .append_key(get_hashed_key(b"ONE"), &old_value)
+            .unwrap();
+
+        assert_eq!(tickv.commit_transaction(), Err(ErrorCode::NoTransaction));
+
+        println!("Stage keys ONE and TWO");
+        tickv.begin_transaction().unwrap();
+        assert_eq!(
+            tickv.begin_transaction(),
+            Err(ErrorCode::TransactionInProgress)
+        );
+        tickv
+            .append_named_key(get_hashed_key(b"ONE"), b"ONE", &new_value)
+            .unwrap();
+        tickv
+            .append_key(get_hashed_key(b"TWO"), &new_value)
+            .unwrap();
+        assert_eq!(
+            tickv.append_key(get_hashed_key(b"TWO"), &new_value),
+            Err(ErrorCode::KeyAlreadyExists)
+        );
+
+        println!("Staged keys aren't visible");
+        tickv.get_key(get_hashed_key(b"ONE"), &mut buf).unwrap();
+        assert_eq!(buf, old_value);
+        assert_eq!(
+            tickv.get_key(get_hashed_key(b"TWO"), &mut buf),
+            Err(ErrorCode::KeyNotFound)
+        );
+        let (_, entry) = tickv.next_key(0, &mut key_buf, &mut buf).unwrap();
+        assert_eq!(entry.hashed_key, get_hashed_key(b"ONE"));
+        assert_eq!(entry.key_length, 0);
+        assert_eq!(
+            tickv.next_key(entry.next_position, &mut key_buf, &mut buf),
+            Err(ErrorCode::KeyNotFound)
+        );
+
+        println!("Commit the transaction");
+        tickv.commit_transaction().unwrap();
+
+        tickv.get_key(get_hashed_key(b"ONE"), &mut buf).unwrap();
+        assert_eq!(buf, new_value);
+        tickv.get_key(get_hashed_key(b"TWO"), &mut buf).unwrap();
+        assert_eq!(buf, new_value);
+
+        println!("Only the committed keys are iterated over");
+        let (_, entry) = tickv.next_key(0, &mut key_buf, &mut buf).unwrap();
+        let (_, next_entry) = tickv
+            .next_key(entry.next_position, &mut key_buf, &mut buf)
+            .unwrap();
+        let mut found = [entry.hashed_key, next_entry.hashed_key];
+        found.sort_unstable();
+        let mut expected = [get_hashed_key(b"ONE"), get_hashed_key(b"TWO")];
+        expected.sort_unstable();
+        assert_eq!(found, expected);
+        assert_eq!(
+            tickv.next_key(next_entry.next_position, &mut key_buf, &mut buf),
+            Err(ErrorCode::KeyNotFound)
+        );
+
+        println!("Appends are no longer staged");
+        assert_eq!(
+            tickv.append_key(get_hashed_key(b"TWO"), &new_value),
+            Err(ErrorCode::KeyAlreadyExists)
+        );
+    }
+
+    #[test]
+    fn test_transaction_abort() {
+        let mut read_buf: [u8; 256] = [0; 256];
+        let mut hash_function = DefaultHasher::new();
+        MAIN_KEY.hash(&mut hash_function);
+        let hash = hash_function.finish();
+
+        let tickv = TicKV::<FlashCtrl, 256>::new(FlashCtrl::new(), &mut read_buf, 0x200);
+        tickv.initialise(hash).unwrap();
+
+        let old_value: [u8; 8] = [0x23; 8];
+        let new_value: [u8; 8] = [0x42; 8];
+        let mut buf: [u8; 8] = [0; 8];
+
+        tickv
+            .append_key(get_hashed_key(b"ONE"), &old_value)
+            .unwrap();
+
+        println!("Stage keys ONE and TWO");
+        tickv.begin_transaction().unwrap();
+        tickv
+            .append_key(get_hashed_key(b"ONE"), &new_value)
+            .unwrap();
+        tickv
+            .append_key(get_hashed_key(b"TWO"), &new_value)
+            .unwrap();
+
+        println!("Abort the transaction");
+        tickv.abort_transaction().unwrap();
+        assert_eq!(tickv.abort_transaction(), Err(ErrorCode::NoTransaction));
+
+        tickv.get_key(get_hashed_key(b"ONE"), &mut buf).unwrap();
+        assert_eq!(buf, old_value);
+        assert_eq!(
+            tickv.get_key(get_hashed_key(b"TWO"), &mut buf),
+            Err(ErrorCode::KeyNotFound)
+        );
+
+        println!("The key can be staged again");
+        tickv.begin_transaction().unwrap();
+        tickv
+            .append_key(get_hashed_key(b"TWO"), &new_value)
+            .unwrap();
+        tickv.commit_transaction().unwrap();
+        tickv.get_key(get_hashed_key(b"TWO"), &mut buf).unwrap();
+        assert_eq!(buf, new_value);
+    }
+
+    #[test]
+    fn test_transaction_power_loss_before_commit() {
+        let mut read_buf: [u8; 256] = [0; 256];
+        let mut hash_function = DefaultHasher::new();
+        MAIN_KEY.hash(&mut hash_function);
+        let hash = hash_function.finish();
+
+        let tickv = TicKV::<FlashCtrl, 256>::new(FlashCtrl::new(), &mut read_buf, 0x200);
+        tickv.initialise(hash).unwrap();
+
+        let old_value: [u8; 8] = [0x23; 8];
+        let new_value: [u8; 8] = [0x42; 8];
+        let mut buf: [u8; 8] = [0; 8];
+
+        tickv
+            .append_key(get_hashed_key(b"ONE"), &old_value)
+            .unwrap();
+
+        println!("Stage keys ONE and TWO");
+        tickv.begin_transaction().unwrap();
+        tickv
+            .append_key(get_hashed_key(b"ONE"), &new_value)
+            .unwrap();
+        tickv
+            .append_key(get_hashed_key(b"TWO"), &new_value)
+            .unwrap();
+
+        println!("Lose power before the commit record is written");
+        tickv.controller.writes_left.set(0);
+        assert_eq!(tickv.commit_transaction(), Err(ErrorCode::WriteFail));
+
+        let mut read_buf: [u8; 256] = [0; 256];
+        let tickv = TicKV::<FlashCtrl, 256>::new(tickv.controller.reboot(), &mut read_buf, 0x200);
+        tickv.initialise(hash).unwrap();
+
+        tickv.get_key(get_hashed_key(b"ONE"), &mut buf).unwrap();
+        assert_eq!(buf, old_value);
+        assert_eq!(
+            tickv.get_key(get_hashed_key(b"TWO"), &mut buf),
+            Err(ErrorCode::KeyNotFound)
+        );
+
+        println!("The staged objects were discarded");
+        tickv.begin_transaction().unwrap();
+        tickv
+            .append_key(get_hashed_key(b"TWO"), &new_value)
+            .unwrap();
+    }
+
+    #[test]
+    fn test_transaction_power_loss_after_commit() {
+        let mut read_buf: [u8; 256] = [0; 256];
+        let mut hash_function = DefaultHasher::new();
+        MAIN_KEY.hash(&mut hash_function);
+        let hash = hash_function.finish();
+
+        let tickv = TicKV::<FlashCtrl, 256>::new(FlashCtrl::new(), &mut read_buf, 0x200);
+        tickv.initialise(hash).unwrap();
+
+        let old_value: [u8; 8] = [0x23; 8];
+        let new_value: [u8; 8] = [0x42; 8];
+        let mut buf: [u8; 8] = [0; 8];
+
+        tickv
+            .append_key(get_hashed_key(b"ONE"), &old_value)
+            .unwrap();
+
+        println!("Stage keys ONE and TWO");
+        tickv.begin_transaction().unwrap();
+        tickv
+            .append_key(get_hashed_key(b"ONE"), &new_value)
+            .unwrap();
+        tickv
+            .append_key(get_hashed_key(b"TWO"), &new_value)
+            .unwrap();
+
+        println!("Lose power after the commit record is written");
+        tickv.controller.writes_left.set(2);
+        assert_eq!(tickv.commit_transaction(), Err(ErrorCode::WriteFail));
+
+        let mut read_buf: [u8; 256] = [0; 256];
+        let tickv = TicKV::<FlashCtrl, 256>::new(tickv.controller.reboot(), &mut read_buf, 0x200);
+        tickv.initialise(hash).unwrap();
+
+        tickv.get_key(get_hashed_key(b"ONE"), &mut buf).unwrap();
+        assert_eq!(buf, new_value);
+        tickv.get_key(get_hashed_key(b"TWO"), &mut buf).unwrap();
+        assert_eq!(buf, new_value);
+    }
+
+    // The region and offset of the header of the last object with
+    // `hashed_key`
+    fn header_of(flash: &FlashCtrl, hashed_key: u64) -> (usize, usize) {
+        let flash = flash.buf.borrow();
+        flash
+            .iter()
+            .enumerate()
+            .filter_map(|(region, data)| {
+                data.windows(HASH_OFFSET + 8)
+                    .rposition(|header| header[HASH_OFFSET..] == hashed_key.to_be_bytes())
+                    .map(|offset| (region, offset))
+            })
+            .last()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_transaction_pending_version() {
+        let mut read_buf: [u8; 256] = [0; 256];
+        let mut hash_function = DefaultHasher::new();
+        MAIN_KEY.hash(&mut hash_function);
+        let hash = hash_function.finish();
+
+        let tickv = TicKV::<FlashCtrl, 256>::new(FlashCtrl::new(), &mut read_buf, 0x200);
+        tickv.initialise(hash).unwrap();
+
+        let value: [u8; 8] = [0x42; 8];
+        let mut buf: [u8; 8] = [0; 8];
+        let version_of = |hashed_key: u64| {
+            let (region, offset) = header_of(&tickv.controller, hashed_key);
+            tickv.controller.buf.borrow()[region][offset + VERSION_OFFSET]
+        };
+
+        println!("Stage named key ONE and unnamed key TWO");
+        tickv.begin_transaction().unwrap();
+        tickv
+            .append_named_key(get_hashed_key(b"ONE"), b"ONE", &value)
+            .unwrap();
+        tickv.append_key(get_hashed_key(b"TWO"), &value).unwrap();
+
+        println!("Staged objects are written as the pending version");
+        assert_eq!(version_of(get_hashed_key(b"ONE")), PENDING_VERSION);
+        assert_eq!(version_of(get_hashed_key(b"TWO")), PENDING_VERSION);
+
+        println!("Committing restores the version they were hashed with");
+        tickv.commit_transaction().unwrap();
+        assert_eq!(version_of(get_hashed_key(b"ONE")), NAMED_VERSION);
+        assert_eq!(version_of(get_hashed_key(b"TWO")), VERSION);
+        tickv.get_key(get_hashed_key(b"ONE"), &mut buf).unwrap();
+        assert_eq!(buf, value);
+        tickv.get_key(get_hashed_key(b"TWO"), &mut buf).unwrap();
+        assert_eq!(buf, value);
+    }
+
+    #[test]
+    fn test_transaction_pending_version_without_flag() {
+        let mut read_buf: [u8; 256] = [0; 256];
+        let mut hash_function = DefaultHasher::new();
+        MAIN_KEY.hash(&mut hash_function);
+        let hash = hash_function.finish();
+
+        let tickv = TicKV::<FlashCtrl, 256>::new(FlashCtrl::new(), &mut read_buf, 0x200);
+        tickv.initialise(hash).unwrap();
+
+        let value: [u8; 8] = [0x42; 8];
+        let mut buf: [u8; 8] = [0; 8];
+
+        println!("Stage keys ONE and TWO");
+        tickv.begin_transaction().unwrap();
+        tickv.append_key(get_hashed_key(b"ONE"), &value).unwrap();
+        tickv.append_key(get_hashed_key(b"TWO"), &value).unwrap();
+
+        println!("Clear only the pending flag of TWO");
+        let (region, offset) = header_of(&tickv.controller, get_hashed_key(b"TWO"));
+        tickv.controller.buf.borrow_mut()[region][offset + LEN_OFFSET] &= !(FLAGS_PENDING << 4);
+
+        println!("An object with the pending version is still staged");
+        assert_eq!(
+            tickv.get_key(get_hashed_key(b"TWO"), &mut buf),
+            Err(ErrorCode::KeyNotFound)
+        );
+
+        println!("Lose power after the commit record is written");
+        tickv.controller.writes_left.set(1);
+        assert_eq!(tickv.commit_transaction(), Err(ErrorCode::WriteFail));
+
+        let mut read_buf: [u8; 256] = [0; 256];
+        let tickv = TicKV::<FlashCtrl, 256>::new(tickv.controller.reboot(), &mut read_buf, 0x200);
+        tickv.initialise(hash).unwrap();
+
+        println!("Recovery commits both objects");
+        tickv.get_key(get_hashed_key(b"ONE"), &mut buf).unwrap();
+        assert_eq!(buf, value);
+        tickv.get_key(get_hashed_key(b"TWO"), &mut buf).unwrap();
+        assert_eq!(buf, value);
+        let (region, offset) = header_of(&tickv.controller, get_hashed_key(b"TWO"));
+        assert_eq!(
+            tickv.controller.buf.borrow()[region][offset + VERSION_OFFSET],
+            VERSION
+        );
+    }
 }
@@ -10,8 +10,45 @@ use crate::flash_controller::FlashController;
 use crate::success_codes::SuccessCode;
 use core::cell::Cell;
 
-/// The current version of TicKV
+/// The version of objects in the original format
 pub const VERSION: u8 = 1;
+/// The version of objects that use the `named` flag
+///
+/// Version 2 objects are laid out as version 1 objects, but the value of
+/// named objects is prefixed with the unhashed key. They have a different
+/// version so that implementations that only support version 1 reject them
+/// rather than returning the prefix as part of the value. Objects that
+/// don't use the flag are still written as version 1.
+pub const NAMED_VERSION: u8 = 2;
+/// The version of objects staged by a transaction
+///
+/// Staged objects are laid out as version 1 or 2 objects, but have their own
+/// version so that implementations without transactions reject them rather
+/// than returning values that haven't been committed. Committing the object
+/// rewrites the version to 1 or 2, which only clears bits.
+pub const PENDING_VERSION: u8 = 3;
+
+/// Check if objects of `version` can be read.
+pub(crate) fn supported_version(version: u8) -> bool {
+    version == VERSION || version == NAMED_VERSION || version == PENDING_VERSION
+}
+
+/// Check if an object was staged by a transaction and not committed yet.
+///
+/// Either the `pending` flag or the version is enough, as a power loss while
+/// committing the object can leave only one of them cleared.
+pub(crate) fn is_staged(version: u8, len_flags: u8) -> bool {
+    version == PENDING_VERSION || len_flags & (FLAGS_PENDING << 4) != 0
+}
+
+/// The version of a staged object once it has been committed.
+pub(crate) fn committed_version(len_flags: u8) -> u8 {
+    if len_flags & (FLAGS_NAMED << 4) != 0 {
+        NAMED_VERSION
+    } else {
+        VERSION
+    }
+}
 
 #[derive(Clone, Copy, PartialEq)]
 pub(crate) enum InitState {
@@ -37,6 +74,26 @@ pub(crate) enum RubbishState {
     EraseRegion(usize, usize),
 }
 
+/// The `Option` and `bool` values in these states indicate that a read of
+/// the region has already been started, so the data will be in the read
+/// buffer.
+#[derive(Clone, Copy, PartialEq)]
+pub(crate) enum TransactionState {
+    /// Trying to find the commit record
+    FindCommit(Option<usize>),
+    /// Trying to append the commit record
+    AppendCommit(Option<usize>),
+    /// Searching a region for staged objects, either committing (`true`)
+    /// or discarding (`false`) them
+    ScanRegion(bool, usize, bool),
+    /// Invalidating the previous value of a staged object in a region
+    Supersede(usize, u64, Option<usize>),
+    /// Clearing the pending flag of a staged object in a region
+    ClearPending(usize, u64, bool),
+    /// Trying to invalidate the commit record
+    InvalidateCommit(Option<usize>),
+}
+
 #[derive(Clone, Copy, PartialEq)]
 /// The current state machine when trying to complete a previous operation.
 /// This is used when returning from a complete async `FlashController` call.
@@ -55,6 +112,10 @@ pub(crate) enum State {
     ZeroiseKey(KeyState),
     /// Running garbage collection
     GarbageCollect(RubbishState),
+    /// Iterating over the stored keys
+    NextKey(KeyState),
+    /// Committing, discarding or recovering a transaction
+    Transaction(TransactionState),
 }
 
 /// The struct storing all of the TicKV information.
@@ -64,6 +125,25 @@ pub struct TicKV<'a, C: FlashController<S>, const S: usize> {
     flash_size: usize,
     pub(crate) read_buffer: Cell<Option<&'a mut [u8; S]>>,
     pub(crate) state: Cell<State>,
+    /// The hashed main key, skipped when iterating over keys
+    main_key: Cell<u64>,
+    /// Set if a transaction is in progress, in which case appended objects
+    /// are staged until the transaction is committed
+    transaction: Cell<bool>,
+}
+
+/// Details of an object found by `next_key()`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct KeyEntry {
+    /// The hashed key of the object
+    pub hashed_key: u64,
+    /// The length of the unhashed key stored with the object. This is zero
+    /// if the object was added with `append_key()`.
+    pub key_length: usize,
+    /// The length of the value stored in the object
+    pub value_length: usize,
+    /// The position to pass to `next_key()` to continue iterating
+    pub next_position: usize,
 }
 
 /// This is the current object header used for TicKV objects
@@ -77,6 +157,8 @@ struct ObjectHeader {
 }
 
 pub(crate) const FLAGS_VALID: u8 = 8;
+pub(crate) const FLAGS_NAMED: u8 = 4;
+pub(crate) const FLAGS_PENDING: u8 = 2;
 
 impl ObjectHeader {
     fn new(hashed_key: u64, len: u16) -> Self {
@@ -115,6 +197,8 @@ impl<'a, C: FlashController<S>, const S: usize> TicKV<'a, C, S> {
             flash_size,
             read_buffer: Cell::new(Some(read_buffer)),
             state: Cell::new(State::None),
+            main_key: Cell::new(0),
+            transaction: Cell::new(false),
         }
     }
 
@@ -126,10 +210,15 @@ impl<'a, C: FlashController<S>, const S: usize> TicKV<'a, C, S> {
     /// If the specified region has not already been setup for TicKV
     /// the entire region will be erased.
     ///
+    /// If the region is already setup any transaction that was interrupted
+    /// is recovered. If the commit record was written the staged objects are
+    /// committed, otherwise they are discarded.
+    ///
     /// On success nothing will be returned.
     /// On error a `ErrorCode` will be returned.
     pub fn initialise(&self, hashed_main_key: u64) -> Result<SuccessCode, ErrorCode> {
         let mut buf: [u8; 0] = [0; 0];
+        self.main_key.set(hashed_main_key);
 
         let key_ret = match self.state.get() {
             State::None => self.get_key(hashed_main_key, &mut buf),
@@ -137,11 +226,18 @@ impl<'a, C: FlashController<S>, const S: usize> TicKV<'a, C, S> {
                 InitState::GetKeyReadRegion(_) => self.get_key(hashed_main_key, &mut buf),
                 _ => Err(ErrorCode::EraseNotReady(0)),
             },
+            State::Transaction(_) => return self.continue_transaction(),
             _ => unreachable!(),
         };
 
         match key_ret {
-            Ok((ret, _len)) => Ok(ret),
+            Ok((_ret, _len)) => {
+                // Recover from any interrupted transaction
+                self.transaction.set(false);
+                self.state
+                    .set(State::Transaction(TransactionState::FindCommit(None)));
+                self.continue_transaction()
+            }
             Err(e) => {
                 match e {
                     ErrorCode::ReadNotReady(reg) => {
@@ -264,6 +360,9 @@ impl<'a, C: FlashController<S>, const S: usize> TicKV<'a, C, S> {
 
     /// Find a key in some loaded region data.
     ///
+    /// If `pending` is set only objects staged by a transaction are found,
+    /// otherwise staged objects are skipped.
+    ///
     /// On success return the offset in the region_data where the key is and the
     /// total length of the key.
     /// On failure return a bool indicating if the caller should keep looking in
@@ -272,6 +371,7 @@ impl<'a, C: FlashController<S>, const S: usize> TicKV<'a, C, S> {
         &self,
         hash: u64,
         region_data: &[u8],
+        pending: bool,
     ) -> Result<(usize, u16), (bool, ErrorCode)> {
         // Determine the total size of our payload
 
@@ -297,11 +397,11 @@ impl<'a, C: FlashController<S>, const S: usize> TicKV<'a, C, S> {
                 empty = false;
 
                 // We found a version, check that we support it
-                if *region_data
-                    .get(offset + VERSION_OFFSET)
-                    .ok_or((false, ErrorCode::KeyNotFound))?
-                    != VERSION
-                {
+                if !supported_version(
+                    *region_data
+                        .get(offset + VERSION_OFFSET)
+                        .ok_or((false, ErrorCode::KeyNotFound))?,
+                ) {
                     return Err((false, ErrorCode::UnsupportedVersion));
                 }
 
@@ -334,6 +434,20 @@ impl<'a, C: FlashController<S>, const S: usize> TicKV<'a, C, S> {
                     continue;
                 }
 
+                // Only look at staged entries if we were asked to
+                if is_staged(
+                    *region_data
+                        .get(offset + VERSION_OFFSET)
+                        .ok_or((false, ErrorCode::CorruptData))?,
+                    *region_data
+                        .get(offset + LEN_OFFSET)
+                        .ok_or((false, ErrorCode::CorruptData))?,
+                ) != pending
+                {
+                    offset += total_length as usize;
+                    continue;
+                }
+
                 // We have found a valid entry, see if it is ours.
                 if *region_data
                     .get(offset + HASH_OFFSET)
@@ -382,6 +496,38 @@ impl<'a, C: FlashController<S>, const S: usize> TicKV<'a, C, S> {
         }
     }
 
+    /// Determine the length of the unhashed key prefix of the object at
+    /// `offset` in some loaded region data.
+    ///
+    /// Objects added with `append_named_key()` store the length of the
+    /// unhashed key followed by the key itself before the value. This returns
+    /// the total length of that prefix, or zero if the object has no key
+    /// stored.
+    fn key_prefix_length(&self, region_data: &[u8], offset: usize) -> Result<usize, ErrorCode> {
+        let len_flags = *region_data
+            .get(offset + LEN_OFFSET)
+            .ok_or(ErrorCode::CorruptData)?;
+
+        if len_flags & (FLAGS_NAMED << 4) == 0 {
+            return Ok(0);
+        }
+
+        let total_length = ((len_flags as u16) & !0xF0) << 8
+            | *region_data
+                .get(offset + LEN_OFFSET + 1)
+                .ok_or(ErrorCode::CorruptData)? as u16;
+        let prefix_length = *region_data
+            .get(offset + HEADER_LENGTH)
+            .ok_or(ErrorCode::CorruptData)? as usize
+            + 1;
+
+        if HEADER_LENGTH + prefix_length + CHECK_SUM_LEN > total_length as usize {
+            return Err(ErrorCode::CorruptData);
+        }
+
+        Ok(prefix_length)
+    }
+
     /// Appends the key/value pair to flash storage.
     ///
     /// `hash`: A hashed key. This key will be used in future to retrieve
@@ -391,19 +537,62 @@ impl<'a, C: FlashController<S>, const S: usize> TicKV<'a, C, S> {
     /// On success nothing will be returned.
     /// On error a `ErrorCode` will be returned.
     pub fn append_key(&self, hash: u64, value: &[u8]) -> Result<SuccessCode, ErrorCode> {
+        self.append_object(hash, None, value)
+    }
+
+    /// Appends the key/value pair to flash storage, storing the unhashed
+    /// key alongside the value.
+    ///
+    /// This behaves the same as `append_key()`, except that `key` is
+    /// saved in the object so that it can be returned by `next_key()`.
+    /// The stored key is not returned by `get_key()`.
+    ///
+    /// `hash`: A hashed key. This key will be used in future to retrieve
+    ///         or remove the `value`.
+    /// `key`: The unhashed key, at most 255 bytes long.
+    /// `value`: A buffer containing the data to be stored to flash.
+    ///
+    /// On success nothing will be returned.
+    /// On error a `ErrorCode` will be returned.
+    pub fn append_named_key(
+        &self,
+        hash: u64,
+        key: &[u8],
+        value: &[u8],
+    ) -> Result<SuccessCode, ErrorCode> {
+        if key.len() > 0xFF {
+            return Err(ErrorCode::ObjectTooLarge);
+        }
+
+        self.append_object(hash, Some(key), value)
+    }
+
+    fn append_object(
+        &self,
+        hash: u64,
+        key: Option<&[u8]>,
+        value: &[u8],
+    ) -> Result<SuccessCode, ErrorCode> {
         let region = self.get_region(hash);
         let check_sum = crc32::Crc32::new();
 
+        // The stored key is prefixed with its length
+        let prefix_length = key.map_or(0, |k| k.len() + 1);
+
         // Length not including check sum
-        let package_length = HEADER_LENGTH + value.len();
-        let object_length = HEADER_LENGTH + value.len() + CHECK_SUM_LEN;
+        let package_length = HEADER_LENGTH + prefix_length + value.len();
+        let object_length = package_length + CHECK_SUM_LEN;
 
         if object_length > 0xFFF {
             return Err(ErrorCode::ObjectTooLarge);
         }
 
         // Create the header:
-        let header = ObjectHeader::new(hash, object_length as u16);
+        let mut header = ObjectHeader::new(hash, object_length as u16);
+        if key.is_some() {
+            header.version = NAMED_VERSION;
+            header.flags |= FLAGS_NAMED;
+        }
 
         let mut region_offset: isize = 0;
 
@@ -441,7 +630,10 @@ impl<'a, C: FlashController<S>, const S: usize> TicKV<'a, C, S> {
                 };
             }
 
-            if self.find_key_offset(hash, region_data).is_ok() {
+            if self
+                .find_key_offset(hash, region_data, self.transaction.get())
+                .is_ok()
+            {
                 // Check to make sure we don't already have this key
                 self.read_buffer.replace(Some(region_data));
                 return Err(ErrorCode::KeyAlreadyExists);
@@ -477,11 +669,11 @@ impl<'a, C: FlashController<S>, const S: usize> TicKV<'a, C, S> {
                     != 0xFF
                 {
                     // We found a version, check that we support it
-                    if *region_data
-                        .get(offset + VERSION_OFFSET)
-                        .ok_or(ErrorCode::KeyNotFound)?
-                        != VERSION
-                    {
+                    if !supported_version(
+                        *region_data
+                            .get(offset + VERSION_OFFSET)
+                            .ok_or(ErrorCode::KeyNotFound)?,
+                    ) {
                         self.read_buffer.replace(Some(region_data));
                         return Err(ErrorCode::UnsupportedVersion);
                     }
@@ -618,9 +810,41 @@ impl<'a, C: FlashController<S>, const S: usize> TicKV<'a, C, S> {
                         .ok_or(ErrorCode::CorruptData)?,
                 );
 
+                // Mark the object as staged if we are in a transaction. This
+                // isn't included in the check sum as it is cleared once the
+                // transaction is committed.
+                if self.transaction.get() {
+                    *region_data
+                        .get_mut(offset + VERSION_OFFSET)
+                        .ok_or(ErrorCode::RegionFull)? = PENDING_VERSION;
+                    *region_data
+                        .get_mut(offset + LEN_OFFSET)
+                        .ok_or(ErrorCode::RegionFull)? |= FLAGS_PENDING << 4;
+                }
+
+                // Copy the unhashed key, if we have one
+                if let Some(key) = key {
+                    *region_data
+                        .get_mut(offset + HEADER_LENGTH)
+                        .ok_or(ErrorCode::ObjectTooLarge)? = key.len() as u8;
+                    let slice = region_data
+                        .get_mut(
+                            (offset + HEADER_LENGTH + 1)..(offset + HEADER_LENGTH + prefix_length),
+                        )
+                        .ok_or(ErrorCode::ObjectTooLarge)?;
+                    slice.copy_from_slice(key);
+
+                    // Include the key in the hash
+                    check_sum.update(
+                        region_data
+                            .get((offset + HEADER_LENGTH)..(offset + HEADER_LENGTH + prefix_length))
+                            .ok_or(ErrorCode::CorruptData)?,
+                    );
+                }
+
                 // Copy the value
                 let slice = region_data
-                    .get_mut((offset + HEADER_LENGTH)..(offset + package_length))
+                    .get_mut((offset + HEADER_LENGTH + prefix_length)..(offset + package_length))
                     .ok_or(ErrorCode::ObjectTooLarge)?;
                 slice.copy_from_slice(value);
 
@@ -705,7 +929,7 @@ impl<'a, C: FlashController<S>, const S: usize> TicKV<'a, C, S> {
                 };
             }
 
-            match self.find_key_offset(hash, region_data) {
+            match self.find_key_offset(hash, region_data, false) {
                 Ok((offset, total_length)) => {
                     // Add the header data to the check hash
                     check_sum.update(
@@ -714,8 +938,24 @@ impl<'a, C: FlashController<S>, const S: usize> TicKV<'a, C, S> {
                             .ok_or(ErrorCode::ObjectTooLarge)?,
                     );
 
+                    // Skip over the unhashed key, if one was stored
+                    let prefix_length = match self.key_prefix_length(region_data, offset) {
+                        Ok(len) => len,
+                        Err(e) => {
+                            self.read_buffer.replace(Some(region_data));
+                            return Err(e);
+                        }
+                    };
+                    check_sum.update(
+                        region_data
+                            .get((offset + HEADER_LENGTH)..(offset + HEADER_LENGTH + prefix_length))
+                            .ok_or(ErrorCode::CorruptData)?,
+                    );
+                    let value_offset = offset + HEADER_LENGTH + prefix_length;
+
                     // The size of the stored object's actual data;
-                    let value_length = total_length as usize - HEADER_LENGTH - CHECK_SUM_LEN;
+                    let value_length =
+                        total_length as usize - HEADER_LENGTH - CHECK_SUM_LEN - prefix_length;
 
                     // Make sure if will fit in the buffer
                     if buf.len() < value_length {
@@ -724,7 +964,7 @@ impl<'a, C: FlashController<S>, const S: usize> TicKV<'a, C, S> {
                         for i in 0..buf.len() {
                             *buf.get_mut(i)
                                 .ok_or(ErrorCode::BufferTooSmall(value_length))? = *region_data
-                                .get(offset + HEADER_LENGTH + i)
+                                .get(value_offset + i)
                                 .ok_or(ErrorCode::BufferTooSmall(value_length))?;
                         }
 
@@ -736,7 +976,7 @@ impl<'a, C: FlashController<S>, const S: usize> TicKV<'a, C, S> {
                     for i in 0..value_length {
                         *buf.get_mut(i)
                             .ok_or(ErrorCode::BufferTooSmall(value_length))? = *region_data
-                            .get(offset + HEADER_LENGTH + i)
+                            .get(value_offset + i)
                             .ok_or(ErrorCode::CorruptData)?;
                         check_sum.update(&[*buf.get(i).ok_or(ErrorCode::CorruptData)?])
                     }
@@ -831,7 +1071,7 @@ impl<'a, C: FlashController<S>, const S: usize> TicKV<'a, C, S> {
                 };
             }
 
-            match self.find_key_offset(hash, region_data) {
+            match self.find_key_offset(hash, region_data, false) {
                 Ok((offset, _data_len)) => {
                     // We found a key, let's delete it
                     *region_data
@@ -929,7 +1169,7 @@ impl<'a, C: FlashController<S>, const S: usize> TicKV<'a, C, S> {
                 };
             }
 
-            match self.find_key_offset(hash, region_data) {
+            match self.find_key_offset(hash, region_data, false) {
                 Ok((offset, data_len)) => {
                     // We found a key, let's delete it
                     *region_data
@@ -983,6 +1223,580 @@ impl<'a, C: FlashController<S>, const S: usize> TicKV<'a, C, S> {
         }
     }
 
+    /// Finds the next valid object in flash storage, starting at `position`.
+    ///
+    /// This allows iterating over all of the keys currently stored. To start
+    /// iterating pass a `position` of zero, then pass the `next_position` of
+    /// the returned `KeyEntry` to find the following object. The main key,
+    /// the transaction commit record and objects staged by a transaction
+    /// that hasn't been committed are not returned. Objects with an invalid
+    /// check sum are skipped.
+    ///
+    /// - `position`: The position in flash to start searching from.
+    /// - `key`: A buffer to store the unhashed key to. This is only filled
+    ///          for objects added with `append_named_key()`.
+    /// - `value`: A buffer to store the value to.
+    ///
+    /// If either buffer is too small to hold the entire key or value then
+    /// as much as fits is copied in. The full lengths are reported in the
+    /// returned `KeyEntry`.
+    ///
+    /// On success a `SuccessCode` and a `KeyEntry` describing the object
+    /// will be returned.
+    /// Once there are no more objects `ErrorCode::KeyNotFound` will be
+    /// returned. On any other error a `ErrorCode` will be returned.
+    pub fn next_key(
+        &self,
+        position: usize,
+        key: &mut [u8],
+        value: &mut [u8],
+    ) -> Result<(SuccessCode, KeyEntry), ErrorCode> {
+        let num_region = self.flash_size / S;
+        let mut region = position / S;
+        let mut offset = position % S;
+
+        if let State::NextKey(KeyState::ReadRegion(reg)) = self.state.get() {
+            if reg != region {
+                // We have already moved on to a later region
+                region = reg;
+                offset = 0;
+            }
+        }
+
+        while region < num_region {
+            // Get the data from that region
+            let region_data = self.read_buffer.take().unwrap();
+            if self.state.get() != State::NextKey(KeyState::ReadRegion(region)) {
+                match self.controller.read_region(region, region_data) {
+                    Ok(()) => {}
+                    Err(e) => {
+                        self.read_buffer.replace(Some(region_data));
+                        if let ErrorCode::ReadNotReady(reg) = e {
+                            self.state.set(State::NextKey(KeyState::ReadRegion(reg)));
+                        }
+                        return Err(e);
+                    }
+                };
+            }
+
+            match self.next_entry_in_region(region, offset, region_data, key, value) {
+                Ok(Some(entry)) => {
+                    self.read_buffer.replace(Some(region_data));
+                    self.state.set(State::None);
+                    return Ok((SuccessCode::Complete, entry));
+                }
+                Ok(None) => {
+                    // Nothing left in this region, try the next one
+                    self.read_buffer.replace(Some(region_data));
+                    self.state.set(State::None);
+                    region += 1;
+                    offset = 0;
+                }
+                Err(e) => {
+                    self.read_buffer.replace(Some(region_data));
+                    self.state.set(State::None);
+                    return Err(e);
+                }
+            }
+        }
+
+        Err(ErrorCode::KeyNotFound)
+    }
+
+    /// Find the next valid object in some loaded region data, starting at
+    /// `offset`.
+    ///
+    /// On success return the details of the object, or `None` if there are no
+    /// more valid objects in this region.
+    fn next_entry_in_region(
+        &self,
+        region: usize,
+        mut offset: usize,
+        region_data: &[u8],
+        key: &mut [u8],
+        value: &mut [u8],
+    ) -> Result<Option<KeyEntry>, ErrorCode> {
+        loop {
+            if offset + HEADER_LENGTH >= S {
+                // We have reached the end of the region
+                return Ok(None);
+            }
+
+            let version = *region_data
+                .get(offset + VERSION_OFFSET)
+                .ok_or(ErrorCode::CorruptData)?;
+            if version == 0xFF {
+                // We hit the end.
+                return Ok(None);
+            }
+            if !supported_version(version) {
+                return Err(ErrorCode::UnsupportedVersion);
+            }
+
+            let len_flags = *region_data
+                .get(offset + LEN_OFFSET)
+                .ok_or(ErrorCode::CorruptData)?;
+            let total_length = (((len_flags as u16) & !0xF0) << 8
+                | *region_data
+                    .get(offset + LEN_OFFSET + 1)
+                    .ok_or(ErrorCode::CorruptData)? as u16) as usize;
+
+            if total_length == 0 {
+                // We found something invalid here
+                return Ok(None);
+            }
+
+            let object_offset = offset;
+            offset += total_length;
+
+            // Skip entries that have been deleted or haven't been committed
+            if len_flags & 0x80 != 0x80 || is_staged(version, len_flags) {
+                continue;
+            }
+
+            let hash = u64::from_be_bytes(
+                region_data
+                    .get((object_offset + HASH_OFFSET)..(object_offset + HASH_OFFSET + 8))
+                    .ok_or(ErrorCode::CorruptData)?
+                    .try_into()
+                    .or(Err(ErrorCode::CorruptData))?,
+            );
+            if hash == self.main_key.get() || hash == !self.main_key.get() {
+                continue;
+            }
+
+            let object = match region_data.get(object_offset..offset) {
+                Some(object) if total_length >= HEADER_LENGTH + CHECK_SUM_LEN => object,
+                _ => return Err(ErrorCode::CorruptData),
+            };
+
+            // Check the check sum, skipping the object if it doesn't match
+            let check_sum = crc32::Crc32::new();
+            check_sum.update(&object[..(total_length - CHECK_SUM_LEN)]);
+            if object[(total_length - CHECK_SUM_LEN)..] != check_sum.finalise().to_ne_bytes() {
+                continue;
+            }
+
+            let prefix_length = self.key_prefix_length(region_data, object_offset)?;
+            let key_length = prefix_length.saturating_sub(1);
+            let value_length = total_length - HEADER_LENGTH - CHECK_SUM_LEN - prefix_length;
+
+            // Copy in as much of the key and value as will fit
+            let stored_key = &object[(HEADER_LENGTH + 1).min(HEADER_LENGTH + prefix_length)
+                ..(HEADER_LENGTH + prefix_length)];
+            let copy_len = key.len().min(key_length);
+            key[..copy_len].copy_from_slice(&stored_key[..copy_len]);
+
+            let stored_value = &object
+                [(HEADER_LENGTH + prefix_length)..(HEADER_LENGTH + prefix_length + value_length)];
+            let copy_len = value.len().min(value_length);
+            value[..copy_len].copy_from_slice(&stored_value[..copy_len]);
+
+            return Ok(Some(KeyEntry {
+                hashed_key: hash,
+                key_length,
+                value_length,
+                next_position: S * region + offset,
+            }));
+        }
+    }
+
+    /// Starts a transaction.
+    ///
+    /// Until the transaction is committed with `commit_transaction()` all
+    /// objects added with `append_key()` or `append_named_key()` are staged.
+    /// Staged objects are not returned by `get_key()` or `next_key()`. When
+    /// the transaction is committed any existing object with the same key as
+    /// a staged object is invalidated, so staged appends replace the current
+    /// value. A key can only be staged once per transaction.
+    ///
+    /// Only appends are staged, `invalidate_key()` and `zeroise_key()` take
+    /// effect immediately.
+    ///
+    /// If a power loss occurs before the transaction is committed all of the
+    /// staged objects are discarded by `initialise()`.
+    ///
+    /// On success nothing will be returned.
+    /// On error a `ErrorCode` will be returned.
+    pub fn begin_transaction(&self) -> Result<SuccessCode, ErrorCode> {
+        if self.transaction.get() {
+            return Err(ErrorCode::TransactionInProgress);
+        }
+
+        self.transaction.set(true);
+        Ok(SuccessCode::Complete)
+    }
+
+    /// Commits the current transaction.
+    ///
+    /// This first appends a commit record, once that has been written the
+    /// transaction will be committed even if a power loss occurs. Then the
+    /// values replaced by staged objects are invalidated, the staged objects
+    /// are made visible and finally the commit record is invalidated.
+    ///
+    /// This can require multiple writes. If the `FlashController` returns
+    /// `WriteNotReady` this function returns the error and should be called
+    /// again once the write has completed.
+    ///
+    /// If the commit record can't be written, for example because the flash
+    /// is full, the transaction is still in progress and can be aborted.
+    ///
+    /// On success nothing will be returned.
+    /// On error a `ErrorCode` will be returned.
+    pub fn commit_transaction(&self) -> Result<SuccessCode, ErrorCode> {
+        if self.state.get() == State::None {
+            if !self.transaction.get() {
+                return Err(ErrorCode::NoTransaction);
+            }
+
+            self.transaction.set(false);
+            self.state
+                .set(State::Transaction(TransactionState::AppendCommit(None)));
+        }
+
+        self.continue_transaction()
+    }
+
+    /// Aborts the current transaction, invalidating all of the staged objects.
+    ///
+    /// This can require multiple writes. If the `FlashController` returns
+    /// `WriteNotReady` this function returns the error and should be called
+    /// again once the write has completed.
+    ///
+    /// On success nothing will be returned.
+    /// On error a `ErrorCode` will be returned.
+    pub fn abort_transaction(&self) -> Result<SuccessCode, ErrorCode> {
+        if self.state.get() == State::None {
+            if !self.transaction.get() {
+                return Err(ErrorCode::NoTransaction);
+            }
+
+            self.transaction.set(false);
+            self.state
+                .set(State::Transaction(TransactionState::ScanRegion(
+                    false, 0, false,
+                )));
+        }
+
+        self.continue_transaction()
+    }
+
+    /// Continue committing, discarding or recovering a transaction.
+    ///
+    /// The commit record is stored with the inverse of the hashed main key.
+    /// The individual steps reuse the key operations by switching `state`
+    /// to the matching key state before calling them.
+    pub(crate) fn continue_transaction(&self) -> Result<SuccessCode, ErrorCode> {
+        let commit_hash = !self.main_key.get();
+        let num_region = self.flash_size / S;
+
+        loop {
+            let transaction_state = match self.state.get() {
+                State::Transaction(transaction_state) => transaction_state,
+                _ => unreachable!(),
+            };
+
+            let next_state = match transaction_state {
+                TransactionState::FindCommit(reg) => {
+                    self.state.set(
+                        reg.map_or(State::None, |reg| State::GetKey(KeyState::ReadRegion(reg))),
+                    );
+
+                    let mut buf: [u8; 0] = [0; 0];
+                    match self.get_key(commit_hash, &mut buf) {
+                        // The commit record was written, finish committing
+                        Ok(_) | Err(ErrorCode::BufferTooSmall(_)) => {
+                            TransactionState::ScanRegion(true, 0, false)
+                        }
+                        // There is no complete commit record, discard any
+                        // staged objects
+                        Err(ErrorCode::KeyNotFound) | Err(ErrorCode::InvalidCheckSum) => {
+                            TransactionState::ScanRegion(false, 0, false)
+                        }
+                        Err(ErrorCode::ReadNotReady(reg)) => {
+                            self.state
+                                .set(State::Transaction(TransactionState::FindCommit(Some(reg))));
+                            return Err(ErrorCode::ReadNotReady(reg));
+                        }
+                        Err(e) => {
+                            self.state.set(State::None);
+                            return Err(e);
+                        }
+                    }
+                }
+                TransactionState::AppendCommit(reg) => {
+                    self.state.set(reg.map_or(State::None, |reg| {
+                        State::AppendKey(KeyState::ReadRegion(reg))
+                    }));
+
+                    match self.append_key(commit_hash, &[]) {
+                        Ok(SuccessCode::Queued) => {
+                            self.state
+                                .set(State::Transaction(TransactionState::ScanRegion(
+                                    true, 0, false,
+                                )));
+                            return Err(ErrorCode::WriteNotReady(0));
+                        }
+                        Ok(_) => TransactionState::ScanRegion(true, 0, false),
+                        Err(ErrorCode::ReadNotReady(reg)) => {
+                            self.state
+                                .set(State::Transaction(TransactionState::AppendCommit(Some(
+                                    reg,
+                                ))));
+                            return Err(ErrorCode::ReadNotReady(reg));
+                        }
+                        Err(e) => {
+                            // Nothing has been committed, so the transaction
+                            // can still be aborted.
+                            self.transaction.set(true);
+                            self.state.set(State::None);
+                            return Err(e);
+                        }
+                    }
+                }
+                TransactionState::ScanRegion(_, region, _) if region >= num_region => {
+                    // All regions have been handled. When discarding this
+                    // still removes any partially written commit record.
+                    TransactionState::InvalidateCommit(None)
+                }
+                TransactionState::ScanRegion(commit, region, read) => {
+                    let region_data = self.read_buffer.take().unwrap();
+                    if !read {
+                        if let Err(e) = self.controller.read_region(region, region_data) {
+                            self.read_buffer.replace(Some(region_data));
+                            match e {
+                                ErrorCode::ReadNotReady(_) => self.state.set(State::Transaction(
+                                    TransactionState::ScanRegion(commit, region, true),
+                                )),
+                                _ => self.state.set(State::None),
+                            }
+                            return Err(e);
+                        }
+                    }
+
+                    let staged = match self.find_staged_offset(region_data) {
+                        Ok(staged) => staged,
+                        Err(e) => {
+                            self.read_buffer.replace(Some(region_data));
+                            self.state.set(State::None);
+                            return Err(e);
+                        }
+                    };
+
+                    match staged {
+                        None => {
+                            self.read_buffer.replace(Some(region_data));
+                            TransactionState::ScanRegion(commit, region + 1, false)
+                        }
+                        Some((_offset, hash)) if commit => {
+                            self.read_buffer.replace(Some(region_data));
+                            TransactionState::Supersede(region, hash, None)
+                        }
+                        Some((offset, _hash)) => {
+                            // Discard the staged object
+                            *region_data
+                                .get_mut(offset + LEN_OFFSET)
+                                .ok_or(ErrorCode::CorruptData)? &= !0x80;
+
+                            let ret = self.controller.write(
+                                S * region + offset + LEN_OFFSET,
+                                region_data
+                                    .get(offset + LEN_OFFSET..offset + LEN_OFFSET + 1)
+                                    .ok_or(ErrorCode::ObjectTooLarge)?,
+                            );
+                            self.read_buffer.replace(Some(region_data));
+
+                            match ret {
+                                // The read buffer is still up to date
+                                Ok(()) => TransactionState::ScanRegion(false, region, true),
+                                Err(ErrorCode::WriteNotReady(address)) => {
+                                    self.state.set(State::Transaction(
+                                        TransactionState::ScanRegion(false, region, false),
+                                    ));
+                                    return Err(ErrorCode::WriteNotReady(address));
+                                }
+                                Err(e) => {
+                                    self.state.set(State::None);
+                                    return Err(e);
+                                }
+                            }
+                        }
+                    }
+                }
+                TransactionState::Supersede(region, hash, reg) => {
+                    self.state.set(reg.map_or(State::None, |reg| {
+                        State::InvalidateKey(KeyState::ReadRegion(reg))
+                    }));
+
+                    match self.invalidate_key(hash) {
+                        Ok(SuccessCode::Queued) => {
+                            self.state
+                                .set(State::Transaction(TransactionState::ClearPending(
+                                    region, hash, false,
+                                )));
+                            return Err(ErrorCode::WriteNotReady(0));
+                        }
+                        Ok(_) | Err(ErrorCode::KeyNotFound) => {
+                            TransactionState::ClearPending(region, hash, false)
+                        }
+                        Err(ErrorCode::ReadNotReady(reg)) => {
+                            self.state
+                                .set(State::Transaction(TransactionState::Supersede(
+                                    region,
+                                    hash,
+                                    Some(reg),
+                                )));
+                            return Err(ErrorCode::ReadNotReady(reg));
+                        }
+                        Err(e) => {
+                            self.state.set(State::None);
+                            return Err(e);
+                        }
+                    }
+                }
+                TransactionState::ClearPending(region, hash, read) => {
+                    let region_data = self.read_buffer.take().unwrap();
+                    if !read {
+                        if let Err(e) = self.controller.read_region(region, region_data) {
+                            self.read_buffer.replace(Some(region_data));
+                            match e {
+                                ErrorCode::ReadNotReady(_) => self.state.set(State::Transaction(
+                                    TransactionState::ClearPending(region, hash, true),
+                                )),
+                                _ => self.state.set(State::None),
+                            }
+                            return Err(e);
+                        }
+                    }
+
+                    match self.find_key_offset(hash, region_data, true) {
+                        Ok((offset, _total_length)) => {
+                            // Restore the version the object was hashed with
+                            // and clear the pending flag, in one write.
+                            let len_flags = region_data
+                                .get_mut(offset + LEN_OFFSET)
+                                .ok_or(ErrorCode::CorruptData)?;
+                            *len_flags &= !(FLAGS_PENDING << 4);
+                            let version = committed_version(*len_flags);
+                            *region_data
+                                .get_mut(offset + VERSION_OFFSET)
+                                .ok_or(ErrorCode::CorruptData)? = version;
+
+                            let ret = self.controller.write(
+                                S * region + offset + VERSION_OFFSET,
+                                region_data
+                                    .get(offset + VERSION_OFFSET..offset + LEN_OFFSET + 1)
+                                    .ok_or(ErrorCode::ObjectTooLarge)?,
+                            );
+                            self.read_buffer.replace(Some(region_data));
+
+                            match ret {
+                                Ok(()) => TransactionState::ScanRegion(true, region, true),
+                                Err(ErrorCode::WriteNotReady(address)) => {
+                                    self.state.set(State::Transaction(
+                                        TransactionState::ScanRegion(true, region, false),
+                                    ));
+                                    return Err(ErrorCode::WriteNotReady(address));
+                                }
+                                Err(e) => {
+                                    self.state.set(State::None);
+                                    return Err(e);
+                                }
+                            }
+                        }
+                        Err(_) => {
+                            // The object has already been committed
+                            self.read_buffer.replace(Some(region_data));
+                            TransactionState::ScanRegion(true, region, true)
+                        }
+                    }
+                }
+                TransactionState::InvalidateCommit(reg) => {
+                    self.state.set(reg.map_or(State::None, |reg| {
+                        State::InvalidateKey(KeyState::ReadRegion(reg))
+                    }));
+
+                    return match self.invalidate_key(commit_hash) {
+                        Ok(ret) => {
+                            self.state.set(State::None);
+                            Ok(ret)
+                        }
+                        Err(ErrorCode::KeyNotFound) => {
+                            self.state.set(State::None);
+                            Ok(SuccessCode::Complete)
+                        }
+                        Err(ErrorCode::ReadNotReady(reg)) => {
+                            self.state
+                                .set(State::Transaction(TransactionState::InvalidateCommit(
+                                    Some(reg),
+                                )));
+                            Err(ErrorCode::ReadNotReady(reg))
+                        }
+                        Err(e) => {
+                            self.state.set(State::None);
+                            Err(e)
+                        }
+                    };
+                }
+            };
+
+            self.state.set(State::Transaction(next_state));
+        }
+    }
+
+    /// Find the first valid object staged by a transaction in some loaded
+    /// region data.
+    ///
+    /// On success return the offset and hashed key of the object, or `None`
+    /// if there are no staged objects in this region.
+    fn find_staged_offset(&self, region_data: &[u8]) -> Result<Option<(usize, u64)>, ErrorCode> {
+        let mut offset: usize = 0;
+
+        loop {
+            if offset + HEADER_LENGTH >= S {
+                // We have reached the end of the region
+                return Ok(None);
+            }
+
+            let version = *region_data
+                .get(offset + VERSION_OFFSET)
+                .ok_or(ErrorCode::CorruptData)?;
+            if version == 0xFF {
+                // We hit the end.
+                return Ok(None);
+            }
+            if !supported_version(version) {
+                return Err(ErrorCode::UnsupportedVersion);
+            }
+
+            let len_flags = *region_data
+                .get(offset + LEN_OFFSET)
+                .ok_or(ErrorCode::CorruptData)?;
+            let total_length = (((len_flags as u16) & !0xF0) << 8
+                | *region_data
+                    .get(offset + LEN_OFFSET + 1)
+                    .ok_or(ErrorCode::CorruptData)? as u16) as usize;
+
+            if total_length == 0 {
+                // We found something invalid here
+                return Ok(None);
+            }
+
+            if len_flags & 0x80 == 0x80 && is_staged(version, len_flags) {
+                let hash = u64::from_be_bytes(
+                    region_data
+                        .get((offset + HASH_OFFSET)..(offset + HASH_OFFSET + 8))
+                        .ok_or(ErrorCode::CorruptData)?
+                        .try_into()
+                        .or(Err(ErrorCode::CorruptData))?,
+                );
+                return Ok(Some((offset, hash)));
+            }
+
+            offset += total_length;
+        }
+    }
+
     fn garbage_collect_region(
         &self,
         region: usize,
@@ -1025,11 +1839,11 @@ impl<'a, C: FlashController<S>, const S: usize> TicKV<'a, C, S> {
                 != 0xFF
             {
                 // We found a version, check that we support it
-                if *region_data
-                    .get(offset + VERSION_OFFSET)
-                    .ok_or(ErrorCode::KeyNotFound)?
-                    != VERSION
-                {
+                if !supported_version(
+                    *region_data
+                        .get(offset + VERSION_OFFSET)
+                        .ok_or(ErrorCode::KeyNotFound)?,
+                ) {
                     self.read_buffer.replace(Some(region_data));
                     return Err(ErrorCode::UnsupportedVersion);
                 }
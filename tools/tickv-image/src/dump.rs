@@ -0,0 +1,292 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Walk the regions of an image and decode every object.
+//!
+//! This deliberately doesn't go through `TicKV`, which skips over invalid and
+//! corrupt objects. The object layout is described in `libraries/tickv/SPEC.md`.
+
+use std::fmt::Write;
+
+use tickv::crc32::Crc32;
+use tickv::flash_controller::FlashController;
+
+use crate::flash::ERASED;
+
+const VERSION: u8 = tickv::tickv::VERSION;
+const NAMED_VERSION: u8 = tickv::tickv::NAMED_VERSION;
+const PENDING_VERSION: u8 = tickv::tickv::PENDING_VERSION;
+const LEN_OFFSET: usize = 1;
+const HASH_OFFSET: usize = 3;
+const HEADER_LENGTH: usize = HASH_OFFSET + 8;
+const CHECK_SUM_LEN: usize = 4;
+
+// The flags are stored in the top nibble of the `LEN_OFFSET` byte.
+const FLAGS_VALID: u8 = 0x80;
+const FLAGS_NAMED: u8 = 0x40;
+const FLAGS_PENDING: u8 = 0x20;
+
+/// An object found in the image.
+pub struct Object {
+    pub offset: usize,
+    pub hash: u64,
+    pub valid: bool,
+    pub pending: bool,
+    pub check_sum_ok: bool,
+    pub key: Option<Vec<u8>>,
+    pub value: Vec<u8>,
+}
+
+/// What ended the walk through a region.
+enum RegionEnd {
+    /// The rest of the region, this many bytes, is erased.
+    Free(usize),
+    /// An object header couldn't be decoded.
+    Corrupt(usize, &'static str),
+}
+
+/// Decode the objects in a region.
+fn region_objects(data: &[u8]) -> (Vec<Object>, RegionEnd) {
+    let mut objects = Vec::new();
+    let mut offset = 0;
+
+    loop {
+        if offset + HEADER_LENGTH > data.len() {
+            return (objects, RegionEnd::Free(data.len() - offset));
+        }
+
+        let version = data[offset];
+        if version == ERASED {
+            return (objects, RegionEnd::Free(data.len() - offset));
+        }
+        if version != VERSION && version != NAMED_VERSION && version != PENDING_VERSION {
+            return (objects, RegionEnd::Corrupt(offset, "unsupported version"));
+        }
+
+        let len_flags = data[offset + LEN_OFFSET];
+        let length = ((len_flags as usize & 0x0F) << 8) | data[offset + LEN_OFFSET + 1] as usize;
+        if length < HEADER_LENGTH + CHECK_SUM_LEN || offset + length > data.len() {
+            return (objects, RegionEnd::Corrupt(offset, "invalid length"));
+        }
+
+        let object = &data[offset..offset + length];
+        let hash = u64::from_be_bytes(object[HASH_OFFSET..HEADER_LENGTH].try_into().unwrap());
+
+        // The check sum is calculated when the object is written, before it
+        // has been invalidated, with the pending flag clear and the version
+        // it has once committed.
+        let mut header = [0; HEADER_LENGTH];
+        header.copy_from_slice(&object[..HEADER_LENGTH]);
+        header[LEN_OFFSET] = (header[LEN_OFFSET] | FLAGS_VALID) & !FLAGS_PENDING;
+        if version == PENDING_VERSION {
+            header[0] = if len_flags & FLAGS_NAMED != 0 {
+                NAMED_VERSION
+            } else {
+                VERSION
+            };
+        }
+        let check_sum = Crc32::new();
+        check_sum.update(&header);
+        check_sum.update(&object[HEADER_LENGTH..length - CHECK_SUM_LEN]);
+        let check_sum_ok = object[length - CHECK_SUM_LEN..] == check_sum.finalise().to_ne_bytes();
+
+        let body = &object[HEADER_LENGTH..length - CHECK_SUM_LEN];
+        let (key, value) = match body.split_first() {
+            Some((&key_length, rest))
+                if len_flags & FLAGS_NAMED != 0 && key_length as usize <= rest.len() =>
+            {
+                let (key, value) = rest.split_at(key_length as usize);
+                (Some(key.to_vec()), value.to_vec())
+            }
+            _ => (None, body.to_vec()),
+        };
+
+        objects.push(Object {
+            offset,
+            hash,
+            valid: len_flags & FLAGS_VALID != 0,
+            pending: version == PENDING_VERSION || len_flags & FLAGS_PENDING != 0,
+            check_sum_ok,
+            key,
+            value,
+        });
+
+        offset += length;
+    }
+}
+
+fn read_regions<const S: usize, C: FlashController<S>>(
+    controller: &C,
+    flash_size: usize,
+) -> impl Iterator<Item = (usize, Result<[u8; S], tickv::ErrorCode>)> + '_ {
+    (0..flash_size / S).map(move |region| {
+        let mut data = [0; S];
+        (
+            region,
+            controller.read_region(region, &mut data).map(|_| data),
+        )
+    })
+}
+
+/// Return every object in the image.
+pub fn objects<const S: usize, C: FlashController<S>>(
+    controller: &C,
+    flash_size: usize,
+) -> Vec<Object> {
+    read_regions(controller, flash_size)
+        .filter_map(|(_, data)| data.ok().map(|data| region_objects(&data).0))
+        .flatten()
+        .collect()
+}
+
+/// Check that the image has been initialised with `main_key`.
+pub fn has_main_key<const S: usize, C: FlashController<S>>(
+    controller: &C,
+    flash_size: usize,
+    main_key: u64,
+) -> bool {
+    objects(controller, flash_size)
+        .iter()
+        .any(|o| o.hash == main_key && o.valid && o.check_sum_ok)
+}
+
+/// Format `bytes` as a string if it is printable, otherwise as hex.
+fn format_bytes(bytes: &[u8]) -> String {
+    match core::str::from_utf8(bytes) {
+        Ok(s) if s.chars().all(|c| !c.is_control()) => format!("{:?}", s),
+        _ => bytes.iter().fold(String::new(), |mut s, b| {
+            let _ = write!(s, "{:02x}", b);
+            s
+        }),
+    }
+}
+
+/// Print every region and object in the image.
+pub fn dump<const S: usize, C: FlashController<S>>(
+    controller: &C,
+    flash_size: usize,
+    main_key: u64,
+) -> Result<(), String> {
+    let mut valid = 0;
+    let mut invalid = 0;
+    let mut pending = 0;
+    let mut bad_check_sums = 0;
+    let mut free = 0;
+
+    for (region, data) in read_regions(controller, flash_size) {
+        let data = data.map_err(|e| format!("failed to read region {}: {:?}", region, e))?;
+        let (objects, end) = region_objects(&data);
+
+        println!("Region {} (offset {:#x})", region, region * S);
+
+        for object in &objects {
+            let state = if object.pending {
+                "pending"
+            } else if object.valid {
+                "valid"
+            } else {
+                "invalid"
+            };
+            let name = if object.hash == main_key {
+                "main key".to_string()
+            } else if object.hash == !main_key {
+                "commit record".to_string()
+            } else {
+                match &object.key {
+                    Some(key) => format!("key {}", format_bytes(key)),
+                    None => "unnamed".to_string(),
+                }
+            };
+
+            println!(
+                "  {:#06x}  {:<7}  {:#018x}  {}  crc {}",
+                object.offset,
+                state,
+                object.hash,
+                name,
+                if object.check_sum_ok { "ok" } else { "BAD" }
+            );
+            if !object.value.is_empty() {
+                println!(
+                    "          {} bytes: {}",
+                    object.value.len(),
+                    format_bytes(&object.value)
+                );
+            }
+
+            match (object.pending, object.valid) {
+                (true, _) => pending += 1,
+                (false, true) => valid += 1,
+                (false, false) => invalid += 1,
+            }
+            if !object.check_sum_ok {
+                bad_check_sums += 1;
+            }
+        }
+
+        match end {
+            RegionEnd::Free(bytes) => {
+                println!("  {} bytes free", bytes);
+                free += bytes;
+            }
+            RegionEnd::Corrupt(offset, reason) => {
+                println!("  {:#06x}  corrupt: {}", offset, reason);
+            }
+        }
+    }
+
+    println!(
+        "\n{} valid, {} invalid, {} pending, {} bad check sums, {} bytes free",
+        valid, invalid, pending, bad_check_sums, free
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flash::FileFlashController;
+    use tickv::TicKV;
+
+    #[test]
+    fn staged_objects() {
+        let dir = std::env::temp_dir().join(format!("tickv-dump-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let image = dir.join("image.bin");
+
+        let controller = FileFlashController::<1024>::create(&image, 2 * 1024).unwrap();
+        let mut read_buffer = [0; 1024];
+        let tickv = TicKV::<_, 1024>::new(controller, &mut read_buffer, 2 * 1024);
+        tickv.initialise(1).unwrap();
+        tickv.begin_transaction().unwrap();
+        tickv.append_named_key(2, b"two", b"2").unwrap();
+        tickv.append_key(3, b"3").unwrap();
+
+        // Staged objects have their own version, but the check sum of the
+        // committed object.
+        let staged = objects(&tickv.controller, 2 * 1024);
+        let staged: Vec<_> = staged
+            .iter()
+            .filter(|o| o.hash == 2 || o.hash == 3)
+            .collect();
+        assert_eq!(staged.len(), 2);
+        assert!(staged
+            .iter()
+            .all(|o| o.pending && o.valid && o.check_sum_ok));
+        assert_eq!(staged[0].key.as_deref(), Some(&b"two"[..]));
+
+        tickv.commit_transaction().unwrap();
+        let committed = objects(&tickv.controller, 2 * 1024);
+        let committed: Vec<_> = committed
+            .iter()
+            .filter(|o| o.hash == 2 || o.hash == 3)
+            .collect();
+        assert_eq!(committed.len(), 2);
+        assert!(committed
+            .iter()
+            .all(|o| !o.pending && o.valid && o.check_sum_ok));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,83 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! A TicKV `FlashController` backed by an image file on the host.
+
+use std::cell::RefCell;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use tickv::error_codes::ErrorCode;
+use tickv::flash_controller::FlashController;
+
+/// The value of erased flash.
+pub const ERASED: u8 = 0xFF;
+
+/// Flash controller that reads and writes regions of an image file.
+///
+/// All operations complete synchronously, so TicKV never has to be
+/// continued.
+pub struct FileFlashController<const S: usize> {
+    file: RefCell<File>,
+}
+
+impl<const S: usize> FileFlashController<S> {
+    /// Open an existing image.
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let file = OpenOptions::new().read(true).write(true).open(path)?;
+
+        Ok(Self {
+            file: RefCell::new(file),
+        })
+    }
+
+    /// Create a new, fully erased, image of `size` bytes.
+    pub fn create(path: &Path, size: usize) -> io::Result<Self> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+        file.write_all(&vec![ERASED; size])?;
+
+        Ok(Self {
+            file: RefCell::new(file),
+        })
+    }
+
+    /// The length of the image in bytes.
+    pub fn len(&self) -> io::Result<usize> {
+        Ok(self.file.borrow().metadata()?.len() as usize)
+    }
+
+    fn read_at(&self, address: usize, buf: &mut [u8]) -> io::Result<()> {
+        let mut file = self.file.borrow_mut();
+        file.seek(SeekFrom::Start(address as u64))?;
+        file.read_exact(buf)
+    }
+
+    fn write_at(&self, address: usize, buf: &[u8]) -> io::Result<()> {
+        let mut file = self.file.borrow_mut();
+        file.seek(SeekFrom::Start(address as u64))?;
+        file.write_all(buf)
+    }
+}
+
+impl<const S: usize> FlashController<S> for FileFlashController<S> {
+    fn read_region(&self, region_number: usize, buf: &mut [u8; S]) -> Result<(), ErrorCode> {
+        self.read_at(region_number * S, buf)
+            .or(Err(ErrorCode::ReadFail))
+    }
+
+    fn write(&self, address: usize, buf: &[u8]) -> Result<(), ErrorCode> {
+        self.write_at(address, buf).or(Err(ErrorCode::WriteFail))
+    }
+
+    fn erase_region(&self, region_number: usize) -> Result<(), ErrorCode> {
+        self.write_at(region_number * S, &[ERASED; S])
+            .or(Err(ErrorCode::WriteFail))
+    }
+}
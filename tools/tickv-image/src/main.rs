@@ -0,0 +1,335 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Host tool for building and inspecting TicKV flash images.
+//!
+//! See the README for usage.
+
+mod dump;
+mod flash;
+mod manifest;
+
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+use flash::FileFlashController;
+use tickv::TicKV;
+
+/// The hashed main key used by the Tock kernel's TicKV capsule.
+const DEFAULT_MAIN_KEY: u64 = 0x7bc9f7ff4f76f244;
+const DEFAULT_REGION_SIZE: usize = 2048;
+const DEFAULT_FLASH_SIZE: usize = 64 * 1024;
+
+/// Version of the Tock K-V permissions header.
+const KV_HEADER_VERSION: u8 = 0;
+
+const USAGE: &str = "\
+Usage:
+    tickv-image create [options] <manifest> <image>
+    tickv-image dump [options] <image>
+    tickv-image gc [options] <image>
+
+Commands:
+    create    Create a new image containing the objects in <manifest>
+    dump      Print every region and object in <image> with its CRC status
+    gc        Run garbage collection on <image>, freeing deleted objects
+
+Options:
+    --region-size <bytes>  Size of a flash region (default 2048)
+    --flash-size <bytes>   Size of the image to create (default 65536)
+    --main-key <hash>      Hashed main key (default 0x7bc9f7ff4f76f244, as
+                           used by the Tock kernel)
+    --write-id <id>        Prefix each value with a Tock K-V permissions
+                           header owned by <id>, so that the objects can be
+                           accessed through `hil::kv::KVPermissions`
+    --unnamed              Only store the hashed key, not the key itself
+";
+
+enum Command {
+    Create { manifest: PathBuf, image: PathBuf },
+    Dump { image: PathBuf },
+    Gc { image: PathBuf },
+}
+
+struct Options {
+    command: Command,
+    region_size: usize,
+    flash_size: usize,
+    main_key: u64,
+    write_id: Option<u32>,
+    named: bool,
+}
+
+fn parse_number(arg: &str) -> Result<u64, String> {
+    let parsed = match arg.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => arg.parse(),
+    };
+
+    parsed.map_err(|_| format!("`{}` is not a valid number", arg))
+}
+
+fn parse_args(args: &[String]) -> Result<Options, String> {
+    let mut region_size = DEFAULT_REGION_SIZE;
+    let mut flash_size = DEFAULT_FLASH_SIZE;
+    let mut main_key = DEFAULT_MAIN_KEY;
+    let mut write_id = None;
+    let mut named = true;
+    let mut positional = Vec::new();
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| {
+            args.next()
+                .ok_or(format!("{} requires a value", name))
+                .and_then(|v| parse_number(v))
+        };
+
+        match arg.as_str() {
+            "--region-size" => region_size = value(arg)? as usize,
+            "--flash-size" => flash_size = value(arg)? as usize,
+            "--main-key" => main_key = value(arg)?,
+            "--write-id" => {
+                let id = value(arg)?;
+                write_id = Some(u32::try_from(id).map_err(|_| "--write-id is too large")?);
+            }
+            "--unnamed" => named = false,
+            "-h" | "--help" => return Err(String::new()),
+            _ if arg.starts_with("--") => return Err(format!("unknown option `{}`", arg)),
+            _ => positional.push(PathBuf::from(arg)),
+        }
+    }
+
+    let mut positional = positional.into_iter();
+    let command = match (
+        positional.next().as_ref().and_then(|c| c.to_str()),
+        positional.next(),
+        positional.next(),
+        positional.next(),
+    ) {
+        (Some("create"), Some(manifest), Some(image), None) => Command::Create { manifest, image },
+        (Some("dump"), Some(image), None, None) => Command::Dump { image },
+        (Some("gc"), Some(image), None, None) => Command::Gc { image },
+        _ => return Err(String::new()),
+    };
+
+    if flash_size == 0 || flash_size % region_size != 0 {
+        return Err("--flash-size must be a multiple of --region-size".to_string());
+    }
+
+    Ok(Options {
+        command,
+        region_size,
+        flash_size,
+        main_key,
+        write_id,
+        named,
+    })
+}
+
+/// Hash `key` the same way as the Tock kernel.
+///
+/// The kernel uses SipHash-2-4 with zero keys. The `SipHasher24` capsule
+/// writes the digest out little-endian and `TicKVSystem` reads it back
+/// big-endian, so the bytes are swapped.
+#[allow(deprecated)]
+fn hash_key(key: &[u8]) -> u64 {
+    use std::hash::Hasher;
+
+    let mut hasher = std::hash::SipHasher::new();
+    hasher.write(key);
+    hasher.finish().swap_bytes()
+}
+
+/// Prefix `value` with a Tock K-V permissions header.
+fn add_kv_header(write_id: u32, value: &[u8]) -> Vec<u8> {
+    let mut object = vec![KV_HEADER_VERSION];
+    object.extend_from_slice(&(value.len() as u32).to_le_bytes());
+    object.extend_from_slice(&write_id.to_le_bytes());
+    object.extend_from_slice(value);
+    object
+}
+
+fn create<const S: usize>(options: &Options, manifest: &Path, image: &Path) -> Result<(), String> {
+    let contents = std::fs::read_to_string(manifest)
+        .map_err(|e| format!("failed to read {}: {}", manifest.display(), e))?;
+    let entries = manifest::parse(&contents)?;
+
+    let controller = FileFlashController::<S>::create(image, options.flash_size)
+        .map_err(|e| format!("failed to create {}: {}", image.display(), e))?;
+    let mut read_buffer = [0; S];
+    let tickv = TicKV::<_, S>::new(controller, &mut read_buffer, options.flash_size);
+
+    tickv
+        .initialise(options.main_key)
+        .map_err(|e| format!("failed to initialise the image: {:?}", e))?;
+
+    for entry in &entries {
+        let value = match options.write_id {
+            Some(write_id) => add_kv_header(write_id, &entry.value),
+            None => entry.value.clone(),
+        };
+
+        let hash = hash_key(&entry.key);
+        let ret = if options.named {
+            tickv.append_named_key(hash, &entry.key, &value)
+        } else {
+            tickv.append_key(hash, &value)
+        };
+
+        ret.map_err(|e| {
+            format!(
+                "failed to add `{}`: {:?}",
+                String::from_utf8_lossy(&entry.key),
+                e
+            )
+        })?;
+    }
+
+    println!("Wrote {} objects to {}", entries.len(), image.display());
+    Ok(())
+}
+
+fn open<const S: usize>(image: &Path) -> Result<FileFlashController<S>, String> {
+    let controller = FileFlashController::<S>::open(image)
+        .map_err(|e| format!("failed to open {}: {}", image.display(), e))?;
+
+    let len = controller
+        .len()
+        .map_err(|e| format!("failed to read {}: {}", image.display(), e))?;
+    if len == 0 || len % S != 0 {
+        return Err(format!(
+            "{} is {} bytes, which is not a multiple of the region size",
+            image.display(),
+            len
+        ));
+    }
+
+    Ok(controller)
+}
+
+fn gc<const S: usize>(options: &Options, image: &Path) -> Result<(), String> {
+    let controller = open::<S>(image)?;
+    let flash_size = controller.len().map_err(|e| e.to_string())?;
+
+    // `initialise()` erases the flash if it can't find the main key, don't let
+    // that happen to an image we were asked to inspect.
+    if !dump::has_main_key(&controller, flash_size, options.main_key) {
+        return Err(format!(
+            "{} doesn't contain the main key {:#x}",
+            image.display(),
+            options.main_key
+        ));
+    }
+
+    let mut read_buffer = [0; S];
+    let tickv = TicKV::<_, S>::new(controller, &mut read_buffer, flash_size);
+
+    tickv
+        .initialise(options.main_key)
+        .map_err(|e| format!("failed to initialise the image: {:?}", e))?;
+    let freed = tickv
+        .garbage_collect()
+        .map_err(|e| format!("garbage collection failed: {:?}", e))?;
+
+    println!("Freed {} bytes", freed);
+    Ok(())
+}
+
+fn run<const S: usize>(options: &Options) -> Result<(), String> {
+    match &options.command {
+        Command::Create { manifest, image } => create::<S>(options, manifest, image),
+        Command::Dump { image } => {
+            let controller = open::<S>(image)?;
+            let flash_size = controller.len().map_err(|e| e.to_string())?;
+            dump::dump(&controller, flash_size, options.main_key)
+        }
+        Command::Gc { image } => gc::<S>(options, image),
+    }
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+
+    let options = match parse_args(&args) {
+        Ok(options) => options,
+        Err(e) => {
+            if !e.is_empty() {
+                eprintln!("error: {}\n", e);
+            }
+            eprint!("{}", USAGE);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    // TicKV's region size is a const generic, so support the common sizes.
+    let ret = match options.region_size {
+        256 => run::<256>(&options),
+        512 => run::<512>(&options),
+        1024 => run::<1024>(&options),
+        2048 => run::<2048>(&options),
+        4096 => run::<4096>(&options),
+        8192 => run::<8192>(&options),
+        16384 => run::<16384>(&options),
+        size => Err(format!("unsupported region size {}", size)),
+    };
+
+    match ret {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn create_dump_gc() {
+        let dir = std::env::temp_dir().join(format!("tickv-image-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let manifest = dir.join("manifest.txt");
+        let image = dir.join("image.bin");
+        std::fs::write(&manifest, "one = \"1\"\ntwo = hex:0202\n").unwrap();
+
+        let options = Options {
+            command: Command::Create {
+                manifest: manifest.clone(),
+                image: image.clone(),
+            },
+            region_size: 1024,
+            flash_size: 8 * 1024,
+            main_key: DEFAULT_MAIN_KEY,
+            write_id: Some(7),
+            named: true,
+        };
+        run::<1024>(&options).unwrap();
+
+        let controller = open::<1024>(&image).unwrap();
+        let objects = dump::objects(&controller, 8 * 1024);
+        let one = objects
+            .iter()
+            .find(|o| o.key.as_deref() == Some(&b"one"[..]))
+            .unwrap();
+        assert!(one.valid && one.check_sum_ok);
+        assert_eq!(one.hash, hash_key(b"one"));
+        assert_eq!(one.value, add_kv_header(7, b"1"));
+        assert!(objects
+            .iter()
+            .any(|o| o.key.as_deref() == Some(&b"two"[..])));
+
+        run::<1024>(&Options {
+            command: Command::Gc {
+                image: image.clone(),
+            },
+            ..options
+        })
+        .unwrap();
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,172 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Parser for the key/value manifest used to build images.
+//!
+//! Each non-empty line that doesn't start with `#` is an entry of the form
+//! `<key> = <value>`. Keys and values are either:
+//!
+//! - a quoted string, supporting the `\\`, `\"`, `\n`, `\t` and `\xNN`
+//!   escapes,
+//! - `hex:` followed by an even number of hex digits, or
+//! - for keys only, a bare word with no spaces.
+//!
+//! ```text
+//! # Network configuration
+//! wifi-ssid = "tock"
+//! "calibration data" = hex:00112233
+//! ```
+
+/// A single key/value pair from the manifest.
+#[derive(Debug, PartialEq)]
+pub struct Entry {
+    pub key: Vec<u8>,
+    pub value: Vec<u8>,
+}
+
+/// Parse the contents of a manifest file.
+pub fn parse(manifest: &str) -> Result<Vec<Entry>, String> {
+    let mut entries = Vec::new();
+
+    for (number, line) in manifest.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let entry = parse_line(line).map_err(|e| format!("line {}: {}", number + 1, e))?;
+        entries.push(entry);
+    }
+
+    Ok(entries)
+}
+
+fn parse_line(line: &str) -> Result<Entry, String> {
+    let (key, rest) = if line.starts_with('"') || line.starts_with("hex:") {
+        parse_item(line)?
+    } else {
+        let end = line
+            .find(|c: char| c.is_whitespace() || c == '=')
+            .unwrap_or(line.len());
+        (line.as_bytes()[..end].to_vec(), &line[end..])
+    };
+
+    let rest = rest
+        .trim_start()
+        .strip_prefix('=')
+        .ok_or("expected `=` after the key")?
+        .trim_start();
+
+    let (value, rest) = parse_item(rest)?;
+    if !rest.trim().is_empty() {
+        return Err(format!("unexpected `{}` after the value", rest.trim()));
+    }
+
+    if key.is_empty() {
+        return Err("the key is empty".to_string());
+    }
+
+    Ok(Entry { key, value })
+}
+
+/// Parse a quoted string or hex item, returning the bytes and the remainder of
+/// the line.
+fn parse_item(item: &str) -> Result<(Vec<u8>, &str), String> {
+    if let Some(hex) = item.strip_prefix("hex:") {
+        let end = hex.find(char::is_whitespace).unwrap_or(hex.len());
+        return Ok((parse_hex(&hex[..end])?, &hex[end..]));
+    }
+
+    let quoted = item
+        .strip_prefix('"')
+        .ok_or("expected a quoted string or `hex:`")?;
+
+    let mut bytes = Vec::new();
+    let mut chars = quoted.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => return Ok((bytes, &quoted[i + 1..])),
+            '\\' => match chars.next() {
+                Some((_, '\\')) => bytes.push(b'\\'),
+                Some((_, '"')) => bytes.push(b'"'),
+                Some((_, 'n')) => bytes.push(b'\n'),
+                Some((_, 't')) => bytes.push(b'\t'),
+                Some((i, 'x')) => {
+                    let digits = quoted.get(i + 1..i + 3).ok_or("incomplete `\\x` escape")?;
+                    bytes.extend(parse_hex(digits)?);
+                    chars.next();
+                    chars.next();
+                }
+                _ => return Err("unknown escape sequence".to_string()),
+            },
+            c => {
+                let mut buf = [0; 4];
+                bytes.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+            }
+        }
+    }
+
+    Err("unterminated string".to_string())
+}
+
+fn parse_hex(hex: &str) -> Result<Vec<u8>, String> {
+    if hex.len() % 2 != 0 {
+        return Err(format!("`{}` has an odd number of hex digits", hex));
+    }
+
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| {
+            hex.get(i..i + 2)
+                .and_then(|digits| u8::from_str_radix(digits, 16).ok())
+                .ok_or(format!("`{}` is not valid hex", hex))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_entries() {
+        let entries = parse(
+            "# comment\n\
+             \n\
+             wifi-ssid = \"tock\"\n\
+             \"two words\"=hex:00ff10\n\
+             hex:6b6579 = \"a\\\"b\\x01\\n\"\n",
+        )
+        .unwrap();
+
+        assert_eq!(
+            entries,
+            vec![
+                Entry {
+                    key: b"wifi-ssid".to_vec(),
+                    value: b"tock".to_vec(),
+                },
+                Entry {
+                    key: b"two words".to_vec(),
+                    value: vec![0x00, 0xff, 0x10],
+                },
+                Entry {
+                    key: b"key".to_vec(),
+                    value: b"a\"b\x01\n".to_vec(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert!(parse("key").is_err());
+        assert!(parse("key = tock").is_err());
+        assert!(parse("key = \"tock").is_err());
+        assert!(parse("key = hex:123").is_err());
+        assert!(parse("key = hex:zz").is_err());
+        assert!(parse("key = \"a\" b").is_err());
+        assert!(parse("= \"a\"").is_err());
+    }
+}